@@ -146,6 +146,560 @@ fn sled_random_crud(c: &mut Criterion) {
     });
 }
 
+fn sled_fold_range(c: &mut Criterion) {
+    const SIZE: u64 = 65536;
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+
+    for i in 0..SIZE {
+        db.insert(i.to_be_bytes(), &i.to_le_bytes()).unwrap();
+    }
+
+    let decode = |v: &[u8]| {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(v);
+        u64::from_le_bytes(buf)
+    };
+
+    c.bench_function("sum u64 values with iterator fold", |b| {
+        b.iter(|| {
+            db.iter().fold(0_u64, |acc, res| {
+                let (_k, v) = res.unwrap();
+                acc.wrapping_add(decode(&v))
+            })
+        })
+    });
+
+    c.bench_function("sum u64 values with fold_range", |b| {
+        b.iter(|| {
+            db.fold_range::<&[u8], _, _, _>(.., 0_u64, |acc, _k, v| {
+                acc.wrapping_add(decode(v))
+            })
+            .unwrap()
+        })
+    });
+}
+
+fn sled_multi_get(c: &mut Criterion) {
+    const SIZE: u32 = 1 << 20;
+    const BATCH: u32 = 50;
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+
+    db.bulk_load((0..SIZE).map(|i| (i.to_be_bytes(), vec![]))).unwrap();
+
+    let clustered = || {
+        let start = random(SIZE - BATCH);
+        (start..start + BATCH).map(u32::to_be_bytes).collect::<Vec<_>>()
+    };
+
+    let scattered = || {
+        (0..BATCH).map(|_| random(SIZE).to_be_bytes()).collect::<Vec<_>>()
+    };
+
+    c.bench_function("50 clustered gets", |b| {
+        b.iter(|| {
+            for k in clustered() {
+                db.get(k).unwrap();
+            }
+        })
+    });
+
+    c.bench_function("50 clustered keys with multi_get", |b| {
+        b.iter(|| db.multi_get(clustered()).unwrap())
+    });
+
+    c.bench_function("50 scattered gets", |b| {
+        b.iter(|| {
+            for k in scattered() {
+                db.get(k).unwrap();
+            }
+        })
+    });
+
+    c.bench_function("50 scattered keys with multi_get", |b| {
+        b.iter(|| db.multi_get(scattered()).unwrap())
+    });
+}
+
+fn sled_reverse_scan(c: &mut Criterion) {
+    const SIZE: u64 = 10_000_000;
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+
+    db.bulk_load((0..SIZE).map(|i| (i.to_be_bytes(), vec![]))).unwrap();
+
+    // if reverse scans walked the tree from the start, this
+    // would be orders of magnitude slower than the forward
+    // scan of the first 1000 keys below.
+    let start = (SIZE - 1000).to_be_bytes();
+    c.bench_function("reverse scan of the last 1000 of 10M keys", |b| {
+        b.iter(|| assert_eq!(db.range_rev(start..).count(), 1000))
+    });
+
+    let end = 1000_u64.to_be_bytes();
+    c.bench_function("forward scan of the first 1000 of 10M keys", |b| {
+        b.iter(|| assert_eq!(db.range(..end).count(), 1000))
+    });
+}
+
+fn sled_key_scan(c: &mut Criterion) {
+    const SIZE: u32 = 16 * 1024;
+
+    // a small cache, so that most leaves are read from disk
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .cache_capacity(1024 * 1024)
+        .open()
+        .unwrap();
+
+    db.bulk_load((0..SIZE).map(|i| (i.to_be_bytes(), vec![0; 4096])))
+        .unwrap();
+    db.flush().unwrap();
+
+    c.bench_function("key scan of 16k items with 4k values", |b| {
+        b.iter(|| assert_eq!(db.iter_keys().count(), SIZE as usize))
+    });
+
+    c.bench_function("full scan of 16k items with 4k values", |b| {
+        b.iter(|| {
+            let keys = db.iter().map(|res| res.map(|(k, _v)| k));
+            assert_eq!(keys.count(), SIZE as usize)
+        })
+    });
+}
+
+fn sled_relaxed_scan(c: &mut Criterion) {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+    const SIZE: u32 = 100_000;
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+
+    db.bulk_load((0..SIZE).map(|i| (i.to_be_bytes(), vec![0; 16]))).unwrap();
+
+    // a normal scan takes the concurrency control for each
+    // item, and a relaxed one skips it
+    c.bench_function("scan of 100k keys", |b| {
+        b.iter(|| assert_eq!(db.iter().count(), SIZE as usize))
+    });
+    c.bench_function("relaxed scan of 100k keys", |b| {
+        b.iter(|| assert_eq!(db.iter().relaxed().count(), SIZE as usize))
+    });
+
+    // batches hold the concurrency control exclusively while
+    // they are applied, which normal scans wait for
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let db = db.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(SeqCst) {
+                let mut batch = sled::Batch::default();
+                for _ in 0..16 {
+                    batch.insert(&(i % SIZE).to_be_bytes(), vec![1; 16]);
+                    i += 1;
+                }
+                db.apply_batch(batch).unwrap();
+            }
+        })
+    };
+
+    c.bench_function("scan of 100k keys while batches are applied", |b| {
+        b.iter(|| assert_eq!(db.iter().count(), SIZE as usize))
+    });
+    c.bench_function(
+        "relaxed scan of 100k keys while batches are applied",
+        |b| b.iter(|| assert_eq!(db.iter().relaxed().count(), SIZE as usize)),
+    );
+
+    stop.store(true, SeqCst);
+    writer.join().unwrap();
+}
+
+fn sled_prefix_scan(c: &mut Criterion) {
+    use sled::ControlFlow;
+
+    const PREFIXES: u32 = 4;
+    const PER_PREFIX: u32 = 250_000;
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open().unwrap();
+
+    db.bulk_load((0..PREFIXES * PER_PREFIX).map(|i| {
+        let mut key = vec![(i / PER_PREFIX) as u8];
+        key.extend_from_slice(&i.to_be_bytes());
+        (key, vec![0; 16])
+    }))
+    .unwrap();
+
+    c.bench_function("iterate over a prefix of 250k keys", |b| {
+        b.iter(|| {
+            let mut bytes = 0;
+            for res in db.scan_prefix([1]) {
+                let (k, v) = res.unwrap();
+                bytes += k.len() + v.len();
+            }
+            assert_eq!(bytes, PER_PREFIX as usize * 21)
+        })
+    });
+
+    c.bench_function("for_each_prefix over a prefix of 250k keys", |b| {
+        b.iter(|| {
+            let mut bytes = 0;
+            db.for_each_prefix([1], |k, v| {
+                bytes += k.len() + v.len();
+                ControlFlow::Continue
+            })
+            .unwrap();
+            assert_eq!(bytes, PER_PREFIX as usize * 21)
+        })
+    });
+}
+
+fn sled_hot_key_writes(c: &mut Criterion) {
+    const WRITES: u32 = 1000;
+    const HOT_KEYS: u32 = 16;
+
+    let mut bench = |coalesce_writes: bool| {
+        let db = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .coalesce_writes(coalesce_writes)
+            .open()
+            .unwrap();
+
+        // criterion does not measure bytes, so the write
+        // amplification is printed along with the timings
+        let mut flushes = 0;
+        let mut flushed_bytes = 0;
+        let name = format!(
+            "{} writes to {} hot keys, then flush, coalesce_writes: {}",
+            WRITES, HOT_KEYS, coalesce_writes
+        );
+        c.bench_function(&name, |b| {
+            b.iter(|| {
+                for i in 0..WRITES {
+                    db.insert((i % HOT_KEYS).to_be_bytes(), &i.to_be_bytes())
+                        .unwrap();
+                }
+                flushed_bytes += db.flush().unwrap();
+                flushes += 1;
+            })
+        });
+        // nothing ran if the benchmark was filtered out
+        if flushes > 0 {
+            println!(
+                "coalesce_writes: {} logged {} bytes per {} writes",
+                coalesce_writes,
+                flushed_bytes / flushes,
+                WRITES
+            );
+        }
+    };
+
+    bench(false);
+    bench(true);
+}
+
+fn sled_contended_cas(c: &mut Criterion) {
+    use sled::IVec;
+
+    const THREADS: usize = 4;
+    const SWAPS: usize = 1000;
+
+    // every thread swaps its own value into the same key, retrying
+    // with the current value after each conflict. The proposed value
+    // is either copied from a slice on every attempt, or converted
+    // into an `IVec` once and passed by reference.
+    let db = Config::new().temporary(true).open().unwrap();
+    for &by_ref in &[false, true] {
+        let name = format!(
+            "{} threads swapping 4kb values into one key, by_ref: {}",
+            THREADS, by_ref
+        );
+        c.bench_function(&name, |b| {
+            b.iter(|| {
+                let threads: Vec<_> = (0..THREADS)
+                    .map(|t| {
+                        let db = db.clone();
+                        std::thread::spawn(move || {
+                            let value = vec![t as u8; 4096];
+                            let proposed = IVec::from(value.as_slice());
+                            for _ in 0..SWAPS {
+                                let mut current = db.get(b"k").unwrap();
+                                loop {
+                                    let res = if by_ref {
+                                        db.compare_and_swap(
+                                            b"k",
+                                            current.as_ref(),
+                                            Some(&proposed),
+                                        )
+                                    } else {
+                                        db.compare_and_swap(
+                                            b"k",
+                                            current.as_ref(),
+                                            Some(value.as_slice()),
+                                        )
+                                    };
+                                    match res.unwrap() {
+                                        Ok(()) => break,
+                                        Err(e) => current = e.current,
+                                    }
+                                }
+                            }
+                        })
+                    })
+                    .collect();
+                for thread in threads {
+                    thread.join().unwrap();
+                }
+            })
+        });
+    }
+}
+
+fn sled_insert_many(c: &mut Criterion) {
+    use sled::IVec;
+
+    const ITEMS: u32 = 10_000;
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .open()
+        .unwrap();
+
+    // the same keys are overwritten by every iteration, so
+    // that both variants also read the previous values
+    let items: Vec<(IVec, IVec)> = (0..ITEMS)
+        .map(|i| (IVec::from(&i.to_be_bytes()), IVec::from(vec![7; 64])))
+        .collect();
+
+    c.bench_function(&format!("{} sorted inserts", ITEMS), |b| {
+        b.iter(|| {
+            for (key, value) in &items {
+                db.insert(key, value.clone()).unwrap();
+            }
+        })
+    });
+
+    c.bench_function(&format!("insert_many of {} sorted items", ITEMS), |b| {
+        b.iter(|| db.insert_many(items.iter().cloned()).unwrap())
+    });
+}
+
+fn sled_checksum_writes(c: &mut Criterion) {
+    use sled::ChecksumAlgorithm;
+
+    const WRITES: u32 = 1000;
+    const SIZE: u32 = 65536;
+
+    for &algorithm in &[
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::XxHash,
+        ChecksumAlgorithm::None,
+    ] {
+        let db = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .checksum_algorithm(algorithm)
+            .open()
+            .unwrap();
+
+        let value = vec![7_u8; 4096];
+        let name = format!(
+            "{} 4kb writes, then flush, checksum_algorithm: {:?}",
+            WRITES, algorithm
+        );
+        c.bench_function(&name, |b| {
+            b.iter(|| {
+                for _ in 0..WRITES {
+                    db.insert(random(SIZE).to_be_bytes(), value.as_slice())
+                        .unwrap();
+                }
+                db.flush().unwrap();
+            })
+        });
+    }
+}
+
+fn sled_scan_resistant_cache(c: &mut Criterion) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use sled::CacheMode;
+
+    const ITEMS: u32 = 500_000;
+    const HOT_KEYS: u32 = 4096;
+    const SCANNERS: usize = 3;
+
+    for &mode in &[CacheMode::Lru, CacheMode::ScanResistant] {
+        // the tree is several times larger than the cache, so
+        // each full scan pages in more than the cache holds,
+        // while the pages of the hot keys take about half of it
+        let db = Config::new()
+            .temporary(true)
+            .cache_capacity(32 * 1024 * 1024)
+            .cache_mode(mode)
+            .open()
+            .unwrap();
+
+        let value = vec![7_u8; 256];
+        for i in 0..ITEMS {
+            db.insert(i.to_be_bytes(), value.as_slice()).unwrap();
+        }
+        db.flush().unwrap();
+
+        // the hot keys are spread over the whole keyspace so
+        // that each of them is on its own page
+        let hot_key = |i: u32| (i * (ITEMS / HOT_KEYS)).to_be_bytes();
+        for i in 0..HOT_KEYS {
+            db.get(hot_key(i)).unwrap().unwrap();
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let scanners: Vec<_> = (0..SCANNERS)
+            .map(|_| {
+                let db = db.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        for item in db.iter() {
+                            item.unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // the hot keys are read in rounds with pauses in
+        // between, during which the scans keep paging in
+        // pages, and only the rounds are measured
+        let name = format!(
+            "get {} hot keys during {} full scans, cache_mode: {:?}",
+            HOT_KEYS, SCANNERS, mode
+        );
+        c.bench_function(&name, |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::from_secs(0);
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    for i in 0..HOT_KEYS {
+                        db.get(hot_key(i)).unwrap().unwrap();
+                    }
+                    elapsed += start.elapsed();
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                elapsed
+            })
+        });
+
+        done.store(true, Ordering::Relaxed);
+        for scanner in scanners {
+            scanner.join().unwrap();
+        }
+    }
+}
+
+fn sled_flush_syncs_across_trees(c: &mut Criterion) {
+    use std::time::Duration;
+
+    const WRITERS: usize = 10;
+    const WRITES: usize = 1000;
+
+    // the same writes are spread over 1 or 100 trees, which
+    // share one log, so both flush with the same syncs
+    for &tree_count in &[1_usize, 100] {
+        let path = format!("flush_syncs_{}_trees", tree_count);
+        let _ = std::fs::remove_dir_all(&path);
+        let db = Config::new().path(&path).flush_every_ms(None).open().unwrap();
+        let trees: Vec<sled::Tree> = (0..tree_count)
+            .map(|i| db.open_tree(i.to_be_bytes()).unwrap())
+            .collect();
+
+        let mut flushes = 0;
+        let mut syncs = 0;
+        let name = format!(
+            "flush {} writes from {} threads to {} trees",
+            WRITERS * WRITES,
+            WRITERS,
+            tree_count
+        );
+        c.bench_function(&name, |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::from_secs(0);
+                for _ in 0..iters {
+                    let writers: Vec<_> = (0..WRITERS)
+                        .map(|w| {
+                            let trees = trees.clone();
+                            std::thread::spawn(move || {
+                                for i in 0..WRITES {
+                                    let tree = &trees[(w + i) % trees.len()];
+                                    tree.insert(
+                                        (w * WRITES + i).to_be_bytes(),
+                                        b"v",
+                                    )
+                                    .unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    for writer in writers {
+                        writer.join().unwrap();
+                    }
+
+                    let start = Instant::now();
+                    let report = db.flush_detailed().unwrap();
+                    elapsed += start.elapsed();
+                    flushes += 1;
+                    syncs += report.syncs;
+                }
+                elapsed
+            })
+        });
+        // nothing ran if the benchmark was filtered out
+        if flushes > 0 {
+            println!(
+                "{} trees: {:.2} log syncs per flush",
+                tree_count,
+                syncs as f64 / flushes as f64
+            );
+        }
+
+        drop((trees, db));
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
+
+fn ivec_from_large_values(c: &mut Criterion) {
+    use std::sync::Arc;
+
+    use sled::IVec;
+
+    // Converting from a slice copies the value into sled's own
+    // allocation, while an `Arc<[u8]>` or a `Bytes` is shared.
+    for &size in &[4096, 65536, 1024 * 1024] {
+        let value = vec![7_u8; size];
+        let arc: Arc<[u8]> = value.clone().into();
+        let bytes = bytes::Bytes::from(value.clone());
+
+        c.bench_function(&format!("IVec from {}b slice", size), |b| {
+            b.iter(|| IVec::from(value.as_slice()))
+        });
+        c.bench_function(&format!("IVec from {}b Arc<[u8]>", size), |b| {
+            b.iter(|| IVec::from(arc.clone()))
+        });
+        c.bench_function(&format!("IVec from {}b Bytes", size), |b| {
+            b.iter(|| IVec::from(bytes.clone()))
+        });
+    }
+}
+
 fn sled_empty_opens(c: &mut Criterion) {
     let _ = std::fs::remove_dir_all("empty_opens");
     c.bench_function("empty opens", |b| {
@@ -160,6 +714,91 @@ fn sled_empty_opens(c: &mut Criterion) {
     let _ = std::fs::remove_dir_all("empty_opens");
 }
 
+/// Cold opens of a database of about 2gb, which was either
+/// closed with `Db::close`, so that `open` only reads the
+/// snapshot that `close` brought up to date, or just
+/// dropped, so that `open` also reads all of the log. As
+/// `open` brings the snapshot up to date as well, the stale
+/// snapshot of the dropped database is put back before each
+/// iteration.
+fn sled_cold_opens(c: &mut Criterion) {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    const ITEMS: u32 = 2 * 1024 * 1024;
+
+    fn snapshot_files(path: &str) -> Vec<PathBuf> {
+        std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("snap.")
+            })
+            .collect()
+    }
+
+    let mut group = c.benchmark_group("cold opens of a 2gb db");
+    group.sample_size(10);
+
+    for &closed in &[true, false] {
+        let path =
+            if closed { "cold_opens_closed" } else { "cold_opens_dropped" };
+        let _ = std::fs::remove_dir_all(path);
+        let config = Config::new().path(path).flush_every_ms(None);
+
+        let db = config.open().unwrap();
+        let value = vec![0; 1024];
+        for i in 0..ITEMS {
+            db.insert(&i.to_be_bytes(), &*value).unwrap();
+        }
+        if closed {
+            db.close().unwrap();
+        } else {
+            db.flush().unwrap();
+            drop(db);
+        }
+
+        let stale: Vec<(PathBuf, Vec<u8>)> = snapshot_files(path)
+            .into_iter()
+            .map(|file| {
+                let data = std::fs::read(&file).unwrap();
+                (file, data)
+            })
+            .collect();
+
+        let name = if closed { "after close" } else { "after drop" };
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::default();
+                for _ in 0..iters {
+                    if !closed {
+                        for file in snapshot_files(path) {
+                            std::fs::remove_file(file).unwrap();
+                        }
+                        for (file, data) in &stale {
+                            std::fs::write(file, data).unwrap();
+                        }
+                    }
+
+                    let start = Instant::now();
+                    let db = config.open().unwrap();
+                    elapsed += start.elapsed();
+
+                    if closed {
+                        db.close().unwrap();
+                    }
+                }
+                elapsed
+            })
+        });
+
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    group.finish();
+}
+
 fn tx_sled_bulk_load(c: &mut Criterion) {
     let mut bench = |key_len, val_len| {
         let db = Config::new()
@@ -558,7 +1197,22 @@ criterion_group!(
     tx_sled_random_crud,
     persy_random_crud,
     //
+    sled_fold_range,
+    sled_reverse_scan,
+    sled_key_scan,
+    sled_relaxed_scan,
+    sled_prefix_scan,
+    sled_multi_get,
+    sled_hot_key_writes,
+    sled_contended_cas,
+    sled_insert_many,
+    sled_checksum_writes,
+    sled_scan_resistant_cache,
+    sled_flush_syncs_across_trees,
+    ivec_from_large_values,
+    //
     sled_empty_opens,
     persy_empty_opens,
+    sled_cold_opens,
 );
 criterion_main!(benches);
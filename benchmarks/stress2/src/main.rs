@@ -40,7 +40,8 @@ Usage: stress [--threads=<#>] [--burn-in] [--duration=<s>] \
     [--entries=<n>] \
     [--sequential] \
     [--total-ops=<n>] \
-    [--flush-every=<ms>]
+    [--flush-every=<ms>] \
+    [--io-threads=<#>]
 
 Options:
     --threads=<#>      Number of threads [default: 4].
@@ -58,6 +59,7 @@ Options:
     --sequential       Run the test in sequential mode instead of random.
     --total-ops=<n>    Stop test after executing a total number of operations.
     --flush-every=<m>  Flush and sync the database every ms [default: 200].
+    --io-threads=<#>   Threads that write the log, unlimited if unset.
 ";
 
 #[derive(Clone, Copy)]
@@ -77,6 +79,7 @@ struct Args {
     sequential: bool,
     total_ops: Option<usize>,
     flush_every: u64,
+    io_threads: Option<usize>,
 }
 
 impl Default for Args {
@@ -97,6 +100,7 @@ impl Default for Args {
             sequential: false,
             total_ops: None,
             flush_every: 200,
+            io_threads: None,
         }
     }
 }
@@ -131,6 +135,7 @@ impl Args {
                 "sequential" => args.sequential = true,
                 "total-ops" => args.total_ops = Some(parse(&mut splits)),
                 "flush-every" => args.flush_every = parse(&mut splits),
+                "io-threads" => args.io_threads = Some(parse(&mut splits)),
                 other => panic!("unknown option: {}, {}", other, USAGE),
             }
         }
@@ -288,11 +293,15 @@ fn main() {
 
     let shutdown = Arc::new(AtomicBool::new(false));
 
-    let config = sled::Config::new()
+    let mut config = sled::Config::new()
         .cache_capacity(256 * 1024 * 1024)
         .flush_every_ms(Some(args.flush_every))
         .print_profile_on_drop(true);
 
+    if let Some(io_threads) = args.io_threads {
+        config = config.io_threads(io_threads);
+    }
+
     let tree = Arc::new(config.open().unwrap());
     tree.set_merge_operator(concatenate_merge);
 
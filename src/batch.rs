@@ -58,3 +58,72 @@ impl Batch {
         self.writes.insert(IVec::from(key), None);
     }
 }
+
+/// A batch of updates to several `Tree`s, keyed by tree name,
+/// that `Db::apply_batch` applies atomically.
+///
+/// This is a lighter alternative to a transaction for writes
+/// that don't depend on reading the current state.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use sled::{Config, MultiTreeBatch};
+///
+/// let db = Config::new().temporary(true).open()?;
+/// let users = db.open_tree("users")?;
+/// users.insert("alice", "pending")?;
+///
+/// let mut batch = MultiTreeBatch::default();
+/// batch.insert("users", "alice", "active");
+/// batch.insert("emails", "alice@example.com", "alice");
+/// batch.remove("pending", "alice");
+///
+/// // either every write lands, or none do
+/// db.apply_batch(batch)?;
+///
+/// assert_eq!(users.get("alice")?, Some("active".into()));
+/// let emails = db.open_tree("emails")?;
+/// assert_eq!(emails.get("alice@example.com")?, Some("alice".into()));
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MultiTreeBatch {
+    pub(crate) trees: Map<IVec, Batch>,
+}
+
+impl MultiTreeBatch {
+    /// Set a key in the named tree to a new value
+    pub fn insert<N, K, V>(&mut self, tree: N, key: K, value: V)
+    where
+        IVec: From<N> + From<K> + From<V>,
+    {
+        self.tree(tree).insert(key, value);
+    }
+
+    /// Remove a key from the named tree
+    pub fn remove<N, K>(&mut self, tree: N, key: K)
+    where
+        IVec: From<N> + From<K>,
+    {
+        self.tree(tree).remove(key);
+    }
+
+    fn tree<N>(&mut self, tree: N) -> &mut Batch
+    where
+        IVec: From<N>,
+    {
+        self.trees.entry(IVec::from(tree)).or_default()
+    }
+}
+
+/// A `Batch` converts into a `MultiTreeBatch` for the default
+/// tree, so that it can still be passed to `Db::apply_batch`.
+impl From<Batch> for MultiTreeBatch {
+    fn from(batch: Batch) -> MultiTreeBatch {
+        let mut trees = Map::default();
+        trees.insert(IVec::from(DEFAULT_TREE_ID), batch);
+        MultiTreeBatch { trees }
+    }
+}
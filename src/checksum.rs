@@ -0,0 +1,247 @@
+//! The checksums of log messages, see
+//! `Config::checksum_algorithm`.
+
+use std::convert::TryFrom;
+
+use crate::*;
+
+impl ChecksumAlgorithm {
+    /// The name of the algorithm in the configuration file.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "crc32",
+            ChecksumAlgorithm::Crc32c => "crc32c",
+            ChecksumAlgorithm::XxHash => "xxhash",
+            ChecksumAlgorithm::None => "none",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<ChecksumAlgorithm> {
+        match name {
+            "crc32" => Some(ChecksumAlgorithm::Crc32),
+            "crc32c" => Some(ChecksumAlgorithm::Crc32c),
+            "xxhash" => Some(ChecksumAlgorithm::XxHash),
+            "none" => Some(ChecksumAlgorithm::None),
+            _ => None,
+        }
+    }
+
+    /// Returns the checksum of a log message, covering its
+    /// body and its header after the checksum itself.
+    pub(crate) fn message_checksum(self, header: &[u8], body: &[u8]) -> u32 {
+        let checksum = match self {
+            ChecksumAlgorithm::Crc32 => {
+                let mut hasher = crc32fast::Hasher::new();
+                hasher.update(body);
+                hasher.update(&header[4..]);
+                hasher.finalize()
+            }
+            ChecksumAlgorithm::Crc32c => {
+                let crc = crc32c_update(0xFFFF_FFFF, body);
+                crc32c_update(crc, &header[4..]) ^ 0xFFFF_FFFF
+            }
+            // the header is hashed with the hash of the body
+            // as its seed, rather than copying both into one
+            // buffer
+            ChecksumAlgorithm::XxHash => xxh32(&header[4..], xxh32(body, 0)),
+            ChecksumAlgorithm::None => return 0,
+        };
+        checksum ^ 0xFFFF_FFFF
+    }
+}
+
+/// Feeds `buf` into a CRC32C (Castagnoli) whose state is
+/// `crc`, using the SSE4.2 `crc32` instruction if the CPU
+/// supports it.
+fn crc32c_update(crc: u32, buf: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            #[allow(unsafe_code)]
+            unsafe {
+                return crc32c_sse42(crc, buf);
+            }
+        }
+    }
+
+    crc32c_table(crc, buf)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+#[allow(unsafe_code, clippy::cast_possible_truncation)]
+unsafe fn crc32c_sse42(crc: u32, buf: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut chunks = buf.chunks_exact(8);
+    let mut crc64 = u64::from(crc);
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(<[u8; 8]>::try_from(chunk).unwrap());
+        crc64 = _mm_crc32_u64(crc64, word);
+    }
+
+    // the instruction leaves the upper half zeroed
+    let mut crc32c = crc64 as u32;
+    for &byte in chunks.remainder() {
+        crc32c = _mm_crc32_u8(crc32c, byte);
+    }
+    crc32c
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn crc32c_table(mut crc: u32, buf: &[u8]) -> u32 {
+    static TABLE: Lazy<[u32; 256], fn() -> [u32; 256]> =
+        Lazy::new(init_crc32c_table);
+
+    let table = &*TABLE;
+    for &byte in buf {
+        crc = table[usize::from(crc as u8 ^ byte)] ^ (crc >> 8);
+    }
+    crc
+}
+
+fn init_crc32c_table() -> [u32; 256] {
+    // the reflected Castagnoli polynomial
+    const POLY: u32 = 0x82F6_3B78;
+
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = u32::try_from(i).unwrap();
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+const XXH_PRIME_1: u32 = 2_654_435_761;
+const XXH_PRIME_2: u32 = 2_246_822_519;
+const XXH_PRIME_3: u32 = 3_266_489_917;
+const XXH_PRIME_4: u32 = 668_265_263;
+const XXH_PRIME_5: u32 = 374_761_393;
+
+fn read_u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[..4]).unwrap())
+}
+
+fn xxh32_round(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(XXH_PRIME_2))
+        .rotate_left(13)
+        .wrapping_mul(XXH_PRIME_1)
+}
+
+/// The 32-bit xxHash of `buf`.
+#[allow(clippy::cast_possible_truncation)]
+fn xxh32(buf: &[u8], seed: u32) -> u32 {
+    let mut stripes = buf.chunks_exact(16);
+
+    let mut hash = if buf.len() >= 16 {
+        let mut v1 = seed.wrapping_add(XXH_PRIME_1).wrapping_add(XXH_PRIME_2);
+        let mut v2 = seed.wrapping_add(XXH_PRIME_2);
+        let mut v3 = seed;
+        let mut v4 = seed.wrapping_sub(XXH_PRIME_1);
+        for stripe in &mut stripes {
+            v1 = xxh32_round(v1, read_u32_le(&stripe[0..]));
+            v2 = xxh32_round(v2, read_u32_le(&stripe[4..]));
+            v3 = xxh32_round(v3, read_u32_le(&stripe[8..]));
+            v4 = xxh32_round(v4, read_u32_le(&stripe[12..]));
+        }
+        v1.rotate_left(1)
+            .wrapping_add(v2.rotate_left(7))
+            .wrapping_add(v3.rotate_left(12))
+            .wrapping_add(v4.rotate_left(18))
+    } else {
+        seed.wrapping_add(XXH_PRIME_5)
+    };
+
+    // the length is mixed in modulo 2^32
+    hash = hash.wrapping_add(buf.len() as u32);
+
+    let mut words = stripes.remainder().chunks_exact(4);
+    for word in &mut words {
+        hash = hash
+            .wrapping_add(read_u32_le(word).wrapping_mul(XXH_PRIME_3))
+            .rotate_left(17)
+            .wrapping_mul(XXH_PRIME_4);
+    }
+    for &byte in words.remainder() {
+        hash = hash
+            .wrapping_add(u32::from(byte).wrapping_mul(XXH_PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(XXH_PRIME_1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(XXH_PRIME_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(XXH_PRIME_3);
+    hash ^ (hash >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_reference_values() {
+        let crc32c = |buf: &[u8]| crc32c_update(0xFFFF_FFFF, buf) ^ 0xFFFF_FFFF;
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8A91_36AA);
+
+        // the instruction and the table agree on every
+        // length, including the bytes after the last word
+        let buf: Vec<u8> = (0..100_u8).collect();
+        for len in 0..buf.len() {
+            let table = crc32c_table(0xFFFF_FFFF, &buf[..len]);
+            assert_eq!(crc32c_update(0xFFFF_FFFF, &buf[..len]), table);
+        }
+    }
+
+    #[test]
+    fn xxh32_reference_values() {
+        assert_eq!(xxh32(b"", 0), 0x02CC_5D05);
+        assert_eq!(xxh32(b"a", 0), 0x550D_7456);
+        assert_eq!(xxh32(b"abc", 0), 0x32D1_53FF);
+        assert_eq!(
+            xxh32(b"Nobody inspects the spammish repetition", 0),
+            0xE229_3B2F
+        );
+    }
+
+    #[test]
+    fn message_checksums_cover_header_and_body() {
+        let header = [0, 0, 0, 0, 1, 2, 3];
+        let body = [4, 5, 6];
+        for &algorithm in &[
+            ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Crc32c,
+            ChecksumAlgorithm::XxHash,
+        ] {
+            let checksum = algorithm.message_checksum(&header, &body);
+
+            // the bytes of the checksum itself are skipped
+            let mut other_header = header;
+            other_header[0] = 9;
+            assert_eq!(
+                algorithm.message_checksum(&other_header, &body),
+                checksum
+            );
+
+            other_header[4] = 9;
+            assert_ne!(
+                algorithm.message_checksum(&other_header, &body),
+                checksum
+            );
+            assert_ne!(algorithm.message_checksum(&header, &[4, 5]), checksum);
+        }
+
+        // the original format of the log
+        assert_eq!(
+            ChecksumAlgorithm::Crc32.message_checksum(&header, &body),
+            crc32(&[4, 5, 6, 1, 2, 3]) ^ 0xFFFF_FFFF
+        );
+        assert_eq!(ChecksumAlgorithm::None.message_checksum(&header, &body), 0);
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use crate::*;
+
+/// A source of monotonic time, which can be replaced with
+/// a `VirtualClock` by `Config::test_clock` to make the
+/// time-dependent behavior of sled deterministic in tests.
+pub trait Clock: Send + Sync + Debug {
+    /// Returns the time that has passed since an arbitrary
+    /// fixed starting point. This must never go backwards.
+    fn now(&self) -> Duration;
+}
+
+/// A `Clock` that only moves when `advance` is called.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use sled::{Clock, VirtualClock};
+///
+/// let clock = VirtualClock::new();
+/// assert_eq!(clock.now(), Duration::from_secs(0));
+///
+/// clock.advance(Duration::from_millis(500));
+/// assert_eq!(clock.now(), Duration::from_millis(500));
+/// ```
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    nanos: AtomicU64,
+}
+
+impl VirtualClock {
+    /// Creates a new `VirtualClock` starting at zero.
+    pub fn new() -> VirtualClock {
+        VirtualClock::default()
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let nanos = u64::try_from(by.as_nanos()).unwrap();
+        self.nanos.fetch_add(nanos, SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(SeqCst))
+    }
+}
@@ -7,6 +7,7 @@ use std::{
     ops::Deref,
     path::{Path, PathBuf},
     sync::atomic::AtomicUsize,
+    time::{Duration, Instant},
 };
 
 use crate::pagecache::{arr_to_u32, u32_to_arr, Lsn};
@@ -14,6 +15,17 @@ use crate::*;
 
 const DEFAULT_PATH: &str = "default.sled";
 
+/// The file that marks the directory of a temporary database,
+/// so that `Db::cleanup_temporaries` can find it if the
+/// process dies before removing it.
+const TEMPORARY_MARKER: &str = "temporary";
+
+/// The largest key or value that sled supports. A leaf
+/// holding an item of this size still serializes to less
+/// than 4gb, so that it can be addressed on 32-bit
+/// platforms.
+const MAX_ITEM_SIZE: u64 = 1 << 30;
+
 /// The high-level database mode, according to
 /// the trade-offs of the RUM conjecture.
 #[derive(Debug, Clone, Copy)]
@@ -31,12 +43,207 @@ pub enum Mode {
     HighThroughput,
 }
 
+/// How the log is made durable when sled syncs it. The
+/// options trade protection against power failure for
+/// speed. The default is `SyncFileRange` on linux,
+/// `FullFsync` on macOS and iOS, and `Fsync` elsewhere.
+/// None of them matter for crashes of the process alone,
+/// which never lose data that was flushed.
+///
+/// The strategy applies to the default file backend.
+/// A custom `IoBackend` decides for itself how to
+/// implement `sync_all` and `sync_range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStrategy {
+    /// Use `sync_file_range` on linux to sync only the
+    /// part of the log that was written, and `Fsync`
+    /// elsewhere. This is the cheapest option, but it
+    /// flushes neither the metadata of the file nor the
+    /// write cache of the drive, so flushed data may
+    /// be lost on power failure.
+    SyncFileRange,
+    /// Use `fdatasync`, which flushes the data of the file
+    /// and the metadata needed to read it back, such as its
+    /// length, but skips metadata such as timestamps. On
+    /// linux it also flushes the write cache of the drive,
+    /// so it survives power failure more cheaply than
+    /// `Fsync`. This is the same as `Fsync` on macOS and
+    /// iOS, and as `FlushFileBuffers` on windows.
+    Fdatasync,
+    /// Use `fsync`, or `FlushFileBuffers` on windows, which
+    /// flushes the data and all metadata of the file. On
+    /// macOS and iOS this only hands the data to the drive,
+    /// which may keep it in a volatile write cache, so
+    /// flushed data may be lost on power failure there.
+    Fsync,
+    /// Use `fcntl(F_FULLFSYNC)` on macOS and iOS, which also
+    /// tells the drive to flush its write cache. This is the
+    /// only option that survives power failure there, and
+    /// it is much slower than `Fsync`. On filesystems that
+    /// do not support it, and on other platforms, this is
+    /// the same as `Fsync`.
+    FullFsync,
+}
+
+impl Default for SyncStrategy {
+    fn default() -> SyncStrategy {
+        if cfg!(target_os = "linux") {
+            SyncStrategy::SyncFileRange
+        } else if cfg!(any(target_os = "macos", target_os = "ios")) {
+            SyncStrategy::FullFsync
+        } else {
+            SyncStrategy::Fsync
+        }
+    }
+}
+
+/// What `open` does about pages that fail their checksum
+/// because the storage file was damaged, such as by a bad
+/// disk. The default is `Strict`.
+///
+/// Only the pages of trees are checked, including the
+/// internal ones that route lookups. Corruption in the
+/// metadata that locates the trees always fails `open`.
+/// The log is always recovered up to its first damaged
+/// message after the latest snapshot, because the messages
+/// after it describe changes to state that can no longer
+/// be verified. Damage to messages that the snapshot
+/// already covers is found when their pages are read.
+///
+/// `BestEffort` and `ReadOnlyAfterCorruption` read every
+/// page of every tree while opening, so `open` takes time
+/// proportional to the size of the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Open without checking the pages of trees. Reading a
+    /// damaged page fails with `Error::Corruption`, every
+    /// time it is read.
+    Strict,
+    /// Replace every damaged page with an empty one that
+    /// covers the same range of keys, and log each range
+    /// that was lost. Afterwards the `Db` behaves as if
+    /// the keys in those ranges had been removed, and
+    /// `Db::lost_data_report` lists the ranges. Keys
+    /// outside of them keep their values, and no read
+    /// returns a value that did not pass its checksum.
+    /// With `Config::dedup_values`, reading a key whose
+    /// shared value was lost fails with an error instead.
+    BestEffort,
+    /// Check every page, and if any is damaged, list the
+    /// ranges it covers in `Db::lost_data_report` and
+    /// refuse all writes with `Error::Unsupported`, so
+    /// that the damaged file can be copied or inspected
+    /// before anything changes it. Reading a damaged page
+    /// still fails with `Error::Corruption`.
+    ReadOnlyAfterCorruption,
+}
+
+/// What writes do once writing the log fails because the
+/// disk is full (`ENOSPC`, or `ERROR_DISK_FULL` on
+/// windows). The default is `Error`. This is separate from
+/// `Config::min_free_space`, which refuses writes before
+/// the disk fills up.
+///
+/// With `Reject` and `Block`, the writes that were already
+/// accepted when the disk filled up are kept in memory and
+/// written again every 100ms until there is room for them,
+/// so nothing that a write returned successfully for is
+/// lost, unless the process exits first. Reads keep
+/// working, and so do flushes, although they only return
+/// once the pending writes have been written. To recover,
+/// free space on the volume, such as by removing other
+/// files or growing it, and the `Db` continues on its own.
+/// Removing keys does not free space while the disk is
+/// full, because removals are written to the log as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFull {
+    /// Fail the write that hit the full disk, and every
+    /// operation after it that needs to write or flush the
+    /// log, with `Error::Io`. The `Db` stays in this state
+    /// until it is reopened, which replays the log up to
+    /// the last write that succeeded.
+    Error,
+    /// Refuse writes with `Error::Io`, carrying the same
+    /// error that the OS returned, until the writes that
+    /// were already accepted have been written. Refused
+    /// writes have no effect.
+    Reject,
+    /// Park writers until the writes that were already
+    /// accepted have been written, then let them proceed.
+    /// Writers may wait for as long as the disk stays full.
+    Block,
+}
+
+/// How the page cache chooses which pages to evict once it
+/// is full. The default is `Lru`. See `Config::cache_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Evict the least recently used page. A scan over more
+    /// pages than the cache holds, such as iterating over a
+    /// whole large `Tree`, evicts every page that it does not
+    /// read itself, including those that other operations
+    /// use all the time.
+    Lru,
+    /// Keep the pages that were used more than once apart
+    /// from those that were only used once, and evict the
+    /// latter first, like a segmented LRU. Pages that are
+    /// read by iterators, such as those returned by
+    /// `Tree::iter` and `Tree::range`, are never counted as
+    /// used more than once for this, so a large scan only
+    /// evicts pages that were used once, and the pages that
+    /// other operations keep using stay cached.
+    ///
+    /// The pages that were used more than once take up to
+    /// 80% of the capacity. Pages that are new to the cache
+    /// are evicted sooner than with `Lru`, even if they are
+    /// about to be used again, when most of the cache is
+    /// taken by pages that were used more than once.
+    ScanResistant,
+}
+
+/// How the messages of the log are checksummed, so that
+/// recovery can find where a crash interrupted writing the
+/// log, and so that damaged messages are never read back.
+/// The default is `Crc32`. See `Config::checksum_algorithm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC32 (IEEE), using the SIMD instructions of x86 and
+    /// ARM where they are available. This is the format of
+    /// databases created before the algorithm could be
+    /// chosen.
+    Crc32,
+    /// CRC32C (Castagnoli), using the SSE4.2 `crc32`
+    /// instruction on `x86_64` CPUs that have it, which is
+    /// the fastest option there. Elsewhere, including on
+    /// ARM, it is computed a byte at a time from a table,
+    /// which is much slower than `Crc32`.
+    Crc32c,
+    /// The 32-bit xxHash, which is fast on every platform
+    /// without any special instructions, but detects fewer
+    /// kinds of damage than a CRC is guaranteed to.
+    XxHash,
+    /// Don't checksum the log at all.
+    ///
+    /// **Warning: recovery then trusts every message in the
+    /// log.** A crash while writing can leave a partially
+    /// written message at the end of the log, and a damaged
+    /// disk can change a message, and either is replayed as
+    /// if it were intact, which can lose or corrupt any
+    /// data in the database, or make it impossible to open.
+    /// Only use this if the storage below sled already
+    /// guarantees that writes are atomic and that reads
+    /// return what was written.
+    None,
+}
+
 /// A persisted configuration about high-level
 /// storage file information
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 struct StorageParameters {
     pub segment_size: usize,
     pub use_compression: bool,
+    pub dedup_values: bool,
+    pub checksum_algorithm: ChecksumAlgorithm,
     pub version: (usize, usize),
 }
 
@@ -47,6 +254,13 @@ impl StorageParameters {
         writeln!(&mut out, "segment_size: {}", self.segment_size).unwrap();
         writeln!(&mut out, "use_compression: {}", self.use_compression)
             .unwrap();
+        writeln!(&mut out, "dedup_values: {}", self.dedup_values).unwrap();
+        writeln!(
+            &mut out,
+            "checksum_algorithm: {}",
+            self.checksum_algorithm.name()
+        )
+        .unwrap();
         writeln!(&mut out, "version: {}.{}", self.version.0, self.version.1)
             .unwrap();
 
@@ -119,6 +333,35 @@ impl StorageParameters {
             return Err(Error::corruption(None));
         };
 
+        // databases created before this was persisted
+        // never deduplicated values
+        let dedup_values: bool = if let Some(raw) = lines.get("dedup_values")
+        {
+            if let Ok(parsed) = raw.parse() {
+                parsed
+            } else {
+                error!("failed to parse dedup_values value: {}", raw);
+                return Err(Error::corruption(None));
+            }
+        } else {
+            false
+        };
+
+        // databases created before this was persisted
+        // always used crc32
+        let checksum_algorithm = if let Some(raw) =
+            lines.get("checksum_algorithm")
+        {
+            if let Some(parsed) = ChecksumAlgorithm::from_name(raw) {
+                parsed
+            } else {
+                error!("failed to parse checksum_algorithm value: {}", raw);
+                return Err(Error::corruption(None));
+            }
+        } else {
+            ChecksumAlgorithm::Crc32
+        };
+
         let version: (usize, usize) = if let Some(raw) = lines.get("version") {
             let mut split = raw.split('.');
             let major = if let Some(raw_major) = split.next() {
@@ -159,7 +402,13 @@ impl StorageParameters {
             return Err(Error::corruption(None));
         };
 
-        Ok(StorageParameters { segment_size, use_compression, version })
+        Ok(StorageParameters {
+            segment_size,
+            use_compression,
+            dedup_values,
+            checksum_algorithm,
+            version,
+        })
     }
 }
 
@@ -170,7 +419,7 @@ impl StorageParameters {
 /// ```
 /// let _config = sled::Config::default()
 ///     .path("/path/to/data".to_owned())
-///     .cache_capacity(10_000_000_000)
+///     .cache_capacity_bytes(10_000_000_000)
 ///     .flush_every_ms(Some(1000));
 /// ```
 #[derive(Default, Debug, Clone)]
@@ -190,6 +439,10 @@ pub struct Inner {
     #[doc(hidden)]
     pub cache_capacity: u64,
     #[doc(hidden)]
+    pub cache_capacity_fraction: Option<f64>,
+    #[doc(hidden)]
+    pub cache_mode: CacheMode,
+    #[doc(hidden)]
     pub flush_every_ms: Option<u64>,
     #[doc(hidden)]
     pub segment_size: usize,
@@ -198,12 +451,34 @@ pub struct Inner {
     #[doc(hidden)]
     pub create_new: bool,
     #[doc(hidden)]
+    pub must_exist: bool,
+    #[doc(hidden)]
+    pub min_free_space: u64,
+    #[doc(hidden)]
+    pub max_key_size: u64,
+    #[doc(hidden)]
+    pub max_value_size: u64,
+    #[doc(hidden)]
+    pub open_timeout: Option<Duration>,
+    #[doc(hidden)]
     pub mode: Mode,
     #[doc(hidden)]
+    pub sync_strategy: SyncStrategy,
+    #[doc(hidden)]
+    pub recovery_policy: RecoveryPolicy,
+    #[doc(hidden)]
+    pub on_disk_full: OnFull,
+    #[doc(hidden)]
     pub temporary: bool,
     #[doc(hidden)]
     pub use_compression: bool,
     #[doc(hidden)]
+    pub dedup_values: bool,
+    #[doc(hidden)]
+    pub checksum_algorithm: ChecksumAlgorithm,
+    #[doc(hidden)]
+    pub collect_latency_histograms: bool,
+    #[doc(hidden)]
     pub compression_factor: i32,
     #[doc(hidden)]
     pub print_profile_on_drop: bool,
@@ -212,7 +487,27 @@ pub struct Inner {
     #[doc(hidden)]
     pub version: (usize, usize),
     tmp_path: PathBuf,
+    pub(crate) io_backend: Option<std::sync::Arc<dyn IoBackend>>,
+    pub(crate) clock: Option<std::sync::Arc<dyn Clock>>,
+    pub(crate) flush_jitter: Duration,
+    pub(crate) flush_dirty_threshold: u64,
+    pub(crate) manual_flush: bool,
+    pub(crate) coalesce_writes: bool,
+    pub(crate) snapshot_after_ops: Option<u64>,
+    pub(crate) snapshot_interval: Option<Duration>,
+    pub(crate) snapshot_retention: usize,
+    pub(crate) separate_log_trees: Vec<IVec>,
+    pub(crate) max_db_size: Option<u64>,
+    pub(crate) eviction_policy: EvictionPolicy,
+    // the directory that `Config::open_at` was given, which
+    // stays open for as long as the database is
+    pub(crate) dir_fd: Option<std::sync::Arc<File>>,
+    // the files of an in-memory database, see `Config::in_memory`
+    pub(crate) mem_fs: Option<std::sync::Arc<files::MemFs>>,
     pub(crate) global_error: Arc<Atomic<Error>>,
+    pub(crate) disk_full: Arc<DiskFull>,
+    pub(crate) io_threads: Option<usize>,
+    pub(crate) io_queue: Arc<IoQueue>,
     #[cfg(feature = "event_log")]
     /// an event log for concurrent debugging
     pub event_log: Arc<event_log::EventLog>,
@@ -225,9 +520,30 @@ impl Default for Inner {
             path: PathBuf::from(DEFAULT_PATH),
             tmp_path: Config::gen_temp_path(),
             create_new: false,
+            must_exist: false,
+            min_free_space: 0,
+            max_key_size: 16 * 1024 * 1024, // 16mb
+            max_value_size: MAX_ITEM_SIZE,
+            // we block during testing because there are
+            // many filesystem race conditions that cause
+            // locks to be held for long periods of time,
+            // so we should wait on reopening files.
+            open_timeout: if cfg!(feature = "testing") {
+                None
+            } else {
+                Some(Duration::from_secs(0))
+            },
             cache_capacity: 1024 * 1024 * 1024, // 1gb
+            cache_capacity_fraction: None,
+            cache_mode: CacheMode::Lru,
             mode: Mode::LowSpace,
+            sync_strategy: SyncStrategy::default(),
+            recovery_policy: RecoveryPolicy::Strict,
+            on_disk_full: OnFull::Error,
             use_compression: false,
+            dedup_values: false,
+            checksum_algorithm: ChecksumAlgorithm::Crc32,
+            collect_latency_histograms: false,
             compression_factor: 5,
             temporary: false,
             version: crate_version(),
@@ -237,7 +553,24 @@ impl Default for Inner {
             print_profile_on_drop: false,
             flush_every_ms: Some(500),
             idgen_persist_interval: 1_000_000,
+            io_backend: None,
+            clock: None,
+            flush_jitter: Duration::from_secs(0),
+            flush_dirty_threshold: 0,
+            manual_flush: false,
+            coalesce_writes: false,
+            snapshot_after_ops: None,
+            snapshot_interval: None,
+            snapshot_retention: 2,
+            separate_log_trees: vec![],
+            max_db_size: None,
+            eviction_policy: EvictionPolicy::Lru,
+            dir_fd: None,
+            mem_fs: None,
             global_error: Arc::new(Atomic::default()),
+            disk_full: Arc::new(DiskFull::default()),
+            io_threads: None,
+            io_queue: Arc::new(IoQueue::default()),
             #[cfg(feature = "event_log")]
             event_log: Arc::new(crate::event_log::EventLog::default()),
         }
@@ -255,10 +588,78 @@ impl Inner {
         }
     }
 
+    pub(crate) fn separate_logs_path(&self) -> PathBuf {
+        self.get_path().join("separate_logs")
+    }
+
+    /// The configuration of the database that holds the
+    /// tree `name`, which has a log of its own. It is stored
+    /// in a directory named after the tree in hex, and shares
+    /// the error state of this database.
+    pub(crate) fn separate_log_config(&self, name: &[u8]) -> Config {
+        let dir: String = name.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let mut inner = self.clone();
+        inner.path = self.separate_logs_path().join(dir);
+        inner.create_new = false;
+        inner.must_exist = false;
+        inner.separate_log_trees = vec![];
+        #[cfg(feature = "event_log")]
+        {
+            inner.event_log = Arc::new(crate::event_log::EventLog::default());
+        }
+
+        Config(Arc::new(inner))
+    }
+
     pub(crate) fn blob_path(&self, id: Lsn) -> PathBuf {
         self.get_path().join("blobs").join(format!("{}", id))
     }
 
+    /// Runs `work` on the threadpool, or right away on the
+    /// calling thread if `Config::test_clock` is set, so that
+    /// no work happens in the background unless a test
+    /// drives it.
+    pub(crate) fn spawn<F, R>(&self, work: F) -> OneShot<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        if self.clock.is_some() {
+            let (promise_filler, promise) = OneShot::pair();
+            promise_filler.fill(work());
+            promise
+        } else {
+            threadpool::spawn(work)
+        }
+    }
+
+    /// Runs background IO, like writing the log, as limited
+    /// by `Config::io_threads`.
+    pub(crate) fn spawn_io<F, R>(&self, work: F) -> OneShot<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        match self.io_threads {
+            Some(0) => {
+                let (promise_filler, promise) = OneShot::pair();
+                promise_filler.fill(work());
+                promise
+            }
+            Some(threads) if self.clock.is_none() => {
+                IoQueue::spawn(&self.io_queue, threads, work)
+            }
+            _ => self.spawn(work),
+        }
+    }
+
+    /// Returns the time according to `Config::test_clock`,
+    /// or `None` if it is not set.
+    pub(crate) fn test_now(&self) -> Option<Duration> {
+        self.clock.as_ref().map(|clock| clock.now())
+    }
+
     fn db_path(&self) -> PathBuf {
         self.get_path().join("db")
     }
@@ -321,6 +722,607 @@ impl Config {
         self
     }
 
+    /// Use the provided `IoBackend` to read and write the
+    /// log instead of a file named `db` in the configured
+    /// path. The configured path is still used for the
+    /// configuration file, snapshots and blobs, and for
+    /// temporary databases it is still removed on drop.
+    ///
+    /// The backend is not locked against concurrent use by
+    /// other processes, and `create_new` and `must_exist`
+    /// only consider the configuration file when deciding
+    /// whether a database exists. It is not supported with
+    /// the `io_uring` feature.
+    ///
+    /// See the `IoBackend` documentation for the guarantees
+    /// that a backend must provide.
+    pub fn io_backend(
+        mut self,
+        backend: std::sync::Arc<dyn IoBackend>,
+    ) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.io_backend = Some(backend);
+        self
+    }
+
+    /// Keep every file of the database in memory, including
+    /// the log, which is held by an in-memory `IoBackend`, so
+    /// that nothing is read from or written to the filesystem.
+    /// The database is gone once it is dropped.
+    pub(crate) fn in_memory(mut self) -> Config {
+        let m = Arc::make_mut(&mut self.0);
+        m.mem_fs = Some(std::sync::Arc::new(files::MemFs::default()));
+        m.io_backend = Some(std::sync::Arc::new(files::MemFile::default()));
+        self
+    }
+
+    /// Refuse writes while fewer than `bytes` bytes are
+    /// free on the volume that holds the database, so that
+    /// the disk does not fill up partway through a write.
+    /// Refused writes return `Error::Io` with the same
+    /// error that the OS returns when writing to a full
+    /// disk (`ENOSPC`, or `ERROR_DISK_FULL` on windows),
+    /// and have no effect. Reads are not affected.
+    ///
+    /// The free space is measured at most every 100ms, so
+    /// the threshold should leave room for the writes that
+    /// may happen in between. Background work such as
+    /// flushing and segment cleaning is not refused. Use
+    /// `Db::free_space` to read the current value. Defaults
+    /// to 0, which disables the check. Only supported on
+    /// linux, macos and windows.
+    pub fn min_free_space(mut self, bytes: u64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.min_free_space = bytes;
+        self
+    }
+
+    /// Take a snapshot, as `Db::snapshot_now` does, once
+    /// `ops` writes have happened since the last one. Writes
+    /// are counted as by `Db::write_generation`, across all
+    /// trees. Disabled by default, and must be above 0.
+    ///
+    /// Automatic snapshots are read like `Db::to_bytes`, in
+    /// the background, or by `Db::step` if
+    /// `Config::test_clock` is set. Without a test clock,
+    /// they are only taken on platforms where sled runs
+    /// background threads, which are linux, macos, windows
+    /// and the BSDs. Each snapshot is a full copy of the
+    /// items, about as large as their logical size as
+    /// reported by `Db::space_report`, so frequent snapshots
+    /// of a large database take a lot of IO. Only the newest
+    /// `snapshot_retention` snapshots are kept.
+    ///
+    /// Snapshots are kept in the `snapshots` directory of
+    /// the database, apart from the log. They do not keep
+    /// log segments from being cleaned or reused, and
+    /// cleaning the log does not make them smaller, so the
+    /// space they take adds to that of the database.
+    pub fn snapshot_after_ops(mut self, ops: u64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.snapshot_after_ops = Some(ops);
+        self
+    }
+
+    /// Take a snapshot, as `Db::snapshot_now` does, once
+    /// `interval` has passed since the last one. This is
+    /// checked on every write, so no snapshots are taken
+    /// while nothing is written. Time is measured with
+    /// `Config::test_clock` if it is set, in which case
+    /// this is checked by `Db::step` instead. Disabled by
+    /// default, and must be above 0. See
+    /// `Config::snapshot_after_ops` for how snapshots are
+    /// taken and stored.
+    pub fn snapshot_interval(mut self, interval: Duration) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.snapshot_interval = Some(interval);
+        self
+    }
+
+    /// How many snapshots to keep. Each new snapshot, taken
+    /// automatically or by `Db::snapshot_now`, removes the
+    /// oldest ones beyond this number. Defaults to 2, and
+    /// must be at least 1.
+    pub fn snapshot_retention(mut self, count: usize) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.snapshot_retention = count;
+        self
+    }
+
+    /// Give the tree `name` a log of its own, instead of
+    /// writing it to the log that the other trees share. Its
+    /// segments are then only ever cleaned and rewritten
+    /// because of its own writes, so a tree that is written
+    /// heavily doesn't make the log cleaner move the pages of
+    /// quieter trees, and the other way around. This can be
+    /// called once for each tree that should be separated.
+    ///
+    /// A separated tree is stored in a database of its own
+    /// in the `separate_logs` directory of this one, which
+    /// is opened along with it and has its own file, page
+    /// cache of `cache_capacity_bytes`, and background
+    /// flusher. `Db::flush` and the flusher of the `Db`
+    /// only cover the shared log, so use `Tree::flush` on
+    /// a separated tree to make its writes durable.
+    ///
+    /// Writes are only recovered atomically within one
+    /// log, so transactions and `Db::apply_batch` return
+    /// `Error::Unsupported` if they span a separated tree
+    /// and any other tree, as do `Db::copy_tree` and
+    /// `Db::move_range` on separated trees. `Db::open_tree`,
+    /// `Db::drop_tree`, `Db::tree_names`, `Db::export` and
+    /// `Db::size_on_disk` include separated trees, while
+    /// checksums, images, snapshots and reports of the
+    /// `Db` only cover the shared log.
+    ///
+    /// Only trees that don't exist yet can be separated,
+    /// and a separated tree has to be named here every time
+    /// the database is opened, otherwise opening it returns
+    /// `Error::Unsupported`. The default tree can't be
+    /// separated, and this is not supported along with
+    /// `Config::io_backend`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{transaction::TransactionResult, Transactional};
+    ///
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .separate_log_for_tree(b"events")
+    ///     .open()?;
+    ///
+    /// let events = db.open_tree(b"events")?;
+    /// events.insert(b"k", b"v")?;
+    /// events.flush()?;
+    ///
+    /// let users = db.open_tree(b"users")?;
+    /// let res: TransactionResult<()> =
+    ///     (&events, &users).transaction(|(events, users)| {
+    ///         events.insert(b"k", b"v2")?;
+    ///         users.insert(b"k", b"v2")?;
+    ///         Ok(())
+    ///     });
+    /// assert!(res.is_err());
+    /// # Ok(()) }
+    /// ```
+    pub fn separate_log_for_tree<N: AsRef<[u8]>>(mut self, name: N) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        let name = IVec::from(name.as_ref());
+        if !m.separate_log_trees.contains(&name) {
+            m.separate_log_trees.push(name);
+        }
+        self
+    }
+
+    /// The most memory, in bytes, that cached pages may take.
+    /// Defaults to 1gb, is lowered to the memory limit of the
+    /// process if that is smaller, and must be at least 256
+    /// bytes. This is the same setting as `cache_capacity`,
+    /// and is ignored if `cache_capacity_fraction` is set and
+    /// the memory limit can be detected.
+    ///
+    /// A page counts with the keys and values it holds,
+    /// including their heap allocations and bookkeeping, so
+    /// a page of large values counts with their full size,
+    /// even if they are stored as blobs or compressed on
+    /// disk. Each size is rounded up to the next power of
+    /// two, so the cache may hold less than this. The cache
+    /// is split into shards with an equal part of the
+    /// capacity each, and a page that is larger than its
+    /// shard's part is evicted right after it is used, so
+    /// very small capacities cause frequent reads from disk.
+    /// `Db::cache_resident_bytes` returns how much of the
+    /// capacity is in use.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .cache_capacity_bytes(64 * 1024 * 1024)
+    ///     .open()?;
+    ///
+    /// db.insert(b"k", vec![0; 1024])?;
+    /// assert!(db.cache_resident_bytes() <= 64 * 1024 * 1024);
+    /// # Ok(()) }
+    /// ```
+    pub fn cache_capacity_bytes(mut self, bytes: u64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.cache_capacity = bytes;
+        self
+    }
+
+    /// Sizes the cache as a fraction of the memory that the
+    /// process may use, instead of setting an absolute
+    /// capacity with `cache_capacity_bytes`, so that the same
+    /// configuration suits hosts and containers with
+    /// different amounts of memory. The fraction must be
+    /// above 0 and at most 1. The capacity is computed when
+    /// the `Db` is opened and on each call to
+    /// `Db::retune_cache`, and is at least 256 bytes.
+    ///
+    /// The memory that the process may use is the smallest
+    /// of the following limits that can be detected:
+    ///
+    /// * on Linux, the memory limits of the cgroups of the
+    ///   process and of their ancestors, from `memory.max`
+    ///   with cgroup v2 and from `memory.limit_in_bytes` with
+    ///   cgroup v1, which is what limits the memory of a
+    ///   container, rather than the memory of its host.
+    ///   Cgroups without a limit are skipped.
+    /// * on Linux and macOS, the limit on the address space
+    ///   of the process, `RLIMIT_AS`.
+    /// * on Linux and macOS, the physical memory of the host.
+    ///
+    /// If none of them can be detected, such as on other
+    /// platforms, the capacity falls back to
+    /// `cache_capacity_bytes`, which defaults to 1gb.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .cache_capacity_fraction(0.25)
+    ///     .open()?;
+    ///
+    /// // after the memory limit of the container changed
+    /// let capacity = db.retune_cache()?;
+    /// assert!(db.cache_resident_bytes() <= capacity);
+    /// # Ok(()) }
+    /// ```
+    pub fn cache_capacity_fraction(mut self, fraction: f64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.cache_capacity_fraction = Some(fraction);
+        self
+    }
+
+    /// The largest key, in bytes, that writes accept. Writes
+    /// with larger keys fail with `Error::Unsupported`,
+    /// which reports the size of the key, and have no
+    /// effect. Keys are copied into index nodes when
+    /// leaves split, so this defaults to 16mb. Larger
+    /// keys are supported up to 1gb, and are stored in
+    /// their own blob files like other large nodes.
+    pub fn max_key_size(mut self, bytes: u64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.max_key_size = bytes;
+        self
+    }
+
+    /// The largest value, in bytes, that writes accept.
+    /// Writes with larger values, including values
+    /// produced by a merge operator, fail with
+    /// `Error::Unsupported`, which reports the size of the
+    /// value, and have no effect. Defaults to 1gb, which
+    /// is also the largest value that sled supports.
+    pub fn max_value_size(mut self, bytes: u64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.max_value_size = bytes;
+        self
+    }
+
+    /// Store each distinct value once, no matter how many
+    /// keys, in any tree of the `Db`, are set to it. This
+    /// suits workloads where many keys share a few large
+    /// values. Defaults to `false`, and cannot be changed
+    /// for an existing database.
+    ///
+    /// Values shorter than 64 bytes are still stored with
+    /// their keys, since a reference would take about as
+    /// much space. Longer values are kept once in an
+    /// internal tree, and keys hold a reference to them,
+    /// along with a count of the keys that refer to them.
+    /// When the last key referring to a value is removed or
+    /// overwritten, the value is removed as well. Reads
+    /// resolve references transparently, at the cost of an
+    /// extra lookup for each deduplicated value.
+    ///
+    /// Values are found by a 64-bit FNV-1a hash of their
+    /// bytes. A hash never identifies a value on its own:
+    /// the stored bytes are compared with the new value,
+    /// and a value whose hash collides with a different
+    /// value is stored separately under the same hash.
+    ///
+    /// Each write must look up and update the count of its
+    /// new and old values, so writes are slower, and they
+    /// are serialized against each other like `apply_batch`
+    /// is. Single-key writes are recovered atomically along
+    /// with the counts they change.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .dedup_values(true)
+    ///     .open()?;
+    ///
+    /// let big = vec![7; 4096];
+    /// db.insert(b"a", big.clone())?;
+    /// db.insert(b"b", big.clone())?;
+    ///
+    /// db.remove(b"a")?;
+    /// assert_eq!(db.get(b"b")?, Some(sled::IVec::from(big)));
+    /// # Ok(()) }
+    /// ```
+    pub fn dedup_values(mut self, dedup_values: bool) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.dedup_values = dedup_values;
+        self
+    }
+
+    /// Choose how the messages of the log are checksummed,
+    /// see `ChecksumAlgorithm` for the options. Defaults to
+    /// `ChecksumAlgorithm::Crc32`, and cannot be changed for
+    /// an existing database: the algorithm is stored with
+    /// it, and opening it with a different one fails with
+    /// `Error::Unsupported`, rather than reading every
+    /// message as damaged.
+    ///
+    /// Checksums are computed for every write to the log,
+    /// and when it is read back during recovery and when
+    /// pages are paged in, so a faster algorithm helps
+    /// workloads that write a lot of data. The headers of
+    /// segments, blobs, snapshots and the configuration
+    /// file always use `Crc32`.
+    ///
+    /// **`ChecksumAlgorithm::None` makes recovery trust
+    /// whatever it finds in the log, so that a crash or a
+    /// damaged disk can corrupt the database.** Read its
+    /// documentation before using it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::ChecksumAlgorithm;
+    ///
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .checksum_algorithm(ChecksumAlgorithm::Crc32c)
+    ///     .open()?;
+    ///
+    /// db.insert(b"a", b"1")?;
+    /// # Ok(()) }
+    /// ```
+    pub fn checksum_algorithm(
+        mut self,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.checksum_algorithm = checksum_algorithm;
+        self
+    }
+
+    /// Measure the latency of every `get`, `insert` and
+    /// `flush` on the `Db` and its trees, so that their
+    /// percentiles can be read with `Db::latency_stats`.
+    /// This works whether or not the `no_metrics` feature
+    /// is enabled. Defaults to `false`.
+    ///
+    /// Each measured operation reads the clock twice and
+    /// increments a few shared counters, and the histograms
+    /// take 1.5mb of memory. When this is disabled, an
+    /// operation only checks that there is no histogram.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .collect_latency_histograms(true)
+    ///     .open()?;
+    ///
+    /// db.insert(b"a", vec![1])?;
+    /// db.flush()?;
+    ///
+    /// let stats = db.latency_stats();
+    /// assert_eq!(stats.insert.count, 1);
+    /// println!("p99 flush latency: {:?}", stats.flush.p99);
+    /// # Ok(()) }
+    /// ```
+    pub fn collect_latency_histograms(mut self, collect: bool) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.collect_latency_histograms = collect;
+        self
+    }
+
+    /// How long `open` waits for another `Db`, in this or
+    /// another process, to release the lock on the database
+    /// file, such as when a new process starts while the
+    /// old one is still shutting down. If the lock is still
+    /// held after the timeout, `open` fails with
+    /// `Error::LockHeld`. A zero timeout, the default,
+    /// fails immediately, and `None` waits forever. Has no
+    /// effect with a custom `IoBackend`, which is not
+    /// locked.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    ///
+    /// # let _ = std::fs::remove_dir_all("open_timeout_db");
+    /// let config = sled::Config::new()
+    ///     .path("open_timeout_db")
+    ///     .open_timeout(Some(Duration::from_millis(10)));
+    /// let db = config.open()?;
+    ///
+    /// match config.open() {
+    ///     Err(sled::Error::LockHeld { .. }) => {}
+    ///     other => panic!("expected the lock to be held, got {:?}", other),
+    /// }
+    ///
+    /// drop(db);
+    /// let db = config.open()?;
+    /// # drop(db);
+    /// # let _ = std::fs::remove_dir_all("open_timeout_db");
+    /// # Ok(()) }
+    /// ```
+    pub fn open_timeout(mut self, timeout: Option<Duration>) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.open_timeout = timeout;
+        self
+    }
+
+    /// Run in a deterministic mode for testing, where time
+    /// is read from `clock` instead of the system, and no
+    /// background threads run. Instead of being flushed and
+    /// cleaned by a background thread every `flush_every_ms`,
+    /// the database only does this work when `Db::step` is
+    /// called after that much time has passed on `clock`,
+    /// and log writes happen on the thread that triggers
+    /// them. This makes time-dependent behavior, such as the
+    /// flush cadence and the refresh of the free space used
+    /// by `min_free_space`, reproducible in tests.
+    ///
+    /// Writes still become durable when `flush` is called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::{sync::Arc, time::Duration};
+    ///
+    /// use sled::VirtualClock;
+    ///
+    /// let clock = Arc::new(VirtualClock::new());
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .flush_every_ms(Some(1000))
+    ///     .test_clock(clock.clone())
+    ///     .open()?;
+    ///
+    /// // the first step always flushes
+    /// assert!(db.step()?);
+    ///
+    /// db.insert(b"k", b"v")?;
+    /// clock.advance(Duration::from_millis(999));
+    /// assert!(!db.step()?);
+    ///
+    /// clock.advance(Duration::from_millis(1));
+    /// assert!(db.step()?);
+    /// # Ok(()) }
+    /// ```
+    pub fn test_clock(mut self, clock: std::sync::Arc<dyn Clock>) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.clock = Some(clock);
+        self
+    }
+
     /// A testing-only method for reducing the io-buffer size
     /// to trigger correctness-critical behavior more often
     /// by shrinking the buffer size. Don't rely on this.
@@ -338,20 +1340,146 @@ impl Config {
         self
     }
 
+    /// Sets the parameters that are stored with the database
+    /// at `path` and can't change across restarts, so that
+    /// this config can open it or create a copy of it.
+    pub(crate) fn storage_parameters_of(
+        mut self,
+        path: &Path,
+    ) -> Result<Config> {
+        let stored = if let Some(parameters) =
+            Config::new().path(path).read_config()?
+        {
+            parameters
+        } else {
+            return Err(Error::DatabaseNotFound(path.to_path_buf()));
+        };
+        let m = Arc::make_mut(&mut self.0);
+        m.segment_size = stored.segment_size;
+        m.use_compression = stored.use_compression;
+        m.dedup_values = stored.dedup_values;
+        m.checksum_algorithm = stored.checksum_algorithm;
+        Ok(self)
+    }
+
     /// Opens a `Db` based on the provided config.
     pub fn open(&self) -> Result<Db> {
         // only validate, setup directory, and open file once
         self.validate()?;
 
-        let mut config = self.clone();
-        config.limit_cache_max_memory();
+        let config = self.clone();
+
+        let file = config.open_file()?;
+
+        // seal config in a Config
+        Db::start_inner(RunningConfig { inner: config, file })
+    }
+
+    /// Opens a `Db` in the directory `dir_fd`, which is an
+    /// open file descriptor of a directory, instead of at
+    /// the configured path. This is for sandboxes that hand
+    /// out directories as descriptors rather than as paths.
+    /// The descriptor is duplicated, so it can be closed
+    /// once this returns, and the `Db` keeps the directory
+    /// open until it is dropped.
+    ///
+    /// Files are opened relative to the directory with the
+    /// `*at` family of system calls, like `openat` and
+    /// `renameat`, so the directory does not need to be
+    /// reachable by path, for instance across a chroot. The
+    /// paths that sled reports, for instance in errors, are
+    /// relative to the directory.
+    ///
+    /// Returns `Error::Unsupported` for temporary databases,
+    /// which are removed by path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # #[cfg(unix)] {
+    /// use std::os::unix::io::AsRawFd;
+    ///
+    /// # let _ = std::fs::remove_dir_all("open_at_db");
+    /// std::fs::create_dir_all("open_at_db")?;
+    /// let dir = std::fs::File::open("open_at_db")?;
+    ///
+    /// let db = sled::Config::new().open_at(dir.as_raw_fd())?;
+    /// drop(dir);
+    /// db.insert(b"k", b"v")?;
+    /// # drop(db);
+    /// assert!(std::path::Path::new("open_at_db/db").exists());
+    /// # let _ = std::fs::remove_dir_all("open_at_db");
+    /// # }
+    /// # Ok(()) }
+    /// ```
+    #[cfg(unix)]
+    pub fn open_at(&self, dir_fd: std::os::unix::io::RawFd) -> Result<Db> {
+        use std::os::unix::io::FromRawFd;
+
+        supported!(
+            !self.temporary,
+            "temporary databases can not be opened at a descriptor"
+        );
 
-        let file = config.open_file()?;
+        #[allow(unsafe_code)]
+        let dup = unsafe { libc::fcntl(dir_fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if dup < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // owns the duplicate, closing it on drop
+        #[allow(unsafe_code)]
+        let dir = unsafe { File::from_raw_fd(dup) };
 
-        // seal config in a Config
-        let config = RunningConfig { inner: config, file: Arc::new(file) };
+        // an empty path is the directory itself, and every
+        // path joined onto it stays relative to it
+        let mut config = self.clone();
+        let m = Arc::make_mut(&mut config.0);
+        m.path = PathBuf::new();
+        m.dir_fd = Some(std::sync::Arc::new(dir));
+        config.open()
+    }
 
-        Db::start_inner(config)
+    /// Opens a `Db` based on the provided config, like
+    /// `Config::open`, but on the threadpool, so that
+    /// recovering a large database does not block the thread
+    /// of an async executor. The work of opening it still
+    /// blocks, just on a thread of the threadpool instead of
+    /// the caller's, until the returned future resolves.
+    ///
+    /// Like an `async fn`, nothing happens until the future
+    /// is first polled. It does not borrow the config, so it
+    /// can be spawned onto an executor. With
+    /// `Config::test_clock`, the database is opened on the
+    /// thread that polls the future.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # async fn example() -> sled::Result<()> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open_async().await?;
+    /// db.insert(b"k", b"v")?;
+    /// db.flush_async().await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn open_async(
+        &self,
+    ) -> impl std::future::Future<Output = Result<Db>> + Send + 'static {
+        let config = self.clone();
+        async move {
+            if config.clock.is_some() {
+                return config.open();
+            }
+            let opened = threadpool::spawn(move || config.open()).await;
+            opened.unwrap_or_else(|| {
+                Err(Error::ReportableBug(
+                    "threadpool failed to complete \
+                    action before shutdown"
+                        .to_string(),
+                ))
+            })
+        }
     }
 
     #[doc(hidden)]
@@ -372,15 +1500,6 @@ impl Config {
         self
     }
 
-    #[doc(hidden)]
-    #[deprecated(
-        since = "0.31.0",
-        note = "this does nothing for now. maybe it will come back in the future."
-    )]
-    pub const fn snapshot_after_ops(self, _: u64) -> Self {
-        self
-    }
-
     #[doc(hidden)]
     #[deprecated(
         since = "0.31.0",
@@ -404,6 +1523,271 @@ impl Config {
         self
     }
 
+    /// Wait up to `jitter` longer than `flush_every_ms`
+    /// between background flushes, by a random amount that
+    /// is chosen anew for each flush. This keeps many
+    /// databases on the same host that were opened at the
+    /// same time from flushing and syncing in lockstep.
+    /// Defaults to 0, which flushes exactly every
+    /// `flush_every_ms`.
+    pub fn flush_jitter(mut self, jitter: Duration) -> Self {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.flush_jitter = jitter;
+        self
+    }
+
+    /// Flush in the background before `flush_every_ms` has
+    /// passed once at least `bytes` bytes have been written
+    /// to the log since the last flush. The background
+    /// flusher checks this every 10ms, so more may be
+    /// written in between. This bounds how much recent data
+    /// a crash loses under heavy writes, without flushing
+    /// more often while writes are light. Has no effect if
+    /// `flush_every_ms` is `None`. Defaults to 0, which only
+    /// flushes every `flush_every_ms`.
+    pub fn flush_dirty_threshold(mut self, bytes: u64) -> Self {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.flush_dirty_threshold = bytes;
+        self
+    }
+
+    /// Start no background flush thread, and leave all of
+    /// its work to the caller. Writes are then only written
+    /// to the log once a buffer of the log fills up, or when
+    /// `Db::tick` or `flush` is called, and fragmented
+    /// segments are only cleaned by `Db::tick`.
+    ///
+    /// Writes are not durable until one of these is called,
+    /// so a crash loses everything written since then, no
+    /// matter how long ago that was. `flush_every_ms` and
+    /// `flush_dirty_threshold` have no effect, and neither
+    /// does `Db::set_flush_every_ms`. The database is still
+    /// flushed when its last handle is dropped. Defaults to
+    /// `false`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .manual_flush(true)
+    ///     .open()?;
+    ///
+    /// db.insert(b"k", b"v")?;
+    ///
+    /// // called from the application's own event loop
+    /// while db.tick()? > 0 {}
+    /// # Ok(()) }
+    /// ```
+    pub fn manual_flush(mut self, manual_flush: bool) -> Self {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.manual_flush = manual_flush;
+        self
+    }
+
+    /// Log only the latest state of each leaf that
+    /// `Tree::insert` and `Tree::remove` changed since the
+    /// last flush, instead of logging every write. A key
+    /// that is written many times between flushes then
+    /// costs log space once per flush, rather than once per
+    /// write. Each such leaf is logged as a whole, so this
+    /// writes more, not less, when there is only one write
+    /// to each leaf between flushes. Defaults to `false`.
+    ///
+    /// This changes which writes a crash can lose:
+    ///
+    /// * the values that a key held between two flushes are
+    ///   never written to the log, so they are not durable,
+    ///   and only the latest one is recovered after a flush.
+    /// * a coalesced write is not durable until the next
+    ///   flush, even if a write that was made after it, like
+    ///   an `apply_batch` or a transaction, which is logged
+    ///   right away, is durable.
+    /// * each leaf is logged on its own, so a crash during a
+    ///   flush may recover a write to one leaf but not an
+    ///   earlier write to another.
+    ///
+    /// Reads in memory see every write right away, and
+    /// subscribers are still notified of every write, so
+    /// they may see values that are never recovered.
+    /// `Tree::flush`, the flushes every `flush_every_ms`,
+    /// and the methods that wait for a write to be durable
+    /// log the coalesced writes first, but they do not count
+    /// towards `flush_dirty_threshold`. Batches,
+    /// transactions, compare and swap, merges, and the
+    /// writes of trees that use `Config::dedup_values` are
+    /// logged right away as before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .coalesce_writes(true)
+    ///     .open()?;
+    ///
+    /// for i in 0..1000_u32 {
+    ///     db.insert(b"hot", &i.to_be_bytes())?;
+    /// }
+    /// let last = sled::IVec::from(&999_u32.to_be_bytes());
+    /// assert_eq!(db.get(b"hot")?, Some(last));
+    ///
+    /// // logs the leaf of `hot` once
+    /// db.flush()?;
+    /// # Ok(()) }
+    /// ```
+    pub fn coalesce_writes(mut self, coalesce_writes: bool) -> Self {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.coalesce_writes = coalesce_writes;
+        self
+    }
+
+    /// Turns the database into a bounded cache that holds
+    /// at most `bytes` bytes of keys and values, summed over
+    /// all trees. Once a write takes the total over the
+    /// limit, items are removed, in the order set by
+    /// `Config::eviction_policy`, until it holds again.
+    ///
+    /// Eviction removes the items themselves, not just their
+    /// pages from the cache, so they are gone for good, and
+    /// subscribers of their trees receive an `Event::Remove`
+    /// for each of them, like for any other removal. The item
+    /// that was just written is never evicted by its own
+    /// write, even if it is larger than the limit on its own.
+    ///
+    /// Some things to keep in mind:
+    ///
+    /// * the limit is on the lengths of the keys and values,
+    ///   not on the size of the files, which also hold the
+    ///   log's headers and the old versions of pages until
+    ///   their segments are cleaned.
+    /// * every item is tracked in memory, which takes about
+    ///   200 bytes plus twice the length of its key. Reads
+    ///   with `Tree::get` take a lock to record the use.
+    /// * opening the database reads every item of every tree
+    ///   to track it, so `open` takes time proportional to
+    ///   the number of items, even after a clean shutdown.
+    /// * the writer that goes over the limit does the
+    ///   evicting, once its write is complete and no longer
+    ///   holds up other writes. Until then the total may
+    ///   exceed the limit, and writers that go over it while
+    ///   another evicts wait for it to finish.
+    /// * which items were used recently is not persisted, so
+    ///   after opening the database, items are evicted in key
+    ///   order until they are used again.
+    ///
+    /// This is not supported with `Config::dedup_values` or
+    /// `Config::separate_log_for_tree`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .max_db_size(4096)
+    ///     .open()?;
+    ///
+    /// for i in 0..100_u32 {
+    ///     db.insert(&i.to_be_bytes(), vec![0; 96])?;
+    /// }
+    ///
+    /// // only the 40 most recently written items fit
+    /// assert_eq!(db.len(), 40);
+    /// assert!(db.get(&0_u32.to_be_bytes())?.is_none());
+    /// assert!(db.get(&99_u32.to_be_bytes())?.is_some());
+    /// # Ok(()) }
+    /// ```
+    pub fn max_db_size(mut self, bytes: u64) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.max_db_size = Some(bytes);
+        self
+    }
+
+    /// Sets which items are removed first once the size set
+    /// by `Config::max_db_size` is exceeded. See
+    /// `EvictionPolicy`. Defaults to `EvictionPolicy::Lru`.
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Config {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.eviction_policy = policy;
+        self
+    }
+
+    /// Write the log and truncate segments in the background
+    /// on at most `threads` threads at a time, which are
+    /// taken from a threadpool that is shared by all
+    /// databases. Each write of the log goes to its own
+    /// offset of the file, so more threads let more of them
+    /// run at once on disks that serve many writes in
+    /// parallel. With 0, this work runs on the thread that
+    /// triggered it, such as a writer that filled the
+    /// current buffer of the log, and no background threads
+    /// are used for it, which suits small embedded systems.
+    /// The thread that `flush_every_ms` starts only decides
+    /// when to flush, and is disabled by setting that to
+    /// `None`.
+    ///
+    /// Defaults to no limit, where the threadpool grows as
+    /// work queues up, and is shared with other background
+    /// work like `Tree::flush_async`, which is never limited
+    /// by this.
+    pub fn io_threads(mut self, threads: usize) -> Self {
+        if Arc::strong_count(&self.0) != 1 {
+            error!(
+                "config has already been used to start \
+                 the system and probably should not be \
+                 mutated",
+            );
+        }
+        let m = Arc::make_mut(&mut self.0);
+        m.io_threads = Some(threads);
+        self
+    }
+
     #[doc(hidden)]
     pub fn idgen_persist_interval(mut self, interval: u64) -> Self {
         if Arc::strong_count(&self.0) != 1 {
@@ -428,18 +1812,16 @@ impl Config {
     /// basic sanity checks.
     #[doc(hidden)]
     #[deprecated(since = "0.29.0", note = "use Config::open instead")]
-    pub fn build(mut self) -> RunningConfig {
+    pub fn build(self) -> RunningConfig {
         // only validate, setup directory, and open file once
         self.validate().unwrap();
 
-        self.limit_cache_max_memory();
-
         let file = self.open_file().unwrap_or_else(|e| {
             panic!("open file at {:?}: {}", self.db_path(), e);
         });
 
         // seal config in a Config
-        RunningConfig { inner: self, file: Arc::new(file) }
+        RunningConfig { inner: self, file }
     }
 
     fn gen_temp_path() -> PathBuf {
@@ -467,30 +1849,69 @@ impl Config {
         }
     }
 
-    fn limit_cache_max_memory(&mut self) {
+    /// Returns the capacity of the cache, which is the
+    /// configured capacity or fraction of the memory limit,
+    /// lowered to the memory limit. The limit is detected
+    /// again on each call, see `cache_capacity_fraction`.
+    pub(crate) fn effective_cache_capacity(&self) -> u64 {
         let limit = sys_limits::get_memory_limit();
+
+        if let Some(fraction) = self.cache_capacity_fraction {
+            if limit > 0 {
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_precision_loss,
+                    clippy::cast_sign_loss,
+                    clippy::float_arithmetic
+                )]
+                let capacity = (limit as f64 * fraction) as u64;
+                return capacity.max(256);
+            }
+        }
+
         if limit > 0 && self.cache_capacity > limit {
-            let m = Arc::make_mut(&mut self.0);
-            m.cache_capacity = limit;
             error!(
-                "cache capacity is limited to the cgroup memory \
+                "cache capacity is limited to the memory \
                  limit: {} bytes",
-                self.cache_capacity
+                limit
             );
+            return limit;
         }
+
+        self.cache_capacity
     }
 
     builder!(
         (
             cache_capacity,
             u64,
-            "maximum size in bytes for the system page cache"
+            "maximum size in bytes for the system page cache, see `cache_capacity_bytes`"
+        ),
+        (
+            cache_mode,
+            CacheMode,
+            "how the page cache chooses which pages to evict, see `CacheMode`"
         ),
         (
             mode,
             Mode,
             "specify whether the system should run in \"small\" or \"fast\" mode"
         ),
+        (
+            sync_strategy,
+            SyncStrategy,
+            "how the log is made durable, see `SyncStrategy` for the trade-offs"
+        ),
+        (
+            recovery_policy,
+            RecoveryPolicy,
+            "what `open` does about damaged pages, see `RecoveryPolicy`"
+        ),
+        (
+            on_disk_full,
+            OnFull,
+            "what writes do once the disk is full, see `OnFull`"
+        ),
         (use_compression, bool, "whether to use zstd compression"),
         (
             compression_factor,
@@ -505,7 +1926,12 @@ impl Config {
         (
             create_new,
             bool,
-            "attempts to exclusively open the database, failing if it already exists"
+            "attempts to exclusively open the database, failing with `Error::DatabaseAlreadyExists` if it already exists"
+        ),
+        (
+            must_exist,
+            bool,
+            "fails with `Error::DatabaseNotFound` instead of creating a new database if one does not already exist"
         ),
         (
             print_profile_on_drop,
@@ -546,30 +1972,136 @@ impl Config {
             self.idgen_persist_interval > 0,
             "idgen_persist_interval must be above 0"
         );
+        supported!(
+            self.min_free_space == 0
+                || cfg!(any(
+                    windows,
+                    target_os = "linux",
+                    target_os = "macos"
+                )),
+            "min_free_space is only supported on linux, macos and windows"
+        );
+        supported!(
+            self.max_key_size <= MAX_ITEM_SIZE,
+            "max_key_size must be <= 1gb"
+        );
+        supported!(
+            self.max_value_size <= MAX_ITEM_SIZE,
+            "max_value_size must be <= 1gb"
+        );
+        supported!(
+            !(self.create_new && self.must_exist),
+            "create_new and must_exist can not both be set"
+        );
+        supported!(
+            !cfg!(feature = "io_uring") || self.io_backend.is_none(),
+            "custom io backends are not supported with the 'io_uring' feature"
+        );
+        supported!(
+            self.snapshot_after_ops != Some(0),
+            "snapshot_after_ops must be above 0"
+        );
+        supported!(
+            self.snapshot_interval != Some(Duration::from_secs(0)),
+            "snapshot_interval must be above 0"
+        );
+        supported!(
+            self.snapshot_retention >= 1,
+            "snapshot_retention must be at least 1"
+        );
+        supported!(
+            !self.separate_log_trees.iter().any(|name| name == DEFAULT_TREE_ID),
+            "the default tree can not have a separate log"
+        );
+        supported!(
+            self.separate_log_trees.is_empty() || self.io_backend.is_none(),
+            "separate logs are not supported with custom io backends"
+        );
+        supported!(self.max_db_size != Some(0), "max_db_size must be above 0");
+        supported!(
+            self.max_db_size.is_none() || !self.dedup_values,
+            "max_db_size is not supported with dedup_values"
+        );
+        supported!(
+            self.max_db_size.is_none() || self.separate_log_trees.is_empty(),
+            "max_db_size is not supported with separate logs"
+        );
+        if let Some(fraction) = self.cache_capacity_fraction {
+            supported!(
+                fraction > 0.0 && fraction <= 1.0,
+                "cache_capacity_fraction must be above 0 and at most 1"
+            );
+        }
         Ok(())
     }
 
-    fn open_file(&self) -> Result<File> {
+    fn open_file(&self) -> Result<std::sync::Arc<dyn IoBackend>> {
+        // check before creating any directories, so that a
+        // failed open leaves the filesystem untouched
+        let exists = files::exists(self, &self.config_path())
+            || (self.io_backend.is_none()
+                && files::exists(self, &self.db_path()));
+
+        if self.create_new && exists {
+            return Err(Error::DatabaseAlreadyExists(self.get_path()));
+        }
+
+        if self.must_exist && !exists {
+            return Err(Error::DatabaseNotFound(self.get_path()));
+        }
+
         let blob_dir: PathBuf = self.get_path().join("blobs");
 
-        if !blob_dir.exists() {
-            fs::create_dir_all(blob_dir)?;
+        if !files::exists(self, &blob_dir) {
+            files::create_dir_all(self, &blob_dir)?;
         }
 
         self.verify_config()?;
 
-        // open the data file
-        let mut options = fs::OpenOptions::new();
+        if let Some(backend) = &self.io_backend {
+            self.fsync_dir()?;
+            return Ok(backend.clone());
+        }
 
-        let _ = options.create(true);
-        let _ = options.read(true);
-        let _ = options.write(true);
+        // open the data file
+        let options = files::OpenOptions::new()
+            .create(!self.must_exist)
+            .read(true)
+            .write(true)
+            .create_new(self.create_new);
+
+        // the checks above are racy, so map the errors that
+        // `create_new` and `must_exist` cause here as well
+        let file = match options.open(self, &self.db_path()) {
+            // in-memory databases have a log backend
+            Ok(file) => self.try_lock(file.into_os().unwrap())?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::DatabaseAlreadyExists(self.get_path()));
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound && self.must_exist => {
+                return Err(Error::DatabaseNotFound(self.get_path()));
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        if self.create_new {
-            options.create_new(true);
+        // only mark the directory once we hold the lock, so
+        // that a marked directory whose `db` file is unlocked
+        // belongs to a process that is gone
+        if self.temporary {
+            let marker = self.get_path().join(TEMPORARY_MARKER);
+            let mut f = files::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(self, &marker)?;
+            f.write_all(std::process::id().to_string().as_bytes())?;
         }
 
-        self.try_lock(options.open(&self.db_path())?)
+        // make sure that the directory entries of the files
+        // we may have just created are durable as well
+        self.fsync_dir()?;
+
+        Ok(std::sync::Arc::new(file))
     }
 
     fn try_lock(&self, file: File) -> Result<File> {
@@ -577,26 +2109,35 @@ impl Config {
         {
             use fs2::FileExt;
 
-            let try_lock = if cfg!(feature = "testing") {
-                // we block here because during testing
-                // there are many filesystem race condition
-                // that happen, causing locks to be held
-                // for long periods of time, so we should
-                // block to wait on reopening files.
-                file.lock_exclusive()
+            let timeout = if let Some(timeout) = self.open_timeout {
+                timeout
             } else {
-                file.try_lock_exclusive()
+                file.lock_exclusive()?;
+                return Ok(file);
             };
 
-            if let Err(e) = try_lock {
-                return Err(Error::Io(io::Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "could not acquire lock on {:?}: {:?}",
-                        self.db_path().to_string_lossy(),
-                        e
-                    ),
-                )));
+            let contended = fs2::lock_contended_error().kind();
+            let start = Instant::now();
+            let mut backoff = Duration::from_millis(1);
+            loop {
+                match file.try_lock_exclusive() {
+                    Ok(()) => break,
+                    Err(e) if e.kind() == contended => {}
+                    Err(e) => return Err(e.into()),
+                }
+
+                let elapsed = start.elapsed();
+                if elapsed >= timeout {
+                    return Err(Error::LockHeld {
+                        path: self.db_path(),
+                        pid: lock_holder(&file),
+                    });
+                }
+
+                let remaining = timeout - elapsed;
+                std::thread::sleep(std::cmp::min(backoff, remaining));
+                backoff =
+                    std::cmp::min(backoff * 2, Duration::from_millis(100));
             }
         }
 
@@ -616,6 +2157,26 @@ impl Config {
                     )
                 );
 
+                supported!(
+                    self.dedup_values == old.dedup_values,
+                    format!(
+                        "cannot change dedup_values across restarts. \
+                         old value of dedup_values loaded from disk: {}, \
+                         currently set value: {}.",
+                        old.dedup_values, self.dedup_values,
+                    )
+                );
+
+                supported!(
+                    self.checksum_algorithm == old.checksum_algorithm,
+                    format!(
+                        "cannot change checksum_algorithm across restarts. \
+                         old value of checksum_algorithm loaded from disk: \
+                         {:?}, currently set value: {:?}.",
+                        old.checksum_algorithm, self.checksum_algorithm,
+                    )
+                );
+
                 supported!(
                     self.segment_size == old.segment_size,
                     format!(
@@ -651,6 +2212,8 @@ impl Config {
             version: self.version,
             segment_size: self.segment_size,
             use_compression: self.use_compression,
+            dedup_values: self.dedup_values,
+            checksum_algorithm: self.checksum_algorithm,
         };
 
         persisted_config.serialize()
@@ -663,21 +2226,64 @@ impl Config {
 
         let path = self.config_path();
 
-        let mut f =
-            fs::OpenOptions::new().write(true).create(true).open(path)?;
+        let mut f = files::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(self, &path)?;
 
         io_fail!(self, "write_config bytes");
         f.write_all(&*bytes)?;
         io_fail!(self, "write_config crc");
         f.write_all(&crc_arr)?;
+        if !self.temporary {
+            f.sync_all()?;
+        }
         io_fail!(self, "write_config post");
         Ok(())
     }
 
+    /// Fsyncs the storage directory, its `blobs` subdirectory,
+    /// and the directory that contains it, so that the directory
+    /// entries of newly created files survive a crash. Syncing
+    /// a file's contents does not make its directory entry
+    /// durable on POSIX filesystems.
+    pub(crate) fn fsync_dir(&self) -> Result<()> {
+        if self.temporary {
+            return Ok(());
+        }
+
+        io_fail!(self, "fsync dir");
+
+        #[cfg(unix)]
+        {
+            let path = self.get_path();
+            let blob_dir = path.join("blobs");
+
+            let mut dirs = vec![blob_dir.as_path(), path.as_path()];
+            // the parent of a directory opened with
+            // `Config::open_at` is out of reach, and was
+            // set up by whoever handed out the directory
+            if let (None, Some(parent)) = (&self.dir_fd, path.parent()) {
+                if parent.as_os_str().is_empty() {
+                    dirs.push(Path::new("."));
+                } else {
+                    dirs.push(parent);
+                }
+            }
+
+            for dir in dirs {
+                files::sync_dir(self, dir)?;
+            }
+        }
+
+        io_fail!(self, "fsync dir post");
+        Ok(())
+    }
+
     fn read_config(&self) -> Result<Option<StorageParameters>> {
         let path = self.config_path();
 
-        let f_res = fs::OpenOptions::new().read(true).open(&path);
+        let f_res = files::OpenOptions::new().read(true).open(self, &path);
 
         let mut f = match f_res {
             Err(ref e) if e.kind() == ErrorKind::NotFound => {
@@ -689,7 +2295,7 @@ impl Config {
             Ok(f) => f,
         };
 
-        if f.metadata()?.len() <= 8 {
+        if f.len()? <= 8 {
             warn!("empty/corrupt configuration file found");
             return Ok(None);
         }
@@ -777,8 +2383,12 @@ impl Config {
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug, Clone)]
 pub struct RunningConfig {
+    // the file is declared first so that it is closed before
+    // the last reference to `inner` removes the directory of
+    // a temporary database, which fails on windows while the
+    // file is open
+    pub(crate) file: std::sync::Arc<dyn IoBackend>,
     inner: Config,
-    pub(crate) file: Arc<File>,
 }
 
 #[allow(unsafe_code)]
@@ -806,47 +2416,136 @@ impl Drop for Inner {
         }
 
         // Our files are temporary, so nuke them.
-        debug!("removing temporary storage file {:?}", self.get_path());
-        let _res = fs::remove_dir_all(&self.get_path());
+        let path = self.get_path();
+        debug!("removing temporary storage file {:?}", path);
+        if let Err(e) = remove_dir_retrying(&path) {
+            warn!(
+                "failed to remove temporary storage at {:?}: {}. \
+                 it can be removed with Db::cleanup_temporaries",
+                path, e
+            );
+        }
+    }
+}
+
+/// Removes `path` and everything in it, retrying a few times,
+/// because files that are being created or closed concurrently,
+/// for instance by the threadpool, can make `remove_dir_all`
+/// fail. A `path` that does not exist is not an error.
+fn remove_dir_retrying(path: &Path) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match fs::remove_dir_all(path) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) if attempt >= 4 => return Err(e),
+            Err(_) => {
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(10 << attempt));
+            }
+        }
+    }
+}
+
+/// Removes the directories directly inside `base_dir` that hold
+/// temporary databases of processes that are gone. See
+/// `Db::cleanup_temporaries`.
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+pub(crate) fn cleanup_temporaries(base_dir: &Path) -> Result<usize> {
+    use fs2::FileExt;
+
+    let mut removed = 0;
+    for entry in fs::read_dir(base_dir)? {
+        let path = entry?.path();
+        if !path.is_dir() || !path.join(TEMPORARY_MARKER).exists() {
+            continue;
+        }
+
+        let stale = match fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.join("db"))
+        {
+            // the lock is released when the file is closed at
+            // the end of this statement, before removing it
+            Ok(file) => file.try_lock_exclusive().is_ok(),
+            // the marker is written after the `db` file is
+            // locked, so this directory was partly removed
+            Err(e) if e.kind() == ErrorKind::NotFound => true,
+            Err(e) => return Err(e.into()),
+        };
+
+        if stale {
+            debug!("removing stale temporary storage {:?}", path);
+            remove_dir_retrying(&path)?;
+            removed += 1;
+        }
     }
+    Ok(removed)
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub(crate) fn cleanup_temporaries(_base_dir: &Path) -> Result<usize> {
+    Err(Error::Unsupported(
+        "cleaning up temporary databases requires file locks, \
+         which are not supported on this platform"
+            .to_owned(),
+    ))
 }
 
 impl RunningConfig {
+    /// Makes all previous writes to the log durable, using
+    /// `Config::sync_strategy` for the default file backend.
+    pub(crate) fn sync_log(&self) -> io::Result<()> {
+        if let Some(file) = self.file.as_file() {
+            pagecache::sync_file(file, self.sync_strategy, None)
+        } else {
+            self.file.sync_all()
+        }
+    }
+
+    /// Makes previous writes to the given region of the log
+    /// durable, using `Config::sync_strategy` for the
+    /// default file backend.
+    pub(crate) fn sync_log_range(
+        &self,
+        offset: LogOffset,
+        len: usize,
+    ) -> io::Result<()> {
+        if let Some(file) = self.file.as_file() {
+            pagecache::sync_file(file, self.sync_strategy, Some((offset, len)))
+        } else {
+            self.file.sync_range(offset, len)
+        }
+    }
+
     // returns the snapshot file paths for this system
     #[doc(hidden)]
     pub fn get_snapshot_files(&self) -> io::Result<Vec<PathBuf>> {
-        let path = self.get_path().join("snap.");
+        let path = self.get_path();
 
-        let absolute_path: PathBuf = if Path::new(&path).is_absolute() {
+        // paths relative to a directory descriptor stay
+        // relative, as they are only opened through it
+        let snap_dir: PathBuf = if self.dir_fd.is_some() || path.is_absolute()
+        {
             path
         } else {
             std::env::current_dir()?.join(path)
         };
 
-        let filter = |dir_entry: io::Result<fs::DirEntry>| {
-            if let Ok(de) = dir_entry {
-                let path_buf = de.path();
-                let path = path_buf.as_path();
-                let path_str = &*path.to_string_lossy();
-                if path_str.starts_with(&*absolute_path.to_string_lossy())
-                    && !path_str.ends_with(".in___motion")
-                {
-                    Some(path.to_path_buf())
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        };
-
-        let snap_dir = Path::new(&absolute_path).parent().unwrap();
-
-        if !snap_dir.exists() {
-            fs::create_dir_all(snap_dir)?;
+        if !files::exists(self, &snap_dir) {
+            files::create_dir_all(self, &snap_dir)?;
         }
 
-        Ok(snap_dir.read_dir()?.filter_map(filter).collect())
+        let is_snapshot = |snap: &PathBuf| {
+            let name = snap.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with("snap.") && !name.ends_with(".in___motion")
+        };
+
+        Ok(files::read_dir(self, &snap_dir)?
+            .into_iter()
+            .filter(is_snapshot)
+            .collect())
     }
 }
 
@@ -857,3 +2556,44 @@ fn crate_version() -> (usize, usize) {
     let minor = parts.next().unwrap().parse().unwrap();
     (major, minor)
 }
+
+/// Finds the process holding the lock on `file` in
+/// `/proc/locks`, where `flock` locks are listed by the
+/// device and inode of the locked file.
+#[cfg(target_os = "linux")]
+fn lock_holder(file: &File) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata().ok()?;
+    let dev = metadata.dev();
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+
+    let locks = fs::read_to_string("/proc/locks").ok()?;
+    for line in locks.lines() {
+        // "1: FLOCK ADVISORY WRITE <pid> <major>:<minor>:<inode> 0 EOF",
+        // while processes waiting on a lock are marked with "->"
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 6 || fields[1] == "->" {
+            continue;
+        }
+
+        let mut id = fields[5].split(':');
+        let matches = id.next().and_then(|s| u64::from_str_radix(s, 16).ok())
+            == Some(major)
+            && id.next().and_then(|s| u64::from_str_radix(s, 16).ok())
+                == Some(minor)
+            && id.next().and_then(|s| s.parse().ok()) == Some(metadata.ino());
+
+        if matches {
+            return fields[4].parse().ok();
+        }
+    }
+
+    None
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+fn lock_holder(_file: &File) -> Option<u32> {
+    None
+}
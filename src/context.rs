@@ -1,3 +1,5 @@
+use std::sync::atomic::AtomicBool;
+
 use super::*;
 
 #[derive(Debug, Clone)]
@@ -24,8 +26,35 @@ pub struct Context {
     pub(crate) flusher: Arc<Mutex<Option<flusher::Flusher>>>,
     #[doc(hidden)]
     pub pagecache: Arc<PageCache>,
+    /// Bumped after every successful write to any
+    /// tree. See `Db::write_generation`.
+    pub(crate) write_generation: Arc<AtomicU64>,
+    /// Cached free space of the storage volume, used to
+    /// enforce `Config::min_free_space`.
+    pub(crate) free_space: Arc<FreeSpace>,
+    /// The `Config::test_clock` time at which `Db::step`
+    /// last flushed.
+    pub(crate) last_step: Arc<Mutex<Option<std::time::Duration>>>,
+    /// Set when `RecoveryPolicy::ReadOnlyAfterCorruption`
+    /// found damaged pages while opening the database.
+    pub(crate) read_only: Arc<AtomicBool>,
+    /// Present if `Config::collect_latency_histograms`
+    /// is set.
+    pub(crate) latency: Option<Arc<LatencyHistograms>>,
+    /// The state of `Config::snapshot_after_ops` and
+    /// `Config::snapshot_interval`.
+    pub(crate) snapshots: Arc<Snapshots>,
+    /// The current `Config::flush_every_ms`, which
+    /// `Db::set_flush_every_ms` changes, or `NEVER` for
+    /// `None`.
+    pub(crate) flush_every: Arc<AtomicU64>,
+    /// Present if `Config::max_db_size` is set.
+    pub(crate) eviction: Option<Arc<Eviction>>,
 }
 
+/// Stands for a `flush_every_ms` of `None`.
+pub(crate) const NEVER: u64 = u64::max_value();
+
 impl std::ops::Deref for Context {
     type Target = RunningConfig;
 
@@ -46,8 +75,12 @@ impl Drop for Context {
             target_os = "netbsd",
         ))]
         {
-            if let Some(flusher) = self.flusher.lock().take() {
-                drop(flusher)
+            // only the last handle shuts the flusher down, since
+            // every `Tree` holds a clone of the `Context`
+            if Arc::strong_count(&self.flusher) == 1 {
+                if let Some(flusher) = self.flusher.lock().take() {
+                    drop(flusher)
+                }
             }
         }
 
@@ -74,9 +107,39 @@ impl Context {
 
         let pagecache = Arc::new(PageCache::start(config.clone())?);
 
+        let write_generation =
+            Arc::new(AtomicU64::new(pagecache.recovered_write_generation()));
+
+        let latency = if config.collect_latency_histograms {
+            Some(Arc::new(LatencyHistograms::default()))
+        } else {
+            None
+        };
+
+        let snapshots = Arc::new(Snapshots::new(
+            &config,
+            write_generation.load(SeqCst),
+        ));
+
+        let eviction = config.max_db_size.map(|max_size| {
+            Arc::new(Eviction::new(max_size, config.eviction_policy))
+        });
+
+        let flush_every = Arc::new(AtomicU64::new(
+            config.flush_every_ms.unwrap_or(NEVER),
+        ));
+
         Ok(Self {
             config,
             pagecache,
+            write_generation,
+            free_space: Arc::new(FreeSpace::default()),
+            last_step: Arc::new(Mutex::new(None)),
+            read_only: Arc::new(AtomicBool::new(false)),
+            latency,
+            snapshots,
+            flush_every,
+            eviction,
             #[cfg(any(
                 windows,
                 target_os = "linux",
@@ -90,6 +153,13 @@ impl Context {
         })
     }
 
+    /// Returns the current `flush_every_ms`, as set by
+    /// `Config::flush_every_ms` or `Db::set_flush_every_ms`.
+    pub(crate) fn current_flush_every_ms(&self) -> Option<u64> {
+        let every_ms = self.flush_every.load(SeqCst);
+        if every_ms == NEVER { None } else { Some(every_ms) }
+    }
+
     /// Returns `true` if the database was
     /// recovered from a previous process.
     /// Note that database state is only
@@ -116,7 +186,91 @@ impl Context {
         self.pagecache.generate_id()
     }
 
+    /// Returns the current write generation. See
+    /// `Db::write_generation`.
+    pub fn write_generation(&self) -> u64 {
+        self.write_generation.load(SeqCst)
+    }
+
+    /// Returns an error if a write should be refused
+    /// because of `Config::min_free_space`, or because of
+    /// `check_writable`.
+    pub(crate) fn check_free_space(&self) -> Result<()> {
+        self.check_writable()?;
+        self.free_space.check(&self.config)
+    }
+
+    /// Returns an error if the database is read-only because
+    /// `RecoveryPolicy::ReadOnlyAfterCorruption` found damaged
+    /// pages while opening it, or because it was opened with
+    /// `Db::open_from_bytes`.
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only.load(Acquire) {
+            return Err(Error::Unsupported(
+                "the database is read-only, either because it was \
+                 opened with Db::open_from_bytes, or because it was \
+                 opened with RecoveryPolicy::ReadOnlyAfterCorruption \
+                 and damaged pages were found, see Db::lost_data_report"
+                    .to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns an error if a write should be refused
+    /// because its key or value is larger than
+    /// `Config::max_key_size` or `Config::max_value_size`.
+    pub(crate) fn check_item_size(
+        &self,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<()> {
+        let key_size = u64::try_from(key.len()).unwrap();
+        if key_size > self.config.max_key_size {
+            return Err(Error::Unsupported(format!(
+                "key of {} bytes is larger than max_key_size of {} bytes",
+                key_size, self.config.max_key_size
+            )));
+        }
+
+        let value_size = u64::try_from(value.map_or(0, <[u8]>::len)).unwrap();
+        if value_size > self.config.max_value_size {
+            return Err(Error::Unsupported(format!(
+                "value of {} bytes is larger than max_value_size of {} bytes",
+                value_size, self.config.max_value_size
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Starts measuring an operation for
+    /// `Config::collect_latency_histograms`.
+    #[inline]
+    pub(crate) fn latency_timer<F>(&self, histogram: F) -> LatencyTimer<'_>
+    where
+        F: FnOnce(&LatencyHistograms) -> &Histogram,
+    {
+        let target = self.latency.as_ref().map(|latency| histogram(latency));
+        LatencyTimer::new(target)
+    }
+
     pub(crate) fn pin_log(&self, guard: &Guard) -> Result<RecoveryGuard<'_>> {
         self.pagecache.pin_log(guard)
     }
+
+    /// Evicts items until `Config::max_db_size` holds again,
+    /// keeping `written`, the tree and key of a single-key
+    /// write that may have exceeded it. Writes call this once
+    /// their batch is sealed and they no longer hold the
+    /// concurrency control, which the removals take like any
+    /// other write. The writes have already happened, so a
+    /// failure to evict is only logged.
+    pub(crate) fn evict_over_limit(&self, written: Option<(&[u8], &[u8])>) {
+        if let Some(eviction) = &self.eviction {
+            if let Err(e) = eviction.evict(written) {
+                error!("failed to evict items over max_db_size: {:?}", e);
+            }
+        }
+    }
 }
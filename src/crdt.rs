@@ -0,0 +1,714 @@
+//! Conflict-free replicated data types that converge through
+//! `Tree::merge`.
+//!
+//! Each type in this module is a state-based CRDT: two states can
+//! always be combined with `merge`, and the combination is
+//! associative, commutative and idempotent. That means replicas may
+//! exchange their states in any order, any number of times, and all
+//! of them end up with the same value once they have seen the same
+//! updates.
+//!
+//! The functions [`gcounter`], [`pn_counter`], [`lww_register`] and
+//! [`or_set`] return merge operators that decode the stored state and
+//! the merged operand, join them, and store the encoded result. A
+//! tree holds one kind of CRDT per merge operator, so use a separate
+//! `Tree` for each kind.
+//!
+//! Local updates are applied to a replica's own copy of the state,
+//! either with `Tree::update_and_fetch` or by holding the state in
+//! memory, and the resulting state is then merged into every other
+//! replica with `Tree::merge`. Merging a state that has already been
+//! applied is a no-op, so redelivery is harmless.
+//!
+//! # Examples
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use sled::crdt::{self, GCounter};
+//!
+//! let db = sled::Config::new().temporary(true).open()?;
+//! let counters = db.open_tree("counters")?;
+//! counters.set_merge_operator(crdt::gcounter());
+//!
+//! // two replicas count page views independently
+//! let mut a = GCounter::new();
+//! a.increment(1, 3);
+//! let mut b = GCounter::new();
+//! b.increment(2, 4);
+//!
+//! // their states can be merged in any order
+//! counters.merge("views", b.encode())?;
+//! counters.merge("views", a.encode())?;
+//! counters.merge("views", b.encode())?;
+//!
+//! let stored = counters.get("views")?.unwrap();
+//! assert_eq!(GCounter::decode(&stored)?.value(), 7);
+//! # Ok(()) }
+//! ```
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+};
+
+use crate::{error, Error, MergeOperator, Result};
+
+const GCOUNTER_TAG: u8 = 1;
+const PN_COUNTER_TAG: u8 = 2;
+const LWW_REGISTER_TAG: u8 = 3;
+const OR_SET_TAG: u8 = 4;
+
+/// An identifier for a replica that may update a CRDT independently.
+pub type ReplicaId = u64;
+
+/// A grow-only counter. Each replica increments its own slot, and the
+/// value is the sum over all slots.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GCounter {
+    counts: BTreeMap<ReplicaId, u64>,
+}
+
+impl GCounter {
+    /// Creates a counter with a value of zero.
+    pub fn new() -> GCounter {
+        GCounter::default()
+    }
+
+    /// Adds `by` to the slot owned by `replica`.
+    pub fn increment(&mut self, replica: ReplicaId, by: u64) {
+        let count = self.counts.entry(replica).or_insert(0);
+        *count = count.saturating_add(by);
+    }
+
+    /// Returns the current value of the counter.
+    pub fn value(&self) -> u64 {
+        self.counts.values().fold(0, |acc, c| acc.saturating_add(*c))
+    }
+
+    /// Joins another state into this one by taking the maximum of
+    /// each replica's slot.
+    pub fn merge(&mut self, other: &GCounter) {
+        for (replica, count) in &other.counts {
+            let ours = self.counts.entry(*replica).or_insert(0);
+            *ours = (*ours).max(*count);
+        }
+    }
+
+    /// Encodes the state for storage or for use as a merge operand.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![GCOUNTER_TAG];
+        self.encode_into(&mut buf);
+        buf
+    }
+
+    /// Decodes a state produced by `encode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if the bytes are not an encoded
+    /// `GCounter`.
+    pub fn decode(bytes: &[u8]) -> Result<GCounter> {
+        let mut buf = expect_tag(bytes, GCOUNTER_TAG, "GCounter")?;
+        let ret = GCounter::decode_from(&mut buf)?;
+        expect_end(buf)?;
+        Ok(ret)
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        put_u64(buf, self.counts.len() as u64);
+        for (replica, count) in &self.counts {
+            put_u64(buf, *replica);
+            put_u64(buf, *count);
+        }
+    }
+
+    fn decode_from(buf: &mut &[u8]) -> Result<GCounter> {
+        let len = take_u64(buf)?;
+        let mut counts = BTreeMap::new();
+        for _ in 0..len {
+            let replica = take_u64(buf)?;
+            let count = take_u64(buf)?;
+            counts.insert(replica, count);
+        }
+        Ok(GCounter { counts })
+    }
+}
+
+/// A counter that supports both increments and decrements, built
+/// from a pair of grow-only counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PnCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PnCounter {
+    /// Creates a counter with a value of zero.
+    pub fn new() -> PnCounter {
+        PnCounter::default()
+    }
+
+    /// Adds `by` on behalf of `replica`.
+    pub fn increment(&mut self, replica: ReplicaId, by: u64) {
+        self.increments.increment(replica, by);
+    }
+
+    /// Subtracts `by` on behalf of `replica`.
+    pub fn decrement(&mut self, replica: ReplicaId, by: u64) {
+        self.decrements.increment(replica, by);
+    }
+
+    /// Returns the current value of the counter.
+    pub fn value(&self) -> i128 {
+        i128::from(self.increments.value())
+            - i128::from(self.decrements.value())
+    }
+
+    /// Joins another state into this one.
+    pub fn merge(&mut self, other: &PnCounter) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+
+    /// Encodes the state for storage or for use as a merge operand.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![PN_COUNTER_TAG];
+        self.increments.encode_into(&mut buf);
+        self.decrements.encode_into(&mut buf);
+        buf
+    }
+
+    /// Decodes a state produced by `encode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if the bytes are not an encoded
+    /// `PnCounter`.
+    pub fn decode(bytes: &[u8]) -> Result<PnCounter> {
+        let mut buf = expect_tag(bytes, PN_COUNTER_TAG, "PnCounter")?;
+        let increments = GCounter::decode_from(&mut buf)?;
+        let decrements = GCounter::decode_from(&mut buf)?;
+        expect_end(buf)?;
+        Ok(PnCounter { increments, decrements })
+    }
+}
+
+/// A last-writer-wins register. Writes are ordered by timestamp, and
+/// ties are broken by replica id and then by value, so concurrent
+/// writes resolve the same way on every replica.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LwwRegister {
+    timestamp: u64,
+    replica: ReplicaId,
+    value: Vec<u8>,
+}
+
+impl LwwRegister {
+    /// Creates a register holding `value`, written by `replica` at
+    /// `timestamp`.
+    pub fn new<V: Into<Vec<u8>>>(
+        timestamp: u64,
+        replica: ReplicaId,
+        value: V,
+    ) -> LwwRegister {
+        LwwRegister { timestamp, replica, value: value.into() }
+    }
+
+    /// Writes `value` if it is ordered after the current value.
+    /// Returns `true` if the write took effect.
+    pub fn set<V: Into<Vec<u8>>>(
+        &mut self,
+        timestamp: u64,
+        replica: ReplicaId,
+        value: V,
+    ) -> bool {
+        let write = LwwRegister::new(timestamp, replica, value);
+        if write.cmp_order(self) == Ordering::Greater {
+            *self = write;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the current value.
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Returns the timestamp of the current value.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Returns the replica that wrote the current value.
+    pub fn replica(&self) -> ReplicaId {
+        self.replica
+    }
+
+    /// Joins another state into this one, keeping whichever write is
+    /// ordered last.
+    pub fn merge(&mut self, other: &LwwRegister) {
+        if other.cmp_order(self) == Ordering::Greater {
+            *self = other.clone();
+        }
+    }
+
+    /// Encodes the state for storage or for use as a merge operand.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![LWW_REGISTER_TAG];
+        put_u64(&mut buf, self.timestamp);
+        put_u64(&mut buf, self.replica);
+        put_bytes(&mut buf, &self.value);
+        buf
+    }
+
+    /// Decodes a state produced by `encode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if the bytes are not an encoded
+    /// `LwwRegister`.
+    pub fn decode(bytes: &[u8]) -> Result<LwwRegister> {
+        let mut buf = expect_tag(bytes, LWW_REGISTER_TAG, "LwwRegister")?;
+        let timestamp = take_u64(&mut buf)?;
+        let replica = take_u64(&mut buf)?;
+        let value = take_bytes(&mut buf)?;
+        expect_end(buf)?;
+        Ok(LwwRegister { timestamp, replica, value })
+    }
+
+    fn cmp_order(&self, other: &LwwRegister) -> Ordering {
+        (self.timestamp, self.replica, &self.value).cmp(&(
+            other.timestamp,
+            other.replica,
+            &other.value,
+        ))
+    }
+}
+
+/// A unique tag for one insertion into an `OrSet`.
+type Dot = (ReplicaId, u64);
+
+/// An observed-remove set. An element is present if it has been
+/// inserted more recently than it was removed, as observed by the
+/// replica that removed it, so concurrent insertions win over
+/// removals.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrSet {
+    adds: BTreeMap<Vec<u8>, BTreeSet<Dot>>,
+    removed: BTreeSet<Dot>,
+}
+
+impl OrSet {
+    /// Creates an empty set.
+    pub fn new() -> OrSet {
+        OrSet::default()
+    }
+
+    /// Inserts `element` on behalf of `replica`.
+    pub fn insert<E: Into<Vec<u8>>>(&mut self, replica: ReplicaId, element: E) {
+        let seq = self.max_seq(replica) + 1;
+        self.adds.entry(element.into()).or_default().insert((replica, seq));
+    }
+
+    /// Removes every insertion of `element` observed by this state.
+    /// Returns `true` if the element was present.
+    pub fn remove(&mut self, element: &[u8]) -> bool {
+        if let Some(dots) = self.adds.remove(element) {
+            self.removed.extend(dots);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns `true` if `element` is present.
+    pub fn contains(&self, element: &[u8]) -> bool {
+        self.adds.contains_key(element)
+    }
+
+    /// Returns the number of elements present.
+    pub fn len(&self) -> usize {
+        self.adds.len()
+    }
+
+    /// Returns `true` if no elements are present.
+    pub fn is_empty(&self) -> bool {
+        self.adds.is_empty()
+    }
+
+    /// Iterates over the present elements in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        self.adds.keys().map(Vec::as_slice)
+    }
+
+    /// Joins another state into this one. Insertions and removals
+    /// from both states are combined, and any insertion that either
+    /// side has removed stays removed.
+    pub fn merge(&mut self, other: &OrSet) {
+        self.removed.extend(other.removed.iter().cloned());
+        for (element, dots) in &other.adds {
+            self.adds
+                .entry(element.clone())
+                .or_default()
+                .extend(dots.iter().cloned());
+        }
+        let removed = &self.removed;
+        let mut emptied = vec![];
+        for (element, dots) in &mut self.adds {
+            dots.retain(|dot| !removed.contains(dot));
+            if dots.is_empty() {
+                emptied.push(element.clone());
+            }
+        }
+        for element in emptied {
+            self.adds.remove(&element);
+        }
+    }
+
+    /// Encodes the state for storage or for use as a merge operand.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![OR_SET_TAG];
+        put_u64(&mut buf, self.adds.len() as u64);
+        for (element, dots) in &self.adds {
+            put_bytes(&mut buf, element);
+            put_dots(&mut buf, dots);
+        }
+        put_dots(&mut buf, &self.removed);
+        buf
+    }
+
+    /// Decodes a state produced by `encode`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Unsupported` if the bytes are not an encoded
+    /// `OrSet`.
+    pub fn decode(bytes: &[u8]) -> Result<OrSet> {
+        let mut buf = expect_tag(bytes, OR_SET_TAG, "OrSet")?;
+        let len = take_u64(&mut buf)?;
+        let mut adds = BTreeMap::new();
+        for _ in 0..len {
+            let element = take_bytes(&mut buf)?;
+            let dots = take_dots(&mut buf)?;
+            if dots.is_empty() {
+                return Err(malformed());
+            }
+            adds.insert(element, dots);
+        }
+        let removed = take_dots(&mut buf)?;
+        expect_end(buf)?;
+        let mut ret = OrSet { adds: BTreeMap::new(), removed };
+        // run the decoded insertions through merge so that any
+        // insertion that was also removed is dropped
+        ret.merge(&OrSet { adds, removed: BTreeSet::new() });
+        Ok(ret)
+    }
+
+    fn max_seq(&self, replica: ReplicaId) -> u64 {
+        self.adds
+            .values()
+            .flat_map(|dots| dots.iter())
+            .chain(self.removed.iter())
+            .filter(|dot| dot.0 == replica)
+            .map(|dot| dot.1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Returns a merge operator that joins encoded `GCounter` states.
+pub fn gcounter() -> impl MergeOperator {
+    join::<GCounter>
+}
+
+/// Returns a merge operator that joins encoded `PnCounter` states.
+pub fn pn_counter() -> impl MergeOperator {
+    join::<PnCounter>
+}
+
+/// Returns a merge operator that joins encoded `LwwRegister` states.
+pub fn lww_register() -> impl MergeOperator {
+    join::<LwwRegister>
+}
+
+/// Returns a merge operator that joins encoded `OrSet` states.
+pub fn or_set() -> impl MergeOperator {
+    join::<OrSet>
+}
+
+trait State: Sized {
+    fn decode(bytes: &[u8]) -> Result<Self>;
+    fn merge(&mut self, other: &Self);
+    fn encode(&self) -> Vec<u8>;
+}
+
+macro_rules! impl_state {
+    ($($t:ty),*) => {
+        $(
+            impl State for $t {
+                fn decode(bytes: &[u8]) -> Result<$t> {
+                    <$t>::decode(bytes)
+                }
+
+                fn merge(&mut self, other: &$t) {
+                    <$t>::merge(self, other)
+                }
+
+                fn encode(&self) -> Vec<u8> {
+                    <$t>::encode(self)
+                }
+            }
+        )*
+    };
+}
+
+impl_state!(GCounter, PnCounter, LwwRegister, OrSet);
+
+// Merge operators can't return errors, so a state that fails to decode
+// leaves the stored value untouched rather than clobbering it.
+fn join<T: State>(
+    key: &[u8],
+    old: Option<&[u8]>,
+    operand: &[u8],
+) -> Option<Vec<u8>> {
+    let incoming = match T::decode(operand) {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            error!("ignoring CRDT merge operand for key {:?}: {:?}", key, e);
+            return old.map(<[u8]>::to_vec);
+        }
+    };
+    let old_bytes = if let Some(old_bytes) = old {
+        old_bytes
+    } else {
+        return Some(incoming.encode());
+    };
+    match T::decode(old_bytes) {
+        Ok(mut state) => {
+            state.merge(&incoming);
+            Some(state.encode())
+        }
+        Err(e) => {
+            error!("stored CRDT state for key {:?} is invalid: {:?}", key, e);
+            Some(old_bytes.to_vec())
+        }
+    }
+}
+
+fn malformed() -> Error {
+    Error::Unsupported("malformed CRDT state".to_owned())
+}
+
+fn expect_tag<'a>(bytes: &'a [u8], tag: u8, name: &str) -> Result<&'a [u8]> {
+    match bytes.split_first() {
+        Some((first, rest)) if *first == tag => Ok(rest),
+        _ => Err(Error::Unsupported(format!(
+            "bytes are not an encoded {}",
+            name
+        ))),
+    }
+}
+
+fn expect_end(buf: &[u8]) -> Result<()> {
+    if buf.is_empty() { Ok(()) } else { Err(malformed()) }
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn take_u64(buf: &mut &[u8]) -> Result<u64> {
+    if buf.len() < 8 {
+        return Err(malformed());
+    }
+    let mut array = [0; 8];
+    array.copy_from_slice(&buf[..8]);
+    *buf = &buf[8..];
+    Ok(u64::from_be_bytes(array))
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn take_bytes(buf: &mut &[u8]) -> Result<Vec<u8>> {
+    let len = usize::try_from(take_u64(buf)?).map_err(|_| malformed())?;
+    if buf.len() < len {
+        return Err(malformed());
+    }
+    let ret = buf[..len].to_vec();
+    *buf = &buf[len..];
+    Ok(ret)
+}
+
+fn put_dots(buf: &mut Vec<u8>, dots: &BTreeSet<Dot>) {
+    put_u64(buf, dots.len() as u64);
+    for (replica, seq) in dots {
+        put_u64(buf, *replica);
+        put_u64(buf, *seq);
+    }
+}
+
+fn take_dots(buf: &mut &[u8]) -> Result<BTreeSet<Dot>> {
+    let len = take_u64(buf)?;
+    let mut dots = BTreeSet::new();
+    for _ in 0..len {
+        let replica = take_u64(buf)?;
+        let seq = take_u64(buf)?;
+        dots.insert((replica, seq));
+    }
+    Ok(dots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // applies every permutation of `states` to a fresh state and
+    // checks that they all agree
+    fn converges<T, F>(states: &[T], merge: F) -> T
+    where
+        T: Clone + Default + PartialEq + std::fmt::Debug,
+        F: Fn(&mut T, &T),
+    {
+        fn permutations(n: usize) -> Vec<Vec<usize>> {
+            if n == 0 {
+                return vec![vec![]];
+            }
+            let mut ret = vec![];
+            for rest in permutations(n - 1) {
+                for i in 0..n {
+                    let mut p = rest.clone();
+                    p.insert(i, n - 1);
+                    ret.push(p);
+                }
+            }
+            ret
+        }
+
+        let mut expected = None;
+        for order in permutations(states.len()) {
+            let mut state = T::default();
+            for i in order {
+                merge(&mut state, &states[i]);
+                // idempotence
+                merge(&mut state, &states[i]);
+            }
+            match &expected {
+                None => expected = Some(state),
+                Some(first) => assert_eq!(&state, first),
+            }
+        }
+        expected.unwrap()
+    }
+
+    #[test]
+    fn gcounter_converges() {
+        let states: Vec<GCounter> = (0..4)
+            .map(|r| {
+                let mut c = GCounter::new();
+                c.increment(r % 3, r + 1);
+                c
+            })
+            .collect();
+        let merged = converges(&states, GCounter::merge);
+        // replica 0 incremented by 1 and 4, but each state carries
+        // the replica's whole slot so the max wins
+        assert_eq!(merged.value(), 4 + 2 + 3);
+        assert_eq!(GCounter::decode(&merged.encode()).unwrap(), merged);
+    }
+
+    #[test]
+    fn pn_counter_converges() {
+        let mut a = PnCounter::new();
+        a.increment(1, 10);
+        let mut b = a.clone();
+        b.decrement(2, 3);
+        let mut c = PnCounter::new();
+        c.decrement(3, 20);
+        let merged = converges(&[a, b, c], PnCounter::merge);
+        assert_eq!(merged.value(), -13);
+        assert_eq!(PnCounter::decode(&merged.encode()).unwrap(), merged);
+    }
+
+    impl Default for LwwRegister {
+        fn default() -> LwwRegister {
+            LwwRegister::new(0, 0, vec![])
+        }
+    }
+
+    #[test]
+    fn lww_register_converges() {
+        let states = vec![
+            LwwRegister::new(5, 1, "a"),
+            LwwRegister::new(7, 2, "b"),
+            LwwRegister::new(7, 1, "c"),
+            LwwRegister::new(3, 9, "d"),
+        ];
+        let merged = converges(&states, LwwRegister::merge);
+        assert_eq!(merged.value(), b"b");
+        assert_eq!(merged.timestamp(), 7);
+        assert_eq!(merged.replica(), 2);
+        assert_eq!(LwwRegister::decode(&merged.encode()).unwrap(), merged);
+
+        let mut r = merged;
+        assert!(!r.set(6, 3, "stale"));
+        assert!(r.set(8, 3, "fresh"));
+        assert_eq!(r.value(), b"fresh");
+    }
+
+    #[test]
+    fn or_set_converges() {
+        let mut base = OrSet::new();
+        base.insert(1, "x");
+        base.insert(1, "y");
+
+        // replica 2 removes x after observing it
+        let mut removed = base.clone();
+        assert!(removed.remove(b"x"));
+
+        // replica 3 concurrently re-adds x, which survives the remove
+        let mut readded = base.clone();
+        readded.insert(3, "x");
+        readded.insert(3, "z");
+
+        // replica 1 removes y
+        let mut dropped_y = base.clone();
+        assert!(dropped_y.remove(b"y"));
+
+        let merged = converges(&[base, removed, readded, dropped_y], |a, b| {
+            a.merge(b)
+        });
+        let elements: Vec<&[u8]> = merged.iter().collect();
+        assert_eq!(elements, vec![&b"x"[..], &b"z"[..]]);
+        assert_eq!(merged.len(), 2);
+        assert!(!merged.contains(b"y"));
+        assert_eq!(OrSet::decode(&merged.encode()).unwrap(), merged);
+
+        // new insertions get fresh tags even after removals
+        let mut again = merged;
+        assert!(again.remove(b"x"));
+        again.insert(2, "x");
+        assert!(again.contains(b"x"));
+    }
+
+    #[test]
+    fn decode_rejects_malformed_states() {
+        let counter = GCounter::new().encode();
+        assert!(PnCounter::decode(&counter).is_err());
+        assert!(GCounter::decode(&[]).is_err());
+
+        let mut set = OrSet::new();
+        set.insert(1, "x");
+        let encoded = set.encode();
+        for len in 0..encoded.len() {
+            assert!(OrSet::decode(&encoded[..len]).is_err());
+        }
+        let mut trailing = encoded;
+        trailing.push(0);
+        assert!(OrSet::decode(&trailing).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::ops::{Deref, RangeBounds};
 
 use crate::*;
 
@@ -10,7 +10,9 @@ pub struct Db {
     #[doc(hidden)]
     pub context: Context,
     pub(crate) default: Tree,
-    tenants: Arc<RwLock<FastMap8<IVec, Tree>>>,
+    tenants: std::sync::Arc<Tenants>,
+    lost_data: Arc<Vec<LostRange>>,
+    separated: Arc<BTreeMap<IVec, Db>>,
 }
 
 /// Opens a `Db` with a default configuration at the
@@ -20,11 +22,100 @@ pub struct Db {
 /// method to determine if your database was recovered
 /// from a previous instance. You can use `Config::create_new`
 /// if you want to increase the chances that the database
-/// will be freshly created.
+/// will be freshly created, or `Config::must_exist` if an
+/// existing database must be opened.
 pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Db> {
     Config::new().path(path).open()
 }
 
+/// Starts the background flusher at the current
+/// `flush_every_ms` of the database.
+#[cfg(any(
+    windows,
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+))]
+fn start_flusher(context: &Context) -> flusher::Flusher {
+    flusher::Flusher::new(
+        "log flusher".to_owned(),
+        context.pagecache.clone(),
+        context.flush_every.clone(),
+    )
+}
+
+/// Information about a tree that was removed by
+/// `Db::drop_tree_detailed`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DroppedTreeInfo {
+    /// The number of keys that the tree contained.
+    pub key_count: u64,
+    /// The total size of the tree's pages in the log.
+    /// This space becomes reusable once the segments that
+    /// hold it are cleaned, so the storage file does not
+    /// shrink immediately.
+    pub approx_bytes_reclaimed: u64,
+}
+
+/// The log bytes written by `Db::flush_detailed`, and the
+/// trees whose writes they came from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FlushReport {
+    /// The log bytes taken by the writes to each tree since
+    /// the previous call to `Db::flush_detailed`, by tree
+    /// name, for the trees that were written to. These
+    /// include writes that were already flushed in the
+    /// background since then, and the splits and merges of
+    /// the trees' pages.
+    pub trees: BTreeMap<IVec, u64>,
+    /// The number of bytes that this flush wrote, as
+    /// returned by `Tree::flush`. Buffers of the log that
+    /// filled up were written before, and are not included,
+    /// so this can be less than the bytes of the trees.
+    /// Besides the writes of the trees, this includes the
+    /// padding and metadata of the log, and pages that were
+    /// rewritten to reclaim space.
+    pub total: u64,
+    /// The number of times the log was synced since the
+    /// previous call to `Db::flush_detailed`, including the
+    /// syncs of the background flusher. All trees share one
+    /// log, so each sync covers the writes of every tree that
+    /// were buffered before it, and a flush syncs once unless
+    /// its writes did not fit in one buffer of the log.
+    /// Temporary databases are never synced, so this is 0
+    /// for them.
+    pub syncs: u64,
+}
+
+/// The progress of a flush started by
+/// `Db::flush_with_progress`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushProgress {
+    /// The number of bytes of the log that have been made
+    /// durable so far by this flush.
+    pub flushed: u64,
+    /// The number of bytes of the log that were not yet
+    /// durable when the flush started. The flush is
+    /// complete once `flushed` reaches it.
+    pub total: u64,
+}
+
+/// A range of keys whose pages failed their checksum when
+/// the `Db` was opened, as listed by `Db::lost_data_report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LostRange {
+    /// The name of the tree that held the range.
+    pub tree: IVec,
+    /// The first key of the range.
+    pub lo: IVec,
+    /// The key after the end of the range, or `None` if
+    /// the range extends past every key of the tree.
+    pub hi: Option<IVec>,
+}
+
 #[allow(unsafe_code)]
 unsafe impl Send for Db {}
 
@@ -64,6 +155,54 @@ impl Db {
         Config::new().path(path).open()
     }
 
+    /// Opens a read-only `Db` holding the trees of an image
+    /// that was created by `Db::to_bytes`.
+    ///
+    /// The items are copied into a database that is kept
+    /// entirely in memory, without touching the filesystem,
+    /// and is gone once the `Db` is dropped. Writes, including
+    /// opening a tree that isn't in the image, return
+    /// `Error::Unsupported`. To modify the items, `import` the
+    /// `export` of this `Db` into one that was opened normally.
+    ///
+    /// Returns `Error::Unsupported` if `data` isn't an image,
+    /// and `Error::Corruption` if it is damaged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    /// db.insert(b"a", b"1")?;
+    /// db.open_tree(b"other")?.insert(b"b", b"2")?;
+    ///
+    /// let image = db.to_bytes()?;
+    /// let copy = sled::Db::open_from_bytes(&image)?;
+    ///
+    /// assert_eq!(copy.get(b"a")?, Some(sled::IVec::from(b"1")));
+    /// assert_eq!(copy.open_tree(b"other")?.len(), 1);
+    /// assert!(copy.insert(b"a", b"2").is_err());
+    /// # Ok(()) }
+    /// ```
+    pub fn open_from_bytes(data: &[u8]) -> Result<Self> {
+        let trees = image::decode(data)?;
+
+        let db = Config::new().in_memory().open()?;
+        for (name, items) in trees {
+            let tree = db.open_tree(name)?;
+            let mut batch = Batch::default();
+            for (k, v) in items {
+                batch.insert(k, v);
+            }
+            tree.apply_batch(batch)?;
+        }
+
+        db.context.read_only.store(true, Release);
+
+        Ok(db)
+    }
+
     pub(crate) fn start_inner(config: RunningConfig) -> Result<Self> {
         let _measure = Measure::new(&M.tree_start);
 
@@ -79,53 +218,172 @@ impl Db {
             target_os = "netbsd",
         ))]
         {
-            let flusher_pagecache = context.pagecache.clone();
-            let flusher = context.flush_every_ms.map(move |fem| {
-                flusher::Flusher::new(
-                    "log flusher".to_owned(),
-                    flusher_pagecache,
-                    fem,
-                )
-            });
-            *context.flusher.lock() = flusher;
+            // with a test clock, flushing is driven by `Db::step`,
+            // and with `manual_flush` by `Db::tick`
+            if context.clock.is_none()
+                && !context.manual_flush
+                && context.flush_every_ms.is_some()
+            {
+                *context.flusher.lock() = Some(start_flusher(&context));
+            }
         }
 
-        // create or open the default tree
         let guard = pin();
-        let default =
-            meta::open_tree(&context, DEFAULT_TREE_ID.to_vec(), &guard)?;
 
-        let ret = Self {
+        // the store of shared values is itself a plain tree
+        let dedup = if context.dedup_values {
+            let name = DEDUP_TREE_ID.to_vec();
+            let store = meta::open_tree(&context, name, None, &guard)?;
+            Some(Dedup::new(store))
+        } else {
+            None
+        };
+
+        // create or open the default tree
+        let default = meta::open_tree(
+            &context,
+            DEFAULT_TREE_ID.to_vec(),
+            dedup.clone(),
+            &guard,
+        )?;
+
+        let mut ret = Self {
             context: context.clone(),
             default,
-            tenants: Arc::new(RwLock::new(FastMap8::default())),
+            tenants: std::sync::Arc::new(RwLock::new(FastMap8::default())),
+            lost_data: Arc::new(vec![]),
+            separated: Arc::new(BTreeMap::new()),
         };
 
         let mut tenants = ret.tenants.write();
 
         for (id, root) in context.pagecache.get_meta(&guard)?.tenants() {
+            if dedup.is_some() && id == DEDUP_TREE_ID {
+                continue;
+            }
             let tree = Tree(Arc::new(TreeInner {
                 tree_id: id.clone(),
                 subscribers: Subscribers::default(),
                 context: context.clone(),
                 root: AtomicU64::new(root),
                 merge_operator: RwLock::new(None),
+                write_generation: AtomicU64::new(context.write_generation()),
+                logged_bytes: AtomicU64::new(0),
+                checksum_cache: Mutex::default(),
+                subtree_counts: Mutex::default(),
+                dedup: dedup.clone(),
             }));
             assert!(tenants.insert(id, tree).is_none());
         }
 
         drop(tenants);
 
+        *context.snapshots.tenants.lock() =
+            Some(std::sync::Arc::downgrade(&ret.tenants));
+
+        if context.recovery_policy != RecoveryPolicy::Strict {
+            let lost_data = ret.quarantine(dedup.as_ref())?;
+            if context.recovery_policy
+                == RecoveryPolicy::ReadOnlyAfterCorruption
+                && !lost_data.is_empty()
+            {
+                context.read_only.store(true, Release);
+            }
+            ret.lost_data = Arc::new(lost_data);
+        }
+
+        if let Some(eviction) = &context.eviction {
+            ret.track_for_eviction(eviction)?;
+        }
+
+        ret.separated = Arc::new(ret.open_separate_logs()?);
+
         #[cfg(feature = "event_log")]
         ret.context.event_log.verify();
 
         Ok(ret)
     }
 
+    /// Tracks every item for `Config::max_db_size`, and
+    /// evicts items if the limit was lowered since the
+    /// database was last opened. This reads every item of
+    /// every tree, so it takes time proportional to the
+    /// number of items, on every open.
+    fn track_for_eviction(&self, eviction: &Eviction) -> Result<()> {
+        let mut tenants = self.tenants.write();
+
+        // evictions from the default tree go through the
+        // handle that its subscribers are registered with
+        let _ = tenants.insert(DEFAULT_TREE_ID.into(), self.default.clone());
+
+        for (name, tree) in tenants.iter() {
+            for item in tree {
+                let (key, value) = item?;
+                eviction.record_write(name, &key, Some(value.len()));
+            }
+        }
+        drop(tenants);
+
+        *eviction.tenants.lock() =
+            Some(std::sync::Arc::downgrade(&self.tenants));
+        eviction.evict(None)
+    }
+
+    /// Opens the databases that hold the trees named by
+    /// `Config::separate_log_for_tree`.
+    fn open_separate_logs(&self) -> Result<BTreeMap<IVec, Db>> {
+        let mut separated = BTreeMap::new();
+        for name in &self.context.separate_log_trees {
+            if self.tenants.read().contains_key(name) {
+                return Err(Error::Unsupported(format!(
+                    "tree {:?} already exists in the shared log, \
+                     so it can not be given a separate log",
+                    name
+                )));
+            }
+            let db = self.context.separate_log_config(name).open()?;
+            assert!(separated.insert(name.clone(), db).is_none());
+        }
+
+        // a separated tree that is no longer named would
+        // silently go missing otherwise
+        let dir = self.context.separate_logs_path();
+        if files::exists(&self.context, &dir) {
+            for path in files::read_dir(&self.context, &dir)? {
+                if !separated.values().any(|db| db.context.get_path() == path) {
+                    return Err(Error::Unsupported(format!(
+                        "{:?} holds a tree with a separate log, which \
+                         must be named with Config::separate_log_for_tree",
+                        path
+                    )));
+                }
+            }
+        }
+
+        Ok(separated)
+    }
+
+    /// Returns an error if `name` has a log of its own,
+    /// which `what` can't be done with.
+    fn check_shared_log(&self, name: &[u8], what: &str) -> Result<()> {
+        if self.separated.contains_key(name) {
+            return Err(Error::Unsupported(format!(
+                "{} is not supported for tree {:?}, which has a separate log",
+                what,
+                IVec::from(name)
+            )));
+        }
+        Ok(())
+    }
+
     /// Open or create a new disk-backed Tree with its own keyspace,
     /// accessible from the `Db` via the provided identifier.
     pub fn open_tree<V: AsRef<[u8]>>(&self, name: V) -> Result<Tree> {
         let name_ref = name.as_ref();
+        if let Some(db) = self.separated.get(name_ref) {
+            return db.open_tree(name_ref);
+        }
+        self.check_not_dedup_store(name_ref)?;
         let tenants = self.tenants.read();
         if let Some(tree) = tenants.get(name_ref) {
             return Ok(tree.clone());
@@ -142,20 +400,155 @@ impl Db {
             return Ok(tree.clone());
         }
 
-        let tree = meta::open_tree(&self.context, name_ref.to_vec(), &guard)?;
+        self.context.check_writable()?;
+        let tree = meta::open_tree(
+            &self.context,
+            name_ref.to_vec(),
+            self.default.dedup.clone(),
+            &guard,
+        )?;
 
         assert!(tenants.insert(name_ref.into(), tree.clone()).is_none());
 
         Ok(tree)
     }
 
+    /// Atomically apply a batch of updates that may span
+    /// several `Tree`s. After a crash, either every update
+    /// in the batch is recovered, or none of them are.
+    ///
+    /// Trees named in the batch are opened, and created if
+    /// they don't exist yet, before any update is applied.
+    /// Subscribers of each tree receive events for that
+    /// tree's updates as they are applied. A plain `Batch`
+    /// applies to the default tree, as `Tree::apply_batch`
+    /// does.
+    ///
+    /// Unlike a transaction, this can't read the current
+    /// state of a tree, so it's meant for blind writes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, MultiTreeBatch};
+    ///
+    /// let db = Config::new().temporary(true).open()?;
+    ///
+    /// let mut batch = MultiTreeBatch::default();
+    /// batch.insert("orders", "order_1", "2 widgets");
+    /// batch.insert("inventory", "widgets", "98");
+    /// db.apply_batch(batch)?;
+    ///
+    /// let inventory = db.open_tree("inventory")?;
+    /// assert_eq!(inventory.get("widgets")?, Some("98".into()));
+    /// # Ok(()) }
+    /// ```
+    pub fn apply_batch<B>(&self, batch: B) -> Result<()>
+    where
+        B: Into<MultiTreeBatch>,
+    {
+        let mut slices = vec![];
+        for (name, tree_batch) in batch.into().trees {
+            if tree_batch.writes.is_empty() {
+                continue;
+            }
+            // subscribers to the default tree are registered
+            // with the `Db`'s own handle
+            let tree = if &*name == DEFAULT_TREE_ID {
+                self.default.clone()
+            } else {
+                self.open_tree(&name)?
+            };
+            slices.push((tree, tree_batch));
+        }
+
+        // writes are only recovered atomically within one log
+        let shared = |tree: &Tree| {
+            std::ptr::eq(&*tree.context.pagecache, &*self.context.pagecache)
+        };
+        if slices.iter().any(|(tree, _)| !shared(tree)) {
+            if slices.len() > 1 {
+                return Err(Error::Unsupported(
+                    "a batch can not span a tree with a separate log \
+                     and other trees"
+                        .to_owned(),
+                ));
+            }
+            let (tree, tree_batch) = slices.pop().unwrap();
+            return tree.apply_batch(tree_batch);
+        }
+
+        self.context.check_free_space()?;
+        for (_, tree_batch) in &slices {
+            for (k, v_opt) in &tree_batch.writes {
+                if let Some(v) = v_opt {
+                    self.context.check_item_size(k, Some(v))?;
+                }
+            }
+        }
+
+        let cc = concurrency_control::write();
+        let mut guard = pin();
+        let peg = self.context.pin_log(&guard)?;
+        for (tree, tree_batch) in slices {
+            tree.write_batch(tree_batch, &mut guard)?;
+        }
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()?;
+        drop(cc);
+        self.context.evict_over_limit(None);
+        Ok(())
+    }
+
     /// Remove a disk-backed collection.
     pub fn drop_tree(&self, name: &[u8]) -> Result<bool> {
+        Ok(self.drop_tree_detailed(name)?.is_some())
+    }
+
+    /// Remove a disk-backed collection, returning the
+    /// number of keys it held and the approximate number
+    /// of bytes that were freed, or `None` if no tree
+    /// with this name exists.
+    ///
+    /// Trees do not track their size, so this information
+    /// is gathered while visiting each page of the tree to
+    /// free it. Dropping a tree already does this, so this
+    /// costs no more than `drop_tree`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let tree = db.open_tree(b"dropped")?;
+    /// tree.insert(b"a", b"1")?;
+    /// tree.insert(b"b", b"2")?;
+    ///
+    /// let info = db.drop_tree_detailed(b"dropped")?.unwrap();
+    /// assert_eq!(info.key_count, 2);
+    /// assert!(info.approx_bytes_reclaimed > 0);
+    ///
+    /// assert_eq!(db.drop_tree_detailed(b"dropped")?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn drop_tree_detailed(
+        &self,
+        name: &[u8],
+    ) -> Result<Option<DroppedTreeInfo>> {
         if name == DEFAULT_TREE_ID {
             return Err(Error::Unsupported(
                 "cannot remove the core structures".into(),
             ));
         }
+        if let Some(db) = self.separated.get(name) {
+            return db.drop_tree_detailed(name);
+        }
+        self.check_not_dedup_store(name)?;
+        self.context.check_writable()?;
         trace!("dropping tree {:?}", name,);
 
         let mut tenants = self.tenants.write();
@@ -163,7 +556,7 @@ impl Db {
         let tree = if let Some(tree) = tenants.remove(&*name) {
             tree
         } else {
-            return Ok(false);
+            return Ok(None);
         };
 
         let guard = pin();
@@ -183,6 +576,21 @@ impl Db {
             }
         }
 
+        // the references that the tree's values hold are
+        // released atomically with the removal of its root
+        let release = if let Some(dedup) = &tree.dedup {
+            let cc = concurrency_control::write();
+            let peg = self.context.pin_log(&guard)?;
+            let mut iter = tree.iter();
+            while let Some(item) = iter.next_inner() {
+                let (_key, stored) = item?;
+                dedup.release(&stored)?;
+            }
+            Some((cc, peg))
+        } else {
+            None
+        };
+
         loop {
             let res = self
                 .context
@@ -196,22 +604,352 @@ impl Db {
             }
         }
 
+        // handles to the tree fail from now on. this happens
+        // before writers that wait for the concurrency control
+        // lock can add references that would never be released
         tree.root.store(u64::max_value(), SeqCst);
 
+        if let Some(eviction) = &self.context.eviction {
+            eviction.forget_tree(name);
+        }
+
+        if let Some((_cc, peg)) = release {
+            peg.seal_batch()?;
+        }
+
         // drop writer lock
         drop(tenants);
 
-        tree.gc_pages(leftmost_chain)?;
+        let info = tree.gc_pages(leftmost_chain)?;
 
         guard.flush();
 
-        Ok(true)
+        Ok(Some(info))
     }
 
-    /// Returns the trees names saved in this Db.
-    pub fn tree_names(&self) -> Vec<IVec> {
-        let tenants = self.tenants.read();
-        tenants.iter().map(|(name, _)| name.clone()).collect()
+    /// Creates the tree `dst` as a copy of the tree `src`,
+    /// and returns it. Writes to either tree after this
+    /// returns do not affect the other. Returns
+    /// `Error::CollectionNotFound` if `src` does not exist,
+    /// and `Error::Unsupported` if `dst` already exists.
+    ///
+    /// The copy is eager rather than copy-on-write: every
+    /// item of `src` is read and written to `dst` in a single
+    /// pass, without reading the whole tree into memory
+    /// first. It reflects `src` at one point in time, and is
+    /// recovered atomically, so after a crash `dst` either
+    /// does not exist or is complete. This blocks all writes
+    /// to the `Db` while it runs, and writes as much to the
+    /// log as inserting every item of `src` would. Merge
+    /// operators are not copied, and need to be set on
+    /// `dst` separately.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let blue = db.open_tree(b"blue")?;
+    /// blue.insert(b"k", b"v1")?;
+    ///
+    /// let green = db.copy_tree(b"blue", b"green")?;
+    /// green.insert(b"k", b"v2")?;
+    ///
+    /// assert_eq!(blue.get(b"k")?, Some(sled::IVec::from(b"v1")));
+    /// assert_eq!(green.get(b"k")?, Some(sled::IVec::from(b"v2")));
+    /// # Ok(()) }
+    /// ```
+    pub fn copy_tree<S, D>(&self, src: S, dst: D) -> Result<Tree>
+    where
+        S: AsRef<[u8]>,
+        D: AsRef<[u8]>,
+    {
+        let (src_name, dst_name) = (src.as_ref(), dst.as_ref());
+        self.check_not_dedup_store(src_name)?;
+        self.check_not_dedup_store(dst_name)?;
+        self.check_shared_log(src_name, "copy_tree")?;
+        self.check_shared_log(dst_name, "copy_tree")?;
+        self.context.check_free_space()?;
+
+        let mut tenants = self.tenants.write();
+
+        let from = if let Some(tree) = tenants.get(src_name) {
+            tree.clone()
+        } else {
+            return Err(Error::CollectionNotFound(src_name.into()));
+        };
+        if tenants.contains_key(dst_name) {
+            return Err(Error::Unsupported(format!(
+                "cannot copy tree {:?} to {:?}, which already exists",
+                IVec::from(src_name),
+                IVec::from(dst_name)
+            )));
+        }
+
+        // other writers are kept out until the copy is sealed,
+        // so the iterator does not need to take the lock too
+        let cc = concurrency_control::write();
+        let mut guard = pin();
+        let peg = self.context.pin_log(&guard)?;
+
+        let to = meta::open_tree(
+            &self.context,
+            dst_name.to_vec(),
+            self.default.dedup.clone(),
+            &guard,
+        )?;
+
+        trace!("copying tree {:?} to {:?}", from.tree_id, to.tree_id);
+        for (i, item) in from.iter().relaxed().enumerate() {
+            let (k, v) = item?;
+            while to.insert_inner(&k, Some(v.clone()), &mut guard)?.is_err() {}
+
+            // let the epoch advance so that large copies
+            // do not hold on to everything they replaced
+            if i % 1024 == 1023 {
+                guard = pin();
+            }
+        }
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()?;
+
+        assert!(tenants.insert(dst_name.into(), to.clone()).is_none());
+        drop(cc);
+        drop(tenants);
+        self.context.evict_over_limit(None);
+
+        Ok(to)
+    }
+
+    /// Flushes all dirty data, shuts down the background
+    /// flusher thread, and consumes this `Db` handle.
+    /// Unlike dropping a `Db`, which makes a best-effort
+    /// attempt at flushing and logs any failures, this
+    /// returns the first error encountered while flushing.
+    ///
+    /// The storage files and their lock are released once
+    /// the last handle to the underlying database is gone.
+    /// If no clones of this `Db` and no `Tree`s opened from
+    /// it remain, that happens before this method returns,
+    /// so the same path may be opened again immediately
+    /// afterwards. Handles that outlive this call are still
+    /// flushed in the background, until the last of them is
+    /// dropped.
+    ///
+    /// When the storage files are released after a call to
+    /// `close`, the snapshot of the page table is brought up
+    /// to date with the end of the log, which marks the
+    /// database as cleanly closed. The next `open` then only
+    /// reads the snapshot, rather than also scanning all of
+    /// the log that was written since the previous `open`,
+    /// which can take seconds for large databases. This moves
+    /// that work from the next `open` to `close`. Without
+    /// this, such as after a crash or if the `Db` was just
+    /// dropped, or if more was written after it, the log is
+    /// read from wherever the latest snapshot ends.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("close_db");
+    /// let db = sled::open("close_db")?;
+    /// db.insert(b"k", b"v")?;
+    /// db.close()?;
+    ///
+    /// let db = sled::open("close_db")?;
+    /// assert_eq!(db.get(b"k")?, Some(sled::IVec::from(b"v")));
+    /// # drop(db);
+    /// # let _ = std::fs::remove_dir_all("close_db");
+    /// # Ok(()) }
+    /// ```
+    pub fn close(self) -> Result<()> {
+        trace!("closing Db");
+
+        for db in self.separated.values() {
+            db.clone().close()?;
+        }
+
+        // the flusher is shared with the other handles, and
+        // is stopped when the last of them drops the `Context`
+        while self.context.pagecache.flush()? > 0 {}
+        self.context.fsync_dir()?;
+        self.context.pagecache.log.snapshot_on_drop.store(true, Release);
+
+        self.context.global_error()
+    }
+
+    /// Returns the names of all trees that currently exist
+    /// in this `Db`, in sorted order, including the default
+    /// tree, whose name is `__sled__default`. The names are
+    /// read from the metadata that maps tree names to their
+    /// roots, so this is cheap and does not read any data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// db.open_tree(b"b")?;
+    /// db.open_tree(b"a")?;
+    ///
+    /// assert_eq!(
+    ///     db.tree_names()?,
+    ///     vec![
+    ///         sled::IVec::from(b"__sled__default"),
+    ///         sled::IVec::from(b"a"),
+    ///         sled::IVec::from(b"b"),
+    ///     ]
+    /// );
+    ///
+    /// db.drop_tree(b"a")?;
+    /// assert_eq!(db.tree_names()?.len(), 2);
+    /// # Ok(()) }
+    /// ```
+    pub fn tree_names(&self) -> Result<Vec<IVec>> {
+        let guard = pin();
+        let meta = self.context.pagecache.get_meta(&guard)?;
+        let mut names: Vec<IVec> = meta
+            .inner
+            .keys()
+            .filter(|name| {
+                self.default.dedup.is_none() || *name != DEDUP_TREE_ID
+            })
+            .cloned()
+            .collect();
+
+        for (name, db) in self.separated.iter() {
+            if db.tree_names()?.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// The store of shared values is only accessed through
+    /// the trees whose values it holds.
+    fn check_not_dedup_store(&self, name: &[u8]) -> Result<()> {
+        if self.default.dedup.is_some() && name == DEDUP_TREE_ID {
+            return Err(Error::Unsupported(
+                "cannot open or remove the store of deduplicated values"
+                    .to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the pages of every tree, including the store of
+    /// shared values, for `Config::recovery_policy`.
+    fn quarantine(&self, dedup: Option<&Dedup>) -> Result<Vec<LostRange>> {
+        let excise =
+            self.context.recovery_policy == RecoveryPolicy::BestEffort;
+
+        let mut trees: Vec<Tree> =
+            self.tenants.read().values().cloned().collect();
+        trees.extend(dedup.map(|shared| shared.store.clone()));
+        trees.sort_by(|a, b| a.tree_id.cmp(&b.tree_id));
+
+        let mut lost = vec![];
+        for tree in &trees {
+            lost.extend(quarantine::quarantine(tree, excise)?);
+        }
+        Ok(lost)
+    }
+
+    /// Atomically moves every item in `range` from the tree
+    /// `from` to the tree `to`, replacing any values that
+    /// `to` already has for those keys, and returns the
+    /// number of items moved. Both trees must belong to
+    /// this `Db`. After a crash, every moved item is either
+    /// still in `from` or already in `to`, never in both
+    /// or neither.
+    ///
+    /// This does not stream: the whole range is read into
+    /// memory first, and its writes are recovered as a
+    /// single batch, like `Tree::apply_batch`. This takes
+    /// memory proportional to the size of the range, and
+    /// blocks transactions and other atomic multi-key
+    /// writes while it runs, so very large ranges are best
+    /// moved in smaller pieces.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let hot = db.open_tree(b"hot")?;
+    /// let cold = db.open_tree(b"cold")?;
+    ///
+    /// for i in 0..10_u8 {
+    ///     hot.insert(&[i], &[i])?;
+    /// }
+    ///
+    /// let start: &[u8] = &[0];
+    /// let end: &[u8] = &[5];
+    /// assert_eq!(db.move_range(&hot, &cold, start..end)?, 5);
+    /// assert_eq!(hot.len(), 5);
+    /// assert_eq!(cold.len(), 5);
+    /// assert_eq!(cold.get(&[4])?, Some(sled::IVec::from(&[4])));
+    /// # Ok(()) }
+    /// ```
+    pub fn move_range<K, R>(
+        &self,
+        from: &Tree,
+        to: &Tree,
+        range: R,
+    ) -> Result<usize>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let ours = |tree: &Tree| {
+            std::ptr::eq(&*tree.context.pagecache, &*self.context.pagecache)
+        };
+        if !ours(from) || !ours(to) {
+            return Err(Error::Unsupported(
+                "move_range requires both trees to belong to this Db"
+                    .to_owned(),
+            ));
+        }
+        if from.tree_id == to.tree_id {
+            return Err(Error::Unsupported(
+                "move_range requires two different trees".to_owned(),
+            ));
+        }
+
+        self.context.check_free_space()?;
+        let cc = concurrency_control::write();
+        let mut guard = pin();
+
+        // the write lock already keeps other atomic writes
+        // out, so the iterator does not need to take it too
+        let items = from.range(range).relaxed().collect::<Result<Vec<_>>>()?;
+
+        let peg = self.context.pin_log(&guard)?;
+        trace!(
+            "moving {} items from tree {:?} to tree {:?}",
+            items.len(),
+            from.tree_id,
+            to.tree_id
+        );
+        for (k, v) in &items {
+            while to.insert_inner(k, Some(v.clone()), &mut guard)?.is_err() {}
+            while from.insert_inner(k, None, &mut guard)?.is_err() {}
+        }
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()?;
+        drop(cc);
+        self.context.evict_over_limit(None);
+
+        Ok(items.len())
     }
 
     /// Returns `true` if the database was
@@ -228,6 +966,158 @@ impl Db {
         self.context.was_recovered()
     }
 
+    /// Returns percentiles of the latency of `get`, `insert`
+    /// and `flush` on this `Db` and its trees since it was
+    /// opened. Latencies are only measured if
+    /// `Config::collect_latency_histograms` is set, and
+    /// every count is zero otherwise.
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.context
+            .latency
+            .as_ref()
+            .map_or_else(LatencyStats::default, |latency| latency.stats())
+    }
+
+    /// Returns the ranges of keys whose pages failed their
+    /// checksum when this `Db` was opened, sorted by tree.
+    /// Pages are only checked under
+    /// `RecoveryPolicy::BestEffort` and
+    /// `RecoveryPolicy::ReadOnlyAfterCorruption`, so this is
+    /// always empty with the default `RecoveryPolicy::Strict`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, RecoveryPolicy};
+    ///
+    /// let db = Config::new()
+    ///     .temporary(true)
+    ///     .recovery_policy(RecoveryPolicy::BestEffort)
+    ///     .open()?;
+    ///
+    /// for lost in db.lost_data_report() {
+    ///     eprintln!(
+    ///         "lost keys of tree {:?} from {:?} to {:?}",
+    ///         lost.tree, lost.lo, lost.hi
+    ///     );
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn lost_data_report(&self) -> Vec<LostRange> {
+        self.lost_data.to_vec()
+    }
+
+    /// Rebuilds the database at `path` from the pages that
+    /// pass their checksum, and returns a report of what was
+    /// salvaged and what was lost. The database must not be
+    /// open, in this or another process.
+    ///
+    /// Every key is copied into a new database, which gets a
+    /// fresh index, so damaged index nodes are rebuilt from
+    /// the leaves below them as long as those leaves can
+    /// still be found. The keys held by damaged pages, and
+    /// those below a damaged index node, cannot be salvaged,
+    /// and are listed in `RepairReport::lost_ranges` like in
+    /// `Db::lost_data_report`. Keys whose values can't be
+    /// read, such as those whose shared value was lost with
+    /// `Config::dedup_values`, are listed in
+    /// `RepairReport::lost_keys`. Neither is copied. Like
+    /// `open`, this recovers the log up to its first damaged
+    /// message, and writes after it are lost without being
+    /// reported. Damage to the metadata that locates the
+    /// trees can't be repaired, and returns the same error
+    /// as `open`.
+    ///
+    /// With `RepairOptions::output`, the repaired database
+    /// is written there and the damaged one is left as it
+    /// is. Otherwise, the repaired database is written next
+    /// to it, with `.repair___in_motion` appended to its
+    /// name, and then replaces it, along with its snapshots.
+    /// If this is interrupted before the replacement, the
+    /// damaged database is left as it was. The repaired
+    /// database keeps the parameters that can't change
+    /// across restarts, like `Config::dedup_values`, and
+    /// `Db::generate_id` never returns an ID that the damaged
+    /// one could have returned. Subscribers, merge operators
+    /// and the write generation are not carried over.
+    ///
+    /// This takes time proportional to the size of the
+    /// database, and needs room for a second copy of it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("repair_db");
+    /// let db = sled::open("repair_db")?;
+    /// db.insert(b"a", b"1")?;
+    /// drop(db);
+    ///
+    /// let options = sled::RepairOptions::new();
+    /// let report = sled::Db::repair("repair_db", options)?;
+    /// for lost in &report.lost_ranges {
+    ///     eprintln!(
+    ///         "lost keys of tree {:?} from {:?} to {:?}",
+    ///         lost.tree, lost.lo, lost.hi
+    ///     );
+    /// }
+    /// assert!(report.is_clean());
+    /// assert_eq!(report.keys, 1);
+    ///
+    /// let db = sled::open("repair_db")?;
+    /// assert_eq!(db.get(b"a")?, Some(sled::IVec::from(b"1")));
+    /// # drop(db);
+    /// # let _ = std::fs::remove_dir_all("repair_db");
+    /// # Ok(()) }
+    /// ```
+    pub fn repair<P: AsRef<std::path::Path>>(
+        path: P,
+        options: RepairOptions,
+    ) -> Result<RepairReport> {
+        repair::repair(path.as_ref(), options)
+    }
+
+    /// Removes the temporary databases left behind in
+    /// `base_dir` by processes that exited without dropping
+    /// them, for instance because they crashed or were
+    /// killed. Returns the number of databases removed.
+    ///
+    /// Databases opened with `Config::temporary` mark their
+    /// directory with a file, and each directory directly
+    /// inside `base_dir` that has this mark is removed
+    /// unless the `db` file in it is locked by an open
+    /// database. Without an explicit path, temporary
+    /// databases are created in `/dev/shm` on linux and in
+    /// `std::env::temp_dir()` elsewhere. Temporary databases
+    /// that use `Config::io_backend` are not marked.
+    ///
+    /// Returns `Error::Unsupported` on platforms where sled
+    /// does not lock its files.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let _ = std::fs::remove_dir_all("cleanup_temporaries_doc");
+    /// let db = sled::Config::new()
+    ///     .path("cleanup_temporaries_doc/a")
+    ///     .temporary(true)
+    ///     .open()?;
+    ///
+    /// // a database that is still open is never removed
+    /// let base_dir = "cleanup_temporaries_doc";
+    /// assert_eq!(sled::Db::cleanup_temporaries(base_dir)?, 0);
+    /// # drop(db);
+    /// # let _ = std::fs::remove_dir_all("cleanup_temporaries_doc");
+    /// # Ok(()) }
+    /// ```
+    pub fn cleanup_temporaries<P: AsRef<std::path::Path>>(
+        base_dir: P,
+    ) -> Result<usize> {
+        config::cleanup_temporaries(base_dir.as_ref())
+    }
+
     /// Generate a monotonic ID. Not guaranteed to be
     /// contiguous. Written to disk every `idgen_persist_interval`
     /// operations, followed by a blocking flush. During recovery, we
@@ -240,6 +1130,436 @@ impl Db {
         self.context.generate_id()
     }
 
+    /// Fsyncs the storage directory, along with the
+    /// directory that contains it, so that the directory
+    /// entries of the files that make up this `Db` survive
+    /// a crash. On POSIX filesystems, syncing the contents
+    /// of a newly created file does not make its directory
+    /// entry durable.
+    ///
+    /// This is already done when the `Db` is opened and
+    /// as part of `flush`, so it only needs to be called
+    /// directly to make the directory durable without also
+    /// flushing dirty IO buffers. This does nothing for
+    /// temporary databases.
+    pub fn flush_dir(&self) -> Result<()> {
+        self.context.fsync_dir()
+    }
+
+    /// Returns a number that is bumped after every
+    /// successful write to any `Tree` in this `Db`,
+    /// including writes performed by batches and
+    /// transactions. No-op writes, such as removing
+    /// a key that is not present, do not bump it.
+    /// Reading it is a single atomic load, so it is
+    /// a cheap way to check whether anything has
+    /// changed since the last time it was read.
+    ///
+    /// The generation is not contiguous across
+    /// restarts, but it never goes backwards as long
+    /// as all writes that were observed have been
+    /// made durable, for instance by calling `flush`
+    /// or by dropping the `Db`. Values observed for
+    /// writes that were lost in a crash may be
+    /// handed out again after recovery.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// let before = db.write_generation();
+    /// db.insert(b"a", vec![1])?;
+    /// assert!(db.write_generation() > before);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_generation(&self) -> u64 {
+        self.context.write_generation()
+    }
+
+    /// Registers a callback that is invoked every time
+    /// more of the log has been written and synced to
+    /// disk, whether by `flush`, by the background flusher
+    /// or because an IO buffer filled up. The callback
+    /// receives the number of bytes that became durable
+    /// and a generation that increases with every call.
+    /// Writes that completed before a call to `flush` are
+    /// durable by the time the first notification sent
+    /// after that `flush` returns is delivered.
+    ///
+    /// Callbacks run in registration order on a dedicated
+    /// thread, so they may block or call back into sled
+    /// without stalling the writer that triggered the
+    /// flush, but a slow callback delays the notifications
+    /// after it. A callback that panics is skipped for that
+    /// notification. Callbacks are kept until the database
+    /// is closed, so they should not hold on to a `Db` or
+    /// `Tree`, which would keep it open.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::sync::mpsc::channel;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    ///
+    /// let (tx, rx) = channel();
+    /// let tx = std::sync::Mutex::new(tx);
+    /// db.on_flush(Box::new(move |info| {
+    ///     tx.lock().unwrap().send(info).unwrap();
+    /// }));
+    ///
+    /// db.insert(b"a", b"1")?;
+    /// db.flush()?;
+    ///
+    /// let info = rx.recv()?;
+    /// assert!(info.bytes_flushed > 0);
+    /// # Ok(()) }
+    /// ```
+    pub fn on_flush(&self, callback: Box<dyn Fn(FlushInfo) + Send + Sync>) {
+        self.context.pagecache.log.iobufs.flush_hooks.register(callback)
+    }
+
+    /// Flushes like `Tree::flush`, and reports how many log
+    /// bytes each tree's writes took since the last call, to
+    /// find the trees that drive IO. Trees that were not
+    /// written to are left out. `Tree::flush` and the
+    /// background flusher do not reset these counts, so
+    /// calling this periodically attributes all writes.
+    ///
+    /// Writes held back by `Config::coalesce_writes` are
+    /// only counted in the total, as are the writes of the
+    /// store of `Config::dedup_values`.
+    ///
+    /// The report also counts the syncs of the log, to check
+    /// that writes to many trees are made durable together.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let busy = db.open_tree(b"busy")?;
+    /// let _idle = db.open_tree(b"idle")?;
+    ///
+    /// busy.insert(b"k", vec![0; 1024])?;
+    ///
+    /// let report = db.flush_detailed()?;
+    /// assert_eq!(report.trees.len(), 1);
+    /// assert!(report.trees[&sled::IVec::from(b"busy")] > 1024);
+    ///
+    /// assert_eq!(db.flush_detailed()?, sled::FlushReport::default());
+    /// # Ok(()) }
+    /// ```
+    pub fn flush_detailed(&self) -> Result<FlushReport> {
+        // taken before flushing, so that every counted
+        // write was reserved in the log before the flush
+        let mut trees = BTreeMap::new();
+        // the `Db` writes to the default tree through its own
+        // handle, besides the one in the tenants
+        let default_logged = self.default.logged_bytes.swap(0, SeqCst);
+        for (name, tree) in self.sorted_tenants() {
+            let mut logged = tree.logged_bytes.swap(0, SeqCst);
+            if name == DEFAULT_TREE_ID {
+                logged += default_logged;
+            }
+            if logged > 0 {
+                let _ = trees.insert(name, logged);
+            }
+        }
+
+        let total = u64::try_from(self.flush()?).unwrap();
+        let syncs = self.context.pagecache.log.iobufs.log_syncs.swap(0, SeqCst);
+
+        Ok(FlushReport { trees, total, syncs })
+    }
+
+    /// Flushes like `Tree::flush`, calling `progress` each
+    /// time another IO buffer of the log has been written and
+    /// synced, with the number of bytes flushed so far and
+    /// the number of bytes that were pending when the flush
+    /// started. Writes that happen during the flush are not
+    /// waited for.
+    ///
+    /// If `progress` returns `ControlFlow::Break`, the flush
+    /// stops before writing the next buffer and the progress
+    /// made so far is returned. A cancelled flush does not
+    /// corrupt anything: the buffers it wrote are durable,
+    /// and the rest are written later by the background
+    /// flusher or the next flush, as if this flush had not
+    /// been called. Only a flush that runs to completion
+    /// guarantees that the writes before it survive a crash.
+    /// To cancel from another thread, have `progress` check
+    /// a shared flag.
+    ///
+    /// Like `Tree::flush`, this only flushes the shared log,
+    /// and not the logs of `Config::separate_log_for_tree`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    ///
+    /// use sled::ControlFlow;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// db.insert(b"k", vec![0; 1024])?;
+    ///
+    /// let shutting_down = AtomicBool::new(false);
+    /// let done = db.flush_with_progress(|progress| {
+    ///     println!("flushed {} of {}", progress.flushed, progress.total);
+    ///     if shutting_down.load(Ordering::Relaxed) {
+    ///         ControlFlow::Break
+    ///     } else {
+    ///         ControlFlow::Continue
+    ///     }
+    /// })?;
+    /// assert_eq!(done.flushed, done.total);
+    /// # Ok(()) }
+    /// ```
+    pub fn flush_with_progress<F>(
+        &self,
+        mut progress: F,
+    ) -> Result<FlushProgress>
+    where
+        F: FnMut(FlushProgress) -> ControlFlow,
+    {
+        let _latency = self.context.latency_timer(|l| &l.flush);
+        let report =
+            self.context.pagecache.flush_with_progress(&mut progress)?;
+        if report.flushed == report.total {
+            self.context.fsync_dir()?;
+        }
+        Ok(report)
+    }
+
+    /// Returns the total length of the keys and values of
+    /// all trees, which `Config::max_db_size` limits, or
+    /// `None` if it is not set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .max_db_size(1024)
+    ///     .open()?;
+    ///
+    /// db.insert(b"key", b"value")?;
+    /// assert_eq!(db.items_size(), Some(8));
+    /// # Ok(()) }
+    /// ```
+    pub fn items_size(&self) -> Option<u64> {
+        self.context.eviction.as_ref().map(|eviction| eviction.size())
+    }
+
+    /// Returns the number of bytes that are free for this
+    /// process on the volume that holds the database,
+    /// measured now. This also refreshes the value that
+    /// `Config::min_free_space` is checked against.
+    ///
+    /// Returns `Error::Unsupported` on platforms other
+    /// than linux, macos and windows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let db = config.open()?;
+    ///
+    /// assert!(db.free_space()? > 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn free_space(&self) -> Result<u64> {
+        self.context.free_space.refresh(&self.context)
+    }
+
+    /// Changes how often the background flusher writes
+    /// buffered data to the log and syncs it, like
+    /// `Config::flush_every_ms` does when opening. This
+    /// applies to every handle to this database, and lasts
+    /// until it is changed again or the database is closed.
+    ///
+    /// A running flusher starts waiting for the new interval
+    /// after its next flush, which happens right away. `None`
+    /// stops the flusher and blocks until its thread has
+    /// exited, after which writes only become durable through
+    /// `Db::flush`. Changing it back to `Some` starts the
+    /// flusher again. Writes may continue concurrently, and
+    /// calls to this method are applied one at a time. With
+    /// `Config::test_clock`, this changes how often `Db::step`
+    /// flushes instead, and with `Config::manual_flush` it has
+    /// no effect.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    ///
+    /// // flush eagerly while shutting down
+    /// db.set_flush_every_ms(Some(10));
+    ///
+    /// // rely on explicit flushes only
+    /// db.set_flush_every_ms(None);
+    /// db.insert(b"k", b"v")?;
+    /// db.flush()?;
+    /// # Ok(()) }
+    /// ```
+    pub fn set_flush_every_ms(&self, every_ms: Option<u64>) {
+        let never_if_none = every_ms.unwrap_or(context::NEVER);
+
+        #[cfg(any(
+            windows,
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+        ))]
+        {
+            let mut flusher = self.context.flusher.lock();
+            if every_ms.is_none() {
+                // dropping the flusher blocks until its
+                // thread has written out any remaining
+                // data and been joined.
+                drop(flusher.take());
+            }
+            self.context.flush_every.store(never_if_none, SeqCst);
+            if every_ms.is_some()
+                && self.context.clock.is_none()
+                && !self.context.manual_flush
+            {
+                if let Some(running) = &*flusher {
+                    running.wake();
+                } else {
+                    *flusher = Some(start_flusher(&self.context));
+                }
+            }
+        }
+
+        #[cfg(not(any(
+            windows,
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+        )))]
+        self.context.flush_every.store(never_if_none, SeqCst);
+    }
+
+    /// When `Config::test_clock` is set, performs the work
+    /// that the background flusher would otherwise do, if
+    /// at least `flush_every_ms` has passed on the clock
+    /// since the last time that `step` did it, or if it has
+    /// never done it. This writes buffered data to the log,
+    /// cleans fragmented segments, and syncs the log. It
+    /// also takes the snapshot that is due under
+    /// `Config::snapshot_after_ops` or
+    /// `Config::snapshot_interval`, if any, on every call.
+    ///
+    /// Returns `true` if the work was due and was done. If
+    /// `flush_every_ms` is `None` this only takes snapshots
+    /// and returns `false`. Returns `Error::Unsupported` if
+    /// `Config::test_clock` is not set. See
+    /// `Config::test_clock` for an example.
+    pub fn step(&self) -> Result<bool> {
+        let now = if let Some(now) = self.context.test_now() {
+            now
+        } else {
+            return Err(Error::Unsupported(
+                "Db::step requires Config::test_clock to be set".to_owned(),
+            ));
+        };
+
+        self.context.snapshots.step(&self.context, &self.tenants)?;
+
+        let flush_every = if let Some(ms) =
+            self.context.current_flush_every_ms()
+        {
+            std::time::Duration::from_millis(ms)
+        } else {
+            return Ok(false);
+        };
+
+        {
+            let mut last_step = self.context.last_step.lock();
+            if let Some(last) = *last_step {
+                if now < last + flush_every {
+                    return Ok(false);
+                }
+            }
+            *last_step = Some(now);
+        }
+
+        let pagecache = &self.context.pagecache;
+
+        {
+            let _cc = concurrency_control::read();
+            pagecache.log_coalesced()?;
+            let _ = pagecache.log.roll_iobuf()?;
+        }
+
+        // unlike the background flusher, there is no sleep
+        // to budget, so clean until there is nothing left
+        while pagecache.attempt_gc()? {}
+
+        pagecache.config.sync_log()?;
+
+        Ok(true)
+    }
+
+    /// Performs one unit of the work that the background
+    /// flusher would otherwise do, for use with
+    /// `Config::manual_flush`. If there are buffered writes,
+    /// they are written to the log. Otherwise, one page is
+    /// moved out of a fragmented segment so that the
+    /// segment can be reused, and the page is written by
+    /// the next call.
+    ///
+    /// Returns the number of bytes that were written to or
+    /// moved in the log, which is 0 once there is nothing
+    /// left to do. The writes are durable once they
+    /// complete, which `flush` waits for and this does not.
+    /// See `Config::manual_flush` for an example.
+    pub fn tick(&self) -> Result<usize> {
+        let pagecache = &self.context.pagecache;
+
+        let written = {
+            let _cc = concurrency_control::read();
+            pagecache.log_coalesced()?;
+            pagecache.log.roll_iobuf()?
+        };
+        if written > 0 {
+            return Ok(written);
+        }
+
+        // a page that already left the segment is skipped
+        // without being rewritten, so keep going until one
+        // is actually moved
+        loop {
+            let before = pagecache.log.max_reserved_lsn();
+            if !pagecache.attempt_gc()? {
+                return Ok(0);
+            }
+            let moved = pagecache.log.max_reserved_lsn() - before;
+            if moved > 0 {
+                return Ok(usize::try_from(moved).unwrap());
+            }
+        }
+    }
+
     /// A database export method for all collections in the `Db`,
     /// for use in sled version upgrades. Can be used in combination
     /// with the `import` method below on a database running a later
@@ -284,11 +1604,21 @@ impl Db {
         &self,
     ) -> Vec<(CollectionType, CollectionName, impl Iterator<Item = Vec<Vec<u8>>>)>
     {
-        let tenants = self.tenants.read();
+        let mut trees: Vec<(IVec, Tree)> = self
+            .tenants
+            .read()
+            .iter()
+            .map(|(name, tree)| (name.clone(), tree.clone()))
+            .collect();
+        for (name, db) in self.separated.iter() {
+            if let Some(tree) = db.tenants.read().get(name) {
+                trees.push((name.clone(), tree.clone()));
+            }
+        }
 
         let mut ret = vec![];
 
-        for (name, tree) in tenants.iter() {
+        for (name, tree) in trees {
             ret.push((
                 b"tree".to_vec(),
                 name.to_vec(),
@@ -374,6 +1704,158 @@ impl Db {
         }
     }
 
+    /// Returns the keys and values of every tree in a
+    /// single byte buffer, from which a read-only copy can
+    /// be opened with `Db::open_from_bytes`. The image is a
+    /// consistent snapshot of all trees, and only depends on
+    /// their items, so it can be opened with any
+    /// configuration.
+    ///
+    /// This is O(N). Writes go on while the trees are read,
+    /// but if they keep racing with it, they are blocked for
+    /// one last scan.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let (image, _) = snapshots::write_image(
+            &self.context.write_generation,
+            &self.tenants,
+            || Ok(vec![]),
+        )?;
+        Ok(image)
+    }
+
+    /// Writes a consistent snapshot of all trees to the
+    /// `snapshots` directory of the database, and returns
+    /// its id, which can be passed to `Db::restore_snapshot`
+    /// to roll the trees back to their current items, even
+    /// after the database is reopened. Snapshots hold the
+    /// same image as `Db::to_bytes`, and the oldest ones
+    /// beyond `Config::snapshot_retention` are removed.
+    ///
+    /// Like `Db::to_bytes`, this only blocks writes if they
+    /// keep racing with the read of the trees. See
+    /// `Config::snapshot_after_ops` for taking snapshots
+    /// automatically, and for the space they take.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// db.insert(b"k", b"good")?;
+    ///
+    /// let id = db.snapshot_now()?;
+    /// db.insert(b"k", b"bad")?;
+    /// db.insert(b"other", b"bad")?;
+    ///
+    /// db.restore_snapshot(id)?;
+    /// assert_eq!(db.get(b"k")?, Some(sled::IVec::from(b"good")));
+    /// assert_eq!(db.get(b"other")?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn snapshot_now(&self) -> Result<SnapshotId> {
+        self.context.snapshots.take(
+            &self.context,
+            &self.context.write_generation,
+            &self.tenants,
+        )
+    }
+
+    /// Returns the ids of the snapshots that are kept, from
+    /// oldest to newest.
+    pub fn snapshots(&self) -> Result<Vec<SnapshotId>> {
+        snapshots::list(&self.context)
+    }
+
+    /// Rolls all trees back to their items at the time of the
+    /// snapshot `id`. Items that were written since are
+    /// removed or set back, trees that were dropped since are
+    /// opened again, and trees that were opened since are
+    /// left empty rather than dropped. The snapshot itself is
+    /// kept.
+    ///
+    /// The restore is atomic: other writes are blocked while
+    /// it runs, and after a crash either all or none of it is
+    /// recovered. Only the differences are written to the log,
+    /// as if they were written one by one. Subscribers of the
+    /// default tree see them as inserts and removes, but
+    /// merge operators are not called.
+    ///
+    /// Returns an `Error::Io` with `ErrorKind::NotFound` if
+    /// the snapshot does not exist, for instance because it
+    /// was removed after `Config::snapshot_retention` newer
+    /// snapshots were taken.
+    pub fn restore_snapshot(&self, id: SnapshotId) -> Result<()> {
+        self.context.check_free_space()?;
+        let data = snapshots::read(&self.context, id)?;
+        let images = image::decode(&data)?;
+
+        let mut tenants = self.tenants.write();
+
+        let cc = concurrency_control::write();
+        let mut guard = pin();
+        let peg = self.context.pin_log(&guard)?;
+
+        let mut snapshot: FastMap8<IVec, Vec<(IVec, IVec)>> =
+            images.into_iter().collect();
+        for name in snapshot.keys() {
+            if !tenants.contains_key(name) {
+                let tree = meta::open_tree(
+                    &self.context,
+                    name.to_vec(),
+                    self.default.dedup.clone(),
+                    &guard,
+                )?;
+                assert!(tenants.insert(name.clone(), tree).is_none());
+            }
+        }
+
+        for (name, tenant) in tenants.iter() {
+            // writes to the default tree go through the handle
+            // that its subscribers are registered with
+            let tree = if &**name == DEFAULT_TREE_ID {
+                &self.default
+            } else {
+                tenant
+            };
+            let items = snapshot.remove(name).unwrap_or_default();
+
+            let mut stale = vec![];
+            for key_res in tree.iter().relaxed().keys() {
+                let key = key_res?;
+                if items.binary_search_by(|(k, _)| k.cmp(&key)).is_err() {
+                    stale.push(key);
+                }
+            }
+
+            let writes = stale
+                .into_iter()
+                .map(|key| (key, None))
+                .chain(items.into_iter().map(|(k, v)| (k, Some(v))));
+            for (i, (key, value)) in writes.enumerate() {
+                while tree
+                    .insert_inner(&key, value.clone(), &mut guard)?
+                    .is_err()
+                {}
+
+                // let the epoch advance so that large restores
+                // do not hold on to everything they replaced
+                if i % 1024 == 1023 {
+                    guard = pin();
+                }
+            }
+        }
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()?;
+        drop(cc);
+        drop(tenants);
+        self.context.evict_over_limit(None);
+
+        Ok(())
+    }
+
     /// Returns the CRC32 of all keys and values
     /// in this Db.
     ///
@@ -396,7 +1878,7 @@ impl Db {
             hasher.update(name);
 
             let mut iter = tree.iter();
-            while let Some(kv_res) = iter.next_inner() {
+            while let Some(kv_res) = iter.next_resolved() {
                 let (k, v) = kv_res?;
                 hasher.update(&k);
                 hasher.update(&v);
@@ -406,10 +1888,237 @@ impl Db {
         Ok(hasher.finalize())
     }
 
+    /// Reads every key and value of every tree and returns
+    /// histograms of their sizes, along with the logical
+    /// bytes of each tree by key prefix of `prefix_depth`
+    /// bytes, to help choose how to shard or compress the
+    /// data. A depth of 0 gives a single total per tree.
+    ///
+    /// This is O(N), and the report holds an entry for every
+    /// distinct prefix, so for large databases,
+    /// `space_report_sampled` is much faster. Unlike
+    /// `checksum`, it does not lock the trees, so items
+    /// written during the scan may or may not be counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// db.insert(b"user/1", vec![0; 100])?;
+    /// db.insert(b"user/2", vec![0; 200])?;
+    /// db.insert(b"post/1", vec![0; 1000])?;
+    ///
+    /// let report = db.space_report(5)?;
+    /// assert_eq!(report.keys.count, 3);
+    /// assert_eq!(report.values.total_bytes, 1300);
+    ///
+    /// let bytes: Vec<_> =
+    ///     report.prefixes.iter().map(|p| (&*p.prefix, p.bytes)).collect();
+    /// assert_eq!(bytes, vec![(&b"post/"[..], 1006), (&b"user/"[..], 312)]);
+    /// # Ok(()) }
+    /// ```
+    pub fn space_report(&self, prefix_depth: usize) -> Result<SpaceReport> {
+        let mut builder = SpaceReportBuilder::new(prefix_depth, false);
+
+        for (name, tree) in self.sorted_tenants() {
+            for kv_res in &tree {
+                let (k, v) = kv_res?;
+                let value_len = u64::try_from(v.len()).unwrap();
+                builder.record(&name, &k, value_len, 1.);
+            }
+        }
+
+        Ok(builder.finish())
+    }
+
+    /// Like `space_report`, but estimates the report from
+    /// `samples` leaves of each tree instead of reading
+    /// every item, so it takes about the same time however
+    /// large the trees are.
+    ///
+    /// Each sample descends from the root of a tree to a
+    /// leaf, choosing a child at random at every level, and
+    /// the items of the leaf are weighed by how many leaves
+    /// such a path stands for. The estimated counts and byte
+    /// totals are unbiased, and the 95% confidence interval
+    /// of the total bytes is given by
+    /// `SpaceReport::relative_error`, which shrinks with the
+    /// square root of `samples`: a few hundred samples per
+    /// tree usually bring it to about 10%, and four times as
+    /// many samples halve it. The
+    /// maximum sizes, and the prefixes that are listed, only
+    /// cover the sampled leaves, so rare prefixes may be
+    /// missing. Trees that are being split or merged while
+    /// they are sampled may be slightly misestimated.
+    ///
+    /// Returns an `Unsupported` error if `samples` is 0.
+    pub fn space_report_sampled(
+        &self,
+        prefix_depth: usize,
+        samples: usize,
+    ) -> Result<SpaceReport> {
+        if samples == 0 {
+            return Err(Error::Unsupported(
+                "space_report_sampled needs at least one sample".to_owned(),
+            ));
+        }
+
+        let mut builder = SpaceReportBuilder::new(prefix_depth, true);
+        let mut rng = SampleRng::new();
+
+        for (name, tree) in self.sorted_tenants() {
+            let mut leaves = Vec::with_capacity(samples);
+            for _ in 0..samples {
+                leaves.push(tree.sample_leaf(|n| rng.below(n))?);
+            }
+            builder.record_samples(&name, &leaves);
+        }
+
+        Ok(builder.finish())
+    }
+
+    /// Walks every node of every tree and returns their
+    /// heights, the number of nodes at each level, their
+    /// average fan-out and how many keys their leaves hold,
+    /// along with how many bytes of each segment of the log
+    /// hold current versions of pages. This helps to diagnose
+    /// trees that are deeper or sparser than expected, and
+    /// logs that are fragmented.
+    ///
+    /// This is O(N) in the number of nodes, and reads any
+    /// that are not cached from disk. It does not lock the
+    /// trees, so nodes that are split or merged during the
+    /// walk may be missed. Only available with the `debug`
+    /// feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// db.insert(b"k", b"v")?;
+    ///
+    /// let report = db.structure_report()?;
+    /// let default = report
+    ///     .trees
+    ///     .iter()
+    ///     .find(|tree| &*tree.name == b"__sled__default")
+    ///     .unwrap();
+    /// assert_eq!(default.leaf_keys, 1);
+    /// assert!(report.segments.iter().all(|s| s.live_ratio() <= 1.));
+    /// # Ok(()) }
+    /// ```
+    #[cfg(feature = "debug")]
+    pub fn structure_report(&self) -> Result<StructureReport> {
+        let mut trees = vec![];
+        for (name, tree) in self.sorted_tenants() {
+            trees.push(tree.structure(name)?);
+        }
+
+        Ok(StructureReport {
+            trees,
+            segments: self.context.pagecache.segment_structure()?,
+        })
+    }
+
+    /// Returns handles to all trees, sorted by name, without
+    /// holding on to the lock of the tenants.
+    fn sorted_tenants(&self) -> BTreeMap<IVec, Tree> {
+        self.tenants
+            .read()
+            .iter()
+            .map(|(name, tree)| (name.clone(), tree.clone()))
+            .collect()
+    }
+
+    /// Returns `true` if none of the trees of this `Db`,
+    /// including the default tree, contain any keys. Trees
+    /// that exist but have no keys are empty, and a `Db`
+    /// that has such trees is still empty. Use
+    /// `Tree::is_empty` through `Deref`, as in
+    /// `Tree::is_empty(&db)`, to check the default tree
+    /// alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let tree = db.open_tree(b"tree")?;
+    /// assert!(db.is_empty()?);
+    ///
+    /// tree.insert(b"a", b"1")?;
+    /// assert!(!db.is_empty()?);
+    /// assert!(sled::Tree::is_empty(&db)?);
+    /// # Ok(()) }
+    /// ```
+    pub fn is_empty(&self) -> Result<bool> {
+        if !self.default.is_empty()? {
+            return Ok(false);
+        }
+        for tree in self.tenants.read().values() {
+            if !tree.is_empty()? {
+                return Ok(false);
+            }
+        }
+        for db in self.separated.values() {
+            if !db.is_empty()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     /// Returns the on-disk size of the storage files
     /// for this database.
     pub fn size_on_disk(&self) -> Result<u64> {
-        self.context.pagecache.size_on_disk()
+        let mut size = self.context.pagecache.size_on_disk()?;
+        for db in self.separated.values() {
+            size += db.size_on_disk()?;
+        }
+        Ok(size)
+    }
+
+    /// Returns the bytes of cached pages that count towards
+    /// `Config::cache_capacity_bytes`, which never exceeds
+    /// it. Accesses are counted in batches, so pages that
+    /// were read or written most recently may not be
+    /// counted yet.
+    pub fn cache_resident_bytes(&self) -> u64 {
+        self.context.pagecache.cache_resident_bytes()
+    }
+
+    /// Detects the memory limit of the process again, and
+    /// resizes the cache accordingly, returning its new
+    /// capacity. This lets long-running processes follow
+    /// changes to the memory limit of their container. See
+    /// `Config::cache_capacity_fraction` for how the capacity
+    /// is computed and how the limit is detected. Without a
+    /// fraction, the capacity is `Config::cache_capacity_bytes`,
+    /// lowered to the limit if that is smaller.
+    ///
+    /// Pages that no longer fit in a smaller cache are
+    /// evicted before this returns, which may write pages
+    /// that only exist in memory to the log.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new()
+    ///     .temporary(true)
+    ///     .cache_capacity_fraction(0.5)
+    ///     .open()?;
+    ///
+    /// let capacity = db.retune_cache()?;
+    /// assert!(capacity >= 256);
+    /// # Ok(()) }
+    /// ```
+    pub fn retune_cache(&self) -> Result<u64> {
+        let capacity = self.context.effective_cache_capacity();
+        self.context.pagecache.set_cache_capacity(capacity)?;
+        Ok(capacity)
     }
 
     /// Traverses all files and calculates their total physical
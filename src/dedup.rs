@@ -0,0 +1,247 @@
+//! The store of shared values for `Config::dedup_values`.
+//!
+//! When values are deduplicated, each key is stored with a
+//! tagged form of its value: either the value itself,
+//! prefixed with `INLINE`, or a reference to a shared
+//! copy, which is `REFERENCE` followed by the copy's slot.
+//! A slot is the 64-bit FNV-1a hash of the value followed
+//! by an id, and the store holds two items for it:
+//!
+//! * `slot ++ [CONTENT]`: the bytes of the value
+//! * `slot ++ [REFCOUNT]`: the number of keys referring to
+//!   it, as a big-endian `u64`
+//!
+//! Values with the same hash are compared byte for byte,
+//! and a different value whose hash collides gets its own
+//! id. Ids come from a counter in the store and are never
+//! reused, so a stale reference can fail to resolve, but
+//! never resolves to a different value.
+//!
+//! The store is only changed while holding the write lock
+//! of the concurrency control, and reads that hold the read
+//! lock always find the values they refer to.
+
+use crate::*;
+
+/// The name of the tree that holds shared values.
+pub(crate) const DEDUP_TREE_ID: &[u8] = b"__sled__dedup";
+
+/// Values shorter than this are stored inline, because a
+/// reference would not be much shorter.
+const MIN_SHARED_LEN: usize = 64;
+
+const INLINE: u8 = 0;
+const REFERENCE: u8 = 1;
+
+const CONTENT: u8 = 0;
+const REFCOUNT: u8 = 1;
+
+const SLOT_LEN: usize = 16;
+
+/// Shorter than every other key in the store, so it never
+/// matches the hash prefix of a slot.
+const NEXT_ID_KEY: &[u8] = b"next_id";
+
+#[derive(Debug, Clone)]
+pub(crate) struct Dedup {
+    pub(crate) store: Tree,
+}
+
+impl Dedup {
+    pub(crate) fn new(store: Tree) -> Dedup {
+        Dedup { store }
+    }
+
+    /// Returns the form of `value` to store with its key,
+    /// taking a reference to its shared copy if it is long
+    /// enough to be shared.
+    pub(crate) fn intern(&self, value: &IVec) -> Result<IVec> {
+        if value.len() < MIN_SHARED_LEN {
+            let mut inline = Vec::with_capacity(1 + value.len());
+            inline.push(INLINE);
+            inline.extend_from_slice(value);
+            return Ok(IVec::from(inline));
+        }
+
+        let hash = fnv1a(value).to_be_bytes();
+        let guard = &mut pin();
+
+        let mut slots = self.store.scan_prefix(hash);
+        while let Some(item) = slots.next_inner() {
+            let (key, content) = item?;
+            if key.len() != SLOT_LEN + 1 || key[SLOT_LEN] != CONTENT {
+                continue;
+            }
+            // the hash only narrows the search down,
+            // the bytes decide
+            if content == *value {
+                let slot = &key[..SLOT_LEN];
+                let count = self.refcount(slot, guard)? + 1;
+                self.set(&item_key(slot, REFCOUNT), Some(count), guard)?;
+                return Ok(reference(slot));
+            }
+        }
+
+        let id = self.next_id(guard)?;
+        let mut slot = hash.to_vec();
+        slot.extend_from_slice(&id.to_be_bytes());
+
+        let key = item_key(&slot, CONTENT);
+        let content = Some(value.clone());
+        while self.store.insert_inner(&key, content.clone(), guard)?.is_err() {}
+        self.set(&item_key(&slot, REFCOUNT), Some(1), guard)?;
+
+        Ok(reference(&slot))
+    }
+
+    /// Drops the reference that a key held through its
+    /// stored value, removing the shared copy when this was
+    /// the last reference to it.
+    pub(crate) fn release(&self, stored: &IVec) -> Result<()> {
+        let slot = match stored.split_first() {
+            Some((&INLINE, _)) => return Ok(()),
+            Some((&REFERENCE, slot)) if slot.len() == SLOT_LEN => slot,
+            _ => return Err(Error::corruption(None)),
+        };
+
+        let guard = &mut pin();
+        let count = self.refcount(slot, guard)? - 1;
+        if count > 0 {
+            return self.set(&item_key(slot, REFCOUNT), Some(count), guard);
+        }
+
+        self.set(&item_key(slot, REFCOUNT), None, guard)?;
+        let key = item_key(slot, CONTENT);
+        while self.store.insert_inner(&key, None, guard)?.is_err() {}
+        Ok(())
+    }
+
+    /// Returns the value that `stored` represents, or `None`
+    /// if it refers to a shared copy that has been removed
+    /// since it was read.
+    pub(crate) fn load(&self, stored: &IVec) -> Result<Option<IVec>> {
+        match stored.split_first() {
+            Some((&INLINE, _)) => {
+                Ok(Some(stored.subslice(1, stored.len() - 1)))
+            }
+            Some((&REFERENCE, slot)) if slot.len() == SLOT_LEN => {
+                self.get(&item_key(slot, CONTENT), &mut pin())
+            }
+            _ => Err(Error::corruption(None)),
+        }
+    }
+
+    fn refcount(&self, slot: &[u8], guard: &mut Guard) -> Result<u64> {
+        match self.get(&item_key(slot, REFCOUNT), guard)? {
+            Some(raw) => read_u64(&raw),
+            None => Err(Error::ReportableBug(
+                "a deduplicated value is missing its reference count"
+                    .to_owned(),
+            )),
+        }
+    }
+
+    fn next_id(&self, guard: &mut Guard) -> Result<u64> {
+        let id = match self.get(NEXT_ID_KEY, guard)? {
+            Some(raw) => read_u64(&raw)?,
+            None => 0,
+        };
+        self.set(NEXT_ID_KEY, Some(id + 1), guard)?;
+        Ok(id)
+    }
+
+    fn get(&self, key: &[u8], guard: &mut Guard) -> Result<Option<IVec>> {
+        loop {
+            if let Ok(value) = self.store.get_inner(key, guard)? {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn set(
+        &self,
+        key: &[u8],
+        count: Option<u64>,
+        guard: &mut Guard,
+    ) -> Result<()> {
+        let value = count.map(|n| IVec::from(&n.to_be_bytes()[..]));
+        loop {
+            if self.store.insert_inner(key, value.clone(), guard)?.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn item_key(slot: &[u8], kind: u8) -> Vec<u8> {
+    let mut key = Vec::with_capacity(slot.len() + 1);
+    key.extend_from_slice(slot);
+    key.push(kind);
+    key
+}
+
+fn reference(slot: &[u8]) -> IVec {
+    let mut stored = Vec::with_capacity(1 + slot.len());
+    stored.push(REFERENCE);
+    stored.extend_from_slice(slot);
+    IVec::from(stored)
+}
+
+fn read_u64(raw: &[u8]) -> Result<u64> {
+    match <[u8; 8]>::try_from(raw) {
+        Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+        Err(_) => Err(Error::corruption(None)),
+    }
+}
+
+/// 64-bit FNV-1a. Stored slots are keyed by this, so it
+/// must never change.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    let mut hash = FNV_OFFSET;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+#[test]
+fn shared_values_are_counted_and_compared() {
+    let db = Config::new().temporary(true).dedup_values(true).open().unwrap();
+    let dedup = db.dedup.clone().unwrap();
+
+    let value = IVec::from(vec![1; 100]);
+    db.insert(b"a", value.clone()).unwrap();
+    db.insert(b"b", value.clone()).unwrap();
+    db.insert(b"c", vec![1; 10]).unwrap();
+
+    // one content, one count, and the id counter
+    assert_eq!(dedup.store.len(), 3);
+    db.remove(b"a").unwrap();
+    assert_eq!(dedup.store.len(), 3);
+    db.insert(b"b", vec![2; 100]).unwrap();
+    assert_eq!(dedup.store.len(), 3);
+    db.remove(b"b").unwrap();
+    assert_eq!(dedup.store.len(), 1);
+
+    // a different value under the same hash, as if the
+    // hashes collided, is never mistaken for this one
+    let stored = dedup.intern(&value).unwrap();
+    let impostor = IVec::from(vec![3; 100]);
+    let key = item_key(&stored[1..], CONTENT);
+    while dedup
+        .store
+        .insert_inner(&key, Some(impostor.clone()), &mut pin())
+        .unwrap()
+        .is_err()
+    {}
+
+    let other = dedup.intern(&value).unwrap();
+    assert_ne!(stored, other);
+    assert_eq!(stored[1..9], other[1..9]);
+    assert_eq!(dedup.load(&other).unwrap(), Some(value));
+    assert_eq!(dedup.load(&stored).unwrap(), Some(impostor));
+}
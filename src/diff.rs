@@ -0,0 +1,120 @@
+use std::cmp::Ordering;
+
+use crate::*;
+
+/// A difference between two trees, as returned by
+/// `Tree::diff`. Keys are compared in the order that
+/// `Tree::iter` returns them in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diff {
+    /// The key and value are only present in the left tree.
+    OnlyLeft(IVec, IVec),
+    /// The key and value are only present in the right tree.
+    OnlyRight(IVec, IVec),
+    /// The key is present in both trees, with the left
+    /// value followed by the right value.
+    Changed(IVec, IVec, IVec),
+}
+
+impl Diff {
+    /// Returns the key that this difference is about.
+    pub fn key(&self) -> &IVec {
+        match self {
+            Diff::OnlyLeft(k, _)
+            | Diff::OnlyRight(k, _)
+            | Diff::Changed(k, _, _) => k,
+        }
+    }
+}
+
+/// An iterator over the differences between two trees,
+/// returned by `Tree::diff`, `Tree::diff_prefix` and
+/// `Tree::diff_range`.
+pub struct DiffIter {
+    left: Side,
+    right: Side,
+}
+
+struct Side {
+    iter: Iter,
+    head: Option<(IVec, IVec)>,
+    done: bool,
+}
+
+impl Side {
+    fn new(iter: Iter) -> Side {
+        Side { iter, head: None, done: false }
+    }
+
+    fn fill(&mut self) -> Result<()> {
+        if self.head.is_none() && !self.done {
+            match self.iter.next() {
+                Some(item) => self.head = Some(item?),
+                None => self.done = true,
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&mut self) -> (IVec, IVec) {
+        self.head.take().expect("head was filled before being taken")
+    }
+}
+
+impl DiffIter {
+    pub(crate) fn new(left: Iter, right: Iter) -> DiffIter {
+        DiffIter { left: Side::new(left), right: Side::new(right) }
+    }
+}
+
+impl Iterator for DiffIter {
+    type Item = Result<Diff>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Err(e) = self.left.fill() {
+                return Some(Err(e));
+            }
+            if let Err(e) = self.right.fill() {
+                return Some(Err(e));
+            }
+
+            let ordering = match (&self.left.head, &self.right.head) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((lk, _)), Some((rk, _))) => lk.cmp(rk),
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    let (k, v) = self.left.take();
+                    return Some(Ok(Diff::OnlyLeft(k, v)));
+                }
+                Ordering::Greater => {
+                    let (k, v) = self.right.take();
+                    return Some(Ok(Diff::OnlyRight(k, v)));
+                }
+                Ordering::Equal => {
+                    let (k, left) = self.left.take();
+                    let (_, right) = self.right.take();
+                    if left != right {
+                        return Some(Ok(Diff::Changed(k, left, right)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Debug for DiffIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiffIter")
+            .field("left_done", &(self.left.done && self.left.head.is_none()))
+            .field(
+                "right_done",
+                &(self.right.done && self.right.head.is_none()),
+            )
+            .finish()
+    }
+}
@@ -137,6 +137,25 @@ impl DoublyLinkedList {
         }
     }
 
+    /// Removes the item at `ptr`, wherever it is in the list.
+    pub(crate) fn remove(&mut self, ptr: *mut Node) -> PageId {
+        self.len -= 1;
+
+        unsafe {
+            if self.tail == ptr {
+                self.tail = (*ptr).next;
+            }
+
+            if self.head == ptr {
+                self.head = (*ptr).prev;
+            }
+
+            let mut node = Box::from_raw(ptr);
+            node.unwire();
+            node.inner
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn pop_head(&mut self) -> Option<PageId> {
         if self.head.is_null() {
@@ -207,4 +226,15 @@ fn test_dll() {
     dll.push_head(1);
     assert_eq!(dll.len(), 9);
     assert_eq!(dll.into_vec(), vec![1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+    let mut removing = DoublyLinkedList::default();
+    let first = removing.push_head(1);
+    let middle = removing.push_head(2);
+    let last = removing.push_head(3);
+    removing.push_head(4);
+    assert_eq!(removing.remove(middle), 2);
+    assert_eq!(removing.remove(first), 1);
+    removing.promote(last);
+    assert_eq!(removing.len(), 2);
+    assert_eq!(removing.into_vec(), vec![3, 4]);
 }
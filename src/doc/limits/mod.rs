@@ -2,4 +2,7 @@
 //! 
 //! * The underlying pagecache can currently store 2^36 pages. Leaf nodes in the `Tree` tend to split when they have more than 16 keys and values. This means that sled can hold a little less than **4,294,967,296 total items** (index nodes in the tree will also consume pages, but ideally far fewer than 1%). This is easy to increase without requiring migration, as it is entirely a runtime concern, but nobody has expressed any interest in this being larger yet. Note to future folks who need to increase this: increase the width of the Node1 type in the pagetable module, and correspondingly increase the number of bits that are used to index into it. It's just a simple wait-free grow-only 2-level pagetable.
 //! * keys and values use `usize` for the length fields due to the way that Rust uses `usize` for slice lengths, and will be limited to the target platform's pointer width. On 64-bit machines, this will be 64 bits. On 32-bit machines, it will be limited to `u32::max_value()`. 
-//! * Due to the 32-bit limitation on slice sizes on 32-bit architectures, we currently do not support systems large enough for the snapshot file to reach over 4gb. The snapshot file tends to be a small fraction of the total db size, and it's likely we'll be able to implement a streaming deserializer if this ever becomes an issue, but it seems unclear if anyone will encounter this limitation.
\ No newline at end of file
+//! * keys and values can be at most 1gb each. Writes with larger keys or values fail with `Error::Unsupported` instead of being stored. Keys are limited further to 16mb by default, because they are copied into index nodes when leaves split. Both limits can be lowered, and the key limit raised up to 1gb, with `Config::max_key_size` and `Config::max_value_size`. Nodes that grow too large for the log are stored in their own blob files, so large items don't need to fit in a segment.
+//! * Due to the 32-bit limitation on slice sizes on 32-bit architectures, we currently do not support systems large enough for the snapshot file to reach over 4gb. The snapshot file tends to be a small fraction of the total db size, and it's likely we'll be able to implement a streaming deserializer if this ever becomes an issue, but it seems unclear if anyone will encounter this limitation.
+//! * sled requires `std` and does not support `no_std` environments, even with `alloc`. The page cache, flusher, and epoch-based reclamation depend on threads, OS synchronization primitives (through `parking_lot` and `crossbeam-epoch`'s `std` feature), and `std::fs`, and `Config::temporary` is not an in-memory mode: it stores files under `/dev/shm` on linux or the system temporary directory elsewhere, and removes them on drop. Splitting the tree, `IVec`, and transaction logic away from those dependencies would require reworking most module boundaries. The closest supported option is a custom `IoBackend`, set with `Config::io_backend`, which can keep the log in memory or in non-filesystem storage, although the configuration file, snapshots and blobs are still written to the configured path.
+//! * sled does not support `wasm32-unknown-unknown`, so it cannot run in the browser, either in memory or on top of IndexedDB. Besides the dependencies on threads and `std::fs` above, opening a database reads and writes the configuration file through `std::fs`, which always fails on that target, `std::time::Instant::now`, which the flusher, metrics and `Config::snapshot_interval` use, panics there, and IndexedDB is only reachable asynchronously, while `IoBackend` reads and writes are blocking calls. On other platforms without background threads, work that is normally done in the background, like cleaning log segments, runs on the calling thread instead, and `Config::snapshot_after_ops` and `Config::snapshot_interval` take no snapshots.
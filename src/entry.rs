@@ -0,0 +1,110 @@
+use crate::*;
+
+type Modify<'a> = Box<dyn FnMut(&[u8]) -> IVec + 'a>;
+
+/// A key of a `Tree` that may or may not have a value,
+/// returned by `Tree::entry`. Like the entries of a
+/// `HashMap`, it is consumed by `or_insert` or
+/// `or_insert_with`, optionally after `and_modify`, which
+/// together perform a single atomic read-modify-write of
+/// the key and return its resulting value.
+///
+/// # Note
+///
+/// The read-modify-write is a compare and swap loop, like
+/// `Tree::update_and_fetch`, so the closures may be called
+/// multiple times if the key is changed by other threads in
+/// the meantime, and only the result of the last call is
+/// stored. They should not have side effects that depend on
+/// running exactly once.
+#[must_use = "an Entry does nothing until or_insert or \
+              or_insert_with is called"]
+pub struct Entry<'a> {
+    tree: &'a Tree,
+    key: IVec,
+    modify: Option<Modify<'a>>,
+}
+
+impl<'a> Entry<'a> {
+    pub(crate) fn new(tree: &'a Tree, key: IVec) -> Entry<'a> {
+        Entry { tree, key, modify: None }
+    }
+
+    /// Replaces the value with the result of `f` if the key
+    /// is present. `f` is never called for an absent key. If
+    /// this is called more than once, the functions are
+    /// applied in order.
+    pub fn and_modify<F, V>(self, mut f: F) -> Entry<'a>
+    where
+        F: FnMut(&[u8]) -> V + 'a,
+        IVec: From<V>,
+    {
+        let modify: Modify<'a> = match self.modify {
+            Some(mut previous) => Box::new(move |current: &[u8]| {
+                let modified = previous(current);
+                IVec::from(f(&modified))
+            }),
+            None => Box::new(move |current: &[u8]| IVec::from(f(current))),
+        };
+        Entry { tree: self.tree, key: self.key, modify: Some(modify) }
+    }
+
+    /// Inserts `value` if the key is absent, and returns the
+    /// resulting value of the key.
+    pub fn or_insert<V>(self, value: V) -> Result<IVec>
+    where
+        IVec: From<V>,
+    {
+        let inserted = IVec::from(value);
+        self.apply(|| inserted.clone())
+    }
+
+    /// Inserts the result of `f` if the key is absent, and
+    /// returns the resulting value of the key. `f` is only
+    /// called if the key is absent.
+    pub fn or_insert_with<F, V>(self, mut f: F) -> Result<IVec>
+    where
+        F: FnMut() -> V,
+        IVec: From<V>,
+    {
+        self.apply(|| IVec::from(f()))
+    }
+
+    fn apply<F>(mut self, mut default: F) -> Result<IVec>
+    where
+        F: FnMut() -> IVec,
+    {
+        let mut current = self.tree.get(&self.key)?;
+
+        loop {
+            let next = match (&current, &mut self.modify) {
+                (Some(value), Some(modify)) => modify(value),
+                // present and left as it is, so there is
+                // nothing to write
+                (Some(value), None) => return Ok(value.clone()),
+                (None, _) => default(),
+            };
+
+            match self.tree.compare_and_swap(
+                &self.key,
+                current.as_ref(),
+                Some(next.clone()),
+            )? {
+                Ok(()) => return Ok(next),
+                Err(CompareAndSwapError { current: cur, .. }) => {
+                    current = cur;
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Debug for Entry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("tree", &self.tree.name())
+            .field("key", &self.key)
+            .field("and_modify", &self.modify.is_some())
+            .finish()
+    }
+}
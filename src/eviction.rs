@@ -0,0 +1,221 @@
+//! Enforcement of `Config::max_db_size`, which removes items
+//! once the keys and values of all trees take up more than
+//! the limit, to use a `Db` as a bounded cache.
+//!
+//! Every item is tracked in memory along with when it was
+//! inserted, when it was last used and how often, and
+//! ranked by the `EvictionPolicy`. Writes are accounted for
+//! when they complete, and once the writer has sealed its
+//! batch and released the concurrency control, it removes
+//! the lowest ranked items until the limit holds again.
+//! This history is not persisted: when the database is
+//! opened, every item of every tree is read and tracked
+//! again in key order, as if it had just been inserted, so
+//! opening takes time proportional to the number of items.
+
+use std::collections::BTreeSet;
+
+use crate::*;
+
+/// Which items are removed first once the size set by
+/// `Config::max_db_size` is exceeded. The default is `Lru`.
+/// See `Config::eviction_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Remove the item that was least recently read with
+    /// `Tree::get` or written.
+    Lru,
+    /// Remove the item that was read with `Tree::get` or
+    /// written the fewest times, and the least recently
+    /// used of those.
+    Lfu,
+    /// Remove the item that was inserted first. Overwriting
+    /// an item does not move it, but removing it and
+    /// inserting it again does.
+    Fifo,
+}
+
+/// A tree name and a key.
+type ItemId = (IVec, IVec);
+
+#[derive(Debug, Clone, Copy)]
+struct Meta {
+    size: u64,
+    inserted: u64,
+    used: u64,
+    uses: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The total size of the tracked items.
+    size: u64,
+    /// Bumped by every use, to order them.
+    clock: u64,
+    items: FastMap8<ItemId, Meta>,
+    /// The tracked items by their rank, lowest first.
+    ranked: BTreeSet<((u64, u64), ItemId)>,
+}
+
+/// The items of a `Db` that uses `Config::max_db_size`,
+/// shared by all of its handles.
+#[derive(Debug)]
+pub(crate) struct Eviction {
+    max_size: u64,
+    policy: EvictionPolicy,
+    state: Mutex<State>,
+    /// Held while items are evicted, so that only one thread
+    /// evicts at a time.
+    evicting: Mutex<()>,
+    /// Set once the `Db` is opened. This is weak so that the
+    /// trees, which refer back to this, can still be dropped.
+    pub(crate) tenants: Mutex<Option<std::sync::Weak<Tenants>>>,
+}
+
+impl Eviction {
+    pub(crate) fn new(max_size: u64, policy: EvictionPolicy) -> Eviction {
+        Eviction {
+            max_size,
+            policy,
+            state: Mutex::new(State::default()),
+            evicting: Mutex::new(()),
+            tenants: Mutex::new(None),
+        }
+    }
+
+    fn rank(&self, meta: &Meta) -> (u64, u64) {
+        match self.policy {
+            EvictionPolicy::Lru => (meta.used, 0),
+            EvictionPolicy::Lfu => (meta.uses, meta.used),
+            EvictionPolicy::Fifo => (meta.inserted, 0),
+        }
+    }
+
+    /// Returns the total size of the keys and values of
+    /// all trees.
+    pub(crate) fn size(&self) -> u64 {
+        self.state.lock().size
+    }
+
+    /// Accounts for `key` of `tree` being set to a value of
+    /// `value_len` bytes, or removed if it is `None`.
+    pub(crate) fn record_write(
+        &self,
+        tree: &[u8],
+        key: &[u8],
+        value_len: Option<usize>,
+    ) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        state.clock += 1;
+
+        let id: ItemId = (tree.into(), key.into());
+        let previous = state.items.remove(&id);
+        if let Some(old) = &previous {
+            assert!(state.ranked.remove(&(self.rank(old), id.clone())));
+            state.size -= old.size;
+        }
+
+        if let Some(len) = value_len {
+            let meta = Meta {
+                size: u64::try_from(key.len() + len).unwrap(),
+                inserted: previous.map_or(state.clock, |old| old.inserted),
+                used: state.clock,
+                uses: previous.map_or(0, |old| old.uses) + 1,
+            };
+            state.size += meta.size;
+            assert!(state.ranked.insert((self.rank(&meta), id.clone())));
+            assert!(state.items.insert(id, meta).is_none());
+        }
+    }
+
+    /// Accounts for `key` of `tree` being read.
+    pub(crate) fn record_read(&self, tree: &[u8], key: &[u8]) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        state.clock += 1;
+
+        let id: ItemId = (tree.into(), key.into());
+        if let Some(meta) = state.items.get_mut(&id) {
+            assert!(state.ranked.remove(&(self.rank(meta), id.clone())));
+            meta.used = state.clock;
+            meta.uses += 1;
+            assert!(state.ranked.insert((self.rank(meta), id)));
+        }
+    }
+
+    /// Stops tracking the items of a tree that was dropped.
+    pub(crate) fn forget_tree(&self, tree: &[u8]) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+
+        let ids: Vec<ItemId> =
+            state.items.keys().filter(|id| id.0 == tree).cloned().collect();
+        for id in ids {
+            let meta = state.items.remove(&id).unwrap();
+            assert!(state.ranked.remove(&(self.rank(&meta), id)));
+            state.size -= meta.size;
+        }
+    }
+
+    /// Returns the item to evict next, other than `kept`, if
+    /// the limit is exceeded.
+    fn victim(&self, kept: Option<(&[u8], &[u8])>) -> Option<ItemId> {
+        let state = self.state.lock();
+        if state.size <= self.max_size {
+            return None;
+        }
+        state
+            .ranked
+            .iter()
+            .map(|(_rank, id)| id)
+            .find(|id| kept != Some((&*id.0, &*id.1)))
+            .cloned()
+    }
+
+    /// Removes items until the limit holds again, other
+    /// than `written`, which is the tree and key of the write
+    /// that exceeded it, and which is kept even if it is
+    /// larger than the limit on its own. The removals notify
+    /// subscribers like any other.
+    ///
+    /// This must not be called while holding the concurrency
+    /// control or the trees, because the removals take them.
+    /// Evictions are serialized, so a write that exceeds the
+    /// limit while another thread evicts waits for it, and
+    /// then checks the limit again.
+    pub(crate) fn evict(&self, written: Option<(&[u8], &[u8])>) -> Result<()> {
+        // writes under the limit do not wait for evictions
+        if self.victim(written).is_none() {
+            return Ok(());
+        }
+
+        let _evicting = self.evicting.lock();
+
+        // not set until the `Db` is opened, which evicts once
+        // it has tracked every item
+        let tenants_arc = if let Some(tenants_arc) =
+            self.tenants.lock().as_ref().and_then(std::sync::Weak::upgrade)
+        {
+            tenants_arc
+        } else {
+            return Ok(());
+        };
+        let tenants = tenants_arc.read();
+
+        let mut guard = pin();
+        while let Some((tree_id, key)) = self.victim(written) {
+            if let Some(tree) = tenants.get(&tree_id) {
+                trace!("evicting key {:?} of tree {:?}", key, tree_id);
+                let _cc = concurrency_control::read();
+                while tree.insert_inner(&key, None, &mut guard)?.is_err() {}
+            }
+
+            // a removal that found nothing to remove does
+            // not record anything
+            self.record_write(&tree_id, &key, None);
+        }
+
+        Ok(())
+    }
+}
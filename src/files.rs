@@ -0,0 +1,583 @@
+//! Filesystem operations on the files of a database. They
+//! are done by path, or for a database opened with
+//! `Config::open_at`, relative to its directory descriptor
+//! with the `*at` family of system calls, in which case the
+//! paths are relative to that directory. The files of an
+//! in-memory database, see `MemFs`, never reach the
+//! filesystem.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use parking_lot::{Mutex, RwLock};
+
+#[cfg(unix)]
+use std::{
+    ffi::{CStr, CString, OsStr},
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+};
+
+use crate::{config::Inner, pagecache::IoBackend, LogOffset};
+
+/// An open file of a database.
+#[derive(Debug)]
+pub(crate) enum File {
+    Os(fs::File),
+    Mem { file: Arc<MemFile>, pos: u64 },
+}
+
+impl File {
+    /// Returns the length of the file.
+    pub(crate) fn len(&self) -> io::Result<u64> {
+        match self {
+            File::Os(file) => Ok(file.metadata()?.len()),
+            File::Mem { file, .. } => file.size(),
+        }
+    }
+
+    /// Like `std::fs::File::sync_all`.
+    pub(crate) fn sync_all(&self) -> io::Result<()> {
+        match self {
+            File::Os(file) => file.sync_all(),
+            File::Mem { .. } => Ok(()),
+        }
+    }
+
+    /// Returns the file on the filesystem, which is `None`
+    /// for the files of an in-memory database.
+    pub(crate) fn into_os(self) -> Option<fs::File> {
+        match self {
+            File::Os(file) => Some(file),
+            File::Mem { .. } => None,
+        }
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            File::Os(file) => file.read(buf),
+            File::Mem { file, pos } => {
+                let read = file.pread_exact_or_eof(buf, *pos)?;
+                *pos += read as u64;
+                Ok(read)
+            }
+        }
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            File::Os(file) => file.write(buf),
+            File::Mem { file, pos } => {
+                file.pwrite_all(buf, *pos)?;
+                *pos += buf.len() as u64;
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            File::Os(file) => file.flush(),
+            File::Mem { .. } => Ok(()),
+        }
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        match self {
+            File::Os(file) => file.seek(to),
+            File::Mem { file, pos } => {
+                let (base, offset) = match to {
+                    SeekFrom::Start(offset) => {
+                        *pos = offset;
+                        return Ok(offset);
+                    }
+                    SeekFrom::End(offset) => (file.size()?, offset),
+                    SeekFrom::Current(offset) => (*pos, offset),
+                };
+                let new_pos = if offset < 0 {
+                    base.checked_sub(offset.unsigned_abs())
+                } else {
+                    base.checked_add(offset.unsigned_abs())
+                };
+                *pos = new_pos.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "invalid seek to a negative or overflowing position",
+                    )
+                })?;
+                Ok(*pos)
+            }
+        }
+    }
+}
+
+/// The contents of a file of an in-memory database. It is
+/// also the `IoBackend` that holds the log of one.
+#[derive(Debug, Default)]
+pub(crate) struct MemFile(RwLock<Vec<u8>>);
+
+impl IoBackend for MemFile {
+    fn pread_exact_or_eof(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> io::Result<usize> {
+        let data = self.0.read();
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        let src = data.get(start..).unwrap_or(&[]);
+        let read = src.len().min(buf.len());
+        buf[..read].copy_from_slice(&src[..read]);
+        Ok(read)
+    }
+
+    fn pwrite_all(&self, buf: &[u8], offset: LogOffset) -> io::Result<()> {
+        let start = usize::try_from(offset).map_err(|_| too_large())?;
+        let end = start.checked_add(buf.len()).ok_or_else(too_large)?;
+        let mut data = self.0.write();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.read().len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        let new_len = usize::try_from(len).map_err(|_| too_large())?;
+        self.0.write().resize(new_len, 0);
+        Ok(())
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "file too large for memory")
+}
+
+/// The files and directories of an in-memory database,
+/// which only live as long as its `Config`.
+#[derive(Debug, Default)]
+pub(crate) struct MemFs(Mutex<MemEntries>);
+
+#[derive(Debug, Default)]
+struct MemEntries {
+    files: BTreeMap<PathBuf, Arc<MemFile>>,
+    dirs: BTreeSet<PathBuf>,
+}
+
+impl MemFs {
+    fn open(&self, options: OpenOptions, path: &Path) -> io::Result<File> {
+        let mut entries = self.0.lock();
+        let file = if let Some(file) = entries.files.get(path) {
+            if options.create_new {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "file exists",
+                ));
+            }
+            if options.truncate {
+                file.0.write().clear();
+            }
+            file.clone()
+        } else if options.create || options.create_new {
+            let file = Arc::new(MemFile::default());
+            let _ = entries.files.insert(path.to_path_buf(), file.clone());
+            file
+        } else {
+            return Err(not_found());
+        };
+        Ok(File::Mem { file, pos: 0 })
+    }
+
+    fn file(&self, path: &Path) -> io::Result<Arc<MemFile>> {
+        self.0.lock().files.get(path).cloned().ok_or_else(not_found)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let entries = self.0.lock();
+        entries.files.contains_key(path) || entries.dirs.contains(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) {
+        let mut entries = self.0.lock();
+        for dir in path.ancestors() {
+            let _ = entries.dirs.insert(dir.to_path_buf());
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self.0.lock();
+        if !entries.dirs.contains(path) {
+            return Err(not_found());
+        }
+        Ok(entries
+            .files
+            .keys()
+            .chain(&entries.dirs)
+            .filter(|entry| entry.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.0.lock();
+        let file = entries.files.remove(from).ok_or_else(not_found)?;
+        let _ = entries.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.0.lock().files.remove(path).map(|_| ()).ok_or_else(not_found)
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file or directory")
+}
+
+/// Like `std::fs::OpenOptions`, for the options that sled
+/// uses.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct OpenOptions {
+    read: bool,
+    write: bool,
+    create: bool,
+    create_new: bool,
+    truncate: bool,
+}
+
+impl OpenOptions {
+    pub(crate) fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    pub(crate) fn read(mut self, read: bool) -> OpenOptions {
+        self.read = read;
+        self
+    }
+
+    pub(crate) fn write(mut self, write: bool) -> OpenOptions {
+        self.write = write;
+        self
+    }
+
+    pub(crate) fn create(mut self, create: bool) -> OpenOptions {
+        self.create = create;
+        self
+    }
+
+    pub(crate) fn create_new(mut self, create_new: bool) -> OpenOptions {
+        self.create_new = create_new;
+        self
+    }
+
+    pub(crate) fn truncate(mut self, truncate: bool) -> OpenOptions {
+        self.truncate = truncate;
+        self
+    }
+
+    pub(crate) fn open(self, config: &Inner, path: &Path) -> io::Result<File> {
+        if let Some(mem) = &config.mem_fs {
+            return mem.open(self, path);
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(dir) = dir_fd(config) {
+                let mut flags = libc::O_CLOEXEC
+                    | match (self.read, self.write) {
+                        (true, true) => libc::O_RDWR,
+                        (false, true) => libc::O_WRONLY,
+                        _ => libc::O_RDONLY,
+                    };
+                if self.create_new {
+                    flags |= libc::O_CREAT | libc::O_EXCL;
+                } else if self.create {
+                    flags |= libc::O_CREAT;
+                }
+                if self.truncate {
+                    flags |= libc::O_TRUNC;
+                }
+
+                let c_path = c_path(path)?;
+                let mode: libc::c_uint = 0o666;
+                #[allow(unsafe_code)]
+                let fd = cvt(unsafe {
+                    libc::openat(dir, c_path.as_ptr(), flags, mode)
+                })?;
+                // owns the descriptor, closing it on drop
+                #[allow(unsafe_code)]
+                return Ok(File::Os(unsafe { fs::File::from_raw_fd(fd) }));
+            }
+        }
+
+        fs::OpenOptions::new()
+            .read(self.read)
+            .write(self.write)
+            .create(self.create)
+            .create_new(self.create_new)
+            .truncate(self.truncate)
+            .open(path)
+            .map(File::Os)
+    }
+}
+
+/// Returns `true` if something exists at `path`.
+pub(crate) fn exists(config: &Inner, path: &Path) -> bool {
+    if let Some(mem) = &config.mem_fs {
+        return mem.exists(path);
+    }
+
+    #[cfg(unix)]
+    {
+        if dir_fd(config).is_some() {
+            return stat(config, path).is_ok();
+        }
+    }
+
+    path.exists()
+}
+
+/// Returns the length of the file at `path`.
+pub(crate) fn file_len(config: &Inner, path: &Path) -> io::Result<u64> {
+    if let Some(mem) = &config.mem_fs {
+        return mem.file(path)?.size();
+    }
+
+    #[cfg(unix)]
+    {
+        if dir_fd(config).is_some() {
+            let stat = stat(config, path)?;
+            return Ok(u64::try_from(stat.st_size).unwrap());
+        }
+    }
+
+    Ok(fs::metadata(path)?.len())
+}
+
+/// Like `std::fs::create_dir_all`.
+pub(crate) fn create_dir_all(config: &Inner, path: &Path) -> io::Result<()> {
+    if let Some(mem) = &config.mem_fs {
+        mem.create_dir_all(path);
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(dir) = dir_fd(config) {
+            let mut prefix = PathBuf::new();
+            for component in path.components() {
+                prefix.push(component);
+                let c_prefix = c_path(&prefix)?;
+                #[allow(unsafe_code)]
+                let ret =
+                    unsafe { libc::mkdirat(dir, c_prefix.as_ptr(), 0o777) };
+                if let Err(e) = cvt(ret) {
+                    if e.kind() != io::ErrorKind::AlreadyExists {
+                        return Err(e);
+                    }
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    fs::create_dir_all(path)
+}
+
+/// Returns the paths of the entries of the directory at
+/// `path`, like the `path` of each `std::fs::DirEntry`.
+pub(crate) fn read_dir(
+    config: &Inner,
+    path: &Path,
+) -> io::Result<Vec<PathBuf>> {
+    if let Some(mem) = &config.mem_fs {
+        return mem.read_dir(path);
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(dir) = dir_fd(config) {
+            let c_dir = c_path(path)?;
+            let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+            #[allow(unsafe_code)]
+            let fd = cvt(unsafe { libc::openat(dir, c_dir.as_ptr(), flags) })?;
+
+            #[allow(unsafe_code)]
+            let stream = unsafe { libc::fdopendir(fd) };
+            if stream.is_null() {
+                let e = io::Error::last_os_error();
+                #[allow(unsafe_code)]
+                let _ = unsafe { libc::close(fd) };
+                return Err(e);
+            }
+
+            // `readdir` returns null at the end of the stream
+            let mut entries = vec![];
+            loop {
+                #[allow(unsafe_code)]
+                let entry = unsafe { libc::readdir(stream) };
+                if entry.is_null() {
+                    break;
+                }
+                #[allow(unsafe_code)]
+                let name =
+                    unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }
+                        .to_bytes();
+                if name != b"." && name != b".." {
+                    entries.push(path.join(OsStr::from_bytes(name)));
+                }
+            }
+
+            // also closes `fd`
+            #[allow(unsafe_code)]
+            let _ = unsafe { libc::closedir(stream) };
+            return Ok(entries);
+        }
+    }
+
+    fs::read_dir(path)?
+        .map(|entry_res| entry_res.map(|entry| entry.path()))
+        .collect()
+}
+
+/// Like `std::fs::read`.
+pub(crate) fn read(config: &Inner, path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = OpenOptions::new().read(true).open(config, path)?;
+    let mut buf = vec![];
+    let _ = file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Like `std::fs::rename`.
+pub(crate) fn rename(config: &Inner, from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(mem) = &config.mem_fs {
+        return mem.rename(from, to);
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(dir) = dir_fd(config) {
+            let (c_from, c_to) = (c_path(from)?, c_path(to)?);
+            #[allow(unsafe_code)]
+            let ret = unsafe {
+                libc::renameat(dir, c_from.as_ptr(), dir, c_to.as_ptr())
+            };
+            return cvt(ret).map(|_| ());
+        }
+    }
+
+    fs::rename(from, to)
+}
+
+/// Like `std::fs::remove_file`.
+pub(crate) fn remove_file(config: &Inner, path: &Path) -> io::Result<()> {
+    if let Some(mem) = &config.mem_fs {
+        return mem.remove_file(path);
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(dir) = dir_fd(config) {
+            let c_path = c_path(path)?;
+            #[allow(unsafe_code)]
+            let ret = unsafe { libc::unlinkat(dir, c_path.as_ptr(), 0) };
+            return cvt(ret).map(|_| ());
+        }
+    }
+
+    fs::remove_file(path)
+}
+
+/// Makes the entries of the directory at `path` durable.
+pub(crate) fn sync_dir(config: &Inner, path: &Path) -> io::Result<()> {
+    if config.mem_fs.is_some() {
+        return Ok(());
+    }
+
+    OpenOptions::new().read(true).open(config, path)?.sync_all()
+}
+
+/// Returns the number of bytes available to this process
+/// on the volume that holds the database, which is
+/// unlimited for an in-memory database.
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+pub(crate) fn available_space(config: &Inner) -> io::Result<u64> {
+    if config.mem_fs.is_some() {
+        return Ok(u64::MAX);
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(dir) = dir_fd(config) {
+            #[allow(unsafe_code)]
+            let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+            #[allow(unsafe_code)]
+            cvt(unsafe { libc::fstatvfs(dir, &mut stat) })?;
+            // the widths of these fields differ between platforms
+            #[allow(clippy::useless_conversion)]
+            let (blocks, block_size) =
+                (u64::from(stat.f_bavail), u64::from(stat.f_frsize));
+            return Ok(blocks.saturating_mul(block_size));
+        }
+    }
+
+    fs2::available_space(config.get_path())
+}
+
+#[cfg(unix)]
+fn dir_fd(config: &Inner) -> Option<RawFd> {
+    config.dir_fd.as_ref().map(|dir| fs::File::as_raw_fd(dir))
+}
+
+#[cfg(unix)]
+fn stat(config: &Inner, path: &Path) -> io::Result<libc::stat> {
+    let dir = dir_fd(config).unwrap();
+    let c_path = c_path(path)?;
+    #[allow(unsafe_code)]
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    #[allow(unsafe_code)]
+    cvt(unsafe { libc::fstatat(dir, c_path.as_ptr(), &mut stat, 0) })?;
+    Ok(stat)
+}
+
+/// Converts a path that is relative to the directory
+/// descriptor, where an empty path is the directory itself.
+#[cfg(unix)]
+fn c_path(path: &Path) -> io::Result<CString> {
+    let mut bytes = path.as_os_str().as_bytes();
+    if bytes.is_empty() {
+        bytes = b".";
+    }
+    CString::new(bytes).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "paths may not contain NUL bytes",
+        )
+    })
+}
+
+#[cfg(unix)]
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(ret) }
+}
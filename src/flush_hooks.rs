@@ -0,0 +1,87 @@
+//! Callbacks that are notified when writes become
+//! durable. See `Db::on_flush`.
+
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{channel, Sender},
+    },
+    thread,
+};
+
+use crate::*;
+
+/// Information about log data that has just been made
+/// durable, passed to callbacks registered with
+/// `Db::on_flush`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushInfo {
+    /// The number of log bytes that became durable since
+    /// the previous notification.
+    pub bytes_flushed: u64,
+    /// The number of log bytes that are now durable in
+    /// total, which identifies this flush. It strictly
+    /// increases from one notification to the next, and
+    /// keeps increasing across restarts.
+    pub generation: u64,
+}
+
+type FlushCallback = Box<dyn Fn(FlushInfo) + Send + Sync>;
+
+/// The callbacks registered with `Db::on_flush`. These run
+/// on a dedicated thread, so that a slow or blocking
+/// callback never holds up the thread that wrote the data.
+#[derive(Default)]
+pub(crate) struct FlushHooks {
+    active: AtomicBool,
+    sender: Mutex<Option<Sender<FlushInfo>>>,
+    callbacks: Arc<RwLock<Vec<FlushCallback>>>,
+}
+
+impl FlushHooks {
+    pub(crate) fn register(&self, callback: FlushCallback) {
+        self.callbacks.write().push(callback);
+
+        let mut sender = self.sender.lock();
+        if sender.is_none() {
+            let (tx, rx) = channel();
+            let callbacks = self.callbacks.clone();
+
+            thread::Builder::new()
+                .name("sled flush callbacks".to_owned())
+                .spawn(move || {
+                    // exits once the log is dropped along with
+                    // the sender
+                    for info in rx {
+                        for hook in callbacks.read().iter() {
+                            let ret = catch_unwind(AssertUnwindSafe(|| {
+                                hook(info)
+                            }));
+                            if ret.is_err() {
+                                error!("flush callback panicked on {:?}", info);
+                            }
+                        }
+                    }
+                })
+                .unwrap();
+
+            *sender = Some(tx);
+            self.active.store(true, SeqCst);
+        }
+    }
+
+    /// Queues a notification for the registered callbacks.
+    /// Callers must serialize calls to this so that the
+    /// notifications are delivered in order.
+    pub(crate) fn notify(&self, info: FlushInfo) {
+        if !self.active.load(SeqCst) {
+            return;
+        }
+
+        if let Some(sender) = &*self.sender.lock() {
+            // this only fails if the callback thread is gone
+            let _ = sender.send(info);
+        }
+    }
+}
@@ -31,10 +31,12 @@ pub(crate) struct Flusher {
 
 impl Flusher {
     /// Spawns a thread that periodically calls `callback` until dropped.
+    /// It waits `flush_every` milliseconds between flushes, which
+    /// is read again after each one.
     pub(crate) fn new(
         name: String,
         pagecache: Arc<PageCache>,
-        flush_every_ms: u64,
+        flush_every: Arc<AtomicU64>,
     ) -> Self {
         #[allow(clippy::mutex_atomic)] // mutex used in CondVar below
         let shutdown = Arc::new(Mutex::new(ShutdownState::Running));
@@ -45,27 +47,42 @@ impl Flusher {
             .spawn({
                 let shutdown = shutdown.clone();
                 let sc = sc.clone();
-                move || run(&shutdown, &sc, &pagecache, flush_every_ms)
+                move || run(&shutdown, &sc, &pagecache, &flush_every)
             })
             .unwrap();
 
         Self { shutdown, sc, join_handle: Mutex::new(Some(join_handle)) }
     }
+
+    /// Ends the current wait between flushes, so that a
+    /// changed `flush_every` takes effect after the next
+    /// flush rather than after the wait.
+    pub(crate) fn wake(&self) {
+        // having held the mutex makes this linearized
+        // with the wait in `sleep`
+        let shutdown = self.shutdown.lock();
+        drop(shutdown);
+        let _notified = self.sc.notify_all();
+    }
 }
 
 fn run(
     shutdown: &Arc<Mutex<ShutdownState>>,
     sc: &Arc<Condvar>,
     pagecache: &Arc<PageCache>,
-    flush_every_ms: u64,
+    flush_every_ms: &AtomicU64,
 ) {
-    let flush_every = Duration::from_millis(flush_every_ms);
+    let jitter_us = usize::try_from(pagecache.config.flush_jitter.as_micros())
+        .unwrap_or(usize::max_value());
+    let mut rng = SampleRng::new();
     let mut shutdown = shutdown.lock();
     let mut wrote_data = false;
     while shutdown.is_running() || wrote_data {
+        let flush_every = Duration::from_millis(flush_every_ms.load(SeqCst));
         let before = std::time::Instant::now();
         let cc = concurrency_control::read();
-        match pagecache.log.roll_iobuf() {
+        match pagecache.log_coalesced().and_then(|_| pagecache.log.roll_iobuf())
+        {
             Ok(0) => {
                 wrote_data = false;
                 if !shutdown.is_running() {
@@ -134,19 +151,26 @@ fn run(
                 && before.elapsed() < flush_every / 2
         } {}
 
-        if let Err(e) = pagecache.config.file.sync_all() {
+        if let Err(e) = pagecache.config.sync_log() {
             error!("failed to fsync from periodic flush thread: {}", e);
         }
 
+        let jitter = if jitter_us == 0 {
+            Duration::from_secs(0)
+        } else {
+            let us = rng.below(jitter_us.saturating_add(1));
+            Duration::from_micros(u64::try_from(us).unwrap())
+        };
         let sleep_duration = flush_every
             .checked_sub(before.elapsed())
-            .unwrap_or_else(|| Duration::from_millis(1));
+            .unwrap_or_else(|| Duration::from_millis(1))
+            + jitter;
 
         if shutdown.is_running() {
             // only sleep before the next flush if we are
             // running normally. if we're shutting down,
             // flush faster.
-            sc.wait_for(&mut shutdown, sleep_duration);
+            sleep(&mut shutdown, sc, pagecache, sleep_duration);
         }
     }
 
@@ -159,6 +183,42 @@ fn run(
     let _notified = sc.notify_all();
 }
 
+/// Waits for `duration`, or until shutdown is requested or
+/// the flusher is woken, or, with
+/// `Config::flush_dirty_threshold`, until that many bytes of
+/// the log are waiting to be flushed.
+fn sleep(
+    shutdown: &mut parking_lot::MutexGuard<'_, ShutdownState>,
+    sc: &Condvar,
+    pagecache: &PageCache,
+    duration: Duration,
+) {
+    let threshold = pagecache.config.flush_dirty_threshold;
+    if threshold == 0 {
+        let _timeout = sc.wait_for(shutdown, duration);
+        return;
+    }
+
+    let poll_every = Duration::from_millis(10);
+    let deadline = std::time::Instant::now() + duration;
+    while shutdown.is_running() {
+        let now = std::time::Instant::now();
+        if now >= deadline {
+            return;
+        }
+        let log = &pagecache.log;
+        let dirty = log.max_reserved_lsn() - log.stable_offset();
+        if u64::try_from(dirty).unwrap_or(0) >= threshold {
+            return;
+        }
+        let timeout =
+            sc.wait_for(shutdown, std::cmp::min(poll_every, deadline - now));
+        if !timeout.timed_out() {
+            return;
+        }
+    }
+}
+
 impl Drop for Flusher {
     fn drop(&mut self) {
         let mut shutdown = self.shutdown.lock();
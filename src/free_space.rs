@@ -0,0 +1,177 @@
+//! Tracking of the free space on the volume that holds
+//! the database, used to refuse writes before the disk
+//! fills up, and to hold them back while it is full. See
+//! `Config::min_free_space` and `OnFull`.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::*;
+
+/// How long a measurement is reused before the
+/// filesystem is queried again.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+const NEVER: u64 = u64::max_value();
+
+#[derive(Debug)]
+pub(crate) struct FreeSpace {
+    start: Instant,
+    /// Free bytes as of the last refresh.
+    bytes: AtomicU64,
+    /// Microseconds since `start` at the last refresh,
+    /// or `NEVER`.
+    refreshed_at: AtomicU64,
+}
+
+impl Default for FreeSpace {
+    fn default() -> FreeSpace {
+        FreeSpace {
+            start: Instant::now(),
+            bytes: AtomicU64::new(0),
+            refreshed_at: AtomicU64::new(NEVER),
+        }
+    }
+}
+
+impl FreeSpace {
+    /// Returns an error if the free space on the volume is
+    /// below `Config::min_free_space`. This only queries the
+    /// filesystem once per `REFRESH_INTERVAL`, and is a
+    /// couple of atomic loads otherwise.
+    pub(crate) fn check(&self, config: &RunningConfig) -> Result<()> {
+        if config.disk_full.is_full() {
+            match config.on_disk_full {
+                OnFull::Block => config.disk_full.wait(),
+                // with OnFull::Error the global error
+                // refuses the write instead
+                OnFull::Reject | OnFull::Error => {
+                    return Err(Error::Io(no_space_error()));
+                }
+            }
+        }
+
+        if config.min_free_space == 0 {
+            return Ok(());
+        }
+
+        let refreshed_at = self.refreshed_at.load(Acquire);
+        let interval = u64::try_from(REFRESH_INTERVAL.as_micros()).unwrap();
+        let bytes = if refreshed_at != NEVER
+            && self.now(config).saturating_sub(refreshed_at) < interval
+        {
+            self.bytes.load(Acquire)
+        } else {
+            self.refresh(config)?
+        };
+
+        if bytes < config.min_free_space {
+            warn!(
+                "refusing write because only {} bytes are free on the \
+                 volume holding {:?}, and min_free_space is {}",
+                bytes,
+                config.get_path(),
+                config.min_free_space
+            );
+            return Err(Error::Io(no_space_error()));
+        }
+
+        Ok(())
+    }
+
+    /// Queries the filesystem for the free space on the
+    /// volume, and caches the result.
+    pub(crate) fn refresh(&self, config: &RunningConfig) -> Result<u64> {
+        let bytes = available_space(config)?;
+        self.bytes.store(bytes, Release);
+        self.refreshed_at.store(self.now(config), Release);
+        Ok(bytes)
+    }
+
+    fn now(&self, config: &RunningConfig) -> u64 {
+        let elapsed =
+            config.test_now().unwrap_or_else(|| self.start.elapsed());
+        u64::try_from(elapsed.as_micros()).unwrap()
+    }
+}
+
+/// Tracks log writes that are being retried because the
+/// disk is full, for `OnFull::Reject` and `OnFull::Block`.
+#[derive(Debug, Default)]
+pub(crate) struct DiskFull {
+    retrying: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl DiskFull {
+    /// Called when a log write first fails because the
+    /// disk is full.
+    pub(crate) fn enter(&self) {
+        *self.retrying.lock() += 1;
+    }
+
+    /// Called when a log write that `enter`ed succeeds or
+    /// fails for another reason.
+    pub(crate) fn exit(&self) {
+        let mut retrying = self.retrying.lock();
+        *retrying -= 1;
+        if *retrying == 0 {
+            self.freed.notify_all();
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        *self.retrying.lock() > 0
+    }
+
+    /// Blocks until no log writes are being retried.
+    pub(crate) fn wait(&self) {
+        let mut retrying = self.retrying.lock();
+        while *retrying > 0 {
+            self.freed.wait(&mut retrying);
+        }
+    }
+}
+
+/// Returns `true` if `error` is what the OS returns when
+/// writing to a full disk.
+#[cfg(not(windows))]
+pub(crate) fn is_no_space(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::ENOSPC)
+}
+
+#[cfg(windows)]
+pub(crate) fn is_no_space(error: &io::Error) -> bool {
+    // ERROR_DISK_FULL and ERROR_HANDLE_DISK_FULL
+    error.raw_os_error() == Some(112) || error.raw_os_error() == Some(39)
+}
+
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+fn available_space(config: &RunningConfig) -> Result<u64> {
+    Ok(files::available_space(config)?)
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn available_space(_config: &RunningConfig) -> Result<u64> {
+    Err(Error::Unsupported(
+        "measuring free space is only supported on \
+         linux, macos and windows"
+            .to_owned(),
+    ))
+}
+
+/// The error that the OS returns when writing to a
+/// full disk, so that callers can handle refused
+/// writes the same way as actual out-of-space errors.
+#[cfg(not(windows))]
+fn no_space_error() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOSPC)
+}
+
+#[cfg(windows)]
+fn no_space_error() -> io::Error {
+    // ERROR_DISK_FULL
+    io::Error::from_raw_os_error(112)
+}
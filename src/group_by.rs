@@ -0,0 +1,115 @@
+use std::ops::Bound;
+
+use crate::*;
+
+type KeyGroupFn = dyn Fn(&[u8]) -> &[u8] + Send + Sync;
+
+/// An iterator over the groups of adjacent keys of a
+/// `Tree`, returned by `Tree::group_by`. Each item is a
+/// group, as returned by the key group function, along
+/// with a `GroupIter` over its keys and values.
+pub struct GroupBy {
+    iter: Iter,
+    key_group_fn: std::sync::Arc<KeyGroupFn>,
+    group: Option<IVec>,
+}
+
+impl GroupBy {
+    pub(crate) fn new<F>(iter: Iter, key_group_fn: F) -> GroupBy
+    where
+        F: Fn(&[u8]) -> &[u8] + Send + Sync + 'static,
+    {
+        GroupBy {
+            iter,
+            key_group_fn: std::sync::Arc::new(key_group_fn),
+            group: None,
+        }
+    }
+}
+
+impl Iterator for GroupBy {
+    type Item = Result<(IVec, GroupIter)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = match self.iter.next()? {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e)),
+            };
+
+            // skip what is left of the previous group
+            let group_key = (self.key_group_fn)(&key);
+            if self.group.as_ref().map(AsRef::as_ref) == Some(group_key) {
+                continue;
+            }
+            let group = IVec::from(group_key);
+            self.group = Some(group.clone());
+
+            let mut rest = self
+                .iter
+                .tree
+                .range((Bound::Excluded(key.clone()), self.iter.hi.clone()));
+            rest.relaxed = self.iter.relaxed;
+
+            let group_iter = GroupIter {
+                first: Some((key, value)),
+                iter: Some(rest),
+                key_group_fn: self.key_group_fn.clone(),
+                group: group.clone(),
+            };
+
+            return Some(Ok((group, group_iter)));
+        }
+    }
+}
+
+impl Debug for GroupBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupBy")
+            .field("tree", &self.iter.tree.name())
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+/// An iterator over the keys and values of one group of a
+/// `GroupBy`, in order. It ends at the first key that
+/// belongs to a different group.
+pub struct GroupIter {
+    first: Option<(IVec, IVec)>,
+    // `None` once the group has ended
+    iter: Option<Iter>,
+    key_group_fn: std::sync::Arc<KeyGroupFn>,
+    group: IVec,
+}
+
+impl Iterator for GroupIter {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(first) = self.first.take() {
+            return Some(Ok(first));
+        }
+
+        let (key, value) = match self.iter.as_mut()?.next()? {
+            Ok(item) => item,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if *self.group == *(self.key_group_fn)(&key) {
+            Some(Ok((key, value)))
+        } else {
+            self.iter = None;
+            None
+        }
+    }
+}
+
+impl Debug for GroupIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GroupIter")
+            .field("group", &self.group)
+            .field("done", &self.iter.is_none())
+            .finish()
+    }
+}
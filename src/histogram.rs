@@ -77,19 +77,29 @@ impl Histogram {
     #[inline]
     pub fn measure(&self, raw_value: u64) {
         #[cfg(not(feature = "no_metrics"))]
-        {
-            let value_float: f64 = raw_value as f64;
-            self.sum.fetch_add(value_float.round() as usize, Ordering::Relaxed);
+        self.record(raw_value);
+    }
 
-            self.count.fetch_add(1, Ordering::Relaxed);
+    /// Record a value, even if metrics are disabled by the
+    /// `no_metrics` feature. For histograms that are only
+    /// created when requested, like the ones of
+    /// `Config::collect_latency_histograms`.
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[inline]
+    pub fn record(&self, raw_value: u64) {
+        let value_float: f64 = raw_value as f64;
+        self.sum.fetch_add(value_float.round() as usize, Ordering::Relaxed);
 
-            // compress the value to one of 2**16 values
-            // using logarithmic bucketing
-            let compressed: u16 = compress(value_float);
+        self.count.fetch_add(1, Ordering::Relaxed);
 
-            // increment the counter for this compressed value
-            self.vals[compressed as usize].fetch_add(1, Ordering::Relaxed);
-        }
+        // compress the value to one of 2**16 values
+        // using logarithmic bucketing
+        let compressed: u16 = compress(value_float);
+
+        // increment the counter for this compressed value
+        self.vals[compressed as usize].fetch_add(1, Ordering::Relaxed);
     }
 
     /// Retrieve a percentile [0-100]. Returns NAN if no metrics have been
@@ -97,28 +107,40 @@ impl Histogram {
     pub fn percentile(&self, p: f64) -> f64 {
         #[cfg(not(feature = "no_metrics"))]
         {
-            assert!(p <= 100., "percentiles must not exceed 100.0");
+            self.recorded_percentile(p)
+        }
 
-            let count = self.count.load(Ordering::Acquire);
+        #[cfg(feature = "no_metrics")]
+        {
+            std::f64::NAN
+        }
+    }
 
-            if count == 0 {
-                return std::f64::NAN;
-            }
+    /// Like `percentile`, for values added by `record`.
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn recorded_percentile(&self, p: f64) -> f64 {
+        assert!(p <= 100., "percentiles must not exceed 100.0");
 
-            let mut target = count as f64 * (p / 100.);
-            if target == 0. {
-                target = 1.;
-            }
+        let count = self.count.load(Ordering::Acquire);
+
+        if count == 0 {
+            return std::f64::NAN;
+        }
+
+        let mut target = count as f64 * (p / 100.);
+        if target == 0. {
+            target = 1.;
+        }
 
-            let mut sum = 0.;
+        let mut sum = 0.;
 
-            for (idx, val) in self.vals.iter().enumerate() {
-                let count = val.load(Ordering::Acquire);
-                sum += count as f64;
+        for (idx, val) in self.vals.iter().enumerate() {
+            let bucket_count = val.load(Ordering::Acquire);
+            sum += bucket_count as f64;
 
-                if sum >= target {
-                    return decompress(idx as u16);
-                }
+            if sum >= target {
+                return decompress(idx as u16);
             }
         }
 
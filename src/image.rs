@@ -0,0 +1,124 @@
+//! The byte format of `Db::to_bytes` and `Db::open_from_bytes`.
+//!
+//! An image starts with `MAGIC`, followed by the number of
+//! trees, and for each tree its name, its number of items
+//! and then each key and value. Numbers are varints and
+//! byte strings are prefixed with their length, as in the
+//! log. The image ends with the crc32 of everything before
+//! it, in little endian.
+//!
+//! Only the user-visible items are stored, so the format
+//! doesn't depend on the layout of pages and an image can be
+//! opened with any configuration.
+
+use std::{
+    convert::TryInto,
+    io::{self, Write},
+};
+
+use crate::*;
+
+const MAGIC: &[u8; 8] = b"sledimg1";
+
+/// The items of one tree in an image.
+pub(crate) type TreeImage = (IVec, Vec<(IVec, IVec)>);
+
+/// Writes an image of `trees` to `out`. Each tree is read
+/// twice, first to count its items and then to write them,
+/// so the image is only consistent if no write happened in
+/// between, which the caller has to rule out.
+pub(crate) fn write<W: Write>(
+    out: &mut W,
+    trees: &[(&IVec, &Tree)],
+) -> Result<()> {
+    let mut crc = Crc { out, hasher: crc32fast::Hasher::new() };
+    crc.write(MAGIC)?;
+
+    crc.push(&(trees.len() as u64))?;
+    for (name, tree) in trees {
+        // the caller keeps other writers out or detects
+        // them, so the iterators don't need the lock
+        let mut count = 0_u64;
+        for item in tree.iter().relaxed() {
+            item?;
+            count += 1;
+        }
+
+        crc.push(*name)?;
+        crc.push(&count)?;
+        for item in tree.iter().relaxed() {
+            let (k, v) = item?;
+            crc.push(&k)?;
+            crc.push(&v)?;
+        }
+    }
+
+    let sum = crc.hasher.finalize();
+    crc.out.write_all(&sum.to_le_bytes())?;
+    Ok(())
+}
+
+/// Hashes everything that is written through it.
+struct Crc<'a, W> {
+    out: &'a mut W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<'a, W: Write> Crc<'a, W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hasher.update(bytes);
+        self.out.write_all(bytes)
+    }
+
+    fn push<T: Serialize>(&mut self, item: &T) -> io::Result<()> {
+        self.write(&item.serialize())
+    }
+}
+
+pub(crate) fn decode(data: &[u8]) -> Result<Vec<TreeImage>> {
+    if data.len() < MAGIC.len() + 4 || !data.starts_with(MAGIC) {
+        return Err(Error::Unsupported(
+            "the bytes are not a database image created by Db::to_bytes"
+                .to_owned(),
+        ));
+    }
+
+    let (body, crc_bytes) = data.split_at(data.len() - 4);
+    let crc = u32::from_le_bytes(crc_bytes.try_into().unwrap());
+    if crc32(body) != crc {
+        return Err(Error::corruption(None));
+    }
+
+    let mut buf = &body[MAGIC.len()..];
+    let tree_count = u64::deserialize(&mut buf)?;
+    let mut trees = vec![];
+    for _ in 0..tree_count {
+        let name = pull(&mut buf)?;
+        let item_count = u64::deserialize(&mut buf)?;
+        let mut items = vec![];
+        for _ in 0..item_count {
+            let k = pull(&mut buf)?;
+            let v = pull(&mut buf)?;
+            items.push((k, v));
+        }
+        trees.push((name, items));
+    }
+
+    if !buf.is_empty() {
+        return Err(Error::corruption(None));
+    }
+
+    Ok(trees)
+}
+
+/// Reads a byte string, failing instead of panicking like
+/// `IVec::deserialize` if its length runs past the end.
+fn pull(buf: &mut &[u8]) -> Result<IVec> {
+    let len = u64::deserialize(buf)?;
+    if len > buf.len() as u64 {
+        return Err(Error::corruption(None));
+    }
+    let (bytes, rest) = buf.split_at(usize::try_from(len).unwrap());
+    *buf = rest;
+    Ok(IVec::from(bytes))
+}
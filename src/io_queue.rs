@@ -0,0 +1,73 @@
+//! The queue of background IO of a database, such as writes
+//! of the log and truncations of segments, which runs on at
+//! most `Config::io_threads` threads of the threadpool at a
+//! time.
+
+use std::collections::VecDeque;
+
+use crate::*;
+
+type Work = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Default)]
+pub(crate) struct IoQueue {
+    /// The work waiting to run, and the number of threads
+    /// that are running it.
+    state: Mutex<(VecDeque<Work>, usize)>,
+}
+
+impl Debug for IoQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock();
+        f.debug_struct("IoQueue")
+            .field("waiting", &state.0.len())
+            .field("threads", &state.1)
+            .finish()
+    }
+}
+
+impl IoQueue {
+    /// Queues `work`, and starts another thread to run the
+    /// queue unless `threads` are running it already. Each
+    /// write of the log is a separate piece of work, and
+    /// writes go to different offsets of the file, so the
+    /// threads don't contend with each other.
+    pub(crate) fn spawn<F, R>(
+        queue: &Arc<IoQueue>,
+        threads: usize,
+        work: F,
+    ) -> OneShot<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (promise_filler, promise) = OneShot::pair();
+
+        let mut state = queue.state.lock();
+        state.0.push_back(Box::new(move || promise_filler.fill(work())));
+        if state.1 < threads {
+            state.1 += 1;
+            drop(state);
+            let runner = queue.clone();
+            let _result = threadpool::spawn(move || runner.run());
+        }
+
+        promise
+    }
+
+    /// Runs work until the queue is empty.
+    fn run(&self) {
+        loop {
+            let work = {
+                let mut state = self.state.lock();
+                if let Some(work) = state.0.pop_front() {
+                    work
+                } else {
+                    state.1 -= 1;
+                    return;
+                }
+            };
+            (work)();
+        }
+    }
+}
@@ -25,6 +25,72 @@ fn possible_predecessor(s: &[u8]) -> Option<Vec<u8>> {
     }
 }
 
+/// Returns the smallest key that is greater than every key
+/// starting with `prefix`, which is the exclusive upper bound
+/// of a range over the keys with that prefix. This is `None`
+/// if there is no such key because the prefix is empty or
+/// consists only of `0xFF` bytes, in which case the range
+/// over the keys with that prefix is unbounded above.
+///
+/// Trailing `0xFF` bytes can not be incremented, so they are
+/// dropped before the last remaining byte is incremented,
+/// which carries like in addition: `[1, 0xFF]` gives `[2]`,
+/// and `[1, 0xFE, 0xFF]` gives `[1, 0xFF]`. Incrementing the
+/// last byte of `[1, 0xFF]` in place would wrap around to
+/// `[1, 0]`, which is less than every key with the prefix,
+/// and appending to it, such as `[1, 0xFF, 0xFF]`, leaves
+/// out longer keys like `[1, 0xFF, 0xFF, 0]`.
+///
+/// This is the bound that `Tree::scan_prefix` uses.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use sled::{prefix_upper_bound, Config, IVec};
+///
+/// assert_eq!(prefix_upper_bound(&[1, 2]), Some(vec![1, 3]));
+/// assert_eq!(prefix_upper_bound(&[1, 0xFF]), Some(vec![2]));
+/// assert_eq!(prefix_upper_bound(&[0xFF, 0xFF]), None);
+/// assert_eq!(prefix_upper_bound(&[]), None);
+///
+/// let config = Config::new().temporary(true);
+/// let t = config.open()?;
+/// t.insert(&[1, 0xFF], vec![])?;
+/// t.insert(&[1, 0xFF, 0xFF, 0], vec![])?;
+/// t.insert(&[2], vec![])?;
+///
+/// // every key starting with the prefix, in reverse
+/// let prefix: &[u8] = &[1, 0xFF];
+/// let end = prefix_upper_bound(prefix).unwrap();
+/// let mut r = t.range(prefix..&*end).rev();
+/// assert_eq!(r.next().unwrap()?.0, IVec::from(&[1, 0xFF, 0xFF, 0]));
+/// assert_eq!(r.next().unwrap()?.0, IVec::from(&[1, 0xFF]));
+/// assert!(r.next().is_none());
+/// # Ok(()) }
+/// ```
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut ret = prefix.to_vec();
+    while let Some(last) = ret.pop() {
+        if last < u8::max_value() {
+            ret.push(last + 1);
+            return Some(ret);
+        }
+    }
+    None
+}
+
+/// Whether `Tree::for_each_prefix` should go on to the next
+/// entry. This mirrors `std::ops::ControlFlow`, which is
+/// newer than the minimum Rust version that sled supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Go on to the next entry.
+    Continue,
+    /// Stop without visiting any more entries.
+    Break,
+}
+
 macro_rules! iter_try {
     ($e:expr) => {
         match $e {
@@ -35,15 +101,65 @@ macro_rules! iter_try {
 }
 
 /// An iterator over keys and values in a `Tree`.
+///
+/// It can be reversed with `rev`, which walks the `Tree`
+/// backwards from the end of the range without scanning
+/// it forward first. See `Tree::range_rev` for details.
 pub struct Iter {
     pub(super) tree: Tree,
     pub(super) hi: Bound<IVec>,
     pub(super) lo: Bound<IVec>,
     pub(super) cached_node: Option<(PageId, Node, Guard)>,
     pub(super) going_forward: bool,
+    pub(super) relaxed: bool,
 }
 
 impl Iter {
+    /// Returns an iterator that skips the coordination
+    /// that normally keeps it from observing half-applied
+    /// atomic multi-key writes, in exchange for less
+    /// overhead on each step. This is useful for large
+    /// analytical scans that do not need to reflect a
+    /// consistent state of the `Tree`.
+    ///
+    /// A relaxed iterator may witness the following
+    /// anomalies, in addition to those that are possible
+    /// with a normal iterator, which already makes no
+    /// point-in-time snapshot guarantees across keys:
+    ///
+    /// * only some of the writes of a `Batch` applied
+    ///   with `Tree::apply_batch`, or of a committed
+    ///   transaction, may be observed.
+    /// * writes of a transaction that is in the process
+    ///   of committing may be observed before the
+    ///   transaction returns.
+    ///
+    /// Every item returned is a key and value that was
+    /// actually present in the `Tree` at some point
+    /// during iteration. Values are never torn or
+    /// corrupted, only potentially stale, because the
+    /// underlying pages are never modified in place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![10])?;
+    /// t.insert(&[2], vec![20])?;
+    ///
+    /// let start: &[u8] = &[2];
+    /// let mut iter = t.range(start..).relaxed();
+    /// assert_eq!(&*iter.next().unwrap()?.0, &[2]);
+    /// assert_eq!(iter.next(), None);
+    /// # Ok(()) }
+    /// ```
+    pub fn relaxed(mut self) -> Self {
+        self.relaxed = true;
+        self
+    }
+
     /// Iterate over the keys of this Tree
     pub fn keys(self) -> impl DoubleEndedIterator<Item = Result<IVec>> {
         self.map(|r| r.map(|(k, _v)| k))
@@ -54,13 +170,52 @@ impl Iter {
         self.map(|r| r.map(|(_k, v)| v))
     }
 
+    /// Returns the position of this iterator, from which
+    /// `Tree::resume` continues later, even in another
+    /// process. The cursor holds the bounds of the keys that
+    /// are left, which start after the last key returned by
+    /// `next`, and end before the last key returned by
+    /// `next_back`, so it works in either direction. Call
+    /// `next_back` directly rather than going through `rev`
+    /// to keep access to this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::Cursor;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// for i in 0..10_u8 {
+    ///     db.insert([i], vec![])?;
+    /// }
+    ///
+    /// let mut page = db.iter();
+    /// let first: Vec<_> = page.by_ref().take(4).collect();
+    /// assert_eq!(first.len(), 4);
+    /// let token = page.cursor().to_bytes();
+    ///
+    /// // later, for the next page
+    /// let cursor = Cursor::from_bytes(&token)?;
+    /// let mut rest = db.resume(cursor);
+    /// assert_eq!(rest.next().unwrap()?.0, vec![4]);
+    /// # Ok(()) }
+    /// ```
+    pub fn cursor(&self) -> Cursor {
+        Cursor { lo: self.lo.clone(), hi: self.hi.clone() }
+    }
+
     fn bounds_collapsed(&self) -> bool {
         match (&self.lo, &self.hi) {
-            (Bound::Included(ref start), Bound::Included(ref end))
-            | (Bound::Included(ref start), Bound::Excluded(ref end))
+            (Bound::Included(ref start), Bound::Included(ref end)) => {
+                start > end
+            }
+            // stops at the end of a range that ends where a
+            // page does, without reading the next page
+            (Bound::Included(ref start), Bound::Excluded(ref end))
             | (Bound::Excluded(ref start), Bound::Included(ref end))
             | (Bound::Excluded(ref start), Bound::Excluded(ref end)) => {
-                start > end
+                start >= end
             }
             _ => false,
         }
@@ -81,6 +236,35 @@ impl Iter {
         }
     }
 
+    /// Like `next_inner`, but returns values in the form
+    /// users see them if the `Tree` deduplicates values.
+    pub(crate) fn next_resolved(
+        &mut self,
+    ) -> Option<<Self as Iterator>::Item> {
+        loop {
+            let (key, stored) = iter_try!(self.next_inner()?);
+            if let Some(item) = iter_try!(self.resolve(key, stored)) {
+                return Some(Ok(item));
+            }
+        }
+    }
+
+    /// Resolves a value that was read from a leaf, which may
+    /// be a reference to a shared value that has been
+    /// released since if the leaf is stale. The key is then
+    /// read again, and `None` is returned if it is gone.
+    fn resolve(
+        &self,
+        key: IVec,
+        stored: IVec,
+    ) -> Result<Option<(IVec, IVec)>> {
+        if self.tree.dedup.is_none() {
+            return Ok(Some((key, stored)));
+        }
+        let value = self.tree.resolve_current(&key, stored)?;
+        Ok(value.map(|v| (key, v)))
+    }
+
     pub(crate) fn next_inner(&mut self) -> Option<<Self as Iterator>::Item> {
         let (mut pid, mut node, guard) =
             if let (true, Some((pid, node, guard))) =
@@ -151,27 +335,9 @@ impl Iter {
             self.lo, self.tree
         );
     }
-}
-
-impl Iterator for Iter {
-    type Item = Result<(IVec, IVec)>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let _measure = Measure::new(&M.tree_scan);
-        let _cc = concurrency_control::read();
-        self.next_inner()
-    }
-
-    fn last(mut self) -> Option<Self::Item> {
-        self.next_back()
-    }
-}
 
-impl DoubleEndedIterator for Iter {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        let _measure = Measure::new(&M.tree_reverse_scan);
+    fn next_back_inner(&mut self) -> Option<<Self as Iterator>::Item> {
         let guard = pin();
-        let _cc = concurrency_control::read();
 
         let (mut pid, mut node, guard) =
             if let (false, Some((pid, node, guard))) =
@@ -243,6 +409,128 @@ impl DoubleEndedIterator for Iter {
     }
 }
 
+/// The position of an `Iter`, as returned by `Iter::cursor`,
+/// from which `Tree::resume` continues iterating. It holds
+/// the bounds of the keys that the iterator had left, so
+/// resuming returns the keys after the last key that was
+/// returned, as they are in the tree at that time, and not
+/// as they were when the cursor was taken.
+///
+/// Cursors do not hold on to the tree, and can be turned
+/// into bytes with `Cursor::to_bytes` to be handed out as
+/// pagination tokens. They contain the keys at which
+/// iteration stopped, so they reveal those keys to whoever
+/// holds them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub(crate) lo: Bound<IVec>,
+    pub(crate) hi: Bound<IVec>,
+}
+
+const CURSOR_VERSION: u8 = 1;
+
+impl Cursor {
+    /// Returns the cursor as bytes, which
+    /// `Cursor::from_bytes` reads back.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![CURSOR_VERSION];
+        for bound in &[&self.lo, &self.hi] {
+            let (tag, key) = match bound {
+                Bound::Unbounded => {
+                    buf.push(0);
+                    continue;
+                }
+                Bound::Included(key) => (1, key),
+                Bound::Excluded(key) => (2, key),
+            };
+            buf.push(tag);
+            let len = u64::try_from(key.len()).unwrap();
+            buf.extend_from_slice(&len.to_le_bytes());
+            buf.extend_from_slice(key);
+        }
+        buf
+    }
+
+    /// Reads a cursor from the bytes returned by
+    /// `Cursor::to_bytes`. Returns `Error::Unsupported` if
+    /// they are not such bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cursor> {
+        fn bound(buf: &mut &[u8]) -> Option<Bound<IVec>> {
+            let (tag, after_tag) = buf.split_first()?;
+            if *tag == 0 {
+                *buf = after_tag;
+                return Some(Bound::Unbounded);
+            }
+            if after_tag.len() < 8 {
+                return None;
+            }
+            let (len_bytes, after_len) = after_tag.split_at(8);
+            let mut len_array = [0; 8];
+            len_array.copy_from_slice(len_bytes);
+            let len = usize::try_from(u64::from_le_bytes(len_array)).ok()?;
+            if after_len.len() < len {
+                return None;
+            }
+            let (key, rest) = after_len.split_at(len);
+            *buf = rest;
+            match tag {
+                1 => Some(Bound::Included(IVec::from(key))),
+                2 => Some(Bound::Excluded(IVec::from(key))),
+                _ => None,
+            }
+        }
+
+        let parse = || {
+            let (version, mut buf) = bytes.split_first()?;
+            if *version != CURSOR_VERSION {
+                return None;
+            }
+            let lo = bound(&mut buf)?;
+            let hi = bound(&mut buf)?;
+            if buf.is_empty() { Some(Cursor { lo, hi }) } else { None }
+        };
+
+        parse().ok_or_else(|| {
+            Error::Unsupported(
+                "the bytes are not a cursor created by Cursor::to_bytes"
+                    .to_owned(),
+            )
+        })
+    }
+}
+
+impl Iterator for Iter {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let _measure = Measure::new(&M.tree_scan);
+        let _cc =
+            if self.relaxed { None } else { Some(concurrency_control::read()) };
+        let _scan = lru::scanning();
+        self.next_resolved()
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let _measure = Measure::new(&M.tree_reverse_scan);
+        let _cc =
+            if self.relaxed { None } else { Some(concurrency_control::read()) };
+        let _scan = lru::scanning();
+        loop {
+            let (key, stored) = iter_try!(self.next_back_inner()?);
+            if let Some(item) = iter_try!(self.resolve(key, stored)) {
+                return Some(Ok(item));
+            }
+        }
+    }
+}
+
+
 #[test]
 fn test_possible_predecessor() {
     assert_eq!(possible_predecessor(b""), None);
@@ -266,3 +554,60 @@ fn test_possible_predecessor() {
         Some(vec![154, 255, 255, 255, 255])
     );
 }
+
+#[test]
+fn test_prefix_upper_bound() {
+    // no upper bound
+    assert_eq!(prefix_upper_bound(b""), None);
+    assert_eq!(prefix_upper_bound(&[255]), None);
+    assert_eq!(prefix_upper_bound(&[255, 255]), None);
+    assert_eq!(prefix_upper_bound(&[255; 64]), None);
+
+    // no carry
+    assert_eq!(prefix_upper_bound(&[0]), Some(vec![1]));
+    assert_eq!(prefix_upper_bound(&[254]), Some(vec![255]));
+    assert_eq!(prefix_upper_bound(&[1, 2]), Some(vec![1, 3]));
+    assert_eq!(prefix_upper_bound(&[255, 0]), Some(vec![255, 1]));
+    assert_eq!(prefix_upper_bound(&[1, 0, 0]), Some(vec![1, 0, 1]));
+
+    // carry over trailing 255s
+    assert_eq!(prefix_upper_bound(&[1, 255]), Some(vec![2]));
+    assert_eq!(prefix_upper_bound(&[0, 255, 255]), Some(vec![1]));
+    assert_eq!(prefix_upper_bound(&[1, 254, 255, 255]), Some(vec![1, 255]));
+    assert_eq!(prefix_upper_bound(&[255, 0, 255]), Some(vec![255, 1]));
+
+    // the bound is above every key with the prefix and at
+    // or below every larger key without it
+    let alphabet = [0_u8, 1, 254, 255];
+    let mut keys = vec![vec![]];
+    for _ in 0..3 {
+        let longer: Vec<Vec<u8>> = keys
+            .iter()
+            .flat_map(|key| {
+                alphabet.iter().map(move |byte| {
+                    let mut longer = key.clone();
+                    longer.push(*byte);
+                    longer
+                })
+            })
+            .collect();
+        keys.extend(longer);
+    }
+    keys.sort();
+    keys.dedup();
+
+    for prefix in &keys {
+        let bound = prefix_upper_bound(prefix);
+        for key in &keys {
+            let below = match bound {
+                Some(ref end) => key < end,
+                None => true,
+            };
+            if key.starts_with(prefix) {
+                assert!(below, "{:?} {:?} {:?}", prefix, key, bound);
+            } else if key > prefix {
+                assert!(!below, "{:?} {:?} {:?}", prefix, key, bound);
+            }
+        }
+    }
+}
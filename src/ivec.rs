@@ -3,7 +3,9 @@ use std::{
     fmt,
     hash::{Hash, Hasher},
     iter::FromIterator,
+    mem::size_of,
     ops::{Deref, DerefMut},
+    sync::Arc as StdArc,
 };
 
 use crate::Arc;
@@ -14,6 +16,21 @@ type Inner = [u8; CUTOFF];
 
 /// A buffer that may either be inline or remote and protected
 /// by an Arc
+///
+/// Buffers of up to `IVec::INLINE_CAPACITY` bytes are stored
+/// inline, without allocating, when they are created from a
+/// slice, a `Vec` or an `Arc`. `IVec::subslice` of a buffer
+/// on the heap shares its allocation instead, even if the
+/// subslice is small. Use `IVec::is_inline` to check how an
+/// `IVec` is stored, and `IVec::from_inline` to create one
+/// that is guaranteed not to allocate.
+///
+/// Larger buffers created from a `std::sync::Arc<[u8]>`, or
+/// from a `bytes::Bytes` with the `bytes` feature enabled,
+/// share the allocation that they came from rather than
+/// copying it. Buffers created from a slice or a `Vec` are
+/// copied into an allocation that sled manages, because
+/// its reference count is stored in front of the bytes.
 #[derive(Clone)]
 pub struct IVec(IVecInner);
 
@@ -23,10 +40,35 @@ impl Default for IVec {
     }
 }
 
+// The variants that would be larger than `Remote` are kept
+// behind a single pointer, so that an `IVec` stays 24 bytes.
 #[derive(Clone)]
 enum IVecInner {
     Inline(u8, Inner),
     Remote(Arc<[u8]>),
+    Subslice(Arc<Slice<Arc<[u8]>>>),
+    Shared(Arc<Slice<StdArc<[u8]>>>),
+    #[cfg(feature = "bytes")]
+    Bytes(Arc<bytes::Bytes>),
+}
+
+/// The bytes `base..base + len` of a buffer that may be
+/// shared with other `IVec`s.
+#[derive(Clone)]
+struct Slice<T> {
+    base: usize,
+    len: usize,
+    data: T,
+}
+
+impl<T: AsRef<[u8]>> Slice<T> {
+    fn new(base: usize, len: usize, data: T) -> Arc<Self> {
+        Arc::new(Self { base, len, data })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.data.as_ref()[self.base..self.base + self.len]
+    }
 }
 
 impl Hash for IVec {
@@ -40,6 +82,46 @@ const fn is_inline_candidate(length: usize) -> bool {
 }
 
 impl IVec {
+    /// The largest number of bytes that an `IVec` stores
+    /// inline, without a heap allocation. This is fixed by
+    /// the size of the `IVec` itself, which is 24 bytes on
+    /// 64-bit platforms, and is not configurable.
+    pub const INLINE_CAPACITY: usize = CUTOFF;
+
+    /// Creates an `IVec` that stores `bytes` inline, or
+    /// returns `None` if they are longer than
+    /// `IVec::INLINE_CAPACITY`. This never allocates.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::IVec;
+    ///
+    /// let small = IVec::from_inline(b"tiny").unwrap();
+    /// assert!(small.is_inline());
+    ///
+    /// let large = vec![0; IVec::INLINE_CAPACITY + 1];
+    /// assert_eq!(IVec::from_inline(&large), None);
+    /// ```
+    pub fn from_inline(bytes: &[u8]) -> Option<Self> {
+        if is_inline_candidate(bytes.len()) {
+            Some(Self::inline(bytes))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the bytes are stored inline rather
+    /// than on the heap. Cloning an inline `IVec` copies it,
+    /// and cloning one on the heap increments a reference
+    /// count, so neither allocates.
+    pub fn is_inline(&self) -> bool {
+        match self.0 {
+            IVecInner::Inline(..) => true,
+            _ => false,
+        }
+    }
+
     fn inline(slice: &[u8]) -> Self {
         assert!(is_inline_candidate(slice.len()));
 
@@ -61,11 +143,119 @@ impl IVec {
         Self(IVecInner::Remote(arc))
     }
 
+    /// Returns an `IVec` of `len` bytes starting at `offset`
+    /// within this one. If this `IVec` is stored on the heap,
+    /// the returned `IVec` shares its allocation instead of
+    /// copying the bytes, which keeps the whole allocation
+    /// alive for as long as either of them exists. Small
+    /// inline values are copied, which is just as cheap.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset + len` is greater than the length
+    /// of this `IVec`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::IVec;
+    ///
+    /// let composite = IVec::from(vec![7; 64]);
+    /// let part = composite.subslice(8, 16);
+    /// assert_eq!(part, vec![7; 16]);
+    /// ```
+    pub fn subslice(&self, offset: usize, len: usize) -> Self {
+        assert!(
+            offset <= self.len() && len <= self.len() - offset,
+            "subslice with offset {} and length {} is out of range \
+             for IVec of length {}",
+            offset,
+            len,
+            self.len()
+        );
+
+        let inner = match self.0 {
+            IVecInner::Inline(_, ref buf) => {
+                let mut data = Inner::default();
+                data[..len].copy_from_slice(&buf[offset..offset + len]);
+                IVecInner::Inline(u8::try_from(len).unwrap(), data)
+            }
+            IVecInner::Remote(ref data) => {
+                IVecInner::Subslice(Slice::new(offset, len, data.clone()))
+            }
+            IVecInner::Subslice(ref slice) => IVecInner::Subslice(Slice::new(
+                slice.base + offset,
+                len,
+                slice.data.clone(),
+            )),
+            IVecInner::Shared(ref slice) => IVecInner::Shared(Slice::new(
+                slice.base + offset,
+                len,
+                slice.data.clone(),
+            )),
+            #[cfg(feature = "bytes")]
+            IVecInner::Bytes(ref data) => {
+                IVecInner::Bytes(Arc::new(data.slice(offset..offset + len)))
+            }
+        };
+
+        Self(inner)
+    }
+
+    /// Returns the number of bytes that this `IVec` keeps
+    /// allocated on the heap, including the reference count,
+    /// which is 0 if it is stored inline. A subslice counts
+    /// the whole allocation that it keeps alive, except for
+    /// a `bytes::Bytes`, whose allocation can't be seen.
+    pub(crate) fn heap_size(&self) -> u64 {
+        let allocated = match self.0 {
+            IVecInner::Inline(..) => return 0,
+            IVecInner::Remote(ref data) => data.len() + size_of::<usize>(),
+            IVecInner::Subslice(ref slice) => {
+                slice.data.len()
+                    + size_of::<Slice<Arc<[u8]>>>()
+                    + 2 * size_of::<usize>()
+            }
+            // strong and weak counts
+            IVecInner::Shared(ref slice) => {
+                slice.data.len()
+                    + size_of::<Slice<StdArc<[u8]>>>()
+                    + 3 * size_of::<usize>()
+            }
+            #[cfg(feature = "bytes")]
+            IVecInner::Bytes(ref data) => {
+                data.len() + size_of::<bytes::Bytes>() + size_of::<usize>()
+            }
+        };
+        u64::try_from(allocated).unwrap()
+    }
+
     fn make_mut(&mut self) {
         match self.0 {
             IVecInner::Remote(ref mut buf) if Arc::strong_count(buf) != 1 => {
                 self.0 = IVecInner::Remote(buf.to_vec().into());
             }
+            IVecInner::Subslice(ref slice)
+                if Arc::strong_count(slice) != 1
+                    || Arc::strong_count(&slice.data) != 1 =>
+            {
+                let bytes = slice.bytes();
+                *self = if is_inline_candidate(bytes.len()) {
+                    Self::inline(bytes)
+                } else {
+                    Self::remote(bytes.into())
+                };
+            }
+            // shared buffers are copied unless this is the only
+            // reference, and `Bytes` are always copied because
+            // they are immutable
+            IVecInner::Shared(ref slice)
+                if Arc::strong_count(slice) == 1
+                    && StdArc::strong_count(&slice.data) == 1
+                    && StdArc::weak_count(&slice.data) == 0 => {}
+            IVecInner::Shared(_) => *self = Self::from(self.as_ref()),
+            #[cfg(feature = "bytes")]
+            IVecInner::Bytes(_) => *self = Self::from(self.as_ref()),
             _ => {}
         }
     }
@@ -101,6 +291,31 @@ impl From<Arc<[u8]>> for IVec {
     }
 }
 
+/// Shares the `Arc`'s allocation without copying it, unless
+/// the bytes are short enough to be stored inline.
+impl From<StdArc<[u8]>> for IVec {
+    fn from(arc: StdArc<[u8]>) -> Self {
+        if is_inline_candidate(arc.len()) {
+            Self::inline(&arc)
+        } else {
+            Self(IVecInner::Shared(Slice::new(0, arc.len(), arc)))
+        }
+    }
+}
+
+/// Shares the `Bytes`' allocation without copying it, unless
+/// the bytes are short enough to be stored inline.
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for IVec {
+    fn from(bytes: bytes::Bytes) -> Self {
+        if is_inline_candidate(bytes.len()) {
+            Self::inline(&bytes)
+        } else {
+            Self(IVecInner::Bytes(Arc::new(bytes)))
+        }
+    }
+}
+
 impl From<&str> for IVec {
     fn from(s: &str) -> Self {
         Self::from(s.as_bytes())
@@ -158,8 +373,13 @@ from_array!(
 impl Into<Arc<[u8]>> for IVec {
     fn into(self) -> Arc<[u8]> {
         match self.0 {
-            IVecInner::Inline(..) => Arc::from(self.as_ref()),
             IVecInner::Remote(arc) => arc,
+            IVecInner::Subslice(ref slice)
+                if slice.base == 0 && slice.len == slice.data.len() =>
+            {
+                slice.data.clone()
+            }
+            _ => Arc::from(self.as_ref()),
         }
     }
 }
@@ -182,6 +402,10 @@ impl AsRef<[u8]> for IVec {
                 buf.get_unchecked(..*sz as usize)
             },
             IVecInner::Remote(buf) => buf,
+            IVecInner::Subslice(slice) => slice.bytes(),
+            IVecInner::Shared(slice) => slice.bytes(),
+            #[cfg(feature = "bytes")]
+            IVecInner::Bytes(data) => data,
         }
     }
 }
@@ -200,10 +424,22 @@ impl AsMut<[u8]> for IVec {
         self.make_mut();
 
         match self.0 {
-            IVecInner::Inline(ref sz, mut buf) => unsafe {
-                std::slice::from_raw_parts_mut(buf.as_mut_ptr(), *sz as usize)
+            IVecInner::Inline(ref sz, ref mut buf) => unsafe {
+                buf.get_unchecked_mut(..*sz as usize)
             },
             IVecInner::Remote(ref mut buf) => Arc::get_mut(buf).unwrap(),
+            IVecInner::Subslice(ref mut slice) => {
+                let Slice { base, len, ref mut data } =
+                    *Arc::get_mut(slice).unwrap();
+                &mut Arc::get_mut(data).unwrap()[base..base + len]
+            }
+            IVecInner::Shared(ref mut slice) => {
+                let Slice { base, len, ref mut data } =
+                    *Arc::get_mut(slice).unwrap();
+                &mut StdArc::get_mut(data).unwrap()[base..base + len]
+            }
+            #[cfg(feature = "bytes")]
+            IVecInner::Bytes(_) => unreachable!(),
         }
     }
 }
@@ -240,6 +476,63 @@ impl fmt::Debug for IVec {
     }
 }
 
+/// Serialized as a byte array, which binary formats store
+/// as a length followed by the bytes, and formats without
+/// a byte array type, like JSON, as a sequence of numbers.
+#[cfg(feature = "serde")]
+impl serde::Serialize for IVec {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(self)
+    }
+}
+
+/// Accepts byte arrays and sequences of bytes, so it reads
+/// back whatever `Serialize` wrote in any format.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for IVec {
+    fn deserialize<D>(deserializer: D) -> Result<IVec, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(IVecVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct IVecVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for IVecVisitor {
+    type Value = IVec;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a byte array")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<IVec, E> {
+        Ok(IVec::from(v))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<IVec, E> {
+        Ok(IVec::from(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<IVec, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let size_hint = seq.size_hint().unwrap_or(0);
+        let mut bytes = Vec::with_capacity(size_hint.min(4096));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(IVec::from(bytes))
+    }
+}
+
 #[test]
 fn ivec_usage() {
     let iv1 = IVec::from(vec![1, 2, 3]);
@@ -247,3 +540,159 @@ fn ivec_usage() {
     let iv2 = IVec::from(&[4; 128][..]);
     assert_eq!(iv2, vec![4; 128]);
 }
+
+#[test]
+fn ivec_subslice() {
+    let bytes: Vec<u8> = (0..100).collect();
+    let iv = IVec::from(bytes.clone());
+
+    let sub = iv.subslice(10, 50);
+    assert_eq!(sub, &bytes[10..60]);
+    if let IVecInner::Subslice(ref slice) = sub.0 {
+        assert_eq!(Arc::strong_count(&slice.data), 2);
+    } else {
+        panic!("heap-backed subslices should share the allocation");
+    }
+
+    let subsub = sub.subslice(5, 10);
+    assert_eq!(subsub, &bytes[15..25]);
+    assert_eq!(iv.subslice(100, 0), &[]);
+    assert_eq!(iv.subslice(0, 100), bytes);
+
+    let inline = IVec::from(&bytes[..8]);
+    assert_eq!(inline.subslice(2, 3), &bytes[2..5]);
+
+    // mutating a shared subslice copies it first
+    let mut mutated = sub.clone();
+    mutated[0] = 255;
+    assert_eq!(mutated[0], 255);
+    assert_eq!(sub[0], 10);
+    assert_eq!(iv[10], 10);
+
+    let arc: Arc<[u8]> = sub.into();
+    assert_eq!(&*arc, &bytes[10..60]);
+}
+
+#[test]
+fn ivec_from_std_arc() {
+    let bytes: Vec<u8> = (0..100).collect();
+    let arc: StdArc<[u8]> = bytes.clone().into();
+    let iv = IVec::from(arc.clone());
+    assert!(!iv.is_inline());
+    assert_eq!(iv.as_ptr(), arc.as_ptr());
+    assert_eq!(StdArc::strong_count(&arc), 2);
+
+    let sub = iv.subslice(10, 50);
+    assert_eq!(sub, &bytes[10..60]);
+    assert_eq!(sub.as_ptr(), arc[10..].as_ptr());
+    assert_eq!(StdArc::strong_count(&arc), 3);
+
+    // mutating a shared buffer copies it first
+    let mut mutated = sub.clone();
+    mutated[0] = 255;
+    assert_eq!(mutated[0], 255);
+    assert_eq!(arc[10], 10);
+
+    // the only reference is mutated in place
+    drop((iv, sub));
+    let ptr = arc.as_ptr();
+    let mut unique = IVec::from(arc);
+    unique[0] = 255;
+    assert_eq!(unique.as_ptr(), ptr);
+
+    let small: StdArc<[u8]> = bytes[..8].into();
+    assert!(IVec::from(small).is_inline());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn ivec_from_bytes() {
+    let data: Vec<u8> = (0..100).collect();
+    let bytes = bytes::Bytes::from(data.clone());
+    let iv = IVec::from(bytes.clone());
+    assert!(!iv.is_inline());
+    assert_eq!(iv.as_ptr(), bytes.as_ptr());
+
+    let sub = iv.subslice(10, 50);
+    assert_eq!(sub, &data[10..60]);
+    assert_eq!(sub.as_ptr(), bytes[10..].as_ptr());
+
+    let mut mutated = sub.clone();
+    mutated[0] = 255;
+    assert_eq!(mutated[0], 255);
+    assert_eq!(sub[0], 10);
+    assert_eq!(bytes[10], 10);
+
+    assert!(IVec::from(bytes.slice(..8)).is_inline());
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn ivec_size() {
+    assert_eq!(size_of::<IVec>(), 24);
+    assert_eq!(size_of::<Option<IVec>>(), 24);
+}
+
+#[test]
+fn ivec_inline() {
+    let max = vec![1; IVec::INLINE_CAPACITY];
+    assert!(IVec::from(max.clone()).is_inline());
+    assert!(IVec::from(&max[..]).is_inline());
+    assert!(IVec::from(Arc::<[u8]>::from(&max[..])).is_inline());
+    assert!(IVec::from_inline(&max).unwrap().is_inline());
+    assert!(IVec::default().is_inline());
+
+    let over = vec![1; IVec::INLINE_CAPACITY + 1];
+    assert!(!IVec::from(over.clone()).is_inline());
+    assert_eq!(IVec::from_inline(&over), None);
+
+    // a small subslice shares its allocation until it is
+    // mutated, and is then copied inline
+    let heap = IVec::from(vec![7; 100]);
+    let mut sub = heap.subslice(10, 4);
+    assert!(!sub.is_inline());
+    sub[0] = 0;
+    assert!(sub.is_inline());
+    assert_eq!(sub, vec![0, 7, 7, 7]);
+    assert_eq!(heap[10], 7);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn ivec_serde() {
+    let bytes: Vec<u8> = (0..=255).collect();
+    let ivecs = vec![
+        IVec::default(),
+        IVec::from(&bytes[..3]),
+        IVec::from(bytes.clone()),
+        IVec::from(bytes.clone()).subslice(100, 50),
+    ];
+
+    for iv in &ivecs {
+        let encoded = bincode::serialize(iv).unwrap();
+        // a u64 length followed by the bytes themselves
+        assert_eq!(encoded.len(), 8 + iv.len());
+        let from_bincode: IVec = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(&from_bincode, iv);
+
+        let json = serde_json::to_string(iv).unwrap();
+        let from_json: IVec = serde_json::from_str(&json).unwrap();
+        assert_eq!(&from_json, iv);
+    }
+
+    assert_eq!(serde_json::to_string(&ivecs[1]).unwrap(), "[0,1,2]");
+    assert_eq!(serde_json::to_string(&ivecs[0]).unwrap(), "[]");
+
+    // nested in other types
+    let nested: Vec<Option<IVec>> = vec![Some(ivecs[2].clone()), None];
+    let encoded = bincode::serialize(&nested).unwrap();
+    let decoded: Vec<Option<IVec>> = bincode::deserialize(&encoded).unwrap();
+    assert_eq!(decoded, nested);
+}
+
+#[test]
+#[should_panic(expected = "out of range")]
+fn ivec_subslice_out_of_range() {
+    let iv = IVec::from(vec![0; 64]);
+    let _ = iv.subslice(60, 5);
+}
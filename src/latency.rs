@@ -0,0 +1,103 @@
+//! Latency histograms that are collected on request, see
+//! `Config::collect_latency_histograms`.
+
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+/// Latency percentiles of one kind of operation since the
+/// `Db` was opened, as returned by `Db::latency_stats`.
+/// Latencies are bucketed logarithmically, so each
+/// percentile is within about 1% of a measured latency.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    /// The number of operations that were measured.
+    pub count: u64,
+    /// The median latency.
+    pub p50: Duration,
+    /// The 90th percentile latency.
+    pub p90: Duration,
+    /// The 99th percentile latency.
+    pub p99: Duration,
+    /// The 99.9th percentile latency.
+    pub p999: Duration,
+    /// The highest latency.
+    pub max: Duration,
+}
+
+/// The latency distributions of operations on a `Db` and
+/// all of its trees, as returned by `Db::latency_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Calls to `Tree::get`.
+    pub get: LatencyPercentiles,
+    /// Calls to `Tree::insert`.
+    pub insert: LatencyPercentiles,
+    /// Calls to `Tree::flush` and `Tree::flush_async`.
+    pub flush: LatencyPercentiles,
+}
+
+#[derive(Default, Debug)]
+pub(crate) struct LatencyHistograms {
+    pub(crate) get: Histogram,
+    pub(crate) insert: Histogram,
+    pub(crate) flush: Histogram,
+}
+
+impl LatencyHistograms {
+    pub(crate) fn stats(&self) -> LatencyStats {
+        LatencyStats {
+            get: percentiles(&self.get),
+            insert: percentiles(&self.insert),
+            flush: percentiles(&self.flush),
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn percentiles(histogram: &Histogram) -> LatencyPercentiles {
+    let count = u64::try_from(histogram.count()).unwrap();
+    if count == 0 {
+        return LatencyPercentiles::default();
+    }
+
+    let at = |p| {
+        Duration::from_nanos(histogram.recorded_percentile(p).round() as u64)
+    };
+
+    LatencyPercentiles {
+        count,
+        p50: at(50.),
+        p90: at(90.),
+        p99: at(99.),
+        p999: at(99.9),
+        max: at(100.),
+    }
+}
+
+/// Records the time from its creation until it is dropped,
+/// if it was given a histogram to record it in.
+pub(crate) struct LatencyTimer<'a> {
+    started: Option<(&'a Histogram, Instant)>,
+}
+
+impl<'a> LatencyTimer<'a> {
+    #[inline]
+    pub(crate) fn new(histogram: Option<&'a Histogram>) -> LatencyTimer<'a> {
+        LatencyTimer {
+            started: histogram.map(|target| (target, Instant::now())),
+        }
+    }
+}
+
+impl<'a> Drop for LatencyTimer<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some((histogram, start)) = self.started {
+            let nanos = u64::try_from(start.elapsed().as_nanos())
+                .unwrap_or(u64::max_value());
+            histogram.record(nanos);
+        }
+    }
+}
@@ -176,29 +176,52 @@ macro_rules! testing_assert {
 mod arc;
 mod batch;
 mod binary_search;
+mod checksum;
+mod clock;
 mod concurrency_control;
 mod config;
 mod context;
+pub mod crdt;
 mod db;
+mod dedup;
+mod diff;
 mod dll;
+mod entry;
+mod eviction;
 mod fastcmp;
 mod fastlock;
+mod files;
+mod flush_hooks;
+mod free_space;
+mod group_by;
 mod histogram;
+mod image;
+mod io_queue;
 mod iter;
 mod ivec;
+mod latency;
 mod lazy;
 mod lru;
 mod meta;
 mod metrics;
 mod node;
 mod oneshot;
+mod ordered_key;
 mod pagecache;
+mod pinned_value;
 mod prefix;
+mod quarantine;
+mod repair;
 mod result;
 mod serialization;
+mod snapshots;
+mod space_report;
+#[cfg(feature = "debug")]
+mod structure_report;
 mod stack;
 mod subscriber;
 mod sys_limits;
+mod tail;
 pub mod transaction;
 mod tree;
 
@@ -291,15 +314,40 @@ pub use {
 };
 
 pub use self::{
-    batch::Batch,
-    config::{Config, Mode},
-    db::{open, Db},
-    iter::Iter,
+    batch::{Batch, MultiTreeBatch},
+    clock::{Clock, VirtualClock},
+    config::{
+        CacheMode, ChecksumAlgorithm, Config, Mode, OnFull, RecoveryPolicy,
+        SyncStrategy,
+    },
+    db::{open, Db, DroppedTreeInfo, FlushProgress, FlushReport, LostRange},
+    diff::{Diff, DiffIter},
+    entry::Entry,
+    eviction::EvictionPolicy,
+    flush_hooks::FlushInfo,
+    group_by::{GroupBy, GroupIter},
+    iter::{prefix_upper_bound, ControlFlow, Cursor, Iter},
     ivec::IVec,
+    latency::{LatencyPercentiles, LatencyStats},
+    ordered_key::OrderedKey,
+    pagecache::IoBackend,
+    pinned_value::PinnedValue,
+    repair::{RepairOptions, RepairReport},
     result::{Error, Result},
-    subscriber::{Event, Subscriber},
+    snapshots::SnapshotId,
+    space_report::{PrefixSpace, SizeHistogram, SpaceReport},
+    subscriber::{BatchedSubscriber, Event, EventFilter, Subscriber},
+    tail::TailIter,
     transaction::Transactional,
-    tree::{CompareAndSwapError, Tree},
+    tree::{
+        CasOp, CompareAndSwapError, Durability, MultiCasError, Tree,
+        Version, VersionMismatch,
+    },
+};
+
+#[cfg(feature = "debug")]
+pub use self::structure_report::{
+    SegmentState, SegmentStructure, StructureReport, TreeStructure,
 };
 
 use {
@@ -308,14 +356,22 @@ use {
         binary_search::binary_search_lub,
         concurrency_control::Protector,
         context::Context,
+        dedup::{Dedup, DEDUP_TREE_ID},
+        eviction::Eviction,
         fastcmp::fastcmp,
+        flush_hooks::FlushHooks,
+        free_space::{is_no_space, DiskFull, FreeSpace},
         histogram::Histogram,
+        io_queue::IoQueue,
+        latency::{LatencyHistograms, LatencyTimer},
         lru::Lru,
         meta::Meta,
         metrics::{clock, Measure, M},
         node::{Data, Node},
         oneshot::{OneShot, OneShotFiller},
         result::CasResult,
+        snapshots::{Snapshots, Tenants},
+        space_report::{SampleRng, SpaceReportBuilder},
         subscriber::Subscribers,
         tree::TreeInner,
     },
@@ -366,14 +422,6 @@ fn crc32(buf: &[u8]) -> u32 {
     hasher.finalize()
 }
 
-fn calculate_message_crc32(header: &[u8], body: &[u8]) -> u32 {
-    let mut hasher = crc32fast::Hasher::new();
-    hasher.update(body);
-    hasher.update(&header[4..]);
-    let crc32 = hasher.finalize();
-    crc32 ^ 0xFFFF_FFFF
-}
-
 #[cfg(any(test, feature = "lock_free_delays"))]
 mod debug_delay;
 
@@ -390,8 +438,9 @@ const fn debug_delay() {}
 /// key addition or removal.
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Link {
-    /// A new value is set for a given key
-    Set(IVec, IVec),
+    /// A new value is set for a given key, along with
+    /// the value's version
+    Set(IVec, IVec, u64),
     /// The associated value is removed for a given key
     Del(IVec),
     /// A child of this Index node is marked as mergable
@@ -1,5 +1,6 @@
 #![allow(unsafe_code)]
 
+use std::cell::Cell;
 use std::convert::TryFrom;
 use std::ptr;
 use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
@@ -8,7 +9,7 @@ use crate::{
     debug_delay,
     dll::{DoublyLinkedList, Node},
     fastlock::FastLock,
-    Guard, PageId,
+    CacheMode, Guard, PageId,
 };
 
 #[cfg(any(test, feature = "lock_free_delays"))]
@@ -23,6 +24,28 @@ const N_SHARDS: usize = 2;
 #[cfg(not(any(test, feature = "lock_free_delays")))]
 const N_SHARDS: usize = 256;
 
+thread_local! {
+    static SCANNING: Cell<bool> = Cell::new(false);
+}
+
+/// Marks the pages that this thread accesses as read by a
+/// scan until the returned guard is dropped, so that
+/// `CacheMode::ScanResistant` does not keep them cached at
+/// the expense of the pages that other operations use.
+pub(crate) fn scanning() -> ScanGuard {
+    ScanGuard(SCANNING.with(|scanning| scanning.replace(true)))
+}
+
+/// Restores the previous scan flag of the thread when dropped.
+pub(crate) struct ScanGuard(bool);
+
+impl Drop for ScanGuard {
+    fn drop(&mut self) {
+        let previous = self.0;
+        SCANNING.with(|scanning| scanning.set(previous));
+    }
+}
+
 struct AccessBlock {
     len: AtomicUsize,
     block: [AtomicU64; MAX_QUEUE_ITEMS],
@@ -186,32 +209,37 @@ impl<'a> Iterator for CacheAccessIter<'a> {
 struct CacheAccess(u64);
 
 impl CacheAccess {
-    fn new(pid: PageId, sz: u64) -> CacheAccess {
+    fn new(pid: PageId, sz: u64, scan: bool) -> CacheAccess {
         let rounded_up_power_of_2 =
             u64::from(sz.next_power_of_two().trailing_zeros());
 
-        assert!(rounded_up_power_of_2 < 256);
+        assert!(rounded_up_power_of_2 < 128);
 
-        CacheAccess(pid | (rounded_up_power_of_2 << 56))
+        CacheAccess(
+            pid | (rounded_up_power_of_2 << 56) | (u64::from(scan) << 63),
+        )
     }
 
-    const fn decompose(self) -> (PageId, u64) {
-        let sz = 1 << (self.0 >> 56);
+    const fn decompose(self) -> (PageId, u64, bool) {
+        let sz = 1 << ((self.0 >> 56) & 0x7F);
         let pid = self.0 << 8 >> 8;
-        (pid, sz)
+        let scan = self.0 >> 63 == 1;
+        (pid, sz, scan)
     }
 }
 
 /// A simple LRU cache.
 pub struct Lru {
     shards: Vec<(AccessQueue, FastLock<Shard>)>,
+    resident: AtomicU64,
+    capacity: AtomicU64,
 }
 
 unsafe impl Sync for Lru {}
 
 impl Lru {
     /// Instantiates a new `Lru` cache.
-    pub(crate) fn new(cache_capacity: u64) -> Self {
+    pub(crate) fn new(cache_capacity: u64, mode: CacheMode) -> Self {
         assert!(
             cache_capacity >= 256,
             "Please configure the cache \
@@ -221,10 +249,63 @@ impl Lru {
 
         let mut shards = Vec::with_capacity(N_SHARDS);
         shards.resize_with(N_SHARDS, || {
-            (AccessQueue::default(), FastLock::new(Shard::new(shard_capacity)))
+            let shard = Shard::new(shard_capacity, mode);
+            (AccessQueue::default(), FastLock::new(shard))
         });
 
-        Self { shards }
+        Self {
+            shards,
+            resident: AtomicU64::new(0),
+            capacity: AtomicU64::new(cache_capacity),
+        }
+    }
+
+    /// Returns the capacity of the cache, as passed to `new`
+    /// or to the last call of `set_capacity`.
+    pub(crate) fn capacity(&self) -> u64 {
+        self.capacity.load(Ordering::Acquire)
+    }
+
+    /// Changes the capacity of the cache, returning the items
+    /// to be evicted because they no longer fit. Unlike
+    /// `accessed`, this waits for each shard to be unlocked.
+    pub(crate) fn set_capacity(&self, cache_capacity: u64) -> Vec<PageId> {
+        assert!(
+            cache_capacity >= 256,
+            "Please configure the cache \
+             capacity to be at least 256 bytes"
+        );
+        self.capacity.store(cache_capacity, Ordering::Release);
+        let shard_capacity = cache_capacity / N_SHARDS as u64;
+
+        let mut ret = vec![];
+        let shards = self.shards.len() as u64;
+        for (shard_idx, (_, shard_mu)) in self.shards.iter().enumerate() {
+            let mut shard = loop {
+                if let Some(shard) = shard_mu.try_lock() {
+                    break shard;
+                }
+                std::thread::yield_now();
+            };
+
+            let size_before = shard.size;
+            for pos in shard.set_capacity(shard_capacity) {
+                ret.push((pos * shards) + shard_idx as u64);
+            }
+            let size_after = shard.size;
+            self.resident.fetch_sub(size_before - size_after, Ordering::AcqRel);
+        }
+        ret
+    }
+
+    /// Returns the total size of the items that the cache
+    /// currently accounts for, which never exceeds its
+    /// capacity. Each size is rounded up to the next power
+    /// of two, and accesses are counted in batches, so the
+    /// most recent accesses to each shard may not be
+    /// reflected yet.
+    pub(crate) fn resident_bytes(&self) -> u64 {
+        self.resident.load(Ordering::Acquire)
     }
 
     /// Called when an item is accessed. Returns a Vec of items to be
@@ -247,22 +328,35 @@ impl Lru {
         let (shard_idx, item_pos) = (id % shards, id / shards);
         let (stack, shard_mu) = &self.shards[safe_usize(shard_idx)];
 
-        let filled = stack.push(CacheAccess::new(item_pos, item_size));
+        let scan = SCANNING.with(Cell::get);
+        let filled = stack.push(CacheAccess::new(item_pos, item_size, scan));
 
         if filled {
             // only try to acquire this if
             if let Some(mut shard) = shard_mu.try_lock() {
+                let size_before = shard.size;
                 let accesses = stack.take(guard);
                 for item in accesses {
-                    let (item_pos, item_size) = item.decompose();
-                    let to_evict =
-                        shard.accessed(safe_usize(item_pos), item_size);
+                    let (item_pos, item_size, scanned) = item.decompose();
+                    let to_evict = shard.accessed(
+                        safe_usize(item_pos),
+                        item_size,
+                        scanned,
+                    );
                     // map shard internal offsets to global items ids
                     for pos in to_evict {
                         let item = (pos * shards) + shard_idx;
                         ret.push(item);
                     }
                 }
+                let size_after = shard.size;
+                if size_after >= size_before {
+                    self.resident
+                        .fetch_add(size_after - size_before, Ordering::AcqRel);
+                } else {
+                    self.resident
+                        .fetch_sub(size_before - size_after, Ordering::AcqRel);
+                }
             }
         }
         ret
@@ -273,35 +367,55 @@ impl Lru {
 struct Entry {
     ptr: *mut Node,
     size: u64,
+    protected: bool,
+    referenced: bool,
 }
 
 impl Default for Entry {
     fn default() -> Self {
-        Self { ptr: ptr::null_mut(), size: 0 }
+        Self {
+            ptr: ptr::null_mut(),
+            size: 0,
+            protected: false,
+            referenced: false,
+        }
     }
 }
 
+/// With `CacheMode::Lru`, every item is in `list`. With
+/// `CacheMode::ScanResistant`, `protected` holds the items
+/// that something other than a scan accessed more than once,
+/// including before they were last evicted, and `list`
+/// holds the rest.
 struct Shard {
     list: DoublyLinkedList,
+    protected: DoublyLinkedList,
     entries: Vec<Entry>,
     capacity: u64,
+    protected_capacity: u64,
     size: u64,
+    protected_size: u64,
+    mode: CacheMode,
 }
 
 impl Shard {
-    fn new(capacity: u64) -> Self {
+    fn new(capacity: u64, mode: CacheMode) -> Self {
         assert!(capacity > 0, "shard capacity must be non-zero");
 
         Self {
             list: DoublyLinkedList::default(),
+            protected: DoublyLinkedList::default(),
             entries: vec![],
             capacity,
+            protected_capacity: capacity / 5 * 4,
             size: 0,
+            protected_size: 0,
+            mode,
         }
     }
 
-    /// `PageId`s in the shard list are indexes of the entries.
-    fn accessed(&mut self, pos: usize, size: u64) -> Vec<PageId> {
+    /// `PageId`s in the shard lists are indexes of the entries.
+    fn accessed(&mut self, pos: usize, size: u64, scan: bool) -> Vec<PageId> {
         if pos >= self.entries.len() {
             self.entries.resize(pos + 1, Entry::default());
         }
@@ -310,32 +424,87 @@ impl Shard {
             let entry = &mut self.entries[pos];
 
             self.size -= entry.size;
-            entry.size = size;
             self.size += size;
+            if entry.protected {
+                self.protected_size -= entry.size;
+                self.protected_size += size;
+            }
+            entry.size = size;
 
-            if entry.ptr.is_null() {
+            let resistant = self.mode == CacheMode::ScanResistant;
+
+            if entry.ptr.is_null() && resistant && entry.referenced && !scan {
+                let pid = PageId::try_from(pos).unwrap();
+                entry.ptr = self.protected.push_head(pid);
+                entry.protected = true;
+                self.protected_size += size;
+            } else if entry.ptr.is_null() {
                 entry.ptr = self.list.push_head(PageId::try_from(pos).unwrap());
+            } else if entry.protected {
+                if !scan {
+                    entry.ptr = self.protected.promote(entry.ptr);
+                }
+            } else if resistant && !scan {
+                let pid = self.list.remove(entry.ptr);
+                entry.ptr = self.protected.push_head(pid);
+                entry.protected = true;
+                self.protected_size += size;
             } else {
                 entry.ptr = self.list.promote(entry.ptr);
             }
+
+            entry.referenced |= !scan;
         }
 
+        self.rebalance()
+    }
+
+    /// Changes the capacity of the shard, returning the items
+    /// that no longer fit.
+    fn set_capacity(&mut self, capacity: u64) -> Vec<PageId> {
+        assert!(capacity > 0, "shard capacity must be non-zero");
+
+        self.capacity = capacity;
+        self.protected_capacity = capacity / 5 * 4;
+        self.rebalance()
+    }
+
+    /// Brings the lists back within their capacities, returning
+    /// the items that were evicted.
+    fn rebalance(&mut self) -> Vec<PageId> {
+        // items that no longer fit in the protected list go
+        // back to the most recently used end of the other one
+        while self.protected_size > self.protected_capacity {
+            let demoted = self.protected.pop_tail().unwrap();
+            let entry = &mut self.entries[safe_usize(demoted)];
+
+            entry.ptr = self.list.push_head(demoted);
+            entry.protected = false;
+            self.protected_size -= entry.size;
+        }
+
+        // an item that is larger than the whole shard is
+        // evicted right away, so that the capacity is a
+        // hard limit even for very large items
         let mut to_evict = vec![];
         while self.size > self.capacity {
-            if self.list.len() == 1 {
-                // don't evict what we just added
-                break;
-            }
+            let min_pid = self
+                .list
+                .pop_tail()
+                .or_else(|| self.protected.pop_tail())
+                .unwrap();
+            let entry = &mut self.entries[safe_usize(min_pid)];
 
-            let min_pid = self.list.pop_tail().unwrap();
-            let min_pid_idx = safe_usize(min_pid);
-
-            self.entries[min_pid_idx].ptr = ptr::null_mut();
+            entry.ptr = ptr::null_mut();
 
             to_evict.push(min_pid);
 
-            self.size -= self.entries[min_pid_idx].size;
-            self.entries[min_pid_idx].size = 0;
+            self.size -= entry.size;
+            if entry.protected {
+                self.protected_size -= entry.size;
+                entry.protected = false;
+            }
+            entry.size = 0;
         }
 
         to_evict
@@ -346,3 +515,60 @@ impl Shard {
 fn safe_usize(value: PageId) -> usize {
     usize::try_from(value).unwrap()
 }
+
+#[test]
+fn scan_resistant_shard() {
+    fn scan_evicts_hot_items(mode: CacheMode) -> bool {
+        let mut shard = Shard::new(100, mode);
+        let mut evicted = vec![];
+        for pos in &[0, 1, 0, 1] {
+            evicted.extend(shard.accessed(*pos, 10, false));
+        }
+        for pos in 2..50 {
+            evicted.extend(shard.accessed(pos, 10, true));
+        }
+        assert!(shard.size <= 100);
+        evicted.contains(&0) || evicted.contains(&1)
+    }
+
+    assert!(scan_evicts_hot_items(CacheMode::Lru));
+    assert!(!scan_evicts_hot_items(CacheMode::ScanResistant));
+
+    // hot items beyond the protected share are demoted, and
+    // are then evicted before the other protected items
+    let mut hot = Shard::new(100, CacheMode::ScanResistant);
+    for pos in (0..9).chain(0..9) {
+        assert!(hot.accessed(pos, 10, false).is_empty());
+    }
+    assert_eq!(hot.protected_size, 80);
+    assert_eq!(hot.accessed(9, 20, false), vec![0]);
+    assert_eq!(hot.size, 100);
+
+    // an item that was evicted before its second access is
+    // protected when it is accessed again
+    let mut returning = Shard::new(100, CacheMode::ScanResistant);
+    returning.accessed(0, 10, false);
+    let evicted: Vec<_> =
+        (1..20).flat_map(|pos| returning.accessed(pos, 10, true)).collect();
+    assert!(evicted.contains(&0));
+    returning.accessed(0, 10, false);
+    assert!(returning.entries[0].protected);
+    assert_eq!(returning.protected_size, 10);
+}
+
+#[test]
+fn shard_set_capacity() {
+    let mut shard = Shard::new(100, CacheMode::Lru);
+    for pos in 0..10 {
+        assert!(shard.accessed(pos, 10, false).is_empty());
+    }
+
+    assert_eq!(shard.set_capacity(50), vec![0, 1, 2, 3, 4]);
+    assert_eq!(shard.size, 50);
+
+    assert!(shard.set_capacity(200).is_empty());
+    for pos in 10..25 {
+        assert!(shard.accessed(pos, 10, false).is_empty());
+    }
+    assert_eq!(shard.size, 200);
+}
@@ -47,6 +47,7 @@ impl Meta {
 pub(crate) fn open_tree<V>(
     context: &Context,
     raw_name: V,
+    dedup: Option<Dedup>,
     guard: &Guard,
 ) -> Result<Tree>
 where
@@ -65,6 +66,13 @@ where
                     subscribers: Subscribers::default(),
                     root: AtomicU64::new(root_id),
                     merge_operator: RwLock::new(None),
+                    write_generation: AtomicU64::new(
+                        context.write_generation(),
+                    ),
+                    logged_bytes: AtomicU64::new(0),
+                    checksum_cache: Mutex::default(),
+                    subtree_counts: Mutex::default(),
+                    dedup,
                 })));
             }
             Err(Error::CollectionNotFound(_)) => {}
@@ -116,6 +124,11 @@ where
             context: context.clone(),
             root: AtomicU64::new(root_id),
             merge_operator: RwLock::new(None),
+            write_generation: AtomicU64::new(context.write_generation()),
+            logged_bytes: AtomicU64::new(0),
+            checksum_cache: Mutex::default(),
+            subtree_counts: Mutex::default(),
+            dedup,
         })));
     }
 }
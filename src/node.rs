@@ -1,4 +1,4 @@
-use std::{num::NonZeroU64, ops::Bound};
+use std::{mem::size_of, num::NonZeroU64, ops::Bound};
 
 use super::*;
 
@@ -38,14 +38,17 @@ impl Node {
         }
     }
 
+    /// Returns an estimate of the memory held by this node,
+    /// counting the heap allocations of its keys and values
+    /// along with the space they take in the node.
     pub(crate) fn rss(&self) -> u64 {
-        std::mem::size_of::<Node>() as u64
-            + self.lo.len() as u64
-            + self.hi.len() as u64
+        size_of::<Node>() as u64
+            + self.lo.heap_size()
+            + self.hi.heap_size()
             + self.data.rss()
     }
 
-    fn prefix_decode(&self, key: &[u8]) -> IVec {
+    pub(crate) fn prefix_decode(&self, key: &[u8]) -> IVec {
         prefix::decode(self.prefix(), key)
     }
 
@@ -71,8 +74,8 @@ impl Node {
         );
 
         match *link {
-            Set(ref k, ref v) => {
-                self.set_leaf(k.clone(), v.clone());
+            Set(ref k, ref v, version) => {
+                self.set_leaf(k.clone(), v.clone(), version);
             }
             Del(ref k) => {
                 self.del_leaf(k);
@@ -106,17 +109,21 @@ impl Node {
         }
     }
 
-    pub(crate) fn set_leaf(&mut self, key: IVec, val: IVec) {
+    pub(crate) fn set_leaf(&mut self, key: IVec, val: IVec, version: u64) {
         if !self.hi.is_empty() {
             assert!(*key < self.hi[self.prefix_len as usize..]);
         }
         if let Data::Leaf(ref mut leaf) = self.data {
             let search = leaf.keys.binary_search_by(|k| fastcmp(k, &key));
             match search {
-                Ok(idx) => leaf.values[idx] = val,
+                Ok(idx) => {
+                    leaf.values[idx] = val;
+                    leaf.versions[idx] = version;
+                }
                 Err(idx) => {
                     leaf.keys.insert(idx, key);
                     leaf.values.insert(idx, val);
+                    leaf.versions.insert(idx, version);
                 }
             }
             testing_assert!(is_sorted(&leaf.keys));
@@ -131,6 +138,7 @@ impl Node {
             if let Ok(idx) = search {
                 leaf.keys.remove(idx);
                 leaf.values.remove(idx);
+                leaf.versions.remove(idx);
             }
             testing_assert!(is_sorted(&leaf.keys));
         } else {
@@ -272,11 +280,16 @@ impl Node {
                         prefixed_hi,
                         true,
                     );
+                let right_versions = leaf.versions.split_off(leaf.keys.len());
 
                 (
                     split,
                     right_prefix_len,
-                    Data::Leaf(Leaf { keys: right_keys, values: right_values }),
+                    Data::Leaf(Leaf {
+                        keys: right_keys,
+                        values: right_values,
+                        versions: right_versions,
+                    }),
                 )
             }
         };
@@ -425,6 +438,7 @@ impl Node {
                     right_leaf.keys.as_ref(),
                     right_leaf.values.as_ref(),
                 );
+                left_leaf.versions.extend_from_slice(&right_leaf.versions);
             }
             _ => panic!("Can't merge incompatible Data!"),
         }
@@ -563,6 +577,31 @@ impl Node {
         None
     }
 
+    /// `leaf_count_in` returns the number of keys in `lo..hi`,
+    /// where an empty `hi` is unbounded. `lo` must be within
+    /// the bounds of this node.
+    pub(crate) fn leaf_count_in(&self, lo: &[u8], hi: &[u8]) -> usize {
+        let leaf =
+            self.data.leaf_ref().expect("leaf_count_in called on index node");
+
+        let position = |key: &[u8]| {
+            let suffix = self.prefix_encode(key);
+            match leaf.keys.binary_search_by(|k| fastcmp(k, suffix)) {
+                Ok(idx) | Err(idx) => idx,
+            }
+        };
+
+        let start = position(lo);
+        let end = if hi.is_empty() || (!self.hi.is_empty() && *self.hi <= *hi)
+        {
+            leaf.keys.len()
+        } else {
+            position(hi)
+        };
+
+        end.saturating_sub(start)
+    }
+
     /// `leaf_pair_for_key` finds an existing value pair for a given key.
     pub(crate) fn leaf_pair_for_key(
         &self,
@@ -580,6 +619,21 @@ impl Node {
         search.map(|idx| (&leaf.keys[idx], &leaf.values[idx]))
     }
 
+    /// `leaf_version_for_key` finds the version of an
+    /// existing value for a given key.
+    pub(crate) fn leaf_version_for_key(&self, key: &[u8]) -> Option<u64> {
+        let leaf = self
+            .data
+            .leaf_ref()
+            .expect("leaf_version_for_key called on index node");
+
+        let suffix = &key[self.prefix_len as usize..];
+
+        let search = leaf.keys.binary_search_by(|k| fastcmp(k, suffix)).ok();
+
+        search.map(|idx| leaf.versions[idx])
+    }
+
     /// `node_kv_pair` returns either existing (node/key, value) pair or
     /// (node/key, none) where a node/key is node level encoded key.
     pub fn node_kv_pair(&self, key: &[u8]) -> (IVec, Option<IVec>) {
@@ -596,16 +650,29 @@ impl Node {
         }
     }
 
-    pub(crate) fn should_split(&self) -> bool {
-        let threshold = if cfg!(any(test, feature = "lock_free_delays")) {
+    fn split_threshold(&self) -> usize {
+        if cfg!(any(test, feature = "lock_free_delays")) {
             2
         } else if self.data.is_index() {
             256
         } else {
             16
-        };
+        }
+    }
 
-        let size_checks = self.data.len() > threshold;
+    pub(crate) fn should_split(&self) -> bool {
+        let size_checks = self.data.len() > self.split_threshold();
+        let safety_checks = self.merging_child.is_none() && !self.merging;
+
+        size_checks && safety_checks
+    }
+
+    /// Whether `Tree::compact_range` should merge this node into
+    /// its left sibling `left`, which it does whenever the merged
+    /// node would not immediately be split again.
+    pub(crate) fn should_compact_into(&self, left: &Node) -> bool {
+        let size_checks =
+            self.data.len() + left.data.len() <= left.split_threshold();
         let safety_checks = self.merging_child.is_none() && !self.merging;
 
         size_checks && safety_checks
@@ -658,16 +725,19 @@ impl Default for Data {
 
 impl Data {
     pub(crate) fn rss(&self) -> u64 {
+        let ivec = size_of::<IVec>() as u64;
         match self {
-            Data::Index(ref index) => {
-                index.keys.iter().map(|k| k.len() + 8).sum::<usize>() as u64
-            }
+            Data::Index(ref index) => index
+                .keys
+                .iter()
+                .map(|k| ivec + k.heap_size() + 8)
+                .sum::<u64>(),
             Data::Leaf(ref leaf) => leaf
                 .keys
                 .iter()
                 .zip(leaf.values.iter())
-                .map(|(k, v)| k.len() + v.len())
-                .sum::<usize>() as u64,
+                .map(|(k, v)| 2 * ivec + k.heap_size() + v.heap_size() + 8)
+                .sum::<u64>(),
         }
     }
 
@@ -716,6 +786,8 @@ impl Data {
 pub(crate) struct Leaf {
     pub(crate) keys: Vec<IVec>,
     pub(crate) values: Vec<IVec>,
+    /// The version of each value, see `Tree::get_versioned`.
+    pub(crate) versions: Vec<u64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Default)]
@@ -735,6 +807,7 @@ fn merge_uneven_nodes() {
         data: Data::Leaf(Leaf {
             keys: vec![vec![230, 126, 1, 0].into()],
             values: vec![vec![].into()],
+            versions: vec![1],
         }),
         next: NonZeroU64::new(1),
         lo: vec![230, 125, 1, 0].into(),
@@ -748,6 +821,7 @@ fn merge_uneven_nodes() {
         data: Data::Leaf(Leaf {
             keys: vec![vec![134, 0, 0].into()],
             values: vec![vec![].into()],
+            versions: vec![1],
         }),
         next: None,
         lo: vec![230, 134, 0, 0].into(),
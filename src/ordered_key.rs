@@ -0,0 +1,357 @@
+//! Encodings of typed keys into bytes that sort in the same
+//! order as the keys. See `OrderedKey`.
+
+use crate::IVec;
+
+/// Types that can be encoded into bytes that sort in the
+/// same order as the values themselves, so that they can be
+/// used as keys of a `Tree`, whose keys are ordered by their
+/// bytes. `Tree::range_typed` uses this to scan a range of
+/// typed keys.
+///
+/// The encoding of every implementation is self-delimiting:
+/// no encoding is a prefix of the encoding of a different
+/// value of the same type. This is what allows tuples to be
+/// encoded by concatenating the encodings of their fields,
+/// and it is required of implementations for other types.
+///
+/// * unsigned integers are encoded in big-endian order.
+/// * signed integers are encoded in big-endian order with
+///   the sign bit flipped, so negative numbers sort before
+///   positive ones.
+/// * `bool` is encoded as a single 0 or 1 byte, and `char`
+///   as its `u32` value.
+/// * `str`, `String` and `IVec` are encoded as their bytes,
+///   with each 0 byte escaped as `[0, 0xFF]`, followed by a
+///   `[0, 0]` terminator. This sorts like their bytes do.
+/// * `Option` is encoded as a 0 byte for `None`, which
+///   sorts first, or a 1 byte followed by the value.
+/// * slices and `Vec`s are encoded as each element preceded
+///   by a 1 byte, followed by a 0 byte, so they sort
+///   element by element, with a prefix first. This takes
+///   two bytes for each element of a `Vec<u8>`, so use an
+///   `IVec` for byte strings.
+/// * tuples of up to 8 fields are encoded as the
+///   concatenation of their fields, so they sort by their
+///   first field, then by the second, and so on.
+///
+/// # Examples
+///
+/// ```
+/// use sled::OrderedKey;
+///
+/// assert!((-1_i32).to_key_bytes() < 1_i32.to_key_bytes());
+/// assert!(
+///     (1_u32, "b".to_string()).to_key_bytes()
+///         < (2_u32, "a".to_string()).to_key_bytes()
+/// );
+/// assert!(("a", 2_u8).to_key_bytes() < ("ab", 1_u8).to_key_bytes());
+/// ```
+pub trait OrderedKey {
+    /// Appends the encoding of `self` to `out`.
+    fn encode_key(&self, out: &mut Vec<u8>);
+
+    /// Returns the encoding of `self`.
+    fn to_key_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_key(&mut out);
+        out
+    }
+}
+
+impl<T: OrderedKey + ?Sized> OrderedKey for &T {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (**self).encode_key(out)
+    }
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => {$(
+        impl OrderedKey for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes())
+            }
+        }
+    )*};
+}
+
+unsigned_key!(u8, u16, u32, u64, u128, usize);
+
+macro_rules! signed_key {
+    ($($t:ty => $unsigned:ty),*) => {$(
+        impl OrderedKey for $t {
+            #[allow(clippy::cast_sign_loss)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let sign_bit = 1 << (std::mem::size_of::<$t>() * 8 - 1);
+                let flipped = (*self as $unsigned) ^ sign_bit;
+                out.extend_from_slice(&flipped.to_be_bytes())
+            }
+        }
+    )*};
+}
+
+signed_key!(
+    i8 => u8,
+    i16 => u16,
+    i32 => u32,
+    i64 => u64,
+    i128 => u128,
+    isize => usize
+);
+
+impl OrderedKey for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(*self))
+    }
+}
+
+impl OrderedKey for char {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        u32::from(*self).encode_key(out)
+    }
+}
+
+/// Appends `bytes` with escaped 0 bytes and a terminator, which
+/// keeps their order while making them self-delimiting.
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.reserve(bytes.len() + 2);
+    for &byte in bytes {
+        out.push(byte);
+        if byte == 0 {
+            out.push(0xFF);
+        }
+    }
+    out.extend_from_slice(&[0, 0]);
+}
+
+impl OrderedKey for str {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out)
+    }
+}
+
+impl OrderedKey for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out)
+    }
+}
+
+impl OrderedKey for IVec {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out)
+    }
+}
+
+impl<T: OrderedKey> OrderedKey for Option<T> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        if let Some(value) = self {
+            out.push(1);
+            value.encode_key(out);
+        } else {
+            out.push(0);
+        }
+    }
+}
+
+impl<T: OrderedKey> OrderedKey for [T] {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for item in self {
+            out.push(1);
+            item.encode_key(out);
+        }
+        out.push(0);
+    }
+}
+
+impl<T: OrderedKey> OrderedKey for Vec<T> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode_key(out)
+    }
+}
+
+impl OrderedKey for () {
+    fn encode_key(&self, _: &mut Vec<u8>) {}
+}
+
+macro_rules! tuple_key {
+    ($($name:ident)+) => {
+        impl<$($name: OrderedKey),+> OrderedKey for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode_key(out);)+
+            }
+        }
+    };
+}
+
+tuple_key!(A);
+tuple_key!(A B);
+tuple_key!(A B C);
+tuple_key!(A B C D);
+tuple_key!(A B C D E);
+tuple_key!(A B C D E F);
+tuple_key!(A B C D E F G);
+tuple_key!(A B C D E F G H);
+
+#[cfg(test)]
+mod qc {
+    use std::fmt::Debug;
+
+    use quickcheck::quickcheck;
+
+    use super::*;
+
+    /// Checks that the encodings of `a` and `b` compare like
+    /// `a` and `b` do.
+    fn prop_ordered<T: OrderedKey + Ord + Debug>(a: &T, b: &T) -> bool {
+        let ordered = a.cmp(b) == a.to_key_bytes().cmp(&b.to_key_bytes());
+        if !ordered {
+            eprintln!(
+                "{:?} vs {:?} encoded as {:?} vs {:?}",
+                a,
+                b,
+                a.to_key_bytes(),
+                b.to_key_bytes()
+            );
+        }
+        ordered
+    }
+
+    fn all_pairs_ordered<T: OrderedKey + Ord + Debug>(items: &[T]) -> bool {
+        items.iter().all(|a| items.iter().all(|b| prop_ordered(a, b)))
+    }
+
+    #[test]
+    fn exhaustive_small_integers() {
+        let u: Vec<u8> = (0..=u8::max_value()).collect();
+        assert!(all_pairs_ordered(&u));
+        let i: Vec<i8> = (i8::min_value()..=i8::max_value()).collect();
+        assert!(all_pairs_ordered(&i));
+        let i16s: Vec<i16> = (-300..300)
+            .chain(vec![i16::min_value(), i16::max_value()])
+            .collect();
+        assert!(all_pairs_ordered(&i16s));
+    }
+
+    #[test]
+    fn integer_edges() {
+        let i64s = vec![
+            i64::min_value(),
+            i64::min_value() + 1,
+            -256,
+            -1,
+            0,
+            1,
+            256,
+            i64::max_value() - 1,
+            i64::max_value(),
+        ];
+        assert!(all_pairs_ordered(&i64s));
+        let i128s: Vec<i128> = i64s.iter().map(|&i| i128::from(i)).collect();
+        assert!(all_pairs_ordered(&i128s));
+        let isizes = vec![isize::min_value(), -1, 0, 1, isize::max_value()];
+        assert!(all_pairs_ordered(&isizes));
+        assert!(all_pairs_ordered(&[false, true]));
+        assert!(all_pairs_ordered(&['\0', 'a', 'z', '\u{e9}', '\u{10FFFF}']));
+    }
+
+    /// Every string of up to 3 bytes from an alphabet that
+    /// includes the escaped and escaping bytes.
+    fn small_strings() -> Vec<Vec<u8>> {
+        let alphabet = [0_u8, 1, 0xFE, 0xFF];
+        let mut strings = vec![vec![]];
+        let mut last = vec![vec![]];
+        for _ in 0..3 {
+            let mut next = vec![];
+            for prefix in &last {
+                for &byte in &alphabet {
+                    let mut string = prefix.clone();
+                    string.push(byte);
+                    next.push(string);
+                }
+            }
+            strings.extend(next.iter().cloned());
+            last = next;
+        }
+        strings
+    }
+
+    #[test]
+    fn exhaustive_byte_strings() {
+        let ivecs: Vec<IVec> =
+            small_strings().into_iter().map(IVec::from).collect();
+        assert!(all_pairs_ordered(&ivecs));
+
+        // the escaping keeps strings self-delimiting in tuples
+        let mut pairs = vec![];
+        for a in &ivecs {
+            for b in ivecs.iter().take(12) {
+                pairs.push((a.clone(), b.clone()));
+            }
+        }
+        assert!(all_pairs_ordered(&pairs));
+    }
+
+    #[test]
+    fn exhaustive_nested_sequences() {
+        let strings: Vec<String> = vec!["", "\0", "\0\0", "a", "a\0", "ab"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let mut seqs: Vec<Vec<String>> = vec![vec![]];
+        for a in &strings {
+            seqs.push(vec![a.clone()]);
+            for b in &strings {
+                seqs.push(vec![a.clone(), b.clone()]);
+            }
+        }
+        assert!(all_pairs_ordered(&seqs));
+
+        let nested: Vec<(Vec<String>, Option<u8>)> = seqs
+            .iter()
+            .take(20)
+            .flat_map(|seq| {
+                vec![None, Some(0), Some(1)]
+                    .into_iter()
+                    .map(move |opt| (seq.clone(), opt))
+            })
+            .collect();
+        assert!(all_pairs_ordered(&nested));
+
+        let vec_of_vecs: Vec<Vec<Vec<u8>>> = vec![
+            vec![],
+            vec![vec![]],
+            vec![vec![], vec![]],
+            vec![vec![0]],
+            vec![vec![0], vec![]],
+            vec![vec![0, 0]],
+            vec![vec![1]],
+        ];
+        assert!(all_pairs_ordered(&vec_of_vecs));
+    }
+
+    quickcheck! {
+        fn integer_tuples(a: (u32, i64, i8), b: (u32, i64, i8)) -> bool {
+            prop_ordered(&a, &b)
+        }
+
+        fn string_tuples(a: (u32, String), b: (u32, String)) -> bool {
+            prop_ordered(&a, &b)
+        }
+
+        fn nested(
+            a: (Vec<(i16, String)>, Option<bool>, u8),
+            b: (Vec<(i16, String)>, Option<bool>, u8)
+        ) -> bool {
+            prop_ordered(&a, &b)
+        }
+
+        fn byte_strings(a: (Vec<u8>, Vec<u8>), b: (Vec<u8>, Vec<u8>)) -> bool {
+            let a = (IVec::from(a.0), IVec::from(a.1));
+            let b = (IVec::from(b.0), IVec::from(b.1));
+            prop_ordered(&a, &b)
+        }
+    }
+}
@@ -6,7 +6,7 @@ pub(crate) fn read_blob(
     config: &Config,
 ) -> Result<(MessageKind, Vec<u8>)> {
     let path = config.blob_path(blob_ptr);
-    let f_res = std::fs::OpenOptions::new().read(true).open(&path);
+    let f_res = files::OpenOptions::new().read(true).open(config, &path);
 
     if let Err(e) = &f_res {
         debug!("failed to open file for blob read at {}: {:?}", blob_ptr, e);
@@ -69,8 +69,10 @@ pub(crate) fn write_blob<T: Serialize>(
     item: &T,
 ) -> Result<()> {
     let path = config.blob_path(id);
-    let mut f =
-        std::fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+    let mut f = files::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(config, &path)?;
 
     let kind_buf = &[kind.into()];
 
@@ -102,13 +104,12 @@ pub(crate) fn gc_blobs(config: &Config, stable_lsn: Lsn) -> Result<()> {
     let mut base_dir = config.get_path();
     base_dir.push("blobs");
     let blob_dir = base_dir;
-    let blobs = std::fs::read_dir(blob_dir)?;
+    let blobs = files::read_dir(config, &blob_dir)?;
 
     debug!("gc_blobs removing any blob with an lsn above {}", stable_lsn);
 
     let mut to_remove = vec![];
-    for blob in blobs {
-        let path = blob?.path();
+    for path in blobs {
         let lsn_str = path.file_name().unwrap().to_str().unwrap();
         let lsn_res: std::result::Result<Lsn, _> = lsn_str.parse();
 
@@ -138,7 +139,7 @@ pub(crate) fn gc_blobs(config: &Config, stable_lsn: Lsn) -> Result<()> {
     }
 
     for path in to_remove {
-        std::fs::remove_file(&path)?;
+        files::remove_file(config, &path)?;
     }
 
     Ok(())
@@ -146,7 +147,7 @@ pub(crate) fn gc_blobs(config: &Config, stable_lsn: Lsn) -> Result<()> {
 
 pub(crate) fn remove_blob(id: Lsn, config: &Config) -> Result<()> {
     let path = config.blob_path(id);
-    if let Err(e) = std::fs::remove_file(&path) {
+    if let Err(e) = files::remove_file(config, &path) {
         debug!("removing blob at {:?} failed: {}", path, e);
     } else {
         trace!("successfully removed blob at {:?}", path);
@@ -0,0 +1,234 @@
+use std::{fs::File, io};
+
+use super::{pread_exact, pread_exact_or_eof, pwrite_all, LogOffset};
+
+use crate::*;
+
+/// The operations that sled performs on the storage
+/// file that holds its log. By default this is a file
+/// named `db` in the configured path, but a different
+/// implementation can be provided with
+/// `Config::io_backend`, for instance to add encryption,
+/// to store the log somewhere other than a local
+/// filesystem, or to inject faults in tests.
+///
+/// Only the log goes through the backend. The
+/// configuration file, snapshots and blobs are still
+/// stored in the configured path on the local
+/// filesystem.
+///
+/// # Required guarantees
+///
+/// Implementations must behave like a single file that
+/// is accessed with positional reads and writes:
+///
+/// * `pread_*` must return the most recently written
+///   bytes for every offset, even if they have not
+///   been synced yet, and may be called concurrently
+///   with each other and with writes to other regions.
+/// * `pwrite_all` must be able to write beyond the
+///   current end of the file, growing it as needed.
+///   Writes to disjoint regions may happen concurrently.
+///   A write does not need to be atomic or durable
+///   until it is synced, and recovery will discard any
+///   torn writes, as long as bytes that were previously
+///   synced are never corrupted.
+/// * once `sync_all` or `sync_range` returns
+///   successfully, every write to the synced region
+///   that completed before the call must survive a
+///   crash, and writes must never become durable in a
+///   way that reorders them with respect to a sync
+///   that has returned.
+/// * `set_len` must truncate or extend the file, with
+///   extended regions reading as zeroes. It does not
+///   need to be durable until the next sync.
+///
+/// Errors returned by the backend are surfaced to users
+/// as `Error::Io`, and a failed write or sync leaves the
+/// database in an error state that requires a restart.
+pub trait IoBackend: Send + Sync + Debug {
+    /// Reads bytes starting at `offset` until `buf` is full
+    /// or the end of the file is reached, returning the
+    /// number of bytes read.
+    fn pread_exact_or_eof(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> io::Result<usize>;
+
+    /// Fills `buf` with the bytes starting at `offset`,
+    /// returning an error of kind `UnexpectedEof` if the
+    /// file ends first.
+    fn pread_exact(&self, buf: &mut [u8], offset: LogOffset) -> io::Result<()> {
+        let read = self.pread_exact_or_eof(buf, offset)?;
+        if read < buf.len() {
+            Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes all of `buf` starting at `offset`.
+    fn pwrite_all(&self, buf: &[u8], offset: LogOffset) -> io::Result<()>;
+
+    /// Makes all previous writes durable.
+    fn sync_all(&self) -> io::Result<()>;
+
+    /// Makes previous writes to the given region durable.
+    /// Defaults to `sync_all`.
+    fn sync_range(&self, offset: LogOffset, len: usize) -> io::Result<()> {
+        let _ = (offset, len);
+        self.sync_all()
+    }
+
+    /// Returns the current length of the file in bytes.
+    fn size(&self) -> io::Result<u64>;
+
+    /// Truncates or extends the file to `len` bytes.
+    fn set_len(&self, len: u64) -> io::Result<()>;
+
+    /// Returns the underlying `File`, if there is one. This
+    /// is used by the `io_uring` feature, which requires the
+    /// default backend.
+    #[doc(hidden)]
+    fn as_file(&self) -> Option<&File> {
+        None
+    }
+}
+
+impl IoBackend for File {
+    fn pread_exact_or_eof(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> io::Result<usize> {
+        pread_exact_or_eof(self, buf, offset)
+    }
+
+    fn pread_exact(&self, buf: &mut [u8], offset: LogOffset) -> io::Result<()> {
+        pread_exact(self, buf, offset)
+    }
+
+    fn pwrite_all(&self, buf: &[u8], offset: LogOffset) -> io::Result<()> {
+        pwrite_all(self, buf, offset)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sync_range(&self, offset: LogOffset, len: usize) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let ret = unsafe {
+            libc::sync_file_range(
+                self.as_raw_fd(),
+                i64::try_from(offset).unwrap(),
+                i64::try_from(len).unwrap(),
+                libc::SYNC_FILE_RANGE_WAIT_BEFORE
+                    | libc::SYNC_FILE_RANGE_WRITE
+                    | libc::SYNC_FILE_RANGE_WAIT_AFTER,
+            )
+        };
+
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if let Some(libc::ENOSYS) = err.raw_os_error() {
+                File::sync_all(self)
+            } else {
+                Err(err)
+            }
+        } else {
+            Ok(())
+        }
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        Some(self)
+    }
+}
+
+/// Makes previous writes to `file` durable according to
+/// `strategy`. `range` is the region that was written, if
+/// it is known, which only `SyncStrategy::SyncFileRange`
+/// makes use of.
+pub(crate) fn sync_file(
+    file: &File,
+    strategy: SyncStrategy,
+    range: Option<(LogOffset, usize)>,
+) -> io::Result<()> {
+    match strategy {
+        SyncStrategy::SyncFileRange => {
+            if let Some((offset, len)) = range {
+                IoBackend::sync_range(file, offset, len)
+            } else {
+                File::sync_all(file)
+            }
+        }
+        SyncStrategy::Fdatasync => fdatasync(file),
+        SyncStrategy::Fsync => fsync(file),
+        SyncStrategy::FullFsync => full_fsync(file),
+    }
+}
+
+#[cfg(unix)]
+fn fsync(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // called directly instead of through `File::sync_all`,
+    // which issues F_FULLFSYNC on macOS
+    let ret = unsafe { libc::fsync(file.as_raw_fd()) };
+    if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(not(unix))]
+fn fsync(file: &File) -> io::Result<()> {
+    File::sync_all(file)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn fdatasync(file: &File) -> io::Result<()> {
+    // fdatasync is not part of the public API on macOS
+    fsync(file)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn fdatasync(file: &File) -> io::Result<()> {
+    File::sync_data(file)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn full_fsync(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_FULLFSYNC) };
+    if ret < 0 {
+        // some filesystems, such as network filesystems,
+        // do not support F_FULLFSYNC. fsync is the best
+        // that we can do on those.
+        debug!(
+            "F_FULLFSYNC failed with {}, falling back to fsync",
+            io::Error::last_os_error()
+        );
+        fsync(file)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn full_fsync(file: &File) -> io::Result<()> {
+    fsync(file)
+}
@@ -2,6 +2,7 @@ use std::{
     alloc::{alloc, dealloc, Layout},
     cell::UnsafeCell,
     sync::atomic::{AtomicBool, AtomicPtr},
+    time::Duration,
 };
 
 use crate::{pagecache::*, *};
@@ -13,6 +14,10 @@ pub(in crate::pagecache) const MAX_WRITERS: Header = 127;
 
 pub(in crate::pagecache) type Header = u64;
 
+// How often a log write that failed because the disk
+// is full is retried, see `OnFull`.
+const DISK_FULL_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
 macro_rules! io_fail {
     ($self:expr, $e:expr) => {
         #[cfg(feature = "failpoints")]
@@ -337,6 +342,10 @@ pub(crate) struct IoBufs {
     pub segment_accountant: Mutex<SegmentAccountant>,
     pub segment_cleaner: SegmentCleaner,
     deferred_segment_ops: stack::Stack<SegmentOp>,
+    pub flush_hooks: FlushHooks,
+    // The number of times the log was synced after writing a
+    // buffer since `Db::flush_detailed` last took it.
+    pub log_syncs: AtomicU64,
     #[cfg(feature = "io_uring")]
     pub submission_mutex: Mutex<()>,
     #[cfg(feature = "io_uring")]
@@ -442,6 +451,8 @@ impl IoBufs {
             segment_accountant: Mutex::new(segment_accountant),
             segment_cleaner,
             deferred_segment_ops: stack::Stack::default(),
+            flush_hooks: FlushHooks::default(),
+            log_syncs: AtomicU64::new(0),
             #[cfg(feature = "io_uring")]
             submission_mutex: Mutex::new(()),
             #[cfg(feature = "io_uring")]
@@ -501,6 +512,22 @@ impl IoBufs {
         })
     }
 
+    /// Returns the state and live bytes of every segment that
+    /// is not free, once the replacements that were deferred
+    /// while the accountant was busy have been applied.
+    #[cfg(feature = "debug")]
+    pub(in crate::pagecache) fn sa_structure(
+        &self,
+        guard: &Guard,
+    ) -> Result<Vec<SegmentStructure>> {
+        self.with_sa(|sa| {
+            for op in self.deferred_segment_ops.take_iter(guard) {
+                sa.apply_op(op)?;
+            }
+            Ok(sa.structure())
+        })
+    }
+
     /// `SegmentAccountant` access for coordination with the `PageCache`
     pub(in crate::pagecache) fn with_sa<B, F>(&self, f: F) -> B
     where
@@ -589,6 +616,40 @@ impl IoBufs {
         Ok(())
     }
 
+    // Like `write_to_log`, but if the disk is full and
+    // `Config::on_disk_full` is not `OnFull::Error`, keeps
+    // retrying the write until there is room for it. This is
+    // safe because `write_to_log` has no effects that can't
+    // be repeated before the data is written.
+    pub(crate) fn write_to_log_or_retry(&self, iobuf: &IoBuf) -> Result<()> {
+        let mut entered = false;
+        loop {
+            let result = self.write_to_log(iobuf);
+            let no_space = match &result {
+                Err(Error::Io(e)) => is_no_space(e),
+                _ => false,
+            };
+
+            if !no_space || self.config.on_disk_full == OnFull::Error {
+                if entered {
+                    self.config.disk_full.exit();
+                }
+                return result;
+            }
+
+            if !entered {
+                error!(
+                    "the disk is full, retrying the write of iobuf with \
+                     lsn {} every {:?} until space is freed",
+                    iobuf.lsn, DISK_FULL_RETRY_INTERVAL
+                );
+                self.config.disk_full.enter();
+                entered = true;
+            }
+            std::thread::sleep(DISK_FULL_RETRY_INTERVAL);
+        }
+    }
+
     // Write an IO buffer's data to stable storage and set up the
     // next IO buffer for writing.
     pub(crate) fn write_to_log(&self, iobuf: &IoBuf) -> Result<()> {
@@ -669,10 +730,11 @@ impl IoBufs {
             }
 
             // this as to stay aligned with the hashing
-            let crc32_arr = u32_to_arr(calculate_message_crc32(
-                &header_bytes,
-                &padding_bytes[..pad_len],
-            ));
+            let crc32_arr =
+                u32_to_arr(self.config.checksum_algorithm.message_checksum(
+                    &header_bytes,
+                    &padding_bytes[..pad_len],
+                ));
 
             #[allow(unsafe_code)]
             unsafe {
@@ -706,6 +768,14 @@ impl IoBufs {
         io_fail!(self, "buffer write");
         #[cfg(feature = "io_uring")]
         {
+            // custom io backends are rejected by
+            // `Config::validate` when using io_uring
+            let file = self
+                .config
+                .file
+                .as_file()
+                .expect("io_uring requires the default io backend");
+
             let mut wrote = 0;
             while wrote < total_len {
                 let to_write = &data[wrote..];
@@ -726,19 +796,20 @@ impl IoBufs {
                 // until the previous write is
                 // complete.
                 let wrote_completion = self.io_uring.write_at_ordered(
-                    &*self.config.file,
+                    file,
                     &to_write,
                     offset,
                     rio::Ordering::Link,
                 );
 
                 let sync_completion = self.io_uring.sync_file_range(
-                    &*self.config.file,
+                    file,
                     offset,
                     to_write.len(),
                 );
 
                 sync_completion.wait()?;
+                let _ = self.log_syncs.fetch_add(1, SeqCst);
 
                 // TODO we want to move this above the previous `wait`
                 // but there seems to be an issue in `rio` that is
@@ -752,33 +823,17 @@ impl IoBufs {
         #[cfg(not(feature = "io_uring"))]
         {
             let f = &self.config.file;
-            pwrite_all(f, data, log_offset)?;
+            f.pwrite_all(data, log_offset)?;
             if !self.config.temporary {
-                #[cfg(target_os = "linux")]
-                {
-                    use std::os::unix::io::AsRawFd;
-                    let ret = unsafe {
-                        libc::sync_file_range(
-                            f.as_raw_fd(),
-                            i64::try_from(log_offset).unwrap(),
-                            i64::try_from(total_len).unwrap(),
-                            libc::SYNC_FILE_RANGE_WAIT_BEFORE
-                                | libc::SYNC_FILE_RANGE_WRITE
-                                | libc::SYNC_FILE_RANGE_WAIT_AFTER,
-                        )
-                    };
-                    if ret < 0 {
-                        let err = std::io::Error::last_os_error();
-                        if let Some(libc::ENOSYS) = err.raw_os_error() {
-                            f.sync_all()?;
-                        } else {
-                            return Err(err.into());
-                        }
-                    }
-                }
-
-                #[cfg(not(target_os = "linux"))]
-                f.sync_all()?;
+                // The default `SyncStrategy` on linux syncs only
+                // this range with `sync_file_range`, which is far
+                // cheaper than syncing the whole file and survives
+                // crashes of the process and the kernel. macOS
+                // defaults to F_FULLFSYNC instead, because a plain
+                // fsync there leaves the data in the write cache
+                // of the drive, where it is lost on power failure.
+                self.config.sync_log_range(log_offset, total_len)?;
+                let _ = self.log_syncs.fetch_add(1, SeqCst);
             }
         }
         io_fail!(self, "buffer write post");
@@ -847,7 +902,15 @@ impl IoBufs {
 
         if let Some(new_stable_lsn) = updated {
             trace!("mark_interval new highest lsn {}", new_stable_lsn);
-            self.stable_lsn.store(new_stable_lsn, SeqCst);
+            let old_stable_lsn = self.stable_lsn.swap(new_stable_lsn, SeqCst);
+
+            // notified while holding intervals so that
+            // notifications are queued in order
+            self.flush_hooks.notify(FlushInfo {
+                bytes_flushed: u64::try_from(new_stable_lsn - old_stable_lsn)
+                    .unwrap(),
+                generation: u64::try_from(new_stable_lsn + 1).unwrap(),
+            });
 
             #[cfg(feature = "event_log")]
             {
@@ -997,7 +1060,7 @@ pub(in crate::pagecache) fn make_stable_inner(
             if cfg!(feature = "event_log") {
                 let timeout = iobufs
                     .interval_updated
-                    .wait_for(&mut waiter, std::time::Duration::from_secs(30));
+                    .wait_for(&mut waiter, Duration::from_secs(30));
                 if timeout.timed_out() {
                     fn tn() -> String {
                         std::thread::current()
@@ -1036,6 +1099,43 @@ pub(in crate::pagecache) fn flush(iobufs: &Arc<IoBufs>) -> Result<usize> {
     make_stable(iobufs, max_reserved_lsn)
 }
 
+/// Like `flush`, but makes the log stable one IO buffer
+/// at a time, calling `progress` after each one, and stops
+/// early if it returns `ControlFlow::Break`. Returns the
+/// progress made when it stopped.
+pub(in crate::pagecache) fn flush_with_progress(
+    iobufs: &Arc<IoBufs>,
+    progress: &mut dyn FnMut(FlushProgress) -> ControlFlow,
+) -> Result<FlushProgress> {
+    let (first_stable, target) = {
+        let _cc = concurrency_control::read();
+        (iobufs.stable(), iobufs.max_reserved_lsn.load(SeqCst))
+    };
+    let flushed_by = |stable: Lsn| {
+        u64::try_from(stable.min(target) - first_stable).unwrap_or(0)
+    };
+
+    let mut report = FlushProgress { flushed: 0, total: flushed_by(target) };
+    let step = Lsn::try_from(iobufs.config.segment_size).unwrap();
+
+    let mut stable = first_stable;
+    while stable < target {
+        {
+            // not held while calling `progress`, which may
+            // call back into sled
+            let _cc = concurrency_control::read();
+            let _ = make_stable(iobufs, target.min(stable + step))?;
+        }
+        stable = iobufs.stable();
+        report.flushed = flushed_by(stable);
+        if let ControlFlow::Break = progress(report) {
+            break;
+        }
+    }
+
+    Ok(report)
+}
+
 /// Attempt to seal the current IO buffer, possibly
 /// writing it to disk if there are no other writers
 /// operating on it.
@@ -1218,8 +1318,9 @@ pub(in crate::pagecache) fn maybe_seal_and_write_iobuf(
         );
         let iobufs = iobufs.clone();
         let iobuf = iobuf.clone();
-        let _result = threadpool::spawn(move || {
-            if let Err(e) = iobufs.write_to_log(&iobuf) {
+        let config = iobufs.config.clone();
+        let _result = config.spawn_io(move || {
+            if let Err(e) = iobufs.write_to_log_or_retry(&iobuf) {
                 error!(
                     "hit error while writing iobuf with lsn {}: {:?}",
                     lsn, e
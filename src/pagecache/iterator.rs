@@ -1,9 +1,9 @@
 use std::{collections::BTreeMap, io};
 
 use super::{
-    pread_exact_or_eof, read_message, read_segment_header, BasedBuf, DiskPtr,
-    LogKind, LogOffset, LogRead, Lsn, SegmentHeader, SegmentNumber,
-    MAX_MSG_HEADER_LEN, SEG_HEADER_LEN,
+    read_message, read_segment_header, BasedBuf, DiskPtr, LogKind, LogOffset,
+    LogRead, Lsn, SegmentHeader, SegmentNumber, MAX_MSG_HEADER_LEN,
+    SEG_HEADER_LEN,
 };
 use crate::*;
 
@@ -222,7 +222,7 @@ impl LogIter {
         assert!(
             lsn + self.config.segment_size as Lsn >= self.cur_lsn.unwrap_or(0)
         );
-        let f = &self.config.file;
+        let f = &*self.config.file;
         let segment_header = read_segment_header(f, offset)?;
         if offset % self.config.segment_size as LogOffset != 0 {
             debug!("segment offset not divisible by segment length");
@@ -253,12 +253,28 @@ impl LogIter {
         trace!("read segment header {:?}", segment_header);
 
         let mut buf = vec![0; self.config.segment_size];
-        let size = pread_exact_or_eof(f, &mut buf, offset)?;
+        let size = f.pread_exact_or_eof(&mut buf, offset)?;
 
         trace!("setting stored segment buffer length to {} after read", size);
         buf.truncate(size);
 
-        self.cur_lsn = Some(segment_header.lsn + SEG_HEADER_LEN as Lsn);
+        // an iterator may start partway into its first segment,
+        // after messages that an earlier recovery already
+        // applied, which are not read again. this keeps damage
+        // to those messages from hiding the ones after them,
+        // under every `RecoveryPolicy`: with `Strict` as well,
+        // stopping there would put the end of the log below
+        // the snapshot that already covers it, and lose every
+        // write since. a damaged page is still reported when
+        // it is read.
+        let segment_start =
+            segment_header.lsn + Lsn::try_from(SEG_HEADER_LEN).unwrap();
+        let segment_end = segment_header.lsn
+            + Lsn::try_from(self.config.segment_size).unwrap();
+        let resume_lsn = self
+            .cur_lsn
+            .filter(|cur| *cur > segment_start && *cur < segment_end);
+        self.cur_lsn = Some(resume_lsn.unwrap_or(segment_start));
 
         self.segment_base = Some(BasedBuf { buf, offset });
 
@@ -287,6 +303,7 @@ fn valid_entry_offset(lid: LogOffset, segment_len: usize) -> bool {
 // and recover the order of segments, and the highest Lsn.
 fn scan_segment_headers_and_tail(
     min: Lsn,
+    recovered_lsn: Lsn,
     config: &RunningConfig,
 ) -> Result<(BTreeMap<Lsn, LogOffset>, Lsn)> {
     fn fetch(
@@ -296,7 +313,7 @@ fn scan_segment_headers_and_tail(
     ) -> Option<(LogOffset, SegmentHeader)> {
         let segment_len = u64::try_from(config.segment_size).unwrap();
         let base_lid = idx * segment_len;
-        let segment = read_segment_header(&config.file, base_lid).ok()?;
+        let segment = read_segment_header(&*config.file, base_lid).ok()?;
         trace!(
             "SA scanned header at lid {} during startup: {:?}",
             base_lid,
@@ -319,8 +336,7 @@ fn scan_segment_headers_and_tail(
 
     let segment_len = LogOffset::try_from(config.segment_size).unwrap();
 
-    let f = &config.file;
-    let file_len = f.metadata()?.len();
+    let file_len = config.file.size()?;
     let segments = (file_len / segment_len)
         + if file_len % segment_len
             < LogOffset::try_from(SEG_HEADER_LEN).unwrap()
@@ -343,7 +359,7 @@ fn scan_segment_headers_and_tail(
         .map({
             // let config = config.clone();
             move |idx| {
-                threadpool::spawn({
+                config.spawn({
                     let config = config.clone();
                     move || fetch(idx, min, &config)
                 })
@@ -391,6 +407,7 @@ fn scan_segment_headers_and_tail(
     let end_of_last_contiguous_message_in_unstable_tail =
         check_contiguity_in_unstable_tail(
             max_header_stable_lsn,
+            recovered_lsn,
             &ordering,
             config,
         )?;
@@ -405,6 +422,7 @@ fn scan_segment_headers_and_tail(
 // never reuse buffers within this safety range.
 fn check_contiguity_in_unstable_tail(
     max_header_stable_lsn: Lsn,
+    recovered_lsn: Lsn,
     ordering: &BTreeMap<Lsn, LogOffset>,
     config: &RunningConfig,
 ) -> Result<Lsn> {
@@ -447,7 +465,7 @@ fn check_contiguity_in_unstable_tail(
         segments: logical_tail,
         segment_base: None,
         max_lsn: missing_item_in_tail,
-        cur_lsn: None,
+        cur_lsn: Some(recovered_lsn),
         last_stage: false,
     };
 
@@ -478,7 +496,7 @@ pub fn raw_segment_iter_from(
     let normalized_lsn = lsn / segment_len * segment_len;
 
     let (ordering, end_of_last_msg) =
-        scan_segment_headers_and_tail(normalized_lsn, config)?;
+        scan_segment_headers_and_tail(normalized_lsn, lsn, config)?;
 
     // find the last stable tip, to properly handle batch manifests.
     let tip_segment_iter: BTreeMap<_, _> = ordering
@@ -512,9 +530,82 @@ pub fn raw_segment_iter_from(
     Ok(LogIter {
         config: config.clone(),
         max_lsn: Some(end_of_last_msg),
-        cur_lsn: None,
+        cur_lsn: Some(lsn),
         segment_base: None,
         segments,
         last_stage: true,
     })
 }
+
+#[test]
+fn recovery_skips_messages_it_already_applied() {
+    use std::{
+        fs::OpenOptions,
+        io::{Read, Seek, SeekFrom, Write},
+        path::Path,
+        sync::atomic::Ordering::SeqCst,
+    };
+
+    let path = "iterator_skips_applied_db_test";
+    let _ = std::fs::remove_dir_all(path);
+    let open = || Config::new().path(path).open().unwrap();
+
+    let written = open();
+    for i in 0..100_u8 {
+        written.open_tree(b"old").unwrap().insert([i], vec![i]).unwrap();
+    }
+    drop(written);
+
+    // recovery writes a snapshot that covers the whole log,
+    // so everything written after this is only in the log
+    let recovered = open();
+    let old = recovered.open_tree(b"old").unwrap();
+    let lid = {
+        let guard = pin();
+        let root = old.root.load(SeqCst);
+        let mut view = old.view_for_pid(root, &guard).unwrap().unwrap();
+        while view.data.is_index() {
+            let (_, next) = view.index_next_node(&[50]);
+            view = old.view_for_pid(next, &guard).unwrap().unwrap();
+        }
+        let base = view.node_view.0.cache_infos[0];
+        base.pointer.lid() + base.log_size - 1
+    };
+    drop(old);
+    for i in 0..100_u8 {
+        recovered.open_tree(b"new").unwrap().insert([i], vec![i]).unwrap();
+    }
+    recovered.flush().unwrap();
+    drop(recovered);
+
+    // damage a message below the snapshot. reading the log
+    // from the start of its segment would stop there, and
+    // lose the writes after the snapshot.
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(Path::new(path).join("db"))
+        .unwrap();
+    let mut byte = [0];
+    file.seek(SeekFrom::Start(lid)).unwrap();
+    file.read_exact(&mut byte).unwrap();
+    byte[0] ^= 0xFF;
+    file.seek(SeekFrom::Start(lid)).unwrap();
+    file.write_all(&byte).unwrap();
+    drop(file);
+
+    let reopened = open();
+    let new = reopened.open_tree(b"new").unwrap();
+    for i in 0..100_u8 {
+        assert_eq!(new.get([i]).unwrap(), Some(IVec::from(&[i])));
+    }
+    match reopened.open_tree(b"old").unwrap().get([50]) {
+        Err(Error::Corruption { .. }) => {}
+        other => panic!("expected corruption, got {:?}", other),
+    }
+    new.insert([100], vec![100]).unwrap();
+    drop(new);
+    drop(reopened);
+
+    std::fs::remove_dir_all(path).unwrap();
+}
@@ -1,10 +1,10 @@
-use std::fs::File;
+use std::sync::atomic::AtomicBool;
 
 use super::{
     arr_to_lsn, arr_to_u32, assert_usize, bump_atomic_lsn, iobuf, lsn_to_arr,
-    maybe_decompress, pread_exact, pread_exact_or_eof, read_blob, roll_iobuf,
-    u32_to_arr, Arc, BasedBuf, BlobPointer, DiskPtr, IoBuf, IoBufs, LogKind,
-    LogOffset, Lsn, MessageKind, Reservation, Serialize, Snapshot,
+    maybe_decompress, read_blob, read_snapshot_or_default, roll_iobuf,
+    u32_to_arr, Arc, BasedBuf, BlobPointer, DiskPtr, IoBackend, IoBuf, IoBufs,
+    LogKind, LogOffset, Lsn, MessageKind, Reservation, Serialize, Snapshot,
     BATCH_MANIFEST_PID, COUNTER_PID, MAX_MSG_HEADER_LEN, META_PID,
     MINIMUM_ITEMS_PER_SEGMENT, SEG_HEADER_LEN,
 };
@@ -20,6 +20,8 @@ pub struct Log {
     /// iobufs is the underlying lock-free IO write buffer.
     pub(crate) iobufs: Arc<IoBufs>,
     pub(crate) config: RunningConfig,
+    /// Set by `Db::close`, see `write_shutdown_snapshot`.
+    pub(crate) snapshot_on_drop: AtomicBool,
 }
 
 impl Log {
@@ -28,7 +30,11 @@ impl Log {
     pub fn start(config: RunningConfig, snapshot: &Snapshot) -> Result<Self> {
         let iobufs = Arc::new(IoBufs::start(config.clone(), snapshot)?);
 
-        Ok(Self { iobufs, config })
+        Ok(Self {
+            iobufs,
+            config,
+            snapshot_on_drop: AtomicBool::new(false),
+        })
     }
 
     /// Flushes any pending IO buffers to disk to ensure durability.
@@ -37,6 +43,15 @@ impl Log {
         iobuf::flush(&self.iobufs)
     }
 
+    /// Flushes like `flush`, reporting progress after each
+    /// IO buffer that was made stable.
+    pub(crate) fn flush_with_progress(
+        &self,
+        progress: &mut dyn FnMut(FlushProgress) -> ControlFlow,
+    ) -> Result<FlushProgress> {
+        iobuf::flush_with_progress(&self.iobufs, progress)
+    }
+
     /// Return an iterator over the log, starting with
     /// a specified offset.
     pub fn iter_from(&self, lsn: Lsn) -> super::LogIter {
@@ -47,6 +62,24 @@ impl Log {
         roll_iobuf(&self.iobufs)
     }
 
+    /// Advances the snapshot to the tip of the log once the
+    /// last handle is gone after `Db::close`, so that the
+    /// next recovery finds nothing to read in the log,
+    /// instead of everything that was written since the
+    /// database was last opened.
+    /// If the process crashes before this or writes more
+    /// after it, recovery reads the log from wherever the
+    /// latest snapshot ends, as usual.
+    ///
+    /// This runs the same code as recovery, which repairs
+    /// the tail of the log, so it is only safe once nothing
+    /// can write to the log anymore.
+    fn write_shutdown_snapshot(&self) -> Result<()> {
+        self.iobufs.segment_accountant.lock().wait_for_truncations()?;
+        let _snapshot = read_snapshot_or_default(&self.config)?;
+        Ok(())
+    }
+
     /// read a buffer from the disk
     pub fn read(&self, pid: PageId, lsn: Lsn, ptr: DiskPtr) -> Result<LogRead> {
         trace!("reading log lsn {} ptr {}", lsn, ptr);
@@ -84,6 +117,13 @@ impl Log {
         self.iobufs.stable()
     }
 
+    /// returns the highest log sequence number reserved so
+    /// far, which is at least the lsn of every write that
+    /// has completed
+    pub(crate) fn max_reserved_lsn(&self) -> Lsn {
+        self.iobufs.max_reserved_lsn.load(Acquire)
+    }
+
     /// blocks until the specified log sequence number has
     /// been made stable on disk. Returns the number of
     /// bytes written during this call. this is appropriate
@@ -435,8 +475,8 @@ impl Log {
             );
             let iobufs = self.iobufs.clone();
             let iobuf = iobuf.clone();
-            threadpool::spawn(move || {
-                if let Err(e) = iobufs.write_to_log(&iobuf) {
+            let _result = self.config.spawn_io(move || {
+                if let Err(e) = iobufs.write_to_log_or_retry(&iobuf) {
                     error!(
                         "hit error while writing iobuf with lsn {}: {:?}",
                         lsn, e
@@ -464,7 +504,17 @@ impl Drop for Log {
         }
 
         if !self.config.temporary {
-            self.config.file.sync_all().unwrap();
+            self.config.sync_log().unwrap();
+
+            // the directory is checked so that a handle that
+            // outlives its removal does not recreate it
+            if self.snapshot_on_drop.load(Acquire)
+                && files::exists(&self.config, &self.config.get_path())
+            {
+                if let Err(e) = self.write_shutdown_snapshot() {
+                    error!("failed to write snapshot from Log::drop: {}", e);
+                }
+            }
         }
 
         debug!("IoBufs dropped");
@@ -612,13 +662,13 @@ impl Into<[u8; SEG_HEADER_LEN]> for SegmentHeader {
 }
 
 pub(crate) fn read_segment_header(
-    file: &File,
+    file: &dyn IoBackend,
     lid: LogOffset,
 ) -> Result<SegmentHeader> {
     trace!("reading segment header at {}", lid);
 
     let mut seg_header_buf = [0; SEG_HEADER_LEN];
-    pread_exact(file, &mut seg_header_buf, lid)?;
+    file.pread_exact(&mut seg_header_buf, lid)?;
     let segment_header = SegmentHeader::from(seg_header_buf);
 
     if segment_header.lsn < Lsn::try_from(lid).unwrap() {
@@ -642,9 +692,9 @@ pub(crate) trait ReadAt {
     ) -> std::io::Result<usize>;
 }
 
-impl ReadAt for File {
+impl ReadAt for dyn IoBackend {
     fn pread_exact(&self, dst: &mut [u8], at: u64) -> std::io::Result<()> {
-        pread_exact(self, dst, at)
+        IoBackend::pread_exact(self, dst, at)
     }
 
     fn pread_exact_or_eof(
@@ -652,7 +702,7 @@ impl ReadAt for File {
         dst: &mut [u8],
         at: u64,
     ) -> std::io::Result<usize> {
-        pread_exact_or_eof(self, dst, at)
+        IoBackend::pread_exact_or_eof(self, dst, at)
     }
 }
 
@@ -701,7 +751,7 @@ impl ReadAt for BasedBuf {
 }
 
 /// read a buffer from the disk
-pub(crate) fn read_message<R: ReadAt>(
+pub(crate) fn read_message<R: ReadAt + ?Sized>(
     file: &R,
     lid: LogOffset,
     expected_segment_number: SegmentNumber,
@@ -763,7 +813,7 @@ pub(crate) fn read_message<R: ReadAt>(
         buf.copy_from_slice(header_cursor[..header_len].as_ref());
     }
 
-    let crc32 = calculate_message_crc32(
+    let crc32 = config.checksum_algorithm.message_checksum(
         msg_header_buf[..message_offset].as_ref(),
         &buf,
     );
@@ -7,6 +7,7 @@ pub mod logger;
 
 mod blob_io;
 mod disk_pointer;
+mod io_backend;
 mod iobuf;
 mod iterator;
 mod pagetable;
@@ -20,7 +21,7 @@ mod reservation;
 mod segment;
 mod snapshot;
 
-use std::{collections::BinaryHeap, ops::Deref};
+use std::{collections::BinaryHeap, mem::size_of, ops::Deref};
 
 use crate::*;
 
@@ -46,6 +47,7 @@ use self::{
 };
 
 pub(crate) use self::{
+    io_backend::sync_file,
     logger::{
         read_message, read_segment_header, MessageHeader, SegmentHeader,
         SegmentNumber,
@@ -60,6 +62,7 @@ pub use self::{
         SEG_HEADER_LEN,
     },
     disk_pointer::DiskPtr,
+    io_backend::IoBackend,
     logger::{Log, LogRead},
 };
 
@@ -84,6 +87,17 @@ pub type PageId = u64;
 #[repr(transparent)]
 pub struct BatchManifest(pub Lsn);
 
+/// The contents of the counter page, which persists the ID
+/// generator and the write generation every
+/// `idgen_persist_interval`, see `PageCache::generate_id`.
+/// Databases written before the write generation was
+/// persisted have no value for it, which is read as 0.
+#[derive(Default, Clone, Copy, Eq, PartialEq, Debug)]
+pub(crate) struct Counters {
+    pub(crate) idgen: u64,
+    pub(crate) write_generation: u64,
+}
+
 /// A buffer with an associated offset. Useful for
 /// batching many reads over a file segment.
 #[derive(Debug)]
@@ -353,7 +367,7 @@ pub(crate) enum Update {
     Link(Link),
     Node(Node),
     Free,
-    Counter(u64),
+    Counter(Counters),
     Meta(Meta),
 }
 
@@ -387,9 +401,9 @@ impl Update {
         }
     }
 
-    pub(crate) fn as_counter(&self) -> u64 {
-        if let Update::Counter(counter) = self {
-            *counter
+    pub(crate) fn as_counter(&self) -> Counters {
+        if let Update::Counter(counters) = self {
+            *counters
         } else {
             panic!("called as_counter on {:?}", self)
         }
@@ -433,6 +447,10 @@ impl<'a> RecoveryGuard<'a> {
 pub struct Page {
     pub(crate) update: Option<Box<Update>>,
     pub(crate) cache_infos: Vec<CacheInfo>,
+    /// Set when the in-memory node holds writes that were
+    /// coalesced instead of being logged, which are written
+    /// out with the whole node by `PageCache::log_coalesced`.
+    pub(crate) unlogged: bool,
 }
 
 impl Page {
@@ -466,7 +484,7 @@ impl Page {
         self.update.as_ref().unwrap().as_meta()
     }
 
-    pub(crate) fn as_counter(&self) -> u64 {
+    pub(crate) fn as_counter(&self) -> Counters {
         self.update.as_ref().unwrap().as_counter()
     }
 
@@ -483,6 +501,26 @@ impl Page {
         self.cache_infos.iter().map(|ci| ci.log_size).sum()
     }
 
+    /// The log space taken by the last link or replacement
+    /// of this page.
+    pub(crate) fn last_log_size(&self) -> u64 {
+        self.cache_infos.last().map_or(0, |ci| ci.log_size)
+    }
+
+    /// Returns an estimate of the memory held by this page
+    /// while it is cached, which is what counts towards
+    /// `Config::cache_capacity_bytes`.
+    pub(crate) fn memory_size(&self) -> u64 {
+        let update = self.update.as_ref().map_or(0, |update| match **update {
+            Update::Node(ref node) => node.rss(),
+            Update::Meta(ref meta) => meta.rss(),
+            Update::Counter(_) | Update::Link(_) | Update::Free => 0,
+        });
+        let cache_infos = size_of::<CacheInfo>() as u64
+            * self.cache_infos.len() as u64;
+        size_of::<Page>() as u64 + cache_infos + update
+    }
+
     fn ts(&self) -> u64 {
         self.cache_infos.last().map_or(0, |ci| ci.ts)
     }
@@ -504,11 +542,15 @@ pub struct PageCache {
     inner: PageTable,
     next_pid_to_allocate: AtomicU64,
     free: Arc<Mutex<BinaryHeap<PageId>>>,
+    coalesced: Mutex<FastSet8<PageId>>,
     #[doc(hidden)]
     pub log: Log,
     lru: Lru,
     idgen: Arc<AtomicU64>,
     idgen_persists: Arc<AtomicU64>,
+    write_generation_persists: Arc<AtomicU64>,
+    // also serializes persisting the write generation, which
+    // shares the counter page
     idgen_persist_mu: Arc<Mutex<()>>,
     was_recovered: bool,
 }
@@ -637,19 +679,21 @@ impl PageCache {
 
         let _measure = Measure::new(&M.start_pagecache);
 
-        let cache_capacity = config.cache_capacity;
-        let lru = Lru::new(cache_capacity);
+        let cache_capacity = config.effective_cache_capacity();
+        let lru = Lru::new(cache_capacity, config.cache_mode);
 
         let mut pc = Self {
             config: config.clone(),
             inner: PageTable::default(),
             next_pid_to_allocate: AtomicU64::new(0),
             free: Arc::new(Mutex::new(BinaryHeap::new())),
+            coalesced: Mutex::new(FastSet8::default()),
             log: Log::start(config, &snapshot)?,
             lru,
             idgen_persist_mu: Arc::new(Mutex::new(())),
             idgen: Arc::new(AtomicU64::new(0)),
             idgen_persists: Arc::new(AtomicU64::new(0)),
+            write_generation_persists: Arc::new(AtomicU64::new(0)),
             was_recovered: false,
         };
 
@@ -702,11 +746,11 @@ impl PageCache {
                 );
             }
 
-            if let Err(Error::ReportableBug(..)) = pc.get_idgen(&guard) {
+            if let Err(Error::ReportableBug(..)) = pc.get_counters(&guard) {
                 // set up idgen
                 was_recovered = false;
 
-                let counter_update = Update::Counter(0);
+                let counter_update = Update::Counter(Counters::default());
 
                 let (counter_id, _) =
                     pc.allocate_inner(counter_update, &guard)?;
@@ -718,7 +762,8 @@ impl PageCache {
                 );
             }
 
-            let (_, counter) = pc.get_idgen(&guard)?;
+            let (_, counters) = pc.get_counters(&guard)?;
+            let counter = counters.idgen;
             let idgen_recovery = if was_recovered {
                 counter + (2 * pc.config.idgen_persist_interval)
             } else {
@@ -729,6 +774,8 @@ impl PageCache {
 
             pc.idgen.store(idgen_recovery, Release);
             pc.idgen_persists.store(idgen_persists, Release);
+            pc.write_generation_persists
+                .store(counters.write_generation, Release);
         }
 
         pc.was_recovered = was_recovered;
@@ -753,9 +800,84 @@ impl PageCache {
     /// Flushes any pending IO buffers to disk to ensure durability.
     /// Returns the number of bytes written during this call.
     pub(crate) fn flush(&self) -> Result<usize> {
+        self.log_coalesced()?;
         self.log.flush()
     }
 
+    /// Flushes like `flush`, reporting progress after each
+    /// IO buffer that was made stable, and stopping early if
+    /// `progress` returns `ControlFlow::Break`.
+    pub(crate) fn flush_with_progress(
+        &self,
+        progress: &mut dyn FnMut(FlushProgress) -> ControlFlow,
+    ) -> Result<FlushProgress> {
+        self.log_coalesced()?;
+        self.log.flush_with_progress(progress)
+    }
+
+    /// Logs every node that holds writes that were coalesced
+    /// by `link_coalesced`, each as a single replacement of
+    /// the whole node. This must happen before the log is
+    /// flushed for those writes to become durable.
+    pub(crate) fn log_coalesced(&self) -> Result<()> {
+        if !self.config.coalesce_writes {
+            return Ok(());
+        }
+
+        let pids: Vec<PageId> = self.coalesced.lock().drain().collect();
+        if pids.is_empty() {
+            return Ok(());
+        }
+
+        let guard = pin();
+        for pid in pids {
+            self.log_unlogged(pid, &guard)?;
+        }
+        Ok(())
+    }
+
+    /// Logs the whole node of `pid` if it holds coalesced
+    /// writes. The node itself is unchanged, so this is done
+    /// as a rewrite that does not cause concurrent links and
+    /// replacements to fail.
+    fn log_unlogged(&self, pid: PageId, guard: &Guard) -> Result<()> {
+        loop {
+            let page_view = match self.inner.get(pid, guard) {
+                Some(page_view) => page_view,
+                None => return Ok(()),
+            };
+            if !page_view.unlogged {
+                return Ok(());
+            }
+
+            let node = page_view.as_node().clone();
+            let update = Update::Node(node);
+            if self.cas_page(pid, page_view, update, true, guard)?.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the bytes of cached pages that count towards
+    /// `Config::cache_capacity_bytes`.
+    pub(crate) fn cache_resident_bytes(&self) -> u64 {
+        self.lru.resident_bytes()
+    }
+
+    /// Returns the current capacity of the cache.
+    pub(crate) fn cache_capacity(&self) -> u64 {
+        self.lru.capacity()
+    }
+
+    /// Changes the capacity of the cache, paging out the
+    /// pages that no longer fit.
+    pub(crate) fn set_cache_capacity(&self, capacity: u64) -> Result<()> {
+        let guard = pin();
+        let to_evict = self.lru.set_capacity(capacity);
+        trace!("cache capacity set to {}, paging out {:?}", capacity, to_evict);
+        self.page_out(to_evict, &guard)
+    }
+
     /// Create a new page, trying to reuse old freed pages if possible
     /// to maximize underlying `PageTable` pointer density. Returns
     /// the page ID and its pointer for use in future atomic `replace`
@@ -797,7 +919,11 @@ impl PageCache {
 
             trace!("allocating pid {} for the first time", pid);
 
-            let new_page = Page { update: None, cache_infos: Vec::default() };
+            let new_page = Page {
+                update: None,
+                cache_infos: Vec::default(),
+                unlogged: false,
+            };
 
             let page_view = self.inner.insert(pid, new_page, guard);
 
@@ -997,6 +1123,7 @@ impl PageCache {
         let mut new_page = Some(Owned::new(Page {
             update: Some(Box::new(Update::Node(node))),
             cache_infos: Vec::default(),
+            unlogged: false,
         }));
 
         loop {
@@ -1037,6 +1164,8 @@ impl PageCache {
 
             let mut page_ptr = new_page.take().unwrap();
             page_ptr.cache_infos = new_cache_infos;
+            // coalesced writes are only logged with the whole node
+            page_ptr.unlogged = old.unlogged;
 
             debug_delay();
             let result =
@@ -1063,7 +1192,7 @@ impl PageCache {
 
                     // possibly evict an item now that our cache has grown
                     let total_page_size =
-                        unsafe { new_shared.deref().log_size() };
+                        unsafe { new_shared.deref().memory_size() };
                     let to_evict =
                         self.lru.accessed(pid, total_page_size, guard);
                     trace!(
@@ -1102,6 +1231,96 @@ impl PageCache {
         }
     }
 
+    /// Like `link`, but only applies `new` to the node in
+    /// memory, without logging it. The node is logged as a
+    /// whole by `log_coalesced` the next time the log is
+    /// flushed, or before it is paged out, so later links to
+    /// it before then cost no log space. Used for single-key
+    /// writes when `Config::coalesce_writes` is set.
+    pub(crate) fn link_coalesced<'g>(
+        &'g self,
+        pid: PageId,
+        mut old: PageView<'g>,
+        new: Link,
+        guard: &'g Guard,
+    ) -> Result<CasResult<'g, Link>> {
+        let _measure = Measure::new(&M.link_page);
+
+        trace!("linking pid {} with coalesced {:?}", pid, new);
+
+        let mut node: Node = old.as_node().clone();
+        node.apply(&new);
+
+        let mut new_page = Some(Owned::new(Page {
+            update: Some(Box::new(Update::Node(node))),
+            cache_infos: Vec::default(),
+            unlogged: true,
+        }));
+
+        loop {
+            // nothing is logged, but the ts is bumped like in
+            // `link` so that links and replacements that were
+            // based on the old node still fail.
+            let mut new_cache_infos = old.cache_infos.clone();
+            new_cache_infos.last_mut().unwrap().ts += 1;
+
+            let mut page_ptr = new_page.take().unwrap();
+            page_ptr.cache_infos = new_cache_infos;
+
+            debug_delay();
+            let result =
+                old.entry.compare_and_set(old.read, page_ptr, SeqCst, guard);
+
+            match result {
+                Ok(new_shared) => {
+                    trace!("coalesced link of pid {} succeeded", pid);
+
+                    unsafe {
+                        guard.defer_destroy(old.read);
+                    }
+
+                    let _ = self.coalesced.lock().insert(pid);
+
+                    // possibly evict an item now that our cache has grown
+                    let total_page_size =
+                        unsafe { new_shared.deref().memory_size() };
+                    let to_evict =
+                        self.lru.accessed(pid, total_page_size, guard);
+                    if !to_evict.is_empty() {
+                        self.page_out(to_evict, guard)?;
+                    }
+
+                    old.read = new_shared;
+
+                    return Ok(Ok(old));
+                }
+                Err(cas_error) => {
+                    let actual = cas_error.current;
+                    let actual_ts = unsafe { actual.deref().ts() };
+                    if actual_ts == old.ts() {
+                        trace!(
+                            "coalesced link of pid {} failed due to \
+                             movement, retrying",
+                            pid
+                        );
+                        new_page = Some(cas_error.new);
+
+                        old.read = actual;
+                    } else {
+                        trace!(
+                            "coalesced link of pid {} failed due to \
+                             new update",
+                            pid
+                        );
+                        let mut page_view = old;
+                        page_view.read = actual;
+                        return Ok(Err(Some((page_view, new))));
+                    }
+                }
+            }
+        }
+    }
+
     /// Node an existing page with a different set of `PageLink`s.
     /// Returns `Ok(new_key)` if the operation was successful. Returns
     /// `Err(None)` if the page no longer exists. Returns
@@ -1172,6 +1391,34 @@ impl PageCache {
         }))
     }
 
+    /// Sets a page to `new` without reading what it held
+    /// before, so that pages that fail their checksum can be
+    /// replaced. See `RecoveryPolicy::BestEffort`.
+    pub(crate) fn overwrite(
+        &self,
+        pid: PageId,
+        new: Node,
+        guard: &Guard,
+    ) -> Result<()> {
+        let mut update = Update::Node(new);
+        loop {
+            let old = match self.inner.get(pid, guard) {
+                Some(old) => old,
+                None => {
+                    return Err(Error::ReportableBug(format!(
+                        "tried to overwrite pid {} that was never allocated",
+                        pid
+                    )));
+                }
+            };
+
+            match self.cas_page(pid, old, update, false, guard)? {
+                Ok(_) => return Ok(()),
+                Err(fail) => update = fail.unwrap().1,
+            }
+        }
+    }
+
     // rewrite a page so we can reuse the segment that it is
     // (at least partially) located in. This happens when a
     // segment has had enough resident page replacements moved
@@ -1227,6 +1474,7 @@ impl PageCache {
                 let new_page = Owned::new(Page {
                     update: page_view.update.clone(),
                     cache_infos: vec![cache_info],
+                    unlogged: page_view.unlogged,
                 });
 
                 debug_delay();
@@ -1259,7 +1507,7 @@ impl PageCache {
 
                     // possibly evict an item now that our cache has grown
                     let total_page_size =
-                        unsafe { new_shared.deref().log_size() };
+                        unsafe { new_shared.deref().memory_size() };
                     let to_evict =
                         self.lru.accessed(pid, total_page_size, guard);
                     trace!(
@@ -1287,8 +1535,8 @@ impl PageCache {
                     let meta_view = self.get_meta(guard)?;
                     (meta_view.0, Update::Meta(meta_view.deref().clone()))
                 } else if pid == COUNTER_PID {
-                    let (key, counter) = self.get_idgen(guard)?;
-                    (key, Update::Counter(counter))
+                    let (key, counters) = self.get_counters(guard)?;
+                    (key, Update::Counter(counters))
                 } else if let Some(node_view) = self.get(pid, guard)? {
                     (node_view.0, Update::Node(node_view.deref().clone()))
                 } else {
@@ -1347,25 +1595,27 @@ impl PageCache {
         Ok(on_disk_bytes / logical_size)
     }
 
+    /// Returns the state and live bytes of every segment
+    /// that is not free, in order of offset.
+    #[cfg(feature = "debug")]
+    pub(crate) fn segment_structure(&self) -> Result<Vec<SegmentStructure>> {
+        let guard = pin();
+        self.log.iobufs.sa_structure(&guard)
+    }
+
     pub(crate) fn size_on_disk(&self) -> Result<u64> {
-        let mut size = self.config.file.metadata()?.len();
+        let mut size = self.config.file.size()?;
 
         let stable = self.config.blob_path(0);
         let blob_dir = stable.parent().expect(
             "should be able to determine the parent for the blob directory",
         );
-        let blob_files = std::fs::read_dir(blob_dir)?;
+        let blob_files = files::read_dir(&self.config, blob_dir)?;
 
         for blob_file in blob_files {
-            let blob_file = if let Ok(bf) = blob_file {
-                bf
-            } else {
-                continue;
-            };
-
             // it's possible the blob file was removed lazily
             // in the background and no longer exists
-            size += blob_file.metadata().map(|m| m.len()).unwrap_or(0);
+            size += files::file_len(&self.config, &blob_file).unwrap_or(0);
         }
 
         Ok(size)
@@ -1408,6 +1658,7 @@ impl PageCache {
         let mut new_page = Some(Owned::new(Page {
             update: Some(Box::new(update)),
             cache_infos: Vec::default(),
+            unlogged: false,
         }));
 
         loop {
@@ -1485,7 +1736,7 @@ impl PageCache {
 
                     // possibly evict an item now that our cache has grown
                     let total_page_size =
-                        unsafe { new_shared.deref().log_size() };
+                        unsafe { new_shared.deref().memory_size() };
                     let to_evict =
                         self.lru.accessed(pid, total_page_size, guard);
                     trace!(
@@ -1558,11 +1809,12 @@ impl PageCache {
         }
     }
 
-    /// Retrieve the current persisted IDGEN value
-    pub(crate) fn get_idgen<'g>(
+    /// Retrieve the current persisted IDGEN and write
+    /// generation values
+    pub(crate) fn get_counters<'g>(
         &self,
         guard: &'g Guard,
-    ) -> Result<(PageView<'g>, u64)> {
+    ) -> Result<(PageView<'g>, Counters)> {
         trace!("getting page iter for idgen");
 
         let page_view = match self.inner.get(COUNTER_PID, guard) {
@@ -1577,8 +1829,8 @@ impl PageCache {
         };
 
         if page_view.update.is_some() {
-            let counter = page_view.as_counter();
-            Ok((page_view, counter))
+            let counters = page_view.as_counter();
+            Ok((page_view, counters))
         } else {
             Err(Error::ReportableBug(
                 "failed to retrieve counter page \
@@ -1626,7 +1878,7 @@ impl PageCache {
 
             if page_view.update.is_some() {
                 // possibly evict an item now that our cache has grown
-                let total_page_size = page_view.log_size();
+                let total_page_size = page_view.memory_size();
                 let to_evict = self.lru.accessed(pid, total_page_size, guard);
                 trace!(
                     "accessed pid {} -> paging out pids {:?}",
@@ -1683,6 +1935,7 @@ impl PageCache {
         let page = Owned::new(Page {
             update: Some(Box::new(base)),
             cache_infos: page_view.cache_infos.clone(),
+            unlogged: false,
         });
 
         debug_delay();
@@ -1701,7 +1954,7 @@ impl PageCache {
             }
 
             // possibly evict an item now that our cache has grown
-            let total_page_size = unsafe { new_shared.deref().log_size() };
+            let total_page_size = unsafe { new_shared.deref().memory_size() };
             let to_evict = self.lru.accessed(pid, total_page_size, guard);
             trace!("accessed pid {} -> paging out pids {:?}", pid, to_evict);
             if !to_evict.is_empty() {
@@ -1763,11 +2016,14 @@ impl PageCache {
                     necessary_persists
                 );
                 let guard = pin();
-                let (key, current) = self.get_idgen(&guard)?;
+                let (key, current) = self.get_counters(&guard)?;
 
-                assert_eq!(current, persisted);
+                assert_eq!(current.idgen, persisted);
 
-                let counter_update = Update::Counter(necessary_persists);
+                let counter_update = Update::Counter(Counters {
+                    idgen: necessary_persists,
+                    ..current
+                });
 
                 let old = self.idgen_persists.swap(necessary_persists, Release);
                 assert_eq!(old, persisted);
@@ -1795,6 +2051,102 @@ impl PageCache {
         Ok(ret)
     }
 
+    /// Returns the write generation that a `Db` starts from,
+    /// which is above every generation that was handed out
+    /// before it was last closed or crashed. It is recovered
+    /// like the ID generator, see `persist_write_generation`.
+    /// Databases that did not persist it yet derive it from
+    /// the log tip instead, which every write used to take up
+    /// at least a byte of.
+    pub(crate) fn recovered_write_generation(&self) -> u64 {
+        let stable_lsn =
+            u64::try_from(self.log.stable_offset().max(0)).unwrap();
+        let persisted = if self.was_recovered {
+            self.write_generation_persists.load(Acquire)
+                + (2 * self.config.idgen_persist_interval)
+        } else {
+            0
+        };
+        persisted.max(stable_lsn)
+    }
+
+    /// Persists the write generation once a write bumps it
+    /// to `generation`, in the same way that `generate_id`
+    /// persists the ID generator: every
+    /// `idgen_persist_interval` writes, and recovery adds
+    /// twice that interval to the last persisted value. Must
+    /// be called with the concurrency control held, like any
+    /// other write to a page.
+    pub(crate) fn persist_write_generation(
+        &self,
+        generation: u64,
+    ) -> Result<()> {
+        let interval = self.config.idgen_persist_interval;
+        let necessary_persists = generation / interval * interval;
+        let mut persisted = self.write_generation_persists.load(Acquire);
+
+        while persisted < necessary_persists {
+            let _mu = self.idgen_persist_mu.lock();
+            persisted = self.write_generation_persists.load(Acquire);
+            if persisted < necessary_persists {
+                trace!(
+                    "persisting write generation {}, as {} was persisted last",
+                    necessary_persists,
+                    persisted
+                );
+                let guard = pin();
+                let (key, current) = self.get_counters(&guard)?;
+
+                assert_eq!(current.write_generation, persisted);
+
+                let counter_update = Update::Counter(Counters {
+                    write_generation: necessary_persists,
+                    ..current
+                });
+
+                let old = self
+                    .write_generation_persists
+                    .swap(necessary_persists, Release);
+                assert_eq!(old, persisted);
+
+                if self
+                    .cas_page(COUNTER_PID, key, counter_update, false, &guard)?
+                    .is_err()
+                {
+                    // CAS failed
+                    continue;
+                }
+
+                // see `generate_id`
+                iobuf::make_durable(&self.log.iobufs, key.last_lsn())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the next ID that `generate_id` would return.
+    pub(crate) fn next_id(&self) -> u64 {
+        self.idgen.load(Acquire)
+    }
+
+    /// Makes `generate_id` return IDs of at least `next`
+    /// from now on, even after a restart.
+    pub(crate) fn skip_ids_below(&self, next: u64) -> Result<()> {
+        let mut current = self.idgen.load(Acquire);
+        while current < next {
+            current = match self
+                .idgen
+                .compare_exchange(current, next, SeqCst, SeqCst)
+            {
+                Ok(_) => break,
+                Err(actual) => actual,
+            };
+        }
+        // persists the counter up to `next`
+        self.generate_id().map(|_| ())
+    }
+
     /// Look up a `PageId` for a given identifier in the `Meta`
     /// mapping. This is pretty cheap, but in some cases
     /// you may prefer to maintain your own atomic references
@@ -1879,9 +2231,15 @@ impl PageCache {
                         // don't page-out Freed suckas
                         break;
                     }
+                    if page_view.unlogged {
+                        // coalesced writes only exist in memory
+                        self.log_unlogged(pid, guard)?;
+                        continue;
+                    }
                     let new_page = Owned::new(Page {
                         update: None,
                         cache_infos: page_view.cache_infos.clone(),
+                        unlogged: false,
                     });
 
                     debug_delay();
@@ -1970,7 +2328,7 @@ impl PageCache {
             let _deserialize_latency = Measure::new(&M.deserialize);
 
             match header.kind {
-                Counter => u64::deserialize(buf).map(Update::Counter),
+                Counter => Counters::deserialize(buf).map(Update::Counter),
                 BlobMeta | InlineMeta => {
                     Meta::deserialize(buf).map(Update::Meta)
                 }
@@ -2070,7 +2428,7 @@ impl PageCache {
             } else {
                 None
             };
-            let page = Page { update, cache_infos };
+            let page = Page { update, cache_infos, unlogged: false };
 
             self.inner.insert(pid, page, &guard);
         }
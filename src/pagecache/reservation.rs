@@ -122,7 +122,7 @@ impl<'a> Reservation<'a> {
             self.buf[4] = MessageKind::Canceled.into();
         }
 
-        let crc32 = calculate_message_crc32(
+        let crc32 = self.log.config.checksum_algorithm.message_checksum(
             self.buf[..self.header_len].as_ref(),
             &self.buf[self.header_len..],
         );
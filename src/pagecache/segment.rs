@@ -174,6 +174,7 @@ struct Inactive {
 #[derive(Debug, Clone, Default)]
 struct Draining {
     lsn: Lsn,
+    rss: usize,
     max_pids: usize,
     replaced_pids: usize,
     latest_replacement_lsn: Lsn,
@@ -296,6 +297,7 @@ impl Segment {
             let ret = mem::replace(&mut inactive.pids, Default::default());
             *self = Segment::Draining(Draining {
                 lsn: inactive.lsn,
+                rss: inactive.rss,
                 max_pids: inactive.max_pids,
                 replaced_pids: inactive.replaced_pids,
                 latest_replacement_lsn: inactive.latest_replacement_lsn,
@@ -402,6 +404,7 @@ impl Segment {
             }
             Segment::Draining(Draining {
                 lsn,
+                rss,
                 latest_replacement_lsn,
                 replaced_pids,
                 ..
@@ -410,6 +413,7 @@ impl Segment {
                 if replacement_lsn != *lsn {
                     *replaced_pids += 1;
                 }
+                *rss = rss.saturating_sub(sz);
                 if replacement_lsn > *latest_replacement_lsn {
                     *latest_replacement_lsn = replacement_lsn;
                 }
@@ -498,7 +502,7 @@ impl SegmentAccountant {
 
     fn initial_segments(&self, snapshot: &Snapshot) -> Result<Vec<Segment>> {
         let segment_size = self.config.segment_size;
-        let file_len = self.config.file.metadata()?.len();
+        let file_len = self.config.file.size()?;
         let number_of_segments =
             usize::try_from(file_len / segment_size as u64).unwrap()
                 + if file_len % segment_size as u64 == 0 { 0 } else { 1 };
@@ -622,9 +626,7 @@ impl SegmentAccountant {
 
         // we want to complete all truncations because
         // they could cause calls to `next` to block.
-        for (_, promise) in self.async_truncations.split_off(&0) {
-            promise.wait().expect("threadpool should not crash")?;
-        }
+        self.wait_for_truncations()?;
 
         for (idx, segment_lsn) in maybe_clean {
             self.possibly_clean_or_free_segment(idx, segment_lsn)?;
@@ -1050,6 +1052,15 @@ impl SegmentAccountant {
             .collect()
     }
 
+    /// Blocks until every truncation of the file that was
+    /// started in the background has completed.
+    pub(super) fn wait_for_truncations(&mut self) -> Result<()> {
+        for (_, promise) in self.async_truncations.split_off(&0) {
+            promise.wait().expect("threadpool should not crash")?;
+        }
+        Ok(())
+    }
+
     // truncate the file to the desired length
     fn truncate(&mut self, at: LogOffset) -> Result<()> {
         trace!("asynchronously truncating file to length {}", at);
@@ -1068,12 +1079,12 @@ impl SegmentAccountant {
 
         let config = self.config.clone();
 
-        threadpool::spawn(move || {
+        self.config.spawn_io(move || {
             debug!("truncating file to length {}", at);
             let res = config
                 .file
                 .set_len(at)
-                .and_then(|_| config.file.sync_all())
+                .and_then(|_| config.sync_log())
                 .map_err(|e| e.into());
             completer.fill(res);
         });
@@ -1089,6 +1100,45 @@ impl SegmentAccountant {
         Ok(())
     }
 
+    /// Returns the state and live bytes of every segment
+    /// that is not free, for `Db::structure_report`.
+    #[cfg(feature = "debug")]
+    pub(super) fn structure(&self) -> Vec<SegmentStructure> {
+        let segment_size = self.config.segment_size as u64;
+        let mut ret = vec![];
+        for (idx, segment) in self.segments.iter().enumerate() {
+            let (state, live_bytes, dead_bytes) = match segment {
+                Segment::Free(_) => continue,
+                // the rest of the active segment is not
+                // written yet, so only replaced pages are dead
+                Segment::Active(active) => {
+                    let live = active
+                        .rss
+                        .saturating_sub(active.deferred_replaced_rss);
+                    let dead = active.deferred_replaced_rss;
+                    (SegmentState::Active, live as u64, dead as u64)
+                }
+                Segment::Inactive(Inactive { rss, .. }) => {
+                    let live = *rss as u64;
+                    let dead = segment_size.saturating_sub(live);
+                    (SegmentState::Inactive, live, dead)
+                }
+                Segment::Draining(Draining { rss, .. }) => {
+                    let live = *rss as u64;
+                    let dead = segment_size.saturating_sub(live);
+                    (SegmentState::Draining, live, dead)
+                }
+            };
+            ret.push(SegmentStructure {
+                offset: idx as LogOffset * segment_size,
+                state,
+                live_bytes,
+                dead_bytes,
+            });
+        }
+        ret
+    }
+
     fn segment_id(&mut self, lid: LogOffset) -> SegmentId {
         let idx = assert_usize(lid / self.config.segment_size as LogOffset);
 
@@ -0,0 +1,96 @@
+use std::ops::Deref;
+
+use crate::*;
+
+/// A value borrowed directly out of the page cache,
+/// returned by `Tree::get_pinned`. It dereferences to
+/// the value's bytes without copying them into a
+/// new `IVec`.
+///
+/// # Lifetime rules
+///
+/// A `PinnedValue` holds an epoch guard that keeps the
+/// version of the page it was read from alive until the
+/// `PinnedValue` is dropped. Writers are never blocked
+/// by it: concurrent writes to the same key will install
+/// new versions of the page as usual, and this
+/// `PinnedValue` will keep pointing to the bytes that
+/// were present at the time it was read, which are never
+/// mutated in place. In other words, it behaves like a
+/// snapshot of a single value.
+///
+/// Because the guard participates in epoch-based
+/// reclamation, memory retired by the system while
+/// any `PinnedValue` is alive can not be reclaimed
+/// until it is dropped. Long-lived pins will cause
+/// memory usage to grow, so they should be dropped
+/// as soon as the value is no longer needed. Call
+/// `to_ivec` if the value must outlive a short scope.
+///
+/// A `PinnedValue` is tied to the thread that created
+/// it, and is neither `Send` nor `Sync`.
+pub struct PinnedValue {
+    // NB `value` points into memory that is only
+    // guaranteed to be valid while this guard
+    // is held, so it must never be released
+    // before the `PinnedValue` is dropped.
+    _guard: CrossbeamGuard,
+    value: *const [u8],
+    // values of trees that deduplicate values are
+    // resolved into an `IVec`, which is held here
+    // instead of pointing into the page
+    resolved: Option<IVec>,
+}
+
+impl PinnedValue {
+    pub(crate) fn new(guard: CrossbeamGuard, value: *const [u8]) -> Self {
+        Self { _guard: guard, value, resolved: None }
+    }
+
+    pub(crate) fn resolved(guard: CrossbeamGuard, value: IVec) -> Self {
+        let empty: &'static [u8] = &[];
+        Self { _guard: guard, value: empty, resolved: Some(value) }
+    }
+
+    /// Copies the pinned value into an `IVec`, which
+    /// does not need to hold an epoch guard.
+    pub fn to_ivec(&self) -> IVec {
+        IVec::from(&**self)
+    }
+}
+
+impl Deref for PinnedValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if let Some(resolved) = &self.resolved {
+            return resolved;
+        }
+        // SAFETY: pages are copy-on-write and are only
+        // reclaimed after all guards that may have
+        // witnessed them are dropped, and we hold one
+        // for our entire lifetime.
+        #[allow(unsafe_code)]
+        unsafe {
+            &*self.value
+        }
+    }
+}
+
+impl AsRef<[u8]> for PinnedValue {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<T: AsRef<[u8]>> PartialEq<T> for PinnedValue {
+    fn eq(&self, other: &T) -> bool {
+        self.as_ref() == other.as_ref()
+    }
+}
+
+impl Debug for PinnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
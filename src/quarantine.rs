@@ -0,0 +1,318 @@
+//! Finds the pages of a tree that fail their checksum, for
+//! `RecoveryPolicy::BestEffort` and
+//! `RecoveryPolicy::ReadOnlyAfterCorruption`.
+//!
+//! The tree is read one level at a time from the root, and
+//! the range of keys of each page comes from the index of
+//! its parent, so the range of a damaged page is known even
+//! though its own bounds can't be read. The pages below a
+//! damaged index node are unknown, and are never read.
+//!
+//! To excise the damage, each damaged page is replaced with
+//! an empty page over the same range. A damaged index node
+//! gets a single child, and so on down to an empty leaf, so
+//! that all leaves stay at the same depth and siblings can
+//! still be merged. Every replacement links to its right
+//! neighbor on its level, and a left neighbor that linked to
+//! a page below a damaged index node is relinked to the
+//! replacement on its level.
+
+use std::num::NonZeroU64;
+
+use crate::{
+    node::{Index, Leaf},
+    *,
+};
+
+struct Slot {
+    pid: PageId,
+    lo: IVec,
+    hi: IVec,
+    state: State,
+}
+
+#[derive(PartialEq)]
+enum State {
+    /// Read successfully, with its link to the right.
+    Read(Option<NonZeroU64>),
+    /// Failed its checksum.
+    Lost,
+    /// Added under a damaged index node.
+    Added,
+}
+
+/// Returns the ranges of `tree` held by pages that fail
+/// their checksum, replacing those pages with empty ones
+/// if `excise` is set.
+pub(crate) fn quarantine(tree: &Tree, excise: bool) -> Result<Vec<LostRange>> {
+    let guard = pin();
+    let pagecache = &tree.context.pagecache;
+
+    let mut levels: Vec<Vec<Slot>> = vec![];
+    let root = tree.root.load(SeqCst);
+    let mut frontier = vec![(root, IVec::default(), IVec::default())];
+
+    while !frontier.is_empty() {
+        let mut level = Vec::with_capacity(frontier.len());
+        let mut children = vec![];
+
+        for (pid, lo, hi) in frontier {
+            let state = match pagecache.get(pid, &guard) {
+                Ok(Some(node)) => {
+                    if let Some(index) = node.data.index_ref() {
+                        let prefix = &node.lo[..node.prefix_len as usize];
+                        for (i, child) in index.pointers.iter().enumerate() {
+                            let child_lo =
+                                prefix::decode(prefix, &index.keys[i]);
+                            let child_hi = index.keys.get(i + 1).map_or_else(
+                                || hi.clone(),
+                                |key| prefix::decode(prefix, key),
+                            );
+                            children.push((*child, child_lo, child_hi));
+                        }
+                    }
+                    State::Read(node.next)
+                }
+                Ok(None) | Err(Error::Corruption { .. }) => State::Lost,
+                Err(e) => return Err(e),
+            };
+            level.push(Slot { pid, lo, hi, state });
+        }
+
+        levels.push(level);
+        frontier = children;
+    }
+
+    let lost: Vec<LostRange> = levels
+        .iter()
+        .flatten()
+        .filter(|slot| slot.state == State::Lost)
+        .map(|slot| LostRange {
+            tree: tree.tree_id.clone(),
+            lo: slot.lo.clone(),
+            hi: if slot.hi.is_empty() { None } else { Some(slot.hi.clone()) },
+        })
+        .collect();
+
+    for range in &lost {
+        error!(
+            "keys of tree {:?} from {:?} to {:?} were lost to corruption",
+            range.tree, range.lo, range.hi
+        );
+    }
+
+    if !excise || lost.is_empty() {
+        return Ok(lost);
+    }
+
+    // every page below a damaged index node is replaced by
+    // a single added page on each level
+    let depth = levels.len();
+    for parent_level in 0..depth - 1 {
+        let damaged: Vec<(IVec, IVec)> = levels[parent_level]
+            .iter()
+            .filter(|slot| slot.state == State::Lost)
+            .map(|slot| (slot.lo.clone(), slot.hi.clone()))
+            .collect();
+
+        for (lo, hi) in damaged {
+            for level in &mut levels[parent_level + 1..] {
+                let (pid, _) = pagecache.allocate(Node::default(), &guard)?;
+                let at = level
+                    .iter()
+                    .position(|slot| slot.lo > lo)
+                    .unwrap_or(level.len());
+                let added = Slot {
+                    pid,
+                    lo: lo.clone(),
+                    hi: hi.clone(),
+                    state: State::Added,
+                };
+                level.insert(at, added);
+            }
+        }
+    }
+
+    // a crash while relinking would leave the tree with
+    // links into the pages that were replaced
+    let peg = tree.context.pin_log(&guard)?;
+
+    for (depth_idx, level) in levels.iter().enumerate() {
+        for (i, slot) in level.iter().enumerate() {
+            let right = level.get(i + 1);
+            let next = right.map(|r| NonZeroU64::new(r.pid).unwrap());
+
+            if let State::Read(stored_next) = slot.state {
+                let relink = stored_next != next
+                    && right.into_iter().any(|r| r.state == State::Added);
+                if relink {
+                    let view = pagecache.get(slot.pid, &guard)?.unwrap();
+                    let mut node: Node = (*view).clone();
+                    node.next = next;
+                    pagecache.overwrite(slot.pid, node, &guard)?;
+                }
+                continue;
+            }
+
+            let data = if depth_idx + 1 == depth {
+                Data::Leaf(Leaf::default())
+            } else {
+                // the added page below has the same range
+                let child = levels[depth_idx + 1]
+                    .iter()
+                    .find(|below| {
+                        below.state == State::Added && below.lo == slot.lo
+                    })
+                    .unwrap();
+                Data::Index(Index {
+                    keys: vec![slot.lo.clone()],
+                    pointers: vec![child.pid],
+                })
+            };
+
+            let node = Node {
+                next,
+                lo: slot.lo.clone(),
+                hi: slot.hi.clone(),
+                data,
+                ..Node::default()
+            };
+            pagecache.overwrite(slot.pid, node, &guard)?;
+        }
+    }
+
+    peg.seal_batch()?;
+
+    Ok(lost)
+}
+
+#[cfg(test)]
+fn damage_and_recover(path: &str, damage_leaf: bool) {
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom},
+        path::Path,
+    };
+
+    let _ = std::fs::remove_dir_all(path);
+    let open = |policy| {
+        Config::new().path(path).recovery_policy(policy).open().unwrap()
+    };
+
+    let written = open(RecoveryPolicy::Strict);
+    for i in 0..100_u8 {
+        written.insert([i], vec![i]).unwrap();
+    }
+    drop(written);
+
+    // recovery writes a snapshot that covers the whole log,
+    // so the damaged message is never replayed, only pulled.
+    // the damaged page is the leaf of the key 50, or the
+    // child of the root on the way to it.
+    let recovered = open(RecoveryPolicy::Strict);
+    let (lid, lo, hi) = {
+        let guard = pin();
+        let root = recovered.root.load(SeqCst);
+        let (_, child) = recovered
+            .view_for_pid(root, &guard)
+            .unwrap()
+            .unwrap()
+            .index_next_node(&[50]);
+        let mut view = recovered.view_for_pid(child, &guard).unwrap().unwrap();
+        assert!(view.data.is_index());
+        while damage_leaf && view.data.is_index() {
+            let (_, next) = view.index_next_node(&[50]);
+            view = recovered.view_for_pid(next, &guard).unwrap().unwrap();
+        }
+        let base = view.node_view.0.cache_infos[0];
+        let lid = base.pointer.lid() + base.log_size - 1;
+        (lid, view.lo.clone(), view.hi.clone())
+    };
+    drop(recovered);
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(Path::new(path).join("db"))
+        .unwrap();
+    let mut byte = [0];
+    file.seek(SeekFrom::Start(lid)).unwrap();
+    file.read_exact(&mut byte).unwrap();
+    byte[0] ^= 0xFF;
+    file.seek(SeekFrom::Start(lid)).unwrap();
+    file.write_all(&byte).unwrap();
+    drop(file);
+
+    let expected = vec![LostRange {
+        tree: IVec::from(DEFAULT_TREE_ID),
+        lo: lo.clone(),
+        hi: if hi.is_empty() { None } else { Some(hi.clone()) },
+    }];
+    let survivors: Vec<IVec> = (0..100_u8)
+        .map(|i| IVec::from(&[i]))
+        .filter(|key| *key < lo || (!hi.is_empty() && *key >= hi))
+        .collect();
+
+    let strict = open(RecoveryPolicy::Strict);
+    assert!(strict.lost_data_report().is_empty());
+    match strict.get([50]) {
+        Err(Error::Corruption { .. }) => {}
+        other => panic!("expected corruption, got {:?}", other),
+    }
+    drop(strict);
+
+    let read_only = open(RecoveryPolicy::ReadOnlyAfterCorruption);
+    assert_eq!(read_only.lost_data_report(), expected);
+    match read_only.insert(&survivors[0], vec![1]) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected the write to be refused, got {:?}", other),
+    }
+    drop(read_only);
+
+    // repairing into a new database leaves the damaged one
+    // as it is
+    let output = format!("{}_repaired", path);
+    let _ = std::fs::remove_dir_all(&output);
+    let report =
+        Db::repair(path, RepairOptions::new().output(&output)).unwrap();
+    assert_eq!(report.path, Path::new(&output));
+    assert_eq!(report.lost_ranges, expected);
+    assert!(report.lost_keys.is_empty());
+    assert_eq!(report.keys, survivors.len() as u64);
+    let repaired = Config::new().path(&output).open().unwrap();
+    let keys: Vec<IVec> = repaired.iter().keys().map(Result::unwrap).collect();
+    assert_eq!(keys, survivors);
+    drop(repaired);
+    std::fs::remove_dir_all(&output).unwrap();
+
+    let best_effort = open(RecoveryPolicy::BestEffort);
+    assert_eq!(best_effort.lost_data_report(), expected);
+    assert_eq!(best_effort.get([50]).unwrap(), None);
+    let keys: Vec<IVec> =
+        best_effort.iter().keys().map(Result::unwrap).collect();
+    assert_eq!(keys, survivors);
+    for i in 0..100_u8 {
+        best_effort.insert([i], vec![i]).unwrap();
+    }
+    drop(best_effort);
+
+    // the replacements were written like any other page
+    let repaired = open(RecoveryPolicy::Strict);
+    assert_eq!(repaired.len(), 100);
+    for i in 0..100_u8 {
+        assert_eq!(repaired.get([i]).unwrap(), Some(IVec::from(&[i])));
+    }
+    drop(repaired);
+
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn damaged_leaves_are_excised() {
+    damage_and_recover("quarantine_leaf_db_test", true);
+}
+
+#[test]
+fn damaged_index_nodes_are_excised() {
+    damage_and_recover("quarantine_index_db_test", false);
+}
@@ -0,0 +1,212 @@
+//! Rebuilds a damaged database from the pages that pass
+//! their checksum, for `Db::repair`.
+//!
+//! The damaged database is opened under
+//! `RecoveryPolicy::ReadOnlyAfterCorruption`, which finds
+//! the ranges of keys held by damaged pages without
+//! changing the file. Every key outside of those ranges is
+//! then copied into a new database, which builds a fresh
+//! index over the surviving leaves.
+
+use std::{
+    fs,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use crate::*;
+
+/// Where `Db::repair` writes the repaired database. By
+/// default, it replaces the damaged one.
+#[derive(Debug, Default, Clone)]
+pub struct RepairOptions {
+    output: Option<PathBuf>,
+}
+
+impl RepairOptions {
+    /// Returns the default `RepairOptions`.
+    pub fn new() -> RepairOptions {
+        RepairOptions::default()
+    }
+
+    /// Writes the repaired database to `path`, which must
+    /// not exist yet, and leaves the damaged one as it is.
+    pub fn output<P: AsRef<Path>>(mut self, path: P) -> RepairOptions {
+        self.output = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
+/// What `Db::repair` salvaged, and what it could not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepairReport {
+    /// The path of the repaired database.
+    pub path: PathBuf,
+    /// The number of trees copied, including the default
+    /// tree.
+    pub trees: usize,
+    /// The number of keys copied, over all trees.
+    pub keys: u64,
+    /// The ranges of keys whose pages failed their
+    /// checksum. None of their keys were copied. With
+    /// `Config::dedup_values`, this includes ranges of the
+    /// store of shared values, whose keys are hashes.
+    pub lost_ranges: Vec<LostRange>,
+    /// The keys whose values could not be read even though
+    /// their own pages were intact, such as those whose
+    /// shared value was lost, as pairs of the tree name and
+    /// the key. These keys were not copied.
+    pub lost_keys: Vec<(IVec, IVec)>,
+}
+
+impl RepairReport {
+    /// Returns `true` if nothing was lost.
+    pub fn is_clean(&self) -> bool {
+        self.lost_ranges.is_empty() && self.lost_keys.is_empty()
+    }
+}
+
+/// Appends `suffix` to the last component of `path`.
+fn sibling(path: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = if let Some(name) = path.file_name() {
+        name
+    } else {
+        return Err(Error::Unsupported(format!(
+            "cannot repair the database at {:?} in place, \
+             because it has no directory name",
+            path
+        )));
+    };
+    let mut suffixed = name.to_os_string();
+    suffixed.push(suffix);
+    Ok(path.with_file_name(suffixed))
+}
+
+pub(crate) fn repair(
+    path: &Path,
+    options: RepairOptions,
+) -> Result<RepairReport> {
+    let source = Config::new()
+        .path(path)
+        .recovery_policy(RecoveryPolicy::ReadOnlyAfterCorruption)
+        .storage_parameters_of(path)?
+        .open()?;
+
+    let in_place = options.output.is_none();
+    let output = if let Some(output) = options.output {
+        output
+    } else {
+        let in_motion = sibling(path, ".repair___in_motion")?;
+        // left behind by a repair that was interrupted
+        if in_motion.exists() {
+            fs::remove_dir_all(&in_motion)?;
+        }
+        in_motion
+    };
+
+    let dest = Config::new()
+        .path(&output)
+        .create_new(true)
+        .storage_parameters_of(path)?
+        .open()?;
+
+    let lost_ranges = source.lost_data_report();
+    let mut report = RepairReport {
+        path: if in_place { path.to_path_buf() } else { output.clone() },
+        trees: 0,
+        keys: 0,
+        lost_ranges: lost_ranges.clone(),
+        lost_keys: vec![],
+    };
+
+    for name in source.tree_names()? {
+        let tree = source.open_tree(&name)?;
+        let copy = dest.open_tree(&name)?;
+        report.trees += 1;
+
+        let mut lost: Vec<&LostRange> =
+            lost_ranges.iter().filter(|lost| lost.tree == name).collect();
+        lost.sort_by(|a, b| a.lo.cmp(&b.lo));
+
+        // the ranges between the lost ones
+        let mut intact = vec![];
+        let mut lo = Some(Bound::Unbounded);
+        for range in lost {
+            match lo.take() {
+                Some(start) if !range.lo.is_empty() => {
+                    intact.push((start, Bound::Excluded(range.lo.clone())));
+                }
+                _ => {}
+            }
+            lo = range.hi.clone().map(Bound::Included);
+        }
+        if let Some(start) = lo {
+            intact.push((start, Bound::Unbounded));
+        }
+
+        for range in intact {
+            let mut iter = tree.range::<IVec, _>(range);
+            while let Some(kv_res) = iter.next_inner() {
+                let (key, stored) = kv_res?;
+                match tree.resolve_current(&key, stored) {
+                    Ok(Some(value)) => {
+                        copy.insert(key, value)?;
+                        report.keys += 1;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!(
+                            "failed to read the value of key {:?} \
+                             of tree {:?} while repairing: {:?}",
+                            key, name, e
+                        );
+                        report.lost_keys.push((name.clone(), key));
+                    }
+                }
+            }
+        }
+    }
+
+    dest.context
+        .pagecache
+        .skip_ids_below(source.context.pagecache.next_id())?;
+    dest.flush()?;
+    drop(dest);
+    drop(source);
+
+    if in_place {
+        // snapshots are images of the trees, so they are
+        // still valid for the repaired database
+        let snapshots = path.join("snapshots");
+        if snapshots.exists() {
+            fs::rename(&snapshots, output.join("snapshots"))?;
+        }
+
+        let old = sibling(path, ".repair___old")?;
+        fs::rename(path, &old)?;
+        fs::rename(&output, path)?;
+        fs::remove_dir_all(&old)?;
+
+        #[cfg(unix)]
+        {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            fs::File::open(parent)?.sync_all()?;
+        }
+    }
+
+    if report.is_clean() {
+        debug!("repaired {:?}, nothing was lost", path);
+    } else {
+        error!(
+            "repaired {:?}, losing {} ranges and {} keys",
+            path,
+            report.lost_ranges.len(),
+            report.lost_keys.len()
+        );
+    }
+
+    Ok(report)
+}
@@ -3,6 +3,7 @@ use std::{
     error::Error as StdError,
     fmt::{self, Display},
     io,
+    path::PathBuf,
 };
 
 use backtrace::Backtrace;
@@ -29,6 +30,28 @@ pub(crate) type CasResult<'a, R> =
 pub enum Error {
     /// The underlying collection no longer exists.
     CollectionNotFound(IVec),
+    /// The `Tree` with the contained name was dropped with
+    /// `Db::drop_tree` after this handle to it was opened.
+    /// The handle stays in this state even if a tree with
+    /// the same name is opened again, see `Tree::is_valid`.
+    TreeDropped(IVec),
+    /// `Config::create_new` was set, but a database
+    /// already exists at the contained path.
+    DatabaseAlreadyExists(PathBuf),
+    /// `Config::must_exist` was set, but no database
+    /// exists at the contained path.
+    DatabaseNotFound(PathBuf),
+    /// Another `Db`, in this or another process, held the
+    /// lock on the database file for longer than
+    /// `Config::open_timeout`.
+    LockHeld {
+        /// The path of the locked database file.
+        path: PathBuf,
+        /// The id of the process holding the lock, if it
+        /// could be determined. This is only attempted
+        /// on linux.
+        pid: Option<u32>,
+    },
     /// The system has been used in an unsupported way.
     Unsupported(String),
     /// An unexpected bug has happened. Please open an issue on github!
@@ -62,6 +85,12 @@ impl Clone for Error {
         match self {
             Io(ioe) => Io(io::Error::new(ioe.kind(), format!("{:?}", ioe))),
             CollectionNotFound(name) => CollectionNotFound(name.clone()),
+            TreeDropped(name) => TreeDropped(name.clone()),
+            DatabaseAlreadyExists(path) => DatabaseAlreadyExists(path.clone()),
+            DatabaseNotFound(path) => DatabaseNotFound(path.clone()),
+            LockHeld { path, pid } => {
+                LockHeld { path: path.clone(), pid: *pid }
+            }
             Unsupported(why) => Unsupported(why.clone()),
             ReportableBug(what) => ReportableBug(what.clone()),
             Corruption { at, bt } => Corruption { at: *at, bt: bt.clone() },
@@ -85,6 +114,34 @@ impl PartialEq for Error {
                     false
                 }
             }
+            TreeDropped(ref l) => {
+                if let TreeDropped(ref r) = *other {
+                    l == r
+                } else {
+                    false
+                }
+            }
+            DatabaseAlreadyExists(ref l) => {
+                if let DatabaseAlreadyExists(ref r) = *other {
+                    l == r
+                } else {
+                    false
+                }
+            }
+            DatabaseNotFound(ref l) => {
+                if let DatabaseNotFound(ref r) = *other {
+                    l == r
+                } else {
+                    false
+                }
+            }
+            LockHeld { path: ref l_path, pid: l_pid } => {
+                if let LockHeld { path: ref r_path, pid: r_pid } = *other {
+                    l_path == r_path && l_pid == r_pid
+                } else {
+                    false
+                }
+            }
             Unsupported(ref l) => {
                 if let Unsupported(ref r) = *other {
                     l == r
@@ -139,6 +196,31 @@ impl Display for Error {
             CollectionNotFound(ref name) => {
                 write!(f, "Collection {:?} does not exist", name,)
             }
+            TreeDropped(ref name) => write!(
+                f,
+                "Tree {:?} was dropped while this handle to it was open",
+                name
+            ),
+            DatabaseAlreadyExists(ref path) => write!(
+                f,
+                "A database already exists at {:?}, \
+                 but create_new was set",
+                path
+            ),
+            DatabaseNotFound(ref path) => write!(
+                f,
+                "No database exists at {:?}, \
+                 but must_exist was set",
+                path
+            ),
+            LockHeld { ref path, pid: Some(pid) } => write!(
+                f,
+                "The database file {:?} is locked by process {}",
+                path, pid
+            ),
+            LockHeld { ref path, pid: None } => {
+                write!(f, "The database file {:?} is locked", path)
+            }
             Unsupported(ref e) => write!(f, "Unsupported: {}", e),
             ReportableBug(ref e) => write!(
                 f,
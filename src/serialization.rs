@@ -9,7 +9,8 @@ use std::{
 use crate::{
     node::{Index, Leaf},
     pagecache::{
-        BatchManifest, MessageHeader, PageState, SegmentNumber, Snapshot,
+        BatchManifest, Counters, MessageHeader, PageState, SegmentNumber,
+        Snapshot,
     },
     Data, DiskPtr, Error, IVec, Link, Meta, Node, Result,
 };
@@ -78,6 +79,26 @@ impl Serialize for BatchManifest {
     }
 }
 
+impl Serialize for Counters {
+    fn serialized_size(&self) -> u64 {
+        self.idgen.serialized_size() + self.write_generation.serialized_size()
+    }
+
+    fn serialize_into(&self, buf: &mut &mut [u8]) {
+        self.idgen.serialize_into(buf);
+        self.write_generation.serialize_into(buf);
+    }
+
+    fn deserialize(buf: &mut &[u8]) -> Result<Self> {
+        let idgen = u64::deserialize(buf)?;
+        // counter pages written before the write generation
+        // was persisted only hold the ID generator
+        let write_generation =
+            if buf.is_empty() { 0 } else { u64::deserialize(buf)? };
+        Ok(Counters { idgen, write_generation })
+    }
+}
+
 impl Serialize for () {
     fn serialized_size(&self) -> u64 {
         0
@@ -338,11 +359,12 @@ impl Serialize for Meta {
 impl Serialize for Link {
     fn serialized_size(&self) -> u64 {
         match self {
-            Link::Set(key, value) => {
+            Link::Set(key, value, version) => {
                 1 + (key.len() as u64).serialized_size()
                     + (value.len() as u64).serialized_size()
                     + u64::try_from(key.len()).unwrap()
                     + u64::try_from(value.len()).unwrap()
+                    + version.serialized_size()
             }
             Link::Del(key) => {
                 1 + (key.len() as u64).serialized_size()
@@ -355,10 +377,11 @@ impl Serialize for Link {
 
     fn serialize_into(&self, buf: &mut &mut [u8]) {
         match self {
-            Link::Set(key, value) => {
+            Link::Set(key, value, version) => {
                 0_u8.serialize_into(buf);
                 key.serialize_into(buf);
                 value.serialize_into(buf);
+                version.serialize_into(buf);
             }
             Link::Del(key) => {
                 1_u8.serialize_into(buf);
@@ -384,7 +407,11 @@ impl Serialize for Link {
         let discriminant = buf[0];
         *buf = &buf[1..];
         Ok(match discriminant {
-            0 => Link::Set(IVec::deserialize(buf)?, IVec::deserialize(buf)?),
+            0 => Link::Set(
+                IVec::deserialize(buf)?,
+                IVec::deserialize(buf)?,
+                u64::deserialize(buf)?,
+            ),
             1 => Link::Del(IVec::deserialize(buf)?),
             2 => Link::ParentMergeIntention(u64::deserialize(buf)?),
             3 => Link::ParentMergeConfirm,
@@ -537,6 +564,7 @@ impl Serialize for Data {
                                 + (v.len() as u64).serialized_size()
                                 + k.len() as u64
                                 + v.len() as u64
+                                + leaf.versions[idx].serialized_size()
                         })
                         .sum::<u64>()
             }
@@ -569,6 +597,9 @@ impl Serialize for Data {
                 for value in &leaf.values {
                     value.serialize_into(buf);
                 }
+                for version in &leaf.versions {
+                    version.serialize_into(buf);
+                }
             }
             Data::Index(index) => {
                 1_u8.serialize_into(buf);
@@ -594,6 +625,7 @@ impl Serialize for Data {
             0 => Data::Leaf(Leaf {
                 keys: deserialize_bounded_sequence(buf, len)?,
                 values: deserialize_bounded_sequence(buf, len)?,
+                versions: deserialize_bounded_sequence(buf, len)?,
             }),
             1 => Data::Index(Index {
                 keys: deserialize_bounded_sequence(buf, len)?,
@@ -841,10 +873,12 @@ mod qc {
             } else {
                 let keys = Arbitrary::arbitrary(g);
                 let mut values = vec![];
+                let mut versions = vec![];
                 for _ in &keys {
-                    values.push(Arbitrary::arbitrary(g))
+                    values.push(Arbitrary::arbitrary(g));
+                    versions.push(Arbitrary::arbitrary(g));
                 }
-                Data::Leaf(Leaf { keys, values })
+                Data::Leaf(Leaf { keys, values, versions })
             }
         }
 
@@ -874,6 +908,12 @@ mod qc {
                                 .take(keys.len())
                                 .cloned()
                                 .collect(),
+                            versions: leaf
+                                .versions
+                                .iter()
+                                .take(keys.len())
+                                .copied()
+                                .collect(),
                             keys,
                         })
                     }))
@@ -923,7 +963,11 @@ mod qc {
         fn arbitrary<G: Gen>(g: &mut G) -> Link {
             let discriminant = g.gen_range(0, 5);
             match discriminant {
-                0 => Link::Set(IVec::arbitrary(g), IVec::arbitrary(g)),
+                0 => Link::Set(
+                    IVec::arbitrary(g),
+                    IVec::arbitrary(g),
+                    u64::arbitrary(g),
+                ),
                 1 => Link::Del(IVec::arbitrary(g)),
                 2 => Link::ParentMergeIntention(u64::arbitrary(g)),
                 3 => Link::ParentMergeConfirm,
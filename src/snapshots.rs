@@ -0,0 +1,299 @@
+//! Consistent copies of all trees, taken by `Db::snapshot_now`
+//! or automatically as set by `Config::snapshot_after_ops`
+//! and `Config::snapshot_interval`, and restored by
+//! `Db::restore_snapshot`.
+//!
+//! Each snapshot is an image, as written by `Db::to_bytes`,
+//! in the `snapshots` directory of the database, named after
+//! its id. It is written to a temporary file first and then
+//! renamed, so a crash never leaves a partial snapshot
+//! behind under its final name.
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    sync::atomic::AtomicBool,
+    time::{Duration, Instant},
+};
+
+use crate::*;
+
+/// The trees of a `Db`, by name.
+pub(crate) type Tenants = RwLock<FastMap8<IVec, Tree>>;
+
+/// Identifies a snapshot taken by `Db::snapshot_now` or
+/// `Config::snapshot_after_ops`. Later snapshots have
+/// larger ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SnapshotId(u64);
+
+impl From<u64> for SnapshotId {
+    fn from(id: u64) -> SnapshotId {
+        SnapshotId(id)
+    }
+}
+
+impl From<SnapshotId> for u64 {
+    fn from(id: SnapshotId) -> u64 {
+        id.0
+    }
+}
+
+impl fmt::Display for SnapshotId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How many times the trees are read while writes go on,
+/// before writers are kept out for a last read because each
+/// of them raced with a write.
+const OPTIMISTIC_READS: usize = 3;
+
+/// Writes a consistent image of all trees to a writer from
+/// `open`, and returns it with the write generation that
+/// the image reflects. Writers are not blocked while the
+/// trees are read, but if one completes in the meantime,
+/// the image is thrown away and written again to a new
+/// writer.
+pub(crate) fn write_image<W, F>(
+    write_generation: &AtomicU64,
+    tenants: &Tenants,
+    mut open: F,
+) -> Result<(W, u64)>
+where
+    W: Write,
+    F: FnMut() -> Result<W>,
+{
+    let tenants_mu = tenants.read();
+    let sorted: BTreeMap<_, _> = tenants_mu.iter().collect();
+    let trees: Vec<_> = sorted.into_iter().collect();
+
+    // atomic writes bump the generation under the write
+    // lock, so it is never read in the middle of one
+    let generation = || {
+        let _cc = concurrency_control::read();
+        write_generation.load(SeqCst)
+    };
+
+    for _ in 0..OPTIMISTIC_READS {
+        let before = generation();
+        let mut out = open()?;
+        image::write(&mut out, &trees)?;
+        if generation() == before {
+            return Ok((out, before));
+        }
+        trace!("a write raced with an image, writing it again");
+    }
+
+    let _cc = concurrency_control::write();
+    let mut out = open()?;
+    image::write(&mut out, &trees)?;
+    Ok((out, write_generation.load(SeqCst)))
+}
+
+fn dir(config: &RunningConfig) -> PathBuf {
+    config.get_path().join("snapshots")
+}
+
+/// Returns the ids of the snapshots on disk, oldest first.
+pub(crate) fn list(config: &RunningConfig) -> Result<Vec<SnapshotId>> {
+    let entries = match files::read_dir(config, &dir(config)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut ids = vec![];
+    for path in entries {
+        let name = path.file_name().and_then(|n| n.to_str());
+        // skips snapshots that are still being written
+        if let Some(id) = name.and_then(|n| n.parse().ok()) {
+            ids.push(SnapshotId(id));
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Reads the image of a snapshot.
+pub(crate) fn read(config: &RunningConfig, id: SnapshotId) -> Result<Vec<u8>> {
+    Ok(files::read(config, &dir(config).join(id.to_string()))?)
+}
+
+/// The state of automatic snapshots, shared by all handles
+/// to a `Db`.
+#[derive(Debug)]
+pub(crate) struct Snapshots {
+    /// Set once the `Db` is opened. This is weak so that the
+    /// trees, which refer back to this, can still be dropped.
+    pub(crate) tenants: Mutex<Option<std::sync::Weak<Tenants>>>,
+    /// The write generation and the time of the last
+    /// snapshot, or of opening the database.
+    last: Mutex<(u64, Duration)>,
+    /// Set while an automatic snapshot is being taken.
+    running: AtomicBool,
+    /// Held while a snapshot is written, to hand out ids in
+    /// order.
+    taking: Mutex<()>,
+    started: Instant,
+}
+
+impl Snapshots {
+    pub(crate) fn new(config: &RunningConfig, generation: u64) -> Snapshots {
+        let started = Instant::now();
+        let now = config.test_now().unwrap_or_default();
+        Snapshots {
+            tenants: Mutex::new(None),
+            last: Mutex::new((generation, now)),
+            running: AtomicBool::new(false),
+            taking: Mutex::new(()),
+            started,
+        }
+    }
+
+    fn now(&self, config: &RunningConfig) -> Duration {
+        config.test_now().unwrap_or_else(|| self.started.elapsed())
+    }
+
+    /// Writes a snapshot of `tenants`, and removes the
+    /// oldest ones beyond `Config::snapshot_retention`.
+    pub(crate) fn take(
+        &self,
+        config: &RunningConfig,
+        write_generation: &AtomicU64,
+        tenants: &Tenants,
+    ) -> Result<SnapshotId> {
+        let _taking = self.taking.lock();
+
+        let existing = list(config)?;
+        let id = SnapshotId(existing.last().map_or(1, |last| last.0 + 1));
+
+        let dir = dir(config);
+        files::create_dir_all(config, &dir)?;
+        let tmp_path = dir.join(format!("{}.in___motion", id));
+        let (out, generation) = write_image(write_generation, tenants, || {
+            let f = files::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(config, &tmp_path)?;
+            Ok(io::BufWriter::new(f))
+        })?;
+        let f = out.into_inner().map_err(io::Error::from)?;
+        f.sync_all()?;
+        files::rename(config, &tmp_path, &dir.join(id.to_string()))?;
+        #[cfg(unix)]
+        {
+            if !config.temporary {
+                files::sync_dir(config, &dir)?;
+            }
+        }
+
+        let keep = config.snapshot_retention.saturating_sub(1);
+        let stale = existing.len().saturating_sub(keep);
+        for old in &existing[..stale] {
+            files::remove_file(config, &dir.join(old.to_string()))?;
+        }
+
+        *self.last.lock() = (generation, self.now(config));
+
+        trace!("wrote snapshot {}", id);
+        Ok(id)
+    }
+
+    /// Returns `true` if a snapshot is due at `generation`
+    /// under `Config::snapshot_after_ops` or
+    /// `Config::snapshot_interval`.
+    fn due(&self, config: &RunningConfig, generation: u64) -> bool {
+        let (last_generation, last_time) = *self.last.lock();
+        let due_ops = if let Some(ops) = config.snapshot_after_ops {
+            generation.saturating_sub(last_generation) >= ops
+        } else {
+            false
+        };
+        let elapsed = self.now(config).checked_sub(last_time);
+        let due_time = match (config.snapshot_interval, elapsed) {
+            (Some(interval), Some(since)) => since >= interval,
+            _ => false,
+        };
+        due_ops || due_time
+    }
+
+    /// Takes a snapshot in the background if one is due.
+    /// Called after every write. With `Config::test_clock`,
+    /// snapshots are taken by `Db::step` instead.
+    pub(crate) fn maybe_take(&self, context: &Context, generation: u64) {
+        if context.snapshot_after_ops.is_none()
+            && context.snapshot_interval.is_none()
+        {
+            return;
+        }
+
+        if context.test_now().is_some() {
+            return;
+        }
+
+        // without a threadpool, the snapshot would be taken on
+        // the writer's thread, which holds the locks that it
+        // waits for
+        if !cfg!(any(
+            windows,
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+        )) {
+            return;
+        }
+
+        if !self.due(context, generation) {
+            return;
+        }
+
+        let weak_tenants = if let Some(weak) = &*self.tenants.lock() {
+            weak.clone()
+        } else {
+            return;
+        };
+
+        if self.running.swap(true, SeqCst) {
+            return;
+        }
+
+        let config = RunningConfig::clone(context);
+        let write_generation = context.write_generation.clone();
+        let snapshots = context.snapshots.clone();
+        let _result = threadpool::spawn(move || {
+            if let Some(tenants) = weak_tenants.upgrade() {
+                if let Err(e) =
+                    snapshots.take(&config, &write_generation, &tenants)
+                {
+                    error!("failed to take an automatic snapshot: {:?}", e);
+                }
+            }
+            snapshots.running.store(false, SeqCst);
+        });
+    }
+
+    /// Takes a snapshot on the calling thread if one is due,
+    /// for `Db::step`.
+    pub(crate) fn step(
+        &self,
+        context: &Context,
+        tenants: &Tenants,
+    ) -> Result<()> {
+        if context.snapshot_after_ops.is_none()
+            && context.snapshot_interval.is_none()
+        {
+            return Ok(());
+        }
+
+        if self.due(context, context.write_generation()) {
+            self.take(context, &context.write_generation, tenants)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! Summaries of the sizes of keys and values, see
+//! `Db::space_report` and `Db::space_report_sampled`.
+#![allow(clippy::float_arithmetic)]
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::*;
+
+/// Enough buckets for sizes up to `u64::max_value()`.
+const BUCKETS: usize = 65;
+
+/// The distribution of the sizes of keys or of values, as
+/// returned in a `SpaceReport`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// The number of keys or values.
+    pub count: u64,
+    /// The sum of their sizes in bytes.
+    pub total_bytes: u64,
+    /// The size of the largest one. In a sampled report,
+    /// this is the largest one in the sample.
+    pub max: u64,
+    /// Counts by size, in powers of two: `buckets[0]`
+    /// counts the empty ones, and `buckets[i]` counts those
+    /// of at least `2^(i - 1)` and less than `2^i` bytes.
+    /// Trailing empty buckets are left out.
+    pub buckets: Vec<u64>,
+}
+
+impl SizeHistogram {
+    /// Returns the size in bytes below which `p` percent of
+    /// the sizes fall, rounded up to the end of its bucket,
+    /// so it is less than twice the actual size. Returns 0
+    /// if the histogram is empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `p` is not between 0 and 100.
+    pub fn percentile(&self, p: f64) -> u64 {
+        assert!(
+            (0. ..=100.).contains(&p),
+            "percentiles must be between 0.0 and 100.0"
+        );
+
+        #[allow(clippy::cast_precision_loss)]
+        let target = (self.count as f64 * p / 100.).max(1.);
+
+        let mut seen = 0.;
+        for (i, count) in self.buckets.iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            {
+                seen += *count as f64;
+            }
+            if seen >= target {
+                if i == 0 {
+                    return 0;
+                }
+                let end = u64::max_value() >> (64 - i);
+                return std::cmp::min(end, self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// The logical bytes of the keys of a tree that start with
+/// a prefix, as returned in a `SpaceReport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixSpace {
+    /// The name of the tree.
+    pub tree: IVec,
+    /// The first bytes of the keys, as many as the
+    /// `prefix_depth` of the report. Keys that are shorter
+    /// than that are counted under the whole key.
+    pub prefix: IVec,
+    /// The number of keys.
+    pub count: u64,
+    /// The sum of the sizes of the keys and their values.
+    pub bytes: u64,
+}
+
+/// The shape of the data in a `Db`, as returned by
+/// `Db::space_report` and `Db::space_report_sampled`.
+/// Sizes are logical, meaning the lengths of keys and values
+/// as they are read and written, before any compression or
+/// deduplication, and without the overhead of storing them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SpaceReport {
+    /// Whether the report was estimated from a sample by
+    /// `Db::space_report_sampled`.
+    pub sampled: bool,
+    /// The sizes of the keys of all trees.
+    pub keys: SizeHistogram,
+    /// The sizes of the values of all trees.
+    pub values: SizeHistogram,
+    /// The logical bytes of each tree by key prefix, sorted
+    /// by tree and then by prefix.
+    pub prefixes: Vec<PrefixSpace>,
+    /// For a sampled report, the half width of the 95%
+    /// confidence interval of the total logical bytes,
+    /// relative to the estimate, so `0.05` means that the
+    /// actual total is likely within 5% of the sum of
+    /// `keys.total_bytes` and `values.total_bytes`. This is
+    /// 0 for a report that read every item.
+    pub relative_error: f64,
+}
+
+#[derive(Default)]
+struct Sizes {
+    count: f64,
+    total_bytes: f64,
+    max: u64,
+    buckets: Vec<f64>,
+}
+
+impl Sizes {
+    fn record(&mut self, size: u64, weight: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0.; BUCKETS];
+        }
+        let bucket = 64 - size.leading_zeros() as usize;
+        self.buckets[bucket] += weight;
+        self.count += weight;
+        #[allow(clippy::cast_precision_loss)]
+        {
+            self.total_bytes += size as f64 * weight;
+        }
+        self.max = std::cmp::max(self.max, size);
+    }
+
+    fn finish(&self) -> SizeHistogram {
+        let mut buckets: Vec<u64> =
+            self.buckets.iter().map(|count| round(*count)).collect();
+        while buckets.last() == Some(&0) {
+            let _ = buckets.pop();
+        }
+        SizeHistogram {
+            count: round(self.count),
+            total_bytes: round(self.total_bytes),
+            max: self.max,
+            buckets,
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn round(value: f64) -> u64 {
+    value.round() as u64
+}
+
+/// Collects the items of a `SpaceReport`, each of which
+/// stands for `weight` items when sampling.
+pub(crate) struct SpaceReportBuilder {
+    prefix_depth: usize,
+    sampled: bool,
+    keys: Sizes,
+    values: Sizes,
+    prefixes: BTreeMap<(IVec, IVec), (f64, f64)>,
+    variance: f64,
+}
+
+impl SpaceReportBuilder {
+    pub(crate) fn new(prefix_depth: usize, sampled: bool) -> Self {
+        SpaceReportBuilder {
+            prefix_depth,
+            sampled,
+            keys: Sizes::default(),
+            values: Sizes::default(),
+            prefixes: BTreeMap::new(),
+            variance: 0.,
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        tree: &IVec,
+        key: &[u8],
+        value_len: u64,
+        weight: f64,
+    ) {
+        let key_len = u64::try_from(key.len()).unwrap();
+        self.keys.record(key_len, weight);
+        self.values.record(value_len, weight);
+
+        let prefix = &key[..std::cmp::min(key.len(), self.prefix_depth)];
+        let entry = self
+            .prefixes
+            .entry((tree.clone(), IVec::from(prefix)))
+            .or_insert((0., 0.));
+        entry.0 += weight;
+        #[allow(clippy::cast_precision_loss)]
+        {
+            entry.1 += (key_len + value_len) as f64 * weight;
+        }
+    }
+
+    /// Records `samples` leaves of a tree, drawn by
+    /// `Tree::sample_leaf`. By Knuth's estimator, the logical
+    /// bytes of a leaf times its weight is an unbiased
+    /// estimate of those of the whole tree, so each item
+    /// stands for the weight of its leaf divided by the
+    /// number of samples.
+    pub(crate) fn record_samples(
+        &mut self,
+        tree: &IVec,
+        samples: &[(u64, Vec<(IVec, u64)>)],
+    ) {
+        #[allow(clippy::cast_precision_loss)]
+        let n = samples.len() as f64;
+        let mut estimates = Vec::with_capacity(samples.len());
+
+        for (leaf_weight, items) in samples {
+            #[allow(clippy::cast_precision_loss)]
+            let weight = *leaf_weight as f64;
+            let mut bytes = 0;
+            for (key, value_len) in items {
+                self.record(tree, key, *value_len, weight / n);
+                bytes += u64::try_from(key.len()).unwrap() + value_len;
+            }
+            #[allow(clippy::cast_precision_loss)]
+            estimates.push(bytes as f64 * weight);
+        }
+
+        // the variance of the mean of the estimates, which
+        // adds up over independently sampled trees
+        if samples.len() > 1 {
+            let mean = estimates.iter().sum::<f64>() / n;
+            let squares: f64 =
+                estimates.iter().map(|e| (e - mean) * (e - mean)).sum();
+            self.variance += squares / (n - 1.) / n;
+        }
+    }
+
+    pub(crate) fn finish(self) -> SpaceReport {
+        let prefixes = self
+            .prefixes
+            .into_iter()
+            .map(|((tree, prefix), (count, bytes))| PrefixSpace {
+                tree,
+                prefix,
+                count: round(count),
+                bytes: round(bytes),
+            })
+            .collect();
+
+        let total = self.keys.total_bytes + self.values.total_bytes;
+        let relative_error = if total > 0. {
+            1.96 * self.variance.sqrt() / total
+        } else {
+            0.
+        };
+
+        SpaceReport {
+            sampled: self.sampled,
+            keys: self.keys.finish(),
+            values: self.values.finish(),
+            prefixes,
+            relative_error,
+        }
+    }
+}
+
+/// A xorshift generator for choosing leaves to sample,
+/// seeded differently every time.
+pub(crate) struct SampleRng(u64);
+
+impl SampleRng {
+    pub(crate) fn new() -> SampleRng {
+        let seed = RandomState::new().build_hasher().finish();
+        SampleRng(seed | 1)
+    }
+
+    /// Returns a number in `0..n`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        ((u128::from(self.0) * n as u128) >> 64) as usize
+    }
+}
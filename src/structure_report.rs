@@ -0,0 +1,107 @@
+//! The shape of the trees and segments of a database, see
+//! `Db::structure_report`. Only built with the `debug`
+//! feature.
+#![allow(clippy::float_arithmetic)]
+
+use crate::*;
+
+/// The shape of the trees of a `Db`, and how full the
+/// segments of its log are, as returned by
+/// `Db::structure_report`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StructureReport {
+    /// Every tree, including the default tree, sorted by
+    /// name.
+    pub trees: Vec<TreeStructure>,
+    /// Every segment of the log that is not free, sorted by
+    /// offset.
+    pub segments: Vec<SegmentStructure>,
+}
+
+/// The shape of one tree, as returned in a
+/// `StructureReport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStructure {
+    /// The name of the tree.
+    pub name: IVec,
+    /// The number of levels, including the leaves. A new
+    /// tree has 2, an index node over a single leaf.
+    pub height: usize,
+    /// The number of nodes at each level, starting with the
+    /// root, so the last one is the number of leaves.
+    pub nodes_per_level: Vec<u64>,
+    /// The average number of children of the index nodes,
+    /// or 0 if there are none.
+    pub average_fan_out: f64,
+    /// The number of keys in all leaves, including those
+    /// that are shadowed by newer versions or hidden by
+    /// snapshots.
+    pub leaf_keys: u64,
+    /// The fewest keys in a leaf.
+    pub min_leaf_keys: u64,
+    /// The most keys in a leaf.
+    pub max_leaf_keys: u64,
+}
+
+impl TreeStructure {
+    /// Returns the average number of keys per leaf.
+    pub fn average_leaf_keys(&self) -> f64 {
+        let leaves = self.nodes_per_level.last().copied().unwrap_or(0);
+        if leaves == 0 {
+            return 0.;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        {
+            self.leaf_keys as f64 / leaves as f64
+        }
+    }
+}
+
+/// What a segment of the log is being used for, as returned
+/// in a `SegmentStructure`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentState {
+    /// Pages are being written to the segment, or were
+    /// until recently and are not all durable yet.
+    Active,
+    /// The segment is full, and its pages are only ever
+    /// replaced.
+    Inactive,
+    /// The pages left in the segment are being rewritten
+    /// elsewhere so that it can be reused.
+    Draining,
+}
+
+/// How much of a segment of the log holds the current
+/// versions of pages, as returned in a `StructureReport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentStructure {
+    /// The offset of the segment in the log file.
+    pub offset: u64,
+    /// What the segment is being used for.
+    pub state: SegmentState,
+    /// The bytes of the current versions of pages.
+    pub live_bytes: u64,
+    /// The bytes that do not hold current versions of pages.
+    /// For the active segment, these are only the pages
+    /// that have been replaced, since the rest of it is not
+    /// written yet. For other segments, this is the rest of
+    /// the segment, including headers and padding.
+    pub dead_bytes: u64,
+}
+
+impl SegmentStructure {
+    /// Returns the share of the written bytes of the segment
+    /// that are live, between 0 and 1, or 1 for a segment
+    /// with nothing written yet.
+    pub fn live_ratio(&self) -> f64 {
+        let written = self.live_bytes + self.dead_bytes;
+        if written == 0 {
+            return 1.;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        {
+            self.live_bytes as f64 / written as f64
+        }
+    }
+}
@@ -1,11 +1,15 @@
 use std::{
+    collections::HashSet,
     future::Future,
     pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering::Relaxed},
-        mpsc::{sync_channel, Receiver, SyncSender, TryRecvError},
+        mpsc::{
+            sync_channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError,
+        },
     },
     task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
 #[cfg(not(feature = "testing"))]
@@ -29,11 +33,17 @@ pub enum Event {
         key: IVec,
         /// The value that has been set
         value: IVec,
+        /// The value that was replaced, if the key
+        /// was already present
+        previous: Option<IVec>,
     },
     /// A deleted key
     Remove {
         /// The key that has been removed
         key: IVec,
+        /// The value that was removed, if the key
+        /// was present
+        previous: Option<IVec>,
     },
 }
 
@@ -41,12 +51,48 @@ impl Event {
     /// Return the key associated with the `Event`
     pub fn key(&self) -> &IVec {
         match self {
-            Event::Insert { key, .. } | Event::Remove { key } => key,
+            Event::Insert { key, .. } | Event::Remove { key, .. } => key,
         }
     }
+
+    /// Return the value that the key had before the
+    /// write that caused this `Event`, if any. This is
+    /// read atomically with the write, so it is always
+    /// the value that the write replaced.
+    pub fn previous(&self) -> Option<&IVec> {
+        match self {
+            Event::Insert { previous, .. }
+            | Event::Remove { previous, .. } => previous.as_ref(),
+        }
+    }
+}
+
+/// The kinds of `Event` that a `Subscriber` receives, see
+/// `Tree::watch_prefix_filtered`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFilter {
+    /// Both `Event::Insert` and `Event::Remove`.
+    All,
+    /// Only `Event::Insert`.
+    Inserts,
+    /// Only `Event::Remove`.
+    Removes,
 }
 
-type Senders = Map<usize, (Option<Waker>, SyncSender<OneShot<Option<Event>>>)>;
+impl EventFilter {
+    fn accepts(self, is_insert: bool) -> bool {
+        match self {
+            EventFilter::All => true,
+            EventFilter::Inserts => is_insert,
+            EventFilter::Removes => !is_insert,
+        }
+    }
+}
+
+type Senders = Map<
+    usize,
+    (Option<Waker>, SyncSender<OneShot<Option<Event>>>, EventFilter),
+>;
 
 /// A subscriber listening on a specified prefix
 ///
@@ -74,8 +120,8 @@ type Senders = Map<usize, (Option<Waker>, SyncSender<OneShot<Option<Event>>>)>;
 /// // `Subscription` implements `Iterator<Item=Event>`
 /// for event in subscriber.take(1) {
 ///     match event {
-///         Event::Insert{ key, value } => assert_eq!(key.as_ref(), &[0]),
-///         Event::Remove {key } => {}
+///         Event::Insert{ key, value, .. } => assert_eq!(key.as_ref(), &[0]),
+///         Event::Remove { key, .. } => {}
 ///     }
 /// }
 ///
@@ -135,6 +181,71 @@ impl Future for Subscriber {
     }
 }
 
+impl Subscriber {
+    /// Returns a subscriber that collects the events of this
+    /// one into batches, which is cheaper than handling them
+    /// one by one when keys change thousands of times per
+    /// second.
+    ///
+    /// Each batch starts with the next event, waiting as long
+    /// as it takes for one, and then takes the events that
+    /// follow it for up to `max_wait`, or until it holds
+    /// `max_events` events, whichever comes first. A batch
+    /// always holds at least one event, even if `max_events`
+    /// is 0. Events are in the order of their writes, unless
+    /// `BatchedSubscriber::dedup_keys` is used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let mut batches =
+    ///     db.watch_prefix(b"hot").batched(Duration::from_millis(10), 100);
+    ///
+    /// for i in 0..3_u8 {
+    ///     db.insert(b"hot", vec![i])?;
+    /// }
+    ///
+    /// let batch = batches.next().unwrap();
+    /// assert_eq!(batch.len(), 3);
+    /// # Ok(()) }
+    /// ```
+    pub fn batched(
+        self,
+        max_wait: Duration,
+        max_events: usize,
+    ) -> BatchedSubscriber {
+        BatchedSubscriber {
+            subscriber: self,
+            max_wait,
+            max_events,
+            dedup_keys: false,
+            done: false,
+        }
+    }
+
+    /// Like `next`, but returns `RecvTimeoutError::Timeout` if
+    /// no event arrives before `deadline`.
+    fn next_before(
+        &mut self,
+        deadline: Instant,
+    ) -> std::result::Result<Event, RecvTimeoutError> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let future_rx = self.rx.recv_timeout(timeout)?;
+            match future_rx.wait() {
+                Some(Some(event)) => return Ok(event),
+                Some(None) => return Err(RecvTimeoutError::Disconnected),
+                // the write was aborted
+                None => {}
+            }
+        }
+    }
+}
+
 impl Iterator for Subscriber {
     type Item = Event;
 
@@ -150,20 +261,139 @@ impl Iterator for Subscriber {
     }
 }
 
+/// A subscriber that returns events in batches, returned by
+/// `Subscriber::batched`.
+///
+/// `BatchedSubscriber` implements `Iterator<Item = Vec<Event>>`,
+/// which blocks until the next batch is complete. Unlike
+/// `Subscriber`, it does not implement `Future`, because
+/// waiting out the window of a batch needs a timer.
+pub struct BatchedSubscriber {
+    subscriber: Subscriber,
+    max_wait: Duration,
+    max_events: usize,
+    dedup_keys: bool,
+    // set once the subscriber is disconnected, after the
+    // events that were left were returned
+    done: bool,
+}
+
+impl BatchedSubscriber {
+    /// Returns a subscriber whose batches only hold the last
+    /// event for each key, so that a key that was written
+    /// several times within a batch is only handled once.
+    /// The events are then in the order of the last write to
+    /// each key, and the `previous` value of an event is the
+    /// value before that last write. `max_events` still
+    /// counts the events that were superseded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use sled::Event;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let mut batches = db
+    ///     .watch_prefix(vec![])
+    ///     .batched(Duration::from_millis(10), 100)
+    ///     .dedup_keys();
+    ///
+    /// db.insert(b"a", vec![1])?;
+    /// db.insert(b"b", vec![1])?;
+    /// db.insert(b"a", vec![2])?;
+    ///
+    /// let batch = batches.next().unwrap();
+    /// assert_eq!(batch.len(), 2);
+    /// assert_eq!(batch[0].key(), b"b");
+    /// match &batch[1] {
+    ///     Event::Insert { key, value, .. } => {
+    ///         assert_eq!(key, b"a");
+    ///         assert_eq!(value, &[2]);
+    ///     }
+    ///     other => panic!("unexpected event {:?}", other),
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn dedup_keys(mut self) -> Self {
+        self.dedup_keys = true;
+        self
+    }
+}
+
+impl Iterator for BatchedSubscriber {
+    type Item = Vec<Event>;
+
+    fn next(&mut self) -> Option<Vec<Event>> {
+        if self.done {
+            return None;
+        }
+
+        let first = if let Some(event) = self.subscriber.next() {
+            event
+        } else {
+            self.done = true;
+            return None;
+        };
+
+        // a window too long to end is waited out like one that
+        // never ends
+        let deadline = Instant::now().checked_add(self.max_wait);
+        let mut batch = vec![first];
+        while batch.len() < self.max_events {
+            let next = match deadline {
+                Some(at) => self.subscriber.next_before(at),
+                None => self
+                    .subscriber
+                    .next()
+                    .ok_or(RecvTimeoutError::Disconnected),
+            };
+            match next {
+                Ok(event) => batch.push(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        if self.dedup_keys {
+            let mut seen = HashSet::new();
+            batch = batch
+                .into_iter()
+                .rev()
+                .filter(|event| seen.insert(event.key().clone()))
+                .collect();
+            batch.reverse();
+        }
+
+        Some(batch)
+    }
+}
+
+type Watched = RwLock<BTreeMap<Vec<u8>, Arc<RwLock<Senders>>>>;
+
 #[derive(Debug, Default)]
 pub(crate) struct Subscribers {
-    watched: RwLock<BTreeMap<Vec<u8>, Arc<RwLock<Senders>>>>,
+    /// Subscribers by the prefix of the keys they watch.
+    watched: Watched,
+    /// Subscribers by the single key they watch, which are
+    /// found without comparing the key to every prefix.
+    watched_keys: Watched,
     ever_used: AtomicBool,
 }
 
 impl Drop for Subscribers {
     fn drop(&mut self) {
         let watched = self.watched.read();
+        let watched_keys = self.watched_keys.read();
 
-        for senders in watched.values() {
+        for senders in watched.values().chain(watched_keys.values()) {
             let senders =
                 std::mem::replace(&mut *senders.write(), Map::default());
-            for (_, (waker, sender)) in senders {
+            for (_, (waker, sender, _)) in senders {
                 drop(sender);
                 if let Some(waker) = waker {
                     waker.wake();
@@ -174,15 +404,37 @@ impl Drop for Subscribers {
 }
 
 impl Subscribers {
-    pub(crate) fn register(&self, prefix: &[u8]) -> Subscriber {
+    pub(crate) fn register(
+        &self,
+        prefix: &[u8],
+        filter: EventFilter,
+    ) -> Subscriber {
+        self.register_in(&self.watched, prefix, filter)
+    }
+
+    /// Registers a subscriber to the writes of `key` alone.
+    pub(crate) fn register_key(
+        &self,
+        key: &[u8],
+        filter: EventFilter,
+    ) -> Subscriber {
+        self.register_in(&self.watched_keys, key, filter)
+    }
+
+    fn register_in(
+        &self,
+        watched: &Watched,
+        prefix: &[u8],
+        filter: EventFilter,
+    ) -> Subscriber {
         self.ever_used.store(true, Relaxed);
         let r_mu = {
-            let r_mu = self.watched.read();
+            let r_mu = watched.read();
             if r_mu.contains_key(prefix) {
                 r_mu
             } else {
                 drop(r_mu);
-                let mut w_mu = self.watched.write();
+                let mut w_mu = watched.write();
                 if !w_mu.contains_key(prefix) {
                     let old = w_mu.insert(
                         prefix.to_vec(),
@@ -191,7 +443,7 @@ impl Subscribers {
                     assert!(old.is_none());
                 }
                 drop(w_mu);
-                self.watched.read()
+                watched.read()
             }
         };
 
@@ -202,28 +454,42 @@ impl Subscribers {
 
         let id = ID_GEN.fetch_add(1, Relaxed);
 
-        w_senders.insert(id, (None, tx));
+        w_senders.insert(id, (None, tx, filter));
 
         Subscriber { id, rx, home: arc_senders.clone() }
     }
 
+    /// Reserves a place in the queue of each subscriber
+    /// whose prefix or key and `EventFilter` match a write
+    /// that is about to happen to `key`, which inserts a
+    /// value if `is_insert` is set, and removes it otherwise.
     pub(crate) fn reserve<R: AsRef<[u8]>>(
         &self,
         key: R,
+        is_insert: bool,
     ) -> Option<ReservedBroadcast> {
         if !self.ever_used.load(Relaxed) {
             return None;
         }
 
         let r_mu = self.watched.read();
-        let prefixes = r_mu.iter().filter(|(k, _)| key.as_ref().starts_with(k));
+        let prefixes = r_mu
+            .iter()
+            .filter(|(k, _)| key.as_ref().starts_with(k))
+            .map(|(_, subs_rwl)| subs_rwl);
+
+        let r_keys = self.watched_keys.read();
+        let exact = r_keys.get(key.as_ref());
 
         let mut subscribers = vec![];
 
-        for (_, subs_rwl) in prefixes {
+        for subs_rwl in prefixes.chain(exact) {
             let subs = subs_rwl.read();
 
-            for (_id, (waker, sender)) in subs.iter() {
+            for (_id, (waker, sender, filter)) in subs.iter() {
+                if !filter.accepts(is_insert) {
+                    continue;
+                }
                 let (tx, rx) = OneShot::pair();
                 if sender.send(rx).is_err() {
                     continue;
@@ -261,53 +527,57 @@ impl ReservedBroadcast {
 fn basic_subscriber() {
     let subs = Subscribers::default();
 
-    let mut s2 = subs.register(&[0]);
-    let mut s3 = subs.register(&[0, 1]);
-    let mut s4 = subs.register(&[1, 2]);
+    let mut s2 = subs.register(&[0], EventFilter::All);
+    let mut s3 = subs.register(&[0, 1], EventFilter::All);
+    let mut s4 = subs.register(&[1, 2], EventFilter::All);
 
-    let r1 = subs.reserve(b"awft");
+    let r1 = subs.reserve(b"awft", true);
     assert!(r1.is_none());
 
-    let mut s1 = subs.register(&[]);
+    let mut s1 = subs.register(&[], EventFilter::All);
 
     let k2: IVec = vec![].into();
-    let r2 = subs.reserve(&k2).unwrap();
+    let r2 = subs.reserve(&k2, true).unwrap();
     r2.complete(&Event::Insert {
         key: k2.clone(),
         value: IVec::from(k2.clone()),
+        previous: None,
     });
 
     let k3: IVec = vec![0].into();
-    let r3 = subs.reserve(&k3).unwrap();
+    let r3 = subs.reserve(&k3, true).unwrap();
     r3.complete(&Event::Insert {
         key: k3.clone(),
         value: IVec::from(k3.clone()),
+        previous: None,
     });
 
     let k4: IVec = vec![0, 1].into();
-    let r4 = subs.reserve(&k4).unwrap();
-    r4.complete(&Event::Remove { key: k4.clone() });
+    let r4 = subs.reserve(&k4, false).unwrap();
+    r4.complete(&Event::Remove { key: k4.clone(), previous: None });
 
     let k5: IVec = vec![0, 1, 2].into();
-    let r5 = subs.reserve(&k5).unwrap();
+    let r5 = subs.reserve(&k5, true).unwrap();
     r5.complete(&Event::Insert {
         key: k5.clone(),
         value: IVec::from(k5.clone()),
+        previous: None,
     });
 
     let k6: IVec = vec![1, 1, 2].into();
-    let r6 = subs.reserve(&k6).unwrap();
-    r6.complete(&Event::Remove { key: k6.clone() });
+    let r6 = subs.reserve(&k6, false).unwrap();
+    r6.complete(&Event::Remove { key: k6.clone(), previous: None });
 
     let k7: IVec = vec![1, 1, 2].into();
-    let r7 = subs.reserve(&k7).unwrap();
+    let r7 = subs.reserve(&k7, true).unwrap();
     drop(r7);
 
     let k8: IVec = vec![1, 2, 2].into();
-    let r8 = subs.reserve(&k8).unwrap();
+    let r8 = subs.reserve(&k8, true).unwrap();
     r8.complete(&Event::Insert {
         key: k8.clone(),
         value: IVec::from(k8.clone()),
+        previous: None,
     });
 
     assert_eq!(s1.next().unwrap().key(), &*k2);
@@ -326,3 +596,105 @@ fn basic_subscriber() {
 
     assert_eq!(s4.next().unwrap().key(), &*k8);
 }
+
+#[test]
+fn key_subscriber() {
+    let subs = Subscribers::default();
+
+    let mut exact = subs.register_key(&[0, 1], EventFilter::All);
+    let mut removes = subs.register_key(&[0, 1], EventFilter::Removes);
+    let mut prefix = subs.register(&[0], EventFilter::All);
+
+    // neither a prefix nor an extension of the key matches
+    assert!(subs.reserve([1], true).is_none());
+    let r1 = subs.reserve([0], true).unwrap();
+    r1.complete(&Event::Insert {
+        key: vec![0].into(),
+        value: vec![].into(),
+        previous: None,
+    });
+    let r2 = subs.reserve([0, 1, 2], true).unwrap();
+    r2.complete(&Event::Insert {
+        key: vec![0, 1, 2].into(),
+        value: vec![].into(),
+        previous: None,
+    });
+
+    let k: IVec = vec![0, 1].into();
+    let r3 = subs.reserve(&k, true).unwrap();
+    r3.complete(&Event::Insert {
+        key: k.clone(),
+        value: vec![].into(),
+        previous: None,
+    });
+    let r4 = subs.reserve(&k, false).unwrap();
+    r4.complete(&Event::Remove { key: k.clone(), previous: None });
+
+    assert_eq!(exact.next().unwrap(), Event::Insert {
+        key: k.clone(),
+        value: vec![].into(),
+        previous: None,
+    });
+    assert_eq!(exact.next().unwrap(), Event::Remove {
+        key: k.clone(),
+        previous: None
+    });
+    assert_eq!(removes.next().unwrap().key(), &*k);
+    assert_eq!(prefix.next().unwrap().key(), &[0]);
+    assert_eq!(prefix.next().unwrap().key(), &[0, 1, 2]);
+    assert_eq!(prefix.next().unwrap().key(), &*k);
+
+    // once the subscribers are dropped, writes to the key
+    // have nobody to notify
+    drop((exact, removes, prefix));
+    assert!(subs.reserve(&k, true).is_none());
+}
+
+#[test]
+fn batched_subscriber() {
+    let subs = Subscribers::default();
+
+    let insert = |key: u8, value: u8| {
+        let r = subs.reserve([key], true).unwrap();
+        r.complete(&Event::Insert {
+            key: vec![key].into(),
+            value: vec![value].into(),
+            previous: None,
+        });
+    };
+
+    let wait = Duration::from_millis(10);
+    let mut batches = subs.register(&[], EventFilter::All).batched(wait, 3);
+    let mut deduped =
+        subs.register(&[], EventFilter::All).batched(wait, 10).dedup_keys();
+
+    insert(1, 0);
+    insert(2, 0);
+    insert(1, 1);
+    insert(3, 0);
+
+    // a full batch does not wait for the window to end
+    let keys = |batch: Vec<Event>| -> Vec<u8> {
+        batch.iter().map(|event| event.key()[0]).collect()
+    };
+    assert_eq!(keys(batches.next().unwrap()), vec![1, 2, 1]);
+    assert_eq!(keys(batches.next().unwrap()), vec![3]);
+
+    // the last write to key 1 supersedes the first one
+    let batch = deduped.next().unwrap();
+    assert_eq!(keys(batch.clone()), vec![2, 1, 3]);
+    assert_eq!(batch[1], Event::Insert {
+        key: vec![1].into(),
+        value: vec![1].into(),
+        previous: None,
+    });
+
+    // the events that are left are returned once the
+    // subscribers are disconnected
+    insert(4, 0);
+    drop(subs);
+    assert_eq!(keys(batches.next().unwrap()), vec![4]);
+    assert!(batches.next().is_none());
+    assert_eq!(keys(deduped.next().unwrap()), vec![4]);
+    assert!(deduped.next().is_none());
+}
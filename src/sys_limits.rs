@@ -2,29 +2,85 @@
 
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 use std::io;
-#[cfg(any(target_os = "linux"))]
-use {std::fs::File, std::io::Read};
-
-/// See the Kernel's documentation for more information about this subsystem,
-/// found at:  [Documentation/cgroup-v1/memory.txt](https://www.kernel.org/doc/Documentation/cgroup-v1/memory.txt)
+#[cfg(any(target_os = "linux", test))]
+use std::path::PathBuf;
+
+/// Returns the smallest memory limit of the cgroups that this
+/// process is in, and of their ancestors, whose limits apply
+/// to it too. Both the unified hierarchy of cgroup v2, with
+/// limits in `memory.max`, and the memory controller of
+/// cgroup v1, with limits in `memory.limit_in_bytes`, are
+/// read, from where systemd and container runtimes mount
+/// them under `/sys/fs/cgroup`. The cgroups of the process
+/// are listed in `/proc/self/cgroup`, and when a cgroup
+/// namespace or a different mount hides their directories,
+/// the limit at the root of the mount is used instead, which
+/// is the limit of the container from inside of it.
 ///
-/// If there's no memory limit specified on the container this may return
-/// 0x7FFFFFFFFFFFF000 (2^63-1 rounded down to 4k which is a common page size).
-/// So we know we are not running in a memory restricted environment.
+/// See the Kernel's documentation for more information about these
+/// subsystems, found at:
+/// [Documentation/admin-guide/cgroup-v2.rst](https://www.kernel.org/doc/Documentation/admin-guide/cgroup-v2.rst)
+/// and [Documentation/cgroup-v1/memory.txt](https://www.kernel.org/doc/Documentation/cgroup-v1/memory.txt)
 #[cfg(target_os = "linux")]
 fn get_cgroup_memory_limit() -> io::Result<u64> {
-    File::open("/sys/fs/cgroup/memory/memory.limit_in_bytes")
-        .and_then(read_u64_from)
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup")?;
+
+    cgroup_limit_files(&cgroups)
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .filter_map(|limit| parse_cgroup_limit(&limit))
+        .min()
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no cgroup memory limit")
+        })
 }
 
-#[cfg(target_os = "linux")]
-fn read_u64_from(mut file: File) -> io::Result<u64> {
-    let mut s = String::new();
-    file.read_to_string(&mut s).and_then(|_| {
-        s.trim()
-            .parse()
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-    })
+/// Returns the files that may hold memory limits of the
+/// cgroups listed in the contents of `/proc/self/cgroup`,
+/// from each cgroup up to the root of its hierarchy.
+#[cfg(any(target_os = "linux", test))]
+fn cgroup_limit_files(cgroups: &str) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for line in cgroups.lines() {
+        // hierarchy-id:controller-list:cgroup-path
+        let mut fields = line.splitn(3, ':').skip(1);
+        let (controllers, path) = match (fields.next(), fields.next()) {
+            (Some(controllers), Some(path)) => (controllers, path),
+            _ => continue,
+        };
+
+        let (mount, file) = if controllers.is_empty() {
+            ("/sys/fs/cgroup", "memory.max")
+        } else if controllers.split(',').any(|c| c == "memory") {
+            ("/sys/fs/cgroup/memory", "memory.limit_in_bytes")
+        } else {
+            continue;
+        };
+
+        let mut dir = PathBuf::from(mount);
+        dir.push(path.trim_start_matches('/'));
+        while dir.starts_with(mount) {
+            files.push(dir.join(file));
+            if !dir.pop() {
+                break;
+            }
+        }
+    }
+    files
+}
+
+/// Parses the contents of a cgroup memory limit file, which
+/// are `max` in cgroup v2 when there is no limit. Without a
+/// limit, cgroup v1 reports a value close to 2^63, such as
+/// 0x7FFFFFFFFFFFF000 (2^63-1 rounded down to 4k which is a
+/// common page size), so we know we are not running in a
+/// memory restricted environment.
+#[cfg(any(target_os = "linux", test))]
+fn parse_cgroup_limit(contents: &str) -> Option<u64> {
+    let limit: u64 = contents.trim().parse().ok()?;
+
+    // src: https://github.com/dotnet/coreclr/blob/master/src/pal/src/misc/cgroup.cpp#L385-L428
+    if limit > 0x7FFF_FFFF_0000_0000 { None } else { Some(limit) }
 }
 
 /// Returns the maximum size of total available memory of the process, in bytes.
@@ -71,15 +127,6 @@ pub fn get_memory_limit() -> u64 {
         if let Ok(mem) = get_cgroup_memory_limit() {
             max = mem;
         }
-
-        // If there's no memory limit specified on the container this
-        // actually returns 0x7FFFFFFFFFFFF000 (2^63-1 rounded down to
-        // 4k which is a common page size). So we know we are not
-        // running in a memory restricted environment.
-        // src: https://github.com/dotnet/coreclr/blob/master/src/pal/src/misc/cgroup.cpp#L385-L428
-        if max > 0x7FFF_FFFF_0000_0000 {
-            return 0;
-        }
     }
 
     #[cfg(any(target_os = "linux", target_os = "macos"))]
@@ -107,3 +154,37 @@ pub fn get_memory_limit() -> u64 {
 
     max
 }
+
+#[test]
+fn cgroup_limits() {
+    let files = |cgroups| {
+        cgroup_limit_files(cgroups)
+            .into_iter()
+            .map(|path| path.to_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        files("0::/system.slice/app.service\n"),
+        vec![
+            "/sys/fs/cgroup/system.slice/app.service/memory.max",
+            "/sys/fs/cgroup/system.slice/memory.max",
+            "/sys/fs/cgroup/memory.max",
+        ]
+    );
+    assert_eq!(
+        files("12:cpu,cpuacct:/docker/abc\n4:memory:/docker/abc\n"),
+        vec![
+            "/sys/fs/cgroup/memory/docker/abc/memory.limit_in_bytes",
+            "/sys/fs/cgroup/memory/docker/memory.limit_in_bytes",
+            "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+        ]
+    );
+    assert_eq!(files("0::/\n"), vec!["/sys/fs/cgroup/memory.max"]);
+    assert!(files("3:pids:/user.slice\n").is_empty());
+    assert!(files("").is_empty());
+
+    assert_eq!(parse_cgroup_limit("536870912\n"), Some(512 * 1024 * 1024));
+    assert_eq!(parse_cgroup_limit("max\n"), None);
+    assert_eq!(parse_cgroup_limit("9223372036854771712\n"), None);
+}
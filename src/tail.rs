@@ -0,0 +1,112 @@
+use std::ops::Bound;
+
+use crate::*;
+
+/// An iterator over the keys of a `Tree` from a given key
+/// onwards that, instead of ending after the last key, waits
+/// for keys to be inserted beyond it, returned by
+/// `Tree::tail`.
+///
+/// Keys are returned in strictly increasing order, so a key
+/// that is inserted at or before the last key returned is
+/// never returned, even if it was not present when that key
+/// was returned. Keys that are inserted beyond the last key
+/// returned are returned in key order, rather than in the
+/// order they were inserted in, along with their value at
+/// the time they are read. Removals are not reported.
+pub struct TailIter {
+    tree: Tree,
+    subscriber: Subscriber,
+    // the key after the last one returned, or the key that
+    // the tail started at
+    position: Bound<IVec>,
+    // `None` once the keys present at the last scan have
+    // been returned
+    iter: Option<Iter>,
+}
+
+impl TailIter {
+    pub(crate) fn new(tree: &Tree, from: &[u8]) -> TailIter {
+        // subscribe before scanning, so that no insertion
+        // falls between the scan and the subscription
+        let subscriber =
+            tree.watch_prefix_filtered(vec![], EventFilter::Inserts);
+        let position = Bound::Included(IVec::from(from));
+        let iter = Some(tree.range((position.clone(), Bound::Unbounded)));
+
+        TailIter { tree: tree.clone(), subscriber, position, iter }
+    }
+
+    /// Returns the next item without waiting for one if the
+    /// current keys of the tree have all been returned.
+    fn next_present(&mut self) -> Option<Result<(IVec, IVec)>> {
+        let item = self.iter.as_mut()?.next();
+        match item {
+            Some(Ok((key, value))) => {
+                self.position = Bound::Excluded(key.clone());
+                Some(Ok((key, value)))
+            }
+            Some(Err(e)) => Some(Err(e)),
+            None => {
+                self.iter = None;
+                None
+            }
+        }
+    }
+
+    /// Scans the tree again if `event` inserted a key beyond
+    /// the current position. Older events, which the last
+    /// scan already covered, are skipped.
+    fn observe(&mut self, event: &Event) {
+        let beyond = match &self.position {
+            Bound::Included(position) => event.key() >= position,
+            Bound::Excluded(position) => event.key() > position,
+            Bound::Unbounded => true,
+        };
+
+        if beyond {
+            let range = (self.position.clone(), Bound::Unbounded);
+            self.iter = Some(self.tree.range(range));
+        }
+    }
+
+    /// Asynchronously waits for the next item, like `next`
+    /// does, but without blocking the thread.
+    pub async fn next_async(&mut self) -> Option<Result<(IVec, IVec)>> {
+        loop {
+            if let Some(item) = self.next_present() {
+                return Some(item);
+            }
+
+            let event = (&mut self.subscriber).await?;
+            self.observe(&event);
+        }
+    }
+}
+
+impl Iterator for TailIter {
+    type Item = Result<(IVec, IVec)>;
+
+    /// Returns the next key and its value, blocking until a
+    /// key is inserted beyond the last one returned if there
+    /// is none yet, so it never returns `None`.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.next_present() {
+                return Some(item);
+            }
+
+            let event = self.subscriber.next()?;
+            self.observe(&event);
+        }
+    }
+}
+
+impl Debug for TailIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TailIter")
+            .field("tree", &self.tree.name())
+            .field("position", &self.position)
+            .finish()
+    }
+}
@@ -73,7 +73,16 @@
 //! # }
 //! ```
 #![allow(clippy::module_name_repetitions)]
-use std::{cell::RefCell, fmt, rc::Rc};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt,
+    iter::Peekable,
+    ops::{Bound, RangeBounds},
+    rc::Rc,
+    vec,
+};
 
 #[cfg(not(feature = "testing"))]
 use std::collections::HashMap as Map;
@@ -84,8 +93,8 @@ use std::collections::HashMap as Map;
 use std::collections::BTreeMap as Map;
 
 use crate::{
-    concurrency_control, pin, Batch, Error, Guard, IVec, Protector, Result,
-    Tree,
+    concurrency_control, pin, prefix_upper_bound, Batch, Error, Guard, IVec,
+    Iter, Protector, Result, Tree,
 };
 
 /// A transaction that will
@@ -96,6 +105,18 @@ pub struct TransactionalTree {
     pub(super) tree: Tree,
     pub(super) writes: Rc<RefCell<Map<IVec, Option<IVec>>>>,
     pub(super) read_cache: Rc<RefCell<Map<IVec, Option<IVec>>>>,
+    predicates: Rc<RefCell<Vec<RangePredicate>>>,
+}
+
+/// A range that a transaction requires to be unchanged
+/// when it commits, see
+/// `TransactionalTree::assert_range_unchanged`.
+#[derive(Debug, Clone)]
+struct RangePredicate {
+    lo: Bound<IVec>,
+    hi: Bound<IVec>,
+    write_generation: u64,
+    fingerprint: u32,
 }
 
 /// An error type that is returned from the closure
@@ -265,10 +286,11 @@ impl TransactionalTree {
         IVec: From<K> + From<V>,
         K: AsRef<[u8]>,
     {
+        let new = IVec::from(value);
+        self.tree.context.check_item_size(key.as_ref(), Some(&new))?;
         let old = self.get(key.as_ref())?;
         let mut writes = self.writes.borrow_mut();
-        let _last_write =
-            writes.insert(IVec::from(key), Some(IVec::from(value)));
+        let _last_write = writes.insert(IVec::from(key), Some(new));
         Ok(old)
     }
 
@@ -287,6 +309,47 @@ impl TransactionalTree {
         old
     }
 
+    /// Remove a key only if its value equals `expected`,
+    /// returning `true` if it did and was removed. Like
+    /// `Tree::remove_if`, but the value is compared as this
+    /// transaction sees it, including its own writes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::TransactionResult;
+    /// # use sled::Config;
+    /// # fn main() -> TransactionResult<()> {
+    /// let db = Config::new().temporary(true).open()?;
+    /// db.insert(b"lock", b"owner-1")?;
+    ///
+    /// let released = db.transaction(|tx| {
+    ///     assert!(!tx.remove_if(b"lock", b"owner-2")?);
+    ///     Ok(tx.remove_if(b"lock", b"owner-1")?)
+    /// })?;
+    /// assert!(released);
+    /// assert_eq!(db.get(b"lock")?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn remove_if<K, V>(
+        &self,
+        key: K,
+        expected: V,
+    ) -> UnabortableTransactionResult<bool>
+    where
+        IVec: From<K>,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        match self.get(key.as_ref())? {
+            Some(current) if current == expected.as_ref() => {
+                let _old = self.remove(key)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
     /// Get the value associated with a key
     pub fn get<K: AsRef<[u8]>>(
         &self,
@@ -303,11 +366,12 @@ impl TransactionalTree {
 
         // not found in a cache, need to hit the backing db
         let mut guard = pin();
-        let get = loop {
+        let stored = loop {
             if let Ok(get) = self.tree.get_inner(key.as_ref(), &mut guard)? {
                 break get;
             }
         };
+        let get = self.tree.resolve(stored)?;
         let last = reads.insert(key.as_ref().into(), get.clone());
         assert!(last.is_none());
 
@@ -315,26 +379,296 @@ impl TransactionalTree {
     }
 
     /// Atomically apply multiple inserts and removals.
+    ///
+    /// The writes of the batch are staged like those of
+    /// `insert` and `remove`, so they are visible to later
+    /// reads in the same transaction, may be overwritten by
+    /// later writes, and are committed atomically with the
+    /// rest of the transaction, including its writes to
+    /// other trees, or discarded if it aborts. Unlike
+    /// `insert` and `remove`, this does not read the
+    /// previous values of the keys. The keys of the batch
+    /// need no separate conflict detection, because a
+    /// running transaction excludes every other atomic
+    /// write to the `Db`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::{abort, TransactionResult, Transactional};
+    /// # use sled::{Batch, Config};
+    /// # fn main() -> TransactionResult<(), ()> {
+    /// let db = Config::new().temporary(true).open()?;
+    /// let items = db.open_tree(b"items")?;
+    /// let stats = db.open_tree(b"stats")?;
+    ///
+    /// let mut batch = Batch::default();
+    /// batch.insert(b"a", b"1");
+    /// batch.insert(b"b", b"2");
+    ///
+    /// (&items, &stats).transaction(|(items, stats)| {
+    ///     items.apply_batch(&batch)?;
+    ///     assert_eq!(items.get(b"a")?, Some(sled::IVec::from(b"1")));
+    ///     stats.insert(b"count", &2_u64.to_be_bytes())?;
+    ///     Ok(())
+    /// })?;
+    /// assert_eq!(items.len(), 2);
+    ///
+    /// // an aborted transaction discards the batch
+    /// let res: TransactionResult<(), ()> = items.transaction(|items| {
+    ///     items.apply_batch(&batch)?;
+    ///     abort(())
+    /// });
+    /// assert!(res.is_err());
+    /// # Ok(()) }
+    /// ```
     pub fn apply_batch(
         &self,
         batch: &Batch,
     ) -> UnabortableTransactionResult<()> {
         for (k, v_opt) in &batch.writes {
             if let Some(v) = v_opt {
-                let _old = self.insert(k, v)?;
-            } else {
-                let _old = self.remove(k)?;
+                self.tree.context.check_item_size(k, Some(v))?;
             }
         }
+
+        let mut writes = self.writes.borrow_mut();
+        for (k, v_opt) in &batch.writes {
+            let _last_write = writes.insert(k.clone(), v_opt.clone());
+        }
         Ok(())
     }
 
+    /// Makes the transaction retry if any committed key in
+    /// `range` is inserted, removed or changed by another
+    /// writer between this call and the commit of the
+    /// transaction. This guards invariants over ranges, such
+    /// as uniqueness within a secondary index, against
+    /// phantom writes, which the reads of `get` don't cover
+    /// because they only see the keys that they ask for.
+    ///
+    /// Only the committed contents of the range are checked.
+    /// The writes of this transaction are not committed yet,
+    /// and do not count as changes.
+    ///
+    /// A transaction already holds a lock that excludes all
+    /// other writes to the `Db` from the moment its closure
+    /// starts until it commits, so no other writer can
+    /// currently change the range in between, and asserting
+    /// it never causes a retry by itself. The assertion
+    /// makes the invariant explicit, and keeps holding if
+    /// transactions ever stop excluding other writers.
+    ///
+    /// This reads every item of the range once, to record a
+    /// checksum of it, which costs as much as iterating over
+    /// the range. When the transaction commits, the range is
+    /// only read again if the tree was written in the
+    /// meantime, which makes the check itself constant time
+    /// otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::{abort, TransactionResult};
+    /// # use sled::Config;
+    /// # fn main() -> TransactionResult<(), ()> {
+    /// let db = Config::new().temporary(true).open()?;
+    /// db.insert(b"user/1", b"alice")?;
+    ///
+    /// db.transaction(|tx| {
+    ///     tx.assert_range_unchanged(&b"user/"[..]..&b"user0"[..])?;
+    ///     if tx.get(b"user/2")?.is_some() {
+    ///         return abort(());
+    ///     }
+    ///     tx.insert(b"user/2", b"bob")?;
+    ///     Ok(())
+    /// })?;
+    /// # Ok(()) }
+    /// ```
+    pub fn assert_range_unchanged<K, R>(
+        &self,
+        range: R,
+    ) -> UnabortableTransactionResult<()>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let iter = self.tree.range(range);
+        let (lo, hi) = (iter.lo, iter.hi);
+
+        // read before the range, so that a write that the
+        // checksum misses always moves it
+        let write_generation = self.tree.write_generation();
+        let fingerprint = self.fingerprint(&lo, &hi)?;
+
+        self.predicates.borrow_mut().push(RangePredicate {
+            lo,
+            hi,
+            write_generation,
+            fingerprint,
+        });
+        Ok(())
+    }
+
+    /// Create an iterator over the keys and values in `range`
+    /// as this transaction sees them, including the writes
+    /// that it made before this call, and excluding the keys
+    /// that it removed. Writes that the transaction makes
+    /// after this call are not visible to the iterator.
+    ///
+    /// The whole range takes part in conflict detection like
+    /// with `assert_range_unchanged`, even the part after
+    /// where the iteration stopped: the transaction retries
+    /// if any committed key in `range` is inserted, removed
+    /// or changed by another writer before it commits. This
+    /// makes it safe to decide what to write based on what
+    /// the scan returned, such as updating every item of the
+    /// range or inserting a key that no item of the range
+    /// has. A wide range, such as `..`, conflicts with every
+    /// write to the tree.
+    ///
+    /// Like `assert_range_unchanged`, this reads the range
+    /// once up front, to record a checksum of it, so a full
+    /// iteration costs as much as iterating over the range
+    /// of the tree twice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::TransactionResult;
+    /// # use sled::{Config, IVec};
+    /// # fn main() -> TransactionResult<()> {
+    /// let db = Config::new().temporary(true).open()?;
+    /// db.insert(b"a", b"1")?;
+    /// db.insert(b"b", b"2")?;
+    /// db.insert(b"c", b"3")?;
+    ///
+    /// db.transaction(|tx| {
+    ///     tx.remove(b"b")?;
+    ///     tx.insert(b"bb", b"4")?;
+    ///
+    ///     // double every value in the range
+    ///     let start: &[u8] = b"a";
+    ///     let end: &[u8] = b"c";
+    ///     for item in tx.range(start..end)? {
+    ///         let (k, v) = item?;
+    ///         tx.insert(k, vec![v[0]; 2])?;
+    ///     }
+    ///     Ok(())
+    /// })?;
+    ///
+    /// assert_eq!(db.get(b"a")?, Some(IVec::from(b"11")));
+    /// assert_eq!(db.get(b"b")?, None);
+    /// assert_eq!(db.get(b"bb")?, Some(IVec::from(b"44")));
+    /// assert_eq!(db.get(b"c")?, Some(IVec::from(b"3")));
+    /// # Ok(()) }
+    /// ```
+    pub fn range<K, R>(
+        &self,
+        range: R,
+    ) -> UnabortableTransactionResult<
+        impl Iterator<Item = Result<(IVec, IVec)>>,
+    >
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let Iter { lo, hi, .. } = self.tree.range(range);
+        self.assert_range_unchanged((lo.clone(), hi.clone()))?;
+
+        let bounds = (lo.clone(), hi.clone());
+        let mut writes: Vec<(IVec, Option<IVec>)> = self
+            .writes
+            .borrow()
+            .iter()
+            .filter(|(k, _)| bounds.contains(*k))
+            .map(|(k, v_opt)| (k.clone(), v_opt.clone()))
+            .collect();
+        writes.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        // the transaction holds the concurrency control
+        // lock, so the iterator must not take it again
+        Ok(TransactionalIter {
+            committed: self.tree.range::<IVec, _>(bounds).relaxed().peekable(),
+            writes: writes.into_iter().peekable(),
+        })
+    }
+
+    /// Create an iterator over the keys and values where the
+    /// keys start with `prefix`, as this transaction sees
+    /// them. This is `TransactionalTree::range` over the
+    /// range from the prefix to `prefix_upper_bound` of the
+    /// prefix, and takes part in conflict detection the same
+    /// way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sled::transaction::TransactionResult;
+    /// # use sled::Config;
+    /// # fn main() -> TransactionResult<()> {
+    /// let db = Config::new().temporary(true).open()?;
+    /// db.insert(b"user/1", b"alice")?;
+    ///
+    /// let users = db.transaction(|tx| {
+    ///     tx.insert(b"user/2", b"bob")?;
+    ///     Ok(tx.scan_prefix(b"user/")?.count())
+    /// })?;
+    /// assert_eq!(users, 2);
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_prefix<P>(
+        &self,
+        prefix: P,
+    ) -> UnabortableTransactionResult<
+        impl Iterator<Item = Result<(IVec, IVec)>>,
+    >
+    where
+        P: AsRef<[u8]>,
+    {
+        let prefix_ref = prefix.as_ref();
+        let hi = match prefix_upper_bound(prefix_ref) {
+            Some(upper) => Bound::Excluded(IVec::from(upper)),
+            None => Bound::Unbounded,
+        };
+        self.range((Bound::Included(IVec::from(prefix_ref)), hi))
+    }
+
+    fn fingerprint(&self, lo: &Bound<IVec>, hi: &Bound<IVec>) -> Result<u32> {
+        let mut hasher = crc32fast::Hasher::new();
+
+        // the transaction holds the concurrency control
+        // lock, so the iterator must not take it again
+        let range = (lo.clone(), hi.clone());
+        for item in self.tree.range::<IVec, _>(range).relaxed() {
+            let (k, v) = item?;
+            hasher.update(&u64::try_from(k.len()).unwrap().to_le_bytes());
+            hasher.update(&k);
+            hasher.update(&u64::try_from(v.len()).unwrap().to_le_bytes());
+            hasher.update(&v);
+        }
+
+        Ok(hasher.finalize())
+    }
+
     fn unstage(&self) {
-        unimplemented!()
+        self.writes.borrow_mut().clear();
+        self.read_cache.borrow_mut().clear();
+        self.predicates.borrow_mut().clear();
     }
 
-    const fn validate(&self) -> bool {
-        true
+    fn validate(&self) -> Result<bool> {
+        for predicate in &*self.predicates.borrow() {
+            if predicate.write_generation == self.tree.write_generation() {
+                continue;
+            }
+            let fingerprint =
+                self.fingerprint(&predicate.lo, &predicate.hi)?;
+            if fingerprint != predicate.fingerprint {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     }
 
     fn commit(&self) -> Result<()> {
@@ -352,6 +686,45 @@ impl TransactionalTree {
             tree: tree.clone(),
             writes: Default::default(),
             read_cache: Default::default(),
+            predicates: Default::default(),
+        }
+    }
+}
+
+/// Merges the committed items of a range with the writes
+/// that a transaction made to it, which take precedence.
+struct TransactionalIter {
+    committed: Peekable<Iter>,
+    writes: Peekable<vec::IntoIter<(IVec, Option<IVec>)>>,
+}
+
+impl Iterator for TransactionalIter {
+    type Item = Result<(IVec, IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.committed.peek(), self.writes.peek()) {
+                (None, None) => return None,
+                (Some(Err(_)), _) | (Some(Ok(_)), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(Ok((committed, _))), Some((written, _))) => {
+                    committed.cmp(written)
+                }
+            };
+
+            let (k, v_opt) = match order {
+                Ordering::Less => return self.committed.next(),
+                Ordering::Equal => {
+                    let _shadowed = self.committed.next();
+                    self.writes.next().unwrap()
+                }
+                Ordering::Greater => self.writes.next().unwrap(),
+            };
+
+            // removed by the transaction
+            if let Some(v) = v_opt {
+                return Some(Ok((k, v)));
+            }
         }
     }
 }
@@ -363,19 +736,9 @@ pub struct TransactionalTrees {
 
 impl TransactionalTrees {
     fn stage(&self) -> UnabortableTransactionResult<Vec<Protector<'_>>> {
-        // we want to stage our trees in
-        // lexicographic order to guarantee
-        // no deadlocks should they block
-        // on mutexes in their own staging
-        // implementations.
-        let mut tree_idxs: Vec<(&[u8], usize)> = self
-            .inner
-            .iter()
-            .enumerate()
-            .map(|(idx, t)| (&*t.tree.tree_id, idx))
-            .collect();
-        tree_idxs.sort_unstable();
-
+        // one global lock serializes all transactions, so
+        // there is no per-tree locking order to follow, and
+        // single-tree transactions take the same path
         let all_guards = vec![concurrency_control::write()];
         Ok(all_guards)
     }
@@ -386,16 +749,31 @@ impl TransactionalTrees {
         }
     }
 
-    fn validate(&self) -> bool {
+    fn validate(&self) -> Result<bool> {
         for tree in &self.inner {
-            if !tree.validate() {
-                return false;
+            if !tree.validate()? {
+                return Ok(false);
             }
         }
-        true
+        Ok(true)
     }
 
     fn commit(&self, guard: &Guard) -> Result<()> {
+        // refuse the whole transaction up front rather
+        // than failing after some trees were written
+        let pagecache = &*self.inner[0].tree.context.pagecache;
+        if !self
+            .inner
+            .iter()
+            .all(|tree| std::ptr::eq(&*tree.tree.context.pagecache, pagecache))
+        {
+            return Err(Error::Unsupported(
+                "transactions can not span a tree with a separate log \
+                 and other trees"
+                    .to_owned(),
+            ));
+        }
+        self.inner[0].tree.context.check_free_space()?;
         let peg = self.inner[0].tree.context.pin_log(guard)?;
         for tree in &self.inner {
             tree.commit()?;
@@ -406,6 +784,11 @@ impl TransactionalTrees {
         // recovered atomically
         peg.seal_batch()
     }
+
+    /// See `Context::evict_over_limit`.
+    fn evict_over_limit(&self) {
+        self.inner[0].tree.context.evict_over_limit(None);
+    }
 }
 
 /// A simple constructor for `Err(TransactionError::Abort(_))`
@@ -440,15 +823,16 @@ pub trait Transactional<E = ()> {
             let tt = self.make_overlay();
             let view = Self::view_overlay(&tt);
 
-            // NB locks must exist until this function returns.
-            let _locks = if let Ok(l) = tt.stage() {
+            // NB locks must exist until the transaction is
+            // committed or given up on.
+            let locks = if let Ok(l) = tt.stage() {
                 l
             } else {
                 tt.unstage();
                 continue;
             };
             let ret = f(&view);
-            if !tt.validate() {
+            if !tt.validate()? {
                 tt.unstage();
                 continue;
             }
@@ -456,6 +840,8 @@ pub trait Transactional<E = ()> {
                 Ok(r) => {
                     let guard = pin();
                     tt.commit(&guard)?;
+                    drop(locks);
+                    tt.evict_over_limit();
                     return Ok(r);
                 }
                 Err(ConflictableTransactionError::Abort(e)) => {
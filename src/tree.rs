@@ -5,12 +5,17 @@ use std::{
     ops::{self, Deref, RangeBounds},
     sync::{
         atomic::{AtomicU64, Ordering::SeqCst},
+        mpsc::{sync_channel, Receiver},
     },
+    thread,
 };
 
 use parking_lot::RwLock;
 
-use crate::{pagecache::NodeView, *};
+use crate::{
+    pagecache::{NodeView, PageView},
+    *,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) struct View<'g> {
@@ -36,6 +41,38 @@ impl IntoIterator for &'_ Tree {
     }
 }
 
+/// Inserts all items using `Tree::bulk_load`.
+///
+/// # Panics
+///
+/// Panics if the underlying `bulk_load` returns an
+/// error. Call `Tree::bulk_load` directly to handle it.
+impl<K, V> Extend<(K, V)> for &'_ Tree
+where
+    K: AsRef<[u8]>,
+    IVec: From<V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, items: I) {
+        self.bulk_load(items).expect("failed to extend Tree");
+    }
+}
+
+/// Inserts all items using `Tree::bulk_load`.
+///
+/// # Panics
+///
+/// Panics if the underlying `bulk_load` returns an
+/// error. Call `Tree::bulk_load` directly to handle it.
+impl<K, V> Extend<(K, V)> for Tree
+where
+    K: AsRef<[u8]>,
+    IVec: From<V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, items: I) {
+        self.bulk_load(items).expect("failed to extend Tree");
+    }
+}
+
 /// A flash-sympathetic persistent lock-free B+ tree
 ///
 /// # Examples
@@ -80,6 +117,40 @@ pub struct TreeInner {
     pub(crate) subscribers: Subscribers,
     pub(crate) root: AtomicU64,
     pub(crate) merge_operator: RwLock<Option<Box<dyn MergeOperator>>>,
+    pub(crate) write_generation: AtomicU64,
+    /// The log bytes taken by writes to this tree's pages
+    /// since the last `Db::flush_detailed`.
+    pub(crate) logged_bytes: AtomicU64,
+    pub(crate) checksum_cache: Mutex<ChecksumCache>,
+    pub(crate) subtree_counts: Mutex<SubtreeCounts>,
+    /// The store of shared values, if `Config::dedup_values`
+    /// is set. Values in this tree are then stored in the
+    /// tagged form described in the `dedup` module.
+    pub(crate) dedup: Option<Dedup>,
+}
+
+/// The results of the last `Tree::checksum` and
+/// `Tree::checksum_ranges` calls, along with the
+/// write generation that they were computed at.
+#[derive(Debug, Default)]
+pub(crate) struct ChecksumCache {
+    /// `(generation, checksum)`
+    checksum: Option<(u64, u64)>,
+    /// `(generation, granularity)` of `ranges`
+    ranges_key: Option<(u64, usize)>,
+    ranges: Vec<(IVec, u64)>,
+}
+
+/// The numbers of keys below index nodes that were counted
+/// by `Tree::range_count`, along with the write generation
+/// that they were counted at. They are keyed by the bounds
+/// of the nodes rather than their pages, because splits and
+/// merges don't change the number of keys within bounds,
+/// so only writes make them stale.
+#[derive(Debug, Default)]
+pub(crate) struct SubtreeCounts {
+    generation: u64,
+    counts: FastMap8<(IVec, IVec), u64>,
 }
 
 impl Deref for Tree {
@@ -127,23 +198,47 @@ impl Tree {
         K: AsRef<[u8]>,
         IVec: From<V>,
     {
+        let _latency = self.context.latency_timer(|l| &l.insert);
+        self.context.check_free_space()?;
         let value = IVec::from(value);
+        self.context.check_item_size(key.as_ref(), Some(&value))?;
         let mut guard = pin();
-        let _cc = concurrency_control::read();
-        loop {
+        let (cc, peg) = self.single_key_write(&guard)?;
+        let coalesce = peg.is_none() && self.context.coalesce_writes;
+        let res = loop {
             trace!("setting key {:?}", key.as_ref());
-            if let Ok(res) =
-                self.insert_inner(key.as_ref(), Some(value.clone()), &mut guard)?
-            {
-                return Ok(res);
+            if let Ok(res) = self.write_inner(
+                key.as_ref(),
+                Some(value.clone()),
+                coalesce,
+                &mut guard,
+            )? {
+                peg.map_or(Ok(()), RecoveryGuard::seal_batch)?;
+                break res;
             }
-        }
+        };
+        drop(cc);
+        self.context.evict_over_limit(Some((&self.tree_id, key.as_ref())));
+        Ok(res)
     }
 
     pub(crate) fn insert_inner(
+        &self,
+        key: &[u8],
+        value: Option<IVec>,
+        guard: &mut Guard,
+    ) -> Result<Abortable<Option<IVec>>> {
+        self.write_inner(key, value, false, guard)
+    }
+
+    /// Sets or removes `key`, with the write left out of the
+    /// log until the next flush if `coalesce` is set. See
+    /// `Config::coalesce_writes`.
+    fn write_inner(
         &self,
         key: &[u8],
         mut value: Option<IVec>,
+        coalesce: bool,
         guard: &mut Guard,
     ) -> Result<Abortable<Option<IVec>>> {
         let _measure = Measure::new(&M.tree_set);
@@ -151,38 +246,51 @@ impl Tree {
         let View { node_view, pid, .. } =
             self.view_for_key(key.as_ref(), guard)?;
 
-        let mut subscriber_reservation = self.subscribers.reserve(&key);
+        let mut subscriber_reservation =
+            self.subscribers.reserve(key, value.is_some());
 
-        let (encoded_key, last_value) = node_view.node_kv_pair(key.as_ref());
+        let (encoded_key, last_stored) = node_view.node_kv_pair(key.as_ref());
+        let last_value = self.resolve(last_stored.clone())?;
 
         if value == last_value {
             // short-circuit a no-op set or delete
             return Ok(Ok(value))
         }
 
-        let frag = if let Some(value) = value.clone() {
-            Link::Set(encoded_key, value)
+        let stored = self.store_value(value.clone())?;
+
+        let frag = if let Some(stored_value) = stored.clone() {
+            let version = self.next_version(&node_view, key);
+            Link::Set(encoded_key, stored_value, version)
         } else {
             Link::Del(encoded_key)
         };
 
-        let link = self.context.pagecache.link(
-            pid,
-            node_view.0,
-            frag,
-            guard,
-        )?;
+        let pagecache = &self.context.pagecache;
+        let link = if coalesce {
+            pagecache.link_coalesced(pid, node_view.0, frag, guard)?
+        } else {
+            pagecache.link(pid, node_view.0, frag, guard)?
+        };
 
-        if let Ok(_new_cas_key) = link {
+        if let Ok(new_view) = link {
             // success
+            if !coalesce {
+                self.count_logged(&new_view);
+            }
+            let value_len = value.as_ref().map(|v| v.len());
             if let Some(res) = subscriber_reservation.take() {
                 let event = if let Some(value) = value.take() {
                     subscriber::Event::Insert {
                         key: key.as_ref().into(),
                         value,
+                        previous: last_value.clone(),
                     }
                 } else {
-                    subscriber::Event::Remove { key: key.as_ref().into() }
+                    subscriber::Event::Remove {
+                        key: key.as_ref().into(),
+                        previous: last_value.clone(),
+                    }
                 };
 
                 res.complete(&event);
@@ -190,15 +298,28 @@ impl Tree {
 
             guard.writeset.push(pid);
 
+            self.release(last_stored.as_ref())?;
+            self.bump_write_generation()?;
+            self.record_write(key, value_len);
+
             return Ok(Ok(last_value));
         }
 
+        self.release(stored.as_ref())?;
         M.tree_looped();
         Ok(Err(Abort))
     }
 
     /// Perform a multi-key serializable transaction.
     ///
+    /// This is the single-tree form of
+    /// `Transactional::transaction`: the closure receives
+    /// one `TransactionalTree` instead of a tuple, and the
+    /// conflict detection and retries are the same. All
+    /// transactions are serialized by one lock, whether
+    /// they involve one tree or many, so this is no slower
+    /// than a transaction on a tuple of trees, nor faster.
+    ///
     /// # Examples
     ///
     /// ```
@@ -319,12 +440,295 @@ impl Tree {
         Transactional::transaction(&self, f)
     }
 
+    /// Inserts every key-value pair yielded by `items`,
+    /// as if by calling `Tree::insert` for each of them
+    /// in order. If a key is repeated, the last value
+    /// for it wins.
+    ///
+    /// This is intended for loading large amounts of
+    /// data, and is fastest when the keys are yielded in
+    /// ascending order. Runs of sorted keys are written
+    /// into leaf nodes directly, so that a whole run of
+    /// keys that belong to the same leaf is installed with
+    /// a single tree descent and a single write to the
+    /// log, rather than one of each per key.
+    ///
+    /// Beware of the performance cliff for unsorted input:
+    /// every key that sorts before the key that preceded
+    /// it falls back to a regular `Tree::insert`, which
+    /// performs a full descent and log write of its own.
+    /// Input that is mostly unsorted will therefore load
+    /// no faster than a loop of `insert` calls. Sort the
+    /// input first if possible. With
+    /// `Config::dedup_values`, every item is inserted
+    /// separately, since each value must be looked up in
+    /// the store of shared values.
+    ///
+    /// This is not atomic. If an error is returned,
+    /// some prefix of `items` may have been inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.bulk_load((0..100_u32).map(|i| (i.to_be_bytes(), vec![])))?;
+    /// assert_eq!(t.len(), 100);
+    /// # Ok(()) }
+    /// ```
+    pub fn bulk_load<I, K, V>(&self, iter: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<[u8]>,
+        IVec: From<V>,
+    {
+        let items = iter.into_iter().map(|(k, v)| {
+            (<IVec as From<&[u8]>>::from(k.as_ref()), IVec::from(v))
+        });
+        let res = self.load_runs(items, None);
+        // also after a failure, which may follow earlier runs
+        self.context.evict_over_limit(None);
+        res
+    }
+
+    /// Inserts every key-value pair of `sorted`, whose keys
+    /// must be in ascending order, and returns the value that
+    /// each key had before, in the order of `sorted`. If a key
+    /// is repeated, the previous value returned for each
+    /// repetition is the one it was set to by the repetition
+    /// before it.
+    ///
+    /// Like `bulk_load`, this writes the keys that belong to
+    /// the same leaf with a single tree descent and a single
+    /// write to the log, so loading many sorted keys is much
+    /// faster than calling `insert` for each of them. With
+    /// `Config::dedup_values`, every item is inserted
+    /// separately.
+    ///
+    /// Returns `Error::Unsupported` without inserting
+    /// anything if the keys are not sorted. Otherwise, this
+    /// is not atomic: if an error is returned, some prefix
+    /// of `sorted` may have been inserted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::IVec;
+    ///
+    /// let t = sled::Config::new().temporary(true).open()?;
+    /// t.insert(b"b", b"old")?;
+    ///
+    /// let previous = t.insert_many(vec![
+    ///     (IVec::from(b"a"), IVec::from(b"1")),
+    ///     (IVec::from(b"b"), IVec::from(b"2")),
+    /// ])?;
+    /// assert_eq!(previous, vec![None, Some(IVec::from(b"old"))]);
+    /// assert_eq!(t.get(b"b")?, Some(IVec::from(b"2")));
+    /// # Ok(()) }
+    /// ```
+    pub fn insert_many<I>(&self, sorted: I) -> Result<Vec<Option<IVec>>>
+    where
+        I: IntoIterator<Item = (IVec, IVec)>,
+    {
+        // collected first, so that unsorted input is refused
+        // before anything is written
+        let items: Vec<(IVec, IVec)> = sorted.into_iter().collect();
+        if let Some(pair) = items.windows(2).find(|pair| pair[1].0 < pair[0].0)
+        {
+            return Err(Error::Unsupported(format!(
+                "insert_many requires keys in ascending order, \
+                 but {:?} follows {:?}",
+                pair[1].0, pair[0].0
+            )));
+        }
+
+        let mut previous = Vec::with_capacity(items.len());
+        let res = self.load_runs(items.into_iter(), Some(&mut previous));
+        // also after a failure, which may follow earlier runs
+        self.context.evict_over_limit(None);
+        res.map(|()| previous)
+    }
+
+    /// Writes `items` into leaves a sorted run at a time, see
+    /// `bulk_load`, pushing the previous value of each item
+    /// to `previous_values` if it is given.
+    fn load_runs<I>(
+        &self,
+        iter: I,
+        mut previous_values: Option<&mut Vec<Option<IVec>>>,
+    ) -> Result<()>
+    where
+        I: Iterator<Item = (IVec, IVec)>,
+    {
+        let mut items = iter.peekable();
+
+        if self.dedup.is_some() {
+            for (key, value) in items {
+                let last = self.insert::<_, IVec>(key, value)?;
+                if let Some(out) = previous_values.as_mut() {
+                    out.push(last);
+                }
+            }
+            return Ok(());
+        }
+
+        // the sorted run of items that are being
+        // loaded into the same leaf
+        let mut run: Vec<(IVec, IVec)> = vec![];
+        let mut last_key: Option<IVec> = None;
+
+        loop {
+            // checked once per leaf rather than once per item
+            self.context.check_free_space()?;
+
+            if run.is_empty() {
+                let (key, value) = if let Some(item) = items.next() {
+                    item
+                } else {
+                    return Ok(());
+                };
+                self.context.check_item_size(&key, Some(&value))?;
+
+                let unsorted = match last_key {
+                    Some(ref last) => key < *last,
+                    None => false,
+                };
+
+                if unsorted {
+                    // unsorted input, fall back to a regular insert
+                    let last = self.insert::<_, IVec>(key, value)?;
+                    if let Some(out) = previous_values.as_mut() {
+                        out.push(last);
+                    }
+                    continue;
+                }
+
+                run.push((key, value));
+            }
+
+            let _measure = Measure::new(&M.tree_set);
+            let guard = pin();
+            let cc = concurrency_control::read();
+
+            let View { node_view, pid, .. } =
+                self.view_for_key(&run[0].0, &guard)?;
+
+            if node_view.merging {
+                // let a regular insert deal with the merge
+                drop(cc);
+                let (key, value) = run.remove(0);
+                let last = self.insert::<_, IVec>(&key, value)?;
+                if let Some(out) = previous_values.as_mut() {
+                    out.push(last);
+                }
+                last_key = Some(key);
+                continue;
+            }
+
+            let fits =
+                |key: &IVec| node_view.hi.is_empty() || *key < node_view.hi;
+
+            let mut node: Node = node_view.deref().clone();
+            let mut applied = 0;
+            let mut changed = vec![];
+            let mut run_previous = vec![];
+
+            loop {
+                if applied == run.len() {
+                    // pull more items into the run while
+                    // they are sorted and fit in this leaf
+                    let extends_run = match items.peek() {
+                        // oversized items end the run, and
+                        // are refused when they start the next
+                        Some((key, value)) => {
+                            *key >= run[applied - 1].0
+                                && fits(key)
+                                && !node.should_split()
+                                && self
+                                    .context
+                                    .check_item_size(key, Some(value))
+                                    .is_ok()
+                        }
+                        None => false,
+                    };
+                    if !extends_run {
+                        break;
+                    }
+                    run.push(items.next().unwrap());
+                }
+
+                let (key, value) = &run[applied];
+                if !fits(key) {
+                    break;
+                }
+
+                let (encoded_key, last_value) = node.node_kv_pair(key);
+                if last_value.as_ref() != Some(value) {
+                    let version = self.next_version(&node, key);
+                    node.set_leaf(encoded_key, value.clone(), version);
+                    changed.push((applied, last_value.clone()));
+                }
+                if previous_values.is_some() {
+                    run_previous.push(last_value);
+                }
+                applied += 1;
+            }
+
+            if !changed.is_empty() {
+                let reservations: Vec<_> = changed
+                    .iter()
+                    .map(|(idx, _)| {
+                        self.subscribers.reserve(&run[*idx].0, true)
+                    })
+                    .collect();
+
+                let replace = self.context.pagecache.replace(
+                    pid,
+                    node_view.0,
+                    node,
+                    &guard,
+                )?;
+
+                if let Ok(new_view) = &replace {
+                    self.count_logged(new_view);
+                } else {
+                    M.tree_looped();
+                    continue;
+                }
+
+                for ((idx, previous), reservation) in
+                    changed.into_iter().zip(reservations)
+                {
+                    let (key, value) = &run[idx];
+                    if let Some(res) = reservation {
+                        res.complete(&Event::Insert {
+                            key: key.clone(),
+                            value: value.clone(),
+                            previous,
+                        });
+                    }
+                    self.bump_write_generation()?;
+                    self.record_write(key, Some(value.len()));
+                }
+            }
+
+            if let Some(out) = previous_values.as_mut() {
+                out.extend(run_previous);
+            }
+            last_key = Some(run[applied - 1].0.clone());
+            run.drain(..applied);
+        }
+    }
+
     /// Create a new batched update that can be
     /// atomically applied.
     ///
-    /// It is possible to apply a `Batch` in a transaction
-    /// as well, which is the way you can apply a `Batch`
-    /// to multiple `Tree`s atomically.
+    /// To update several `Tree`s atomically, use a
+    /// `MultiTreeBatch` with `Db::apply_batch`, or apply
+    /// a `Batch` to each of them in a transaction.
     ///
     /// # Examples
     ///
@@ -349,9 +753,18 @@ impl Tree {
     /// # Ok(()) }
     /// ```
     pub fn apply_batch(&self, batch: Batch) -> Result<()> {
-        let _cc = concurrency_control::write();
+        self.context.check_free_space()?;
+        for (k, v_opt) in &batch.writes {
+            if let Some(v) = v_opt {
+                self.context.check_item_size(k, Some(v))?;
+            }
+        }
+        let cc = concurrency_control::write();
         let mut guard = pin();
-        self.apply_batch_inner(batch, &mut guard)
+        self.apply_batch_inner(batch, &mut guard)?;
+        drop(cc);
+        self.context.evict_over_limit(None);
+        Ok(())
     }
 
     pub(crate) fn apply_batch_inner(
@@ -360,6 +773,22 @@ impl Tree {
         guard: &mut Guard,
     ) -> Result<()> {
         let peg = self.context.pin_log(guard)?;
+        self.write_batch(batch, guard)?;
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()
+    }
+
+    /// Writes every update in the batch. Callers are
+    /// responsible for pinning the log around this so
+    /// that the writes are recovered atomically.
+    pub(crate) fn write_batch(
+        &self,
+        batch: Batch,
+        guard: &mut Guard,
+    ) -> Result<()> {
         trace!("applying batch {:?}", batch);
         for (k, v_opt) in batch.writes {
             loop {
@@ -368,11 +797,7 @@ impl Tree {
                 }
             }
         }
-
-        // when the peg drops, it ensures all updates
-        // written to the log since its creation are
-        // recovered atomically
-        peg.seal_batch()
+        Ok(())
     }
 
     /// Retrieve a value from the `Tree` if it exists.
@@ -391,11 +816,17 @@ impl Tree {
     /// # Ok(()) }
     /// ```
     pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        let _latency = self.context.latency_timer(|l| &l.get);
         let mut guard = pin();
         let _cc = concurrency_control::read();
         loop {
             if let Ok(get) = self.get_inner(key.as_ref(), &mut guard)? {
-                return Ok(get);
+                if let (Some(eviction), Some(_)) =
+                    (&self.context.eviction, &get)
+                {
+                    eviction.record_read(&self.tree_id, key.as_ref());
+                }
+                return self.resolve(get);
             }
         }
     }
@@ -419,13 +850,100 @@ impl Tree {
         Ok(Ok(val))
     }
 
-    #[doc(hidden)]
-    #[deprecated(since = "0.24.2", note = "replaced by `Tree::remove`")]
-    pub fn del<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
-        self.remove(key)
+    /// Returns a stored value in the form users see it,
+    /// which is the value itself unless this tree
+    /// deduplicates values. Callers must hold the
+    /// concurrency control, which keeps the shared values
+    /// that stored values refer to from being released.
+    pub(crate) fn resolve(&self, stored: Option<IVec>) -> Result<Option<IVec>> {
+        let (dedup, raw) = match (&self.dedup, &stored) {
+            (Some(dedup), Some(raw)) => (dedup, raw),
+            _ => return Ok(stored),
+        };
+        match dedup.load(raw)? {
+            Some(value) => Ok(Some(value)),
+            None => Err(Error::ReportableBug(
+                "a deduplicated value is missing from the store".to_owned(),
+            )),
+        }
     }
 
-    /// Delete a value, returning the old value if it existed.
+    /// Like `resolve`, for values read from a leaf that may
+    /// be stale, such as one cached by an iterator, where
+    /// the shared value may have been released since. The
+    /// key is then read again, returning `None` if it has
+    /// been removed.
+    pub(crate) fn resolve_current(
+        &self,
+        key: &[u8],
+        mut stored: IVec,
+    ) -> Result<Option<IVec>> {
+        let dedup = if let Some(dedup) = &self.dedup {
+            dedup
+        } else {
+            return Ok(Some(stored));
+        };
+
+        let mut guard = pin();
+        loop {
+            if let Some(value) = dedup.load(&stored)? {
+                return Ok(Some(value));
+            }
+            let current = loop {
+                if let Ok(current) = self.get_inner(key, &mut guard)? {
+                    break current;
+                }
+            };
+            stored = match current {
+                Some(next) => next,
+                None => return Ok(None),
+            };
+        }
+    }
+
+    /// Returns the form in which a new value is stored,
+    /// which refers to a shared copy of it if this tree
+    /// deduplicates values.
+    fn store_value(&self, value: Option<IVec>) -> Result<Option<IVec>> {
+        match (&self.dedup, &value) {
+            (Some(dedup), Some(new)) => dedup.intern(new).map(Some),
+            _ => Ok(value),
+        }
+    }
+
+    /// Drops the reference that a value returned by
+    /// `store_value` holds, once it is no longer stored.
+    fn release(&self, stored: Option<&IVec>) -> Result<()> {
+        match (&self.dedup, stored) {
+            (Some(dedup), Some(old)) => dedup.release(old),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes the concurrency control for a write to a
+    /// single key. Writes to trees that deduplicate values
+    /// also change the store of shared values, so they are
+    /// serialized like batches, and pegged so that they are
+    /// recovered atomically with those changes.
+    fn single_key_write<'a>(
+        &'a self,
+        guard: &Guard,
+    ) -> Result<(Protector<'a>, Option<RecoveryGuard<'a>>)> {
+        if self.dedup.is_some() {
+            let cc = concurrency_control::write();
+            let peg = self.context.pin_log(guard)?;
+            Ok((cc, Some(peg)))
+        } else {
+            Ok((concurrency_control::read(), None))
+        }
+    }
+
+    /// Retrieve a value from the `Tree` without copying it
+    /// out of the page cache. The returned `PinnedValue`
+    /// dereferences to the value's bytes, and keeps the
+    /// page version it was read from alive until it is
+    /// dropped. See the `PinnedValue` docs for the rules
+    /// that govern how long it should be held.
     ///
     /// # Examples
     ///
@@ -433,847 +951,3113 @@ impl Tree {
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// let config = sled::Config::new().temporary(true);
     /// let t = config.open()?;
-    /// t.insert(&[1], vec![1]);
-    /// assert_eq!(t.remove(&[1]), Ok(Some(sled::IVec::from(vec![1]))));
-    /// assert_eq!(t.remove(&[1]), Ok(None));
+    ///
+    /// t.insert(&[0], vec![1, 2, 3])?;
+    ///
+    /// let pinned = t.get_pinned(&[0])?.unwrap();
+    /// assert_eq!(&*pinned, &[1, 2, 3]);
+    ///
+    /// // writers are not blocked, and the pinned
+    /// // value keeps referring to the old bytes.
+    /// t.insert(&[0], vec![4])?;
+    /// assert_eq!(&*pinned, &[1, 2, 3]);
+    /// drop(pinned);
+    ///
+    /// assert!(t.get_pinned(&[1])?.is_none());
     /// # Ok(()) }
     /// ```
-    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
-        let mut guard = pin();
+    pub fn get_pinned<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<PinnedValue>> {
+        let _measure = Measure::new(&M.tree_get);
+
+        trace!("getting pinned key {:?}", key.as_ref());
+
+        let guard = pin();
         let _cc = concurrency_control::read();
-        loop {
-            trace!("removing key {:?}", key.as_ref());
 
-            if let Ok(res) = self.insert_inner(key.as_ref(), None, &mut guard)? {
-                return Ok(res);
-            }
+        let View { node_view, .. } = self.view_for_key(key.as_ref(), &guard)?;
+
+        if self.dedup.is_some() {
+            let stored = node_view.leaf_pair_for_key(key.as_ref());
+            let value = self.resolve(stored.map(|kv| kv.1.clone()))?;
+            return Ok(value.map(|v| PinnedValue::resolved(guard.inner, v)));
         }
+
+        let value_ptr: Option<*const [u8]> =
+            node_view.leaf_pair_for_key(key.as_ref()).map(|(_k, v)| {
+                let ptr: *const [u8] = &**v;
+                ptr
+            });
+
+        Ok(value_ptr.map(|ptr| PinnedValue::new(guard.inner, ptr)))
     }
 
-    /// Compare and swap. Capable of unique creation, conditional modification,
-    /// or deletion. If old is `None`, this will only set the value if it
-    /// doesn't exist yet. If new is `None`, will delete the value if old is
-    /// correct. If both old and new are `Some`, will modify the value if
-    /// old is correct.
-    ///
-    /// It returns `Ok(Ok(()))` if operation finishes successfully.
+    /// Retrieve the values for several keys at once. The
+    /// returned `Vec` has one entry per requested key, in
+    /// the same order as the keys were provided.
     ///
-    /// If it fails it returns:
-    ///     - `Ok(Err(CompareAndSwapError(current, proposed)))` if operation
-    ///       failed to setup a new value. `CompareAndSwapError` contains
-    ///       current and proposed values.
-    ///     - `Err(Error::Unsupported)` if the database is opened in read-only
-    ///       mode.
+    /// This sorts the keys internally and walks the `Tree`
+    /// once, so keys that fall in the same leaf share a
+    /// single traversal. This is much cheaper than calling
+    /// `Tree::get` for each key when the keys are clustered,
+    /// and no more expensive when they are scattered. Like
+    /// `Tree::iter`, this provides no point-in-time snapshot
+    /// guarantees across keys.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::IVec;
+    ///
     /// let config = sled::Config::new().temporary(true);
     /// let t = config.open()?;
     ///
-    /// // unique creation
-    /// assert_eq!(
-    ///     t.compare_and_swap(&[1], None as Option<&[u8]>, Some(&[10])),
-    ///     Ok(Ok(()))
-    /// );
-    ///
-    /// // conditional modification
-    /// assert_eq!(
-    ///     t.compare_and_swap(&[1], Some(&[10]), Some(&[20])),
-    ///     Ok(Ok(()))
-    /// );
-    ///
-    /// // failed conditional modification -- the current value is returned in
-    /// // the error variant
-    /// let operation = t.compare_and_swap(&[1], Some(&[30]), Some(&[40]));
-    /// assert!(operation.is_ok()); // the operation succeeded
-    /// let modification = operation.unwrap();
-    /// assert!(modification.is_err());
-    /// let actual_value = modification.unwrap_err();
-    /// assert_eq!(actual_value.current.map(|ivec| ivec.to_vec()), Some(vec![20]));
+    /// t.insert(&[1], vec![10])?;
+    /// t.insert(&[2], vec![20])?;
     ///
-    /// // conditional deletion
+    /// let values = t.multi_get(vec![&[2], &[0], &[1]])?;
     /// assert_eq!(
-    ///     t.compare_and_swap(&[1], Some(&[20]), None as Option<&[u8]>),
-    ///     Ok(Ok(()))
+    ///     values,
+    ///     vec![Some(IVec::from(&[20])), None, Some(IVec::from(&[10]))]
     /// );
-    /// assert_eq!(t.get(&[1]), Ok(None));
     /// # Ok(()) }
     /// ```
-    #[allow(clippy::needless_pass_by_value)]
-    pub fn compare_and_swap<K, OV, NV>(
-        &self,
-        key: K,
-        old: Option<OV>,
-        new: Option<NV>,
-    ) -> CompareAndSwapResult
+    pub fn multi_get<K, I>(&self, keys: I) -> Result<Vec<Option<IVec>>>
     where
         K: AsRef<[u8]>,
-        OV: AsRef<[u8]>,
-        IVec: From<NV>,
+        I: IntoIterator<Item = K>,
     {
-        trace!("cas'ing key {:?}", key.as_ref());
-        let _measure = Measure::new(&M.tree_cas);
+        let requested: Vec<K> = keys.into_iter().collect();
+
+        let mut order: Vec<usize> = (0..requested.len()).collect();
+        order.sort_unstable_by(|a, b| {
+            requested[*a].as_ref().cmp(requested[*b].as_ref())
+        });
+
+        let mut ret = vec![None; requested.len()];
 
         let guard = pin();
         let _cc = concurrency_control::read();
 
-        let new = new.map(IVec::from);
+        let mut view: Option<View<'_>> = None;
 
-        // we need to retry caps until old != cur, since just because
-        // cap fails it doesn't mean our value was changed.
-        loop {
-            let View { pid, node_view, .. } =
-                self.view_for_key(key.as_ref(), &guard)?;
+        for idx in order {
+            let _measure = Measure::new(&M.tree_get);
 
-            let (encoded_key, current_value) =
-                node_view.node_kv_pair(key.as_ref());
-            let matches = match (old.as_ref(), &current_value) {
-                (None, None) => true,
-                (Some(o), Some(ref c)) => o.as_ref() == &**c,
-                _ => false,
+            let key = requested[idx].as_ref();
+
+            // keys are visited in ascending order, so the
+            // current leaf covers this key as long as the
+            // key is below the leaf's upper bound.
+            let covered = match &view {
+                Some(v) => v.hi.is_empty() || key < v.hi.as_ref(),
+                None => false,
             };
 
-            if !matches {
-                return Ok(Err(CompareAndSwapError {
-                    current: current_value,
-                    proposed: new,
-                }));
+            if !covered {
+                view = Some(self.view_for_key(key, &guard)?);
             }
 
-            let mut subscriber_reservation = self.subscribers.reserve(&key);
+            let node_view = &view.as_ref().unwrap().node_view;
+            let stored = node_view.leaf_pair_for_key(key);
+            ret[idx] = self.resolve(stored.map(|kv| kv.1.clone()))?;
+        }
 
-            let frag = if let Some(ref new) = new {
-                Link::Set(encoded_key, new.clone())
-            } else {
-                Link::Del(encoded_key)
-            };
-            let link =
-                self.context.pagecache.link(pid, node_view.0, frag, &guard)?;
+        Ok(ret)
+    }
 
-            if link.is_ok() {
-                if let Some(res) = subscriber_reservation.take() {
-                    let event = if let Some(new) = new {
-                        subscriber::Event::Insert {
-                            key: key.as_ref().into(),
-                            value: new,
-                        }
-                    } else {
-                        subscriber::Event::Remove { key: key.as_ref().into() }
-                    };
+    #[doc(hidden)]
+    #[deprecated(since = "0.24.2", note = "replaced by `Tree::remove`")]
+    pub fn del<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        self.remove(key)
+    }
 
-                    res.complete(&event);
-                }
+    /// Delete a value, returning the old value if it existed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![1]);
+    /// assert_eq!(t.remove(&[1]), Ok(Some(sled::IVec::from(vec![1]))));
+    /// assert_eq!(t.remove(&[1]), Ok(None));
+    /// # Ok(()) }
+    /// ```
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
+        self.context.check_free_space()?;
+        let mut guard = pin();
+        let (cc, peg) = self.single_key_write(&guard)?;
+        let coalesce = peg.is_none() && self.context.coalesce_writes;
+        let res = loop {
+            trace!("removing key {:?}", key.as_ref());
 
-                return Ok(Ok(()));
+            if let Ok(res) =
+                self.write_inner(key.as_ref(), None, coalesce, &mut guard)?
+            {
+                peg.map_or(Ok(()), RecoveryGuard::seal_batch)?;
+                break res;
             }
-            M.tree_looped();
-        }
+        };
+        drop(cc);
+        self.context.evict_over_limit(Some((&self.tree_id, key.as_ref())));
+        Ok(res)
     }
 
-    /// Fetch the value, apply a function to it and return the result.
-    ///
-    /// # Note
-    ///
-    /// This may call the function multiple times if the value has been
-    /// changed from other threads in the meantime.
+    /// Delete a value only if it currently equals
+    /// `expected`, returning `true` if it did and was
+    /// removed, or `false` if the value was different or
+    /// absent. This is `compare_and_swap` to `None`, for
+    /// things like releasing a lock entry only while still
+    /// owning it.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, Error, IVec};
-    /// use std::convert::TryInto;
-    ///
-    /// let config = Config::new().temporary(true);
-    /// let tree = config.open()?;
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(b"lock", b"owner-1")?;
     ///
-    /// fn u64_to_ivec(number: u64) -> IVec {
-    ///     IVec::from(number.to_be_bytes().to_vec())
-    /// }
+    /// assert!(!t.remove_if(b"lock", b"owner-2")?);
+    /// assert!(t.remove_if(b"lock", b"owner-1")?);
+    /// assert_eq!(t.get(b"lock")?, None);
+    /// assert!(!t.remove_if(b"lock", b"owner-1")?);
+    /// # Ok(()) }
+    /// ```
+    pub fn remove_if<K, V>(&self, key: K, expected: V) -> Result<bool>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let res = self.compare_and_swap(key, Some(expected), None::<IVec>)?;
+        Ok(res.is_ok())
+    }
+
+    /// Insert a key to a new value like `Tree::insert`, and
+    /// block until the write is durable, so that it can be
+    /// acknowledged as surviving a crash without flushing
+    /// the whole database after every write.
+    ///
+    /// The log is written in order, so this also makes every
+    /// earlier write durable, but unlike `Tree::flush` it
+    /// doesn't wait for writes that start after it, nor sync
+    /// the storage directory. If the background flusher has
+    /// already written the buffer holding the write, this
+    /// returns without blocking. Otherwise it seals that
+    /// buffer and waits for it to be written and synced,
+    /// which costs about one fsync, typically well under a
+    /// millisecond on fast SSDs and several milliseconds on
+    /// other disks. Concurrent durable writes that land in
+    /// the same buffer share that fsync.
     ///
-    /// let zero = u64_to_ivec(0);
-    /// let one = u64_to_ivec(1);
-    /// let two = u64_to_ivec(2);
-    /// let three = u64_to_ivec(3);
+    /// # Examples
     ///
-    /// fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
-    ///     let number = match old {
-    ///         Some(bytes) => {
-    ///             let array: [u8; 8] = bytes.try_into().unwrap();
-    ///             let number = u64::from_be_bytes(array);
-    ///             number + 1
-    ///         }
-    ///         None => 0,
-    ///     };
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Durability, IVec};
     ///
-    ///     Some(number.to_be_bytes().to_vec())
-    /// }
+    /// let config = sled::Config::new().flush_every_ms(None).temporary(true);
+    /// let t = config.open()?;
     ///
-    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(zero)));
-    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(one)));
-    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(two)));
-    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(three)));
+    /// assert_eq!(t.insert_durable(b"k", b"v")?, None);
+    /// assert_eq!(
+    ///     t.get_with_durability(b"k")?,
+    ///     Some((IVec::from(b"v"), Durability::Durable))
+    /// );
     /// # Ok(()) }
     /// ```
-    pub fn update_and_fetch<K, V, F>(
+    pub fn insert_durable<K, V>(
         &self,
         key: K,
-        mut f: F,
+        value: V,
     ) -> Result<Option<IVec>>
     where
         K: AsRef<[u8]>,
-        F: FnMut(Option<&[u8]>) -> Option<V>,
         IVec: From<V>,
     {
-        let key_ref = key.as_ref();
-        let mut current = self.get(key_ref)?;
+        let last = self.insert(key, value)?;
+        self.make_written_durable()?;
+        Ok(last)
+    }
 
-        loop {
-            let tmp = current.as_ref().map(AsRef::as_ref);
-            let next = f(tmp).map(IVec::from);
-            match self.compare_and_swap::<_, _, IVec>(
-                key_ref,
-                tmp,
-                next.clone(),
-            )? {
-                Ok(()) => return Ok(next),
-                Err(CompareAndSwapError { current: cur, .. }) => {
-                    current = cur;
-                }
-            }
+    /// Delete a value like `Tree::remove`, and block until
+    /// the removal is durable. See `Tree::insert_durable` for
+    /// what this waits for and what it costs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().flush_every_ms(None).temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(b"k", b"v")?;
+    /// assert_eq!(t.remove_durable(b"k")?, Some(sled::IVec::from(b"v")));
+    /// assert_eq!(t.get_with_durability(b"k")?, None);
+    /// # Ok(()) }
+    /// ```
+    pub fn remove_durable<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<IVec>> {
+        let last = self.remove(key)?;
+        self.make_written_durable()?;
+        Ok(last)
+    }
+
+    /// Blocks until every write that has completed so far,
+    /// including a batch sealed by a `RecoveryGuard`, is
+    /// durable.
+    fn make_written_durable(&self) -> Result<()> {
+        self.context.pagecache.log_coalesced()?;
+        let log = &self.context.pagecache.log;
+        let lsn = log.max_reserved_lsn();
+        if lsn > log.stable_offset() {
+            let _ = log.make_stable(lsn)?;
         }
+        Ok(())
     }
 
-    /// Fetch the value, apply a function to it and return the previous value.
+    /// Compare and swap. Capable of unique creation, conditional modification,
+    /// or deletion. If old is `None`, this will only set the value if it
+    /// doesn't exist yet. If new is `None`, will delete the value if old is
+    /// correct. If both old and new are `Some`, will modify the value if
+    /// old is correct.
     ///
-    /// # Note
+    /// It returns `Ok(Ok(()))` if operation finishes successfully.
     ///
-    /// This may call the function multiple times if the value has been
-    /// changed from other threads in the meantime.
+    /// If it fails it returns:
+    ///     - `Ok(Err(CompareAndSwapError(current, proposed)))` if operation
+    ///       failed to setup a new value. `CompareAndSwapError` contains
+    ///       current and proposed values.
+    ///     - `Err(Error::Unsupported)` if the database is opened in read-only
+    ///       mode.
+    ///
+    /// The new value can be passed as a `&IVec`, which only clones the
+    /// `IVec` and does not copy the bytes of values that are too large to
+    /// be stored inline. In a retry loop, converting the new value into an
+    /// `IVec` once, before the loop, avoids copying it on every attempt,
+    /// and the current value returned by a failed attempt can be passed
+    /// as the old value of the next one.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, Error, IVec};
-    /// use std::convert::TryInto;
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
     ///
-    /// let config = Config::new().temporary(true);
-    /// let tree = config.open()?;
+    /// // unique creation
+    /// assert_eq!(
+    ///     t.compare_and_swap(&[1], None as Option<&[u8]>, Some(&[10])),
+    ///     Ok(Ok(()))
+    /// );
     ///
-    /// fn u64_to_ivec(number: u64) -> IVec {
-    ///     IVec::from(number.to_be_bytes().to_vec())
-    /// }
+    /// // conditional modification
+    /// assert_eq!(
+    ///     t.compare_and_swap(&[1], Some(&[10]), Some(&[20])),
+    ///     Ok(Ok(()))
+    /// );
     ///
-    /// let zero = u64_to_ivec(0);
-    /// let one = u64_to_ivec(1);
-    /// let two = u64_to_ivec(2);
+    /// // failed conditional modification -- the current value is returned in
+    /// // the error variant
+    /// let operation = t.compare_and_swap(&[1], Some(&[30]), Some(&[40]));
+    /// assert!(operation.is_ok()); // the operation succeeded
+    /// let modification = operation.unwrap();
+    /// assert!(modification.is_err());
+    /// let actual_value = modification.unwrap_err();
+    /// assert_eq!(actual_value.current.map(|ivec| ivec.to_vec()), Some(vec![20]));
     ///
-    /// fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
-    ///     let number = match old {
-    ///         Some(bytes) => {
-    ///             let array: [u8; 8] = bytes.try_into().unwrap();
-    ///             let number = u64::from_be_bytes(array);
-    ///             number + 1
-    ///         }
-    ///         None => 0,
-    ///     };
+    /// // conditional deletion
+    /// assert_eq!(
+    ///     t.compare_and_swap(&[1], Some(&[20]), None as Option<&[u8]>),
+    ///     Ok(Ok(()))
+    /// );
+    /// assert_eq!(t.get(&[1]), Ok(None));
     ///
-    ///     Some(number.to_be_bytes().to_vec())
+    /// // retrying with a value that is only converted once
+    /// let proposed = sled::IVec::from(vec![0; 4096]);
+    /// let mut current = t.get(&[2])?;
+    /// while let Err(conflict) =
+    ///     t.compare_and_swap(&[2], current.as_ref(), Some(&proposed))?
+    /// {
+    ///     current = conflict.current;
     /// }
-    ///
-    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(None));
-    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(Some(zero)));
-    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(Some(one)));
-    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(Some(two)));
+    /// assert_eq!(t.get(&[2])?, Some(proposed));
     /// # Ok(()) }
     /// ```
-    pub fn fetch_and_update<K, V, F>(
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn compare_and_swap<K, OV, NV>(
         &self,
         key: K,
-        mut f: F,
-    ) -> Result<Option<IVec>>
+        old: Option<OV>,
+        new: Option<NV>,
+    ) -> CompareAndSwapResult
     where
         K: AsRef<[u8]>,
-        F: FnMut(Option<&[u8]>) -> Option<V>,
-        IVec: From<V>,
+        OV: AsRef<[u8]>,
+        IVec: From<NV>,
     {
-        let key_ref = key.as_ref();
-        let mut current = self.get(key_ref)?;
+        trace!("cas'ing key {:?}", key.as_ref());
 
-        loop {
-            let tmp = current.as_ref().map(AsRef::as_ref);
-            let next = f(tmp);
-            match self.compare_and_swap(key_ref, tmp, next)? {
-                Ok(()) => return Ok(current),
-                Err(CompareAndSwapError { current: cur, .. }) => {
-                    current = cur;
-                }
-            }
-        }
+        let proposed = new.map(IVec::from);
+        let res = self.cas_inner(
+            key.as_ref(),
+            proposed,
+            |current, _version| match (old.as_ref(), current) {
+                (None, None) => true,
+                (Some(o), Some(c)) => o.as_ref() == &**c,
+                _ => false,
+            },
+        )?;
+
+        Ok(res.map_err(|conflict| CompareAndSwapError {
+            current: conflict.current,
+            proposed: conflict.proposed,
+        }))
     }
 
-    /// Subscribe to `Event`s that happen to keys that have
-    /// the specified prefix. Events for particular keys are
-    /// guaranteed to be witnessed in the same order by all
-    /// threads, but threads may witness different interleavings
-    /// of `Event`s across different keys. If subscribers don't
-    /// keep up with new writes, they will cause new writes
-    /// to block. There is a buffer of 1024 items per
-    /// `Subscriber`. This can be used to build reactive
-    /// and replicated systems.
-    ///
-    /// `Subscriber` implements both `Iterator<Item = Event>`
-    /// and `Future<Output=Option<Event>>`
+    /// Retrieve a value from the `Tree` along with its
+    /// `Version`, which can be passed to
+    /// `Tree::compare_and_swap_version` to only write the
+    /// key if nobody else has written it in the meantime.
     ///
     /// # Examples
     ///
-    /// Synchronous, blocking subscriber:
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, Event};
-    /// let config = Config::new().temporary(true);
-    ///
-    /// let tree = config.open()?;
+    /// use sled::IVec;
     ///
-    /// // watch all events by subscribing to the empty prefix
-    /// let mut subscriber = tree.watch_prefix(vec![]);
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
     ///
-    /// let tree_2 = tree.clone();
-    /// let thread = std::thread::spawn(move || {
-    ///     tree.insert(vec![0], vec![1])
-    /// });
+    /// t.insert(b"k", b"v1")?;
+    /// let (value, v1) = t.get_versioned(b"k")?.unwrap();
+    /// assert_eq!(value, IVec::from(b"v1"));
     ///
-    /// // `Subscription` implements `Iterator<Item=Event>`
-    /// for event in subscriber.take(1) {
-    ///     match event {
-    ///         Event::Insert{ key, value } => assert_eq!(key.as_ref(), &[0]),
-    ///         Event::Remove {key } => {}
-    ///     }
-    /// }
+    /// // writing the same bytes again still makes a new version
+    /// t.insert(b"k", b"v2")?;
+    /// t.insert(b"k", b"v1")?;
+    /// let (_, v2) = t.get_versioned(b"k")?.unwrap();
+    /// assert!(v2 > v1);
     ///
-    /// # thread.join().unwrap();
+    /// assert_eq!(t.get_versioned(b"missing")?, None);
     /// # Ok(()) }
     /// ```
-    /// Aynchronous, non-blocking subscriber:
-    ///
-    /// `Subscription` implements `Future<Output=Option<Event>>`.
-    ///
-    /// `while let Some(event) = (&mut subscriber).await { /* use it */ }`
-    pub fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Subscriber {
-        self.subscribers.register(prefix.as_ref())
-    }
+    pub fn get_versioned<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<(IVec, Version)>> {
+        let _measure = Measure::new(&M.tree_get);
 
-    /// Synchronously flushes all dirty IO buffers and calls
-    /// fsync. If this succeeds, it is guaranteed that all
-    /// previous writes will be recovered if the system
-    /// crashes. Returns the number of bytes flushed during
-    /// this call.
-    ///
-    /// Flushing can take quite a lot of time, and you should
-    /// measure the performance impact of using it on
-    /// realistic sustained workloads running on realistic
-    /// hardware.
-    pub fn flush(&self) -> Result<usize> {
-        self.context.pagecache.flush()
-    }
+        trace!("getting versioned key {:?}", key.as_ref());
 
-    /// Asynchronously flushes all dirty IO buffers
-    /// and calls fsync. If this succeeds, it is
-    /// guaranteed that all previous writes will
-    /// be recovered if the system crashes. Returns
-    /// the number of bytes flushed during this call.
-    ///
-    /// Flushing can take quite a lot of time, and you
-    /// should measure the performance impact of
-    /// using it on realistic sustained workloads
-    /// running on realistic hardware.
-    pub async fn flush_async(&self) -> Result<usize> {
-        let pagecache = self.context.pagecache.clone();
-        if let Some(result) = threadpool::spawn(move || pagecache.flush()).await
-        {
-            result
-        } else {
-            Err(Error::ReportableBug(
-                "threadpool failed to complete \
-                action before shutdown"
-                    .to_string(),
-            ))
-        }
+        let guard = pin();
+        let _cc = concurrency_control::read();
+
+        let View { node_view, .. } = self.view_for_key(key.as_ref(), &guard)?;
+
+        let stored =
+            node_view.leaf_pair_for_key(key.as_ref()).map(|(_k, v)| v.clone());
+        let value = self.resolve(stored)?;
+
+        Ok(value.map(|v| {
+            let version = node_view.leaf_version_for_key(key.as_ref());
+            (v, Version(version.unwrap()))
+        }))
     }
 
-    /// Returns `true` if the `Tree` contains a value for
-    /// the specified key.
+    /// Retrieve a value from the `Tree` along with whether
+    /// it has been made durable yet, so that a write can be
+    /// acknowledged only once it would survive a crash.
+    ///
+    /// Durability is tracked for each leaf of the `Tree`
+    /// rather than for each key, so a value is reported as
+    /// `Durability::Pending` until every earlier write to
+    /// the same leaf has been flushed as well, even if the
+    /// value itself already has been. A `Durable` value is
+    /// always durable. This costs one more atomic load
+    /// than `Tree::get`.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// let config = sled::Config::new().temporary(true);
+    /// use sled::{Durability, IVec};
+    ///
+    /// let config = sled::Config::new().flush_every_ms(None).temporary(true);
     /// let t = config.open()?;
     ///
-    /// t.insert(&[0], vec![0])?;
-    /// assert!(t.contains_key(&[0])?);
-    /// assert!(!t.contains_key(&[1])?);
+    /// t.insert(b"k", b"v")?;
+    /// assert_eq!(
+    ///     t.get_with_durability(b"k")?,
+    ///     Some((IVec::from(b"v"), Durability::Pending))
+    /// );
+    ///
+    /// t.flush()?;
+    /// assert_eq!(
+    ///     t.get_with_durability(b"k")?,
+    ///     Some((IVec::from(b"v"), Durability::Durable))
+    /// );
     /// # Ok(()) }
     /// ```
-    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool> {
-        self.get(key).map(|v| v.is_some())
+    pub fn get_with_durability<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+    ) -> Result<Option<(IVec, Durability)>> {
+        let _measure = Measure::new(&M.tree_get);
+
+        trace!("getting key {:?} with durability", key.as_ref());
+
+        let guard = pin();
+        let _cc = concurrency_control::read();
+
+        let View { node_view, .. } = self.view_for_key(key.as_ref(), &guard)?;
+
+        let stored =
+            node_view.leaf_pair_for_key(key.as_ref()).map(|(_k, v)| v.clone());
+        let value = self.resolve(stored)?;
+
+        let stable = self.context.pagecache.log.stable_offset();
+        let durability = if !node_view.0.unlogged
+            && node_view.0.last_lsn() <= stable
+        {
+            Durability::Durable
+        } else {
+            Durability::Pending
+        };
+
+        Ok(value.map(|v| (v, durability)))
     }
 
-    /// Retrieve the key and value before the provided key,
-    /// if one exists.
+    /// Retrieve a value from the `Tree`, first waiting until
+    /// it has been made durable, so that the returned value
+    /// would survive a crash. This is a consistency barrier
+    /// for a single key: writes that have not been flushed
+    /// yet are not skipped, since sled does not keep the
+    /// values they replaced in memory, but are flushed
+    /// before returning. The same applies if the key is
+    /// absent because of a removal that was not flushed.
+    ///
+    /// When nothing needs to be flushed, this costs the same
+    /// as `Tree::get`. Otherwise it blocks until the log is
+    /// durable up to the last write to the key's leaf,
+    /// which may cost as much as a call to `Tree::flush`,
+    /// including an fsync. Use `Tree::get_with_durability`
+    /// to check without blocking.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
-    /// let config = Config::new().temporary(true);
-    /// let tree = config.open()?;
+    /// use sled::{Durability, IVec};
     ///
-    /// for i in 0..10 {
-    ///     tree.insert(&[i], vec![i])
-    ///         .expect("should write successfully");
-    /// }
+    /// let config = sled::Config::new().flush_every_ms(None).temporary(true);
+    /// let t = config.open()?;
     ///
-    /// assert_eq!(tree.get_lt(&[]), Ok(None));
-    /// assert_eq!(tree.get_lt(&[0]), Ok(None));
-    /// assert_eq!(
-    ///     tree.get_lt(&[1]),
-    ///     Ok(Some((IVec::from(&[0]), IVec::from(&[0]))))
-    /// );
-    /// assert_eq!(
-    ///     tree.get_lt(&[9]),
-    ///     Ok(Some((IVec::from(&[8]), IVec::from(&[8]))))
-    /// );
-    /// assert_eq!(
-    ///     tree.get_lt(&[10]),
-    ///     Ok(Some((IVec::from(&[9]), IVec::from(&[9]))))
-    /// );
+    /// t.insert(b"k", b"v")?;
+    /// assert_eq!(t.get_durable(b"k")?, Some(IVec::from(b"v")));
     /// assert_eq!(
-    ///     tree.get_lt(&[255]),
-    ///     Ok(Some((IVec::from(&[9]), IVec::from(&[9]))))
+    ///     t.get_with_durability(b"k")?,
+    ///     Some((IVec::from(b"v"), Durability::Durable))
     /// );
     /// # Ok(()) }
     /// ```
-    pub fn get_lt<K>(&self, key: K) -> Result<Option<(IVec, IVec)>>
-    where
-        K: AsRef<[u8]>,
-    {
+    pub fn get_durable<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<IVec>> {
         let _measure = Measure::new(&M.tree_get);
-        self.range(..key).next_back().transpose()
+
+        trace!("getting durable key {:?}", key.as_ref());
+
+        self.context.pagecache.log_coalesced()?;
+        let guard = pin();
+        let _cc = concurrency_control::read();
+
+        let View { node_view, .. } = self.view_for_key(key.as_ref(), &guard)?;
+
+        let stored =
+            node_view.leaf_pair_for_key(key.as_ref()).map(|(_k, v)| v.clone());
+        let value = self.resolve(stored)?;
+
+        let last_lsn = node_view.0.last_lsn();
+        if last_lsn > self.context.pagecache.log.stable_offset() {
+            let _ = self.context.pagecache.log.make_stable(last_lsn)?;
+        }
+
+        Ok(value)
     }
 
-    /// Retrieve the next key and value from the `Tree` after the
-    /// provided key.
-    ///
-    /// # Note
-    /// The order follows the Ord implementation for `Vec<u8>`:
-    ///
-    /// `[] < [0] < [255] < [255, 0] < [255, 255] ...`
-    ///
-    /// To retain the ordering of numerical types use big endian reprensentation
+    /// Blocks until the last write to `key` is durable, which
+    /// is more targeted than `Tree::flush` when a single key,
+    /// such as a marker handed to another system, must
+    /// survive a crash before proceeding. This applies to
+    /// removals too, so an absent key stays absent.
+    ///
+    /// The log is flushed up to the last write to the key's
+    /// leaf, so this also makes every write that was logged
+    /// before that durable, including writes to other keys
+    /// and trees. It may therefore flush more than the one
+    /// key, but never less. If the key's write is already
+    /// durable, this costs the same as `Tree::get` and
+    /// flushes nothing. See `Tree::get_with_durability` to
+    /// check without blocking.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
-    /// let config = Config::new().temporary(true);
-    /// let tree = config.open()?;
-    ///
-    /// for i in 0..10 {
-    ///     tree.insert(&[i], vec![i])?;
-    /// }
+    /// use sled::{Durability, IVec};
     ///
-    /// assert_eq!(
-    ///     tree.get_gt(&[]),
-    ///     Ok(Some((IVec::from(&[0]), IVec::from(&[0]))))
-    /// );
-    /// assert_eq!(
-    ///     tree.get_gt(&[0]),
-    ///     Ok(Some((IVec::from(&[1]), IVec::from(&[1]))))
-    /// );
-    /// assert_eq!(
-    ///     tree.get_gt(&[1]),
-    ///     Ok(Some((IVec::from(&[2]), IVec::from(&[2]))))
-    /// );
-    /// assert_eq!(
-    ///     tree.get_gt(&[8]),
-    ///     Ok(Some((IVec::from(&[9]), IVec::from(&[9]))))
-    /// );
-    /// assert_eq!(tree.get_gt(&[9]), Ok(None));
+    /// let config = sled::Config::new().flush_every_ms(None).temporary(true);
+    /// let t = config.open()?;
     ///
-    /// tree.insert(500u16.to_be_bytes(), vec![10]);
+    /// t.insert(b"handoff", b"ready")?;
+    /// t.flush_key(b"handoff")?;
     /// assert_eq!(
-    ///     tree.get_gt(&499u16.to_be_bytes()),
-    ///     Ok(Some((IVec::from(&500u16.to_be_bytes()), IVec::from(&[10]))))
+    ///     t.get_with_durability(b"handoff")?,
+    ///     Some((IVec::from(b"ready"), Durability::Durable))
     /// );
     /// # Ok(()) }
     /// ```
-    pub fn get_gt<K>(&self, key: K) -> Result<Option<(IVec, IVec)>>
-    where
-        K: AsRef<[u8]>,
-    {
-        let _measure = Measure::new(&M.tree_get);
-        self.range((ops::Bound::Excluded(key), ops::Bound::Unbounded))
-            .next()
-            .transpose()
+    pub fn flush_key<K: AsRef<[u8]>>(&self, key: K) -> Result<()> {
+        self.context.pagecache.log_coalesced()?;
+        let guard = pin();
+        let View { node_view, .. } = self.view_for_key(key.as_ref(), &guard)?;
+
+        let last_lsn = node_view.0.last_lsn();
+        if last_lsn > self.context.pagecache.log.stable_offset() {
+            let _ = self.context.pagecache.log.make_stable(last_lsn)?;
+        }
+
+        Ok(())
     }
 
-    /// Merge state directly into a given key's value using the
-    /// configured merge operator. This allows state to be written
-    /// into a value directly, without any read-modify-write steps.
-    /// Merge operators can be used to implement arbitrary data
-    /// structures.
-    ///
-    /// Calling `merge` will return an `Unsupported` error if it
-    /// is called without first setting a merge operator function.
+    /// Compare and swap based on versions rather than values.
+    /// The `new` value is only written if the key's current
+    /// `Version`, as returned by `Tree::get_versioned`, is
+    /// `expected`, where `None` means that the key must be
+    /// absent. Setting `new` to `None` removes the key.
     ///
-    /// Merge operators are shared by all instances of a particular
-    /// `Tree`. Different merge operators may be set on different
-    /// `Tree`s.
+    /// This avoids comparing the full current value, which
+    /// is cheaper than `Tree::compare_and_swap` for large
+    /// values. It also detects writes that put back the
+    /// same bytes that were there before, which a value
+    /// comparison cannot tell apart.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
-    ///
-    /// fn concatenate_merge(
-    ///   _key: &[u8],               // the key being merged
-    ///   old_value: Option<&[u8]>,  // the previous value, if one existed
-    ///   merged_bytes: &[u8]        // the new bytes being merged in
-    /// ) -> Option<Vec<u8>> {       // set the new value, return None to delete
-    ///   let mut ret = old_value
-    ///     .map(|ov| ov.to_vec())
-    ///     .unwrap_or_else(|| vec![]);
-    ///
-    ///   ret.extend_from_slice(merged_bytes);
-    ///
-    ///   Some(ret)
-    /// }
-    ///
-    /// let config = Config::new()
-    ///   .temporary(true);
-    ///
-    /// let tree = config.open()?;
-    /// tree.set_merge_operator(concatenate_merge);
-    ///
-    /// let k = b"k1";
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
     ///
-    /// tree.insert(k, vec![0]);
-    /// tree.merge(k, vec![1]);
-    /// tree.merge(k, vec![2]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![0, 1, 2]))));
+    /// // only insert if absent
+    /// assert!(t.compare_and_swap_version(b"k", None, Some(b"v1"))?.is_ok());
+    /// let (_, version) = t.get_versioned(b"k")?.unwrap();
     ///
-    /// // Replace previously merged data. The merge function will not be called.
-    /// tree.insert(k, vec![3]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![3]))));
+    /// // succeeds because nobody wrote k in the meantime
+    /// assert!(
+    ///     t.compare_and_swap_version(b"k", Some(version), Some(b"v2"))?.is_ok()
+    /// );
     ///
-    /// // Merges on non-present values will cause the merge function to be called
-    /// // with `old_value == None`. If the merge function returns something (which it
-    /// // does, in this case) a new value will be inserted.
-    /// tree.remove(k);
-    /// tree.merge(k, vec![4]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![4]))));
+    /// // fails because the version changed
+    /// let mismatch = t
+    ///     .compare_and_swap_version(b"k", Some(version), Some(b"v3"))?
+    ///     .unwrap_err();
+    /// assert_ne!(mismatch.current, Some(version));
+    /// assert_eq!(t.get(b"k")?, Some(sled::IVec::from(b"v2")));
     /// # Ok(()) }
     /// ```
-    pub fn merge<K, V>(&self, key: K, value: V) -> Result<Option<IVec>>
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn compare_and_swap_version<K, V>(
+        &self,
+        key: K,
+        expected: Option<Version>,
+        new: Option<V>,
+    ) -> Result<std::result::Result<(), VersionMismatch>>
     where
         K: AsRef<[u8]>,
-        V: AsRef<[u8]>,
+        IVec: From<V>,
     {
-        let _cc = concurrency_control::read();
-        loop {
-            if let Ok(merge) = self.merge_inner(key.as_ref(), value.as_ref())? {
-                return Ok(merge);
-            }
-        }
+        trace!("cas'ing version of key {:?}", key.as_ref());
+
+        let proposed = new.map(IVec::from);
+        let expected_version = expected.map(|version| version.0);
+        let res =
+            self.cas_inner(key.as_ref(), proposed, |_current, version| {
+                version == expected_version
+            })?;
+
+        Ok(res.map_err(|conflict| VersionMismatch {
+            current: conflict.version.map(Version),
+        }))
     }
 
-    pub(crate) fn merge_inner(
+    /// Writes `new` to `key` if `matches` returns `true` for
+    /// the key's current value and version, and otherwise
+    /// returns them as a `CasConflict`. Shared by the
+    /// compare and swap variants.
+    fn cas_inner<F>(
         &self,
         key: &[u8],
-        value: &[u8],
-    ) -> Result<Abortable<Option<IVec>>> {
-        trace!("merging key {:?}", key);
-        let _measure = Measure::new(&M.tree_merge);
+        new: Option<IVec>,
+        matches: F,
+    ) -> Result<std::result::Result<(), CasConflict>>
+    where
+        F: Fn(Option<&IVec>, Option<u64>) -> bool,
+    {
+        let _measure = Measure::new(&M.tree_cas);
 
-        let merge_operator_opt = self.merge_operator.read();
+        self.context.check_free_space()?;
+        if let Some(proposed) = &new {
+            self.context.check_item_size(key, Some(proposed))?;
+        }
 
-        if merge_operator_opt.is_none() {
-            return Err(Error::Unsupported(
-                "must set a merge operator on this Tree \
-                 before calling merge by calling \
-                 Tree::set_merge_operator"
-                    .to_owned(),
-            ));
-        }
-
-        let merge_operator = merge_operator_opt.as_ref().unwrap();
+        let guard = pin();
+        let (cc, peg) = self.single_key_write(&guard)?;
 
+        // we need to retry caps until old != cur, since just because
+        // cap fails it doesn't mean our value was changed.
         loop {
-            let guard = pin();
-            let View { pid, node_view, .. } =
-                self.view_for_key(key.as_ref(), &guard)?;
+            let View { pid, node_view, .. } = self.view_for_key(key, &guard)?;
 
-            let (encoded_key, current_value) =
-                node_view.node_kv_pair(key.as_ref());
-            let tmp = current_value.as_ref().map(AsRef::as_ref);
-            let new = merge_operator(key, tmp, value).map(IVec::from);
+            let (encoded_key, current_stored) = node_view.node_kv_pair(key);
+            let current_value = self.resolve(current_stored.clone())?;
+            let current_version = node_view.leaf_version_for_key(key);
+
+            if !matches(current_value.as_ref(), current_version) {
+                return Ok(Err(CasConflict {
+                    current: current_value,
+                    version: current_version,
+                    proposed: new,
+                }));
+            }
 
-            let mut subscriber_reservation = self.subscribers.reserve(&key);
+            let mut subscriber_reservation =
+                self.subscribers.reserve(key, new.is_some());
 
-            let frag = if let Some(ref new) = new {
-                Link::Set(encoded_key, new.clone())
+            let stored = self.store_value(new.clone())?;
+            let frag = if let Some(stored_value) = stored.clone() {
+                let version = self.next_version(&node_view, key);
+                Link::Set(encoded_key, stored_value, version)
             } else {
                 Link::Del(encoded_key)
             };
             let link =
                 self.context.pagecache.link(pid, node_view.0, frag, &guard)?;
 
-            if link.is_ok() {
+            if let Ok(new_view) = &link {
+                self.count_logged(new_view);
+                let value_len = new.as_ref().map(|v| v.len());
                 if let Some(res) = subscriber_reservation.take() {
-                    let event = if let Some(new) = &new {
+                    let event = if let Some(new) = new {
                         subscriber::Event::Insert {
                             key: key.as_ref().into(),
-                            value: new.clone(),
+                            value: new,
+                            previous: current_value,
                         }
                     } else {
-                        subscriber::Event::Remove { key: key.as_ref().into() }
+                        subscriber::Event::Remove {
+                            key: key.as_ref().into(),
+                            previous: current_value,
+                        }
                     };
 
                     res.complete(&event);
                 }
 
-                return Ok(Ok(new));
+                self.release(current_stored.as_ref())?;
+                self.bump_write_generation()?;
+                self.record_write(key, value_len);
+
+                peg.map_or(Ok(()), RecoveryGuard::seal_batch)?;
+                drop(cc);
+                self.context.evict_over_limit(Some((&self.tree_id, key)));
+                return Ok(Ok(()));
             }
+            self.release(stored.as_ref())?;
             M.tree_looped();
         }
     }
 
-    /// Sets a merge operator for use with the `merge` function.
+    /// Atomically apply several compare and swap operations.
+    /// Every `CasOp`'s `expected` value is compared against
+    /// the current value of its key, and only if all of them
+    /// match are the `new` values written. Otherwise nothing
+    /// is written, and the returned `MultiCasError` contains
+    /// the first key, in the order of `ops`, whose value did
+    /// not match, along with its current value.
+    ///
+    /// All conditions are checked before any writes, so if
+    /// the same key appears more than once, each condition
+    /// is compared against the value before this call, and
+    /// the last write to that key wins. Like
+    /// `Tree::apply_batch`, the writes are applied atomically
+    /// and are recovered atomically after a crash, and
+    /// other writers are blocked while this runs. This is
+    /// cheaper than an equivalent transaction because there
+    /// is no transactional read overlay to build or
+    /// validate.
     ///
-    /// Merge state directly into a given key's value using the
-    /// configured merge operator. This allows state to be written
-    /// into a value directly, without any read-modify-write steps.
-    /// Merge operators can be used to implement arbitrary data
-    /// structures.
+    /// # Examples
     ///
-    /// # Panics
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{CasOp, IVec};
     ///
-    /// Calling `merge` will panic if no merge operator has been
-    /// configured.
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(b"a", b"x")?;
+    /// t.insert(b"b", b"y")?;
+    ///
+    /// // succeeds, because both conditions hold
+    /// t.compare_and_swap_multi(vec![
+    ///     CasOp::new(b"a", Some(b"x"), Some(b"x2")),
+    ///     CasOp::new(b"b", Some(b"y"), Some(b"y2")),
+    /// ])?
+    /// .unwrap();
+    ///
+    /// // fails, because `b` is no longer `y`
+    /// let err = t
+    ///     .compare_and_swap_multi(vec![
+    ///         CasOp::new(b"a", Some(b"x2"), Some(b"x3")),
+    ///         CasOp::new(b"b", Some(b"y"), None::<&[u8]>),
+    ///     ])?
+    ///     .unwrap_err();
+    /// assert_eq!(err.key, IVec::from(b"b"));
+    /// assert_eq!(err.current, Some(IVec::from(b"y2")));
+    ///
+    /// // nothing was written by the failed call
+    /// assert_eq!(t.get(b"a")?, Some(IVec::from(b"x2")));
+    /// # Ok(()) }
+    /// ```
+    pub fn compare_and_swap_multi(
+        &self,
+        ops: Vec<CasOp>,
+    ) -> Result<std::result::Result<(), MultiCasError>> {
+        let res = self.cas_ops(ops, false)?;
+        Ok(res.map_err(|mut failures| failures.swap_remove(0)))
+    }
+
+    /// Like `Tree::compare_and_swap_multi`, but checks every
+    /// condition even after one has failed, and returns all
+    /// of those that failed, in the order of `ops`, along
+    /// with the current values of their keys. This helps an
+    /// optimistic commit of many keys refresh everything it
+    /// read that is stale at once, rather than finding out
+    /// one key per attempt. As before, nothing is written
+    /// unless every condition holds, and all conditions are
+    /// checked against the same state of the tree.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
+    /// use sled::{CasOp, IVec};
     ///
-    /// fn concatenate_merge(
-    ///   _key: &[u8],               // the key being merged
-    ///   old_value: Option<&[u8]>,  // the previous value, if one existed
-    ///   merged_bytes: &[u8]        // the new bytes being merged in
-    /// ) -> Option<Vec<u8>> {       // set the new value, return None to delete
-    ///   let mut ret = old_value
-    ///     .map(|ov| ov.to_vec())
-    ///     .unwrap_or_else(|| vec![]);
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(b"a", b"x")?;
+    /// t.insert(b"b", b"y")?;
+    ///
+    /// let failures = t
+    ///     .conditional_batch(vec![
+    ///         CasOp::new(b"a", Some(b"old"), Some(b"x2")),
+    ///         CasOp::new(b"b", Some(b"y"), Some(b"y2")),
+    ///         CasOp::new(b"c", Some(b"z"), None::<&[u8]>),
+    ///     ])?
+    ///     .unwrap_err();
+    ///
+    /// let keys: Vec<&[u8]> = failures.iter().map(|f| &*f.key).collect();
+    /// assert_eq!(keys, vec![b"a", b"c"]);
+    /// assert_eq!(failures[0].current, Some(IVec::from(b"x")));
+    /// assert_eq!(failures[1].current, None);
+    ///
+    /// // nothing was written, not even the op that held
+    /// assert_eq!(t.get(b"b")?, Some(IVec::from(b"y")));
+    /// # Ok(()) }
+    /// ```
+    pub fn conditional_batch(
+        &self,
+        ops: Vec<CasOp>,
+    ) -> Result<std::result::Result<(), Vec<MultiCasError>>> {
+        self.cas_ops(ops, true)
+    }
+
+    /// Applies `ops` if all of their conditions hold,
+    /// otherwise returns the first failed condition, or with
+    /// `all_failures`, every one of them.
+    fn cas_ops(
+        &self,
+        ops: Vec<CasOp>,
+        all_failures: bool,
+    ) -> Result<std::result::Result<(), Vec<MultiCasError>>> {
+        let _measure = Measure::new(&M.tree_cas);
+
+        self.context.check_free_space()?;
+        for op in &ops {
+            if let Some(ref new) = op.new {
+                self.context.check_item_size(&op.key, Some(new))?;
+            }
+        }
+
+        let cc = concurrency_control::write();
+        let mut guard = pin();
+
+        let mut failures = vec![];
+        for op in &ops {
+            let stored = loop {
+                if let Ok(stored) = self.get_inner(&op.key, &mut guard)? {
+                    break stored;
+                }
+            };
+            let current = self.resolve(stored)?;
+
+            if current != op.expected {
+                failures.push(MultiCasError { key: op.key.clone(), current });
+                if !all_failures {
+                    break;
+                }
+            }
+        }
+        if !failures.is_empty() {
+            return Ok(Err(failures));
+        }
+
+        let peg = self.context.pin_log(&guard)?;
+        for op in ops {
+            trace!("cas'ing key {:?} in a multi-key cas", op.key);
+            let CasOp { key, new, .. } = op;
+            loop {
+                if self.insert_inner(&key, new.clone(), &mut guard)?.is_ok() {
+                    break;
+                }
+            }
+        }
+
+        // when the peg drops, it ensures all updates
+        // written to the log since its creation are
+        // recovered atomically
+        peg.seal_batch()?;
+        drop(cc);
+        self.context.evict_over_limit(None);
+
+        Ok(Ok(()))
+    }
+
+    /// Fetch the value, apply a function to it and return the result.
     ///
-    ///   ret.extend_from_slice(merged_bytes);
+    /// # Note
     ///
-    ///   Some(ret)
-    /// }
+    /// This may call the function multiple times if the value has been
+    /// changed from other threads in the meantime.
     ///
-    /// let config = Config::new()
-    ///   .temporary(true);
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Error, IVec};
+    /// use std::convert::TryInto;
     ///
+    /// let config = Config::new().temporary(true);
     /// let tree = config.open()?;
-    /// tree.set_merge_operator(concatenate_merge);
     ///
-    /// let k = b"k1";
+    /// fn u64_to_ivec(number: u64) -> IVec {
+    ///     IVec::from(number.to_be_bytes().to_vec())
+    /// }
     ///
-    /// tree.insert(k, vec![0]);
-    /// tree.merge(k, vec![1]);
-    /// tree.merge(k, vec![2]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![0, 1, 2]))));
+    /// let zero = u64_to_ivec(0);
+    /// let one = u64_to_ivec(1);
+    /// let two = u64_to_ivec(2);
+    /// let three = u64_to_ivec(3);
     ///
-    /// // Replace previously merged data. The merge function will not be called.
-    /// tree.insert(k, vec![3]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![3]))));
+    /// fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    ///     let number = match old {
+    ///         Some(bytes) => {
+    ///             let array: [u8; 8] = bytes.try_into().unwrap();
+    ///             let number = u64::from_be_bytes(array);
+    ///             number + 1
+    ///         }
+    ///         None => 0,
+    ///     };
     ///
-    /// // Merges on non-present values will cause the merge function to be called
-    /// // with `old_value == None`. If the merge function returns something (which it
-    /// // does, in this case) a new value will be inserted.
-    /// tree.remove(k);
-    /// tree.merge(k, vec![4]);
-    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![4]))));
+    ///     Some(number.to_be_bytes().to_vec())
+    /// }
+    ///
+    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(zero)));
+    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(one)));
+    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(two)));
+    /// assert_eq!(tree.update_and_fetch("counter", increment), Ok(Some(three)));
     /// # Ok(()) }
     /// ```
-    pub fn set_merge_operator(
+    pub fn update_and_fetch<K, V, F>(
         &self,
-        merge_operator: impl MergeOperator + 'static,
-    ) {
-        let mut mo_write = self.merge_operator.write();
-        *mo_write = Some(Box::new(merge_operator));
+        key: K,
+        mut f: F,
+    ) -> Result<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        IVec: From<V>,
+    {
+        let key_ref = key.as_ref();
+        let mut current = self.get(key_ref)?;
+
+        loop {
+            let tmp = current.as_ref().map(AsRef::as_ref);
+            let next = f(tmp).map(IVec::from);
+            match self.compare_and_swap::<_, _, IVec>(
+                key_ref,
+                tmp,
+                next.clone(),
+            )? {
+                Ok(()) => return Ok(next),
+                Err(CompareAndSwapError { current: cur, .. }) => {
+                    current = cur;
+                }
+            }
+        }
     }
 
-    /// Create a double-ended iterator over the tuples of keys and
-    /// values in this tree.
+    /// Fetch the value, apply a function to it and return the previous value.
     ///
-    /// # Examples
+    /// # Note
     ///
-    /// ```
-    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
-    /// let config = Config::new().temporary(true);
-    /// let t = config.open()?;
-    /// t.insert(&[1], vec![10]);
-    /// t.insert(&[2], vec![20]);
-    /// t.insert(&[3], vec![30]);
-    /// let mut iter = t.iter();
-    /// assert_eq!(
-    ///     iter.next().unwrap(),
-    ///     Ok((IVec::from(&[1]), IVec::from(&[10])))
-    /// );
-    /// assert_eq!(
-    ///     iter.next().unwrap(),
-    ///     Ok((IVec::from(&[2]), IVec::from(&[20])))
-    /// );
-    /// assert_eq!(
-    ///     iter.next().unwrap(),
-    ///     Ok((IVec::from(&[3]), IVec::from(&[30])))
-    /// );
-    /// assert_eq!(iter.next(), None);
-    /// # Ok(()) }
-    /// ```
-    pub fn iter(&self) -> Iter {
-        self.range::<Vec<u8>, _>(..)
-    }
-
-    /// Create a double-ended iterator over tuples of keys and values,
-    /// where the keys fall within the specified range.
+    /// This may call the function multiple times if the value has been
+    /// changed from other threads in the meantime.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
+    /// use sled::{Config, Error, IVec};
+    /// use std::convert::TryInto;
+    ///
     /// let config = Config::new().temporary(true);
-    /// let t = config.open()?;
+    /// let tree = config.open()?;
     ///
-    /// t.insert(&[0], vec![0])?;
-    /// t.insert(&[1], vec![10])?;
-    /// t.insert(&[2], vec![20])?;
-    /// t.insert(&[3], vec![30])?;
-    /// t.insert(&[4], vec![40])?;
-    /// t.insert(&[5], vec![50])?;
+    /// fn u64_to_ivec(number: u64) -> IVec {
+    ///     IVec::from(number.to_be_bytes().to_vec())
+    /// }
     ///
-    /// let start: &[u8] = &[2];
-    /// let end: &[u8] = &[4];
-    /// let mut r = t.range(start..end);
-    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[2]), IVec::from(&[20]))));
-    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[3]), IVec::from(&[30]))));
-    /// assert_eq!(r.next(), None);
+    /// let zero = u64_to_ivec(0);
+    /// let one = u64_to_ivec(1);
+    /// let two = u64_to_ivec(2);
     ///
-    /// let mut r = t.range(start..end).rev();
-    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[3]), IVec::from(&[30]))));
-    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[2]), IVec::from(&[20]))));
-    /// assert_eq!(r.next(), None);
+    /// fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    ///     let number = match old {
+    ///         Some(bytes) => {
+    ///             let array: [u8; 8] = bytes.try_into().unwrap();
+    ///             let number = u64::from_be_bytes(array);
+    ///             number + 1
+    ///         }
+    ///         None => 0,
+    ///     };
+    ///
+    ///     Some(number.to_be_bytes().to_vec())
+    /// }
+    ///
+    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(None));
+    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(Some(zero)));
+    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(Some(one)));
+    /// assert_eq!(tree.fetch_and_update("counter", increment), Ok(Some(two)));
+    /// # Ok(()) }
+    /// ```
+    pub fn fetch_and_update<K, V, F>(
+        &self,
+        key: K,
+        mut f: F,
+    ) -> Result<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        F: FnMut(Option<&[u8]>) -> Option<V>,
+        IVec: From<V>,
+    {
+        let key_ref = key.as_ref();
+        let mut current = self.get(key_ref)?;
+
+        loop {
+            let tmp = current.as_ref().map(AsRef::as_ref);
+            let next = f(tmp);
+            match self.compare_and_swap(key_ref, tmp, next)? {
+                Ok(()) => return Ok(current),
+                Err(CompareAndSwapError { current: cur, .. }) => {
+                    current = cur;
+                }
+            }
+        }
+    }
+
+    /// Atomically adds `by` to the counter stored at `key`,
+    /// and returns its new value. A counter is stored as an
+    /// 8-byte little-endian `i64`, and an absent key counts
+    /// as 0, so the first increment creates the counter.
+    /// Decrement with a negative `by`.
+    ///
+    /// Returns `Error::Unsupported` and leaves the counter
+    /// unchanged if the addition would overflow, or if the
+    /// value at `key` is not 8 bytes long. Like
+    /// `Tree::update_and_fetch`, this retries if the value is
+    /// changed concurrently, so increments are never lost.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    ///
+    /// assert_eq!(db.increment(b"hits", 1)?, 1);
+    /// assert_eq!(db.increment(b"hits", 10)?, 11);
+    /// assert_eq!(db.increment(b"hits", -2)?, 9);
+    /// assert_eq!(db.counter_value(b"hits")?, 9);
+    /// assert_eq!(db.get(b"hits")?, Some((&9_i64.to_le_bytes()).into()));
+    ///
+    /// assert!(db.increment(b"hits", i64::max_value()).is_err());
+    /// assert_eq!(db.counter_value(b"hits")?, 9);
+    /// # Ok(()) }
+    /// ```
+    pub fn increment<K: AsRef<[u8]>>(&self, key: K, by: i64) -> Result<i64> {
+        let key_ref = key.as_ref();
+        let mut current = self.get(key_ref)?;
+
+        loop {
+            let old = decode_counter(key_ref, current.as_ref())?;
+            let new = old.checked_add(by).ok_or_else(|| {
+                Error::Unsupported(format!(
+                    "adding {} to the counter {:?}, which is {}, overflows",
+                    by,
+                    IVec::from(key_ref),
+                    old
+                ))
+            })?;
+            let new_bytes = IVec::from(&new.to_le_bytes());
+            match self.compare_and_swap(key_ref, current, Some(new_bytes))? {
+                Ok(()) => return Ok(new),
+                Err(CompareAndSwapError { current: cur, .. }) => {
+                    current = cur;
+                }
+            }
+        }
+    }
+
+    /// Returns the value of the counter stored at `key` by
+    /// `Tree::increment`, or 0 if the key is absent. Returns
+    /// `Error::Unsupported` if the value at `key` is not 8
+    /// bytes long.
+    pub fn counter_value<K: AsRef<[u8]>>(&self, key: K) -> Result<i64> {
+        let key_ref = key.as_ref();
+        decode_counter(key_ref, self.get(key_ref)?.as_ref())
+    }
+
+    /// Returns the value of `key`, or `default` if the key is
+    /// absent, without inserting `default`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    ///
+    /// let mode = db.get_or(b"mode", b"fast")?;
+    /// assert_eq!(mode, sled::IVec::from(b"fast"));
+    /// assert!(!db.contains_key(b"mode")?);
+    ///
+    /// db.insert(b"mode", b"safe")?;
+    /// let mode = db.get_or(b"mode", b"fast")?;
+    /// assert_eq!(mode, sled::IVec::from(b"safe"));
+    /// # Ok(()) }
+    /// ```
+    pub fn get_or<K, V>(&self, key: K, default: V) -> Result<IVec>
+    where
+        K: AsRef<[u8]>,
+        V: Into<IVec>,
+    {
+        match self.get(key)? {
+            Some(value) => Ok(value),
+            None => Ok(default.into()),
+        }
+    }
+
+    /// Returns the `u64` stored at `key` by `Tree::insert_u64`,
+    /// or `None` if the key is absent. Returns
+    /// `Error::Unsupported` if the value is not 8 bytes long.
+    ///
+    /// Numbers are stored big-endian, so that their values
+    /// compare like the numbers do, unlike the counters of
+    /// `Tree::increment`, which are little-endian `i64`s.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    ///
+    /// assert_eq!(db.get_u64(b"retries")?, None);
+    /// db.insert_u64(b"retries", 3)?;
+    /// assert_eq!(db.get_u64(b"retries")?, Some(3));
+    /// let stored = db.get(b"retries")?.unwrap();
+    /// assert_eq!(stored, 3_u64.to_be_bytes());
+    ///
+    /// db.insert(b"name", b"sled")?;
+    /// assert!(db.get_u64(b"name").is_err());
+    /// # Ok(()) }
+    /// ```
+    pub fn get_u64<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<u64>> {
+        let key_ref = key.as_ref();
+        if let Some(value) = self.get(key_ref)? {
+            let bytes = eight_bytes(key_ref, &value, "a u64")?;
+            Ok(Some(u64::from_be_bytes(bytes)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Stores `value` at `key` as 8 big-endian bytes, to be
+    /// read by `Tree::get_u64`, and returns the previous
+    /// value like `Tree::insert` does. The previous value is
+    /// returned as it was stored, whether or not it was a
+    /// `u64`.
+    pub fn insert_u64<K: AsRef<[u8]>>(
+        &self,
+        key: K,
+        value: u64,
+    ) -> Result<Option<IVec>> {
+        self.insert(key, &value.to_be_bytes())
+    }
+
+    /// Returns the `Entry` of a key, for an atomic
+    /// read-modify-write that inserts the key if it is absent
+    /// and optionally modifies it if it is present, like
+    /// `HashMap::entry`.
+    ///
+    /// # Note
+    ///
+    /// This may call the closures passed to the `Entry`
+    /// multiple times if the value has been changed from
+    /// other threads in the meantime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// let config = Config::new().temporary(true);
+    /// let tree = config.open()?;
+    ///
+    /// fn increment(old: &[u8]) -> Vec<u8> {
+    ///     vec![old[0] + 1]
+    /// }
+    ///
+    /// let n = tree.entry("counter").and_modify(increment).or_insert(&[1])?;
+    /// assert_eq!(n, IVec::from(&[1]));
+    ///
+    /// let n = tree.entry("counter").and_modify(increment).or_insert(&[1])?;
+    /// assert_eq!(n, IVec::from(&[2]));
+    ///
+    /// // present keys are left alone without `and_modify`
+    /// let n = tree.entry("counter").or_insert_with(|| vec![100])?;
+    /// assert_eq!(n, IVec::from(&[2]));
+    /// # Ok(()) }
+    /// ```
+    pub fn entry<K: AsRef<[u8]>>(&self, key: K) -> Entry<'_> {
+        Entry::new(self, IVec::from(key.as_ref()))
+    }
+
+    /// Subscribe to `Event`s that happen to keys that have
+    /// the specified prefix. Events for particular keys are
+    /// guaranteed to be witnessed in the same order by all
+    /// threads, but threads may witness different interleavings
+    /// of `Event`s across different keys. If subscribers don't
+    /// keep up with new writes, they will cause new writes
+    /// to block. There is a buffer of 1024 items per
+    /// `Subscriber`. This can be used to build reactive
+    /// and replicated systems.
+    ///
+    /// `Subscriber` implements both `Iterator<Item = Event>`
+    /// and `Future<Output=Option<Event>>`
+    ///
+    /// # Examples
+    ///
+    /// Synchronous, blocking subscriber:
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Event};
+    /// let config = Config::new().temporary(true);
+    ///
+    /// let tree = config.open()?;
+    ///
+    /// // watch all events by subscribing to the empty prefix
+    /// let mut subscriber = tree.watch_prefix(vec![]);
+    ///
+    /// let tree_2 = tree.clone();
+    /// let thread = std::thread::spawn(move || {
+    ///     tree.insert(vec![0], vec![1])
+    /// });
+    ///
+    /// // `Subscription` implements `Iterator<Item=Event>`
+    /// for event in subscriber.take(1) {
+    ///     match event {
+    ///         Event::Insert{ key, value, .. } => assert_eq!(key.as_ref(), &[0]),
+    ///         Event::Remove { key, .. } => {}
+    ///     }
+    /// }
+    ///
+    /// # thread.join().unwrap();
+    /// # Ok(()) }
+    /// ```
+    /// Aynchronous, non-blocking subscriber:
+    ///
+    /// `Subscription` implements `Future<Output=Option<Event>>`.
+    ///
+    /// `while let Some(event) = (&mut subscriber).await { /* use it */ }`
+    pub fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Subscriber {
+        self.subscribers.register(prefix.as_ref(), EventFilter::All)
+    }
+
+    /// Like `watch_prefix`, but the `Subscriber` only receives
+    /// the kinds of `Event` selected by `filter`. Other events
+    /// are skipped when they are written, so they are neither
+    /// cloned nor queued for this subscriber, and writes never
+    /// block on it because of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Event, EventFilter};
+    ///
+    /// let tree = Config::new().temporary(true).open()?;
+    ///
+    /// // only learn about removed keys, to invalidate a cache
+    /// let mut removals =
+    ///     tree.watch_prefix_filtered(vec![], EventFilter::Removes);
+    ///
+    /// tree.insert(b"a", b"1")?;
+    /// tree.remove(b"a")?;
+    ///
+    /// match removals.next() {
+    ///     Some(Event::Remove { key, .. }) => assert_eq!(key, b"a"),
+    ///     other => panic!("unexpected event {:?}", other),
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn watch_prefix_filtered<P: AsRef<[u8]>>(
+        &self,
+        prefix: P,
+        filter: EventFilter,
+    ) -> Subscriber {
+        self.subscribers.register(prefix.as_ref(), filter)
+    }
+
+    /// Subscribe to the `Event`s of a single key, without
+    /// those of the keys that it is a prefix of. Writes find
+    /// these subscribers by looking up the key, rather than
+    /// by comparing it to every watched prefix, so many keys
+    /// can be watched at once without slowing down writes
+    /// to other keys.
+    ///
+    /// Every write that starts after this returns is
+    /// delivered, so subscribing before writing a request
+    /// guarantees that a response written to the key by
+    /// another thread is seen. Otherwise this behaves like
+    /// `watch_prefix`, and the `Subscriber` can be awaited.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, Event, IVec};
+    ///
+    /// let tree = Config::new().temporary(true).open()?;
+    ///
+    /// // subscribe first, so the result can't be missed
+    /// let mut result = tree.watch_key(b"job/1/result");
+    /// tree.insert(b"job/1", b"request")?;
+    ///
+    /// let worker = tree.clone();
+    /// let thread = std::thread::spawn(move || {
+    ///     worker.insert(b"job/1/result/log", b"ignored")?;
+    ///     worker.insert(b"job/1/result", b"done")
+    /// });
+    ///
+    /// match result.next() {
+    ///     Some(Event::Insert { value, .. }) => {
+    ///         assert_eq!(value, IVec::from(b"done"))
+    ///     }
+    ///     other => panic!("unexpected event {:?}", other),
+    /// }
+    /// # thread.join().unwrap()?;
+    /// # Ok(()) }
+    /// ```
+    pub fn watch_key<K: AsRef<[u8]>>(&self, key: K) -> Subscriber {
+        self.subscribers.register_key(key.as_ref(), EventFilter::All)
+    }
+
+    /// Returns an iterator over the keys from `from` onwards
+    /// that, once it has returned the last key, blocks until
+    /// a key is inserted beyond the last key returned, rather
+    /// than ending, which is useful for tailing a log with
+    /// increasing keys. `TailIter::next_async` waits without
+    /// blocking the thread.
+    ///
+    /// Keys are returned in strictly increasing order. A key
+    /// that is inserted at or before the last key returned is
+    /// skipped, so keys that are inserted out of order, like
+    /// timestamps written by several threads, can be missed
+    /// by a tail that has already passed them. Removals are
+    /// not reported.
+    ///
+    /// Like any `Subscriber`, the tail is told about every
+    /// insertion into the tree, and writes to the tree block
+    /// once it has fallen more than 1024 insertions behind
+    /// while it is not waiting for keys, so a thread must not
+    /// write to the tree while it holds a tail that it does
+    /// not consume.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let tree = sled::Config::new().temporary(true).open()?;
+    /// tree.insert(b"log/1", b"first")?;
+    ///
+    /// let mut tail = tree.tail(b"log/");
+    /// let (key, _) = tail.next().unwrap()?;
+    /// assert_eq!(key, b"log/1");
+    ///
+    /// let writer = tree.clone();
+    /// let thread = std::thread::spawn(move || {
+    ///     writer.insert(b"log/2", b"second")
+    /// });
+    ///
+    /// // blocks until the next entry is written
+    /// let (key, value) = tail.next().unwrap()?;
+    /// assert_eq!(key, b"log/2");
+    /// assert_eq!(value, b"second");
+    /// # thread.join().unwrap()?;
+    /// # Ok(()) }
+    /// ```
+    pub fn tail<K: AsRef<[u8]>>(&self, from: K) -> TailIter {
+        TailIter::new(self, from.as_ref())
+    }
+
+    /// Synchronously flushes all dirty IO buffers and calls
+    /// fsync. If this succeeds, it is guaranteed that all
+    /// previous writes will be recovered if the system
+    /// crashes. Returns the number of bytes flushed during
+    /// this call.
+    ///
+    /// This also fsyncs the storage directory, so that
+    /// the directory entries of any files created since
+    /// the last flush, such as blobs, are durable too.
+    ///
+    /// Flushing can take quite a lot of time, and you should
+    /// measure the performance impact of using it on
+    /// realistic sustained workloads running on realistic
+    /// hardware.
+    pub fn flush(&self) -> Result<usize> {
+        let _latency = self.context.latency_timer(|l| &l.flush);
+        let flushed = self.context.pagecache.flush()?;
+        self.context.fsync_dir()?;
+        Ok(flushed)
+    }
+
+    /// Asynchronously flushes all dirty IO buffers
+    /// and calls fsync. If this succeeds, it is
+    /// guaranteed that all previous writes will
+    /// be recovered if the system crashes. Returns
+    /// the number of bytes flushed during this call.
+    /// Like `flush`, this also fsyncs the storage
+    /// directory.
+    ///
+    /// Flushing can take quite a lot of time, and you
+    /// should measure the performance impact of
+    /// using it on realistic sustained workloads
+    /// running on realistic hardware.
+    pub async fn flush_async(&self) -> Result<usize> {
+        let _latency = self.context.latency_timer(|l| &l.flush);
+        let context = self.context.clone();
+        let flush = move || {
+            let flushed = context.pagecache.flush()?;
+            context.fsync_dir()?;
+            Ok(flushed)
+        };
+        if let Some(result) = self.context.spawn(flush).await {
+            result
+        } else {
+            Err(Error::ReportableBug(
+                "threadpool failed to complete \
+                action before shutdown"
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Returns `true` if the `Tree` contains a value for
+    /// the specified key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(&[0], vec![0])?;
+    /// assert!(t.contains_key(&[0])?);
+    /// assert!(!t.contains_key(&[1])?);
+    /// # Ok(()) }
+    /// ```
+    pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> Result<bool> {
+        self.get(key).map(|v| v.is_some())
+    }
+
+    /// Retrieve the key and value before the provided key,
+    /// if one exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let tree = config.open()?;
+    ///
+    /// for i in 0..10 {
+    ///     tree.insert(&[i], vec![i])
+    ///         .expect("should write successfully");
+    /// }
+    ///
+    /// assert_eq!(tree.get_lt(&[]), Ok(None));
+    /// assert_eq!(tree.get_lt(&[0]), Ok(None));
+    /// assert_eq!(
+    ///     tree.get_lt(&[1]),
+    ///     Ok(Some((IVec::from(&[0]), IVec::from(&[0]))))
+    /// );
+    /// assert_eq!(
+    ///     tree.get_lt(&[9]),
+    ///     Ok(Some((IVec::from(&[8]), IVec::from(&[8]))))
+    /// );
+    /// assert_eq!(
+    ///     tree.get_lt(&[10]),
+    ///     Ok(Some((IVec::from(&[9]), IVec::from(&[9]))))
+    /// );
+    /// assert_eq!(
+    ///     tree.get_lt(&[255]),
+    ///     Ok(Some((IVec::from(&[9]), IVec::from(&[9]))))
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn get_lt<K>(&self, key: K) -> Result<Option<(IVec, IVec)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let _measure = Measure::new(&M.tree_get);
+        self.range(..key).next_back().transpose()
+    }
+
+    /// Retrieve the next key and value from the `Tree` after the
+    /// provided key.
+    ///
+    /// # Note
+    /// The order follows the Ord implementation for `Vec<u8>`:
+    ///
+    /// `[] < [0] < [255] < [255, 0] < [255, 255] ...`
+    ///
+    /// To retain the ordering of numerical types use big endian reprensentation
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let tree = config.open()?;
+    ///
+    /// for i in 0..10 {
+    ///     tree.insert(&[i], vec![i])?;
+    /// }
+    ///
+    /// assert_eq!(
+    ///     tree.get_gt(&[]),
+    ///     Ok(Some((IVec::from(&[0]), IVec::from(&[0]))))
+    /// );
+    /// assert_eq!(
+    ///     tree.get_gt(&[0]),
+    ///     Ok(Some((IVec::from(&[1]), IVec::from(&[1]))))
+    /// );
+    /// assert_eq!(
+    ///     tree.get_gt(&[1]),
+    ///     Ok(Some((IVec::from(&[2]), IVec::from(&[2]))))
+    /// );
+    /// assert_eq!(
+    ///     tree.get_gt(&[8]),
+    ///     Ok(Some((IVec::from(&[9]), IVec::from(&[9]))))
+    /// );
+    /// assert_eq!(tree.get_gt(&[9]), Ok(None));
+    ///
+    /// tree.insert(500u16.to_be_bytes(), vec![10]);
+    /// assert_eq!(
+    ///     tree.get_gt(&499u16.to_be_bytes()),
+    ///     Ok(Some((IVec::from(&500u16.to_be_bytes()), IVec::from(&[10]))))
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn get_gt<K>(&self, key: K) -> Result<Option<(IVec, IVec)>>
+    where
+        K: AsRef<[u8]>,
+    {
+        let _measure = Measure::new(&M.tree_get);
+        self.range((ops::Bound::Excluded(key), ops::Bound::Unbounded))
+            .next()
+            .transpose()
+    }
+
+    /// Merge state directly into a given key's value using the
+    /// configured merge operator. This allows state to be written
+    /// into a value directly, without any read-modify-write steps.
+    /// Merge operators can be used to implement arbitrary data
+    /// structures.
+    ///
+    /// Calling `merge` will return an `Unsupported` error if it
+    /// is called without first setting a merge operator function.
+    ///
+    /// Merge operators are shared by all instances of a particular
+    /// `Tree`. Different merge operators may be set on different
+    /// `Tree`s.
+    ///
+    /// Returns the value of the key after the merge, or `None`
+    /// if the merge operator removed it, so that the result of
+    /// a merge does not need to be read again with `get`. Note
+    /// that this differs from `insert` and `remove`, which
+    /// return the previous value. Use `fetch_and_update` with
+    /// the same function if the previous value is needed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// fn concatenate_merge(
+    ///   _key: &[u8],               // the key being merged
+    ///   old_value: Option<&[u8]>,  // the previous value, if one existed
+    ///   merged_bytes: &[u8]        // the new bytes being merged in
+    /// ) -> Option<Vec<u8>> {       // set the new value, return None to delete
+    ///   let mut ret = old_value
+    ///     .map(|ov| ov.to_vec())
+    ///     .unwrap_or_else(|| vec![]);
+    ///
+    ///   ret.extend_from_slice(merged_bytes);
+    ///
+    ///   Some(ret)
+    /// }
+    ///
+    /// let config = Config::new()
+    ///   .temporary(true);
+    ///
+    /// let tree = config.open()?;
+    /// tree.set_merge_operator(concatenate_merge);
+    ///
+    /// let k = b"k1";
+    ///
+    /// tree.insert(k, vec![0]);
+    /// tree.merge(k, vec![1]);
+    /// // the merged value is returned
+    /// let merged = tree.merge(k, vec![2]);
+    /// assert_eq!(merged, Ok(Some(IVec::from(vec![0, 1, 2]))));
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![0, 1, 2]))));
+    ///
+    /// // Replace previously merged data. The merge function will not be called.
+    /// tree.insert(k, vec![3]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![3]))));
+    ///
+    /// // Merges on non-present values will cause the merge function to be called
+    /// // with `old_value == None`. If the merge function returns something (which it
+    /// // does, in this case) a new value will be inserted.
+    /// tree.remove(k);
+    /// tree.merge(k, vec![4]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![4]))));
+    /// # Ok(()) }
+    /// ```
+    pub fn merge<K, V>(&self, key: K, value: V) -> Result<Option<IVec>>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.context.check_free_space()?;
+        self.context.check_item_size(key.as_ref(), None)?;
+        let guard = pin();
+        let (cc, peg) = self.single_key_write(&guard)?;
+        let merge = loop {
+            if let Ok(merge) = self.merge_inner(key.as_ref(), value.as_ref())? {
+                peg.map_or(Ok(()), RecoveryGuard::seal_batch)?;
+                break merge;
+            }
+        };
+        drop(cc);
+        self.context.evict_over_limit(Some((&self.tree_id, key.as_ref())));
+        Ok(merge)
+    }
+
+    pub(crate) fn merge_inner(
+        &self,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Abortable<Option<IVec>>> {
+        trace!("merging key {:?}", key);
+        let _measure = Measure::new(&M.tree_merge);
+
+        let merge_operator_opt = self.merge_operator.read();
+
+        if merge_operator_opt.is_none() {
+            return Err(Error::Unsupported(
+                "must set a merge operator on this Tree \
+                 before calling merge by calling \
+                 Tree::set_merge_operator"
+                    .to_owned(),
+            ));
+        }
+
+        let merge_operator = merge_operator_opt.as_ref().unwrap();
+
+        loop {
+            let guard = pin();
+            let View { pid, node_view, .. } =
+                self.view_for_key(key.as_ref(), &guard)?;
+
+            let (encoded_key, current_stored) =
+                node_view.node_kv_pair(key.as_ref());
+            let current_value = self.resolve(current_stored.clone())?;
+            let tmp = current_value.as_ref().map(AsRef::as_ref);
+            let new = merge_operator(key, tmp, value).map(IVec::from);
+            if let Some(merged) = &new {
+                self.context.check_item_size(key, Some(merged))?;
+            }
+
+            let mut subscriber_reservation =
+                self.subscribers.reserve(key, new.is_some());
+
+            let stored = self.store_value(new.clone())?;
+            let frag = if let Some(stored_value) = stored.clone() {
+                let version = self.next_version(&node_view, key.as_ref());
+                Link::Set(encoded_key, stored_value, version)
+            } else {
+                Link::Del(encoded_key)
+            };
+            let link =
+                self.context.pagecache.link(pid, node_view.0, frag, &guard)?;
+
+            if let Ok(new_view) = &link {
+                self.count_logged(new_view);
+                if let Some(res) = subscriber_reservation.take() {
+                    let event = if let Some(new) = &new {
+                        subscriber::Event::Insert {
+                            key: key.as_ref().into(),
+                            value: new.clone(),
+                            previous: current_value,
+                        }
+                    } else {
+                        subscriber::Event::Remove {
+                            key: key.as_ref().into(),
+                            previous: current_value,
+                        }
+                    };
+
+                    res.complete(&event);
+                }
+
+                self.release(current_stored.as_ref())?;
+                self.bump_write_generation()?;
+                self.record_write(key.as_ref(), new.as_ref().map(|v| v.len()));
+
+                return Ok(Ok(new));
+            }
+            self.release(stored.as_ref())?;
+            M.tree_looped();
+        }
+    }
+
+    /// Sets a merge operator for use with the `merge` function.
+    ///
+    /// Merge state directly into a given key's value using the
+    /// configured merge operator. This allows state to be written
+    /// into a value directly, without any read-modify-write steps.
+    /// Merge operators can be used to implement arbitrary data
+    /// structures.
+    ///
+    /// # Panics
+    ///
+    /// Calling `merge` will panic if no merge operator has been
+    /// configured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    ///
+    /// fn concatenate_merge(
+    ///   _key: &[u8],               // the key being merged
+    ///   old_value: Option<&[u8]>,  // the previous value, if one existed
+    ///   merged_bytes: &[u8]        // the new bytes being merged in
+    /// ) -> Option<Vec<u8>> {       // set the new value, return None to delete
+    ///   let mut ret = old_value
+    ///     .map(|ov| ov.to_vec())
+    ///     .unwrap_or_else(|| vec![]);
+    ///
+    ///   ret.extend_from_slice(merged_bytes);
+    ///
+    ///   Some(ret)
+    /// }
+    ///
+    /// let config = Config::new()
+    ///   .temporary(true);
+    ///
+    /// let tree = config.open()?;
+    /// tree.set_merge_operator(concatenate_merge);
+    ///
+    /// let k = b"k1";
+    ///
+    /// tree.insert(k, vec![0]);
+    /// tree.merge(k, vec![1]);
+    /// tree.merge(k, vec![2]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![0, 1, 2]))));
+    ///
+    /// // Replace previously merged data. The merge function will not be called.
+    /// tree.insert(k, vec![3]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![3]))));
+    ///
+    /// // Merges on non-present values will cause the merge function to be called
+    /// // with `old_value == None`. If the merge function returns something (which it
+    /// // does, in this case) a new value will be inserted.
+    /// tree.remove(k);
+    /// tree.merge(k, vec![4]);
+    /// assert_eq!(tree.get(k), Ok(Some(IVec::from(vec![4]))));
+    /// # Ok(()) }
+    /// ```
+    pub fn set_merge_operator(
+        &self,
+        merge_operator: impl MergeOperator + 'static,
+    ) {
+        let mut mo_write = self.merge_operator.write();
+        *mo_write = Some(Box::new(merge_operator));
+    }
+
+    /// Create a double-ended iterator over the tuples of keys and
+    /// values in this tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![10]);
+    /// t.insert(&[2], vec![20]);
+    /// t.insert(&[3], vec![30]);
+    /// let mut iter = t.iter();
+    /// assert_eq!(
+    ///     iter.next().unwrap(),
+    ///     Ok((IVec::from(&[1]), IVec::from(&[10])))
+    /// );
+    /// assert_eq!(
+    ///     iter.next().unwrap(),
+    ///     Ok((IVec::from(&[2]), IVec::from(&[20])))
+    /// );
+    /// assert_eq!(
+    ///     iter.next().unwrap(),
+    ///     Ok((IVec::from(&[3]), IVec::from(&[30])))
+    /// );
+    /// assert_eq!(iter.next(), None);
+    /// # Ok(()) }
+    /// ```
+    pub fn iter(&self) -> Iter {
+        self.range::<Vec<u8>, _>(..)
+    }
+
+    /// Create a double-ended iterator over the keys of this
+    /// tree, for when the values are not needed.
+    ///
+    /// Values are stored inline in the leaves of the tree
+    /// rather than on pages of their own, so leaves that
+    /// are not cached are still read from disk as a whole,
+    /// and this does the same IO as `Tree::iter`. Values
+    /// share the memory of the leaf they were read from,
+    /// so dropping them costs no more than skipping them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::IVec;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![10])?;
+    /// t.insert(&[2], vec![20])?;
+    ///
+    /// let keys: Vec<IVec> = t.iter_keys().collect::<Result<_, _>>()?;
+    /// assert_eq!(keys, vec![IVec::from(&[1]), IVec::from(&[2])]);
+    /// # Ok(()) }
+    /// ```
+    pub fn iter_keys(&self) -> impl DoubleEndedIterator<Item = Result<IVec>> {
+        self.iter().keys()
+    }
+
+    /// Create a double-ended iterator over the values of
+    /// this tree, in the order of their keys. Like
+    /// `Tree::iter_keys`, this does the same IO as
+    /// `Tree::iter`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::IVec;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![10])?;
+    /// t.insert(&[2], vec![20])?;
+    ///
+    /// let values: Vec<IVec> =
+    ///     t.iter_values().rev().collect::<Result<_, _>>()?;
+    /// assert_eq!(values, vec![IVec::from(&[20]), IVec::from(&[10])]);
+    /// # Ok(()) }
+    /// ```
+    pub fn iter_values(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = Result<IVec>> {
+        self.iter().values()
+    }
+
+    /// Create a double-ended iterator over all items in this
+    /// tree that avoids some of the synchronization a normal
+    /// iterator performs, at the cost of possibly observing
+    /// only part of a concurrently applied `Batch` or
+    /// transaction. See `Iter::relaxed` for the exact set of
+    /// anomalies that may be witnessed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(&[1], vec![10])?;
+    /// t.insert(&[2], vec![20])?;
+    /// assert_eq!(t.iter_relaxed().count(), 2);
+    /// # Ok(()) }
+    /// ```
+    pub fn iter_relaxed(&self) -> Iter {
+        self.iter().relaxed()
+    }
+
+    /// Create a double-ended iterator over tuples of keys and values,
+    /// where the keys fall within the specified range.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(&[0], vec![0])?;
+    /// t.insert(&[1], vec![10])?;
+    /// t.insert(&[2], vec![20])?;
+    /// t.insert(&[3], vec![30])?;
+    /// t.insert(&[4], vec![40])?;
+    /// t.insert(&[5], vec![50])?;
+    ///
+    /// let start: &[u8] = &[2];
+    /// let end: &[u8] = &[4];
+    /// let mut r = t.range(start..end);
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[2]), IVec::from(&[20]))));
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[3]), IVec::from(&[30]))));
+    /// assert_eq!(r.next(), None);
+    ///
+    /// let mut r = t.range(start..end).rev();
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[3]), IVec::from(&[30]))));
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[2]), IVec::from(&[20]))));
+    /// assert_eq!(r.next(), None);
+    /// # Ok(()) }
+    /// ```
+    pub fn range<K, R>(&self, range: R) -> Iter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let lo = match range.start_bound() {
+            ops::Bound::Included(start) => {
+                ops::Bound::Included(IVec::from(start.as_ref()))
+            }
+            ops::Bound::Excluded(start) => {
+                ops::Bound::Excluded(IVec::from(start.as_ref()))
+            }
+            ops::Bound::Unbounded => ops::Bound::Included(IVec::from(&[])),
+        };
+
+        let hi = match range.end_bound() {
+            ops::Bound::Included(end) => {
+                ops::Bound::Included(IVec::from(end.as_ref()))
+            }
+            ops::Bound::Excluded(end) => {
+                ops::Bound::Excluded(IVec::from(end.as_ref()))
+            }
+            ops::Bound::Unbounded => ops::Bound::Unbounded,
+        };
+
+        Iter {
+            tree: self.clone(),
+            hi,
+            lo,
+            cached_node: None,
+            going_forward: true,
+            relaxed: false,
+        }
+    }
+
+    /// Scans `range` on a dedicated thread, which sends each
+    /// item into a channel that holds at most `buffer` items,
+    /// and returns the receiving end. Reading the tree then
+    /// overlaps with processing the items, and the thread
+    /// blocks whenever the channel is full, so it stays at
+    /// most `buffer` items ahead. A `buffer` of 0 hands each
+    /// item over directly.
+    ///
+    /// Like `Tree::range`, this does not take a snapshot, so
+    /// writes made during the scan may or may not be seen.
+    /// The thread exits after sending the last item of the
+    /// range, or after sending an error, which ends the scan.
+    /// Dropping the receiver cancels the scan: the thread
+    /// notices as soon as it tries to send the next item,
+    /// including if it is already blocked on a full channel,
+    /// and exits without reading further. Until it exits, it
+    /// holds a handle to the tree, which keeps the `Db` open.
+    ///
+    /// Returns an error if the thread could not be spawned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let t = sled::Config::new().temporary(true).open()?;
+    /// for i in 0..100_u8 {
+    ///     t.insert(&[i], vec![i])?;
+    /// }
+    ///
+    /// let start: &[u8] = &[10];
+    /// let end: &[u8] = &[20];
+    /// let rx = t.range_channel(start..end, 4)?;
+    ///
+    /// let mut sum = 0;
+    /// for item in rx {
+    ///     let (_key, value) = item?;
+    ///     sum += u32::from(value[0]);
+    /// }
+    /// assert_eq!(sum, (10..20).sum());
+    /// # Ok(()) }
+    /// ```
+    pub fn range_channel<K, R>(
+        &self,
+        range: R,
+        buffer: usize,
+    ) -> Result<Receiver<Result<(IVec, IVec)>>>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        fn owned<K: AsRef<[u8]>>(bound: ops::Bound<&K>) -> ops::Bound<IVec> {
+            match bound {
+                ops::Bound::Included(k) => {
+                    ops::Bound::Included(k.as_ref().into())
+                }
+                ops::Bound::Excluded(k) => {
+                    ops::Bound::Excluded(k.as_ref().into())
+                }
+                ops::Bound::Unbounded => ops::Bound::Unbounded,
+            }
+        }
+
+        let (tx, rx) = sync_channel(buffer);
+        let tree = self.clone();
+        let bounds = (owned(range.start_bound()), owned(range.end_bound()));
+
+        let _ = thread::Builder::new()
+            .name("sled range channel".to_owned())
+            .spawn(move || {
+                // the iterator can't be sent to the thread
+                for item in tree.range::<IVec, _>(bounds) {
+                    let failed = item.is_err();
+                    // sending fails once the receiver is dropped
+                    if tx.send(item).is_err() || failed {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(rx)
+    }
+
+    /// Returns an iterator that continues from a `Cursor`
+    /// returned by `Iter::cursor`, over the keys that were
+    /// left in the range of that iterator. The tree may
+    /// have changed since, so it returns the keys that are
+    /// in the tree now, starting from the first key after the
+    /// last one that the iterator returned. The cursor may
+    /// come from an iterator over another handle to the
+    /// tree, or from before the `Db` was reopened.
+    ///
+    /// See `Iter::cursor` for an example.
+    pub fn resume(&self, cursor: Cursor) -> Iter {
+        Iter {
+            tree: self.clone(),
+            hi: cursor.hi,
+            lo: cursor.lo,
+            cached_node: None,
+            going_forward: true,
+            relaxed: false,
+        }
+    }
+
+    /// Create a double-ended iterator over tuples of keys and
+    /// values, where the keys fall within the specified range,
+    /// that yields items in descending order. This is
+    /// equivalent to calling `rev` on the iterator returned by
+    /// `Tree::range`, and both use the same code path.
+    ///
+    /// Reverse iteration does not scan forward from the
+    /// beginning of the range. The first item is found by
+    /// descending directly to the leaf that contains the end
+    /// of the range. Because leaves are only linked to their
+    /// right sibling, moving to the previous leaf performs a
+    /// new descent from the root, so a reverse scan costs
+    /// `O(log n)` for each leaf it visits, which is
+    /// proportional to the size of the range rather than
+    /// the size of the `Tree`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// for i in 0..6_u8 {
+    ///     t.insert(&[i], vec![i * 10])?;
+    /// }
+    ///
+    /// let start: &[u8] = &[2];
+    /// let end: &[u8] = &[4];
+    /// let mut r = t.range_rev(start..=end);
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[4]), IVec::from(&[40]))));
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[3]), IVec::from(&[30]))));
+    /// assert_eq!(r.next().unwrap(), Ok((IVec::from(&[2]), IVec::from(&[20]))));
+    /// assert_eq!(r.next(), None);
+    /// # Ok(()) }
+    /// ```
+    pub fn range_rev<K, R>(&self, range: R) -> std::iter::Rev<Iter>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        self.range(range).rev()
+    }
+
+    /// Create a double-ended iterator over the tuples of
+    /// keys and values in a range of typed keys, which are
+    /// encoded with `OrderedKey`. This only finds the keys
+    /// that were written as `OrderedKey::to_key_bytes` of
+    /// the same type, and iterates over them in the order of
+    /// that type, like a numeric order for integers. The
+    /// bounds must have the same type as the keys, as other
+    /// types are encoded differently, so integer literals
+    /// may need a suffix to not be taken as `i32`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::OrderedKey;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// for &(id, name) in &[(9, "b"), (10, "a"), (10, "b"), (-1, "a")] {
+    ///     let key: (i32, String) = (id, name.to_string());
+    ///     t.insert(key.to_key_bytes(), name)?;
+    /// }
+    ///
+    /// // numeric on the first field, then lexical on the second
+    /// let start = (9, "b".to_string());
+    /// let end = (10, "b".to_string());
+    /// let mut r = t.range_typed(start..end);
+    /// assert_eq!(r.next().unwrap()?.1, b"b");
+    /// assert_eq!(r.next().unwrap()?.1, b"a");
+    /// assert!(r.next().is_none());
+    ///
+    /// assert_eq!(t.range_typed(..(0, String::new())).count(), 1);
+    /// # Ok(()) }
+    /// ```
+    pub fn range_typed<K, R>(&self, range: R) -> Iter
+    where
+        K: OrderedKey + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let encode = |bound: ops::Bound<&K>| match bound {
+            ops::Bound::Included(key) => {
+                ops::Bound::Included(key.to_key_bytes())
+            }
+            ops::Bound::Excluded(key) => {
+                ops::Bound::Excluded(key.to_key_bytes())
+            }
+            ops::Bound::Unbounded => ops::Bound::Unbounded,
+        };
+
+        self.range((encode(range.start_bound()), encode(range.end_bound())))
+    }
+
+    /// Create an iterator over tuples of keys and values,
+    /// where the all the keys starts with the given prefix.
+    /// This is the range from the prefix to
+    /// `prefix_upper_bound` of the prefix.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(&[0, 0, 0], vec![0, 0, 0])?;
+    /// t.insert(&[0, 0, 1], vec![0, 0, 1])?;
+    /// t.insert(&[0, 0, 2], vec![0, 0, 2])?;
+    /// t.insert(&[0, 0, 3], vec![0, 0, 3])?;
+    /// t.insert(&[0, 1, 0], vec![0, 1, 0])?;
+    /// t.insert(&[0, 1, 1], vec![0, 1, 1])?;
+    ///
+    /// let prefix: &[u8] = &[0, 0];
+    /// let mut r = t.scan_prefix(prefix);
+    /// assert_eq!(
+    ///     r.next(),
+    ///     Some(Ok((IVec::from(&[0, 0, 0]), IVec::from(&[0, 0, 0]))))
+    /// );
+    /// assert_eq!(
+    ///     r.next(),
+    ///     Some(Ok((IVec::from(&[0, 0, 1]), IVec::from(&[0, 0, 1]))))
+    /// );
+    /// assert_eq!(
+    ///     r.next(),
+    ///     Some(Ok((IVec::from(&[0, 0, 2]), IVec::from(&[0, 0, 2]))))
+    /// );
+    /// assert_eq!(
+    ///     r.next(),
+    ///     Some(Ok((IVec::from(&[0, 0, 3]), IVec::from(&[0, 0, 3]))))
+    /// );
+    /// assert_eq!(r.next(), None);
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_prefix<P>(&self, prefix: P) -> Iter
+    where
+        P: AsRef<[u8]>,
+    {
+        let prefix_ref = prefix.as_ref();
+
+        if let Some(upper) = prefix_upper_bound(prefix_ref) {
+            return self.range(prefix_ref..&upper[..]);
+        }
+
+        self.range(prefix..)
+    }
+
+    /// Create an iterator over tuples of keys and values,
+    /// where the keys start with `prefix`, and the rest of
+    /// each key after the prefix falls within `suffix_range`.
+    /// This is useful for scanning part of a secondary index,
+    /// where keys are the indexed value followed by the
+    /// primary key, without building the bounds by hand.
+    ///
+    /// The bounds of `suffix_range` are appended to `prefix`.
+    /// An unbounded start begins at `prefix` itself, and an
+    /// unbounded end stops at the last key starting with
+    /// `prefix`, even if it ends in `0xFF` bytes. An included
+    /// end of `e` includes the key `prefix + e` but not the
+    /// keys that extend it, which sort after it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// for i in 0..5_u8 {
+    ///     t.insert(&[0xFF, i], vec![i])?;
+    /// }
+    /// t.insert(&[0xFF, 0xFF], vec![])?;
+    ///
+    /// let prefix: &[u8] = &[0xFF];
+    /// let start: &[u8] = &[2];
+    /// let end: &[u8] = &[4];
+    ///
+    /// let mut r = t.scan_prefix_range(prefix, start..end);
+    /// assert_eq!(r.next().unwrap()?.0, IVec::from(&[0xFF, 2]));
+    /// assert_eq!(r.next().unwrap()?.0, IVec::from(&[0xFF, 3]));
+    /// assert!(r.next().is_none());
+    ///
+    /// let mut r = t.scan_prefix_range(prefix, start..);
+    /// let last = r.next_back().unwrap()?;
+    /// assert_eq!(last.0, IVec::from(&[0xFF, 0xFF]));
+    /// assert_eq!(r.count(), 3);
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_prefix_range<P, K, R>(
+        &self,
+        prefix: P,
+        suffix_range: R,
+    ) -> Iter
+    where
+        P: AsRef<[u8]>,
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let prefix_ref = prefix.as_ref();
+        let join = |suffix: &K| {
+            let mut key = prefix_ref.to_vec();
+            key.extend_from_slice(suffix.as_ref());
+            IVec::from(key)
+        };
+
+        let mut iter = self.scan_prefix(prefix_ref);
+
+        match suffix_range.start_bound() {
+            ops::Bound::Included(start) => {
+                iter.lo = ops::Bound::Included(join(start));
+            }
+            ops::Bound::Excluded(start) => {
+                iter.lo = ops::Bound::Excluded(join(start));
+            }
+            ops::Bound::Unbounded => {}
+        }
+
+        match suffix_range.end_bound() {
+            ops::Bound::Included(end) => {
+                iter.hi = ops::Bound::Included(join(end));
+            }
+            ops::Bound::Excluded(end) => {
+                iter.hi = ops::Bound::Excluded(join(end));
+            }
+            ops::Bound::Unbounded => {}
+        }
+
+        iter
+    }
+
+    /// Create an iterator over tuples of keys and values,
+    /// where each key starts with some prefix between
+    /// `start_prefix` and `end_prefix`, inclusive.
+    ///
+    /// This differs from `range(start_prefix..=end_prefix)`
+    /// in that keys extending `end_prefix`, which sort after
+    /// it, are included too. The iterator stops at the
+    /// smallest key greater than every key starting with
+    /// `end_prefix`, as `scan_prefix` does, and runs to the
+    /// end of the tree if `end_prefix` is empty or consists
+    /// only of `0xFF` bytes. Nothing is returned if
+    /// `start_prefix` sorts after `end_prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// t.insert(b"2020-01-31", vec![])?;
+    /// t.insert(b"2020-02", vec![])?;
+    /// t.insert(b"2020-02-29", vec![])?;
+    /// t.insert(b"2020-03", vec![])?;
+    /// t.insert(b"2020-03-01", vec![])?;
+    /// t.insert(b"2020-04-01", vec![])?;
+    ///
+    /// let mut r = t.scan_prefixes(b"2020-02", b"2020-03");
+    /// assert_eq!(r.next().unwrap()?.0, IVec::from(b"2020-02"));
+    /// assert_eq!(r.next().unwrap()?.0, IVec::from(b"2020-02-29"));
+    /// assert_eq!(r.next().unwrap()?.0, IVec::from(b"2020-03"));
+    /// assert_eq!(r.next().unwrap()?.0, IVec::from(b"2020-03-01"));
+    /// assert!(r.next().is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_prefixes<S, E>(&self, start_prefix: S, end_prefix: E) -> Iter
+    where
+        S: AsRef<[u8]>,
+        E: AsRef<[u8]>,
+    {
+        let start = start_prefix.as_ref();
+        let end = end_prefix.as_ref();
+
+        if start > end {
+            return self.range(start..start);
+        }
+
+        if let Some(upper) = prefix_upper_bound(end) {
+            return self.range(start..&upper[..]);
+        }
+
+        self.range(start..)
+    }
+
+    /// Create an iterator over the groups of keys of this
+    /// tree, where `key_group_fn` returns the group of each
+    /// key, such as the part of a `group|item` key before
+    /// the separator. Each distinct group is returned once,
+    /// in key order, along with a `GroupIter` over its keys
+    /// and values, so boundaries between groups do not have
+    /// to be detected by hand.
+    ///
+    /// Groups are runs of adjacent keys, so `key_group_fn`
+    /// should return a part of the key that keys are sorted
+    /// by first, usually a prefix. A group that comes back
+    /// after keys of another group is returned again.
+    ///
+    /// The `GroupIter`s read the tree independently of the
+    /// outer iterator, which reads every key of a group
+    /// again to find the start of the next one, whether or
+    /// not the group was iterated over.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let t = sled::Config::new().temporary(true).open()?;
+    /// t.insert(b"fruit|apple", b"red")?;
+    /// t.insert(b"fruit|kiwi", b"green")?;
+    /// t.insert(b"veg|leek", b"green")?;
+    ///
+    /// fn group_of(key: &[u8]) -> &[u8] {
+    ///     let end = key.iter().position(|b| *b == b'|');
+    ///     &key[..end.unwrap_or(key.len())]
+    /// }
+    ///
+    /// let mut groups = t.group_by(group_of);
+    ///
+    /// let (group, items) = groups.next().unwrap()?;
+    /// assert_eq!(group, b"fruit");
+    /// assert_eq!(items.count(), 2);
+    ///
+    /// let (group, mut items) = groups.next().unwrap()?;
+    /// assert_eq!(group, b"veg");
+    /// let (key, value) = items.next().unwrap()?;
+    /// assert_eq!(key, b"veg|leek");
+    /// assert_eq!(value, b"green");
+    ///
+    /// assert!(groups.next().is_none());
+    /// # Ok(()) }
+    /// ```
+    pub fn group_by<F>(&self, key_group_fn: F) -> GroupBy
+    where
+        F: Fn(&[u8]) -> &[u8] + Send + Sync + 'static,
+    {
+        GroupBy::new(self.iter(), key_group_fn)
+    }
+
+    /// Returns an iterator over the differences between this
+    /// tree and `other`, in key order. Keys that are only in
+    /// this tree are returned as `Diff::OnlyLeft`, keys that
+    /// are only in `other` as `Diff::OnlyRight`, and keys
+    /// whose values differ as `Diff::Changed`. Keys with
+    /// equal values in both trees are skipped.
+    ///
+    /// Both trees are walked side by side, so this runs in
+    /// time proportional to the size of both trees without
+    /// holding either of them in memory. The trees may
+    /// belong to different `Db`s. Like `Tree::iter`, this
+    /// does not take a snapshot of either tree, so writes
+    /// that happen during the walk may or may not be seen.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::Diff;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let a = db.open_tree(b"a")?;
+    /// let b = db.open_tree(b"b")?;
+    ///
+    /// a.insert(b"k1", b"v1")?;
+    /// a.insert(b"k2", b"v2")?;
+    /// b.insert(b"k2", b"changed")?;
+    /// b.insert(b"k3", b"v3")?;
+    ///
+    /// let diffs: Vec<Diff> = a.diff(&b).collect::<sled::Result<_>>()?;
+    /// assert_eq!(
+    ///     diffs,
+    ///     vec![
+    ///         Diff::OnlyLeft(b"k1".into(), b"v1".into()),
+    ///         Diff::Changed(b"k2".into(), b"v2".into(), b"changed".into()),
+    ///         Diff::OnlyRight(b"k3".into(), b"v3".into()),
+    ///     ]
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn diff(&self, other: &Tree) -> DiffIter {
+        DiffIter::new(self.iter(), other.iter())
+    }
+
+    /// Like `Tree::diff`, but only over keys that start with
+    /// `prefix`. Together with `Tree::checksum_ranges`, this
+    /// avoids walking the parts of two trees that match:
+    /// compare the checksums of each prefix first, and only
+    /// diff the prefixes whose checksums differ.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::Diff;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let a = db.open_tree(b"a")?;
+    /// let b = db.open_tree(b"b")?;
+    ///
+    /// for t in &[&a, &b] {
+    ///     t.insert(b"user/1", b"alice")?;
+    ///     t.insert(b"post/1", b"hello")?;
+    /// }
+    /// b.insert(b"user/2", b"bob")?;
+    ///
+    /// let mut diffs = vec![];
+    /// let left = a.checksum_ranges(4)?;
+    /// let right = b.checksum_ranges(4)?;
+    /// for (left, right) in left.into_iter().zip(right) {
+    ///     if left != right {
+    ///         for diff in a.diff_prefix(&b, &left.0) {
+    ///             diffs.push(diff?);
+    ///         }
+    ///     }
+    /// }
+    /// assert_eq!(
+    ///     diffs,
+    ///     vec![Diff::OnlyRight(b"user/2".into(), b"bob".into())]
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn diff_prefix<P>(&self, other: &Tree, prefix: P) -> DiffIter
+    where
+        P: AsRef<[u8]>,
+    {
+        let prefix = prefix.as_ref();
+        DiffIter::new(self.scan_prefix(prefix), other.scan_prefix(prefix))
+    }
+
+    /// Like `Tree::diff`, but only over keys that fall within
+    /// `range`. This narrows a diff down to the parts of two
+    /// trees that differ, such as the keys between two groups
+    /// of `Tree::checksum_ranges` that match, without walking
+    /// the rest of either tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::Diff;
+    ///
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let a = db.open_tree(b"a")?;
+    /// let b = db.open_tree(b"b")?;
+    ///
+    /// a.insert(b"k1", b"v1")?;
+    /// a.insert(b"k2", b"v2")?;
+    /// b.insert(b"k2", b"changed")?;
+    /// b.insert(b"k3", b"v3")?;
+    ///
+    /// let start: &[u8] = b"k2";
+    /// let diffs: Vec<Diff> =
+    ///     a.diff_range(&b, start..).collect::<sled::Result<_>>()?;
+    /// assert_eq!(
+    ///     diffs,
+    ///     vec![
+    ///         Diff::Changed(b"k2".into(), b"v2".into(), b"changed".into()),
+    ///         Diff::OnlyRight(b"k3".into(), b"v3".into()),
+    ///     ]
+    /// );
+    /// # Ok(()) }
+    /// ```
+    pub fn diff_range<K, R>(&self, other: &Tree, range: R) -> DiffIter
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let left = self.range(range);
+        let right = other.range((left.lo.clone(), left.hi.clone()));
+        DiffIter::new(left, right)
+    }
+
+    /// Returns up to `limit` key-value pairs whose keys start
+    /// with the given prefix, beginning just after `start_after`
+    /// if it is provided. If `reverse` is `true`, items are
+    /// returned in descending order, starting just before
+    /// `start_after`. This is intended for paginating over
+    /// a prefix: pass the last key of the previous page as
+    /// `start_after` to get the next one.
+    ///
+    /// Reading stops as soon as `limit` items have been
+    /// collected, so the rest of the prefix is not scanned.
+    ///
+    /// If `start_after` falls outside of the prefix, it
+    /// only restricts the page if it is on the side
+    /// being paged towards. When scanning forward, a
+    /// `start_after` that sorts before the prefix has no
+    /// effect and one that sorts after it yields an empty
+    /// page. When scanning in reverse, the opposite holds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::{Config, IVec};
+    /// let config = Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// for i in 0..10_u8 {
+    ///     t.insert(&[0, i], vec![i])?;
+    /// }
+    /// t.insert(&[1, 0], vec![])?;
+    ///
+    /// let page = t.scan_prefix_page(&[0], None, 3, false)?;
+    /// assert_eq!(page.len(), 3);
+    /// assert_eq!(page[0].0, IVec::from(&[0, 0]));
+    /// assert_eq!(page[2].0, IVec::from(&[0, 2]));
+    ///
+    /// let page = t.scan_prefix_page(&[0], Some(&[0, 2]), 2, false)?;
+    /// assert_eq!(page[0].0, IVec::from(&[0, 3]));
+    /// assert_eq!(page[1].0, IVec::from(&[0, 4]));
+    ///
+    /// let page = t.scan_prefix_page(&[0], Some(&[0, 2]), 5, true)?;
+    /// assert_eq!(page.len(), 2);
+    /// assert_eq!(page[0].0, IVec::from(&[0, 1]));
+    /// assert_eq!(page[1].0, IVec::from(&[0, 0]));
+    /// # Ok(()) }
+    /// ```
+    pub fn scan_prefix_page<P>(
+        &self,
+        prefix: P,
+        start_after: Option<&[u8]>,
+        limit: usize,
+        reverse: bool,
+    ) -> Result<Vec<(IVec, IVec)>>
+    where
+        P: AsRef<[u8]>,
+    {
+        let mut iter = self.scan_prefix(prefix);
+
+        if let Some(after) = start_after {
+            if reverse {
+                let tighter = match iter.hi {
+                    ops::Bound::Unbounded => true,
+                    ops::Bound::Included(ref hi)
+                    | ops::Bound::Excluded(ref hi) => {
+                        after < hi.as_ref()
+                    }
+                };
+                if tighter {
+                    iter.hi = ops::Bound::Excluded(IVec::from(after));
+                }
+            } else {
+                let tighter = match iter.lo {
+                    ops::Bound::Unbounded => true,
+                    ops::Bound::Included(ref lo)
+                    | ops::Bound::Excluded(ref lo) => {
+                        after >= lo.as_ref()
+                    }
+                };
+                if tighter {
+                    iter.lo = ops::Bound::Excluded(IVec::from(after));
+                }
+            }
+        }
+
+        let mut ret = Vec::with_capacity(std::cmp::min(limit, 1024));
+
+        while ret.len() < limit {
+            let next = if reverse { iter.next_back() } else { iter.next() };
+            if let Some(kv_res) = next {
+                ret.push(kv_res?);
+            } else {
+                break;
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Calls `f` with each key and value whose key starts
+    /// with `prefix`, in order, until it returns
+    /// `ControlFlow::Break`. This reads the same entries as
+    /// `Tree::scan_prefix`, but passes them as slices that
+    /// are borrowed from the tree's pages instead of as
+    /// `IVec`s wrapped in a `Result`, so read-only scans
+    /// don't allocate for each entry. Values of trees with
+    /// `Config::dedup_values` are still loaded one by one.
+    ///
+    /// Entries are read a page at a time, so `f` may see
+    /// the value a key had when its page was read, like an
+    /// iterator does, and `f` may write to the tree. Only
+    /// the page being read is kept from being reclaimed
+    /// while `f` runs. The first error reading the tree
+    /// stops the scan and is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use sled::ControlFlow;
+    ///
+    /// let t = sled::Config::new().temporary(true).open()?;
+    /// for i in 0..10_u8 {
+    ///     t.insert(&[0, i], vec![i])?;
+    /// }
+    /// t.insert(&[1, 0], vec![])?;
+    ///
+    /// // sums values until one is too big
+    /// let mut sum = 0;
+    /// t.for_each_prefix(&[0], |_key, value| {
+    ///     if value[0] > 3 {
+    ///         return ControlFlow::Break;
+    ///     }
+    ///     sum += value[0];
+    ///     ControlFlow::Continue
+    /// })?;
+    /// assert_eq!(sum, 6);
+    /// # Ok(()) }
+    /// ```
+    pub fn for_each_prefix<P, F>(&self, prefix: P, mut f: F) -> Result<()>
+    where
+        P: AsRef<[u8]>,
+        F: FnMut(&[u8], &[u8]) -> ControlFlow,
+    {
+        let _measure = Measure::new(&M.tree_scan);
+        let _scan = lru::scanning();
+        self.check_valid()?;
+
+        let prefix = prefix.as_ref();
+        let upper = prefix_upper_bound(prefix);
+        let past_prefix = |key: &[u8]| match upper {
+            Some(ref upper) => key >= &upper[..],
+            None => false,
+        };
+
+        let mut lo = IVec::from(prefix);
+        // each key is put back together here from the prefix
+        // of its node and the rest of it, which the node holds
+        let mut key = Vec::new();
+        loop {
+            // pinned for one leaf at a time, so that a long scan
+            // or slow callbacks don't hold back reclamation of
+            // the pages replaced in the meantime
+            let guard = pin();
+            let cc = concurrency_control::read();
+            let View { node_view, .. } = self.view_for_key(&lo, &guard)?;
+            drop(cc);
+
+            let node_prefix = &node_view.lo[..node_view.prefix_len as usize];
+            let encoded_lo = &lo[node_prefix.len()..];
+            let leaf = node_view.data.leaf_ref().unwrap();
+            let start =
+                match leaf.keys.binary_search_by(|k| fastcmp(k, encoded_lo)) {
+                    Ok(start) | Err(start) => start,
+                };
+
+            for (encoded_key, stored) in
+                leaf.keys[start..].iter().zip(&leaf.values[start..])
+            {
+                key.clear();
+                key.extend_from_slice(node_prefix);
+                key.extend_from_slice(encoded_key);
+                if past_prefix(&key) {
+                    return Ok(());
+                }
+
+                let flow = if self.dedup.is_some() {
+                    let cc = concurrency_control::read();
+                    let value = self.resolve_current(&key, stored.clone())?;
+                    drop(cc);
+                    match value {
+                        Some(value) => f(&key, &value),
+                        None => continue,
+                    }
+                } else {
+                    f(&key, stored)
+                };
+                if flow == ControlFlow::Break {
+                    return Ok(());
+                }
+            }
+
+            if node_view.hi.is_empty() || past_prefix(&node_view.hi) {
+                return Ok(());
+            }
+            lo = node_view.hi.clone();
+        }
+    }
+
+    /// Loads the pages that hold the keys in `range` into
+    /// the cache in the background, so that a scan of the
+    /// range that follows doesn't wait for reads from disk.
+    /// This returns once the page with the first key of the
+    /// range is loaded, and the pages after it are loaded
+    /// one by one on the threadpool.
+    ///
+    /// This is advisory, and nothing is returned about the
+    /// keys or values. Pages that are already in the cache
+    /// are not read again. Loading stops once the pages that
+    /// were visited take up `Config::cache_capacity_bytes`,
+    /// because loading more would evict the first ones
+    /// before the scan reaches them, and any page can still
+    /// be evicted by other reads before the scan reaches it.
+    /// Errors while loading in the background are logged,
+    /// and the scan returns them again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// for i in 0..100_u8 {
+    ///     db.insert([i], vec![i])?;
+    /// }
+    ///
+    /// db.prefetch_range([10_u8]..[90])?;
+    /// assert_eq!(db.range([10_u8]..[90]).count(), 80);
+    /// # Ok(()) }
+    /// ```
+    pub fn prefetch_range<K, R>(&self, range: R) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let iter = self.range(range);
+        self.prefetch(iter.lo.clone(), iter.hi.clone())
+    }
+
+    /// Loads the pages that hold the keys starting with
+    /// `prefix` into the cache in the background, like
+    /// `Tree::prefetch_range`.
+    pub fn prefetch_prefix<P>(&self, prefix: P) -> Result<()>
+    where
+        P: AsRef<[u8]>,
+    {
+        let iter = self.scan_prefix(prefix);
+        self.prefetch(iter.lo.clone(), iter.hi.clone())
+    }
+
+    fn prefetch(
+        &self,
+        lo: ops::Bound<IVec>,
+        hi: ops::Bound<IVec>,
+    ) -> Result<()> {
+        let lo_key = match lo {
+            ops::Bound::Included(key) | ops::Bound::Excluded(key) => key,
+            ops::Bound::Unbounded => IVec::default(),
+        };
+
+        let past_range = move |page_lo: &IVec| past_range(&hi, page_lo);
+
+        let (mut next, mut loaded) = {
+            let guard = pin();
+            let view = self.view_for_key(&lo_key, &guard)?;
+            if past_range(&view.hi) {
+                return Ok(());
+            }
+            (view.next, view.rss())
+        };
+
+        let pagecache = self.context.pagecache.clone();
+        let budget = self.context.pagecache.cache_capacity();
+        let _result = self.context.spawn(move || {
+            while let Some(pid) = next {
+                if loaded >= budget {
+                    return;
+                }
+                let guard = pin();
+                match pagecache.get(pid.get(), &guard) {
+                    Ok(Some(view)) => {
+                        if past_range(&view.hi) {
+                            return;
+                        }
+                        loaded += view.rss();
+                        next = view.next;
+                    }
+                    // merged away since
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!("failed to prefetch page {}: {:?}", pid, e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Merges the pages that hold the keys in `range` into
+    /// their left neighbours wherever both fit into one page,
+    /// so that later scans across the range read fewer pages.
+    ///
+    /// Removing a key takes it out of its page right away,
+    /// without leaving a tombstone behind, but pages are only
+    /// merged when they shrink below a threshold and a later
+    /// operation passes through them, so after a large range
+    /// of keys is removed, some of
+    /// the pages it was stored in may stay behind, empty,
+    /// until something reads them again. A scan that
+    /// crosses the range then has to step over each of
+    /// them. This visits the pages of the range, merging
+    /// them along the way, so the pages that are left are
+    /// roughly those that the remaining keys need.
+    ///
+    /// This is O(n) in the number of pages in the range,
+    /// which are visited a few times, until visiting them
+    /// again would not merge any more of them. It can run
+    /// concurrently with other operations, and keys that are
+    /// inserted into the range meanwhile are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// for i in 0..10_000_u32 {
+    ///     db.insert(i.to_be_bytes(), vec![])?;
+    /// }
+    /// for i in 1..9_999_u32 {
+    ///     db.remove(i.to_be_bytes())?;
+    /// }
+    ///
+    /// db.compact_range(1_u32.to_be_bytes()..9_999_u32.to_be_bytes())?;
+    /// assert_eq!(db.iter().count(), 2);
+    /// # Ok(()) }
+    /// ```
+    pub fn compact_range<K, R>(&self, range: R) -> Result<()>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        let iter = self.range(range);
+
+        let start = match iter.lo {
+            ops::Bound::Included(key) | ops::Bound::Excluded(key) => key,
+            ops::Bound::Unbounded => IVec::default(),
+        };
+
+        // merging a page can make its parent fit into its own
+        // left sibling only after the walk has passed that
+        // sibling, so the range is walked again until a walk
+        // finds no fewer pages than the one before it
+        let mut last_pages = None;
+        loop {
+            let mut pages = 0;
+            let mut key = start.clone();
+            loop {
+                let guard = pin();
+                let view = {
+                    let _cc = concurrency_control::read();
+                    self.traverse(&key, true, &guard)?
+                };
+                pages += 1;
+                if past_range(&iter.hi, &view.hi) {
+                    break;
+                }
+                key = view.hi.clone();
+            }
+
+            match last_pages {
+                Some(last) if pages >= last => return Ok(()),
+                _ => last_pages = Some(pages),
+            }
+        }
+    }
+
+    /// Folds over the keys and values in the given range,
+    /// in ascending order, without allocating a new `IVec`
+    /// for each key and value. This is useful for computing
+    /// aggregates like sums or counts over fixed-width
+    /// values in a single pass.
+    ///
+    /// The closure must not write to this `Tree` or
+    /// hold on to the key and value slices beyond its
+    /// invocation. Like `Tree::iter`, this provides no
+    /// point-in-time snapshot guarantees across keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::convert::TryInto;
+    ///
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// for i in 0..10_u64 {
+    ///     t.insert(&i.to_be_bytes(), &i.to_le_bytes())?;
+    /// }
+    ///
+    /// let start = 5_u64.to_be_bytes();
+    /// let sum = t.fold_range(&start[..].., 0, |acc, _k, v| {
+    ///     acc + u64::from_le_bytes(v.try_into().unwrap())
+    /// })?;
+    /// assert_eq!(sum, 5 + 6 + 7 + 8 + 9);
     /// # Ok(()) }
     /// ```
-    pub fn range<K, R>(&self, range: R) -> Iter
+    pub fn fold_range<K, R, A, F>(
+        &self,
+        range: R,
+        init: A,
+        mut f: F,
+    ) -> Result<A>
     where
         K: AsRef<[u8]>,
         R: RangeBounds<K>,
+        F: FnMut(A, &[u8], &[u8]) -> A,
     {
-        let lo = match range.start_bound() {
-            ops::Bound::Included(start) => {
-                ops::Bound::Included(IVec::from(start.as_ref()))
-            }
-            ops::Bound::Excluded(start) => {
-                ops::Bound::Excluded(IVec::from(start.as_ref()))
-            }
-            ops::Bound::Unbounded => ops::Bound::Included(IVec::from(&[])),
-        };
-
-        let hi = match range.end_bound() {
-            ops::Bound::Included(end) => {
-                ops::Bound::Included(IVec::from(end.as_ref()))
-            }
-            ops::Bound::Excluded(end) => {
-                ops::Bound::Excluded(IVec::from(end.as_ref()))
-            }
-            ops::Bound::Unbounded => ops::Bound::Unbounded,
-        };
+        let res = self.try_fold_range(range, init, |acc, k, v| {
+            Ok::<_, std::convert::Infallible>(f(acc, k, v))
+        })?;
 
-        Iter {
-            tree: self.clone(),
-            hi,
-            lo,
-            cached_node: None,
-            going_forward: true,
+        match res {
+            Ok(acc) => Ok(acc),
+            Err(never) => match never {},
         }
     }
 
-    /// Create an iterator over tuples of keys and values,
-    /// where the all the keys starts with the given prefix.
+    /// Like `Tree::fold_range`, but stops as soon as
+    /// the closure returns an error, which is then
+    /// returned in the inner `Result`. This can be
+    /// used to terminate the fold early.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// use sled::{Config, IVec};
-    /// let config = Config::new().temporary(true);
+    /// let config = sled::Config::new().temporary(true);
     /// let t = config.open()?;
     ///
-    /// t.insert(&[0, 0, 0], vec![0, 0, 0])?;
-    /// t.insert(&[0, 0, 1], vec![0, 0, 1])?;
-    /// t.insert(&[0, 0, 2], vec![0, 0, 2])?;
-    /// t.insert(&[0, 0, 3], vec![0, 0, 3])?;
-    /// t.insert(&[0, 1, 0], vec![0, 1, 0])?;
-    /// t.insert(&[0, 1, 1], vec![0, 1, 1])?;
+    /// for i in 0..10_u8 {
+    ///     t.insert(&[i], &[i])?;
+    /// }
     ///
-    /// let prefix: &[u8] = &[0, 0];
-    /// let mut r = t.scan_prefix(prefix);
-    /// assert_eq!(
-    ///     r.next(),
-    ///     Some(Ok((IVec::from(&[0, 0, 0]), IVec::from(&[0, 0, 0]))))
-    /// );
-    /// assert_eq!(
-    ///     r.next(),
-    ///     Some(Ok((IVec::from(&[0, 0, 1]), IVec::from(&[0, 0, 1]))))
-    /// );
-    /// assert_eq!(
-    ///     r.next(),
-    ///     Some(Ok((IVec::from(&[0, 0, 2]), IVec::from(&[0, 0, 2]))))
-    /// );
-    /// assert_eq!(
-    ///     r.next(),
-    ///     Some(Ok((IVec::from(&[0, 0, 3]), IVec::from(&[0, 0, 3]))))
-    /// );
-    /// assert_eq!(r.next(), None);
+    /// // find the first key whose value exceeds 3
+    /// let res = t.try_fold_range::<&[u8], _, _, _, _>(.., (), |(), k, v| {
+    ///     if v[0] > 3 { Err(k.to_vec()) } else { Ok(()) }
+    /// })?;
+    /// assert_eq!(res, Err(vec![4]));
     /// # Ok(()) }
     /// ```
-    pub fn scan_prefix<P>(&self, prefix: P) -> Iter
+    pub fn try_fold_range<K, R, A, E, F>(
+        &self,
+        range: R,
+        init: A,
+        mut f: F,
+    ) -> Result<std::result::Result<A, E>>
     where
-        P: AsRef<[u8]>,
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+        F: FnMut(A, &[u8], &[u8]) -> std::result::Result<A, E>,
     {
-        let prefix_ref = prefix.as_ref();
-        let mut upper = prefix_ref.to_vec();
+        let _measure = Measure::new(&M.tree_scan);
+
+        let Iter { mut lo, hi, .. } = self.range(range);
+
+        let mut acc = init;
+        let mut key_buf = vec![];
+
+        loop {
+            let guard = pin();
+            let view = {
+                let _cc = concurrency_control::read();
+                let _scan = lru::scanning();
+                let lo_key: &[u8] = match lo {
+                    ops::Bound::Included(ref k)
+                    | ops::Bound::Excluded(ref k) => k,
+                    ops::Bound::Unbounded => &[],
+                };
+                self.view_for_key(lo_key, &guard)?
+            };
+
+            let leaf = view.data.leaf_ref().unwrap();
+            let prefix = &view.lo[..view.prefix_len as usize];
+
+            for (suffix, value) in leaf.keys.iter().zip(&leaf.values) {
+                // decode the key into a reused buffer to avoid
+                // allocating for every item in the range
+                key_buf.clear();
+                key_buf.extend_from_slice(prefix);
+                key_buf.extend_from_slice(suffix);
+                let key = &*key_buf;
+
+                let above_lo = match lo {
+                    ops::Bound::Included(ref l) => key >= &**l,
+                    ops::Bound::Excluded(ref l) => key > &**l,
+                    ops::Bound::Unbounded => true,
+                };
+                if !above_lo {
+                    continue;
+                }
+
+                let below_hi = match hi {
+                    ops::Bound::Included(ref h) => key <= &**h,
+                    ops::Bound::Excluded(ref h) => key < &**h,
+                    ops::Bound::Unbounded => true,
+                };
+                if !below_hi {
+                    return Ok(Ok(acc));
+                }
+
+                let resolved;
+                let item_value: &[u8] = if self.dedup.is_some() {
+                    // the leaf may be stale by now
+                    resolved = match self.resolve_current(key, value.clone())? {
+                        Some(current) => current,
+                        None => continue,
+                    };
+                    &resolved
+                } else {
+                    value
+                };
 
-        while let Some(last) = upper.pop() {
-            if last < u8::max_value() {
-                upper.push(last + 1);
-                return self.range(prefix_ref..&upper);
+                acc = match f(acc, key, item_value) {
+                    Ok(next) => next,
+                    Err(e) => return Ok(Err(e)),
+                };
             }
-        }
 
-        self.range(prefix..)
+            let exhausted = view.hi.is_empty()
+                || match hi {
+                    ops::Bound::Included(ref h) => *h < view.hi,
+                    ops::Bound::Excluded(ref h) => *h <= view.hi,
+                    ops::Bound::Unbounded => false,
+                };
+            if exhausted {
+                return Ok(Ok(acc));
+            }
+
+            lo = ops::Bound::Included(view.hi.clone());
+        }
     }
 
     /// Atomically removes the maximum item in the `Tree` instance.
@@ -1363,35 +4147,505 @@ impl Tree {
                 {
                     return Ok(Some(first));
                 }
-            // try again
-            } else {
-                return Ok(None);
+            // try again
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Inserts a value under the next sequence number, which
+    /// is one greater than the last key in the `Tree` read as
+    /// a big-endian `u64`, or 0 if the `Tree` is empty, and
+    /// returns that number. This makes the `Tree` an
+    /// append-only log or a queue that `pop_min` consumes.
+    ///
+    /// Concurrent appends never collide, and each gets the
+    /// number that follows the last key when its write takes
+    /// effect, so no numbers are skipped. The sequence is
+    /// stored in the keys themselves, so it continues after
+    /// a restart, but removing the last key means that its
+    /// number is reused by the next append.
+    ///
+    /// Returns `Error::Unsupported` if the last key is not 8
+    /// bytes long, or if it is `u64::max_value()`.
+    ///
+    /// # Note
+    ///
+    /// Under contention this may read the last key multiple
+    /// times, like `Tree::update_and_fetch`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// assert_eq!(t.append(b"first")?, 0);
+    /// assert_eq!(t.append(b"second")?, 1);
+    /// let second = t.get(1_u64.to_be_bytes())?;
+    /// assert_eq!(second, Some(sled::IVec::from(b"second")));
+    ///
+    /// t.pop_min()?;
+    /// assert_eq!(t.append(b"third")?, 2);
+    /// # Ok(()) }
+    /// ```
+    pub fn append<V>(&self, value: V) -> Result<u64>
+    where
+        IVec: From<V>,
+    {
+        let appended = IVec::from(value);
+        loop {
+            let next = match self.iter().next_back() {
+                None => 0,
+                Some(last_res) => {
+                    let (key, _) = last_res?;
+                    let last = <[u8; 8]>::try_from(&*key)
+                        .map(u64::from_be_bytes)
+                        .map_err(|_| {
+                            Error::Unsupported(format!(
+                                "append requires the last key to be an \
+                                 8 byte big-endian integer, but it is {:?}",
+                                key
+                            ))
+                        })?;
+                    last.checked_add(1).ok_or_else(|| {
+                        Error::Unsupported(
+                            "append ran out of sequence numbers".to_owned(),
+                        )
+                    })?
+                }
+            };
+
+            if self
+                .compare_and_swap::<_, &[u8], IVec>(
+                    next.to_be_bytes(),
+                    None,
+                    Some(appended.clone()),
+                )?
+                .is_ok()
+            {
+                return Ok(next);
+            }
+        }
+    }
+
+    /// Removes items in key order, starting at `start` or at
+    /// the beginning of the `Tree`, for as long as `pred`
+    /// returns `true` for their key and value. Returns the
+    /// number of items removed.
+    ///
+    /// This only removes the contiguous run of items at the
+    /// start of the scan that match: it stops at the first
+    /// item for which `pred` returns `false`, even if later
+    /// items would match. This makes it suitable for pruning
+    /// items whose keys are ordered by the property being
+    /// checked, such as keys that begin with a timestamp.
+    ///
+    /// Like `Tree::clear`, this is not atomic. Each item is
+    /// removed with a separate compare and swap, so an item
+    /// is only removed if its value is still the one that
+    /// `pred` saw. If it was changed concurrently, `pred` is
+    /// called again with the new value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// // keys are big-endian timestamps
+    /// for ts in &[10_u64, 20, 30, 40] {
+    ///     t.insert(ts.to_be_bytes(), vec![])?;
+    /// }
+    ///
+    /// let cutoff = 25_u64.to_be_bytes();
+    /// let expired = |key: &[u8], _value: &[u8]| key < &cutoff[..];
+    /// assert_eq!(t.remove_while(None, expired)?, 2);
+    /// assert_eq!(t.iter().next().unwrap()?.0, 30_u64.to_be_bytes());
+    /// # Ok(()) }
+    /// ```
+    pub fn remove_while<F>(
+        &self,
+        start: Option<&[u8]>,
+        mut pred: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let mut removed = 0;
+        let mut from: Option<IVec> = start.map(IVec::from);
+
+        'scan: loop {
+            let iter = match from.take() {
+                Some(key) => self.range(key..),
+                None => self.iter(),
+            };
+
+            for res in iter {
+                let (key, value) = res?;
+                if !pred(&key, &value) {
+                    return Ok(removed);
+                }
+
+                let cas = self.compare_and_swap::<_, _, &[u8]>(
+                    &key,
+                    Some(&value),
+                    None,
+                )?;
+
+                if cas.is_err() {
+                    // the item changed after `pred` saw it, so
+                    // scan again from it to check its new value
+                    from = Some(key);
+                    continue 'scan;
+                }
+
+                removed += 1;
+            }
+
+            return Ok(removed);
+        }
+    }
+
+    /// Removes every item for which `f` returns `false`,
+    /// visiting items in key order in a single scan, and
+    /// returns the number of items removed.
+    ///
+    /// Like `Tree::remove_while`, this is not atomic. Each
+    /// item is removed with a separate compare and swap, so
+    /// an item is only removed if its value is still the one
+    /// that `f` saw. If it was changed concurrently, `f` is
+    /// called again with the new value. Items that are
+    /// inserted concurrently may or may not be visited,
+    /// depending on whether the scan has already passed
+    /// their keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    ///
+    /// for i in 0..10_u8 {
+    ///     t.insert([i], vec![i % 3])?;
+    /// }
+    ///
+    /// // keep the items whose value is 0
+    /// assert_eq!(t.retain(|_key, value| value[0] == 0)?, 6);
+    /// assert_eq!(t.len(), 4);
+    /// # Ok(()) }
+    /// ```
+    pub fn retain<F>(&self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&[u8], &[u8]) -> bool,
+    {
+        let mut removed = 0;
+        let mut from: Option<IVec> = None;
+
+        'scan: loop {
+            let iter = match from.take() {
+                Some(key) => self.range(key..),
+                None => self.iter(),
+            };
+
+            for res in iter {
+                let (key, value) = res?;
+                if f(&key, &value) {
+                    continue;
+                }
+
+                let cas = self.compare_and_swap::<_, _, &[u8]>(
+                    &key,
+                    Some(&value),
+                    None,
+                )?;
+
+                if cas.is_err() {
+                    // the item changed after `f` saw it, so
+                    // scan again from it to check its new value
+                    from = Some(key);
+                    continue 'scan;
+                }
+
+                removed += 1;
+            }
+
+            return Ok(removed);
+        }
+    }
+
+    /// Returns the number of elements in this tree.
+    ///
+    /// Beware: performs a full O(n) scan under the hood.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(b"a", vec![0]);
+    /// t.insert(b"b", vec![1]);
+    /// assert_eq!(t.len(), 2);
+    /// # Ok(()) }
+    /// ```
+    #[allow(clippy::len_without_is_empty)] // is_empty can fail
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns the number of keys in `range`. The count is
+    /// exact. Whole leaves are counted without reading their
+    /// keys, and only the leaves at either end of the range
+    /// are searched. The number of keys below each index
+    /// node that the range covers entirely is remembered
+    /// until the tree is next written to, so counting a
+    /// range that was counted before, or that overlaps one,
+    /// only descends into the nodes at its ends, which is
+    /// O(log n). The first count after a write reads every
+    /// leaf in the range again, which is O(n) in the number
+    /// of leaves. Writes do not pay to keep counts current.
+    ///
+    /// The count reflects concurrent writes in the same way
+    /// as `Tree::range` does, so it is not a point-in-time
+    /// count if the range is written to while it is counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// for i in 0..10_u8 {
+    ///     db.insert([i], vec![])?;
+    /// }
+    /// assert_eq!(db.range_count([2_u8]..[5])?, 3);
+    /// assert_eq!(db.range_count::<&[u8], _>(..)?, 10);
+    /// # Ok(()) }
+    /// ```
+    pub fn range_count<K, R>(&self, range: R) -> Result<usize>
+    where
+        K: AsRef<[u8]>,
+        R: RangeBounds<K>,
+    {
+        // the range is turned into bounds `lo..hi` in the
+        // form of a node's, where an empty `hi` is unbounded
+        let lo = match range.start_bound() {
+            ops::Bound::Included(start) => IVec::from(start.as_ref()),
+            ops::Bound::Excluded(start) => {
+                let mut lo = start.as_ref().to_vec();
+                lo.push(0);
+                IVec::from(lo)
+            }
+            ops::Bound::Unbounded => IVec::default(),
+        };
+        let hi = match range.end_bound() {
+            ops::Bound::Included(end) => {
+                let mut hi = end.as_ref().to_vec();
+                hi.push(0);
+                IVec::from(hi)
+            }
+            ops::Bound::Excluded(end) if end.as_ref().is_empty() => {
+                return Ok(0);
+            }
+            ops::Bound::Excluded(end) => IVec::from(end.as_ref()),
+            ops::Bound::Unbounded => IVec::default(),
+        };
+
+        let generation = self.write_generation();
+        let root = self.root.load(SeqCst);
+        let (count, _) = self.count_keys(root, lo, &hi, generation)?;
+        Ok(usize::try_from(count).unwrap())
+    }
+
+    /// Counts the keys in `lo..hi`, where an empty `hi` is
+    /// unbounded, starting at the page `pid`, which should
+    /// hold `lo`. Also returns whether `pid` is an index
+    /// node, whose counts are worth keeping. See
+    /// `Tree::range_count`.
+    fn count_keys(
+        &self,
+        mut pid: PageId,
+        mut lo: IVec,
+        hi: &IVec,
+        generation: u64,
+    ) -> Result<(u64, bool)> {
+        let mut count = 0;
+        let mut is_index = None;
+
+        while hi.is_empty() || lo < *hi {
+            if pid == u64::max_value() {
+                return Err(Error::TreeDropped(self.tree_id.clone()));
+            }
+
+            // the children are collected so that the guard
+            // is not held while they are counted
+            let (children, node_hi, next) = {
+                let _cc = concurrency_control::read();
+                let guard = pin();
+
+                let view = match self.view_for_pid(pid, &guard)? {
+                    Some(view) if view.lo <= lo && !view.merging => view,
+                    _ => {
+                        // merged away since it was linked
+                        // to, so start over from the root
+                        pid = self.root.load(SeqCst);
+                        continue;
+                    }
+                };
+
+                if !view.hi.is_empty() && lo >= view.hi {
+                    // half-complete split
+                    pid = view.next.unwrap().get();
+                    continue;
+                }
+
+                let end = lower_hi(hi, &view.hi);
+                let _ = is_index.get_or_insert(view.data.is_index());
+
+                let children = match &view.data {
+                    Data::Leaf(_) => {
+                        let in_leaf = view.leaf_count_in(&lo, &end);
+                        count += u64::try_from(in_leaf).unwrap();
+                        vec![]
+                    }
+                    Data::Index(index) => {
+                        let mut children = vec![];
+                        for (idx, child) in index.pointers.iter().enumerate()
+                        {
+                            let child_lo =
+                                view.prefix_decode(&index.keys[idx]);
+                            let child_hi = match index.keys.get(idx + 1) {
+                                Some(key) => view.prefix_decode(key),
+                                None => view.hi.clone(),
+                            };
+                            let after_lo =
+                                child_hi.is_empty() || child_hi > lo;
+                            let before_end =
+                                end.is_empty() || child_lo < end;
+                            if after_lo && before_end {
+                                children.push((child_lo, child_hi, *child));
+                            }
+                        }
+                        children
+                    }
+                };
+
+                (children, view.hi.clone(), view.next)
+            };
+
+            let end = lower_hi(hi, &node_hi);
+            for (child_lo, child_hi, child) in children {
+                let sub_lo = std::cmp::max(&lo, &child_lo).clone();
+                let sub_hi = lower_hi(&end, &child_hi);
+                let whole = sub_lo == child_lo && sub_hi == child_hi;
+
+                if whole {
+                    if let Some(cached) = self.cached_subtree_count(
+                        generation, &child_lo, &child_hi,
+                    ) {
+                        count += cached;
+                        continue;
+                    }
+                }
+
+                let (in_child, child_is_index) =
+                    self.count_keys(child, sub_lo, &sub_hi, generation)?;
+                if whole && child_is_index {
+                    self.cache_subtree_count(
+                        generation, child_lo, child_hi, in_child,
+                    );
+                }
+                count += in_child;
+            }
+
+            if node_hi.is_empty() || end < node_hi {
+                break;
             }
+            lo = node_hi;
+            pid = next.unwrap().get();
         }
+
+        Ok((count, is_index.unwrap_or(false)))
     }
 
-    /// Returns the number of elements in this tree.
+    fn cached_subtree_count(
+        &self,
+        generation: u64,
+        lo: &IVec,
+        hi: &IVec,
+    ) -> Option<u64> {
+        let subtree_counts = self.subtree_counts.lock();
+        if subtree_counts.generation == generation {
+            subtree_counts.counts.get(&(lo.clone(), hi.clone())).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Remembers the number of keys in `lo..hi`, unless it
+    /// was counted at an older write generation than the
+    /// counts that are already kept, which are dropped
+    /// otherwise if they are older.
+    fn cache_subtree_count(
+        &self,
+        generation: u64,
+        lo: IVec,
+        hi: IVec,
+        count: u64,
+    ) {
+        let mut subtree_counts = self.subtree_counts.lock();
+        if subtree_counts.generation < generation {
+            subtree_counts.generation = generation;
+            subtree_counts.counts.clear();
+        }
+        if subtree_counts.generation == generation {
+            let _ = subtree_counts.counts.insert((lo, hi), count);
+        }
+    }
+
+    /// Returns `true` if the `Tree` contains no elements.
     ///
-    /// Beware: performs a full O(n) scan under the hood.
+    /// This descends to the first leaf and returns as soon
+    /// as it finds a key, so it is O(log n), unlike `len`.
+    /// Only leaves that were emptied by removals and have
+    /// not been merged yet are skipped over. Returns
+    /// `Error::TreeDropped` if the tree has been dropped,
+    /// rather than reporting it as empty.
     ///
     /// # Examples
     ///
     /// ```
     /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
-    /// let config = sled::Config::new().temporary(true);
-    /// let t = config.open()?;
-    /// t.insert(b"a", vec![0]);
-    /// t.insert(b"b", vec![1]);
-    /// assert_eq!(t.len(), 2);
+    /// let t = sled::Config::new().temporary(true).open()?;
+    /// assert!(t.is_empty()?);
+    ///
+    /// t.insert(b"a", b"1")?;
+    /// assert!(!t.is_empty()?);
+    ///
+    /// t.remove(b"a")?;
+    /// assert!(t.is_empty()?);
     /// # Ok(()) }
     /// ```
-    pub fn len(&self) -> usize {
-        self.iter().count()
-    }
+    pub fn is_empty(&self) -> Result<bool> {
+        self.check_valid()?;
+        let _cc = concurrency_control::read();
+        let guard = pin();
 
-    /// Returns `true` if the `Tree` contains no elements.
-    pub fn is_empty(&self) -> bool {
-        self.iter().next().is_none()
+        let mut key = IVec::default();
+        loop {
+            let View { node_view, .. } = self.view_for_key(&key, &guard)?;
+            if node_view.data.len() > 0 {
+                return Ok(false);
+            }
+            if node_view.hi.is_empty() {
+                return Ok(true);
+            }
+            key = node_view.hi.clone();
+        }
     }
 
     /// Clears the `Tree`, removing all values.
@@ -1410,21 +4664,236 @@ impl Tree {
         self.tree_id.clone()
     }
 
-    /// Returns the CRC32 of all keys and values
-    /// in this Tree.
+    /// Returns `false` if the tree was dropped with
+    /// `Db::drop_tree` after this handle to it was opened.
+    /// Reads and writes through such a handle fail with
+    /// `Error::TreeDropped`, even if a tree with the same name
+    /// has been opened again since, in which case
+    /// `Db::open_tree` returns a handle to the new tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let tree = db.open_tree(b"a")?;
+    /// assert!(tree.is_valid());
+    ///
+    /// db.drop_tree(b"a")?;
+    /// assert!(!tree.is_valid());
+    /// assert_eq!(tree.get(b"k"), Err(sled::Error::TreeDropped(tree.name())));
+    /// # Ok(()) }
+    /// ```
+    pub fn is_valid(&self) -> bool {
+        self.root.load(SeqCst) != u64::max_value()
+    }
+
+    /// Returns `Error::TreeDropped` if `is_valid` is false,
+    /// for operations that may not traverse the tree.
+    fn check_valid(&self) -> Result<()> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            Err(Error::TreeDropped(self.tree_id.clone()))
+        }
+    }
+
+    /// Returns a number that is bumped after every
+    /// successful write to this `Tree`. It carries the
+    /// same guarantees as `Db::write_generation`, but
+    /// is only affected by writes to this `Tree`. The
+    /// values of different trees are not comparable
+    /// to each other.
+    pub fn write_generation(&self) -> u64 {
+        self.write_generation.load(SeqCst)
+    }
+
+    /// Picks the version of a new value for `key`. Versions
+    /// are based on the write generation, which has been
+    /// bumped by every earlier write including removals,
+    /// but are always greater than the version of the value
+    /// they replace, so that they keep increasing for each
+    /// key even if the write generation restarts lower
+    /// after a crash.
+    fn next_version(&self, node: &Node, key: &[u8]) -> u64 {
+        let generation = self.context.write_generation() + 1;
+        match node.leaf_version_for_key(key) {
+            Some(current) => generation.max(current + 1),
+            None => generation,
+        }
+    }
+
+    /// Counts the log space taken by a write to one of this
+    /// tree's pages towards `Db::flush_detailed`.
+    fn count_logged(&self, new_view: &PageView<'_>) {
+        let _ = self.logged_bytes.fetch_add(new_view.last_log_size(), SeqCst);
+    }
+
+    fn bump_write_generation(&self) -> Result<()> {
+        let generation =
+            self.context.write_generation.fetch_add(1, SeqCst) + 1;
+        self.write_generation.fetch_add(1, SeqCst);
+        self.context.pagecache.persist_write_generation(generation)?;
+        self.context.snapshots.maybe_take(&self.context, generation);
+        Ok(())
+    }
+
+    /// Accounts for a completed write of `key` towards
+    /// `Config::max_db_size`. If it exceeded the limit, items
+    /// are evicted by `Context::evict_over_limit` once the
+    /// write has released its locks.
+    fn record_write(&self, key: &[u8], value_len: Option<usize>) {
+        if let Some(eviction) = &self.context.eviction {
+            eviction.record_write(&self.tree_id, key, value_len);
+        }
+    }
+
+    /// Returns a 64-bit hash of all keys and values in
+    /// this Tree. The hash only depends on the tree's
+    /// contents, and not on its name, on how it is laid
+    /// out on disk, or on the order of the writes that
+    /// produced it, so two trees that hold the same items
+    /// have the same checksum on any platform. This can
+    /// be used to cheaply check whether two replicas of
+    /// a tree have diverged.
+    ///
+    /// The checksum is the wrapping sum of a hash of each
+    /// key and value pair, so it is equal to the wrapping
+    /// sum of the checksums returned by
+    /// `Tree::checksum_ranges`. It is not cryptographically
+    /// secure.
+    ///
+    /// This is O(N) and locks the underlying tree for the
+    /// duration of the entire scan, but the result is cached
+    /// until the tree is written to again, so repeated calls
+    /// on an unchanged tree are cheap.
+    ///
+    /// # Examples
     ///
-    /// This is O(N) and locks the underlying tree
-    /// for the duration of the entire scan.
-    pub fn checksum(&self) -> Result<u32> {
-        let mut hasher = crc32fast::Hasher::new();
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let a = db.open_tree(b"a")?;
+    /// let b = db.open_tree(b"b")?;
+    ///
+    /// a.insert(b"k1", b"v1")?;
+    /// a.insert(b"k2", b"v2")?;
+    /// b.insert(b"k2", b"v2")?;
+    /// b.insert(b"k1", b"v1")?;
+    /// assert_eq!(a.checksum()?, b.checksum()?);
+    ///
+    /// b.insert(b"k1", b"v3")?;
+    /// assert_ne!(a.checksum()?, b.checksum()?);
+    /// # Ok(()) }
+    /// ```
+    pub fn checksum(&self) -> Result<u64> {
+        let _cc = concurrency_control::write();
+        self.check_valid()?;
+
+        // no writes can happen while we hold the
+        // write lock, so the generation is stable
+        let generation = self.write_generation();
+        if let Some((cached_generation, checksum)) =
+            self.checksum_cache.lock().checksum
+        {
+            if cached_generation == generation {
+                return Ok(checksum);
+            }
+        }
+
+        let mut checksum = 0_u64;
         let mut iter = self.iter();
+        while let Some(kv_res) = iter.next_resolved() {
+            let (k, v) = kv_res?;
+            checksum = checksum.wrapping_add(item_checksum(&k, &v));
+        }
+
+        self.checksum_cache.lock().checksum = Some((generation, checksum));
+
+        Ok(checksum)
+    }
+
+    /// Returns the checksums of groups of consecutive keys
+    /// that share their first `granularity` bytes, along
+    /// with that shared prefix. Keys that are shorter than
+    /// `granularity` are grouped on their whole key. Each
+    /// checksum is computed in the same way as
+    /// `Tree::checksum`, but only over the items in its
+    /// group, and empty groups are omitted.
+    ///
+    /// Because groups are determined by the keys rather
+    /// than by the tree's layout, the groups of two
+    /// replicas line up, and comparing them shows which
+    /// key prefixes have diverged. Calling this again with
+    /// a larger `granularity`, and only looking at the
+    /// groups under a diverged prefix, narrows the
+    /// divergence down further, similar to walking a
+    /// Merkle tree.
+    ///
+    /// Like `Tree::checksum`, this locks the tree for the
+    /// duration of the scan, and the result for the last
+    /// `granularity` is cached until the tree is written
+    /// to again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let db = sled::Config::new().temporary(true).open()?;
+    /// let a = db.open_tree(b"a")?;
+    /// let b = db.open_tree(b"b")?;
+    ///
+    /// for t in &[&a, &b] {
+    ///     t.insert(b"user/1", b"alice")?;
+    ///     t.insert(b"post/1", b"hello")?;
+    /// }
+    /// b.insert(b"user/2", b"bob")?;
+    ///
+    /// let diverged: Vec<_> = a
+    ///     .checksum_ranges(4)?
+    ///     .into_iter()
+    ///     .zip(b.checksum_ranges(4)?)
+    ///     .filter(|(a, b)| a != b)
+    ///     .map(|(a, _b)| a.0)
+    ///     .collect();
+    /// assert_eq!(diverged, vec![sled::IVec::from(b"user")]);
+    /// # Ok(()) }
+    /// ```
+    pub fn checksum_ranges(
+        &self,
+        granularity: usize,
+    ) -> Result<Vec<(IVec, u64)>> {
         let _cc = concurrency_control::write();
-        while let Some(kv_res) = iter.next_inner() {
+        self.check_valid()?;
+
+        let generation = self.write_generation();
+        {
+            let cache = self.checksum_cache.lock();
+            if cache.ranges_key == Some((generation, granularity)) {
+                return Ok(cache.ranges.clone());
+            }
+        }
+
+        let mut ranges: Vec<(IVec, u64)> = vec![];
+        let mut iter = self.iter();
+        while let Some(kv_res) = iter.next_resolved() {
             let (k, v) = kv_res?;
-            hasher.update(&k);
-            hasher.update(&v);
+            let checksum = item_checksum(&k, &v);
+            let prefix = &k[..granularity.min(k.len())];
+
+            match ranges.last_mut() {
+                Some((last_prefix, sum)) if last_prefix == prefix => {
+                    *sum = sum.wrapping_add(checksum);
+                }
+                _ => ranges.push((IVec::from(prefix), checksum)),
+            }
         }
-        Ok(hasher.finalize())
+
+        let mut cache = self.checksum_cache.lock();
+        cache.ranges_key = Some((generation, granularity));
+        cache.ranges = ranges.clone();
+
+        Ok(ranges)
     }
 
     fn split_node<'g>(
@@ -1441,6 +4910,7 @@ impl Tree {
 
         // install right side
         let (rhs_pid, rhs_ptr) = self.context.pagecache.allocate(rhs, guard)?;
+        self.count_logged(&rhs_ptr);
 
         // replace node, pointing next to installed right
         lhs.next = Some(NonZeroU64::new(rhs_pid).unwrap());
@@ -1451,7 +4921,9 @@ impl Tree {
             guard,
         )?;
         M.tree_child_split_attempt();
-        if replace.is_err() {
+        if let Ok(new_view) = &replace {
+            self.count_logged(new_view);
+        } else {
             // if we failed, don't follow through with the
             // parent split or root hoist.
             let _new_stack = self
@@ -1483,7 +4955,8 @@ impl Tree {
                 parent,
                 guard,
             )?;
-            if replace.is_ok() {
+            if let Ok(new_view) = &replace {
+                self.count_logged(new_view);
                 M.tree_parent_split_success();
             } else {
                 // Parent splits are an optimization
@@ -1511,6 +4984,7 @@ impl Tree {
 
         let (new_root_pid, new_root_ptr) =
             self.context.pagecache.allocate(new_root, guard)?;
+        self.count_logged(&new_root_ptr);
         debug!("allocated pid {} in root_hoist", new_root_pid);
 
         debug_delay();
@@ -1571,14 +5045,145 @@ impl Tree {
         }
     }
 
+    /// Descends from the root to a leaf, choosing each child
+    /// with `choose(n)`, which returns a number in `0..n`.
+    /// Returns the product of the numbers of children on the
+    /// way, the weight that the leaf gets in an estimate of
+    /// the whole tree, along with the keys of the leaf and
+    /// the lengths of their values. See
+    /// `Db::space_report_sampled`.
+    pub(crate) fn sample_leaf<F>(
+        &self,
+        mut choose: F,
+    ) -> Result<(u64, Vec<(IVec, u64)>)>
+    where
+        F: FnMut(usize) -> usize,
+    {
+        let _cc = concurrency_control::read();
+        let guard = pin();
+
+        'retry: loop {
+            let mut cursor = self.root.load(SeqCst);
+            let mut weight = 1_u64;
+
+            loop {
+                if cursor == u64::max_value() {
+                    return Err(Error::TreeDropped(self.tree_id.clone()));
+                }
+
+                let view = if let Some(view) =
+                    self.view_for_pid(cursor, &guard)?
+                {
+                    view
+                } else {
+                    continue 'retry;
+                };
+
+                match &view.data {
+                    Data::Index(index) => {
+                        let children = index.pointers.len();
+                        weight = weight
+                            .saturating_mul(u64::try_from(children).unwrap());
+                        cursor = index.pointers[choose(children)];
+                    }
+                    Data::Leaf(leaf) => {
+                        let mut items = Vec::with_capacity(leaf.keys.len());
+                        for (key, stored) in leaf.keys.iter().zip(&leaf.values)
+                        {
+                            let value = self.resolve(Some(stored.clone()))?;
+                            let value_len = value.map_or(0, |v| v.len());
+                            items.push((
+                                view.prefix_decode(key),
+                                u64::try_from(value_len).unwrap(),
+                            ));
+                        }
+                        return Ok((weight, items));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Walks every node of the tree, level by level, for
+    /// `Db::structure_report`.
+    #[cfg(feature = "debug")]
+    pub(crate) fn structure(&self, name: IVec) -> Result<TreeStructure> {
+        use std::cmp::{max, min};
+
+        let _cc = concurrency_control::read();
+        let guard = pin();
+
+        let root = self.root.load(SeqCst);
+        if root == u64::max_value() {
+            return Err(Error::TreeDropped(self.tree_id.clone()));
+        }
+
+        let mut ret = TreeStructure {
+            name,
+            height: 0,
+            nodes_per_level: vec![],
+            average_fan_out: 0.,
+            leaf_keys: 0,
+            min_leaf_keys: u64::max_value(),
+            max_leaf_keys: 0,
+        };
+        let mut index_nodes = 0_u64;
+        let mut children = 0_u64;
+
+        // each level is walked along the links between
+        // siblings, which also reach the right halves of
+        // splits that are not linked into their parents yet
+        let mut level_start = Some(root);
+        while let Some(start) = level_start.take() {
+            let mut nodes = 0;
+            let mut cursor = Some(start);
+            while let Some(pid) = cursor {
+                let view_opt = self.view_for_pid(pid, &guard)?;
+                let view = if let Some(view) = view_opt {
+                    view
+                } else {
+                    // merged away since it was linked to
+                    break;
+                };
+                nodes += 1;
+                match &view.data {
+                    Data::Index(index) => {
+                        index_nodes += 1;
+                        let fan_out = index.pointers.len();
+                        children += u64::try_from(fan_out).unwrap();
+                        if level_start.is_none() {
+                            level_start = Some(index.pointers[0]);
+                        }
+                    }
+                    Data::Leaf(leaf) => {
+                        let keys = u64::try_from(leaf.keys.len()).unwrap();
+                        ret.leaf_keys += keys;
+                        ret.min_leaf_keys = min(ret.min_leaf_keys, keys);
+                        ret.max_leaf_keys = max(ret.max_leaf_keys, keys);
+                    }
+                }
+                cursor = view.next.map(NonZeroU64::get);
+            }
+            ret.nodes_per_level.push(nodes);
+        }
+
+        ret.height = ret.nodes_per_level.len();
+        if ret.min_leaf_keys == u64::max_value() {
+            ret.min_leaf_keys = 0;
+        }
+        if index_nodes > 0 {
+            #[allow(clippy::cast_precision_loss)]
+            #[allow(clippy::float_arithmetic)]
+            {
+                ret.average_fan_out = children as f64 / index_nodes as f64;
+            }
+        }
+
+        Ok(ret)
+    }
+
     // Returns the traversal path, completing any observed
     // partially complete splits or merges along the way.
-    //
-    // We intentionally leave the cyclometric complexity
-    // high because attempts to split it up have made
-    // the inherent complexity of the operation more
-    // challenging to understand.
-    #[allow(clippy::cognitive_complexity)]
     pub(crate) fn view_for_key<'g, K>(
         &self,
         key: K,
@@ -1587,6 +5192,24 @@ impl Tree {
     where
         K: AsRef<[u8]>,
     {
+        self.traverse(key.as_ref(), false, guard)
+    }
+
+    // Like `view_for_key`, but when `compacting`, nodes on the
+    // path are also merged into their left siblings whenever
+    // they fit, for `Tree::compact_range`.
+    //
+    // We intentionally leave the cyclometric complexity
+    // high because attempts to split it up have made
+    // the inherent complexity of the operation more
+    // challenging to understand.
+    #[allow(clippy::cognitive_complexity)]
+    fn traverse<'g>(
+        &self,
+        key: &[u8],
+        compacting: bool,
+        guard: &'g Guard,
+    ) -> Result<View<'g>> {
         #[cfg(any(test, feature = "lock_free_delays"))]
         const MAX_LOOPS: usize = usize::max_value();
 
@@ -1620,7 +5243,7 @@ impl Tree {
         for _ in 0..MAX_LOOPS {
             if cursor == u64::max_value() {
                 // this collection has been explicitly removed
-                return Err(Error::CollectionNotFound(self.tree_id.clone()));
+                return Err(Error::TreeDropped(self.tree_id.clone()));
             }
 
             let node_opt = self.view_for_pid(cursor, guard)?;
@@ -1641,9 +5264,9 @@ impl Tree {
                 retry!();
             }
 
-            let overshot = key.as_ref() < view.lo.as_ref();
+            let overshot = key < view.lo.as_ref();
             let undershot =
-                key.as_ref() >= view.hi.as_ref() && !view.hi.is_empty();
+                key >= view.hi.as_ref() && !view.hi.is_empty();
 
             if overshot {
                 // merge interfered, reload root and retry
@@ -1700,7 +5323,8 @@ impl Tree {
                     parent,
                     guard,
                 )?;
-                if replace.is_ok() {
+                if let Ok(new_view) = &replace {
+                    self.count_logged(new_view);
                     M.tree_parent_split_success();
                 }
             }
@@ -1712,7 +5336,12 @@ impl Tree {
             // would be merged into a different index, which
             // would add considerable complexity to this already
             // fairly complex implementation.
-            if view.should_merge() && !took_leftmost_branch {
+            let mergeable = view.should_merge()
+                || (compacting
+                    && !took_leftmost_branch
+                    && self.should_compact(key, &view, &parent_view, guard)?);
+
+            if mergeable && !took_leftmost_branch {
                 if let Some(ref mut parent) = parent_view {
                     assert!(parent.merging_child.is_none());
                     if parent.can_merge_child() {
@@ -1726,6 +5355,7 @@ impl Tree {
                         )?;
 
                         if let Ok(new_parent_ptr) = link {
+                            self.count_logged(&new_parent_ptr);
                             parent.node_view = NodeView(new_parent_ptr);
                             self.merge_node(parent, cursor, guard)?;
                             retry!();
@@ -1735,7 +5365,7 @@ impl Tree {
             }
 
             if view.data.is_index() {
-                let next = view.index_next_node(key.as_ref());
+                let next = view.index_next_node(key);
                 took_leftmost_branch = next.0 == 0;
                 parent_view = Some(view);
                 cursor = next.1;
@@ -1747,10 +5377,39 @@ impl Tree {
         panic!(
             "cannot find pid {} in view_for_key, looking for key {:?} in tree",
             cursor,
-            key.as_ref(),
+            key,
         );
     }
 
+    // Whether `view`, which was reached from `parent_view` on
+    // the way to `key`, fits into its left sibling, which is
+    // loaded to find out.
+    fn should_compact(
+        &self,
+        key: &[u8],
+        view: &View<'_>,
+        parent_view: &Option<View<'_>>,
+        guard: &Guard,
+    ) -> Result<bool> {
+        let parent = if let Some(parent) = parent_view {
+            parent
+        } else {
+            return Ok(false);
+        };
+
+        let (child_index, _) = parent.index_next_node(key);
+        if child_index == 0 {
+            return Ok(false);
+        }
+
+        let index = parent.data.index_ref().unwrap();
+        let left_pid = index.pointers[child_index - 1];
+        Ok(match self.view_for_pid(left_pid, guard)? {
+            Some(left) => view.should_compact_into(&left),
+            None => false,
+        })
+    }
+
     fn cap_merging_child<'g>(
         &'g self,
         child_pid: PageId,
@@ -1783,6 +5442,7 @@ impl Tree {
             match install_frag {
                 Ok(new_ptr) => {
                     trace!("child pid {} merge capped", child_pid);
+                    self.count_logged(&new_ptr);
                     child_view.node_view = NodeView(new_ptr);
                     return Ok(Some(child_view));
                 }
@@ -1816,7 +5476,8 @@ impl Tree {
                 guard,
             )?;
             match linked {
-                Ok(_) => {
+                Ok(new_view) => {
+                    self.count_logged(&new_view);
                     trace!(
                         "ParentMergeConfirm succeeded on parent pid {}, \
                          now freeing child pid {}",
@@ -1960,7 +5621,8 @@ impl Tree {
                     guard,
                 )?;
                 match replace {
-                    Ok(_) => {
+                    Ok(new_view) => {
+                        self.count_logged(&new_view);
                         trace!(
                             "merged node pid {} into left sibling pid {}",
                             child_pid,
@@ -2067,12 +5729,14 @@ impl Tree {
 
     // Remove all pages for this tree from the underlying
     // PageCache. This will leave orphans behind if
-    // the tree crashes during gc.
+    // the tree crashes during gc. Returns the number of
+    // keys and the logged bytes of the pages that were freed.
     pub(crate) fn gc_pages(
         &self,
         mut leftmost_chain: Vec<PageId>,
-    ) -> Result<()> {
+    ) -> Result<DroppedTreeInfo> {
         let guard = pin();
+        let mut info = DroppedTreeInfo::default();
 
         while let Some(mut pid) = leftmost_chain.pop() {
             loop {
@@ -2091,6 +5755,12 @@ impl Tree {
                 )?;
 
                 if ret.is_ok() {
+                    info.approx_bytes_reclaimed += cursor_view.size;
+                    if let Some(leaf) = cursor_view.data.leaf_ref() {
+                        info.key_count +=
+                            u64::try_from(leaf.keys.len()).unwrap();
+                    }
+
                     let next_pid = if let Some(next_pid) = cursor_view.next {
                         next_pid
                     } else {
@@ -2102,7 +5772,7 @@ impl Tree {
             }
         }
 
-        Ok(())
+        Ok(info)
     }
 }
 
@@ -2176,6 +5846,79 @@ impl Debug for Tree {
     }
 }
 
+/// Returns the lower of two upper bounds, where an empty
+/// bound is unbounded, like the `hi` of a node.
+fn lower_hi(a: &IVec, b: &IVec) -> IVec {
+    if a.is_empty() || (!b.is_empty() && b < a) {
+        b.clone()
+    } else {
+        a.clone()
+    }
+}
+
+/// Whether a page that starts at `page_lo` holds no keys
+/// below `hi`, the end of a range.
+fn past_range(hi: &ops::Bound<IVec>, page_lo: &IVec) -> bool {
+    match hi {
+        ops::Bound::Unbounded => page_lo.is_empty(),
+        ops::Bound::Included(end) => page_lo.is_empty() || page_lo > end,
+        ops::Bound::Excluded(end) => page_lo.is_empty() || page_lo >= end,
+    }
+}
+
+/// Hashes a single key and value for `Tree::checksum`.
+/// This must never change, because checksums are compared
+/// across processes and versions. It length-prefixes the
+/// key and value so that moving bytes between them changes
+/// the hash, runs 64-bit FNV-1a over them, and finishes
+/// with the `splitmix64` mixer so that summing the results
+/// of similar items does not cancel out.
+fn item_checksum(key: &[u8], value: &[u8]) -> u64 {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    let key_len = u64::try_from(key.len()).unwrap().to_le_bytes();
+    let value_len = u64::try_from(value.len()).unwrap().to_le_bytes();
+
+    let mut hash = FNV_OFFSET;
+    for part in &[&key_len[..], key, &value_len[..], value] {
+        for byte in *part {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Returns the bytes of a value that should hold an 8-byte
+/// number, naming what it should be in the error otherwise.
+fn eight_bytes(key: &[u8], value: &IVec, kind: &str) -> Result<[u8; 8]> {
+    if value.len() != 8 {
+        return Err(Error::Unsupported(format!(
+            "the value of {:?} is {} bytes long, so it is not {}",
+            IVec::from(key),
+            value.len(),
+            kind
+        )));
+    }
+    let mut array = [0; 8];
+    array.copy_from_slice(value);
+    Ok(array)
+}
+
+/// Reads a counter written by `Tree::increment`, where an
+/// absent value counts as 0.
+fn decode_counter(key: &[u8], value: Option<&IVec>) -> Result<i64> {
+    if let Some(bytes) = value {
+        Ok(i64::from_le_bytes(eight_bytes(key, bytes, "a counter")?))
+    } else {
+        Ok(0)
+    }
+}
+
 /// Compare and swap result.
 ///
 /// It returns `Ok(Ok(()))` if operation finishes successfully and
@@ -2202,6 +5945,42 @@ pub struct CompareAndSwapError {
     pub proposed: Option<IVec>,
 }
 
+impl CompareAndSwapError {
+    /// Consumes the error, returning the current value
+    /// that caused the CAS to fail without cloning it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let config = sled::Config::new().temporary(true);
+    /// let t = config.open()?;
+    /// t.insert(b"k", b"v1")?;
+    ///
+    /// let cas_error =
+    ///     t.compare_and_swap(b"k", Some(b"v0"), Some(b"v2"))?.unwrap_err();
+    /// assert_eq!(cas_error.proposed, Some(sled::IVec::from(b"v2")));
+    /// assert_eq!(cas_error.into_current(), Some(sled::IVec::from(b"v1")));
+    /// # Ok(()) }
+    /// ```
+    pub fn into_current(self) -> Option<IVec> {
+        self.current
+    }
+
+    /// Consumes the error, returning the value that was
+    /// proposed unsuccessfully without cloning it.
+    pub fn into_proposed(self) -> Option<IVec> {
+        self.proposed
+    }
+
+    /// Consumes the error, returning both the current
+    /// value and the value that was proposed unsuccessfully,
+    /// in that order.
+    pub fn into_parts(self) -> (Option<IVec>, Option<IVec>) {
+        (self.current, self.proposed)
+    }
+}
+
 impl fmt::Display for CompareAndSwapError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Compare and swap conflict")
@@ -2209,3 +5988,120 @@ impl fmt::Display for CompareAndSwapError {
 }
 
 impl std::error::Error for CompareAndSwapError {}
+
+/// The state of a key that failed the condition of a
+/// compare and swap, see `Tree::cas_inner`.
+struct CasConflict {
+    current: Option<IVec>,
+    version: Option<u64>,
+    proposed: Option<IVec>,
+}
+
+/// An opaque token identifying one write of a key's value,
+/// returned by `Tree::get_versioned` and checked by
+/// `Tree::compare_and_swap_version`.
+///
+/// Every write of a key gives it a new version that is
+/// greater than the previous one, even if the same bytes
+/// are written again, and versions are persisted along
+/// with the values so they remain valid across restarts.
+/// A key that is removed and inserted again gets a new
+/// version as well. Versions of different keys are not
+/// related to each other.
+///
+/// Only the current version of each key is kept. Writes
+/// replace the previous value in the key's page rather than
+/// being chained onto it, and a removal leaves no tombstone
+/// behind, so older versions can't be read back. To look at
+/// the trees as they were at an earlier point, keep an image
+/// from `Db::to_bytes` and open it with
+/// `Db::open_from_bytes`, or roll back to a snapshot with
+/// `Db::restore_snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(u64);
+
+/// Whether a value returned by `Tree::get_with_durability`
+/// would survive a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Durability {
+    /// The value has been flushed to disk.
+    Durable,
+    /// The value may not have been flushed to disk yet.
+    /// It will be after the next successful `Tree::flush`,
+    /// or after the background flusher catches up.
+    Pending,
+}
+
+/// The error returned by `Tree::compare_and_swap_version`
+/// when the key's version did not match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VersionMismatch {
+    /// The current version of the key, or `None` if it is
+    /// absent.
+    pub current: Option<Version>,
+}
+
+impl fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compare and swap version conflict")
+    }
+}
+
+impl std::error::Error for VersionMismatch {}
+
+/// A single condition and write for
+/// `Tree::compare_and_swap_multi` and
+/// `Tree::conditional_batch`. The `new` value is
+/// only written if the key's current value is `expected`,
+/// where `None` means that the key is absent or is to be
+/// removed.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CasOp {
+    /// The key to compare and set.
+    pub key: IVec,
+    /// The value that the key must currently have.
+    pub expected: Option<IVec>,
+    /// The value to set if every condition holds.
+    pub new: Option<IVec>,
+}
+
+impl CasOp {
+    /// Creates a new `CasOp`.
+    pub fn new<K, OV, NV>(
+        key: K,
+        expected: Option<OV>,
+        new: Option<NV>,
+    ) -> CasOp
+    where
+        K: AsRef<[u8]>,
+        IVec: From<OV> + From<NV>,
+    {
+        CasOp {
+            key: IVec::from(key.as_ref()),
+            expected: expected.map(IVec::from),
+            new: new.map(IVec::from),
+        }
+    }
+}
+
+/// The error returned by `Tree::compare_and_swap_multi`
+/// when one of the conditions does not hold, and for each
+/// condition that does not hold by
+/// `Tree::conditional_batch`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MultiCasError {
+    /// The key whose current value did not match the
+    /// expected value. For `Tree::compare_and_swap_multi`,
+    /// this is the first such key.
+    pub key: IVec,
+    /// The current value of `key`.
+    pub current: Option<IVec>,
+}
+
+impl fmt::Display for MultiCasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compare and swap conflict on key {:?}", self.key)
+    }
+}
+
+impl std::error::Error for MultiCasError {}
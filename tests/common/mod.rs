@@ -44,3 +44,41 @@ pub fn cleanup(dir: &str) {
         std::fs::remove_dir_all(dir).unwrap();
     }
 }
+
+/// Polls `future` to completion, parking the calling thread
+/// until the future's waker is woken.
+#[allow(dead_code)]
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+    use std::thread::{self, Thread};
+
+    static VTABLE: RawWakerVTable =
+        RawWakerVTable::new(clone, wake, wake_by_ref, drop_waker);
+
+    fn clone(thread: *const ()) -> RawWaker {
+        let thread = unsafe { &*(thread as *const Thread) };
+        let boxed = Box::into_raw(Box::new(thread.clone()));
+        RawWaker::new(boxed as *const (), &VTABLE)
+    }
+    fn wake(thread: *const ()) {
+        unsafe { Box::from_raw(thread as *mut Thread) }.unpark();
+    }
+    fn wake_by_ref(thread: *const ()) {
+        unsafe { &*(thread as *const Thread) }.unpark();
+    }
+    fn drop_waker(thread: *const ()) {
+        drop(unsafe { Box::from_raw(thread as *mut Thread) });
+    }
+
+    let current = thread::current();
+    let raw = clone(&current as *const Thread as *const ());
+    let waker = unsafe { Waker::from_raw(raw) };
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
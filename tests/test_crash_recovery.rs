@@ -2,7 +2,7 @@ mod common;
 
 use std::env::{self, VarError};
 use std::mem::size_of;
-use std::process::{exit, Child, Command, ExitStatus};
+use std::process::{exit, Child, Command, ExitStatus, Stdio};
 use std::thread;
 use std::time::Duration;
 
@@ -21,6 +21,14 @@ const SEGMENT_SIZE: usize = 1024;
 // test names, also used as dir names
 const RECOVERY_NO_SNAPSHOT: &str = "crash_recovery_no_runtime_snapshot";
 const BATCHES_NO_SNAPSHOT: &str = "crash_batches_no_runtime_snapshot";
+const FLUSH_KEY_NO_FLUSHER: &str = "crash_flush_key_no_flusher";
+
+// the key whose durability is tested by FLUSH_KEY_NO_FLUSHER
+const MARKER: &[u8] = b"marker";
+
+// BATCHES_NO_SNAPSHOT splits each batch between the
+// default tree and this one
+const SECOND_BATCH_TREE: &[u8] = b"second batch tree";
 
 fn main() {
     common::setup_logger();
@@ -29,10 +37,12 @@ fn main() {
         Err(VarError::NotPresent) => {
             test_crash_recovery();
             test_crash_batches();
+            test_crash_flush_key();
         }
 
         Ok(ref s) if s == RECOVERY_NO_SNAPSHOT => run(s),
         Ok(ref s) if s == BATCHES_NO_SNAPSHOT => run_batches(s),
+        Ok(ref s) if s == FLUSH_KEY_NO_FLUSHER => run_flush_key(s),
 
         Ok(_) | Err(_) => panic!("invalid crash test case"),
     }
@@ -149,16 +159,26 @@ fn run_inner(config: Config) {
     }
 }
 
-/// Verifies that the keys in the tree are correctly recovered (i.e., equal).
+/// Verifies that the keys in both batch trees are correctly recovered (i.e., equal).
 /// Panics if they are incorrect.
-fn verify_batches(tree: &sled::Tree) -> u32 {
-    let mut iter = tree.iter();
+fn verify_batches(db: &sled::Db) -> u32 {
+    let second = db.open_tree(SECOND_BATCH_TREE).unwrap();
+    let tree_for = |key| if key < BATCH_SIZE / 2 { &**db } else { &second };
+    let mut iter = db.iter();
     let first_value = match iter.next() {
         Some(Ok((_k, v))) => slice_to_u32(&*v),
         Some(Err(e)) => panic!("{:?}", e),
-        None => return 0,
+        None => {
+            assert!(
+                second.is_empty().unwrap(),
+                "default tree is empty but {:?} is not",
+                second
+            );
+            return 0;
+        }
     };
     for key in 0..BATCH_SIZE {
+        let tree = tree_for(key);
         let res = tree.get(u32_to_vec(key));
         let option = res.unwrap();
         let v = match option {
@@ -179,15 +199,21 @@ fn verify_batches(tree: &sled::Tree) -> u32 {
     first_value
 }
 
+fn batch_tree(key: u32) -> &'static [u8] {
+    if key < BATCH_SIZE / 2 { b"__sled__default" } else { SECOND_BATCH_TREE }
+}
+
 fn run_batches_inner(db: sled::Db) {
     fn do_batch(i: u32, db: &sled::Db) {
         let mut rng = rand::thread_rng();
         let base_value = u32_to_vec(i);
 
-        let mut batch = sled::Batch::default();
+        // half of each batch goes to a second tree, which
+        // must be recovered atomically with the default tree
+        let mut batch = sled::MultiTreeBatch::default();
         if rng.gen_bool(0.1) {
             for key in 0..BATCH_SIZE {
-                batch.remove(u32_to_vec(key));
+                batch.remove(batch_tree(key), u32_to_vec(key));
             }
         } else {
             for key in 0..BATCH_SIZE {
@@ -195,7 +221,7 @@ fn run_batches_inner(db: sled::Db) {
                 let additional_len = rng.gen_range(0, SEGMENT_SIZE / 3);
                 value.append(&mut vec![0u8; additional_len]);
 
-                batch.insert(u32_to_vec(key), value);
+                batch.insert(batch_tree(key), u32_to_vec(key), value);
             }
         }
         db.apply_batch(batch).unwrap();
@@ -259,6 +285,39 @@ fn run_batches(dir: &str) {
     }
 }
 
+fn flush_key_config(dir: &str) -> Config {
+    Config::new()
+        .cache_capacity(128 * 1024 * 1024)
+        .flush_every_ms(None)
+        .path(dir)
+        .segment_size(SEGMENT_SIZE)
+}
+
+/// Writes unrelated keys along with the marker, and prints
+/// each value of the marker once `flush_key` returns, which
+/// the parent checks against what was recovered.
+fn run_flush_key(dir: &str) {
+    let db = flush_key_config(dir).open().unwrap();
+    let other = db.open_tree(b"other").unwrap();
+
+    let mut i = db.get(MARKER).unwrap().map_or(0, |v| slice_to_u32(&v));
+
+    spawn_killah();
+
+    loop {
+        i += 1;
+
+        let mut value = u32_to_vec(i);
+        let additional_len = rand::thread_rng().gen_range(0, SEGMENT_SIZE / 3);
+        value.append(&mut vec![0u8; additional_len]);
+        other.insert(u32_to_vec(i % CYCLE as u32), value).unwrap();
+
+        db.insert(MARKER, u32_to_vec(i)).unwrap();
+        db.flush_key(MARKER).unwrap();
+        println!("{}", i);
+    }
+}
+
 fn run_child_process(test_name: &str) -> Child {
     let bin = env::current_exe().expect("could not get test binary path");
 
@@ -319,3 +378,37 @@ fn test_crash_batches() {
 
     cleanup(dir);
 }
+
+fn test_crash_flush_key() {
+    let dir = FLUSH_KEY_NO_FLUSHER;
+    cleanup(dir);
+
+    for _ in 0..N_TESTS {
+        let bin = env::current_exe().expect("could not get test binary path");
+        let output = Command::new(bin)
+            .env(TEST_ENV_VAR, dir)
+            .stdout(Stdio::piped())
+            .output()
+            .map_err(|e| handle_child_wait_err(dir, e))
+            .unwrap();
+
+        handle_child_exit_status(dir, output.status);
+
+        // the last value printed was durable before the crash
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let acknowledged: u32 =
+            stdout.lines().last().map_or(0, |line| line.parse().unwrap());
+
+        let db = flush_key_config(dir).open().unwrap();
+        let recovered =
+            db.get(MARKER).unwrap().map_or(0, |v| slice_to_u32(&v));
+        assert!(
+            recovered >= acknowledged,
+            "marker {} was flushed before the crash, but {} was recovered",
+            acknowledged,
+            recovered
+        );
+    }
+
+    cleanup(dir);
+}
@@ -502,6 +502,291 @@ fn batch_outside_of_transaction() -> TransactionResult<()> {
     Ok(())
 }
 
+#[test]
+fn batch_mixed_with_transaction_writes() -> TransactionResult<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open().unwrap();
+
+    let t1 = db.open_tree(b"1")?;
+    let t2 = db.open_tree(b"2")?;
+    t1.insert(b"k0", b"v0")?;
+
+    let mut b1 = Batch::default();
+    b1.insert(b"k1", b"v1");
+    b1.insert(b"k2", b"v2");
+    b1.remove(b"k0");
+
+    (&t1, &t2).transaction(|(tx1, tx2)| {
+        tx1.insert(b"k1", b"before")?;
+        tx1.apply_batch(&b1)?;
+        assert_eq!(tx1.get(b"k0")?, None);
+        assert_eq!(tx1.get(b"k1")?, Some(b"v1".into()));
+        tx1.insert(b"k2", b"after")?;
+        tx2.insert(b"k", b"v")?;
+        Ok(())
+    })?;
+
+    assert_eq!(t1.get(b"k0")?, None);
+    assert_eq!(t1.get(b"k1")?, Some(b"v1".into()));
+    assert_eq!(t1.get(b"k2")?, Some(b"after".into()));
+    assert_eq!(t2.get(b"k")?, Some(b"v".into()));
+
+    let mut b2 = Batch::default();
+    b2.insert(b"k3", b"v3");
+    let res: TransactionResult<(), ()> = (&t1, &t2).transaction(|(tx1, tx2)| {
+        tx1.apply_batch(&b2)?;
+        tx2.remove(b"k")?;
+        abort(())
+    });
+    assert_eq!(res, Err(TransactionError::Abort(())));
+    assert_eq!(t1.get(b"k3")?, None);
+    assert_eq!(t2.get(b"k")?, Some(b"v".into()));
+
+    Ok(())
+}
+
+#[test]
+fn multi_tree_batch() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .max_value_size(1024);
+    let db = config.open()?;
+
+    let t1 = db.open_tree(b"1")?;
+    t1.insert(b"k0", b"v0")?;
+    let mut s1 = t1.watch_prefix(vec![]);
+    let mut s_default = db.watch_prefix(vec![]);
+
+    let mut batch = MultiTreeBatch::default();
+    batch.insert(&b"1"[..], &b"k1"[..], &b"v1"[..]);
+    batch.remove(&b"1"[..], &b"k0"[..]);
+    batch.insert(&b"2"[..], &b"k2"[..], &b"v2"[..]);
+    batch.insert(&b"__sled__default"[..], &b"k"[..], &b"v"[..]);
+    db.apply_batch(batch)?;
+
+    // tree "2" was created by the batch
+    let t2 = db.open_tree(b"2")?;
+    assert_eq!(t1.get(b"k0")?, None);
+    assert_eq!(t1.get(b"k1")?, Some(b"v1".into()));
+    assert_eq!(t2.get(b"k2")?, Some(b"v2".into()));
+    assert_eq!(db.get(b"k")?, Some(b"v".into()));
+
+    let mut events: Vec<Event> = (0..2).map(|_| s1.next().unwrap()).collect();
+    events.sort_by(|a, b| a.key().cmp(b.key()));
+    match &events[..] {
+        [Event::Remove { key: k0, .. }, Event::Insert { key: k1, .. }] => {
+            assert_eq!((&**k0, &**k1), (&b"k0"[..], &b"k1"[..]));
+        }
+        other => panic!("unexpected events {:?}", other),
+    }
+    assert_eq!(s_default.next().unwrap().key(), b"k");
+
+    // a plain batch still applies to the default tree
+    let mut plain = Batch::default();
+    plain.insert(b"k", b"plain");
+    db.apply_batch(plain)?;
+    assert_eq!(db.get(b"k")?, Some(b"plain".into()));
+
+    // oversized items are refused before anything is written
+    let mut too_big = MultiTreeBatch::default();
+    too_big.insert(&b"1"[..], &b"k3"[..], &b"v3"[..]);
+    too_big.insert(&b"2"[..], &b"k3"[..], vec![0; 2048]);
+    assert!(db.apply_batch(too_big).is_err());
+    assert_eq!(t1.get(b"k3")?, None);
+
+    db.flush()?;
+    drop(s1);
+    drop(s_default);
+    drop(t1);
+    drop(t2);
+    drop(db);
+
+    let db = config.open()?;
+    let t1 = db.open_tree(b"1")?;
+    let t2 = db.open_tree(b"2")?;
+    assert_eq!(t1.get(b"k1")?, Some(b"v1".into()));
+    assert_eq!(t2.get(b"k2")?, Some(b"v2".into()));
+    assert_eq!(db.get(b"k")?, Some(b"plain".into()));
+
+    Ok(())
+}
+
+#[test]
+fn transaction_assert_range_unchanged() -> TransactionResult<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open().unwrap();
+
+    let users = db.open_tree(b"users")?;
+    let index = db.open_tree(b"index")?;
+    users.insert(b"u1", b"alice")?;
+    index.insert(b"alice/u1", b"")?;
+
+    // the transaction's own writes within the range are not
+    // changes, and nothing else is writing, so it runs once
+    let runs = AtomicUsize::new(0);
+    (&users, &index).transaction(|(users, index)| {
+        runs.fetch_add(1, SeqCst);
+        index.assert_range_unchanged::<&[u8], _>(b"bob/".as_ref()..b"bob0")?;
+        users.assert_range_unchanged::<&[u8], _>(..)?;
+        index.insert(b"bob/u2", b"")?;
+        users.insert(b"u2", b"bob")?;
+        index.assert_range_unchanged::<&[u8], _>(b"bob/".as_ref()..b"bob0")?;
+        Ok(())
+    })?;
+    assert_eq!(runs.load(SeqCst), 1);
+    assert_eq!(index.len(), 2);
+    assert_eq!(users.get(b"u2")?, Some(b"bob".into()));
+
+    // aborted transactions discard their assertions with
+    // the rest of their state
+    let res: TransactionResult<(), ()> = users.transaction(|users| {
+        users.assert_range_unchanged::<&[u8], _>(..)?;
+        abort(())
+    });
+    assert_eq!(res, Err(TransactionError::Abort(())));
+
+    // writers racing with transactions that assert a range
+    // never make them fail
+    const N_WRITERS: usize = 4;
+    const N_TRANSACTIONS: usize = 4;
+    let barrier = Arc::new(Barrier::new(N_WRITERS + N_TRANSACTIONS));
+    let mut threads = vec![];
+    for t in 0..N_WRITERS {
+        let index = index.clone();
+        let barrier = barrier.clone();
+        threads.push(thread::spawn(move || -> TransactionResult<()> {
+            barrier.wait();
+            for i in 0..100_u8 {
+                index.insert([b'c', t as u8, i], b"")?;
+            }
+            Ok(())
+        }));
+    }
+    for t in 0..N_TRANSACTIONS {
+        let index = index.clone();
+        let barrier = barrier.clone();
+        threads.push(thread::spawn(move || -> TransactionResult<()> {
+            barrier.wait();
+            for i in 0..100_u8 {
+                index.transaction(|index| {
+                    index.assert_range_unchanged::<&[u8], _>(
+                        b"c".as_ref()..b"d",
+                    )?;
+                    index.insert(&[b'd', t as u8, i], b"")?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+    assert_eq!(index.len(), 2 + (N_WRITERS + N_TRANSACTIONS) * 100);
+
+    Ok(())
+}
+
+#[test]
+fn transaction_range() -> TransactionResult<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open().unwrap();
+
+    for i in 0..10_u8 {
+        db.insert([1, i], vec![i])?;
+    }
+    db.insert([0xFF, 0xFF], vec![])?;
+    db.insert([0xFF, 0xFF, 0], vec![])?;
+
+    let seen = db.transaction(|tx| {
+        tx.remove(&[1, 0])?;
+        tx.remove(&[1, 5])?;
+        tx.insert(&[1, 5, 0], vec![50])?;
+        tx.insert(&[1, 9], vec![90])?;
+        tx.insert(&[1, 10], vec![100])?;
+        tx.insert(&[2], vec![])?;
+
+        let start: &[u8] = &[1];
+        let end: &[u8] = &[1, 10];
+        let mut seen = vec![];
+        for item in tx.range(start..=end)? {
+            let (k, v) = item?;
+            // writes after the scan started are not visible
+            tx.insert(&[1, 4], vec![40])?;
+            seen.push((k.to_vec(), v.to_vec()));
+        }
+
+        assert_eq!(tx.range::<&[u8], _>(..)?.count(), 13);
+        assert_eq!(tx.scan_prefix([0xFF, 0xFF])?.count(), 2);
+        assert_eq!(tx.scan_prefix([1, 5])?.count(), 1);
+        assert_eq!(tx.scan_prefix([3])?.count(), 0);
+        Ok(seen)
+    })?;
+
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = vec![
+        (vec![1, 1], vec![1]),
+        (vec![1, 2], vec![2]),
+        (vec![1, 3], vec![3]),
+        (vec![1, 4], vec![4]),
+        (vec![1, 5, 0], vec![50]),
+        (vec![1, 6], vec![6]),
+        (vec![1, 7], vec![7]),
+        (vec![1, 8], vec![8]),
+        (vec![1, 9], vec![90]),
+        (vec![1, 10], vec![100]),
+    ];
+    assert_eq!(seen, expected);
+    assert_eq!(db.get([1, 4])?, Some(IVec::from(vec![40])));
+
+    // scanning to pick the next free sequence number never
+    // hands out the same number twice
+    const N_THREADS: usize = 4;
+    const N_ITEMS: u8 = 25;
+    let queue = db.open_tree(b"queue")?;
+    let barrier = Arc::new(Barrier::new(N_THREADS));
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|t| {
+            let queue = queue.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || -> TransactionResult<()> {
+                barrier.wait();
+                for _ in 0..N_ITEMS {
+                    queue.transaction(|tx| {
+                        let next = tx.scan_prefix(b"job/")?.count();
+                        let key = format!("job/{:03}", next);
+                        let old = tx.insert(key.as_bytes(), &[t as u8])?;
+                        assert!(old.is_none());
+                        Ok(())
+                    })?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+    let keys: Vec<IVec> =
+        queue.iter().keys().collect::<Result<_>>().unwrap();
+    let expected: Vec<IVec> = (0..N_THREADS * usize::from(N_ITEMS))
+        .map(|i| IVec::from(format!("job/{:03}", i).as_bytes()))
+        .collect();
+    assert_eq!(keys, expected);
+
+    Ok(())
+}
+
 #[test]
 fn tree_subdir() {
     let mut parent_path = std::env::temp_dir();
@@ -620,6 +905,203 @@ fn tree_big_keys_iterator() {
     assert_eq!(tree_scan.next(), None);
 }
 
+#[test]
+fn subscriber_events_carry_previous_value() -> Result<()> {
+    fn concatenate(
+        _k: &[u8],
+        old: Option<&[u8]>,
+        new: &[u8],
+    ) -> Option<Vec<u8>> {
+        let mut ret = old.map(<[u8]>::to_vec).unwrap_or_default();
+        ret.extend_from_slice(new);
+        Some(ret)
+    }
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    db.set_merge_operator(concatenate);
+
+    let mut sub = db.watch_prefix(vec![]);
+
+    db.insert(b"k", b"1")?;
+    db.insert(b"k", b"2")?;
+    db.compare_and_swap(b"k", Some(b"2"), Some(b"3"))?.unwrap();
+    db.merge(b"k", b"4")?;
+    db.remove(b"k")?;
+    // removing a missing key is a no-op and emits no event
+    db.remove(b"k")?;
+    db.insert(b"k", b"5")?;
+
+    let previous: Vec<Option<IVec>> = (0..5)
+        .map(|_| sub.next().unwrap().previous().cloned())
+        .collect();
+
+    assert_eq!(
+        previous,
+        vec![
+            None,
+            Some(b"1".into()),
+            Some(b"2".into()),
+            Some(b"3".into()),
+            Some(b"34".into()),
+        ]
+    );
+
+    match sub.next().unwrap() {
+        Event::Insert { key, value, previous } => {
+            assert_eq!(key, b"k");
+            assert_eq!(value, b"5");
+            assert_eq!(previous, None);
+        }
+        Event::Remove { .. } => panic!("expected an insert"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn subscriber_event_filter() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    db.set_merge_operator(|_, _, new| {
+        if new.is_empty() { None } else { Some(new.to_vec()) }
+    });
+
+    let mut removes = db.watch_prefix_filtered(b"k", EventFilter::Removes);
+    let mut inserts = db.watch_prefix_filtered(b"k", EventFilter::Inserts);
+    let mut all = db.watch_prefix_filtered(b"k", EventFilter::All);
+
+    db.insert(b"k1", b"a")?;
+    db.remove(b"k1")?;
+    db.insert(b"k2", b"b")?;
+    db.compare_and_swap(b"k2", Some(b"b"), None as Option<&[u8]>)?.unwrap();
+    db.merge(b"k3", b"c")?;
+    db.merge(b"k3", b"")?;
+
+    let keys = |sub: &mut Subscriber, n| -> Vec<(IVec, bool)> {
+        (0..n)
+            .map(|_| match sub.next().unwrap() {
+                Event::Insert { key, .. } => (key, true),
+                Event::Remove { key, .. } => (key, false),
+            })
+            .collect()
+    };
+    let k = |key: &[u8], insert| (IVec::from(key), insert);
+
+    assert_eq!(
+        keys(&mut removes, 3),
+        vec![k(b"k1", false), k(b"k2", false), k(b"k3", false)]
+    );
+    assert_eq!(
+        keys(&mut inserts, 3),
+        vec![k(b"k1", true), k(b"k2", true), k(b"k3", true)]
+    );
+    assert_eq!(keys(&mut all, 6).len(), 6);
+    drop(all);
+    drop(inserts);
+
+    // events that are filtered out are never queued, so
+    // they can not fill up the buffer of a subscriber that
+    // does not read them and block writes
+    for i in 0..5000_u32 {
+        db.insert(b"k4", &i.to_be_bytes())?;
+    }
+    db.remove(b"k4")?;
+    assert_eq!(keys(&mut removes, 1), vec![k(b"k4", false)]);
+
+    Ok(())
+}
+
+#[test]
+fn subscriber_watch_key() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+
+    let mut response = db.watch_key(b"job");
+    let mut other = db.watch_key(b"job/2");
+
+    // batches and transactions notify it like single writes
+    db.insert(b"jo", b"x")?;
+    db.insert(b"job/1", b"x")?;
+    let mut batch = Batch::default();
+    batch.insert(b"job", b"1");
+    batch.insert(b"jobs", b"x");
+    db.apply_batch(batch)?;
+    db.transaction::<_, _, ()>(|tx| {
+        tx.remove(b"job")?;
+        Ok(())
+    })
+    .unwrap();
+
+    match response.next().unwrap() {
+        Event::Insert { key, value, previous } => {
+            assert_eq!((&*key, &*value), (&b"job"[..], &b"1"[..]));
+            assert_eq!(previous, None);
+        }
+        other => panic!("expected an insert, got {:?}", other),
+    }
+    assert_eq!(response.next().unwrap().key(), b"job");
+
+    // a response written by another thread after the
+    // subscription is awaited without being missed
+    let worker = db.clone();
+    let thread = thread::spawn(move || worker.insert(b"job", b"done"));
+    let event = common::block_on(&mut response).unwrap();
+    assert_eq!(event.key(), b"job");
+    thread.join().unwrap()?;
+
+    db.insert(b"job/2", b"x")?;
+    assert_eq!(other.next().unwrap().key(), b"job/2");
+
+    Ok(())
+}
+
+#[test]
+fn tree_tail() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+
+    db.insert(b"a", b"before the tail")?;
+    db.insert(b"b", b"1")?;
+    db.insert(b"c", b"2")?;
+
+    let mut tail = db.tail(b"b");
+    let next_key = |tail: &mut TailIter| tail.next().unwrap().unwrap().0;
+    assert_eq!(next_key(&mut tail), b"b");
+    assert_eq!(next_key(&mut tail), b"c");
+
+    // keys at or behind the tail are skipped, and keys
+    // beyond it are returned in key order
+    db.insert(b"c", b"overwritten")?;
+    db.insert(b"bb", b"behind")?;
+    db.remove(b"b")?;
+    db.insert(b"e", b"3")?;
+    db.insert(b"d", b"4")?;
+    assert_eq!(next_key(&mut tail), b"d");
+    assert_eq!(next_key(&mut tail), b"e");
+
+    // the tail waits for a writer on another thread
+    let writer = db.clone();
+    let thread = thread::spawn(move || {
+        for key in &[b"f", b"g"] {
+            writer.insert(key, b"x")?;
+        }
+        Ok::<_, Error>(())
+    });
+    assert_eq!(next_key(&mut tail), b"f");
+    let (key, value) = common::block_on(tail.next_async()).unwrap()?;
+    assert_eq!((&*key, &*value), (&b"g"[..], &b"x"[..]));
+    thread.join().unwrap()?;
+
+    Ok(())
+}
+
 #[test]
 fn tree_subscribers_and_keyspaces() -> Result<()> {
     let config = Config::new().temporary(true).flush_every_ms(None);
@@ -654,7 +1136,7 @@ fn tree_subscribers_and_keyspaces() -> Result<()> {
     let t2 = db.open_tree(b"2".to_vec())?;
     let mut s2 = t2.watch_prefix(b"".to_vec());
 
-    assert!(db.is_empty());
+    assert!(Tree::is_empty(&db)?);
     assert_eq!(t1.len(), 1);
     assert_eq!(t2.len(), 1);
 
@@ -677,16 +1159,16 @@ fn tree_subscribers_and_keyspaces() -> Result<()> {
     let t1 = db.open_tree(b"1".to_vec())?;
     let t2 = db.open_tree(b"2".to_vec())?;
 
-    assert!(db.is_empty());
+    assert!(Tree::is_empty(&db)?);
     assert_eq!(t1.len(), 2);
     assert_eq!(t2.len(), 2);
 
     db.drop_tree(b"1")?;
     db.drop_tree(b"2")?;
 
-    assert_eq!(t1.get(b""), Err(Error::CollectionNotFound(b"1".into())));
+    assert_eq!(t1.get(b""), Err(Error::TreeDropped(b"1".into())));
 
-    assert_eq!(t2.get(b""), Err(Error::CollectionNotFound(b"2".into())));
+    assert_eq!(t2.get(b""), Err(Error::TreeDropped(b"2".into())));
 
     let guard = pin();
     guard.flush();
@@ -701,7 +1183,7 @@ fn tree_subscribers_and_keyspaces() -> Result<()> {
     let t1 = db.open_tree(b"1".to_vec())?;
     let t2 = db.open_tree(b"2".to_vec())?;
 
-    assert!(db.is_empty());
+    assert!(Tree::is_empty(&db)?);
     assert_eq!(t1.len(), 0);
     assert_eq!(t2.len(), 0);
 
@@ -709,109 +1191,4553 @@ fn tree_subscribers_and_keyspaces() -> Result<()> {
 }
 
 #[test]
-fn tree_range() {
+fn sync_strategies() -> Result<()> {
     common::setup_logger();
 
-    let config = Config::new().temporary(true).flush_every_ms(None);
-    let t = config.open().unwrap();
-
-    t.insert(b"0", vec![0]).unwrap();
-    t.insert(b"1", vec![10]).unwrap();
-    t.insert(b"2", vec![20]).unwrap();
-    t.insert(b"3", vec![30]).unwrap();
-    t.insert(b"4", vec![40]).unwrap();
-    t.insert(b"5", vec![50]).unwrap();
+    for (i, strategy) in [
+        SyncStrategy::SyncFileRange,
+        SyncStrategy::Fdatasync,
+        SyncStrategy::Fsync,
+        SyncStrategy::FullFsync,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let path = format!("sync_strategies_db_{}", i);
+        let _ = std::fs::remove_dir_all(&path);
 
-    let start: &[u8] = b"2";
-    let end: &[u8] = b"4";
-    let mut r = t.range(start..end);
-    assert_eq!(r.next().unwrap().unwrap().0, b"2");
-    assert_eq!(r.next().unwrap().unwrap().0, b"3");
-    assert_eq!(r.next(), None);
+        let config = Config::new().path(&path).sync_strategy(*strategy);
+        let db = config.open()?;
+        for j in 0..100_u32 {
+            db.insert(j.to_be_bytes(), vec![0; 1000])?;
+        }
+        db.flush()?;
+        drop(db);
 
-    let start = b"2".to_vec();
-    let end = b"4".to_vec();
-    let mut r = t.range(start..end).rev();
-    assert_eq!(r.next().unwrap().unwrap().0, b"3");
-    assert_eq!(r.next().unwrap().unwrap().0, b"2");
-    assert_eq!(r.next(), None);
+        let db = Config::new().path(&path).sync_strategy(*strategy).open()?;
+        assert_eq!(db.len(), 100);
+        drop(db);
 
-    let start = b"2".to_vec();
-    let mut r = t.range(start..);
-    assert_eq!(r.next().unwrap().unwrap().0, b"2");
-    assert_eq!(r.next().unwrap().unwrap().0, b"3");
-    assert_eq!(r.next().unwrap().unwrap().0, b"4");
-    assert_eq!(r.next().unwrap().unwrap().0, b"5");
-    assert_eq!(r.next(), None);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
 
-    let start = b"2".to_vec();
-    let mut r = t.range(..=start).rev();
-    assert_eq!(
-        r.next().unwrap().unwrap().0,
-        b"2",
-        "failed to find 2 in tree {:?}",
-        t
-    );
-    assert_eq!(r.next().unwrap().unwrap().0, b"1");
-    assert_eq!(r.next().unwrap().unwrap().0, b"0");
-    assert_eq!(r.next(), None);
+    Ok(())
 }
 
 #[test]
-fn recover_tree() {
+fn cache_modes() -> Result<()> {
     common::setup_logger();
 
-    let config =
-        Config::new().temporary(true).flush_every_ms(None).segment_size(4096);
+    for mode in &[CacheMode::Lru, CacheMode::ScanResistant] {
+        let config = Config::new()
+            .temporary(true)
+            .cache_capacity(64 * 1024)
+            .cache_mode(*mode);
+        let db = config.open()?;
+        for i in 0..2_000_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 100])?;
+        }
 
-    let t = config.open().unwrap();
-    for i in 0..N_PER_THREAD {
-        let k = kv(i);
-        t.insert(&k, k.clone()).unwrap();
+        // pages are evicted and read back while a scan runs
+        let scanner = {
+            let db = db.clone();
+            thread::spawn(move || {
+                for _ in 0..3 {
+                    assert_eq!(db.iter().count(), 2_000);
+                    assert_eq!(db.iter().rev().count(), 2_000);
+                }
+            })
+        };
+        for _ in 0..100 {
+            for i in 0..10_u32 {
+                assert_eq!(db.get(i.to_be_bytes())?.unwrap().len(), 100);
+            }
+        }
+        scanner.join().unwrap();
+
+        assert_eq!(db.len(), 2_000);
     }
-    drop(t);
 
-    let t = config.open().unwrap();
-    for i in 0..N_PER_THREAD {
-        let k = kv(i as usize);
-        assert_eq!(t.get(&*k).unwrap().unwrap(), k);
-        t.remove(&*k).unwrap();
+    Ok(())
+}
+
+#[test]
+fn cache_capacity_fraction() -> Result<()> {
+    common::setup_logger();
+
+    let nan = (-1.0_f64).sqrt();
+    for fraction in &[0.0, -0.5, 1.5, nan] {
+        let config =
+            Config::new().temporary(true).cache_capacity_fraction(*fraction);
+        match config.open() {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("fraction {} opened: {:?}", fraction, other),
+        }
     }
-    drop(t);
 
-    let t = config.open().unwrap();
-    for i in 0..N_PER_THREAD {
-        let k = kv(i as usize);
-        assert_eq!(t.get(&*k), Ok(None));
+    let db = Config::new()
+        .temporary(true)
+        .cache_capacity_fraction(0.5)
+        .open()?;
+    let capacity = db.retune_cache()?;
+    assert!(capacity >= 256);
+    for i in 0..1_000_u32 {
+        db.insert(i.to_be_bytes(), vec![0; 100])?;
     }
+    assert_eq!(db.retune_cache()?, capacity);
+    assert!(db.cache_resident_bytes() <= capacity);
+    assert_eq!(db.len(), 1_000);
+
+    // without a fraction, the configured capacity is kept
+    let db = Config::new()
+        .temporary(true)
+        .cache_capacity_bytes(1024 * 1024)
+        .open()?;
+    assert_eq!(db.retune_cache()?, 1024 * 1024);
+
+    Ok(())
 }
 
 #[test]
-fn create_tree() {
+fn open_async() -> Result<()> {
     common::setup_logger();
 
-    let path = "create_exclusive_db";
+    let path = "open_async_db";
     let _ = std::fs::remove_dir_all(path);
+    let config = Config::new().path(path);
+
+    // nothing is opened until the future is polled, and the
+    // future does not borrow the config
+    let future = config.open_async();
+    assert!(!std::path::Path::new(path).exists());
+    let db = thread::spawn(move || common::block_on(future))
+        .join()
+        .unwrap()?;
+    assert!(!db.was_recovered());
+    db.insert(b"k", b"v")?;
+    common::block_on(db.flush_async())?;
+    drop(db);
 
-    {
-        let config = Config::new().create_new(true).path(path);
-        config.open().unwrap();
+    let db = common::block_on(config.open_async())?;
+    assert!(db.was_recovered());
+    assert_eq!(db.get(b"k")?, Some(IVec::from(b"v")));
+    drop(db);
+
+    // errors of opening are returned by the future
+    match common::block_on(config.create_new(true).open_async()) {
+        Err(Error::DatabaseAlreadyExists(_)) => {}
+        other => panic!("expected DatabaseAlreadyExists, got {:?}", other),
     }
 
-    let config = Config::new().create_new(true).path(path);
-    config.open().unwrap_err();
+    // with a test clock, the polling thread opens it
+    let clock = Arc::new(sled::VirtualClock::new());
+    let db = common::block_on(
+        Config::new().path(path).test_clock(clock).open_async(),
+    )?;
+    assert_eq!(db.len(), 1);
+    drop(db);
+
     std::fs::remove_dir_all(path).unwrap();
+
+    Ok(())
 }
 
 #[test]
-fn tree_import_export() -> Result<()> {
+fn tree_names() -> Result<()> {
     common::setup_logger();
 
-    let config_1 = Config::new().temporary(true);
-    let config_2 = Config::new().temporary(true);
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
 
-    let db = config_1.open()?;
-    for db_id in 0..N_THREADS {
+    let expected = |names: &[&[u8]]| -> Vec<IVec> {
+        names.iter().map(|name| IVec::from(*name)).collect()
+    };
+
+    assert_eq!(db.tree_names()?, expected(&[b"__sled__default"]));
+
+    db.open_tree(b"2")?;
+    db.open_tree(b"1")?;
+    db.open_tree(b"3")?;
+    db.drop_tree(b"2")?;
+    assert_eq!(db.tree_names()?, expected(&[b"1", b"3", b"__sled__default"]));
+
+    db.flush()?;
+    drop(db);
+    let db = config.open()?;
+    assert_eq!(db.tree_names()?, expected(&[b"1", b"3", b"__sled__default"]));
+
+    Ok(())
+}
+
+#[test]
+fn drop_tree_detailed() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    assert_eq!(db.drop_tree_detailed(b"missing")?, None);
+
+    let empty = db.open_tree(b"empty")?;
+    drop(empty);
+    let info = db.drop_tree_detailed(b"empty")?.unwrap();
+    assert_eq!(info.key_count, 0);
+
+    // enough keys to split the tree into many leaves
+    let tree = db.open_tree(b"full")?;
+    for i in 0..5000_u32 {
+        tree.insert(i.to_be_bytes(), vec![0; 16])?;
+    }
+    for i in 0..1000_u32 {
+        tree.remove(i.to_be_bytes())?;
+    }
+
+    let info = db.drop_tree_detailed(b"full")?.unwrap();
+    assert_eq!(info.key_count, 4000);
+    assert!(info.approx_bytes_reclaimed >= 4000 * 20);
+
+    assert_eq!(db.drop_tree_detailed(b"full")?, None);
+    assert!(!db.drop_tree(b"full")?);
+
+    Ok(())
+}
+
+#[test]
+fn dropped_tree_handles() -> Result<()> {
+    common::setup_logger();
+
+    for dedup in &[false, true] {
+        let db = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .dedup_values(*dedup)
+            .open()?;
+
+        let tree = db.open_tree(b"shared")?;
+        let other_handle = tree.clone();
+        for i in 0..1000_u32 {
+            tree.insert(i.to_be_bytes(), vec![0; 100])?;
+        }
+        assert!(tree.is_valid());
+
+        db.drop_tree(b"shared")?;
+
+        let dropped: Result<()> =
+            Err(Error::TreeDropped(IVec::from(b"shared")));
+        assert!(!tree.is_valid());
+        assert!(!other_handle.is_valid());
+        assert_eq!(tree.get(0_u32.to_be_bytes()).map(|_| ()), dropped);
+        assert_eq!(tree.insert(b"k", b"v").map(|_| ()), dropped);
+        assert_eq!(tree.remove(b"k").map(|_| ()), dropped);
+        assert_eq!(
+            tree.compare_and_swap(b"k", None as Option<&[u8]>, Some(b"v"))
+                .map(|_| ()),
+            dropped
+        );
+        let mut batch = Batch::default();
+        batch.insert(b"k", b"v");
+        assert_eq!(tree.apply_batch(batch), dropped.clone());
+        assert_eq!(
+            tree.iter().next().unwrap().map(|_| ()),
+            dropped.clone()
+        );
+        assert_eq!(tree.checksum().map(|_| ()), dropped.clone());
+        let tx_res: TransactionResult<()> = tree.transaction(|tx| {
+            tx.insert(b"k", b"v")?;
+            Ok(())
+        });
+        assert!(tx_res.is_err());
+
+        // the dropped tree is never recreated through a stale
+        // handle, and opening the name again gives a new tree
+        assert_eq!(db.tree_names()?.len(), 1);
+        let reopened = db.open_tree(b"shared")?;
+        assert!(reopened.is_valid());
+        assert!(reopened.is_empty()?);
+        reopened.insert(b"k", b"v")?;
+        assert!(!tree.is_valid());
+        assert_eq!(tree.get(b"k").map(|_| ()), dropped);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn copy_tree() -> Result<()> {
+    common::setup_logger();
+
+    let path = "copy_tree_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    for dedup in &[false, true] {
+        {
+            let db = Config::new()
+                .path(path)
+                .flush_every_ms(None)
+                .dedup_values(*dedup)
+                .open()?;
+
+            let blue = db.open_tree(b"blue")?;
+            for i in 0..3000_u32 {
+                blue.insert(i.to_be_bytes(), vec![1; 100])?;
+            }
+
+            let green = db.copy_tree(b"blue", b"green")?;
+            assert_eq!(green.len(), 3000);
+            assert_eq!(green.checksum()?, blue.checksum()?);
+            assert!(db.tree_names()?.contains(&IVec::from(b"green")));
+            assert_eq!(db.open_tree(b"green")?.len(), 3000);
+
+            // the trees are independent afterwards
+            green.insert(0_u32.to_be_bytes(), vec![2; 100])?;
+            blue.remove(1_u32.to_be_bytes())?;
+            assert_eq!(blue.get(0_u32.to_be_bytes())?.unwrap()[0], 1);
+            assert!(green.contains_key(1_u32.to_be_bytes())?);
+
+            match db.copy_tree(b"missing", b"other") {
+                Err(Error::CollectionNotFound(name)) => {
+                    assert_eq!(name, IVec::from(b"missing"))
+                }
+                other => panic!("expected CollectionNotFound, got {:?}", other),
+            }
+            match db.copy_tree(b"blue", b"green") {
+                Err(Error::Unsupported(_)) => {}
+                other => panic!("expected Unsupported, got {:?}", other),
+            }
+            db.flush()?;
+        }
+
+        let db = Config::new().path(path).dedup_values(*dedup).open()?;
+        let blue = db.open_tree(b"blue")?;
+        let green = db.open_tree(b"green")?;
+        assert_eq!(blue.len(), 2999);
+        assert_eq!(green.len(), 3000);
+        assert_eq!(green.get(0_u32.to_be_bytes())?.unwrap()[0], 2);
+
+        // dropping the source leaves the copy intact
+        db.drop_tree(b"blue")?;
+        assert_eq!(green.get(2_u32.to_be_bytes())?, Some(vec![1; 100].into()));
+        drop(green);
+        drop(db);
+        std::fs::remove_dir_all(path)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn snapshots() -> Result<()> {
+    common::setup_logger();
+
+    let path = "snapshots_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    for dedup in &[false, true] {
+        let first;
+        let second;
+        {
+            let db = Config::new()
+                .path(path)
+                .dedup_values(*dedup)
+                .snapshot_retention(2)
+                .open()?;
+            let blue = db.open_tree(b"blue")?;
+            for i in 0..1000_u32 {
+                db.insert(i.to_be_bytes(), vec![1; 100])?;
+                blue.insert(i.to_be_bytes(), vec![2; 100])?;
+            }
+            first = db.snapshot_now()?;
+
+            db.remove(0_u32.to_be_bytes())?;
+            db.insert(1000_u32.to_be_bytes(), vec![3; 100])?;
+            db.drop_tree(b"blue")?;
+            db.open_tree(b"green")?.insert(b"k", b"v")?;
+            second = db.snapshot_now()?;
+            assert!(second > first);
+            assert_eq!(db.snapshots()?, vec![first, second]);
+            db.flush()?;
+        }
+
+        let db = Config::new()
+            .path(path)
+            .dedup_values(*dedup)
+            .snapshot_retention(2)
+            .open()?;
+        db.insert(b"after", b"v")?;
+        db.restore_snapshot(first)?;
+        assert_eq!(db.len(), 1000);
+        assert_eq!(db.get(0_u32.to_be_bytes())?, Some(vec![1; 100].into()));
+        assert!(!db.contains_key(b"after")?);
+        assert_eq!(db.open_tree(b"blue")?.len(), 1000);
+        assert!(db.open_tree(b"green")?.is_empty()?);
+
+        db.restore_snapshot(second)?;
+        assert_eq!(db.len(), 1000);
+        assert!(!db.contains_key(0_u32.to_be_bytes())?);
+        assert!(db.open_tree(b"blue")?.is_empty()?);
+        assert_eq!(db.open_tree(b"green")?.get(b"k")?, Some(b"v".into()));
+
+        // only the newest two are kept
+        let third = db.snapshot_now()?;
+        assert_eq!(db.snapshots()?, vec![second, third]);
+        match db.restore_snapshot(first) {
+            Err(Error::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::NotFound)
+            }
+            other => panic!("expected a missing snapshot, got {:?}", other),
+        }
+        drop(db);
+        std::fs::remove_dir_all(path)?;
+    }
+
+    // snapshots are taken in the background once enough
+    // writes have happened
+    let db = Config::new()
+        .temporary(true)
+        .snapshot_after_ops(100)
+        .snapshot_retention(100)
+        .open()?;
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), vec![])?;
+    }
+    let mut snapshots = db.snapshots()?;
+    while snapshots.is_empty() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        snapshots = db.snapshots()?;
+    }
+    assert!(snapshots.len() <= 10);
+
+    // with a test clock, they are taken by `step` instead, on
+    // the calling thread
+    let clock = Arc::new(VirtualClock::new());
+    let db = Config::new()
+        .temporary(true)
+        .snapshot_after_ops(100)
+        .snapshot_interval(std::time::Duration::from_secs(60))
+        .snapshot_retention(100)
+        .test_clock(clock.clone())
+        .open()?;
+    for i in 0..250_u32 {
+        db.insert(i.to_be_bytes(), vec![])?;
+    }
+    assert!(db.snapshots()?.is_empty());
+    db.step()?;
+    assert_eq!(db.snapshots()?.len(), 1);
+    db.step()?;
+    assert_eq!(db.snapshots()?.len(), 1);
+    clock.advance(std::time::Duration::from_secs(60));
+    db.step()?;
+    assert_eq!(db.snapshots()?.len(), 2);
+    let newest = *db.snapshots()?.last().unwrap();
+    db.clear()?;
+    db.restore_snapshot(newest)?;
+    assert_eq!(db.len(), 250);
+
+    // images stay consistent while another thread writes,
+    // here by moving units between two keys in batches
+    use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+    let db = Config::new().temporary(true).open()?;
+    // keys in between make the scan from one to the other
+    // slow enough to race with the writes
+    for i in 0..2_000_u32 {
+        db.insert([&b"a"[..], &i.to_be_bytes()].concat(), vec![])?;
+    }
+    let done = Arc::new(AtomicBool::new(false));
+    let mover = {
+        let db = db.clone();
+        let done = done.clone();
+        thread::spawn(move || -> Result<()> {
+            let mut i = 0_u64;
+            while !done.load(SeqCst) {
+                let mut batch = Batch::default();
+                batch.insert(b"a", &(100 - i % 101).to_be_bytes());
+                batch.insert(b"b", &(i % 101).to_be_bytes());
+                db.apply_batch(batch)?;
+                i += 1;
+            }
+            Ok(())
+        })
+    };
+    for _ in 0..5 {
+        let copy = Db::open_from_bytes(&db.to_bytes()?)?;
+        let get = |key: &[u8]| -> Result<u64> {
+            let mut buf = [0; 8];
+            if let Some(value) = copy.get(key)? {
+                buf.copy_from_slice(&value);
+            }
+            Ok(u64::from_be_bytes(buf))
+        };
+        let total = get(b"a")? + get(b"b")?;
+        assert!(total == 100 || total == 0, "{}", total);
+    }
+    done.store(true, SeqCst);
+    mover.join().unwrap()?;
+
+    let config = Config::new().temporary(true).snapshot_after_ops(0);
+    assert!(config.open().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn repair_in_place() -> Result<()> {
+    common::setup_logger();
+
+    let path = "repair_in_place_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let last_id;
+    let snapshot;
+    {
+        let db = Config::new().path(path).dedup_values(true).open()?;
+        let blue = db.open_tree(b"blue")?;
+        for i in 0..1000_u32 {
+            db.insert(i.to_be_bytes(), vec![1; 100])?;
+            blue.insert(i.to_be_bytes(), i.to_be_bytes().to_vec())?;
+        }
+        last_id = db.generate_id()?;
+        snapshot = db.snapshot_now()?;
+        db.flush()?;
+    }
+
+    // the parameters that are stored with the database are
+    // kept, so it opens with the same config afterwards
+    let report = Db::repair(path, RepairOptions::new())?;
+    assert!(report.is_clean());
+    assert_eq!(report.path, std::path::Path::new(path));
+    assert_eq!(report.trees, 2);
+    assert_eq!(report.keys, 2000);
+
+    let db = Config::new().path(path).dedup_values(true).open()?;
+    assert_eq!(db.len(), 1000);
+    assert_eq!(db.get(7_u32.to_be_bytes())?, Some(vec![1; 100].into()));
+    let blue = db.open_tree(b"blue")?;
+    let seven = blue.get(7_u32.to_be_bytes())?;
+    assert_eq!(seven, Some(IVec::from(&[0, 0, 0, 7])));
+    assert!(db.generate_id()? > last_id);
+    assert_eq!(db.snapshots()?, vec![snapshot]);
+    drop(blue);
+    drop(db);
+
+    // the output must not exist yet
+    match Db::repair(path, RepairOptions::new().output(path)) {
+        Err(Error::DatabaseAlreadyExists(_)) => {}
+        other => panic!("expected an existing output, got {:?}", other),
+    }
+    match Db::repair("repair_missing_db", RepairOptions::new()) {
+        Err(Error::DatabaseNotFound(_)) => {}
+        other => panic!("expected a missing database, got {:?}", other),
+    }
+
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn space_report() -> Result<()> {
+    common::setup_logger();
+
+    for dedup in &[false, true] {
+        let db = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .dedup_values(*dedup)
+            .open()?;
+
+        let logs = db.open_tree(b"logs")?;
+        for i in 0..20_000_u32 {
+            let mut key = vec![b"ab"[i as usize % 2]];
+            key.extend_from_slice(&i.to_be_bytes());
+            logs.insert(key, vec![7; 100])?;
+        }
+        db.insert(b"", b"")?;
+        db.insert(b"configuration", vec![1; 1000])?;
+
+        let report = db.space_report(1)?;
+        assert!(!report.sampled);
+        assert_eq!(report.relative_error, 0.);
+        assert_eq!(report.keys.count, 20_002);
+        assert_eq!(report.keys.total_bytes, 13 + 20_000 * 5);
+        assert_eq!(report.keys.max, 13);
+        // the empty key, then 4 to 7 and 8 to 15 bytes
+        assert_eq!(report.keys.buckets, vec![1, 0, 0, 20_000, 1]);
+        assert_eq!(report.keys.percentile(50.), 7);
+        assert_eq!(report.keys.percentile(0.), 0);
+        assert_eq!(report.values.total_bytes, 1000 + 20_000 * 100);
+        assert_eq!(report.values.max, 1000);
+        assert_eq!(report.values.percentile(99.), 127);
+        assert_eq!(report.values.percentile(100.), 1000);
+
+        let prefixes: Vec<(&[u8], &[u8], u64, u64)> = report
+            .prefixes
+            .iter()
+            .map(|p| (&*p.tree, &*p.prefix, p.count, p.bytes))
+            .collect();
+        assert_eq!(
+            prefixes,
+            vec![
+                (&b"__sled__default"[..], &b""[..], 1, 0),
+                (b"__sled__default", b"c", 1, 1013),
+                (b"logs", b"a", 10_000, 10_000 * 105),
+                (b"logs", b"b", 10_000, 10_000 * 105),
+            ]
+        );
+
+        // the sampled report estimates the same shape
+        let sampled = db.space_report_sampled(1, 400)?;
+        assert!(sampled.sampled);
+        assert!(sampled.relative_error > 0.);
+        assert!(sampled.relative_error < 0.25);
+        let off = |estimate: u64, actual: u64| {
+            (estimate as f64 - actual as f64).abs() / actual as f64
+        };
+        assert!(off(sampled.keys.count, 20_002) < 0.25, "{:?}", sampled);
+        let values_total = report.values.total_bytes;
+        assert!(off(sampled.values.total_bytes, values_total) < 0.25);
+        assert_eq!(sampled.keys.max, 13);
+        assert_eq!(sampled.values.percentile(50.), 127);
+        let logs_prefixes: Vec<&[u8]> = sampled
+            .prefixes
+            .iter()
+            .filter(|p| &*p.tree == b"logs")
+            .map(|p| &*p.prefix)
+            .collect();
+        assert_eq!(logs_prefixes, vec![b"a", b"b"]);
+
+        match db.space_report_sampled(1, 0) {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("expected Unsupported, got {:?}", other),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "debug")]
+fn structure_report() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(4096)
+        .open()?;
+
+    let empty = db.open_tree(b"empty")?;
+    let big = db.open_tree(b"big")?;
+    // the items of the default tree are written between
+    // those of `big`, so that they share segments with them
+    for i in 0..4_000_u32 {
+        big.insert(i.to_be_bytes(), vec![0; 8])?;
+        db.insert(i.to_be_bytes(), vec![0; 8])?;
+    }
+    drop(empty);
+
+    let report = db.structure_report()?;
+    let names: Vec<&[u8]> =
+        report.trees.iter().map(|tree| &*tree.name).collect();
+    assert_eq!(names, vec![&b"__sled__default"[..], b"big", b"empty"]);
+
+    let big = &report.trees[1];
+    assert!(big.height > 1, "{:?}", big);
+    assert_eq!(big.nodes_per_level.len(), big.height);
+    assert_eq!(big.nodes_per_level[0], 1);
+    assert!(big.nodes_per_level.windows(2).all(|w| w[0] < w[1]));
+    let levels = &big.nodes_per_level;
+    let index_nodes: u64 = levels[..levels.len() - 1].iter().sum();
+    let children: u64 = levels[1..].iter().sum();
+    // the right halves of splits that are not linked into
+    // their parents yet are counted, but are not children
+    let fan_out = children as f64 / index_nodes as f64;
+    assert!(big.average_fan_out > 1.);
+    assert!(big.average_fan_out <= fan_out + 1e-9, "{:?}", big);
+    assert_eq!(big.leaf_keys, 4_000);
+    assert!(big.min_leaf_keys > 0);
+    assert!(big.min_leaf_keys <= big.max_leaf_keys);
+    assert!(big.average_leaf_keys() > 1.);
+
+    // a new tree is an index node over an empty leaf
+    let empty = &report.trees[2];
+    assert_eq!(empty.height, 2);
+    assert_eq!(empty.nodes_per_level, vec![1, 1]);
+    assert_eq!(empty.average_fan_out, 1.);
+    assert_eq!(empty.leaf_keys, 0);
+    assert_eq!(empty.min_leaf_keys, 0);
+
+    assert!(report.segments.len() > 1);
+    assert!(report.segments.windows(2).all(|w| w[0].offset < w[1].offset));
+    assert!(report
+        .segments
+        .iter()
+        .any(|s| s.state == sled::SegmentState::Active));
+    for segment in &report.segments {
+        assert_eq!(segment.offset % 4096, 0);
+        let ratio = segment.live_ratio();
+        assert!((0. ..=1.).contains(&ratio), "{:?}", segment);
+        if segment.state != sled::SegmentState::Active {
+            assert_eq!(segment.live_bytes + segment.dead_bytes, 4096);
+        }
+    }
+
+    // rewriting every key of `big` often enough for all of
+    // its pages to be consolidated replaces them in the older
+    // segments, which the default tree keeps from being freed
+    let big = db.open_tree(b"big")?;
+    for round in 0..10_u8 {
+        for i in 0..4_000_u32 {
+            big.insert(i.to_be_bytes(), vec![round; 8])?;
+        }
+    }
+    let rewritten = db.structure_report()?;
+    assert_eq!(rewritten.trees[1].leaf_keys, 4_000);
+    assert!(rewritten.segments.iter().any(|s| s.live_ratio() < 0.5));
+
+    Ok(())
+}
+
+#[test]
+fn flush_detailed() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open()?;
+    let hot = db.open_tree(b"hot")?;
+    let cold = db.open_tree(b"cold")?;
+    let _idle = db.open_tree(b"idle")?;
+
+    // enough writes for the hot tree to split
+    for i in 0..10_000_u32 {
+        hot.insert(i.to_be_bytes(), vec![0; 16])?;
+    }
+    cold.insert(b"k", b"v")?;
+    db.insert(b"k", b"v")?;
+
+    let report = db.flush_detailed()?;
+    let names: Vec<&[u8]> = report.trees.keys().map(AsRef::as_ref).collect();
+    assert_eq!(names, vec![&b"__sled__default"[..], b"cold", b"hot"]);
+    let hot_bytes = report.trees[&IVec::from(b"hot")];
+    assert!(hot_bytes > 10_000 * 16, "{:?}", report);
+    assert!(hot_bytes > report.trees[&IVec::from(b"cold")] * 100);
+    assert!(report.total > 0);
+
+    // counts are taken by each call, and include writes that
+    // were flushed another way
+    assert_eq!(db.flush_detailed()?, sled::FlushReport::default());
+    cold.insert(b"k", b"v2")?;
+    cold.flush()?;
+    let report = db.flush_detailed()?;
+    assert_eq!(report.trees.keys().collect::<Vec<_>>(), vec![b"cold"]);
+    assert_eq!(report.total, 0);
+
+    // batches and transactions are counted too
+    let mut batch = sled::Batch::default();
+    batch.insert(b"a", b"a");
+    hot.apply_batch(batch)?;
+    let tx_res: TransactionResult<()> = cold.transaction(|tx| {
+        tx.insert(b"b", b"b")?;
+        Ok(())
+    });
+    tx_res.unwrap();
+    let report = db.flush_detailed()?;
+    let names: Vec<&[u8]> = report.trees.keys().map(AsRef::as_ref).collect();
+    assert_eq!(names, vec![&b"cold"[..], b"hot"]);
+
+    Ok(())
+}
+
+#[test]
+fn flush_syncs_all_trees_once() -> Result<()> {
+    common::setup_logger();
+
+    let path = "flush_syncs_all_trees_once";
+    let _ = std::fs::remove_dir_all(path);
+    let db = Config::new().path(path).flush_every_ms(None).open()?;
+    let trees: Vec<sled::Tree> = (0..100_u8)
+        .map(|i| db.open_tree([i]))
+        .collect::<Result<_>>()?;
+    let _ = db.flush_detailed()?;
+
+    let threads: Vec<_> = trees
+        .chunks(10)
+        .map(|chunk| {
+            let chunk = chunk.to_vec();
+            std::thread::spawn(move || {
+                for tree in &chunk {
+                    tree.insert(b"k", b"v").unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let report = db.flush_detailed()?;
+    assert_eq!(report.trees.len(), 100);
+    assert_eq!(report.syncs, 1, "{:?}", report);
+    assert_eq!(db.flush_detailed()?.syncs, 0);
+
+    drop((trees, db));
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn flush_with_progress() -> Result<()> {
+    common::setup_logger();
+
+    let path = "flush_with_progress";
+    let _ = std::fs::remove_dir_all(path);
+    let config = Config::new().path(path).flush_every_ms(None);
+    let db = config.open()?;
+
+    for i in 0..100_u32 {
+        db.insert(i.to_be_bytes(), vec![0; 1024])?;
+    }
+
+    let mut seen: Vec<FlushProgress> = vec![];
+    let done = db.flush_with_progress(|progress| {
+        seen.push(progress);
+        ControlFlow::Continue
+    })?;
+    assert!(done.total > 100 * 1024, "{:?}", done);
+    assert_eq!(done.flushed, done.total);
+    assert_eq!(seen.last(), Some(&done));
+    assert!(seen.windows(2).all(|w| w[0].flushed <= w[1].flushed));
+
+    // nothing left to flush
+    let mut calls = 0;
+    let done = db.flush_with_progress(|_| {
+        calls += 1;
+        ControlFlow::Continue
+    })?;
+    assert_eq!((calls, done), (0, FlushProgress::default()));
+
+    // a cancelled flush leaves the rest to the next flush
+    for i in 100..200_u32 {
+        db.insert(i.to_be_bytes(), vec![1; 1024])?;
+    }
+    let cancelled = db.flush_with_progress(|_| ControlFlow::Break)?;
+    assert!(cancelled.flushed <= cancelled.total);
+    let rest = db.flush_with_progress(|_| ControlFlow::Continue)?;
+    assert_eq!(rest.flushed, rest.total);
+
+    drop(db);
+    let db = config.open()?;
+    assert_eq!(db.len(), 200);
+    assert_eq!(db.get(199_u32.to_be_bytes())?, Some(vec![1; 1024].into()));
+
+    drop(db);
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn max_db_size_evicts() -> Result<()> {
+    common::setup_logger();
+
+    // every item takes 4 + 96 bytes, so 10 of them fit
+    let key = |i: u32| i.to_be_bytes();
+    let value = vec![0; 96];
+    let keys = |tree: &Tree| -> Result<Vec<u32>> {
+        tree.iter()
+            .keys()
+            .map(|k| k.map(|k| u32::from_be_bytes([k[0], k[1], k[2], k[3]])))
+            .collect()
+    };
+    let open = |policy: EvictionPolicy| {
+        Config::new()
+            .temporary(true)
+            .max_db_size(1000)
+            .eviction_policy(policy)
+            .open()
+    };
+
+    // least recently used, across trees, with events
+    let db = open(EvictionPolicy::Lru)?;
+    let other = db.open_tree(b"other")?;
+    let mut events = db.watch_prefix(vec![]);
+    for i in 0..5 {
+        db.insert(key(i), value.clone())?;
+    }
+    for i in 5..10 {
+        other.insert(key(i), value.clone())?;
+    }
+    assert_eq!(db.items_size(), Some(1000));
+    assert!(db.get(key(0))?.is_some());
+    assert!(other.get(key(5))?.is_some());
+    other.insert(key(10), value.clone())?;
+    other.insert(key(11), value.clone())?;
+    assert_eq!(keys(&db)?, vec![0, 3, 4]);
+    assert_eq!(keys(&other)?, vec![5, 6, 7, 8, 9, 10, 11]);
+    assert_eq!(db.items_size(), Some(1000));
+    let removed: Vec<IVec> = events
+        .by_ref()
+        .skip(5)
+        .take(2)
+        .map(|event| match event {
+            Event::Remove { key, previous } => {
+                assert_eq!(previous, Some(value.clone().into()));
+                key
+            }
+            other => panic!("unexpected event {:?}", other),
+        })
+        .collect();
+    assert_eq!(removed, vec![IVec::from(&key(1)), IVec::from(&key(2))]);
+
+    // the item that was written is kept even if it is too
+    // large on its own
+    db.insert(b"large", vec![0; 2000])?;
+    assert_eq!(db.len() + other.len(), 1);
+    assert_eq!(db.items_size(), Some(2005));
+    db.insert(key(0), value.clone())?;
+    assert_eq!(keys(&db)?, vec![0]);
+
+    // dropped trees no longer count
+    other.insert(key(1), value.clone())?;
+    assert_eq!(db.items_size(), Some(200));
+    assert!(db.drop_tree(b"other")?);
+    assert_eq!(db.items_size(), Some(100));
+    drop(db);
+
+    // least frequently used
+    let db = open(EvictionPolicy::Lfu)?;
+    for i in 0..10 {
+        db.insert(key(i), value.clone())?;
+    }
+    for i in 0..5 {
+        assert!(db.get(key(i))?.is_some());
+    }
+    for i in 10..15 {
+        db.insert(key(i), value.clone())?;
+    }
+    assert_eq!(keys(&db)?, vec![0, 1, 2, 3, 4, 10, 11, 12, 13, 14]);
+    drop(db);
+
+    // first in, first out, even when used since
+    let db = open(EvictionPolicy::Fifo)?;
+    for i in 0..10 {
+        db.insert(key(i), value.clone())?;
+    }
+    db.insert(key(0), vec![1; 96])?;
+    assert!(db.get(key(0))?.is_some());
+    db.insert(key(10), value.clone())?;
+    assert_eq!(keys(&db)?, (1..11).collect::<Vec<_>>());
+    drop(db);
+
+    // writes that lock out all others evict once they are done
+    let db = open(EvictionPolicy::Lru)?;
+    let other = db.open_tree(b"other")?;
+    for i in 0..5 {
+        db.insert(key(i), value.clone())?;
+        other.insert(key(i), value.clone())?;
+    }
+    let copy = db.copy_tree(b"other", b"copy")?;
+    assert_eq!(db.items_size(), Some(1000));
+    assert_eq!(keys(&copy)?, vec![0, 1, 2, 3, 4]);
+    let mut batch = Batch::default();
+    for i in 5..8 {
+        batch.insert(&key(i), value.clone());
+    }
+    db.apply_batch(batch)?;
+    assert_eq!(db.items_size(), Some(1000));
+    db.transaction::<_, _, ()>(|tx| {
+        for i in 8..10 {
+            tx.insert(&key(i), value.clone())?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(db.items_size(), Some(1000));
+    assert_eq!(keys(&db)?, (5..10).collect::<Vec<_>>());
+    drop((other, copy));
+    drop(db);
+
+    // no eviction is lost to writers that go over the limit
+    // at the same time
+    let db = open(EvictionPolicy::Lru)?;
+    let threads: Vec<_> = (0..8_u32)
+        .map(|t| {
+            let db = db.clone();
+            let value = value.clone();
+            thread::spawn(move || -> Result<()> {
+                for i in 0..100 {
+                    db.insert(key(t * 100 + i), value.clone())?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+    assert_eq!(db.items_size(), Some(1000));
+    assert_eq!(db.len(), 10);
+    drop(db);
+
+    // lowering the limit evicts when opening
+    let path = "max_db_size_evicts";
+    let _ = std::fs::remove_dir_all(path);
+    let db = Config::new().path(path).open()?;
+    for i in 0..10 {
+        db.insert(key(i), value.clone())?;
+    }
+    drop(db);
+    let db = Config::new().path(path).max_db_size(500).open()?;
+    assert_eq!(keys(&db)?, vec![5, 6, 7, 8, 9]);
+    assert_eq!(db.items_size(), Some(500));
+    drop(db);
+    std::fs::remove_dir_all(path)?;
+
+    match Config::new()
+        .temporary(true)
+        .max_db_size(1000)
+        .dedup_values(true)
+        .open()
+    {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other.map(|_| ())),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn separate_log_for_tree() -> Result<()> {
+    common::setup_logger();
+
+    let path = "separate_log_for_tree";
+    let _ = std::fs::remove_dir_all(path);
+    let config = Config::new().path(path).separate_log_for_tree(b"hot");
+
+    let db = config.open()?;
+    let hot = db.open_tree(b"hot")?;
+    let cold = db.open_tree(b"cold")?;
+    for i in 0..100_u8 {
+        hot.insert([i], vec![i; 1024])?;
+    }
+    cold.insert(b"k", b"v")?;
+    hot.flush()?;
+    db.flush()?;
+
+    // the tree is written to its own log
+    assert!(
+        std::path::Path::new(path).join("separate_logs/686f74/db").exists()
+    );
+    assert_eq!(
+        db.tree_names()?,
+        vec![IVec::from(b"__sled__default"), IVec::from(b"cold"), b"hot".into()]
+    );
+    assert_eq!(db.export().len(), 3);
+
+    // writes can't be atomic across logs
+    let tx_res: TransactionResult<()> = (&hot, &cold).transaction(|(h, c)| {
+        h.insert(b"tx", b"tx")?;
+        c.insert(b"tx", b"tx")?;
+        Ok(())
+    });
+    match tx_res {
+        Err(TransactionError::Storage(Error::Unsupported(_))) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+    let mut batch = MultiTreeBatch::default();
+    batch.insert("hot", "batch", "batch");
+    batch.insert("cold", "batch", "batch");
+    match db.apply_batch(batch) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+    match db.copy_tree(b"hot", b"copy") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+    assert_eq!(hot.get(b"tx")?, None);
+    assert_eq!(cold.get(b"batch")?, None);
+
+    // but they can be within the separated tree
+    let tx_res: TransactionResult<()> = hot.transaction(|h| {
+        h.insert(b"tx", b"tx")?;
+        Ok(())
+    });
+    tx_res.unwrap();
+    let mut batch = MultiTreeBatch::default();
+    batch.insert("hot", "batch", "batch");
+    db.apply_batch(batch)?;
+    hot.flush()?;
+
+    drop((hot, cold, db));
+    let db = config.open()?;
+    let hot = db.open_tree(b"hot")?;
+    assert_eq!(hot.len(), 102);
+    assert_eq!(hot.get([7])?, Some(IVec::from(vec![7; 1024])));
+    assert_eq!(db.open_tree(b"cold")?.get(b"k")?, Some(IVec::from(b"v")));
+    drop((hot, db));
+
+    // the separated tree must keep being named, and existing
+    // trees can't be separated
+    for config in &[
+        Config::new().path(path),
+        Config::new()
+            .path(path)
+            .separate_log_for_tree(b"hot")
+            .separate_log_for_tree(b"cold"),
+    ] {
+        match config.open() {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+    }
+
+    let db = config.open()?;
+    assert_eq!(db.drop_tree_detailed(b"hot")?.unwrap().key_count, 102);
+    assert_eq!(db.tree_names()?.len(), 2);
+
+    drop(db);
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn open_at_directory_fd() -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    common::setup_logger();
+
+    let path = "open_at_directory_fd";
+    let moved = "open_at_directory_fd_moved";
+    let _ = std::fs::remove_dir_all(path);
+    let _ = std::fs::remove_dir_all(moved);
+    std::fs::create_dir_all(path)?;
+
+    let dir = std::fs::File::open(path)?;
+    let db = Config::new().open_at(dir.as_raw_fd())?;
+    // the db keeps its own handle to the directory
+    drop(dir);
+    db.insert(b"k", b"v")?;
+
+    // nothing is opened by path, so moving the directory
+    // away does not get in the way
+    std::fs::rename(path, moved)?;
+    db.open_tree(b"t")?.insert(b"k", b"v")?;
+    db.flush()?;
+    let snapshot = db.snapshot_now()?;
+    drop(db);
+    assert!(!std::path::Path::new(path).exists());
+    std::fs::rename(moved, path)?;
+
+    // recovery reads the files through the descriptor too
+    let dir = std::fs::File::open(path)?;
+    let db = Config::new().open_at(dir.as_raw_fd())?;
+    assert_eq!(db.get(b"k")?, Some(IVec::from(b"v")));
+    assert_eq!(db.snapshots()?, vec![snapshot]);
+    drop(db);
+
+    // the files are in the directory, as if opened by path
+    let db = Config::new().path(path).open()?;
+    assert_eq!(db.get(b"k")?, Some(IVec::from(b"v")));
+    assert_eq!(db.open_tree(b"t")?.get(b"k")?, Some(IVec::from(b"v")));
+    assert_eq!(db.snapshots()?, vec![snapshot]);
+    drop(db);
+
+    let dir = std::fs::File::open(path)?;
+    match Config::new().temporary(true).open_at(dir.as_raw_fd()) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+    match Config::new().open_at(-1) {
+        Err(Error::Io(_)) => {}
+        other => panic!("expected an io error, got {:?}", other),
+    }
+
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn on_flush_callbacks() -> Result<()> {
+    use std::sync::{mpsc::channel, Mutex};
+
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    let (tx, rx) = channel();
+    let tx = Mutex::new(tx);
+    db.on_flush(Box::new(move |info| {
+        tx.lock().unwrap().send(info).unwrap();
+    }));
+
+    // a callback that blocks must not stall flushes
+    let (unblock_tx, unblock_rx) = channel::<()>();
+    let unblock_rx = Mutex::new(unblock_rx);
+    db.on_flush(Box::new(move |_| {
+        let _ = unblock_rx.lock().unwrap().recv();
+    }));
+
+    let mut last_generation = 0;
+    for i in 0..10_u8 {
+        db.insert([i], vec![i; 64])?;
+        assert!(db.flush()? > 0);
+        assert_eq!(db.flush()?, 0);
+
+        let info = rx.recv().unwrap();
+        assert!(info.bytes_flushed >= 64);
+        assert!(info.generation > last_generation);
+        last_generation = info.generation;
+
+        unblock_tx.send(()).unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_append() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = Arc::new(config.open()?);
+
+    // concurrent appends get every number exactly once
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|thread_id| {
+            let t = t.clone();
+            thread::spawn(move || {
+                (0..100)
+                    .map(|_| t.append(vec![thread_id as u8]).unwrap())
+                    .collect::<Vec<u64>>()
+            })
+        })
+        .collect();
+    let mut numbers = vec![];
+    for thread in threads {
+        let appended = thread.join().unwrap();
+        let mut sorted = appended.clone();
+        sorted.sort_unstable();
+        assert_eq!(appended, sorted);
+        numbers.extend(appended);
+    }
+    numbers.sort_unstable();
+    let expected: Vec<u64> = (0..N_THREADS as u64 * 100).collect();
+    assert_eq!(numbers, expected);
+    assert_eq!(t.len(), N_THREADS * 100);
+
+    // the sequence continues after a restart
+    drop(t);
+    let t = config.open()?;
+    assert_eq!(t.append(b"after restart")?, N_THREADS as u64 * 100);
+
+    t.insert(b"short", b"")?;
+    match t.append(b"v") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+    t.remove(b"short")?;
+    t.insert([0xFF; 8], b"")?;
+    match t.append(b"v") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_remove_if() -> TransactionResult<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    t.insert(b"a", b"1")?;
+    assert!(!t.remove_if(b"a", b"2")?);
+    assert!(!t.remove_if(b"a", b"")?);
+    assert!(!t.remove_if(b"b", b"1")?);
+    assert_eq!(t.get(b"a")?, Some(IVec::from(b"1")));
+    assert!(t.remove_if(b"a", b"1")?);
+    assert_eq!(t.get(b"a")?, None);
+    assert!(!t.remove_if(b"a", b"1")?);
+
+    // an empty value is different from an absent one
+    t.insert(b"e", b"")?;
+    assert!(t.remove_if(b"e", b"")?);
+    assert!(!t.remove_if(b"e", b"")?);
+
+    // in a transaction, the value is compared as the
+    // transaction sees it
+    t.insert(b"c", b"1")?;
+    t.transaction(|tx| {
+        tx.insert(b"c", b"2")?;
+        assert!(!tx.remove_if(b"c", b"1")?);
+        assert!(tx.remove_if(b"c", b"2")?);
+        assert!(!tx.remove_if(b"c", b"2")?);
+        tx.insert(b"d", b"3")?;
+        assert!(tx.remove_if(b"d", b"3")?);
+        Ok(())
+    })?;
+    assert_eq!(t.get(b"c")?, None);
+    assert_eq!(t.get(b"d")?, None);
+
+    // and nothing is removed if it aborts
+    t.insert(b"c", b"1")?;
+    let res: TransactionResult<(), ()> = t.transaction(|tx| {
+        assert!(tx.remove_if(b"c", b"1")?);
+        abort(())
+    });
+    assert!(res.is_err());
+    assert_eq!(t.get(b"c")?, Some(IVec::from(b"1")));
+
+    Ok(())
+}
+
+#[test]
+fn tree_and_db_is_empty() -> Result<()> {
+    common::setup_logger();
+
+    // small segments make the keys span many leaves
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .segment_size(4096)
+        .open()?;
+    assert!(db.is_empty()?);
+
+    // a tree that exists but has no keys is empty
+    let tree = db.open_tree(b"tree")?;
+    assert!(tree.is_empty()?);
+    assert!(db.is_empty()?);
+
+    for i in 0..1000_u32 {
+        tree.insert(i.to_be_bytes(), vec![0; 16])?;
+    }
+    assert!(!tree.is_empty()?);
+    assert!(!db.is_empty()?);
+    assert!(Tree::is_empty(&db)?);
+
+    // the leaves emptied by removals are skipped over
+    for i in 0..999_u32 {
+        tree.remove(i.to_be_bytes())?;
+    }
+    assert!(!tree.is_empty()?);
+    tree.remove(999_u32.to_be_bytes())?;
+    assert!(tree.is_empty()?);
+    assert!(db.is_empty()?);
+
+    db.insert(b"default", b"1")?;
+    assert!(!db.is_empty()?);
+    assert!(tree.is_empty()?);
+    db.remove(b"default")?;
+    assert!(db.is_empty()?);
+
+    // a dropped tree is not reported as empty
+    tree.insert(b"k", b"v")?;
+    assert!(!db.is_empty()?);
+    assert!(db.drop_tree(b"tree")?);
+    assert_eq!(tree.is_empty(), Err(Error::TreeDropped(b"tree".into())));
+    assert!(db.is_empty()?);
+
+    // so are trees with a log of their own
+    let separated = Config::new()
+        .temporary(true)
+        .separate_log_for_tree(b"events")
+        .open()?;
+    let events = separated.open_tree(b"events")?;
+    assert!(separated.is_empty()?);
+    events.insert(b"k", b"v")?;
+    assert!(!events.is_empty()?);
+    assert!(!separated.is_empty()?);
+    events.remove(b"k")?;
+    assert!(separated.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "debug")]
+fn tree_compact_range() -> Result<()> {
+    common::setup_logger();
+
+    const LIVE: u32 = 500;
+    const DELETED: u32 = 50_000;
+
+    let leaves = |db: &sled::Db, name: &[u8]| -> Result<u64> {
+        let report = db.structure_report()?;
+        let tree = report.trees.iter().find(|t| &*t.name == name).unwrap();
+        Ok(*tree.nodes_per_level.last().unwrap())
+    };
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    // the pages that the remaining keys would need if the
+    // deleted ones had never been inserted
+    let reference = db.open_tree(b"reference")?;
+    for i in (0..LIVE).chain(LIVE + DELETED..2 * LIVE + DELETED) {
+        reference.insert(i.to_be_bytes(), vec![])?;
+    }
+    let needed = leaves(&db, b"reference")?;
+
+    let tree = db.open_tree(b"deletes")?;
+    for i in 0..2 * LIVE + DELETED {
+        tree.insert(i.to_be_bytes(), vec![])?;
+    }
+    for i in LIVE..LIVE + DELETED {
+        tree.remove(i.to_be_bytes())?;
+    }
+    assert_eq!(tree.len(), 2 * LIVE as usize);
+
+    let start = LIVE.to_be_bytes();
+    let end = (LIVE + DELETED).to_be_bytes();
+    tree.compact_range(start..end)?;
+
+    // a scan across the deleted range reads about as many
+    // pages as the remaining keys need, however many keys
+    // were deleted
+    let compacted = leaves(&db, b"deletes")?;
+    assert!(compacted <= needed + needed / 10, "{} {}", compacted, needed);
+
+    let from = (LIVE - 1).to_be_bytes();
+    let mut crossing = tree.range(from..).keys();
+    assert_eq!(crossing.next().unwrap()?, from);
+    assert_eq!(crossing.next().unwrap()?, end);
+    assert_eq!(tree.len(), 2 * LIVE as usize);
+
+    // compacting again changes nothing, and the range can
+    // be written to afterwards
+    tree.compact_range(start..end)?;
+    assert_eq!(leaves(&db, b"deletes")?, compacted);
+    tree.insert(start, vec![])?;
+    assert_eq!(tree.get_gt(from)?.unwrap().0, start);
+
+    Ok(())
+}
+
+#[test]
+fn tree_remove_while() -> Result<()> {
+    common::setup_logger();
+
+    let t = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    for i in 0..100_u8 {
+        t.insert([i], vec![i % 10])?;
+    }
+
+    // stops at the first item that does not match, even
+    // though later items would
+    let mut seen = vec![];
+    let removed = t.remove_while(Some(&[5]), |k, v| {
+        seen.push(k[0]);
+        v[0] >= 5
+    })?;
+    assert_eq!(removed, 5);
+    assert_eq!(seen, vec![5, 6, 7, 8, 9, 10]);
+    assert_eq!(t.len(), 95);
+    assert!(t.contains_key([4])?);
+    assert!(!t.contains_key([9])?);
+    assert!(t.contains_key([10])?);
+    assert!(t.contains_key([15])?);
+
+    // the start does not need to exist
+    assert_eq!(t.remove_while(Some(&[7]), |k, _| k[0] < 12)?, 2);
+    assert!(!t.contains_key([11])?);
+
+    assert_eq!(t.remove_while(None, |_, _| false)?, 0);
+    assert_eq!(t.remove_while(None, |k, _| k[0] < 50)?, 43);
+    assert_eq!(t.iter().next().unwrap()?.0, [50]);
+    assert_eq!(t.remove_while(None, |_, _| true)?, 50);
+    assert!(t.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+fn tree_retain() -> Result<()> {
+    common::setup_logger();
+
+    let t = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    for i in 0..100_u8 {
+        t.insert([i], vec![i % 10])?;
+    }
+
+    // every item is visited once, in order
+    let mut seen = vec![];
+    let removed = t.retain(|k, v| {
+        seen.push(k[0]);
+        v[0] != 3
+    })?;
+    assert_eq!(removed, 10);
+    assert_eq!(seen, (0..100).collect::<Vec<u8>>());
+    assert_eq!(t.len(), 90);
+    assert!(!t.contains_key([13])?);
+    assert!(t.contains_key([14])?);
+
+    // an item changed after the predicate saw it is checked
+    // again with its new value
+    let mut changed = false;
+    let removed = t.retain(|k, v| {
+        if k[0] == 50 && !changed {
+            changed = true;
+            t.insert([50], vec![7]).unwrap();
+            return false;
+        }
+        v[0] != 9
+    })?;
+    assert_eq!(removed, 10);
+    assert_eq!(t.get([50])?, Some(IVec::from(&[7])));
+
+    assert_eq!(t.retain(|_, _| true)?, 0);
+    assert_eq!(t.retain(|_, _| false)?, 80);
+    assert!(t.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+fn tree_entry() -> Result<()> {
+    common::setup_logger();
+
+    let t = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    // and_modify is not called for an absent key
+    let value = t
+        .entry(b"k")
+        .and_modify(|_| -> Vec<u8> { panic!("the key is absent") })
+        .or_insert(vec![1])?;
+    assert_eq!(value, IVec::from(&[1]));
+
+    // or_insert_with is not called for a present key
+    let value = t
+        .entry(b"k")
+        .and_modify(|old| vec![old[0] + 1])
+        .and_modify(|old| vec![old[0] * 10])
+        .or_insert_with(|| -> Vec<u8> { panic!("the key is present") })?;
+    assert_eq!(value, IVec::from(&[20]));
+    assert_eq!(t.get(b"k")?, Some(IVec::from(&[20])));
+
+    assert_eq!(t.entry(b"k").or_insert(vec![1])?, IVec::from(&[20]));
+
+    // concurrent increments are never lost
+    let t = Arc::new(t);
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|_| {
+            let t = t.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    t.entry(b"counter")
+                        .and_modify(|old| {
+                            let count = u64::from_be_bytes([
+                                old[0], old[1], old[2], old[3], old[4],
+                                old[5], old[6], old[7],
+                            ]);
+                            (count + 1).to_be_bytes().to_vec()
+                        })
+                        .or_insert(1_u64.to_be_bytes().to_vec())
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let expected = (N_THREADS as u64 * 100).to_be_bytes().to_vec();
+    assert_eq!(t.get(b"counter")?, Some(IVec::from(expected)));
+
+    Ok(())
+}
+
+#[test]
+fn tree_counters() -> Result<()> {
+    common::setup_logger();
+
+    let t = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    assert_eq!(t.counter_value(b"c")?, 0);
+    assert_eq!(t.increment(b"c", -5)?, -5);
+
+    // concurrent increments are never lost
+    let t = Arc::new(t);
+    let threads: Vec<_> = (0..N_THREADS)
+        .map(|_| {
+            let t = t.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    t.increment(b"c", 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let expected = N_THREADS as i64 * 100 - 5;
+    assert_eq!(t.counter_value(b"c")?, expected);
+
+    // an overflow leaves the counter unchanged
+    assert!(t.increment(b"c", i64::max_value()).is_err());
+    assert_eq!(t.counter_value(b"c")?, expected);
+
+    // a value that is not 8 bytes long is not a counter
+    t.insert(b"not a counter", vec![1, 2, 3])?;
+    assert!(t.increment(b"not a counter", 1).is_err());
+    assert!(t.counter_value(b"not a counter").is_err());
+    assert_eq!(t.get(b"not a counter")?, Some(IVec::from(&[1, 2, 3])));
+
+    Ok(())
+}
+
+#[test]
+fn tree_typed_values() -> Result<()> {
+    common::setup_logger();
+
+    let t = Config::new().temporary(true).flush_every_ms(None).open()?;
+
+    assert_eq!(t.get_or(b"k", vec![1])?, IVec::from(&[1]));
+    assert!(t.is_empty()?);
+    t.insert(b"k", vec![2])?;
+    assert_eq!(t.get_or(b"k", vec![1])?, IVec::from(&[2]));
+
+    assert_eq!(t.get_u64(b"n")?, None);
+    assert_eq!(t.insert_u64(b"n", 1)?, None);
+    let old = t.insert_u64(b"n", u64::max_value())?;
+    assert_eq!(old, Some(IVec::from(&1_u64.to_be_bytes())));
+    assert_eq!(t.get_u64(b"n")?, Some(u64::max_value()));
+
+    // values of the wrong length are errors, not panics
+    match t.get_u64(b"k") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected a wrong length error, got {:?}", other),
+    }
+    t.insert(b"k", vec![0; 9])?;
+    assert!(t.get_u64(b"k").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn tree_prefetch() -> Result<()> {
+    common::setup_logger();
+
+    let path = "tree_prefetch_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    {
+        let db = Config::new().path(path).open()?;
+        for i in 0..10_000_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 64])?;
+        }
+        db.flush()?;
+    }
+
+    // a cache smaller than the range only loads a part of it
+    let db = Config::new().path(path).cache_capacity_bytes(64 * 1024).open()?;
+    db.prefetch_range(1000_u32.to_be_bytes()..9000_u32.to_be_bytes())?;
+    db.prefetch_range::<&[u8], _>(..)?;
+    db.prefetch_prefix([0, 0, 1])?;
+    db.prefetch_prefix([1])?;
+    assert_eq!(db.range_count(1000_u32.to_be_bytes()..)?, 9000);
+    assert_eq!(db.scan_prefix([0, 0, 1]).count(), 256);
+
+    db.open_tree(b"empty")?.prefetch_range::<&[u8], _>(..)?;
+
+    drop(db);
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn tree_range() {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open().unwrap();
+
+    t.insert(b"0", vec![0]).unwrap();
+    t.insert(b"1", vec![10]).unwrap();
+    t.insert(b"2", vec![20]).unwrap();
+    t.insert(b"3", vec![30]).unwrap();
+    t.insert(b"4", vec![40]).unwrap();
+    t.insert(b"5", vec![50]).unwrap();
+
+    let start: &[u8] = b"2";
+    let end: &[u8] = b"4";
+    let mut r = t.range(start..end);
+    assert_eq!(r.next().unwrap().unwrap().0, b"2");
+    assert_eq!(r.next().unwrap().unwrap().0, b"3");
+    assert_eq!(r.next(), None);
+
+    let start = b"2".to_vec();
+    let end = b"4".to_vec();
+    let mut r = t.range(start..end).rev();
+    assert_eq!(r.next().unwrap().unwrap().0, b"3");
+    assert_eq!(r.next().unwrap().unwrap().0, b"2");
+    assert_eq!(r.next(), None);
+
+    let start = b"2".to_vec();
+    let mut r = t.range(start..);
+    assert_eq!(r.next().unwrap().unwrap().0, b"2");
+    assert_eq!(r.next().unwrap().unwrap().0, b"3");
+    assert_eq!(r.next().unwrap().unwrap().0, b"4");
+    assert_eq!(r.next().unwrap().unwrap().0, b"5");
+    assert_eq!(r.next(), None);
+
+    let start = b"2".to_vec();
+    let mut r = t.range(..=start).rev();
+    assert_eq!(
+        r.next().unwrap().unwrap().0,
+        b"2",
+        "failed to find 2 in tree {:?}",
+        t
+    );
+    assert_eq!(r.next().unwrap().unwrap().0, b"1");
+    assert_eq!(r.next().unwrap().unwrap().0, b"0");
+    assert_eq!(r.next(), None);
+
+    let (lo, hi): (&[u8], &[u8]) = (b"1", b"4");
+    assert_eq!(t.range_count(lo..hi).unwrap(), 3);
+    assert_eq!(t.range_count(lo..=hi).unwrap(), 4);
+    assert_eq!(t.range_count(hi..lo).unwrap(), 0);
+}
+
+#[test]
+fn tree_range_count() -> Result<()> {
+    use std::ops::Bound;
+
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    for i in 0..2000_u32 {
+        t.insert((i * 2).to_be_bytes(), vec![])?;
+    }
+
+    let check = |t: &Tree| -> Result<()> {
+        for &(lo, hi) in &[
+            (0, 4000),
+            (1, 3999),
+            (100, 101),
+            (1000, 3000),
+            (3998, 5000),
+            (0, 0),
+        ] {
+            let (lo, hi) = (u32::to_be_bytes(lo), u32::to_be_bytes(hi));
+            assert_eq!(t.range_count(lo..hi)?, t.range(lo..hi).count());
+            assert_eq!(t.range_count(lo..=hi)?, t.range(lo..=hi).count());
+            assert_eq!(t.range_count(lo..)?, t.range(lo..).count());
+            assert_eq!(t.range_count(..hi)?, t.range(..hi).count());
+            let excluded = (Bound::Excluded(lo), Bound::Included(hi));
+            assert_eq!(t.range_count(excluded)?, t.range(excluded).count());
+        }
+        assert_eq!(t.range_count::<&[u8], _>(..)?, t.len());
+        Ok(())
+    };
+
+    // counting again reuses the counts of whole subtrees
+    check(&t)?;
+    check(&t)?;
+    let (lo, hi) = (1000_u32.to_be_bytes(), 3000_u32.to_be_bytes());
+    assert_eq!(t.range_count(lo..hi)?, 1000);
+
+    // writes make the kept counts stale, and splits and
+    // merges move keys between nodes
+    for i in 500..1500_u32 {
+        t.remove((i * 2).to_be_bytes())?;
+    }
+    check(&t)?;
+    assert_eq!(t.range_count(lo..hi)?, 0);
+    for i in 0..1000_u32 {
+        t.insert((1001 + i * 2).to_be_bytes(), vec![])?;
+    }
+    check(&t)?;
+    check(&t)?;
+
+    // keys that come and go while counting split and merge
+    // the nodes around the ones that stay
+    let base = t.len();
+    let t = Arc::new(t);
+    let writer = {
+        let t = t.clone();
+        thread::spawn(move || {
+            for _ in 0..5 {
+                for i in 0..500_u32 {
+                    t.insert((i * 2 + 1).to_be_bytes(), vec![]).unwrap();
+                }
+                for i in 0..500_u32 {
+                    t.remove((i * 2 + 1).to_be_bytes()).unwrap();
+                }
+            }
+        })
+    };
+    for _ in 0..20 {
+        let count = t.range_count::<&[u8], _>(..)?;
+        assert!(count >= base && count <= base + 500, "{}", count);
+    }
+    writer.join().unwrap();
+    assert_eq!(t.range_count::<&[u8], _>(..)?, base);
+
+    Ok(())
+}
+
+#[test]
+fn tree_iter_relaxed() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    for i in 0..N_PER_THREAD {
+        let k = kv(i);
+        t.insert(&k, k.clone())?;
+    }
+
+    let strict: Vec<_> = t.iter().collect::<Result<_>>()?;
+    let relaxed: Vec<_> = t.iter_relaxed().collect::<Result<_>>()?;
+    assert_eq!(strict, relaxed);
+
+    let strict: Vec<_> = t.iter().rev().collect::<Result<_>>()?;
+    let relaxed: Vec<_> = t.iter_relaxed().rev().collect::<Result<_>>()?;
+    assert_eq!(strict, relaxed);
+
+    let (lo, hi) = (kv(10), kv(20));
+    let relaxed: Vec<_> =
+        t.range(&*lo..&*hi).relaxed().keys().collect::<Result<_>>()?;
+    assert_eq!(relaxed.len(), 10);
+    assert_eq!(relaxed[0], lo);
+
+    Ok(())
+}
+
+#[test]
+fn tree_scan_prefix_page() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    t.insert(b"a9", vec![])?;
+    for i in 0..10_u8 {
+        t.insert([b'b', b'0' + i], vec![i])?;
+    }
+    t.insert(b"c0", vec![])?;
+
+    let keys = |page: Vec<(IVec, IVec)>| -> Vec<IVec> {
+        page.into_iter().map(|(k, _v)| k).collect()
+    };
+
+    // forward pagination
+    let page = t.scan_prefix_page(b"b", None, 4, false)?;
+    assert_eq!(keys(page), vec![b"b0", b"b1", b"b2", b"b3"]);
+    let page = t.scan_prefix_page(b"b", Some(b"b3"), 4, false)?;
+    assert_eq!(keys(page), vec![b"b4", b"b5", b"b6", b"b7"]);
+    let page = t.scan_prefix_page(b"b", Some(b"b7"), 4, false)?;
+    assert_eq!(keys(page), vec![b"b8", b"b9"]);
+
+    // reverse pagination
+    let page = t.scan_prefix_page(b"b", None, 3, true)?;
+    assert_eq!(keys(page), vec![b"b9", b"b8", b"b7"]);
+    let page = t.scan_prefix_page(b"b", Some(b"b7"), 3, true)?;
+    assert_eq!(keys(page), vec![b"b6", b"b5", b"b4"]);
+    let page = t.scan_prefix_page(b"b", Some(b"b1"), 3, true)?;
+    assert_eq!(keys(page), vec![b"b0"]);
+
+    // start_after outside of the prefix
+    let page = t.scan_prefix_page(b"b", Some(b"a"), 2, false)?;
+    assert_eq!(keys(page), vec![b"b0", b"b1"]);
+    assert!(t.scan_prefix_page(b"b", Some(b"c"), 2, false)?.is_empty());
+    let page = t.scan_prefix_page(b"b", Some(b"c"), 2, true)?;
+    assert_eq!(keys(page), vec![b"b9", b"b8"]);
+    assert!(t.scan_prefix_page(b"b", Some(b"a"), 2, true)?.is_empty());
+
+    // degenerate limits and prefixes
+    assert!(t.scan_prefix_page(b"b", None, 0, false)?.is_empty());
+    assert_eq!(t.scan_prefix_page(b"", None, 100, false)?.len(), 12);
+    assert!(t.scan_prefix_page(b"d", None, 10, true)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn tree_for_each_prefix() -> Result<()> {
+    use sled::ControlFlow;
+
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    // enough keys for the prefixes to span several pages
+    for prefix in &[0_u8, 1, 0xFF] {
+        for i in 0..2000_u16 {
+            let mut key = vec![*prefix];
+            key.extend_from_slice(&i.to_be_bytes());
+            t.insert(key, &i.to_be_bytes())?;
+        }
+    }
+
+    let collect = |prefix: &[u8]| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut ret = vec![];
+        t.for_each_prefix(prefix, |k, v| {
+            ret.push((k.to_vec(), v.to_vec()));
+            ControlFlow::Continue
+        })?;
+        Ok(ret)
+    };
+    let expected = |prefix: &[u8]| -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        t.scan_prefix(prefix)
+            .map(|res| res.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect()
+    };
+
+    for prefix in &[&[1][..], &[0, 3], &[0xFF], &[0xFF, 0xFF], &[], &[2]] {
+        assert_eq!(collect(prefix)?, expected(prefix)?, "{:?}", prefix);
+    }
+    assert_eq!(collect(&[1])?.len(), 2000);
+
+    // stops as soon as the closure breaks
+    let mut seen = 0;
+    t.for_each_prefix([1], |k, _v| {
+        seen += 1;
+        if k == [1, 4, 0] { ControlFlow::Break } else { ControlFlow::Continue }
+    })?;
+    assert_eq!(seen, 1025);
+
+    // values are resolved for trees that deduplicate them
+    let dedup = Config::new().temporary(true).dedup_values(true).open()?;
+    dedup.insert(b"a1", vec![7; 64])?;
+    dedup.insert(b"a2", vec![7; 64])?;
+    dedup.insert(b"b1", vec![8; 64])?;
+    let mut values = vec![];
+    dedup.for_each_prefix(b"a", |_k, v| {
+        values.push(v.to_vec());
+        ControlFlow::Continue
+    })?;
+    assert_eq!(values, vec![vec![7; 64], vec![7; 64]]);
+
+    Ok(())
+}
+
+#[test]
+fn tree_scan_prefix_range() -> Result<()> {
+    use std::ops::Bound;
+
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    for &key in &[
+        &[0x01, 0xFE][..],
+        &[0x01, 0xFF],
+        &[0x01, 0xFF, 0x00],
+        &[0x01, 0xFF, 0xFF],
+        &[0x01, 0xFF, 0xFF, 0x07],
+        &[0x02],
+        &[0x02, 0x00],
+        &[0xFF, 0x00],
+        &[0xFF, 0xFF],
+    ] {
+        t.insert(key, vec![])?;
+    }
+
+    fn keys<I: Iterator<Item = Result<(IVec, IVec)>>>(
+        iter: I,
+    ) -> Vec<Vec<u8>> {
+        iter.map(|res| res.unwrap().0.to_vec()).collect()
+    }
+    let all = |suffix: &[u8]| -> std::ops::RangeFrom<Vec<u8>> {
+        suffix.to_vec()..
+    };
+
+    // the upper bound of a prefix ending in 0xFF carries
+    // into the previous byte, and is not spilled past
+    let prefix: &[u8] = &[0x01, 0xFF];
+    assert_eq!(
+        keys(t.scan_prefix_range(prefix, all(&[]))),
+        vec![
+            vec![0x01, 0xFF],
+            vec![0x01, 0xFF, 0x00],
+            vec![0x01, 0xFF, 0xFF],
+            vec![0x01, 0xFF, 0xFF, 0x07],
+        ]
+    );
+    assert_eq!(
+        keys(t.scan_prefix_range(prefix, all(&[0xFF]))),
+        vec![vec![0x01, 0xFF, 0xFF], vec![0x01, 0xFF, 0xFF, 0x07]]
+    );
+
+    // suffix bounds are relative to the prefix
+    let lo: &[u8] = &[0x00];
+    let hi: &[u8] = &[0xFF];
+    assert_eq!(
+        keys(t.scan_prefix_range(prefix, lo..hi)),
+        vec![vec![0x01, 0xFF, 0x00]]
+    );
+    assert_eq!(
+        keys(t.scan_prefix_range(prefix, lo..=hi)),
+        vec![vec![0x01, 0xFF, 0x00], vec![0x01, 0xFF, 0xFF]]
+    );
+    assert_eq!(
+        keys(t.scan_prefix_range(prefix, ..hi).rev()),
+        vec![vec![0x01, 0xFF, 0x00], vec![0x01, 0xFF]]
+    );
+    let excluded: (Bound<&[u8]>, Bound<&[u8]>) =
+        (Bound::Excluded(lo), Bound::Unbounded);
+    let iter = t.scan_prefix_range::<_, &[u8], _>(prefix, excluded);
+    assert_eq!(keys(iter).len(), 2);
+
+    // a prefix of only 0xFF bytes has no upper bound
+    assert_eq!(
+        keys(t.scan_prefix_range([0xFF], all(&[]))),
+        vec![vec![0xFF, 0x00], vec![0xFF, 0xFF]]
+    );
+
+    // an empty prefix makes the suffix range a plain range
+    assert_eq!(keys(t.scan_prefix_range(b"", all(&[]))).len(), 9);
+    assert_eq!(
+        keys(t.scan_prefix_range(b"", all(&[0x02]))),
+        vec![vec![0x02], vec![0x02, 0x00], vec![0xFF, 0x00], vec![0xFF, 0xFF]]
+    );
+
+    // nothing starts with a missing prefix
+    assert!(keys(t.scan_prefix_range([0x03], all(&[]))).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn tree_scan_prefixes() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    for &key in &[
+        &[0x01][..],
+        &[0x01, 0x00],
+        &[0x01, 0xFF, 0xFF],
+        &[0x02],
+        &[0x02, 0x00],
+        &[0x02, 0xFF],
+        &[0x03],
+        &[0xFF],
+        &[0xFF, 0xFF, 0x07],
+    ] {
+        t.insert(key, vec![])?;
+    }
+
+    fn keys<I: Iterator<Item = Result<(IVec, IVec)>>>(
+        iter: I,
+    ) -> Vec<Vec<u8>> {
+        iter.map(|res| res.unwrap().0.to_vec()).collect()
+    }
+
+    // adjacent prefixes include everything extending either
+    assert_eq!(
+        keys(t.scan_prefixes([0x01], [0x02])),
+        vec![
+            vec![0x01],
+            vec![0x01, 0x00],
+            vec![0x01, 0xFF, 0xFF],
+            vec![0x02],
+            vec![0x02, 0x00],
+            vec![0x02, 0xFF],
+        ]
+    );
+    assert_eq!(
+        keys(t.scan_prefixes([0x02], [0x02])),
+        keys(t.scan_prefix([0x02]))
+    );
+    let mut reversed = keys(t.scan_prefixes([0x01], [0x02]).rev());
+    reversed.reverse();
+    assert_eq!(reversed, keys(t.scan_prefixes([0x01], [0x02])));
+
+    // overlapping prefixes, where one extends the other,
+    // cover the longer one's keys only once
+    assert_eq!(
+        keys(t.scan_prefixes([0x01], [0x01, 0x00])),
+        vec![vec![0x01], vec![0x01, 0x00]]
+    );
+    assert_eq!(
+        keys(t.scan_prefixes([0x01, 0x00], [0x02])),
+        vec![
+            vec![0x01, 0x00],
+            vec![0x01, 0xFF, 0xFF],
+            vec![0x02],
+            vec![0x02, 0x00],
+            vec![0x02, 0xFF],
+        ]
+    );
+
+    // an end prefix ending in 0xFF carries into the previous
+    // byte, and one of only 0xFF bytes runs to the end
+    assert_eq!(
+        keys(t.scan_prefixes([0x02, 0xFF], [0x02, 0xFF])),
+        vec![vec![0x02, 0xFF]]
+    );
+    assert_eq!(
+        keys(t.scan_prefixes([0x03], [0xFF, 0xFF])),
+        vec![vec![0x03], vec![0xFF], vec![0xFF, 0xFF, 0x07]]
+    );
+    assert_eq!(keys(t.scan_prefixes(b"", b"")).len(), 9);
+
+    // a start after the end is empty, even when the start
+    // extends the end
+    assert!(keys(t.scan_prefixes([0x02], [0x01])).is_empty());
+    assert!(keys(t.scan_prefixes([0x01, 0x00], [0x01])).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn tree_range_typed() -> Result<()> {
+    use sled::OrderedKey;
+
+    common::setup_logger();
+
+    let t = Config::new().temporary(true).open()?;
+
+    for i in -300_i64..300 {
+        t.insert(i.to_key_bytes(), &i.to_be_bytes())?;
+    }
+    let values = |iter: sled::Iter| -> Result<Vec<i64>> {
+        iter.map(|res| {
+            let (_k, v) = res?;
+            let mut buf = [0; 8];
+            buf.copy_from_slice(&v);
+            Ok(i64::from_be_bytes(buf))
+        })
+        .collect()
+    };
+
+    assert_eq!(values(t.range_typed(-3_i64..2))?, vec![-3, -2, -1, 0, 1]);
+    assert_eq!(values(t.range_typed(..=-299_i64))?, vec![-300, -299]);
+    assert_eq!(values(t.range_typed(298_i64..))?, vec![298, 299]);
+    assert_eq!(values(t.range_typed::<i64, _>(..))?.len(), 600);
+    let rev = t.range_typed(-2_i64..=1).next_back().unwrap()?;
+    assert_eq!(rev.0, 1_i64.to_key_bytes());
+
+    // variable-length fields keep the later fields in order
+    let names = t.open_tree(b"names")?;
+    let keys = [
+        (1_u32, "a".to_string(), 2_u8),
+        (1, "a".to_string(), 10),
+        (1, "a\0".to_string(), 0),
+        (1, "ab".to_string(), 0),
+        (2, String::new(), 0),
+        (10, "a".to_string(), 0),
+    ];
+    for key in keys.iter().rev() {
+        names.insert(key.to_key_bytes(), vec![])?;
+    }
+    let all: Vec<IVec> = names
+        .range_typed::<(u32, String, u8), _>(..)
+        .keys()
+        .collect::<Result<_>>()?;
+    let expected: Vec<IVec> =
+        keys.iter().map(|key| key.to_key_bytes().into()).collect();
+    assert_eq!(all, expected);
+    let start = (1_u32, "a".to_string(), 3_u8);
+    let end = (10_u32, String::new(), 0_u8);
+    assert_eq!(names.range_typed(start..end).count(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn tree_group_by() -> Result<()> {
+    common::setup_logger();
+
+    fn group_of(key: &[u8]) -> &[u8] {
+        let end = key.iter().position(|b| *b == b'|');
+        &key[..end.unwrap_or(key.len())]
+    }
+
+    fn keys(items: GroupIter) -> Result<Vec<IVec>> {
+        items.map(|item| item.map(|(k, _v)| k)).collect()
+    }
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    assert!(t.group_by(group_of).next().is_none());
+
+    // "ab|.." sorts before "a|.." because b'b' < b'|'
+    for key in &["a|1", "a|2", "ab|1", "b", "c|1", "c|2", "c|3", "d|1"] {
+        t.insert(key, *key)?;
+    }
+
+    let mut groups = vec![];
+    for group_res in t.group_by(group_of) {
+        let (group, items) = group_res?;
+        groups.push((group, keys(items)?));
+    }
+    let expected: Vec<(IVec, Vec<IVec>)> = vec![
+        (b"ab".into(), vec![b"ab|1".into()]),
+        (b"a".into(), vec![b"a|1".into(), b"a|2".into()]),
+        (b"b".into(), vec![b"b".into()]),
+        (b"c".into(), vec![b"c|1".into(), b"c|2".into(), b"c|3".into()]),
+        (b"d".into(), vec![b"d|1".into()]),
+    ];
+    assert_eq!(groups, expected);
+
+    // groups can be skipped or partially read, and read
+    // after the outer iterator has moved past them
+    let mut group_iters = vec![];
+    for group_res in t.group_by(group_of) {
+        let (group, mut items) = group_res?;
+        if &*group == b"c" {
+            assert_eq!(items.next().unwrap()?.0, b"c|1");
+        }
+        group_iters.push(items);
+    }
+    assert_eq!(group_iters.len(), 5);
+    let c = group_iters.remove(3);
+    assert_eq!(keys(c)?, vec![IVec::from(b"c|2"), IVec::from(b"c|3")]);
+    let d = group_iters.pop().unwrap();
+    assert_eq!(keys(d)?, vec![IVec::from(b"d|1")]);
+
+    // a group iterator stops at the next group even if it
+    // is written after the group was returned
+    let mut groups = t.group_by(group_of);
+    let (_, mut b) = groups.next().unwrap()?;
+    assert_eq!(b.next().unwrap()?.0, b"ab|1");
+    t.insert(b"ab|2", b"")?;
+    assert_eq!(b.next().unwrap()?.0, b"ab|2");
+    assert!(b.next().is_none());
+    assert!(b.next().is_none());
+    let (group, a) = groups.next().unwrap()?;
+    assert_eq!(group, b"a");
+    assert_eq!(keys(a)?.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn tree_diff() -> Result<()> {
+    common::setup_logger();
+
+    fn diffs(iter: DiffIter) -> Result<Vec<Diff>> {
+        iter.collect()
+    }
+
+    let k = |i: u32| IVec::from(&i.to_be_bytes());
+    let v = |i: u32| IVec::from(&i.to_le_bytes());
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    let a = db.open_tree(b"a")?;
+    let b = db.open_tree(b"b")?;
+
+    assert_eq!(diffs(a.diff(&b))?, vec![]);
+
+    // disjoint
+    a.insert(b"1", b"a1")?;
+    a.insert(b"3", b"a3")?;
+    b.insert(b"2", b"b2")?;
+    b.insert(b"4", b"b4")?;
+    assert_eq!(
+        diffs(a.diff(&b))?,
+        vec![
+            Diff::OnlyLeft(b"1".into(), b"a1".into()),
+            Diff::OnlyRight(b"2".into(), b"b2".into()),
+            Diff::OnlyLeft(b"3".into(), b"a3".into()),
+            Diff::OnlyRight(b"4".into(), b"b4".into()),
+        ]
+    );
+
+    // identical, across enough keys to span several leaves
+    a.clear()?;
+    b.clear()?;
+    for i in 0..1000_u32 {
+        a.insert(k(i), v(i))?;
+        b.insert(k(i), v(i))?;
+    }
+    assert_eq!(diffs(a.diff(&b))?, vec![]);
+    assert_eq!(diffs(b.diff(&a))?, vec![]);
+
+    // partially overlapping
+    a.remove(k(10))?;
+    b.insert(k(500), b"changed")?;
+    b.remove(k(999))?;
+    b.insert(k(1000), b"new")?;
+    let expected = vec![
+        Diff::OnlyRight(k(10), v(10)),
+        Diff::Changed(
+            k(500),
+            v(500),
+            b"changed".into(),
+        ),
+        Diff::OnlyLeft(k(999), v(999)),
+        Diff::OnlyRight(k(1000), b"new".into()),
+    ];
+    assert_eq!(diffs(a.diff(&b))?, expected);
+
+    // only the diverged prefixes need to be walked
+    let diverged: Vec<IVec> = a
+        .checksum_ranges(3)?
+        .into_iter()
+        .zip(b.checksum_ranges(3)?)
+        .filter(|(left, right)| left != right)
+        .map(|(left, _right)| left.0)
+        .collect();
+    assert_eq!(diverged.len(), 3);
+    let mut found = vec![];
+    for prefix in &diverged {
+        found.extend(diffs(a.diff_prefix(&b, prefix))?);
+    }
+    assert_eq!(found, expected);
+    assert_eq!(diffs(a.diff_range(&b, k(400)..k(1000)))?, expected[1..3]);
+    assert_eq!(diffs(a.diff_range(&b, k(10)..=k(10)))?, expected[..1]);
+    assert_eq!(diffs(a.diff_range(&b, k(1000)..))?, expected[3..]);
+    assert_eq!(diffs(a.diff_range(&b, k(11)..k(500)))?, vec![]);
+
+    // trees in different databases can be compared
+    let other = Config::new().temporary(true).open()?;
+    assert_eq!(diffs(a.diff(&other))?.len(), 999);
+    assert!(
+        diffs(other.diff(&a))?
+            .iter()
+            .all(|d| matches!(d, Diff::OnlyRight(..)))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn tree_range_channel() -> Result<()> {
+    use std::time::Duration;
+
+    common::setup_logger();
+
+    let path = "tree_range_channel";
+    let _ = std::fs::remove_dir_all(path);
+    let config = Config::new()
+        .path(path)
+        .flush_every_ms(None)
+        .open_timeout(Some(Duration::from_secs(10)));
+    let db = config.open()?;
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), &i.to_le_bytes())?;
+    }
+
+    let items = |rx: std::sync::mpsc::Receiver<Result<(IVec, IVec)>>| {
+        rx.into_iter().collect::<Result<Vec<_>>>()
+    };
+
+    let start = 100_u32.to_be_bytes();
+    let end = 200_u32.to_be_bytes();
+    for &buffer in &[0, 1, 16] {
+        let expected: Vec<_> = db.range(start..end).collect::<Result<_>>()?;
+        assert_eq!(items(db.range_channel(start..end, buffer)?)?, expected);
+    }
+    assert_eq!(items(db.range_channel::<&[u8], _>(.., 16)?)?.len(), 1000);
+    assert_eq!(items(db.range_channel(end..start, 16)?)?, vec![]);
+
+    // the thread stops once the receiver is dropped, which
+    // releases its handle to the database
+    let rx = db.range_channel::<&[u8], _>(.., 1)?;
+    assert_eq!(rx.recv().unwrap()?.0, IVec::from(&0_u32.to_be_bytes()));
+    drop(rx);
+    drop(db);
+    let db = config.open()?;
+    assert_eq!(db.len(), 1000);
+
+    drop(db);
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn manual_flush_tick() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .manual_flush(true)
+        .open()?;
+
+    for i in 0..100_u32 {
+        db.insert(i.to_be_bytes(), vec![0; 100])?;
+    }
+    assert!(db.tick()? > 0);
+
+    // drain the writes and any cleaning that they cause
+    for i in 0..100_u32 {
+        db.insert(i.to_be_bytes(), vec![1; 100])?;
+    }
+    let mut ticks = 0;
+    while db.tick()? > 0 {
+        ticks += 1;
+        assert!(ticks < 10_000, "ticks never ran out of work");
+    }
+    assert!(ticks > 0);
+    assert_eq!(db.tick()?, 0);
+
+    assert_eq!(db.len(), 100);
+    assert_eq!(db.get(99_u32.to_be_bytes())?, Some(vec![1; 100].into()));
+    Ok(())
+}
+
+#[test]
+fn tree_resume_cursor() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open()?;
+    for i in 0..100_u8 {
+        db.insert([i], vec![i])?;
+    }
+
+    // page through a range ten keys at a time
+    let mut keys = vec![];
+    let mut cursor = db.range([10_u8]..[50]).cursor();
+    loop {
+        let mut page = db.resume(Cursor::from_bytes(&cursor.to_bytes())?);
+        let items: Vec<_> = page.by_ref().take(10).collect::<Result<_>>()?;
+        if items.is_empty() {
+            break;
+        }
+        keys.extend(items.into_iter().map(|(k, _)| k[0]));
+        cursor = page.cursor();
+    }
+    assert_eq!(keys, (10..50).collect::<Vec<u8>>());
+
+    // resuming sees the tree as it is now
+    let mut iter = db.range([60_u8]..=[70]);
+    assert_eq!(iter.next().unwrap()?.0, vec![60]);
+    assert_eq!(iter.next_back().unwrap()?.0, vec![70]);
+    let cursor = iter.cursor();
+    db.remove([61])?;
+    db.insert([59], vec![])?;
+    db.insert([69, 1], vec![])?;
+    let rest: Vec<_> = db.resume(cursor.clone()).keys().collect::<Result<_>>()?;
+    assert_eq!(rest.first(), Some(&IVec::from(&[62])));
+    assert_eq!(rest.last(), Some(&IVec::from(&[69, 1])));
+    assert_eq!(rest.len(), 9);
+
+    // cursors work on other trees too
+    let other = db.open_tree(b"other")?;
+    other.insert([65], vec![])?;
+    assert_eq!(other.resume(cursor).count(), 1);
+
+    match Cursor::from_bytes(&[1, 9]) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected Unsupported, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_range_rev() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    // enough items to span many leaves
+    for i in 0..1000_u32 {
+        t.insert(i.to_be_bytes(), vec![])?;
+    }
+
+    let ranges: &[(u32, u32)] = &[(0, 1000), (0, 1), (999, 1000), (17, 933)];
+    for &(lo, hi) in ranges {
+        let start = lo.to_be_bytes();
+        let end = hi.to_be_bytes();
+
+        let mut forward: Vec<IVec> =
+            t.range(start..end).keys().collect::<Result<_>>()?;
+        forward.reverse();
+
+        let reverse: Vec<IVec> = t
+            .range_rev(start..end)
+            .map(|res| res.map(|(k, _v)| k))
+            .collect::<Result<_>>()?;
+
+        assert_eq!(reverse.len(), (hi - lo) as usize);
+        assert_eq!(forward, reverse);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_bulk_load() -> Result<()> {
+    common::setup_logger();
+
+    let path = "tree_bulk_load_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let mut expected = std::collections::BTreeMap::new();
+
+    // sorted keys with repeats, then unsorted keys,
+    // then another sorted run that overwrites some
+    let mut items = vec![];
+    for i in 0..500_u32 {
+        items.push((i.to_be_bytes(), i.to_le_bytes().to_vec()));
+        if i % 7 == 0 {
+            items.push((i.to_be_bytes(), (i + 1).to_le_bytes().to_vec()));
+        }
+    }
+    for i in (500..600_u32).rev() {
+        items.push((i.to_be_bytes(), i.to_le_bytes().to_vec()));
+    }
+    for i in (250..750_u32).step_by(3) {
+        items.push((i.to_be_bytes(), (i * 2).to_le_bytes().to_vec()));
+    }
+    let mut expected_events = 0;
+    for (k, v) in &items {
+        if expected.insert(k.to_vec(), v.clone()) != Some(v.clone()) {
+            expected_events += 1;
+        }
+    }
+
+    {
+        let db = Config::new().path(path).flush_every_ms(None).open()?;
+        let mut sub = db.watch_prefix(vec![]);
+
+        db.bulk_load(items.iter().cloned())?;
+
+        // every write that changed a value produces an event
+        assert_eq!((&mut sub).take(expected_events).count(), expected_events);
+        db.insert(b"sentinel", vec![])?;
+        assert_eq!(sub.next().unwrap().key(), b"sentinel");
+        db.remove(b"sentinel")?;
+
+        let mut tree = db.open_tree(b"extended")?;
+        tree.extend(items.iter().cloned());
+
+        for t in &[&*db, &tree] {
+            let actual: Vec<(Vec<u8>, Vec<u8>)> = t
+                .iter()
+                .map(|res| res.map(|(k, v)| (k.to_vec(), v.to_vec())))
+                .collect::<Result<_>>()?;
+            let expected: Vec<_> = expected.clone().into_iter().collect();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    let db = Config::new().path(path).flush_every_ms(None).open()?;
+    assert_eq!(db.len(), expected.len());
+    for (k, v) in &expected {
+        assert_eq!(db.get(k)?, Some(IVec::from(&v[..])));
+    }
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+    Ok(())
+}
+
+#[test]
+fn tree_insert_many() -> Result<()> {
+    common::setup_logger();
+
+    for &dedup in &[false, true] {
+        // small segments make the items span many leaves
+        let db = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .segment_size(4096)
+            .dedup_values(dedup)
+            .open()?;
+
+        let mut subscriber = db.watch_key(1_u32.to_be_bytes());
+
+        // every third key is present already, with the value
+        // that it is set to again, or with an older one
+        for i in (0..3000_u32).step_by(3) {
+            let value = if i % 2 == 0 { i } else { 0 };
+            db.insert(i.to_be_bytes(), &value.to_be_bytes())?;
+        }
+
+        let items: Vec<(IVec, IVec)> = (0..3000_u32)
+            .map(|i| {
+                let bytes = IVec::from(&i.to_be_bytes());
+                (bytes.clone(), bytes)
+            })
+            .collect();
+        let previous = db.insert_many(items)?;
+
+        assert_eq!(previous.len(), 3000);
+        for (i, previous) in (0..3000_u32).zip(previous) {
+            let expected = if i % 3 != 0 {
+                None
+            } else if i % 2 == 0 {
+                Some(IVec::from(&i.to_be_bytes()))
+            } else {
+                Some(IVec::from(&0_u32.to_be_bytes()))
+            };
+            assert_eq!(previous, expected, "previous value of {}", i);
+            let value = IVec::from(&i.to_be_bytes());
+            assert_eq!(db.get(i.to_be_bytes())?, Some(value));
+        }
+        assert_eq!(db.len(), 3000);
+        match subscriber.next().unwrap() {
+            Event::Insert { previous: None, .. } => {}
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // repeated keys see the value of the repetition before
+        let previous = db.insert_many(vec![
+            (IVec::from(b"k"), IVec::from(b"1")),
+            (IVec::from(b"k"), IVec::from(b"2")),
+        ])?;
+        assert_eq!(previous, vec![None, Some(IVec::from(b"1"))]);
+        assert_eq!(db.get(b"k")?, Some(IVec::from(b"2")));
+
+        // unsorted input is refused before writing anything
+        match db.insert_many(vec![
+            (IVec::from(b"y"), IVec::from(b"1")),
+            (IVec::from(b"x"), IVec::from(b"1")),
+        ]) {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert_eq!(db.get(b"y")?, None);
+
+        assert_eq!(db.insert_many(vec![])?, vec![]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_compare_and_swap_multi() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    t.insert(b"a", b"1")?;
+
+    // create `b`, which must be absent, and remove `a`
+    t.compare_and_swap_multi(vec![
+        CasOp::new(b"a", Some(b"1"), None::<&[u8]>),
+        CasOp::new(b"b", None::<&[u8]>, Some(b"2")),
+    ])?
+    .unwrap();
+    assert_eq!(t.get(b"a")?, None);
+    assert_eq!(t.get(b"b")?, Some(IVec::from(b"2")));
+
+    // the first failing condition in the order of the ops is
+    // reported, and nothing is written
+    let err = t
+        .compare_and_swap_multi(vec![
+            CasOp::new(b"b", Some(b"2"), Some(b"3")),
+            CasOp::new(b"c", Some(b"0"), Some(b"4")),
+            CasOp::new(b"a", Some(b"0"), Some(b"5")),
+        ])?
+        .unwrap_err();
+    assert_eq!(err, MultiCasError { key: IVec::from(b"c"), current: None });
+    assert_eq!(t.get(b"b")?, Some(IVec::from(b"2")));
+    assert_eq!(t.len(), 1);
+
+    // conditions are checked against the state before the
+    // call, and the last write to a key wins
+    t.compare_and_swap_multi(vec![
+        CasOp::new(b"b", Some(b"2"), Some(b"6")),
+        CasOp::new(b"b", Some(b"2"), Some(b"7")),
+    ])?
+    .unwrap();
+    assert_eq!(t.get(b"b")?, Some(IVec::from(b"7")));
+
+    t.compare_and_swap_multi(vec![])?.unwrap();
+
+    // concurrent increments of two counters that must
+    // always stay equal
+    let t = Arc::new(t);
+    t.insert(b"x", &0_u64.to_be_bytes())?;
+    t.insert(b"y", &0_u64.to_be_bytes())?;
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let t = t.clone();
+            thread::spawn(move || {
+                let mut done = 0;
+                while done < 100 {
+                    let x = t.get(b"x").unwrap().unwrap();
+                    let y = t.get(b"y").unwrap().unwrap();
+                    if x != y {
+                        continue;
+                    }
+                    let mut buf = [0; 8];
+                    buf.copy_from_slice(&x);
+                    let next = (u64::from_be_bytes(buf) + 1).to_be_bytes();
+                    let res = t
+                        .compare_and_swap_multi(vec![
+                            CasOp::new(b"x", Some(&x), Some(&next)),
+                            CasOp::new(b"y", Some(&y), Some(&next)),
+                        ])
+                        .unwrap();
+                    if res.is_ok() {
+                        done += 1;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(t.get(b"x")?, Some(IVec::from(&400_u64.to_be_bytes())));
+    assert_eq!(t.get(b"y")?, Some(IVec::from(&400_u64.to_be_bytes())));
+
+    Ok(())
+}
+
+#[test]
+fn tree_conditional_batch() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    t.insert(b"a", b"1")?;
+    t.insert(b"b", b"2")?;
+
+    // every failing condition is reported in the order of
+    // the ops, including repeated keys, and nothing is
+    // written
+    let failures = t
+        .conditional_batch(vec![
+            CasOp::new(b"c", Some(b"0"), Some(b"3")),
+            CasOp::new(b"a", Some(b"1"), Some(b"4")),
+            CasOp::new(b"b", None::<&[u8]>, Some(b"5")),
+            CasOp::new(b"c", Some(b"9"), None::<&[u8]>),
+        ])?
+        .unwrap_err();
+    assert_eq!(
+        failures,
+        vec![
+            MultiCasError { key: IVec::from(b"c"), current: None },
+            MultiCasError {
+                key: IVec::from(b"b"),
+                current: Some(IVec::from(b"2"))
+            },
+            MultiCasError { key: IVec::from(b"c"), current: None },
+        ]
+    );
+    assert_eq!(t.get(b"a")?, Some(IVec::from(b"1")));
+    assert_eq!(t.get(b"b")?, Some(IVec::from(b"2")));
+    assert_eq!(t.len(), 2);
+
+    // refreshing the stale conditions from the failures is
+    // enough for the retry to succeed
+    let mut ops = vec![
+        CasOp::new(b"a", Some(b"0"), Some(b"6")),
+        CasOp::new(b"b", Some(b"0"), None::<&[u8]>),
+        CasOp::new(b"c", None::<&[u8]>, Some(b"7")),
+    ];
+    for failure in t.conditional_batch(ops.clone())?.unwrap_err() {
+        for op in ops.iter_mut().filter(|op| op.key == failure.key) {
+            op.expected = failure.current.clone();
+        }
+    }
+    t.conditional_batch(ops)?.unwrap();
+    assert_eq!(t.get(b"a")?, Some(IVec::from(b"6")));
+    assert_eq!(t.get(b"b")?, None);
+    assert_eq!(t.get(b"c")?, Some(IVec::from(b"7")));
+
+    t.conditional_batch(vec![])?.unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn merge_returns_merged_value() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    // a counter that removes itself when it reaches zero
+    db.set_merge_operator(|_, old, new| {
+        let sum = old.map_or(0, |old| old[0] as i8) + new[0] as i8;
+        if sum == 0 { None } else { Some(vec![sum as u8]) }
+    });
+
+    assert_eq!(db.merge(b"k", [2])?, Some(IVec::from(&[2])));
+    assert_eq!(db.merge(b"k", [3])?, Some(IVec::from(&[5])));
+    assert_eq!(db.get(b"k")?, Some(IVec::from(&[5])));
+
+    // removing the key returns `None` rather than the
+    // previous value
+    assert_eq!(db.merge(b"k", [-5_i8 as u8])?, None);
+    assert_eq!(db.get(b"k")?, None);
+
+    Ok(())
+}
+
+#[test]
+fn crdt_merge_operators_converge() -> Result<()> {
+    use sled::crdt::{self, GCounter, LwwRegister, OrSet, PnCounter};
+
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+
+    let mut counters = vec![];
+    let mut pn_counters = vec![];
+    let mut registers = vec![];
+    let mut sets = vec![];
+    let mut base_set = OrSet::new();
+    base_set.insert(0, "shared");
+    for replica in 0..3_u64 {
+        let mut counter = GCounter::new();
+        counter.increment(replica, replica + 1);
+        counters.push(counter.encode());
+
+        let mut pn_counter = PnCounter::new();
+        pn_counter.increment(replica, 10);
+        pn_counter.decrement(replica, replica * 7);
+        pn_counters.push(pn_counter.encode());
+
+        let register = LwwRegister::new(replica % 2, replica, [replica as u8]);
+        registers.push(register.encode());
+
+        let mut set = base_set.clone();
+        if replica == 1 {
+            set.remove(b"shared");
+        } else {
+            set.insert(replica, vec![replica as u8]);
+        }
+        sets.push(set.encode());
+    }
+
+    let orders = [[0, 1, 2], [2, 1, 0], [1, 0, 2], [1, 2, 0]];
+    for (i, order) in orders.iter().enumerate() {
+        let counter_tree = db.open_tree(format!("counter {}", i))?;
+        counter_tree.set_merge_operator(crdt::gcounter());
+        let pn_counter_tree = db.open_tree(format!("pn {}", i))?;
+        pn_counter_tree.set_merge_operator(crdt::pn_counter());
+        let register_tree = db.open_tree(format!("register {}", i))?;
+        register_tree.set_merge_operator(crdt::lww_register());
+        let set_tree = db.open_tree(format!("set {}", i))?;
+        set_tree.set_merge_operator(crdt::or_set());
+
+        for &replica in order.iter().chain(order.iter()) {
+            counter_tree.merge(b"k", &counters[replica])?;
+            pn_counter_tree.merge(b"k", &pn_counters[replica])?;
+            register_tree.merge(b"k", &registers[replica])?;
+            set_tree.merge(b"k", &sets[replica])?;
+        }
+
+        let counter = GCounter::decode(&counter_tree.get(b"k")?.unwrap())?;
+        assert_eq!(counter.value(), 6);
+
+        let pn = PnCounter::decode(&pn_counter_tree.get(b"k")?.unwrap())?;
+        assert_eq!(pn.value(), 30 - 21);
+
+        let register =
+            LwwRegister::decode(&register_tree.get(b"k")?.unwrap())?;
+        assert_eq!(register.value(), &[1]);
+
+        let set = OrSet::decode(&set_tree.get(b"k")?.unwrap())?;
+        let elements: Vec<&[u8]> = set.iter().collect();
+        assert_eq!(elements, vec![&[0_u8][..], &[2]]);
+
+        // operands that don't decode leave the stored state alone
+        let before = set_tree.get(b"k")?;
+        set_tree.merge(b"k", &counters[0])?;
+        assert_eq!(set_tree.get(b"k")?, before);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_versions() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    db.set_merge_operator(|_, old, new| {
+        let mut ret = old.unwrap_or(&[]).to_vec();
+        ret.extend_from_slice(new);
+        Some(ret)
+    });
+
+    assert_eq!(db.get_versioned(b"k")?, None);
+
+    // absent keys only match `None`
+    db.compare_and_swap_version(b"k", None, Some(b"a"))?.unwrap();
+    let (value, v1) = db.get_versioned(b"k")?.unwrap();
+    assert_eq!(value, b"a");
+    assert_eq!(
+        db.compare_and_swap_version(b"k", None, Some(b"b"))?,
+        Err(VersionMismatch { current: Some(v1) })
+    );
+
+    // every kind of write bumps the version, even
+    // when the same bytes are written again
+    let mut last = v1;
+    db.insert(b"k", b"b")?;
+    db.insert(b"k", b"a")?;
+    db.merge(b"k", b"c")?;
+    db.compare_and_swap(b"k", Some(b"ac"), Some(b"a"))?.unwrap();
+    let mut batch = Batch::default();
+    batch.insert(b"k", b"d");
+    db.apply_batch(batch)?;
+    let res: TransactionResult<()> = db.transaction(|tx| {
+        tx.insert(b"k", b"e")?;
+        Ok(())
+    });
+    res.unwrap();
+    let (value, version) = db.get_versioned(b"k")?.unwrap();
+    assert_eq!(value, b"e");
+    assert!(version > last);
+    last = version;
+
+    // stale versions are rejected
+    assert_eq!(
+        db.compare_and_swap_version(b"k", Some(v1), Some(b"f"))?,
+        Err(VersionMismatch { current: Some(last) })
+    );
+    assert_eq!(db.get(b"k")?, Some(IVec::from(b"e")));
+
+    // removal and re-insertion
+    db.compare_and_swap_version(b"k", Some(last), None as Option<&[u8]>)?
+        .unwrap();
+    assert_eq!(db.get(b"k")?, None);
+    db.insert(b"k", b"e")?;
+    let (_, version) = db.get_versioned(b"k")?.unwrap();
+    assert!(version > last);
+    last = version;
+
+    // versions survive splits, merges and restarts
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), vec![])?;
+    }
+    let (_, before) = db.get_versioned(500_u32.to_be_bytes())?.unwrap();
+    for i in 0..1000_u32 {
+        if i != 500 {
+            db.remove(i.to_be_bytes())?;
+        }
+    }
+    assert_eq!(db.get_versioned(500_u32.to_be_bytes())?.unwrap().1, before);
+
+    db.flush()?;
+    drop(db);
+    let db = config.open()?;
+
+    assert_eq!(db.get_versioned(b"k")?.unwrap().1, last);
+    assert_eq!(db.get_versioned(500_u32.to_be_bytes())?.unwrap().1, before);
+    db.compare_and_swap_version(b"k", Some(last), Some(b"g"))?.unwrap();
+    assert!(db.get_versioned(b"k")?.unwrap().1 > last);
+
+    Ok(())
+}
+
+#[test]
+fn tree_cache_capacity_bytes() -> Result<()> {
+    common::setup_logger();
+
+    const CAPACITY: u64 = 1024 * 1024;
+
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .cache_capacity_bytes(CAPACITY)
+        .open()?;
+
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), &i.to_le_bytes())?;
+    }
+    assert!(db.cache_resident_bytes() > 0);
+
+    // large values, stored as blobs on disk, count with their
+    // full size in memory
+    let value = vec![7; 64 * 1024];
+    for i in 0..256_u32 {
+        db.insert(format!("large {}", i), value.clone())?;
+        assert!(db.cache_resident_bytes() <= CAPACITY);
+    }
+
+    for i in 0..256_u32 {
+        let read = db.get(format!("large {}", i))?;
+        assert_eq!(read, Some(IVec::from(value.clone())));
+        assert!(db.cache_resident_bytes() <= CAPACITY);
+    }
+    for i in 0..1000_u32 {
+        let read = db.get(i.to_be_bytes())?;
+        assert_eq!(read, Some(IVec::from(&i.to_le_bytes())));
+        assert!(db.cache_resident_bytes() <= CAPACITY);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_item_size_limits() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new()
+        .temporary(true)
+        .max_key_size(64)
+        .max_value_size(128)
+        .open()?;
+    db.set_merge_operator(|_, old, new| {
+        let mut ret = old.unwrap_or(&[]).to_vec();
+        ret.extend_from_slice(new);
+        Some(ret)
+    });
+
+    let at_limit = vec![0; 64];
+    let too_long = vec![0; 65];
+    let big_value = vec![0; 129];
+
+    let refused = |res: Result<()>, what: &str| match res {
+        Err(Error::Unsupported(msg)) => assert!(msg.contains(what), "{}", msg),
+        other => panic!("expected an oversized {}, got {:?}", what, other),
+    };
+
+    db.insert(&at_limit, vec![0; 128])?;
+    refused(db.insert(&too_long, vec![]).map(drop), "key of 65 bytes");
+    refused(db.insert(b"k", big_value.clone()).map(drop), "value of 129");
+    refused(
+        db.compare_and_swap(b"k", None as Option<IVec>, Some(big_value.clone()))
+            .map(drop),
+        "value of 129",
+    );
+    let mut batch = Batch::default();
+    batch.insert(b"k", b"v");
+    batch.insert(too_long.clone(), b"v");
+    refused(db.apply_batch(batch), "key of 65");
+    refused(db.bulk_load(vec![(too_long.clone(), vec![])]), "key of 65");
+    let res: TransactionResult<()> = db.transaction(|tx| {
+        tx.insert(b"k", big_value.clone())?;
+        Ok(())
+    });
+    match res {
+        Err(TransactionError::Storage(Error::Unsupported(_))) => {}
+        other => panic!("expected an oversized value, got {:?}", other),
+    }
+
+    // merges are refused when the merged value grows too large
+    db.merge(b"m", vec![0; 100])?;
+    refused(db.merge(b"m", vec![0; 29]).map(drop), "value of 129");
+    assert_eq!(db.get(b"m")?.unwrap().len(), 100);
+
+    // removals of oversized keys are harmless
+    assert_eq!(db.remove(&too_long)?, None);
+
+    // refused writes had no effect
+    assert_eq!(db.len(), 2);
+
+    // the ceiling is enforced when opening
+    assert!(Config::new()
+        .temporary(true)
+        .max_key_size((1 << 30) + 1)
+        .open()
+        .is_err());
+
+    // large keys and values are supported
+    // up to the configured limits
+    let db = Config::new()
+        .temporary(true)
+        .max_key_size(64 * 1024 * 1024)
+        .open()?;
+    let big_key = vec![1; 20 * 1024 * 1024];
+    db.insert(&big_key, vec![2; 20 * 1024 * 1024])?;
+    for i in 0..100_u32 {
+        db.insert(i.to_be_bytes(), vec![])?;
+    }
+    assert_eq!(db.get(&big_key)?.unwrap().len(), 20 * 1024 * 1024);
+
+    Ok(())
+}
+
+#[test]
+fn db_move_range() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+    let hot = db.open_tree(b"hot")?;
+    let cold = db.open_tree(b"cold")?;
+
+    for i in 0..1000_u32 {
+        hot.insert(i.to_be_bytes(), &i.to_le_bytes())?;
+    }
+    cold.insert(10_u32.to_be_bytes(), b"old")?;
+
+    let start = 0_u32.to_be_bytes();
+    let end = 500_u32.to_be_bytes();
+    assert_eq!(db.move_range(&hot, &cold, start..end)?, 500);
+    assert_eq!(hot.len(), 500);
+    assert_eq!(cold.len(), 500);
+    assert_eq!(hot.iter().next().unwrap()?.0, end);
+    assert_eq!(
+        cold.get(10_u32.to_be_bytes())?,
+        Some(IVec::from(&10_u32.to_le_bytes()))
+    );
+
+    // an empty range moves nothing
+    assert_eq!(db.move_range(&hot, &cold, start..end)?, 0);
+
+    // the default tree can take part too
+    assert_eq!(db.move_range::<&[u8], _>(&cold, &db, ..)?, 500);
+    assert!(cold.is_empty()?);
+
+    assert!(db.move_range::<&[u8], _>(&hot, &hot, ..).is_err());
+    let other = Config::new().temporary(true).open()?;
+    assert!(db.move_range::<&[u8], _>(&hot, &other, ..).is_err());
+
+    db.flush()?;
+    drop((hot, cold));
+    drop(db);
+
+    let db = config.open()?;
+    assert_eq!(db.len(), 500);
+    assert_eq!(db.open_tree(b"hot")?.len(), 500);
+    assert!(db.open_tree(b"cold")?.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+fn tree_iter_keys_and_values() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .cache_capacity(64 * 1024);
+    let db = config.open()?;
+
+    for i in 0..1000_u32 {
+        db.insert(i.to_be_bytes(), i.to_le_bytes().repeat(64))?;
+    }
+    db.flush()?;
+    drop(db);
+
+    // leaves are read back from disk
+    let db = config.open()?;
+    let keys: Vec<IVec> = db.iter_keys().collect::<Result<_>>()?;
+    let values: Vec<IVec> = db.iter_values().collect::<Result<_>>()?;
+    let items: Vec<(IVec, IVec)> = db.iter().collect::<Result<_>>()?;
+    assert_eq!(keys.len(), 1000);
+    assert_eq!(keys, items.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>());
+    assert_eq!(
+        values,
+        items.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>()
+    );
+
+    let last = db.iter_keys().next_back().unwrap()?;
+    assert_eq!(last, 999_u32.to_be_bytes());
+
+    Ok(())
+}
+
+#[test]
+fn tree_latency_stats() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open()?;
+    db.insert(b"a", vec![1])?;
+    assert_eq!(db.latency_stats(), sled::LatencyStats::default());
+
+    let db = Config::new()
+        .temporary(true)
+        .collect_latency_histograms(true)
+        .open()?;
+    let other = db.open_tree(b"other")?;
+
+    for i in 0..10_u8 {
+        db.insert([i], vec![i])?;
+        other.insert([i], vec![i])?;
+    }
+    for i in 0..5_u8 {
+        assert_eq!(other.get([i])?, Some(IVec::from(&[i])));
+    }
+    db.flush()?;
+
+    let stats = db.latency_stats();
+    assert_eq!(stats.insert.count, 20);
+    assert_eq!(stats.get.count, 5);
+    assert_eq!(stats.flush.count, 1);
+
+    for op in &[stats.get, stats.insert, stats.flush] {
+        assert!(op.p50 <= op.p90);
+        assert!(op.p90 <= op.p99);
+        assert!(op.p99 <= op.p999);
+        assert!(op.p999 <= op.max);
+        assert!(op.max > std::time::Duration::from_nanos(0));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_dedup_values() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .dedup_values(true);
+    let db = config.open()?;
+    let other = db.open_tree(b"other")?;
+
+    let big = IVec::from(vec![1; 1000]);
+    let small = IVec::from(b"small");
+
+    db.insert(b"a", big.clone())?;
+    db.insert(b"b", big.clone())?;
+    db.insert(b"c", small.clone())?;
+    other.insert(b"a", big.clone())?;
+
+    assert_eq!(db.get(b"a")?, Some(big.clone()));
+    assert_eq!(&*db.get_pinned(b"b")?.unwrap(), &*big);
+    assert_eq!(db.get_versioned(b"c")?.unwrap().0, small);
+    assert_eq!(
+        db.multi_get(vec![b"a", b"c"])?,
+        vec![Some(big.clone()), Some(small.clone())]
+    );
+
+    // the value outlives the keys that no longer refer to it
+    assert_eq!(db.remove(b"a")?, Some(big.clone()));
+    assert_eq!(db.insert(b"b", small.clone())?, Some(big.clone()));
+    assert_eq!(other.get(b"a")?, Some(big.clone()));
+
+    // conditions and merges see the values themselves
+    db.compare_and_swap(b"b", Some(small.clone()), Some(big.clone()))?
+        .unwrap();
+    assert_eq!(db.get(b"b")?, Some(big.clone()));
+    db.compare_and_swap_multi(vec![
+        CasOp::new(b"b", Some(&big), Some(&small)),
+        CasOp::new(b"c", Some(&small), Some(&big)),
+    ])?
+    .unwrap();
+    assert_eq!(
+        db.compare_and_swap_multi(vec![CasOp::new(
+            b"c",
+            Some(&small),
+            Some(&small)
+        )])?,
+        Err(MultiCasError { key: IVec::from(b"c"), current: Some(big.clone()) })
+    );
+    assert_eq!(
+        db.conditional_batch(vec![
+            CasOp::new(b"b", Some(&small), Some(&big)),
+            CasOp::new(b"c", Some(&small), Some(&small)),
+        ])?,
+        Err(vec![MultiCasError {
+            key: IVec::from(b"c"),
+            current: Some(big.clone())
+        }])
+    );
+    db.conditional_batch(vec![
+        CasOp::new(b"b", Some(&small), Some(&big)),
+        CasOp::new(b"c", Some(&big), Some(&small)),
+    ])?
+    .unwrap();
+    assert_eq!(db.get(b"b")?, Some(big.clone()));
+    assert_eq!(db.get(b"c")?, Some(small.clone()));
+    db.set_merge_operator(|_, old, new| {
+        let mut ret = old.unwrap_or(&[]).to_vec();
+        ret.extend_from_slice(new);
+        Some(ret)
+    });
+    db.merge(b"m", vec![2; 100])?;
+    db.merge(b"m", vec![3; 100])?;
+    assert_eq!(db.get(b"m")?.unwrap().len(), 200);
+
+    let res: TransactionResult<()> = db.transaction(|tx| {
+        assert_eq!(tx.get(b"b")?, Some(big.clone()));
+        tx.insert(b"t", big.clone())?;
+        tx.remove(b"b")?;
+        Ok(())
+    });
+    res.unwrap();
+
+    let items: Vec<(IVec, IVec)> = db.iter().collect::<Result<_>>()?;
+    let keys: Vec<&[u8]> = items.iter().map(|(k, _)| &**k).collect();
+    assert_eq!(keys, vec![&b"c"[..], b"m", b"t"]);
+    assert_eq!(items[2].1, big);
+    let mut reversed: Vec<(IVec, IVec)> =
+        db.iter().rev().collect::<Result<_>>()?;
+    reversed.reverse();
+    assert_eq!(reversed, items);
+    let total =
+        db.fold_range::<&[u8], _, _, _>(.., 0, |acc, _, v| acc + v.len())?;
+    assert_eq!(total, small.len() + 200 + big.len());
+
+    drop(other);
+    drop(db);
+    let db = config.open()?;
+    assert_eq!(db.get(b"t")?, Some(big.clone()));
+    let other = db.open_tree(b"other")?;
+    assert_eq!(other.get(b"a")?, Some(big.clone()));
+
+    // the store of shared values is internal
+    assert_eq!(db.tree_names()?.len(), 2);
+    assert!(db.open_tree(b"__sled__dedup").is_err());
+    assert!(db.drop_tree(b"__sled__dedup").is_err());
+
+    drop(other);
+    assert!(db.drop_tree(b"other")?);
+    assert_eq!(db.get(b"t")?, Some(big));
+
+    // the setting cannot change for an existing database
+    drop(db);
+    assert!(config.dedup_values(false).open().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn checksum_algorithms() -> Result<()> {
+    common::setup_logger();
+
+    let path = "checksum_algorithm_db";
+    for &algorithm in &[
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::XxHash,
+        ChecksumAlgorithm::None,
+    ] {
+        let _ = std::fs::remove_dir_all(path);
+
+        // small segments, so that the log pads and fills
+        // several of them
+        let config = Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .segment_size(256)
+            .checksum_algorithm(algorithm);
+
+        {
+            let db = config.open()?;
+            for i in 0..N_PER_THREAD {
+                db.insert(kv(i), kv(i))?;
+            }
+            db.insert(b"big", vec![7; 1024])?;
+            db.flush()?;
+        }
+
+        let db = config.open()?;
+        assert!(db.was_recovered());
+        assert_eq!(db.len(), N_PER_THREAD + 1, "{:?}", algorithm);
+        for i in 0..N_PER_THREAD {
+            assert_eq!(db.get(kv(i))?, Some(kv(i).into()));
+        }
+        drop(db);
+
+        // the algorithm is stored with the database
+        let other = if algorithm == ChecksumAlgorithm::Crc32 {
+            ChecksumAlgorithm::Crc32c
+        } else {
+            ChecksumAlgorithm::Crc32
+        };
+        match config.checksum_algorithm(other).open() {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("expected an error, got {:?}", other.is_ok()),
+        }
+    }
+
+    std::fs::remove_dir_all(path).unwrap();
+    Ok(())
+}
+
+#[test]
+fn flush_dirty_threshold() -> Result<()> {
+    common::setup_logger();
+
+    // the periodic flush would not happen during the test,
+    // so only the threshold can make the write durable
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(Some(60_000))
+        .flush_jitter(std::time::Duration::from_millis(100))
+        .flush_dirty_threshold(1)
+        .open()?;
+
+    db.insert(b"a", vec![0; 100])?;
+    let start = std::time::Instant::now();
+    while db.get_with_durability(b"a")?.unwrap().1 != Durability::Durable {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn set_flush_every_ms() -> Result<()> {
+    common::setup_logger();
+
+    let wait_durable = |db: &sled::Db, key: &[u8]| -> Result<()> {
+        let start = std::time::Instant::now();
+        while db.get_with_durability(key)?.unwrap().1 != Durability::Durable {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        Ok(())
+    };
+
+    // starts a flusher where there was none
+    let db = Config::new().temporary(true).flush_every_ms(None).open()?;
+    db.insert(b"a", b"a")?;
+    db.set_flush_every_ms(Some(1));
+    wait_durable(&db, b"a")?;
+
+    // wakes a flusher that would otherwise sleep through the test
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(Some(60_000))
+        .open()?;
+    db.insert(b"b", b"b")?;
+    db.set_flush_every_ms(Some(1));
+    wait_durable(&db, b"b")?;
+
+    // once stopped, the flusher leaves writes pending, and
+    // it can be started again afterwards
+    db.set_flush_every_ms(None);
+    db.set_flush_every_ms(None);
+    db.insert(b"c", b"c")?;
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert_eq!(db.get_with_durability(b"c")?.unwrap().1, Durability::Pending);
+    db.set_flush_every_ms(Some(1));
+    wait_durable(&db, b"c")?;
+
+    // with a test clock, only `Db::step` flushes
+    let clock = Arc::new(sled::VirtualClock::new());
+    let db = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .test_clock(clock.clone())
+        .open()?;
+    db.insert(b"e", b"e")?;
+    clock.advance(std::time::Duration::from_millis(10));
+    db.step()?;
+    assert_eq!(db.get_with_durability(b"e")?.unwrap().1, Durability::Pending);
+    db.set_flush_every_ms(Some(5));
+    clock.advance(std::time::Duration::from_millis(10));
+    db.step()?;
+    assert_eq!(db.get_with_durability(b"e")?.unwrap().1, Durability::Durable);
+
+    Ok(())
+}
+
+#[test]
+fn coalesce_writes() -> Result<()> {
+    common::setup_logger();
+
+    let path = "coalesce_writes_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let hot_writes = |db: &sled::Db| -> Result<usize> {
+        for i in 0..1000_u32 {
+            db.insert(b"hot", &i.to_be_bytes())?;
+        }
+        db.flush()
+    };
+
+    let plain = Config::new().temporary(true).flush_every_ms(None).open()?;
+    let plain_bytes = hot_writes(&plain)?;
+
+    let config = Config::new()
+        .path(path)
+        .flush_every_ms(None)
+        .coalesce_writes(true);
+    let db = config.open()?;
+    let subscriber = db.watch_key(b"hot");
+
+    // writes are visible right away, but not durable until
+    // the next flush
+    db.insert(b"hot", b"first")?;
+    assert_eq!(
+        db.get_with_durability(b"hot")?,
+        Some((IVec::from(b"first"), Durability::Pending))
+    );
+    assert!(db.flush()? > 0);
+    assert_eq!(db.flush()?, 0);
+    let coalesced_bytes = hot_writes(&db)?;
+    assert!(
+        coalesced_bytes * 10 < plain_bytes,
+        "{} >= {} / 10",
+        coalesced_bytes,
+        plain_bytes
+    );
+    assert_eq!(
+        db.get_with_durability(b"hot")?,
+        Some((IVec::from(&999_u32.to_be_bytes()), Durability::Durable))
+    );
+
+    // subscribers still see every write
+    assert_eq!(subscriber.take(1001).count(), 1001);
+
+    // methods that wait for durability log coalesced writes
+    db.remove(b"hot")?;
+    db.insert(b"warm", b"warm")?;
+    assert_eq!(db.get_durable(b"warm")?, Some(IVec::from(b"warm")));
+    assert_eq!(db.get_with_durability(b"hot")?, None);
+
+    // leaves with coalesced writes are logged before they
+    // are paged out of a small cache
+    drop(db);
+    let db = config.clone().cache_capacity(16 * 1024).open()?;
+    for i in 0..10_000_u32 {
+        db.insert(i.to_be_bytes(), vec![1; 16])?;
+    }
+    drop(db);
+
+    let db = config.open()?;
+    assert!(db.was_recovered());
+    assert_eq!(db.get(b"hot")?, None);
+    assert_eq!(db.get(b"warm")?, Some(IVec::from(b"warm")));
+    assert_eq!(db.len(), 10_001);
+    for i in 0..10_000_u32 {
+        assert_eq!(db.get(i.to_be_bytes())?, Some(IVec::from(vec![1; 16])));
+    }
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn io_threads() -> Result<()> {
+    common::setup_logger();
+
+    let path = "io_threads_db";
+
+    for threads in &[0, 1, 4] {
+        let _ = std::fs::remove_dir_all(path);
+        let config = Config::new()
+            .path(path)
+            .segment_size(256)
+            .io_threads(*threads);
+
+        {
+            let db = Arc::new(config.open()?);
+            let writers: Vec<_> = (0..N_THREADS)
+                .map(|t| {
+                    let db = db.clone();
+                    thread::spawn(move || {
+                        for i in 0..N_PER_THREAD {
+                            let key = (t * N_PER_THREAD + i) as u32;
+                            db.insert(key.to_be_bytes(), vec![0; 64]).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            db.flush()?;
+        }
+
+        let db = config.open()?;
+        assert_eq!(db.len(), N);
+        drop(db);
+    }
+
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[test]
+fn tree_durability() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let db = config.open()?;
+
+    assert_eq!(db.get_with_durability(b"a")?, None);
+    assert_eq!(db.get_durable(b"a")?, None);
+
+    db.insert(b"a", b"1")?;
+    assert_eq!(
+        db.get_with_durability(b"a")?,
+        Some((IVec::from(b"1"), Durability::Pending))
+    );
+
+    db.flush()?;
+    assert_eq!(
+        db.get_with_durability(b"a")?,
+        Some((IVec::from(b"1"), Durability::Durable))
+    );
+
+    // get_durable flushes pending writes before returning
+    db.insert(b"a", b"2")?;
+    assert_eq!(db.get_durable(b"a")?, Some(IVec::from(b"2")));
+    assert_eq!(
+        db.get_with_durability(b"a")?,
+        Some((IVec::from(b"2"), Durability::Durable))
+    );
+
+    // an unflushed write to another key in the same leaf
+    // makes the whole leaf pending
+    db.insert(b"b", b"1")?;
+    assert_eq!(
+        db.get_with_durability(b"a")?,
+        Some((IVec::from(b"2"), Durability::Pending))
+    );
+    assert_eq!(db.get_durable(b"a")?, Some(IVec::from(b"2")));
+
+    drop(db);
+    let db = config.open()?;
+    assert_eq!(db.get(b"b")?, Some(IVec::from(b"1")));
+
+    Ok(())
+}
+
+#[test]
+fn tree_insert_durable() -> Result<()> {
+    common::setup_logger();
+
+    for dedup in &[false, true] {
+        let config = Config::new()
+            .temporary(true)
+            .flush_every_ms(None)
+            .dedup_values(*dedup);
+        let db = config.open()?;
+
+        db.insert(b"a", b"1")?;
+        assert_eq!(db.insert_durable(b"b", b"1")?, None);
+        assert_eq!(
+            db.get_with_durability(b"b")?,
+            Some((IVec::from(b"1"), Durability::Durable))
+        );
+        // earlier writes are made durable along with it
+        assert_eq!(
+            db.get_with_durability(b"a")?,
+            Some((IVec::from(b"1"), Durability::Durable))
+        );
+
+        assert_eq!(db.insert_durable(b"b", b"2")?, Some(IVec::from(b"1")));
+        assert_eq!(db.remove_durable(b"a")?, Some(IVec::from(b"1")));
+        assert_eq!(db.remove_durable(b"a")?, None);
+        assert_eq!(
+            db.get_with_durability(b"b")?,
+            Some((IVec::from(b"2"), Durability::Durable))
+        );
+
+        drop(db);
+        let db = config.open()?;
+        assert_eq!(db.get(b"a")?, None);
+        assert_eq!(db.get(b"b")?, Some(IVec::from(b"2")));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn tree_checksum() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).flush_every_ms(None).open()?;
+    let a = db.open_tree(b"a")?;
+    let b = db.open_tree(b"b")?;
+
+    assert_eq!(a.checksum()?, 0);
+    assert!(a.checksum_ranges(1)?.is_empty());
+
+    // different write orders, overwrites, removals and bulk
+    // loading produce different layouts of the same items
+    for i in 0..500_u32 {
+        a.insert(i.to_be_bytes(), &i.to_le_bytes())?;
+    }
+    for i in (0..600_u32).rev() {
+        b.insert(i.to_be_bytes(), vec![1])?;
+    }
+    b.bulk_load(
+        (0..500_u32).map(|i| (i.to_be_bytes(), i.to_le_bytes().to_vec())),
+    )?;
+    for i in 500..600_u32 {
+        b.remove(i.to_be_bytes())?;
+    }
+    assert_eq!(a.checksum()?, b.checksum()?);
+    assert_eq!(a.checksum_ranges(3)?, b.checksum_ranges(3)?);
+
+    // cached results are invalidated by writes
+    let before = a.checksum()?;
+    let ranges_before = a.checksum_ranges(3)?;
+    a.insert(7_u32.to_be_bytes(), vec![7])?;
+    assert_ne!(a.checksum()?, before);
+    let ranges_after = a.checksum_ranges(3)?;
+    let changed: Vec<_> = ranges_before
+        .iter()
+        .zip(&ranges_after)
+        .filter(|(l, r)| l != r)
+        .map(|(l, _r)| l.0.clone())
+        .collect();
+    assert_eq!(changed, vec![IVec::from(&[0, 0, 0])]);
+    a.insert(7_u32.to_be_bytes(), &7_u32.to_le_bytes())?;
+    assert_eq!(a.checksum()?, before);
+
+    // the checksum is the wrapping sum of the range checksums
+    for granularity in 0..5 {
+        let ranges = a.checksum_ranges(granularity)?;
+        let sum = ranges.iter().fold(0_u64, |acc, r| acc.wrapping_add(r.1));
+        assert_eq!(sum, a.checksum()?);
+    }
+    assert_eq!(a.checksum_ranges(0)?.len(), 1);
+    assert_eq!(a.checksum_ranges(3)?.len(), 2);
+    assert_eq!(a.checksum_ranges(4)?.len(), 500);
+
+    // keys shorter than the granularity are grouped on
+    // their whole key
+    let c = db.open_tree(b"c")?;
+    c.insert(b"a", b"")?;
+    c.insert(b"ab", b"")?;
+    c.insert(b"abc", b"")?;
+    c.insert(b"abd", b"")?;
+    let prefixes: Vec<IVec> =
+        c.checksum_ranges(2)?.into_iter().map(|r| r.0).collect();
+    assert_eq!(prefixes, vec![IVec::from(b"a"), IVec::from(b"ab")]);
+
+    // moving bytes between the key and the value
+    // changes the checksum
+    let d = db.open_tree(b"d")?;
+    let e = db.open_tree(b"e")?;
+    d.insert(b"ab", b"c")?;
+    e.insert(b"a", b"bc")?;
+    assert_ne!(d.checksum()?, e.checksum()?);
+
+    // checksums are compared across processes and versions,
+    // so they must never change
+    assert_eq!(d.checksum()?, 0x482f_6f54_3bbc_5465);
+
+    Ok(())
+}
+
+#[test]
+fn tree_multi_get() -> Result<()> {
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    for i in (0..1000_u32).step_by(2) {
+        t.insert(i.to_be_bytes(), &i.to_le_bytes())?;
+    }
+
+    // unsorted, with duplicates, missing keys and keys
+    // spread across many leaves
+    let keys: Vec<u32> = vec![998, 0, 1, 500, 2, 999, 500, 4000, 17, 256];
+    let values = t.multi_get(keys.iter().map(|k| k.to_be_bytes()))?;
+
+    assert_eq!(values.len(), keys.len());
+    for (k, v) in keys.iter().zip(&values) {
+        assert_eq!(v, &t.get(k.to_be_bytes())?, "wrong value for {}", k);
+    }
+    assert_eq!(values[0], Some(IVec::from(&998_u32.to_le_bytes())));
+    assert_eq!(values[2], None);
+
+    let all: Vec<u32> = (0..1000).rev().collect();
+    let values = t.multi_get(all.iter().map(|k| k.to_be_bytes()))?;
+    for (k, v) in all.iter().zip(values) {
+        let expected = if k % 2 == 0 {
+            Some(IVec::from(&k.to_le_bytes()))
+        } else {
+            None
+        };
+        assert_eq!(v, expected);
+    }
+
+    assert!(t.multi_get(Vec::<&[u8]>::new())?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn tree_fold_range() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    // enough items to span many nodes
+    for i in 0..1000_u64 {
+        t.insert(i.to_be_bytes(), &i.to_le_bytes())?;
+    }
+
+    let sum = |acc: u64, _k: &[u8], v: &[u8]| {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(v);
+        acc + u64::from_le_bytes(buf)
+    };
+
+    let naive = |lo: u64, hi: u64| -> Result<u64> {
+        t.range(lo.to_be_bytes()..hi.to_be_bytes())
+            .try_fold(0, |acc, res| res.map(|(k, v)| sum(acc, &k, &v)))
+    };
+
+    let ranges: &[(u64, u64)] =
+        &[(0, 1000), (5, 6), (17, 933), (500, 500), (990, 2000)];
+    for &(lo, hi) in ranges {
+        let lo_key = lo.to_be_bytes();
+        let hi_key = hi.to_be_bytes();
+        let folded = t.fold_range(&lo_key[..]..&hi_key[..], 0, sum)?;
+        assert_eq!(folded, naive(lo, hi)?);
+    }
+
+    let inclusive = 100_u64.to_be_bytes();
+    let folded = t.fold_range(&inclusive[..]..=&inclusive[..], 0, sum)?;
+    assert_eq!(folded, 100);
+
+    let count = t.fold_range::<&[u8], _, _, _>(.., 0, |c, _, _| c + 1)?;
+    assert_eq!(count, 1000);
+
+    // keys must be visited in order, and terminate early
+    let mut seen = 0;
+    let res = t.try_fold_range::<&[u8], _, _, _, _>(.., 0_u64, |n, k, _| {
+        seen += 1;
+        assert_eq!(k, &n.to_be_bytes()[..]);
+        if n == 499 { Err(n) } else { Ok(n + 1) }
+    })?;
+    assert_eq!(res, Err(499));
+    assert_eq!(seen, 500);
+
+    Ok(())
+}
+
+#[test]
+fn tree_get_pinned() -> Result<()> {
+    common::setup_logger();
+
+    let config = Config::new().temporary(true).flush_every_ms(None);
+    let t = config.open()?;
+
+    let big = vec![7; 4096];
+    t.insert(b"small", b"v1")?;
+    t.insert(b"big", big.clone())?;
+
+    let small_pin = t.get_pinned(b"small")?.unwrap();
+    let big_pin = t.get_pinned(b"big")?.unwrap();
+    assert_eq!(small_pin, b"v1");
+    assert_eq!(big_pin, big);
+
+    // overwrite, remove, and churn the tree while
+    // the pins are held
+    t.insert(b"small", b"v2")?;
+    t.remove(b"big")?;
+    for i in 0..N_PER_THREAD {
+        let k = kv(i);
+        t.insert(&k, k.clone())?;
+    }
+
+    assert_eq!(small_pin, b"v1");
+    assert_eq!(big_pin, big);
+    assert_eq!(small_pin.to_ivec(), IVec::from(b"v1"));
+
+    assert_eq!(t.get_pinned(b"small")?.unwrap(), b"v2");
+    assert!(t.get_pinned(b"big")?.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn recover_tree() {
+    common::setup_logger();
+
+    let config =
+        Config::new().temporary(true).flush_every_ms(None).segment_size(4096);
+
+    let t = config.open().unwrap();
+    for i in 0..N_PER_THREAD {
+        let k = kv(i);
+        t.insert(&k, k.clone()).unwrap();
+    }
+    drop(t);
+
+    let t = config.open().unwrap();
+    for i in 0..N_PER_THREAD {
+        let k = kv(i as usize);
+        assert_eq!(t.get(&*k).unwrap().unwrap(), k);
+        t.remove(&*k).unwrap();
+    }
+    drop(t);
+
+    let t = config.open().unwrap();
+    for i in 0..N_PER_THREAD {
+        let k = kv(i as usize);
+        assert_eq!(t.get(&*k), Ok(None));
+    }
+}
+
+#[test]
+fn close_and_reopen() -> Result<()> {
+    common::setup_logger();
+
+    let path = "close_and_reopen_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    for i in 0..N_PER_THREAD {
+        let db = Config::new().path(path).open()?;
+        let k = kv(i);
+        db.insert(&k, k.clone())?;
+        db.close()?;
+    }
+
+    let db = Config::new().path(path).open()?;
+    assert_eq!(db.len(), N_PER_THREAD);
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+    Ok(())
+}
+
+#[test]
+fn close_keeps_flushing_other_handles() -> Result<()> {
+    use std::sync::{mpsc::channel, Mutex};
+
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).flush_every_ms(Some(10)).open()?;
+    let tree = db.open_tree(b"tree")?;
+
+    let (tx, rx) = channel();
+    let tx = Mutex::new(tx);
+    db.on_flush(Box::new(move |info| {
+        let _ = tx.lock().unwrap().send(info);
+    }));
+
+    db.close()?;
+    while rx.try_recv().is_ok() {}
+
+    // nothing but the background flusher writes this out
+    tree.insert(b"k", vec![0; 64])?;
+    let info = rx
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .expect("the flusher should still run after close");
+    assert!(info.bytes_flushed >= 64);
+
+    Ok(())
+}
+
+#[test]
+fn close_snapshots_log_tip() -> Result<()> {
+    common::setup_logger();
+
+    let path = "close_snapshots_log_tip_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let snapshots = || -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("snap."))
+            .collect();
+        names.sort();
+        names
+    };
+    let config = Config::new().path(path).segment_size(4096);
+    let write = |tree: &Tree, round: usize| -> Result<()> {
+        for i in 0..1000 {
+            tree.insert(kv(i), vec![round as u8; 32])?;
+        }
+        Ok(())
+    };
+
+    // dropping leaves the log to be read by the next recovery,
+    // which advances the snapshot past it
+    let db = config.open()?;
+    write(&db, 1)?;
+    drop(db);
+    let before = snapshots();
+    drop(config.open()?);
+    let after = snapshots();
+    assert_eq!(after.len(), 1);
+    assert_ne!(before, after);
+
+    // a closed database already has a snapshot at the tip of
+    // the log, so recovery has nothing to advance it with
+    let db = config.open()?;
+    write(&db, 2)?;
+    db.close()?;
+    let closed = snapshots();
+    assert_ne!(closed, after);
+    let db = config.open()?;
+    assert_eq!(snapshots(), closed);
+    assert_eq!(db.len(), 1000);
+    assert_eq!(db.get(kv(999))?, Some(vec![2; 32].into()));
+
+    // writing after the clean close makes the snapshot stale,
+    // and recovery reads the rest of the log again
+    write(&db, 3)?;
+    drop(db);
+    let db = config.open()?;
+    assert_ne!(snapshots(), closed);
+    assert_eq!(db.get(kv(999))?, Some(vec![3; 32].into()));
+
+    // other handles keep the storage files open past close,
+    // and the snapshot is written once they are gone
+    let tree = db.open_tree(b"tree")?;
+    write(&tree, 4)?;
+    let before = snapshots();
+    db.close()?;
+    assert_eq!(snapshots(), before);
+    drop(tree);
+    let closed = snapshots();
+    assert_ne!(closed, before);
+    let db = config.open()?;
+    assert_eq!(snapshots(), closed);
+    assert_eq!(db.open_tree(b"tree")?.len(), 1000);
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct MemIoBackend {
+    data: std::sync::Mutex<Vec<u8>>,
+    syncs: std::sync::atomic::AtomicUsize,
+    fail_writes: std::sync::atomic::AtomicBool,
+    disk_full: std::sync::atomic::AtomicBool,
+    full_writes: std::sync::atomic::AtomicUsize,
+}
+
+#[cfg(not(windows))]
+fn no_space_error() -> std::io::Error {
+    std::io::Error::from_raw_os_error(libc::ENOSPC)
+}
+
+#[cfg(windows)]
+fn no_space_error() -> std::io::Error {
+    // ERROR_DISK_FULL
+    std::io::Error::from_raw_os_error(112)
+}
+
+impl IoBackend for MemIoBackend {
+    fn pread_exact_or_eof(
+        &self,
+        buf: &mut [u8],
+        offset: LogOffset,
+    ) -> std::io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let start = std::cmp::min(offset as usize, data.len());
+        let len = std::cmp::min(buf.len(), data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn pwrite_all(&self, buf: &[u8], offset: LogOffset) -> std::io::Result<()> {
+        if self.fail_writes.load(std::sync::atomic::Ordering::SeqCst) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "injected write failure",
+            ));
+        }
+        if self.disk_full.load(std::sync::atomic::Ordering::SeqCst) {
+            self.full_writes.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            return Err(no_space_error());
+        }
+        let mut data = self.data.lock().unwrap();
+        let end = offset as usize + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset as usize..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn sync_all(&self) -> std::io::Result<()> {
+        self.syncs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.data.lock().unwrap().len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.data.lock().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_io_backend() -> Result<()> {
+    common::setup_logger();
+
+    let path = "custom_io_backend_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let backend = Arc::new(MemIoBackend::default());
+    let config = Config::new()
+        .path(path)
+        .flush_every_ms(None)
+        .io_backend(backend.clone());
+
+    {
+        let db = config.open()?;
+        for i in 0..N_PER_THREAD {
+            db.insert(kv(i), kv(i))?;
+        }
+        db.flush()?;
+    }
+
+    assert!(backend.syncs.load(std::sync::atomic::Ordering::SeqCst) > 0);
+    assert!(!std::path::Path::new(path).join("db").exists());
+
+    let db = config.open()?;
+    assert!(db.was_recovered());
+    assert_eq!(db.len(), N_PER_THREAD);
+    for i in 0..N_PER_THREAD {
+        assert_eq!(db.get(kv(i))?, Some(kv(i).into()));
+    }
+
+    // injected faults are surfaced as io errors
+    backend.fail_writes.store(true, std::sync::atomic::Ordering::SeqCst);
+    db.insert(b"k", b"v")?;
+    match db.flush() {
+        Err(Error::Io(_)) => {}
+        other => panic!("expected an io error, got {:?}", other),
+    }
+    backend.fail_writes.store(false, std::sync::atomic::Ordering::SeqCst);
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+    Ok(())
+}
+
+#[test]
+fn cleanup_temporaries() -> Result<()> {
+    common::setup_logger();
+
+    let base_dir = std::path::Path::new("cleanup_temporaries_db");
+    let _ = std::fs::remove_dir_all(base_dir);
+
+    let live = base_dir.join("live");
+    let db = Config::new().path(&live).temporary(true).open()?;
+    db.insert(b"k", b"v")?;
+    db.flush()?;
+
+    // a copy of the files of a temporary database is what a
+    // crashed process leaves behind: marked, but not locked
+    let crashed = base_dir.join("crashed");
+    std::fs::create_dir_all(crashed.join("blobs"))?;
+    for entry in std::fs::read_dir(&live)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            std::fs::copy(entry.path(), crashed.join(entry.file_name()))?;
+        }
+    }
+
+    // databases that are not temporary are left alone
+    let persistent = base_dir.join("persistent");
+    drop(Config::new().path(&persistent).open()?);
+
+    assert_eq!(Db::cleanup_temporaries(base_dir)?, 1);
+    assert!(!crashed.exists());
+    assert!(live.exists());
+    assert!(persistent.exists());
+    assert_eq!(db.get(b"k")?, Some(IVec::from(b"v")));
+
+    drop(db);
+    assert!(!live.exists());
+    assert_eq!(Db::cleanup_temporaries(base_dir)?, 0);
+
+    std::fs::remove_dir_all(base_dir).unwrap();
+    Ok(())
+}
+
+#[test]
+fn on_disk_full() -> Result<()> {
+    use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+
+    common::setup_logger();
+
+    let path = "on_disk_full_db";
+
+    // fills the disk, and returns once a log write has failed
+    // because of it and the failure had time to be noticed
+    fn fill(backend: &MemIoBackend, db: &Db) -> thread::JoinHandle<()> {
+        let before = backend.full_writes.load(SeqCst);
+        backend.disk_full.store(true, SeqCst);
+        let db = db.clone();
+        let flusher = thread::spawn(move || {
+            db.flush().unwrap();
+        });
+        while backend.full_writes.load(SeqCst) == before {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        thread::sleep(std::time::Duration::from_millis(50));
+        flusher
+    }
+
+    for &on_full in &[OnFull::Reject, OnFull::Block] {
+        let _ = std::fs::remove_dir_all(path);
+        let backend = Arc::new(MemIoBackend::default());
+        let config = Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .io_backend(backend.clone())
+            .on_disk_full(on_full);
+
+        {
+            let db = config.open()?;
+            db.insert(b"a", b"a")?;
+
+            let flusher = fill(&backend, &db);
+
+            // reads keep working
+            assert_eq!(db.get(b"a")?, Some(IVec::from(b"a")));
+
+            if on_full == OnFull::Reject {
+                match db.insert(b"b", b"b") {
+                    Err(Error::Io(ref e))
+                        if e.raw_os_error()
+                            == no_space_error().raw_os_error() => {}
+                    other => panic!("expected ENOSPC, got {:?}", other),
+                }
+                assert_eq!(db.get(b"b")?, None);
+                backend.disk_full.store(false, SeqCst);
+            } else {
+                let inserted = Arc::new(AtomicBool::new(false));
+                let writer = {
+                    let db = db.clone();
+                    let inserted = inserted.clone();
+                    thread::spawn(move || {
+                        db.insert(b"b", b"b").unwrap();
+                        inserted.store(true, SeqCst);
+                    })
+                };
+                thread::sleep(std::time::Duration::from_millis(100));
+                assert!(!inserted.load(SeqCst));
+                backend.disk_full.store(false, SeqCst);
+                writer.join().unwrap();
+                assert!(inserted.load(SeqCst));
+            }
+
+            // writes resume once space is freed
+            flusher.join().unwrap();
+            db.insert(b"c", b"c")?;
+            db.flush()?;
+        }
+
+        let db = config.open()?;
+        assert_eq!(db.get(b"a")?, Some(IVec::from(b"a")));
+        assert_eq!(db.get(b"b")?.is_some(), on_full == OnFull::Block);
+        assert_eq!(db.get(b"c")?, Some(IVec::from(b"c")));
+    }
+
+    // the default latches the error until the db is reopened
+    let _ = std::fs::remove_dir_all(path);
+    let backend = Arc::new(MemIoBackend::default());
+    let config = Config::new()
+        .path(path)
+        .flush_every_ms(None)
+        .io_backend(backend.clone());
+    let db = config.open()?;
+    db.insert(b"a", b"a")?;
+    backend.disk_full.store(true, SeqCst);
+    assert!(db.flush().is_err());
+    backend.disk_full.store(false, SeqCst);
+    assert!(db.flush().is_err());
+    drop(db);
+
+    let _ = std::fs::remove_dir_all(path);
+    Ok(())
+}
+
+#[test]
+fn write_generation() -> Result<()> {
+    common::setup_logger();
+
+    let path = "write_generation_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let mut last_db_gen = 0;
+    let mut last_tree_gen = 0;
+
+    for i in 0..10 {
+        let db = Config::new().path(path).open()?;
+        let tree = db.open_tree(b"tree")?;
+
+        assert!(db.write_generation() >= last_db_gen);
+        assert!(tree.write_generation() >= last_tree_gen);
+
+        let db_gen = db.write_generation();
+        let tree_gen = tree.write_generation();
+
+        db.insert(kv(i), kv(i))?;
+        assert_eq!(db.write_generation(), db_gen + 1);
+        assert_eq!(tree.write_generation(), tree_gen);
+
+        // no-op writes do not bump the generation
+        db.insert(kv(i), kv(i))?;
+        db.remove(b"missing")?;
+        assert_eq!(db.write_generation(), db_gen + 1);
+
+        tree.insert(kv(i), kv(i))?;
+        tree.compare_and_swap(kv(i), Some(kv(i)), None as Option<&[u8]>)?
+            .unwrap();
+        assert_eq!(db.write_generation(), db_gen + 3);
+        assert_eq!(tree.write_generation(), tree_gen + 2);
+
+        last_db_gen = db.write_generation();
+        last_tree_gen = tree.write_generation();
+    }
+    std::fs::remove_dir_all(path).unwrap();
+
+    // coalesced writes take up far less than a byte of the
+    // log each, which doesn't hold the generation back
+    for &interval in &[1_000_000, 64] {
+        let config = Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .coalesce_writes(true)
+            .idgen_persist_interval(interval);
+
+        let mut last_gen = 0;
+        for _ in 0..3 {
+            let db = config.open()?;
+            assert!(
+                db.write_generation() >= last_gen,
+                "{} < {} with an interval of {}",
+                db.write_generation(),
+                last_gen,
+                interval
+            );
+            for i in 0..10_000_u32 {
+                db.insert(b"hot", &i.to_be_bytes())?;
+            }
+            last_gen = db.write_generation();
+            db.flush()?;
+            drop(db);
+        }
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    Ok(())
+}
+
+#[test]
+fn create_tree() {
+    common::setup_logger();
+
+    let path = "create_exclusive_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    {
+        let config = Config::new().create_new(true).path(path);
+        config.open().unwrap();
+    }
+
+    let config = Config::new().create_new(true).path(path);
+    config.open().unwrap_err();
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn test_clock_drives_flushing() -> Result<()> {
+    use std::time::Duration;
+
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open()?;
+    if let Err(Error::Unsupported(_)) = db.step() {
+    } else {
+        panic!("step should require a test clock");
+    }
+    drop(db);
+
+    let path = "test_clock_drives_flushing_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let clock = Arc::new(VirtualClock::new());
+    let config = Config::new()
+        .path(path)
+        .segment_size(256)
+        .flush_every_ms(Some(100))
+        .test_clock(clock.clone());
+
+    {
+        let db = config.open()?;
+
+        assert!(db.step()?);
+        assert!(!db.step()?);
+
+        // enough writes to seal many io buffers and fill
+        // many segments, with their log writes happening
+        // on this thread
+        for i in 0..2_000_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 64])?;
+            if i % 3 == 0 {
+                db.remove((i / 2).to_be_bytes())?;
+            }
+            if i % 100 == 0 {
+                clock.advance(Duration::from_millis(50));
+                db.step()?;
+            }
+        }
+
+        clock.advance(Duration::from_millis(99));
+        assert!(!db.step()?);
+        clock.advance(Duration::from_secs(1));
+        assert!(db.step()?);
+    }
+
+    let db = Config::new().path(path).segment_size(256).open()?;
+    // `i` was removed when `2 * i` or `2 * i + 1` was
+    // inserted, if that was a multiple of 3
+    let expected: Vec<u32> = (0..2_000_u32)
+        .filter(|i| {
+            ![2 * i, 2 * i + 1].iter().any(|j| *j < 2_000 && j % 3 == 0)
+        })
+        .collect();
+    let keys: Vec<u32> = db
+        .iter()
+        .keys()
+        .map(|k| {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(&k.unwrap());
+            u32::from_be_bytes(buf)
+        })
+        .collect();
+    assert_eq!(keys, expected);
+
+    drop(db);
+    std::fs::remove_dir_all(path)?;
+
+    Ok(())
+}
+
+#[test]
+fn min_free_space() -> Result<()> {
+    common::setup_logger();
+
+    // a threshold that is already satisfied has no effect
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .min_free_space(1);
+    let db = config.open()?;
+    assert!(db.free_space()? > 0);
+    db.insert(b"k", b"v")?;
+    drop(db);
+
+    let config = Config::new()
+        .temporary(true)
+        .flush_every_ms(None)
+        .min_free_space(u64::max_value());
+    let db = config.open()?;
+
+    // ENOSPC, or ERROR_DISK_FULL on windows
+    let no_space_code = if cfg!(windows) { 112 } else { 28 };
+    let is_no_space = |res: Result<()>| match res {
+        Err(Error::Io(e)) => e.raw_os_error() == Some(no_space_code),
+        _ => false,
+    };
+
+    assert!(is_no_space(db.insert(b"k", b"v").map(drop)));
+    assert!(is_no_space(db.remove(b"k").map(drop)));
+    assert!(is_no_space(db.merge(b"k", b"v").map(drop)));
+    assert!(is_no_space(
+        db.compare_and_swap(b"k", None as Option<&[u8]>, Some(b"v")).map(drop)
+    ));
+    let mut batch = Batch::default();
+    batch.insert(b"k", b"v");
+    assert!(is_no_space(db.apply_batch(batch)));
+    assert!(is_no_space(db.bulk_load(vec![(b"k", b"v")])));
+    let tx_res = db.transaction::<_, _, ()>(|tx| {
+        tx.insert(b"k", b"v")?;
+        Ok(())
+    });
+    if let Err(TransactionError::Storage(e)) = tx_res {
+        assert!(is_no_space(Err(e)));
+    } else {
+        panic!("transaction should have been refused");
+    }
+
+    // nothing was written, and reads still work
+    assert_eq!(db.get(b"k")?, None);
+    assert!(db.is_empty()?);
+
+    Ok(())
+}
+
+#[test]
+fn create_new_and_must_exist() {
+    common::setup_logger();
+
+    let path = "create_new_and_must_exist_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let err = Config::new().must_exist(true).path(path).open().unwrap_err();
+    assert_eq!(err, Error::DatabaseNotFound(path.into()));
+    assert!(
+        !std::path::Path::new(path).exists(),
+        "a failed must_exist open should not create the directory"
+    );
+
+    {
+        let db = Config::new().create_new(true).path(path).open().unwrap();
+        db.insert(b"k", b"v").unwrap();
+        db.flush().unwrap();
+    }
+
+    let err = Config::new().create_new(true).path(path).open().unwrap_err();
+    assert_eq!(err, Error::DatabaseAlreadyExists(path.into()));
+
+    {
+        let db = Config::new().must_exist(true).path(path).open().unwrap();
+        assert_eq!(db.get(b"k").unwrap().unwrap(), b"v");
+    }
+
+    let err = Config::new()
+        .create_new(true)
+        .must_exist(true)
+        .path(path)
+        .open()
+        .unwrap_err();
+    if let Error::Unsupported(_) = err {
+    } else {
+        panic!("expected Unsupported, got {:?}", err);
+    }
+
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn open_timeout() {
+    use std::time::Duration;
+
+    common::setup_logger();
+
+    let path = "open_timeout_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    let config =
+        |timeout| Config::new().path(path).open_timeout(Some(timeout));
+    let db = config(Duration::from_secs(0)).open().unwrap();
+
+    let err = config(Duration::from_secs(0)).open().unwrap_err();
+    let pid = if cfg!(target_os = "linux") {
+        Some(std::process::id())
+    } else {
+        None
+    };
+    let db_path = std::path::Path::new(path).join("db");
+    assert_eq!(err, Error::LockHeld { path: db_path, pid });
+
+    // a later open waits for the lock to be released
+    let waiting = config(Duration::from_secs(10));
+    let opener = thread::spawn(move || waiting.open().map(drop));
+    thread::sleep(Duration::from_millis(100));
+    drop(db);
+    opener.join().unwrap().unwrap();
+
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn tree_import_export() -> Result<()> {
+    common::setup_logger();
+
+    let config_1 = Config::new().temporary(true);
+    let config_2 = Config::new().temporary(true);
+
+    let db = config_1.open()?;
+    for db_id in 0..N_THREADS {
         let tree_id = format!("tree_{}", db_id);
         let tree = db.open_tree(tree_id.as_bytes())?;
         for i in 0..N_THREADS {
@@ -871,6 +5797,57 @@ fn tree_import_export() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn tree_to_bytes() -> Result<()> {
+    common::setup_logger();
+
+    let db = Config::new().temporary(true).open()?;
+    for i in 0..N_THREADS {
+        db.insert(kv(i), kv(i))?;
+    }
+    let tree = db.open_tree(b"other")?;
+    for i in 0..N_THREADS {
+        tree.insert(kv(i), vec![0; 100 * i])?;
+    }
+    // stored as a blob, which stays in memory as well
+    tree.insert(b"blob", vec![1; 256 * 1024])?;
+    db.open_tree(b"empty")?;
+
+    let image = db.to_bytes()?;
+    let copy = Db::open_from_bytes(&image)?;
+
+    assert_eq!(copy.checksum()?, db.checksum()?);
+    assert_eq!(copy.tree_names()?, db.tree_names()?);
+    assert!(copy.open_tree(b"empty")?.is_empty()?);
+    assert_eq!(copy.to_bytes()?, image);
+
+    // the copy is read-only
+    match copy.insert(kv(0), vec![]) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected the write to be refused, got {:?}", other),
+    }
+    assert!(copy.open_tree(b"new").is_err());
+    drop(copy);
+
+    match Db::open_from_bytes(b"not an image") {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+
+    let mut damaged = image.clone();
+    damaged[20] ^= 0xFF;
+    match Db::open_from_bytes(&damaged) {
+        Err(Error::Corruption { .. }) => {}
+        other => panic!("expected corruption, got {:?}", other),
+    }
+    match Db::open_from_bytes(&image[..image.len() - 1]) {
+        Err(Error::Corruption { .. }) => {}
+        other => panic!("expected corruption, got {:?}", other),
+    }
+
+    Ok(())
+}
+
 #[test]
 #[cfg(not(target_os = "fuchsia"))]
 fn quickcheck_tree_matches_btreemap() {
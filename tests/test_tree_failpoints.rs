@@ -144,6 +144,10 @@ fn value_factory(set_counter: u16) -> Vec<u8> {
     }
 }
 
+// failpoints are global, so tests that use them must not run concurrently
+static TEST_LOCK: Lazy<Mutex<()>, fn() -> Mutex<()>> =
+    Lazy::new(|| Mutex::new(()));
+
 fn tear_down_failpoints() {
     sled::fail::reset();
 }
@@ -162,9 +166,7 @@ struct ReferenceEntry {
 
 fn prop_tree_crashes_nicely(ops: Vec<Op>, flusher: bool) -> bool {
     // forces quickcheck to run one thread at a time
-    static M: Lazy<Mutex<()>, fn() -> Mutex<()>> = Lazy::new(|| Mutex::new(()));
-
-    let _lock = M.lock().expect("our test lock should not be poisoned");
+    let _lock = TEST_LOCK.lock().expect("our test lock should not be poisoned");
 
     // clear all failpoints that may be left over from the last run
     tear_down_failpoints();
@@ -1720,3 +1722,76 @@ fn failpoints_bug_35() {
         ));
     }
 }
+
+#[test]
+fn failpoints_crash_after_create() {
+    let _lock = TEST_LOCK.lock().expect("our test lock should not be poisoned");
+    tear_down_failpoints();
+    common::setup_logger();
+
+    let path = "failpoints_crash_after_create_db";
+    let _ = std::fs::remove_dir_all(path);
+
+    // crash right after the directory entries of the
+    // freshly created files have been made durable
+    sled::fail::set("fsync dir post", 1);
+    let res = Config::new().path(path).open();
+    tear_down_failpoints();
+    assert!(matches!(res, Err(Error::FailPoint)));
+
+    let db = Config::new().path(path).open().unwrap();
+    db.insert(b"k", b"v").unwrap();
+    db.flush().unwrap();
+    db.flush_dir().unwrap();
+    drop(db);
+
+    let db = Config::new().path(path).open().unwrap();
+    assert!(db.was_recovered());
+    assert_eq!(db.get(b"k").unwrap(), Some(IVec::from(b"v")));
+    drop(db);
+
+    std::fs::remove_dir_all(path).unwrap();
+}
+
+#[test]
+fn failpoints_close_snapshot_fails() {
+    let _lock = TEST_LOCK.lock().expect("our test lock should not be poisoned");
+    tear_down_failpoints();
+    common::setup_logger();
+
+    let path = "failpoints_close_snapshot_fails_db";
+
+    // the snapshot is written from `Drop` once the last
+    // handle is gone, where a failure can only be logged,
+    // so the next open recovers from the log instead
+    for fp in &["snap write", "snap write mv", "snap write rm old"] {
+        let _ = std::fs::remove_dir_all(path);
+
+        // leaves a snapshot behind for the next one to replace
+        let db = Config::new().path(path).open().unwrap();
+        for i in 0..50_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 100]).unwrap();
+        }
+        db.close().unwrap();
+
+        let db = Config::new().path(path).open().unwrap();
+        for i in 50..100_u32 {
+            db.insert(i.to_be_bytes(), vec![0; 100]).unwrap();
+        }
+        sled::fail::set(fp, 1);
+        db.close().unwrap();
+        tear_down_failpoints();
+
+        let db = Config::new().path(path).open().unwrap();
+        assert_eq!(db.len(), 100, "after failing at {}", fp);
+        db.insert(b"k", b"v").unwrap();
+        db.close().unwrap();
+
+        let db = Config::new().path(path).open().unwrap();
+        assert_eq!(db.len(), 101, "after failing at {}", fp);
+        assert_eq!(db.get(b"k").unwrap(), Some(IVec::from(b"v")));
+        drop(db);
+    }
+
+    std::fs::remove_dir_all(path).unwrap();
+}